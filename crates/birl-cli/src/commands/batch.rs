@@ -0,0 +1,379 @@
+use anyhow::{Context, Result};
+use birl_core::naming::versioned_path;
+use birl_core::{
+    parse_params, BatchError, BatchManifest, BatchResult, CollisionPolicy, CompositeOptions,
+    LayerParam, NamingContext, NamingTemplate, View,
+};
+use birl_storage::{Calibration, StorageService};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Status manifest kept in the output directory between runs
+pub const STATUS_FILE: &str = ".birl-status.json";
+
+pub struct BatchOptions {
+    pub view: View,
+    /// File with one "category/sku,..." parameter string per line
+    pub input: PathBuf,
+    /// Directory to write composites into
+    pub output_dir: Option<PathBuf>,
+    /// Path of each composite relative to `output_dir`
+    pub naming: NamingTemplate,
+    pub on_collision: CollisionPolicy,
+    pub options: CompositeOptions,
+    pub bypass_cache: bool,
+    /// Status manifest (default: `.birl-status.json` in the output directory)
+    pub status: Option<PathBuf>,
+    /// Estimate the batch instead of rendering it
+    pub dry_run: Option<DryRun>,
+}
+
+pub struct DryRun {
+    /// Measured costs (default: [`Calibration::default`])
+    pub calibration: Option<PathBuf>,
+    /// Renders in flight to project the duration at
+    pub concurrency: usize,
+}
+
+/// Successful batch item
+pub struct BatchRender {
+    pub params: String,
+    pub cache_key: String,
+    pub cached: bool,
+    pub output: Option<Output>,
+    /// Done in an earlier run, so not rendered again
+    pub resumed: bool,
+}
+
+/// A finished item in the status manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Done {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+}
+
+/// Per-item status of a batch, saved after every item so running the same
+/// input again only retries what failed or never finished
+pub struct StatusFile {
+    path: PathBuf,
+    manifest: BatchManifest<Done>,
+}
+
+impl StatusFile {
+    /// Read the status of an earlier run, or start an empty one
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let manifest = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)
+                .with_context(|| format!("Invalid status manifest {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BatchManifest::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(Self { path, manifest })
+    }
+
+    /// Whether an item succeeded earlier and its output is still there
+    pub fn is_done(&self, cache_key: &str) -> bool {
+        self.manifest
+            .completed(cache_key)
+            .is_some_and(|done| done.output.as_ref().is_none_or(|path| path.exists()))
+    }
+
+    /// Record an item's outcome and save the manifest
+    pub fn record(&mut self, cache_key: &str, result: Result<Done, BatchError>) {
+        self.manifest.record(cache_key, &result);
+        let saved = serde_json::to_string_pretty(&self.manifest)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(std::fs::write(&self.path, json)?));
+        if let Err(e) = saved {
+            warn!("Failed to save {}: {:#}", self.path.display(), e);
+        }
+    }
+}
+
+/// Where a composite was written
+pub enum Output {
+    Written(PathBuf),
+    /// Existing file kept under the skip collision policy
+    Skipped(PathBuf),
+}
+
+pub async fn batch_command(storage: Arc<StorageService>, options: BatchOptions) -> Result<()> {
+    let start = std::time::Instant::now();
+
+    let input = std::fs::read_to_string(&options.input)
+        .with_context(|| format!("Failed to read {}", options.input.display()))?;
+
+    // Skip blank lines and comments
+    let lines: Vec<&str> = input
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+
+    if let Some(dir) = &options.output_dir {
+        std::fs::create_dir_all(dir).context("Failed to create output directory")?;
+    }
+    let status_path = options
+        .status
+        .clone()
+        .or_else(|| options.output_dir.as_ref().map(|dir| dir.join(STATUS_FILE)));
+    let mut status = status_path.map(StatusFile::load).transpose()?;
+
+    if let Some(dry_run) = &options.dry_run {
+        return estimate_batch(&storage, &options, dry_run, status.as_ref(), &lines);
+    }
+
+    info!(
+        "Rendering {} outfits for view {}",
+        lines.len(),
+        options.view
+    );
+
+    let throttle_before = storage.write_throttle_stats();
+    let mut results = Vec::with_capacity(lines.len());
+    for (index, line) in lines.iter().enumerate() {
+        results.push(render_line(&storage, &options, status.as_mut(), index, line).await);
+    }
+
+    let result = BatchResult::from_results(results);
+    print_summary(&result);
+    if let (Some(before), Some(after)) = (throttle_before, storage.write_throttle_stats()) {
+        let throttle = after.since(&before);
+        println!(
+            "Writes: {} ({} throttled, waited {:.1?})",
+            throttle.writes, throttle.throttled, throttle.waited
+        );
+    }
+    for layer in storage.oversized_layers() {
+        println!(
+            "Oversized layer {}: {} ({} fetches)",
+            layer.key, layer.oversize, layer.fetches
+        );
+    }
+
+    info!("Completed in {:?}", start.elapsed());
+
+    if !result.is_success() {
+        anyhow::bail!(
+            "{} of {} items failed",
+            result.summary.failed,
+            result.summary.total
+        );
+    }
+
+    Ok(())
+}
+
+/// Plan every item that would be rendered and print what the batch would cost
+fn estimate_batch(
+    storage: &StorageService,
+    options: &BatchOptions,
+    dry_run: &DryRun,
+    status: Option<&StatusFile>,
+    lines: &[&str],
+) -> Result<()> {
+    let calibration = match &dry_run.calibration {
+        Some(path) => {
+            let json = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            Calibration::from_json(&json)
+                .with_context(|| format!("Invalid calibration {}", path.display()))?
+        }
+        None => Calibration::default(),
+    };
+
+    let mut plans = Vec::with_capacity(lines.len());
+    let (mut done, mut invalid) = (0, 0);
+    for (index, line) in lines.iter().enumerate() {
+        let params = parse_params(line);
+        if params.is_empty() {
+            println!("  ✗ [{}] No valid category/sku pairs in '{}'", index, line);
+            invalid += 1;
+            continue;
+        }
+        match storage.plan(&params, options.view, &options.options) {
+            Ok(plan) => {
+                let resumed = !options.bypass_cache
+                    && status.is_some_and(|status| status.is_done(&plan.cache_key));
+                if resumed {
+                    done += 1;
+                } else {
+                    plans.push(plan);
+                }
+            }
+            Err(e) => {
+                println!("  ✗ [{}] {:#}", index, e);
+                invalid += 1;
+            }
+        }
+    }
+
+    let estimate = storage.estimate(
+        &plans,
+        &calibration,
+        dry_run.concurrency,
+        options.bypass_cache,
+    );
+    let mb = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+    println!(
+        "\nDry run: {} items ({} done earlier, {} invalid)",
+        lines.len(),
+        done,
+        invalid
+    );
+    println!(
+        "  Renders:  {} ({} cache hits)",
+        estimate.renders,
+        estimate.items - estimate.renders
+    );
+    println!("  GETs:     {}", estimate.gets);
+    println!("  PUTs:     {}", estimate.puts);
+    println!("  Read:     {:.1} MB", mb(estimate.bytes_read));
+    println!("  Written:  {:.1} MB", mb(estimate.bytes_written));
+    println!("  CPU:      {:.1?}", estimate.cpu);
+    println!(
+        "  Duration: ~{:.1?} at concurrency {}",
+        estimate.duration,
+        dry_run.concurrency.max(1)
+    );
+    Ok(())
+}
+
+async fn render_line(
+    storage: &StorageService,
+    options: &BatchOptions,
+    status: Option<&mut StatusFile>,
+    index: usize,
+    line: &str,
+) -> Result<BatchRender, BatchError> {
+    let params = parse_params(line);
+    if params.is_empty() {
+        return Err(BatchError::invalid_params(format!(
+            "No valid category/sku pairs in '{}'",
+            line
+        )));
+    }
+
+    let Some(status) = status else {
+        return render_params(storage, options, &params, index, line).await;
+    };
+    let cache_key = storage.cache_key(&params, options.view, &options.options);
+    if !options.bypass_cache && status.is_done(&cache_key) {
+        return Ok(BatchRender {
+            params: line.to_string(),
+            cache_key,
+            cached: true,
+            output: None,
+            resumed: true,
+        });
+    }
+
+    let result = render_params(storage, options, &params, index, line).await;
+    let done = result.as_ref().map_err(Clone::clone).map(|render| Done {
+        output: render.output.as_ref().map(|output| match output {
+            Output::Written(path) | Output::Skipped(path) => path.clone(),
+        }),
+    });
+    status.record(&cache_key, done);
+    result
+}
+
+async fn render_params(
+    storage: &StorageService,
+    options: &BatchOptions,
+    params: &[LayerParam],
+    index: usize,
+    line: &str,
+) -> Result<BatchRender, BatchError> {
+    let rendered = storage
+        .render(params, options.view, &options.options, options.bypass_cache)
+        .await
+        .map_err(|e| BatchError::render(format!("{:#}", e)))?;
+
+    let output = match &options.output_dir {
+        Some(dir) => {
+            let name = options.naming.render(&NamingContext {
+                params,
+                view: options.view,
+                format: rendered.format,
+                cache_key: &rendered.cache_key,
+                index,
+            });
+            let output = write_output(dir, &name, &rendered.data, options.on_collision)
+                .map_err(|e| BatchError::render(format!("{:#}", e)))?;
+            Some(output)
+        }
+        None => None,
+    };
+
+    Ok(BatchRender {
+        params: line.to_string(),
+        cache_key: rendered.cache_key,
+        cached: rendered.cached,
+        output,
+        resumed: false,
+    })
+}
+
+pub fn write_output(
+    dir: &Path,
+    name: &str,
+    data: &[u8],
+    policy: CollisionPolicy,
+) -> Result<Output> {
+    let mut path = dir.join(name);
+
+    if path.exists() {
+        match policy {
+            CollisionPolicy::Overwrite => {}
+            CollisionPolicy::Skip => return Ok(Output::Skipped(path)),
+            CollisionPolicy::Version => {
+                path = (1..)
+                    .map(|version| dir.join(versioned_path(name, version)))
+                    .find(|candidate| !candidate.exists())
+                    .expect("unbounded version range");
+            }
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    std::fs::write(&path, data).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(Output::Written(path))
+}
+
+fn print_summary(result: &BatchResult<BatchRender>) {
+    for (index, render) in result.successes() {
+        let source = if render.resumed {
+            "done earlier"
+        } else if render.cached {
+            "cached"
+        } else {
+            "rendered"
+        };
+        println!(
+            "  ✓ [{}] {} ({}) {}",
+            index, render.cache_key, source, render.params
+        );
+        match &render.output {
+            Some(Output::Written(path)) => println!("      -> {}", path.display()),
+            Some(Output::Skipped(path)) => {
+                println!("      -> {} (exists, skipped)", path.display())
+            }
+            None => {}
+        }
+    }
+    for (index, error) in result.failures() {
+        println!("  ✗ [{}] {}", index, error);
+    }
+
+    println!(
+        "\nBatch: {} succeeded, {} failed ({} total)",
+        result.summary.succeeded, result.summary.failed, result.summary.total
+    );
+}
@@ -1,6 +1,11 @@
 use anyhow::Result;
-use birl_core::{compose_layers, generate_cache_key, parse_params, LayerNormalizer, View};
-use birl_storage::StorageService;
+use birl_core::{
+    compose_layers, generate_cache_key, parse_params, CompositeOptions, LayerNormalizer,
+    OutputFormat, View,
+};
+use birl_storage::{Calibration, StorageService};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tracing::info;
@@ -56,6 +61,94 @@ impl BenchmarkResults {
     }
 }
 
+/// Throughput of one concurrency level
+pub struct ConcurrencyResults {
+    pub concurrency: usize,
+    pub ops: usize,
+    pub elapsed: Duration,
+    /// Render time of each operation, sorted
+    pub latencies: Vec<Duration>,
+    /// Average time an operation waited for a free slot
+    pub avg_queue_wait: Duration,
+}
+
+impl ConcurrencyResults {
+    pub fn ops_per_sec(&self) -> f64 {
+        self.ops as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Latency at or below which `p` percent of operations finished
+    pub fn percentile(&self, p: f64) -> Duration {
+        let rank = ((p / 100.0) * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+
+    fn print(&self) {
+        println!(
+            "  {:>3} parallel: {:>7.1} ops/s | p50 {:>8.2}ms | p95 {:>8.2}ms | queue wait {:>8.2}ms",
+            self.concurrency,
+            self.ops_per_sec(),
+            self.percentile(50.0).as_secs_f64() * 1000.0,
+            self.percentile(95.0).as_secs_f64() * 1000.0,
+            self.avg_queue_wait.as_secs_f64() * 1000.0,
+        );
+    }
+
+    fn to_markdown(&self) -> String {
+        format!(
+            "| {} | {} | {:.1} | {:.2}ms | {:.2}ms | {:.2}ms |",
+            self.concurrency,
+            self.ops,
+            self.ops_per_sec(),
+            self.percentile(50.0).as_secs_f64() * 1000.0,
+            self.percentile(95.0).as_secs_f64() * 1000.0,
+            self.avg_queue_wait.as_secs_f64() * 1000.0,
+        )
+    }
+}
+
+/// Run `ops` uncached renders with at most `concurrency` in flight, like the
+/// server handling parallel requests on its worker threads
+async fn bench_concurrent(
+    storage: &Arc<StorageService>,
+    view: View,
+    params: &str,
+    concurrency: usize,
+    ops: usize,
+) -> Result<ConcurrencyResults> {
+    let params = parse_params(params);
+    let options = CompositeOptions::default();
+    let start = Instant::now();
+
+    let mut timings: Vec<(Duration, Duration)> = stream::iter(0..ops)
+        .map(|_| {
+            let storage = storage.clone();
+            let params = params.clone();
+            let options = options.clone();
+            tokio::spawn(async move {
+                let started = Instant::now();
+                storage.render(&params, view, &options, true).await?;
+                Ok::<_, anyhow::Error>((started - start, started.elapsed()))
+            })
+        })
+        .buffer_unordered(concurrency)
+        .map(|joined| joined?)
+        .try_collect()
+        .await?;
+    let elapsed = start.elapsed();
+
+    let avg_queue_wait = timings.iter().map(|(wait, _)| *wait).sum::<Duration>() / ops as u32;
+    timings.sort_by_key(|(_, latency)| *latency);
+
+    Ok(ConcurrencyResults {
+        concurrency,
+        ops,
+        elapsed,
+        latencies: timings.into_iter().map(|(_, latency)| latency).collect(),
+        avg_queue_wait,
+    })
+}
+
 async fn bench_composition(
     storage: &StorageService,
     view: View,
@@ -66,7 +159,7 @@ async fn bench_composition(
     let mut fetch_times = Vec::new();
     let mut compose_times = Vec::new();
 
-    for i in 0..iterations {
+    for _ in 0..iterations {
         let start = Instant::now();
 
         // Parse and normalize
@@ -83,7 +176,7 @@ async fn bench_composition(
 
         // Compose
         let compose_start = Instant::now();
-        let _composite_data = compose_layers(&base_image_data, layers)?;
+        let _composite_data = compose_layers(&base_image_data, layers, &CompositeOptions::default())?;
         compose_times.push(compose_start.elapsed());
 
         times.push(start.elapsed());
@@ -115,23 +208,104 @@ async fn bench_with_cache(
     let base_image_data = storage.fetch_base_plate(view).await?;
     let layers_result = storage.fetch_layers(&normalized_params, view).await?;
     let layers: Vec<_> = layers_result.into_iter().flatten().collect();
-    let composite_data = compose_layers(&base_image_data, layers)?;
+    let composite_data = compose_layers(&base_image_data, layers, &CompositeOptions::default())?;
 
     // Save to cache
     let cache_key = generate_cache_key(&normalized_params, view, view.plate_value());
-    storage.save_composite(&cache_key, composite_data).await?;
+    storage
+        .save_composite(&cache_key, composite_data, OutputFormat::Jpeg)
+        .await?;
 
     // Now benchmark cache retrieval
     for _ in 0..iterations {
         let start = Instant::now();
-        let _ = storage.get_cached_composite(&cache_key).await?;
+        let _ = storage
+            .get_cached_composite(&cache_key, OutputFormat::Jpeg)
+            .await?;
         times.push(start.elapsed());
     }
 
     Ok(times)
 }
 
-pub async fn run_benchmarks(storage: Arc<StorageService>, output_file: Option<String>) -> Result<()> {
+/// Time reads, writes and renders of one and five layers, for the cost
+/// estimates of `batch --dry-run`
+async fn calibrate(storage: &StorageService, view: View, iterations: usize) -> Result<Calibration> {
+    let outfits = [
+        "hoodies/hoodie-black",
+        "hoodies/hoodie-black,pants/cargo-black,hats/beanie-black,gloves/leather-gloves-black,jackets/softshell-grey",
+    ];
+    let options = CompositeOptions::default();
+    let mut calibration = Calibration::default();
+    let mut renders = Vec::new();
+    let mut reads = Vec::new();
+    let mut writes = Vec::new();
+    let mut asset_bytes = Vec::new();
+
+    for params in outfits {
+        let params = parse_params(params);
+        let normalized_params = LayerNormalizer::new(view, &params).normalize_all(&params);
+        let mut times = Vec::new();
+        for _ in 0..iterations {
+            let read_start = Instant::now();
+            let base_image_data = storage.fetch_base_plate(view).await?;
+            reads.push(read_start.elapsed());
+            let layers: Vec<_> = storage
+                .fetch_layers(&normalized_params, view)
+                .await?
+                .into_iter()
+                .flatten()
+                .collect();
+            asset_bytes.push(base_image_data.len());
+            asset_bytes.extend(layers.iter().map(|layer| layer.len()));
+
+            let layer_count = layers.len();
+            let compose_start = Instant::now();
+            let composite_data = compose_layers(&base_image_data, layers, &options)?;
+            times.push(compose_start.elapsed());
+            calibration.composite_bytes = composite_data.len() as u64;
+
+            let cache_key = generate_cache_key(&normalized_params, view, view.plate_value());
+            let write_start = Instant::now();
+            storage
+                .save_composite(&cache_key, composite_data, options.format)
+                .await?;
+            writes.push(write_start.elapsed());
+            if times.len() == iterations {
+                renders.push((layer_count, average_ms(&times)));
+            }
+        }
+    }
+
+    // Render time grows linearly with the layers
+    if let [(few, few_ms), (many, many_ms)] = renders[..] {
+        if many > few {
+            calibration.layer_ms = ((many_ms - few_ms) / (many - few) as f64).max(0.0);
+            calibration.render_ms = (few_ms - calibration.layer_ms * few as f64).max(0.0);
+        }
+    }
+    calibration.get_ms = average_ms(&reads);
+    calibration.put_ms = average_ms(&writes);
+    calibration.layer_bytes = (asset_bytes.iter().sum::<usize>() / asset_bytes.len().max(1)) as u64;
+    Ok(calibration)
+}
+
+fn average_ms(times: &[Duration]) -> f64 {
+    times.iter().sum::<Duration>().as_secs_f64() * 1000.0 / times.len().max(1) as f64
+}
+
+pub struct BenchOptions {
+    pub output: Option<String>,
+    /// Where to write the measured [`Calibration`] as JSON
+    pub calibration: Option<PathBuf>,
+    /// Parallel renders to measure throughput at
+    pub concurrency: Vec<usize>,
+    /// Renders per concurrency level
+    pub ops: usize,
+}
+
+pub async fn run_benchmarks(storage: Arc<StorageService>, options: BenchOptions) -> Result<()> {
+    let output_file = options.output;
     println!("\n🚀 Running BIRL Rust Benchmarks\n");
 
     let mut all_results = Vec::new();
@@ -183,6 +357,32 @@ pub async fn run_benchmarks(storage: Arc<StorageService>, output_file: Option<St
     result.print();
     all_results.push(result);
 
+    // Test 6: Throughput under concurrent load
+    info!("Running: Concurrent composition");
+    println!("\n{}", "=".repeat(60));
+    println!("Test: Concurrent full outfit ({} renders per level)", options.ops);
+    println!("{}", "=".repeat(60));
+    let mut concurrency_results = Vec::new();
+    for &concurrency in &options.concurrency {
+        let result = bench_concurrent(
+            &storage,
+            View::Front,
+            "hoodies/hoodie-black,pants/cargo-darkgreen,hats/beanie-black",
+            concurrency.max(1),
+            options.ops.max(1),
+        )
+        .await?;
+        result.print();
+        concurrency_results.push(result);
+    }
+
+    if let Some(path) = &options.calibration {
+        info!("Running: Calibration");
+        let calibration = calibrate(&storage, View::Front, 10).await?;
+        std::fs::write(path, serde_json::to_string_pretty(&calibration)?)?;
+        println!("\n📏 Calibration saved to: {}", path.display());
+    }
+
     // Generate summary
     println!("\n{}", "=".repeat(60));
     println!("BENCHMARK SUMMARY");
@@ -192,6 +392,11 @@ pub async fn run_benchmarks(storage: Arc<StorageService>, output_file: Option<St
     for result in &all_results {
         println!("{}", result.to_markdown());
     }
+    println!("\n| Concurrency | Renders | Ops/s | p50 | p95 | Queue wait |");
+    println!("|-------------|---------|-------|-----|-----|------------|");
+    for result in &concurrency_results {
+        println!("{}", result.to_markdown());
+    }
 
     // Save to file if requested
     if let Some(output_path) = output_file {
@@ -204,7 +409,15 @@ pub async fn run_benchmarks(storage: Arc<StorageService>, output_file: Option<St
         output.push_str("|------|------------|------------|----------|----------|----------|\n");
         for result in &all_results {
             output.push_str(&result.to_markdown());
-            output.push_str("\n");
+            output.push('\n');
+        }
+
+        output.push_str("\n## Concurrent Load\n\n");
+        output.push_str("| Concurrency | Renders | Ops/s | p50 (ms) | p95 (ms) | Queue wait (ms) |\n");
+        output.push_str("|-------------|---------|-------|----------|----------|-----------------|\n");
+        for result in &concurrency_results {
+            output.push_str(&result.to_markdown());
+            output.push('\n');
         }
 
         output.push_str("\n## System Information\n\n");
@@ -0,0 +1,141 @@
+use anyhow::{Context, Result};
+use birl_core::{parse_params, CollisionPolicy, CompositeOptions, View};
+use birl_storage::{RenderedComposite, StorageService};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use super::batch::{write_output, Output};
+
+/// Manifest written next to the composites of a bundle
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+pub struct BundleOptions {
+    pub view: View,
+    pub params: String,
+    /// Directory to write the composites and the manifest into
+    pub output_dir: Option<PathBuf>,
+    pub options: CompositeOptions,
+    pub bypass_cache: bool,
+}
+
+/// Keys of everything a bundle produced
+#[derive(Debug, Serialize)]
+struct Manifest {
+    view: View,
+    params: String,
+    outfit: Entry,
+    items: Vec<Entry>,
+}
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sku: Option<String>,
+    cache_key: String,
+    cached: bool,
+    /// Path relative to the output directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+}
+
+pub async fn bundle_command(storage: Arc<StorageService>, options: BundleOptions) -> Result<()> {
+    let start = std::time::Instant::now();
+    info!(
+        "Rendering bundle: view={}, params={}",
+        options.view, options.params
+    );
+
+    let params = parse_params(&options.params);
+    if params.is_empty() {
+        anyhow::bail!("No valid category/sku pairs in '{}'", options.params);
+    }
+
+    let bundle = storage
+        .render_bundle(
+            &params,
+            options.view,
+            &options.options,
+            options.bypass_cache,
+        )
+        .await
+        .context("Failed to render bundle")?;
+
+    let outfit = entry(&options, "outfit", &bundle.outfit)?;
+    let items = bundle
+        .items
+        .iter()
+        .map(|(item, rendered)| {
+            let mut entry = entry(
+                &options,
+                &format!("{}-{}", item.category, item.sku),
+                rendered,
+            )?;
+            entry.category = Some(item.category.clone());
+            entry.sku = Some(item.sku.to_string());
+            Ok(entry)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let manifest = serde_json::to_string_pretty(&Manifest {
+        view: options.view,
+        params: options.params.clone(),
+        outfit,
+        items,
+    })?;
+
+    match &options.output_dir {
+        Some(dir) => {
+            let path = dir.join(MANIFEST_FILE);
+            std::fs::write(&path, manifest)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            info!(
+                "Wrote outfit and {} items to {}",
+                bundle.items.len(),
+                dir.display()
+            );
+        }
+        None => println!("{}", manifest),
+    }
+
+    info!("Completed in {:?}", start.elapsed());
+
+    Ok(())
+}
+
+/// Write one composite of the bundle, if there is an output directory
+fn entry(options: &BundleOptions, stem: &str, rendered: &RenderedComposite) -> Result<Entry> {
+    if rendered.found_layers < rendered.requested_layers {
+        warn!(
+            "[{}] Found {}/{} requested layers",
+            stem, rendered.found_layers, rendered.requested_layers
+        );
+    }
+    for missing in &rendered.missing {
+        warn!("[{}] Missing {}", stem, missing);
+    }
+
+    let file = match &options.output_dir {
+        Some(dir) => {
+            let name = format!("{}.{}", stem, rendered.format.extension());
+            if let Output::Written(path) =
+                write_output(dir, &name, &rendered.data, CollisionPolicy::Overwrite)?
+            {
+                info!("Wrote {}", path.display());
+            }
+            Some(name)
+        }
+        None => None,
+    };
+
+    Ok(Entry {
+        category: None,
+        sku: None,
+        cache_key: rendered.cache_key.clone(),
+        cached: rendered.cached,
+        file,
+    })
+}
@@ -1,107 +1,184 @@
 use anyhow::{Context, Result};
-use birl_core::{compose_layers, generate_cache_key, parse_params, LayerNormalizer, View};
-use birl_storage::StorageService;
+use birl_core::{
+    parse_params, BatchError, CollisionPolicy, CompositeOptions, DisplayNames, LayerParam,
+    NamingContext, NamingTemplate, View,
+};
+use birl_storage::{RenderedComposite, StorageService};
+use futures::future::join_all;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tracing::{info, warn};
+use tracing::{error, info, warn};
+
+use super::batch::{write_output, Done, Output, StatusFile, STATUS_FILE};
 
 pub struct ComposeOptions {
-    pub view: View,
+    /// Views to render, concurrently when more than one
+    pub views: Vec<View>,
     pub params: String,
+    /// Output file (single view only)
     pub output: Option<String>,
+    /// Directory to write one composite per view into
+    pub output_dir: Option<PathBuf>,
+    /// Path of each composite relative to `output_dir`
+    pub naming: NamingTemplate,
+    pub options: CompositeOptions,
     pub bypass_cache: bool,
 }
 
 pub async fn compose_command(storage: Arc<StorageService>, options: ComposeOptions) -> Result<()> {
     let start = std::time::Instant::now();
 
+    if options.views.len() > 1 && options.output.is_some() {
+        anyhow::bail!("--output takes a single view; use --output-dir with several views");
+    }
+
+    let views: Vec<&str> = options.views.iter().map(View::as_str).collect();
     info!(
-        "Composing image: view={}, params={}",
-        options.view.as_str(),
+        "Composing image: views={}, params={}",
+        views.join(","),
         options.params
     );
 
-    // Fetch base plate
-    let base_image_data = storage
-        .fetch_base_plate(options.view)
-        .await
-        .context("Failed to fetch base plate")?;
-
-    // Parse and normalize parameters
     let params = parse_params(&options.params);
-    let normalizer = LayerNormalizer::new(options.view, &params);
-    let normalized_params = normalizer.normalize_all(&params);
-
-    info!("Normalized to {} layers", normalized_params.len());
+    let names = DisplayNames::global();
+    for layer in &params {
+        if let Some(name) = names.name(layer) {
+            info!("  {}: {}", layer, name);
+        }
+    }
 
-    // Generate cache key
-    let cache_key = generate_cache_key(
-        &normalized_params,
-        options.view,
-        options.view.plate_value(),
-    );
+    // With an output directory, views done in an earlier run aren't rendered again
+    let mut status = match &options.output_dir {
+        Some(dir) => Some(StatusFile::load(dir.join(STATUS_FILE))?),
+        None => None,
+    };
+    let mut pending = Vec::new();
+    for &view in &options.views {
+        let cache_key = storage.cache_key(&params, view, &options.options);
+        if !options.bypass_cache && status.as_ref().is_some_and(|s| s.is_done(&cache_key)) {
+            info!("[{}] Done in an earlier run: {}", view, cache_key);
+        } else {
+            pending.push((view, cache_key));
+        }
+    }
 
-    // Check cache (unless bypassing)
-    if !options.bypass_cache {
-        if let Some(cached_data) = storage.get_cached_composite(&cache_key).await? {
-            info!("Found cached composite: {}", cache_key);
-
-            if let Some(output_path) = &options.output {
-                std::fs::write(output_path, cached_data)
-                    .context("Failed to write output file")?;
-                info!("Wrote cached image to {}", output_path);
-            } else {
-                println!("Cache hit: {}.jpg", cache_key);
-            }
-
-            info!("Completed in {:?} (cached)", start.elapsed());
-            return Ok(());
+    let renders = pending.iter().map(|&(view, _)| {
+        let storage = &storage;
+        let params = &params;
+        let options = &options;
+        async move {
+            let rendered = storage
+                .render(params, view, &options.options, options.bypass_cache)
+                .await
+                .with_context(|| format!("Failed to compose layers for {} view", view))?;
+            report(view, &rendered);
+            write(options, params, view, &rendered)
+        }
+    });
+    let results = join_all(renders).await;
+
+    let mut failures = Vec::new();
+    for ((view, cache_key), result) in pending.iter().zip(results) {
+        let done = match &result {
+            Ok(output) => Ok(Done {
+                output: output.clone(),
+            }),
+            Err(e) => Err(BatchError::render(format!("{:#}", e))),
+        };
+        if let Some(status) = &mut status {
+            status.record(cache_key, done);
+        }
+        if let Err(e) = result {
+            error!("[{}] {:#}", view, e);
+            failures.push(e);
         }
     }
+    if failures.len() == 1 {
+        return Err(failures.remove(0));
+    } else if !failures.is_empty() {
+        anyhow::bail!("{} of {} views failed", failures.len(), pending.len());
+    }
 
-    // Fetch layers in parallel
-    let layers_result = storage
-        .fetch_layers(&normalized_params, options.view)
-        .await?;
+    if let Some(stats) = storage.sharing_stats() {
+        info!(
+            "Shared across views: {}/{} fetches reused, {}/{} fetched bytes duplicated, {}/{} decodes reused",
+            stats.shared_fetches(),
+            stats.requests,
+            stats.duplicate_bytes,
+            stats.fetched_bytes,
+            stats.decode_hits,
+            stats.decode_hits + stats.decodes
+        );
+    }
 
-    // Filter out None values
-    let layers: Vec<_> = layers_result.into_iter().flatten().collect();
+    info!("Completed in {:?}", start.elapsed());
 
-    let requested_count = normalized_params.len();
-    let found_count = layers.len();
+    Ok(())
+}
 
-    if found_count < requested_count {
+fn report(view: View, rendered: &RenderedComposite) {
+    if rendered.cached {
+        info!("[{}] Found cached composite: {}", view, rendered.cache_key);
+    } else if rendered.found_layers < rendered.requested_layers {
         warn!(
-            "Found {}/{} requested layers",
-            found_count, requested_count
+            "[{}] Found {}/{} requested layers",
+            view, rendered.found_layers, rendered.requested_layers
         );
     } else {
-        info!("Fetched all {} layers", found_count);
+        info!("[{}] Fetched all {} layers", view, rendered.found_layers);
     }
-
-    // Compose the image
-    info!("Compositing layers...");
-    let composite_data = compose_layers(&base_image_data, layers)
-        .context("Failed to compose layers")?;
-
-    // Save to cache if all layers were found
-    if requested_count == found_count {
-        storage
-            .save_composite(&cache_key, composite_data.clone())
-            .await
-            .context("Failed to save to cache")?;
-        info!("Saved to cache: {}", cache_key);
+    for conflict in &rendered.conflicts {
+        warn!("[{}] {}", view, conflict);
+    }
+    for violation in &rendered.violations {
+        warn!("[{}] {}", view, violation);
+    }
+    for missing in &rendered.missing {
+        warn!("[{}] Missing {}", view, missing);
+    }
+    for dropped in &rendered.dropped {
+        warn!("[{}] {}", view, dropped);
+    }
+    for attribution in &rendered.attributions {
+        info!("[{}] Attribution: {}", view, attribution);
     }
+}
+
+/// Write or print the composite; returns the file written into the output directory
+fn write(
+    options: &ComposeOptions,
+    params: &[LayerParam],
+    view: View,
+    rendered: &RenderedComposite,
+) -> Result<Option<PathBuf>> {
+    let filename = format!("{}.{}", rendered.cache_key, rendered.format.extension());
 
-    // Write output file
     if let Some(output_path) = &options.output {
-        std::fs::write(output_path, &composite_data)
-            .context("Failed to write output file")?;
+        std::fs::write(output_path, &rendered.data).context("Failed to write output file")?;
         info!("Wrote image to {}", output_path);
+    } else if let Some(dir) = &options.output_dir {
+        let name = options.naming.render(&NamingContext {
+            params,
+            view,
+            format: rendered.format,
+            cache_key: &rendered.cache_key,
+            index: 0,
+        });
+        if let Output::Written(path) =
+            write_output(dir, &name, &rendered.data, CollisionPolicy::Overwrite)?
+        {
+            info!("Wrote {} view to {}", view, path.display());
+            return Ok(Some(path));
+        }
+    } else if rendered.cached {
+        println!("Cache hit: {}", filename);
     } else {
-        println!("Composite created: {}.jpg ({} bytes)", cache_key, composite_data.len());
+        println!(
+            "Composite created: {} ({} bytes)",
+            filename,
+            rendered.data.len()
+        );
     }
 
-    info!("Completed in {:?}", start.elapsed());
-
-    Ok(())
+    Ok(None)
 }
@@ -0,0 +1,41 @@
+use anyhow::{Context, Result};
+use birl_core::{CompositeOptions, View};
+use birl_storage::StorageService;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use super::examples::get_example;
+
+pub struct DemoOptions {
+    pub example: String,
+    pub view: View,
+    pub output: PathBuf,
+}
+
+/// Render an example against synthetic assets, no storage required
+pub async fn demo_command(storage: Arc<StorageService>, options: DemoOptions) -> Result<()> {
+    let example = get_example(&options.example)
+        .ok_or_else(|| anyhow::anyhow!("Example '{}' not found", options.example))?;
+    println!(
+        "Rendering example: {} - {}",
+        options.example, example.description
+    );
+
+    let params = example.to_params();
+    let rendered = storage
+        .render(&params, options.view, &CompositeOptions::default(), false)
+        .await
+        .context("Failed to render demo composite")?;
+
+    std::fs::write(&options.output, &rendered.data).context("Failed to write demo output")?;
+
+    println!(
+        "Demo composite written to {} ({} bytes, {}/{} layers)",
+        options.output.display(),
+        rendered.data.len(),
+        rendered.found_layers,
+        rendered.requested_layers
+    );
+
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use anyhow::{Context, Result};
+use birl_core::diff::diff_composites_with_threshold;
+use std::path::PathBuf;
+
+pub struct DiffOptions {
+    pub before: PathBuf,
+    pub after: PathBuf,
+    /// Where to write the visual diff PNG
+    pub output: Option<PathBuf>,
+    /// Per-channel difference ignored as encoding noise
+    pub threshold: u8,
+    /// Fail when more than this percentage of pixels changed
+    pub max_changed: f64,
+}
+
+pub fn diff_command(options: DiffOptions) -> Result<()> {
+    let before = std::fs::read(&options.before)
+        .with_context(|| format!("Failed to read {}", options.before.display()))?;
+    let after = std::fs::read(&options.after)
+        .with_context(|| format!("Failed to read {}", options.after.display()))?;
+
+    let report = diff_composites_with_threshold(&before, &after, options.threshold)?;
+
+    println!(
+        "Changed pixels: {}/{} ({:.3}%)",
+        report.changed_pixels,
+        report.total_pixels,
+        report.changed_percent()
+    );
+
+    if let Some(output) = &options.output {
+        std::fs::write(output, &report.diff_image).context("Failed to write diff image")?;
+        println!("Diff image written to {}", output.display());
+    }
+
+    if report.changed_percent() > options.max_changed {
+        anyhow::bail!(
+            "{:.3}% of pixels changed (max {:.3}%)",
+            report.changed_percent(),
+            options.max_changed
+        );
+    }
+
+    Ok(())
+}
@@ -1,7 +1,23 @@
+pub mod batch;
 pub mod bench;
+pub mod bundle;
 pub mod compose;
+pub mod demo;
+pub mod diff;
 pub mod examples;
+pub mod replay;
+pub mod report;
+pub mod rollups;
+pub mod validate;
 
+pub use batch::batch_command;
 pub use bench::run_benchmarks;
+pub use bundle::bundle_command;
 pub use compose::compose_command;
+pub use demo::demo_command;
+pub use diff::diff_command;
 pub use examples::list_examples;
+pub use replay::replay_command;
+pub use report::coverage_command;
+pub use rollups::rollups_command;
+pub use validate::validate_command;
@@ -0,0 +1,82 @@
+use anyhow::Result;
+use birl_storage::{ReplaySummary, StorageService};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub struct ReplayOptions {
+    /// Capture to replay (`cache/replay/{name}.json`)
+    pub name: String,
+    /// Replay at most this many records
+    pub limit: Option<usize>,
+    /// Write every outcome as JSON to this path
+    pub output: Option<PathBuf>,
+    /// Fail if the median render is more than this many percent slower
+    pub max_slowdown: Option<f64>,
+}
+
+pub async fn replay_command(storage: Arc<StorageService>, options: ReplayOptions) -> Result<()> {
+    let mut records = storage.fetch_replay(&options.name).await?;
+    if let Some(limit) = options.limit {
+        records.truncate(limit);
+    }
+    println!(
+        "Replaying {} requests from '{}'",
+        records.len(),
+        options.name
+    );
+
+    // One at a time, so latencies aren't skewed by renders competing
+    let mut outcomes = Vec::with_capacity(records.len());
+    for (idx, record) in records.iter().enumerate() {
+        let outcome = storage.replay(record).await;
+        if !outcome.matches() {
+            let params: Vec<String> = record.params.iter().map(ToString::to_string).collect();
+            let problem = match &outcome.error {
+                Some(error) => format!("failed: {}", error),
+                None => format!("{} -> {}", record.output_hash, outcome.output_hash),
+            };
+            println!(
+                "  #{} {} [{}]: {}",
+                idx,
+                params.join(","),
+                record.view,
+                problem
+            );
+        }
+        outcomes.push(outcome);
+    }
+
+    let summary = ReplaySummary::new(&outcomes);
+    println!(
+        "Matched {}/{} ({} different, {} failed)",
+        summary.matched, summary.records, summary.mismatched, summary.failed
+    );
+    if let (Some(captured), Some(replayed)) =
+        (summary.captured_median_ms, summary.replayed_median_ms)
+    {
+        println!(
+            "Median render: {} ms captured, {} ms replayed",
+            captured, replayed
+        );
+    }
+
+    if let Some(output) = &options.output {
+        std::fs::write(output, serde_json::to_string_pretty(&outcomes)?)?;
+        println!("Outcomes written to {}", output.display());
+    }
+
+    if summary.matched < summary.records {
+        anyhow::bail!(
+            "{} of {} replayed requests differ from the capture",
+            summary.records - summary.matched,
+            summary.records
+        );
+    }
+    if let (Some(max), Some(slowdown)) = (options.max_slowdown, summary.slowdown_percent()) {
+        if slowdown > max {
+            anyhow::bail!("Median render is {:.1}% slower (max {:.1}%)", slowdown, max);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use anyhow::{Context, Result};
+use birl_core::{Catalog, CategoryRegistry, Cell, Coverage};
+use birl_storage::StorageService;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Output format of the coverage report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+    Html,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "csv" => Ok(ReportFormat::Csv),
+            "json" => Ok(ReportFormat::Json),
+            "html" => Ok(ReportFormat::Html),
+            _ => Err(format!(
+                "Invalid report format: {}. Must be one of: csv, json, html",
+                s
+            )),
+        }
+    }
+}
+
+pub struct CoverageOptions {
+    pub format: ReportFormat,
+    /// Write the report here instead of stdout
+    pub output: Option<PathBuf>,
+}
+
+pub async fn coverage_command(
+    storage: Arc<StorageService>,
+    options: CoverageOptions,
+) -> Result<()> {
+    let paths = storage.list_assets().await?;
+    let coverage = Coverage::from_paths(
+        paths.iter().map(String::as_str),
+        &CategoryRegistry::global(),
+        storage.catalog(),
+    );
+
+    let report = match options.format {
+        ReportFormat::Csv => to_csv(&coverage),
+        ReportFormat::Json => serde_json::to_string_pretty(&coverage)?,
+        ReportFormat::Html => to_html(&coverage, storage.catalog()),
+    };
+
+    match &options.output {
+        Some(output) => {
+            std::fs::write(output, report)
+                .with_context(|| format!("Failed to write {}", output.display()))?;
+            println!(
+                "{} SKUs, {} missing views, {} orphans; report written to {}",
+                coverage.rows.len(),
+                coverage.incomplete().count(),
+                coverage.orphans.len(),
+                output.display()
+            );
+        }
+        None => print!("{}", report),
+    }
+
+    Ok(())
+}
+
+fn cell_label(cell: Cell) -> &'static str {
+    match cell {
+        Cell::Present => "present",
+        Cell::Missing => "missing",
+        Cell::Unexpected => "orphan",
+        Cell::NotApplicable => "",
+    }
+}
+
+/// One row per SKU: category, sku, a column per view, then the missing views
+fn to_csv(coverage: &Coverage) -> String {
+    let mut csv = String::from("category,sku");
+    for view in &coverage.views {
+        write!(csv, ",{}", view).unwrap();
+    }
+    csv.push_str(",missing\n");
+
+    for row in &coverage.rows {
+        write!(csv, "{},{}", csv_field(&row.category), csv_field(&row.sku)).unwrap();
+        for view in &coverage.views {
+            write!(csv, ",{}", cell_label(row.cell(*view))).unwrap();
+        }
+        let missing: Vec<&str> = row.missing().iter().map(|view| view.as_str()).collect();
+        writeln!(csv, ",{}", csv_field(&missing.join(" "))).unwrap();
+    }
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Standalone page with the matrix and the orphans, gaps highlighted
+fn to_html(coverage: &Coverage, catalog: Option<&Catalog>) -> String {
+    let mut html = String::from(concat!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+        "<title>Catalog coverage</title>\n<style>\n",
+        "body { font-family: sans-serif; }\n",
+        "table { border-collapse: collapse; }\n",
+        "th, td { border: 1px solid #ccc; padding: 2px 8px; }\n",
+        ".present { background: #d4f4d4; }\n",
+        ".missing { background: #f8c8c8; }\n",
+        ".orphan { background: #fbe3b0; }\n",
+        "</style>\n</head>\n<body>\n",
+    ));
+
+    writeln!(
+        html,
+        "<h1>Catalog coverage</h1>\n<p>{} SKUs ({}), {} with missing views, {} orphans</p>",
+        coverage.rows.len(),
+        if catalog.is_some() {
+            "expected views from the catalog"
+        } else {
+            "expected views from the categories"
+        },
+        coverage.incomplete().count(),
+        coverage.orphans.len()
+    )
+    .unwrap();
+
+    html.push_str("<table>\n<tr><th>Category</th><th>SKU</th>");
+    for view in &coverage.views {
+        write!(html, "<th>{}</th>", view).unwrap();
+    }
+    html.push_str("</tr>\n");
+    for row in &coverage.rows {
+        write!(
+            html,
+            "<tr><td>{}</td><td>{}</td>",
+            escape(&row.category),
+            escape(&row.sku)
+        )
+        .unwrap();
+        for view in &coverage.views {
+            let label = cell_label(row.cell(*view));
+            write!(html, "<td class=\"{}\">{}</td>", label, label).unwrap();
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+
+    if !coverage.orphans.is_empty() {
+        html.push_str("<h2>Orphans</h2>\n<ul>\n");
+        for (view, count) in coverage.orphans_by_view() {
+            writeln!(html, "<li>{}: {}</li>", view, count).unwrap();
+        }
+        html.push_str("</ul>\n<table>\n<tr><th>Path</th><th>Reason</th></tr>\n");
+        for orphan in &coverage.orphans {
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td></tr>",
+                escape(&orphan.path),
+                escape(&orphan.reason)
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n");
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
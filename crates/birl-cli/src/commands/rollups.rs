@@ -0,0 +1,73 @@
+use anyhow::Result;
+use birl_storage::{DailyRollup, Day, StorageService};
+use std::sync::Arc;
+
+/// Longest range printed at once
+const MAX_DAYS: usize = 366;
+
+pub struct RollupsOptions {
+    pub from: Day,
+    /// Last day of the range (default: `from`)
+    pub to: Option<Day>,
+    /// Outfits listed for the whole range
+    pub top: usize,
+    /// Print the rollups as JSON instead
+    pub json: bool,
+}
+
+pub async fn rollups_command(storage: Arc<StorageService>, options: RollupsOptions) -> Result<()> {
+    let to = options.to.unwrap_or(options.from);
+    if to < options.from {
+        anyhow::bail!("--to {} is before --from {}", to, options.from);
+    }
+    let days: Vec<Day> = std::iter::successors(Some(options.from), |day| Some(day.next()))
+        .take_while(|day| *day <= to)
+        .take(MAX_DAYS + 1)
+        .collect();
+    if days.len() > MAX_DAYS {
+        anyhow::bail!("At most {} days can be printed at once", MAX_DAYS);
+    }
+
+    let mut rollups = Vec::with_capacity(days.len());
+    for day in days {
+        rollups.push(storage.fetch_rollup(day).await?);
+    }
+
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&rollups)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:<12} {:>10} {:>10} {:>8} {:>11} {:>8}",
+        "Date", "Requests", "Hit ratio", "Errors", "Incomplete", "Missing"
+    );
+    let mut total = DailyRollup::new(options.from);
+    for rollup in &rollups {
+        print_row(&rollup.date.to_string(), rollup);
+        total.merge(rollup);
+    }
+    if rollups.len() > 1 {
+        print_row("Total", &total);
+    }
+
+    let top = total.top_outfits(options.top);
+    if !top.is_empty() {
+        println!("\nTop outfits:");
+        for (outfit, count) in top {
+            println!("  {:>8}  {}", count, outfit);
+        }
+    }
+    Ok(())
+}
+
+fn print_row(label: &str, rollup: &DailyRollup) {
+    let ratio = match rollup.cache_hit_ratio() {
+        Some(ratio) => format!("{:.1}%", ratio * 100.0),
+        None => "-".to_string(),
+    };
+    println!(
+        "{:<12} {:>10} {:>10} {:>8} {:>11} {:>8}",
+        label, rollup.requests, ratio, rollup.errors, rollup.incomplete, rollup.missing_layers
+    );
+}
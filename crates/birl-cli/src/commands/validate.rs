@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use birl_core::{convert_image, AssetFile, CategoryRegistry, LintReport, Linter, Severity};
+use std::path::PathBuf;
+use std::sync::Arc;
+use walkdir::WalkDir;
+
+/// Directory of cached composites, which isn't part of an asset drop
+const CACHE_DIR: &str = "cache";
+
+pub struct ValidateOptions {
+    /// Asset root laid out as {view}/{category}/{sku}.{ext}
+    pub dir: PathBuf,
+    pub linter: Linter,
+    /// Print the report as JSON instead of text
+    pub json: bool,
+    /// Fail when a finding is at least this severe
+    pub fail_on: Severity,
+    /// Re-encode images stored in another format than their category's
+    /// (`[formats]`), replacing the original, before linting
+    pub convert: Option<Arc<CategoryRegistry>>,
+}
+
+pub fn validate_command(options: ValidateOptions) -> Result<()> {
+    let mut report = LintReport::default();
+
+    let entries = WalkDir::new(&options.dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| !entry.file_name().to_string_lossy().starts_with('.'));
+    for entry in entries {
+        let entry = entry.context("Failed to list assets")?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let path = entry
+            .path()
+            .strip_prefix(&options.dir)
+            .unwrap_or(entry.path())
+            .to_string_lossy()
+            .replace('\\', "/");
+        if path.starts_with(&format!("{}/", CACHE_DIR)) {
+            continue;
+        }
+
+        let mut data = std::fs::read(entry.path())
+            .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+        let mut asset = AssetFile::new(&path, &data);
+        if let Some(format) = options
+            .convert
+            .as_deref()
+            .and_then(|registry| asset.conversion(registry))
+        {
+            let converted_path = asset.converted_path(format);
+            let target = options.dir.join(&converted_path);
+            if target.exists() {
+                anyhow::bail!("Can't convert {}: {} already exists", path, converted_path);
+            }
+            data = convert_image(&data, format)
+                .with_context(|| format!("Failed to convert {}", path))?
+                .to_vec();
+            std::fs::write(&target, &data)
+                .with_context(|| format!("Failed to write {}", target.display()))?;
+            std::fs::remove_file(entry.path())
+                .with_context(|| format!("Failed to remove {}", entry.path().display()))?;
+            if !options.json {
+                println!("Converted {} -> {}", path, converted_path);
+            }
+            asset = AssetFile::new(&converted_path, &data);
+        }
+        report.add(&options.linter, &asset);
+    }
+
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for finding in &report.findings {
+            println!(
+                "{:<7} {} [{}] {}",
+                finding.severity, finding.path, finding.check, finding.message
+            );
+        }
+        println!(
+            "Checked {} files: {} errors, {} warnings, {} info",
+            report.files,
+            report.count(Severity::Error),
+            report.count(Severity::Warning),
+            report.count(Severity::Info)
+        );
+    }
+
+    if report.fails(options.fail_on) {
+        anyhow::bail!(
+            "Asset validation failed (findings at {} or above)",
+            options.fail_on
+        );
+    }
+
+    Ok(())
+}
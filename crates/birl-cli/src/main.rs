@@ -1,11 +1,17 @@
 mod commands;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
-use birl_core::View;
-use birl_storage::StorageService;
+use clap::{Args, Parser, Subcommand};
+use birl_core::{
+    Background, Capacity, CategoryRegistry, CollisionPolicy, CompositeOptions, Filter, Linter,
+    NamingTemplate, OutputFormat, Presets, ResizeFilter, Severity, View,
+};
+use birl_storage::{
+    Config, Day, DiskLayerCache, StorageService, WriteLimitSettings, DEFAULT_DISK_CACHE_TTL,
+};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::Level;
 use tracing_subscriber::FmtSubscriber;
 
@@ -23,14 +29,136 @@ struct Cli {
     /// Use local filesystem instead of S3 (path to directory containing birl/)
     #[arg(short, long, global = true)]
     local: Option<PathBuf>,
+
+    /// Config file with named profiles (default: birl.toml if present)
+    #[arg(long, global = true, env = birl_storage::config::CONFIG_ENV)]
+    config: Option<PathBuf>,
+
+    /// Profile from the config file (dev, staging, prod, ...)
+    #[arg(long, global = true, env = birl_storage::config::PROFILE_ENV)]
+    profile: Option<String>,
+
+    /// Catalog manifest (JSON/TOML, or "storage") to check renders against
+    #[arg(long, global = true, env = "BIRL_CATALOG")]
+    catalog: Option<String>,
+
+    /// Presets file (JSON/TOML, or "storage") adding to the built-in examples
+    #[arg(long, global = true, env = "BIRL_PRESETS")]
+    presets: Option<String>,
+
+    /// Keep downloaded plates and layers in this directory across runs
+    #[arg(long, global = true, env = "BIRL_LAYER_CACHE")]
+    layer_cache: Option<PathBuf>,
+
+    /// Size cap of the layer cache in megabytes
+    #[arg(long, global = true, default_value_t = 512)]
+    layer_cache_max_mb: u64,
+
+    /// Fetch layers cached longer ago than this again, in seconds
+    #[arg(long, global = true, default_value_t = DEFAULT_DISK_CACHE_TTL.as_secs())]
+    layer_cache_ttl_secs: u64,
+
+    /// Limit storage writes to this many per second (overrides the profile)
+    #[arg(long, global = true, value_parser = parse_write_limit)]
+    write_limit: Option<f64>,
+
+    /// Writes let through at once before --write-limit applies
+    #[arg(long, global = true, requires = "write_limit", default_value_t = 100)]
+    write_burst: u32,
+}
+
+/// Rendering options shared by compose and batch
+#[derive(Args)]
+struct RenderArgs {
+    /// Post-processing filter (grayscale, sepia, brightness:<n>, watermark), repeatable
+    #[arg(long = "filter")]
+    filters: Vec<Filter>,
+
+    /// Output format (jpeg, png, webp, avif)
+    #[arg(long, default_value = "jpeg")]
+    format: OutputFormat,
+
+    /// Encoder quality (1-100) for JPEG and AVIF; defaults to the configured
+    /// quality curve
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: Option<u8>,
+
+    /// Downscale to fit within this width
+    #[arg(long)]
+    max_width: Option<u32>,
+
+    /// Downscale to fit within this height
+    #[arg(long)]
+    max_height: Option<u32>,
+
+    /// Resampling filter for downscaling (nearest, triangle, catmullrom, gaussian, lanczos3)
+    #[arg(long, default_value = "lanczos3")]
+    resize_filter: ResizeFilter,
+
+    /// Resampling filter for layers that don't match the plate size
+    #[arg(long, default_value = "lanczos3")]
+    layer_filter: ResizeFilter,
+
+    /// Fill transparent areas with this color (#rrggbb)
+    #[arg(long)]
+    background: Option<Background>,
+
+    /// Color transparent areas are flattened onto for JPEG output (#rrggbb, default white)
+    #[arg(long)]
+    matte: Option<Background>,
+
+    /// Plate (base model or body type) to render on instead of each view's own
+    #[arg(long, visible_alias = "model")]
+    plate: Option<String>,
+
+    /// Render the garments alone on a transparent image, without the plate
+    /// (PNG unless the format has alpha)
+    #[arg(long)]
+    cutout: bool,
+
+    /// Region the composites are shown in (e.g. DE), for licensed artwork
+    #[arg(long)]
+    region: Option<String>,
+}
+
+impl RenderArgs {
+    fn options(self) -> CompositeOptions {
+        let mut options = CompositeOptions::new()
+            .with_format(self.format)
+            .with_max_size(self.max_width, self.max_height)
+            .with_resize_filter(self.resize_filter)
+            .with_layer_filter(self.layer_filter)
+            .with_filters(self.filters);
+
+        if let Some(quality) = self.quality {
+            options = options.with_quality(quality);
+        }
+        if let Some(background) = self.background {
+            options = options.with_background(background);
+        }
+        if let Some(matte) = self.matte {
+            options = options.with_matte(matte);
+        }
+        if let Some(plate) = self.plate {
+            options = options.with_plate(plate);
+        }
+        if self.cutout {
+            options = options.with_cutout();
+        }
+        if let Some(region) = self.region {
+            options = options.with_region(region.trim().to_uppercase());
+        }
+
+        options
+    }
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Compose a single image
     Compose {
-        /// View to render (front, back, side, left, right)
-        #[arg(long, default_value = "front")]
+        /// Views to render: one, a comma-separated list, or "all"
+        #[arg(long, visible_alias = "views", default_value = "front")]
         view: String,
 
         /// Parameters: "category/sku,category/sku,..."
@@ -41,15 +169,194 @@ enum Commands {
         #[arg(short, long)]
         example: Option<String>,
 
-        /// Output file path
-        #[arg(short, long)]
+        /// Output file path (single view)
+        #[arg(short, long, conflicts_with = "output_dir")]
         output: Option<String>,
 
+        /// Directory to write one composite per view into
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+
+        /// Output path template for --output-dir, e.g. "{sku-slug}/{view}.{ext}"
+        #[arg(long, default_value = birl_core::naming::DEFAULT_NAMING_TEMPLATE)]
+        naming: NamingTemplate,
+
+        #[command(flatten)]
+        render: RenderArgs,
+
+        /// Bypass cache and force regeneration
+        #[arg(short, long)]
+        bypass_cache: bool,
+    },
+
+    /// Compose many outfits from a file, one parameter string per line
+    Batch {
+        /// Input file with "category/sku,..." on each line
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// View to render (front, back, side, left, right)
+        #[arg(long, default_value = "front")]
+        view: String,
+
+        /// Directory to write composites into
+        #[arg(short, long)]
+        output_dir: Option<PathBuf>,
+
+        /// Output path template, e.g. "{category-slug}/{sku-slug}/{view}.{ext}"
+        #[arg(long, default_value = birl_core::naming::DEFAULT_NAMING_TEMPLATE)]
+        naming: NamingTemplate,
+
+        /// What to do when an output file exists (overwrite, skip, version)
+        #[arg(long, default_value = "overwrite")]
+        on_collision: CollisionPolicy,
+
+        /// Per-item status manifest; items it lists as done are skipped on the next run
+        /// (default: .birl-status.json in the output directory)
+        #[arg(long)]
+        status: Option<PathBuf>,
+
+        #[command(flatten)]
+        render: RenderArgs,
+
+        /// Bypass cache and force regeneration, including items done in an earlier run
+        #[arg(short, long)]
+        bypass_cache: bool,
+
+        /// Estimate storage requests, bytes, CPU time and duration without rendering
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Measured costs for --dry-run, written by `bench --calibration`
+        #[arg(long, requires = "dry_run")]
+        calibration: Option<PathBuf>,
+
+        /// Renders in flight to project the --dry-run duration at (this command
+        /// renders one at a time; pass the /batch concurrency for server jobs)
+        #[arg(long, requires = "dry_run", default_value_t = 1)]
+        concurrency: usize,
+    },
+
+    /// Render an outfit plus each of its items alone on the plate
+    Bundle {
+        /// View to render (front, back, side, left, right)
+        #[arg(long, default_value = "front")]
+        view: String,
+
+        /// Parameters: "category/sku,category/sku,..."
+        #[arg(short, long)]
+        params: String,
+
+        /// Directory to write the composites and manifest.json into (default: print the manifest)
+        #[arg(short, long)]
+        output_dir: Option<PathBuf>,
+
+        #[command(flatten)]
+        render: RenderArgs,
+
         /// Bypass cache and force regeneration
         #[arg(short, long)]
         bypass_cache: bool,
     },
 
+    /// Compare two composites pixel by pixel
+    Diff {
+        /// Reference composite
+        before: PathBuf,
+
+        /// Composite to compare against the reference
+        after: PathBuf,
+
+        /// Write the visual diff (PNG) to this path
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Per-channel difference ignored as encoding noise
+        #[arg(long, default_value_t = birl_core::diff::DEFAULT_DIFF_THRESHOLD)]
+        threshold: u8,
+
+        /// Fail if more than this percentage of pixels changed
+        #[arg(long, default_value_t = 0.0)]
+        max_changed: f64,
+    },
+
+    /// Render an example with synthetic assets, no storage required
+    Demo {
+        /// Example to render
+        #[arg(short, long, default_value = "full-outfit")]
+        example: String,
+
+        /// View to render (front, back, side, left, right)
+        #[arg(long, default_value = "front")]
+        view: String,
+
+        /// Output file path
+        #[arg(short, long, default_value = "birl-demo.jpg")]
+        output: PathBuf,
+    },
+
+    /// Lint an asset drop against the [[lint]] rules of the config
+    Validate {
+        /// Asset root (default: the --local path or the profile's local_path)
+        dir: Option<PathBuf>,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Fail on findings of this severity or worse (info, warning, error)
+        #[arg(long, default_value = "error")]
+        fail_on: Severity,
+
+        /// Re-encode images stored in another format than their category's
+        /// `[formats]` entry, replacing the originals
+        #[arg(long)]
+        convert: bool,
+    },
+
+    /// Re-render requests captured by a server and compare them with the capture
+    Replay {
+        /// Capture name (the profile's `[capture]` name)
+        name: String,
+
+        /// Replay at most this many requests
+        #[arg(long)]
+        limit: Option<usize>,
+
+        /// Write every outcome as JSON to this path
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Fail if the median render is more than this many percent slower
+        #[arg(long)]
+        max_slowdown: Option<f64>,
+    },
+
+    /// Print the daily request rollups written by servers
+    Rollups {
+        /// First day (YYYY-MM-DD, UTC)
+        #[arg(long)]
+        from: Day,
+
+        /// Last day (default: --from)
+        #[arg(long)]
+        to: Option<Day>,
+
+        /// Outfits listed for the whole range
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+
+        /// Print the rollups as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Reports over the assets in storage
+    Report {
+        #[command(subcommand)]
+        report: Report,
+    },
+
     /// List available examples
     Examples,
 
@@ -61,6 +368,33 @@ enum Commands {
         /// Output file for results (markdown format)
         #[arg(short, long)]
         output: Option<String>,
+
+        /// Parallel render counts to measure throughput at
+        #[arg(long, value_delimiter = ',', default_value = "1,2,4,8")]
+        concurrency: Vec<usize>,
+
+        /// Renders per concurrency level
+        #[arg(long, default_value_t = 32)]
+        ops: usize,
+
+        /// Also measure per-operation costs and write them here as JSON, for
+        /// `batch --dry-run --calibration`
+        #[arg(long)]
+        calibration: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum Report {
+    /// SKU x view availability, with missing views and orphan assets
+    Coverage {
+        /// Output format (csv, json, html)
+        #[arg(long, default_value = "csv")]
+        format: commands::report::ReportFormat,
+
+        /// Write the report to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
     },
 }
 
@@ -80,26 +414,75 @@ async fn main() -> Result<()> {
         .finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
-    // Create storage service (local or S3 based on --local flag)
-    let storage = if let Some(local_path) = &cli.local {
-        println!("Using local filesystem storage: {}", local_path.display());
-        Arc::new(StorageService::new_local(local_path.clone(), 1000))
-    } else {
-        // Load AWS configuration
-        let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
-        let s3_client = aws_sdk_s3::Client::new(&aws_config);
-
-        // Get bucket name from environment
-        let bucket_name = std::env::var("AWS_BUCKET_NAME")
-            .unwrap_or_else(|_| {
-                eprintln!("Warning: AWS_BUCKET_NAME not set, using default");
-                "birl-bucket".to_string()
-            });
+    // Resolve the profile, categories and rules; --local, --catalog and --presets
+    // override the profile
+    let config = Config::discover(cli.config.as_deref())?;
+    let mut profile = config.profile(cli.profile.as_deref())?;
+    if let Some(name) = &cli.profile {
+        println!("Using profile: {}", name);
+    }
+    let tables = config.tables()?;
+    tables.clone().install();
+    if let Some(local_path) = &cli.local {
+        profile.local_path = Some(local_path.clone());
+    }
+    if let Some(catalog) = &cli.catalog {
+        profile.catalog = Some(catalog.clone());
+    }
+    if let Some(presets) = &cli.presets {
+        profile.presets = Some(presets.clone());
+    }
+    if let Some(ops_per_sec) = cli.write_limit {
+        profile.write_limit = Some(WriteLimitSettings {
+            ops_per_sec,
+            burst: cli.write_burst,
+        });
+    }
 
-        println!("Using S3 storage: {}", bucket_name);
-        #[allow(deprecated)]
-        Arc::new(StorageService::new(s3_client, bucket_name, 1000))
+    // Create storage service (demo, local or S3 based on command and profile)
+    let storage = if matches!(cli.command, Commands::Demo { .. }) {
+        println!("Using synthetic demo assets");
+        let storage = StorageService::new_demo(100);
+        match profile.remote_layer_fetcher()? {
+            Some(fetcher) => storage.with_remote_layers(fetcher),
+            None => storage,
+        }
+    } else {
+        let mut storage = StorageService::from_profile(&profile).await?;
+        // Views rendered together fetch shared layers once
+        if let Commands::Compose { view, .. } = &cli.command {
+            if parse_views(view)?.len() > 1 {
+                storage = storage.with_shared_fetches();
+            }
+        }
+        match &cli.layer_cache {
+            Some(dir) => {
+                println!("Using layer cache: {}", dir.display());
+                let max_bytes = cli.layer_cache_max_mb * 1024 * 1024;
+                let ttl = Duration::from_secs(cli.layer_cache_ttl_secs);
+                storage.with_layer_cache(DiskLayerCache::new(dir, max_bytes).with_ttl(ttl))
+            }
+            None => storage,
+        }
+    };
+    let tables = config.with_aliases(tables, storage.catalog());
+    let tables = match storage.load_presets(profile.presets.as_deref()).await {
+        Ok(presets) => tables.with_presets(presets),
+        Err(e) => {
+            println!("Ignoring presets file: {:#}", e);
+            tables.with_presets(Presets::for_catalog(storage.catalog()))
+        }
     };
+    let tables = match storage.fetch_display_names().await {
+        Ok(Some(names)) => tables.with_names(names),
+        Ok(None) => tables,
+        Err(e) => {
+            println!("Ignoring products JSON: {:#}", e);
+            tables
+        }
+    };
+    tables.install();
+    let storage = Arc::new(storage);
 
     // Execute command
     match cli.command {
@@ -108,34 +491,181 @@ async fn main() -> Result<()> {
             params,
             example,
             output,
+            output_dir,
+            naming,
+            render,
             bypass_cache,
         } => {
             // Get parameters from example or direct input
             let params_string = if let Some(example_name) = example {
                 let example = commands::examples::get_example(&example_name)
                     .ok_or_else(|| anyhow::anyhow!("Example '{}' not found", example_name))?;
-                println!("Using example: {} - {}", example.name, example.description);
-                example.params.to_string()
+                println!("Using example: {} - {}", example_name, example.description);
+                example.params.clone()
             } else if let Some(p) = params {
                 p
             } else {
                 anyhow::bail!("Either --params or --example must be provided");
             };
 
-            // Parse view
-            let view = parse_view(&view)?;
+            // Parse views
+            let views = parse_views(&view)?;
 
             // Execute compose command
             let options = commands::compose::ComposeOptions {
-                view,
+                views,
                 params: params_string,
                 output,
+                output_dir,
+                naming,
+                options: render.options(),
                 bypass_cache,
             };
 
             commands::compose_command(storage, options).await?;
         }
 
+        Commands::Batch {
+            input,
+            view,
+            output_dir,
+            naming,
+            on_collision,
+            status,
+            render,
+            bypass_cache,
+            dry_run,
+            calibration,
+            concurrency,
+        } => {
+            let dry_run = dry_run.then_some(commands::batch::DryRun {
+                calibration,
+                concurrency,
+            });
+            let options = commands::batch::BatchOptions {
+                view: view.parse()?,
+                input,
+                output_dir,
+                naming,
+                on_collision,
+                options: render.options(),
+                bypass_cache,
+                status,
+                dry_run,
+            };
+
+            commands::batch_command(storage, options).await?;
+        }
+
+        Commands::Bundle {
+            view,
+            params,
+            output_dir,
+            render,
+            bypass_cache,
+        } => {
+            let options = commands::bundle::BundleOptions {
+                view: view.parse()?,
+                params,
+                output_dir,
+                options: render.options(),
+                bypass_cache,
+            };
+
+            commands::bundle_command(storage, options).await?;
+        }
+
+        Commands::Diff {
+            before,
+            after,
+            output,
+            threshold,
+            max_changed,
+        } => {
+            commands::diff_command(commands::diff::DiffOptions {
+                before,
+                after,
+                output,
+                threshold,
+                max_changed,
+            })?;
+        }
+
+        Commands::Demo {
+            example,
+            view,
+            output,
+        } => {
+            let options = commands::demo::DemoOptions {
+                example,
+                view: view.parse()?,
+                output,
+            };
+
+            commands::demo_command(storage, options).await?;
+        }
+
+        Commands::Validate {
+            dir,
+            json,
+            fail_on,
+            convert,
+        } => {
+            let dir = dir
+                .or_else(|| profile.local_path.clone())
+                .ok_or_else(|| anyhow::anyhow!("Pass the asset directory or --local"))?;
+            let linter = Linter::from_rules(
+                &config.lint_rules(),
+                &CategoryRegistry::global(),
+                storage.catalog(),
+            )?;
+
+            commands::validate_command(commands::validate::ValidateOptions {
+                dir,
+                linter,
+                json,
+                fail_on,
+                convert: convert.then(CategoryRegistry::global),
+            })?;
+        }
+
+        Commands::Replay {
+            name,
+            limit,
+            output,
+            max_slowdown,
+        } => {
+            let options = commands::replay::ReplayOptions {
+                name,
+                limit,
+                output,
+                max_slowdown,
+            };
+            commands::replay_command(storage, options).await?;
+        }
+
+        Commands::Rollups {
+            from,
+            to,
+            top,
+            json,
+        } => {
+            let options = commands::rollups::RollupsOptions {
+                from,
+                to,
+                top,
+                json,
+            };
+            commands::rollups_command(storage, options).await?;
+        }
+
+        Commands::Report {
+            report: Report::Coverage { format, output },
+        } => {
+            let options = commands::report::CoverageOptions { format, output };
+            commands::coverage_command(storage, options).await?;
+        }
+
         Commands::Examples => {
             commands::list_examples();
         }
@@ -144,24 +674,100 @@ async fn main() -> Result<()> {
             let stats = storage.cache_stats().await;
             println!("Cache Statistics:");
             println!("  Memory entries: {}", stats.memory_entries);
-            println!("  Memory capacity: {}", stats.memory_capacity);
+            match stats.memory.capacity {
+                Capacity::Bytes(max) => println!(
+                    "  Memory size: {:.1} / {} MB",
+                    stats.memory.bytes as f64 / (1024.0 * 1024.0),
+                    max / (1024 * 1024)
+                ),
+                _ => println!("  Memory capacity: {}", stats.memory_capacity),
+            }
+            println!(
+                "  Memory hits: {} ({:.0}%), misses: {}, evictions: {}",
+                stats.memory.hits,
+                stats.memory.hit_rate() * 100.0,
+                stats.memory.misses,
+                stats.memory.evictions
+            );
+            if let Some(ttl) = stats.ttl {
+                println!(
+                    "  TTL: {}s, expired: {}",
+                    ttl.as_secs(),
+                    stats.memory.expirations
+                );
+            }
+            if let Some(disk) = stats.disk {
+                println!("Disk Composite Cache:");
+                println!("  Blobs: {}", disk.blobs);
+                println!(
+                    "  Size: {:.1} / {} MB",
+                    disk.bytes as f64 / (1024.0 * 1024.0),
+                    disk.max_bytes / (1024 * 1024)
+                );
+            }
+
+            if let Some(layers) = storage.layer_memory_stats() {
+                println!("Layer Memory Cache:");
+                println!("  Layers: {}", layers.entries);
+                if let Capacity::Bytes(max) = layers.capacity {
+                    println!(
+                        "  Size: {:.1} / {} MB",
+                        layers.bytes as f64 / (1024.0 * 1024.0),
+                        max / (1024 * 1024)
+                    );
+                }
+            }
+            if let Some(disk) = storage.layer_cache_stats().await? {
+                println!("Layer Cache:");
+                println!("  Blobs: {}", disk.blobs);
+                println!(
+                    "  Size: {:.1} / {} MB",
+                    disk.bytes as f64 / (1024.0 * 1024.0),
+                    disk.max_bytes / (1024 * 1024)
+                );
+            }
         }
 
-        Commands::Bench { output } => {
-            commands::run_benchmarks(storage, output).await?;
+        Commands::Bench {
+            output,
+            concurrency,
+            ops,
+            calibration,
+        } => {
+            let options = commands::bench::BenchOptions {
+                output,
+                calibration,
+                concurrency,
+                ops,
+            };
+            commands::run_benchmarks(storage, options).await?;
         }
     }
 
     Ok(())
 }
 
-fn parse_view(view_str: &str) -> Result<View> {
-    match view_str.to_lowercase().as_str() {
-        "front" => Ok(View::Front),
-        "back" => Ok(View::Back),
-        "side" => Ok(View::Side),
-        "left" => Ok(View::Left),
-        "right" => Ok(View::Right),
-        _ => anyhow::bail!("Invalid view: {}. Must be one of: front, back, side, left, right", view_str),
+/// A --write-limit rate, which must be above 0
+fn parse_write_limit(rate: &str) -> Result<f64, String> {
+    match rate.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        Ok(rate) => Err(format!("must be above 0, got {}", rate)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parse "front", "front,back,left" or "all"
+fn parse_views(views_str: &str) -> Result<Vec<View>> {
+    if views_str.eq_ignore_ascii_case("all") {
+        return Ok(View::all());
+    }
+
+    let mut views = Vec::new();
+    for view in views_str.split(',').map(str::trim) {
+        let view: View = view.parse()?;
+        if !views.contains(&view) {
+            views.push(view);
+        }
     }
+    Ok(views)
 }
@@ -0,0 +1,147 @@
+//! Composition pipeline benchmarks on synthetic assets
+//!
+//! Run with `cargo bench -p birl-core`; compare against a saved baseline with
+//! `cargo bench -p birl-core -- --save-baseline main` / `--baseline main`.
+
+use birl_core::demo::{synthetic_layer, synthetic_plate};
+use birl_core::{
+    generate_cache_key, parse_params, Compositor, LayerNormalizer, OutputFormat, ResizeFilter, View,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const OUTFIT: &str = "hoodies/hoodie-black-xl,pants/cargo-darkgreen-40,hats/beanie-black,\
+                      jackets/softshell-grey,patches-left/flag-patch-red,gloves/ski-black";
+
+fn bench_params(c: &mut Criterion) {
+    c.bench_function("parse_params", |b| {
+        b.iter(|| parse_params(black_box(OUTFIT)))
+    });
+
+    let params = parse_params(OUTFIT);
+    c.bench_function("normalize", |b| {
+        b.iter(|| {
+            let normalizer = LayerNormalizer::new(View::Front, black_box(&params));
+            normalizer.normalize_all(&params)
+        })
+    });
+
+    let normalized = LayerNormalizer::new(View::Front, &params).normalize_all(&params);
+    c.bench_function("cache_key", |b| {
+        b.iter(|| generate_cache_key(black_box(&normalized), View::Front, "base-model-black"))
+    });
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let plate = synthetic_plate(View::Front).unwrap();
+    let layer = synthetic_layer("hoodies", "hoodie-black").unwrap();
+
+    let mut group = c.benchmark_group("decode");
+    group.bench_function("plate_jpeg", |b| {
+        b.iter(|| image::load_from_memory(black_box(&plate)).unwrap())
+    });
+    group.bench_function("layer_png", |b| {
+        b.iter(|| image::load_from_memory(black_box(&layer)).unwrap())
+    });
+    group.finish();
+}
+
+fn bench_overlay(c: &mut Criterion) {
+    let plate = image::load_from_memory(&synthetic_plate(View::Front).unwrap()).unwrap();
+    let layers: Vec<_> = parse_params(OUTFIT)
+        .iter()
+        .map(|p| {
+            image::load_from_memory(&synthetic_layer(&p.category, p.sku.as_str()).unwrap()).unwrap()
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("overlay");
+    for count in [1, 3, layers.len()] {
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, &count| {
+            b.iter(|| {
+                let mut base = plate.clone();
+                for layer in &layers[..count] {
+                    image::imageops::overlay(&mut base, layer, 0, 0);
+                }
+                base
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_layer_resize(c: &mut Criterion) {
+    let plate = image::load_from_memory(&synthetic_plate(View::Front).unwrap()).unwrap();
+    // Half-resolution layer, so every overlay has to upscale it
+    let layer = image::load_from_memory(&synthetic_layer("hoodies", "hoodie-black").unwrap())
+        .unwrap()
+        .thumbnail_exact(plate.width() / 2, plate.height() / 2);
+
+    let mut group = c.benchmark_group("layer_resize");
+    for filter in [
+        ResizeFilter::Nearest,
+        ResizeFilter::Triangle,
+        ResizeFilter::CatmullRom,
+        ResizeFilter::Lanczos3,
+    ] {
+        group.bench_function(filter.as_str(), |b| {
+            b.iter_batched(
+                || (Compositor::from_image(plate.clone()), layer.clone()),
+                |(compositor, layer)| {
+                    let mut compositor = compositor.with_layer_filter(filter);
+                    compositor.overlay(layer);
+                    compositor
+                },
+                criterion::BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let plate = synthetic_plate(View::Front).unwrap();
+    let layers: Vec<_> = parse_params(OUTFIT)
+        .iter()
+        .map(|p| synthetic_layer(&p.category, p.sku.as_str()).unwrap())
+        .collect();
+
+    let composite = || {
+        let mut compositor = Compositor::new(&plate).unwrap();
+        for layer in &layers {
+            compositor.add_layer(layer).unwrap();
+        }
+        compositor
+    };
+
+    let mut group = c.benchmark_group("encode");
+    group.sample_size(10);
+    for format in [
+        OutputFormat::Jpeg,
+        OutputFormat::Png,
+        OutputFormat::WebP,
+        OutputFormat::Avif,
+    ] {
+        group.bench_function(format.as_str(), |b| {
+            b.iter_batched(
+                composite,
+                |compositor| compositor.encode(format).unwrap(),
+                criterion::BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+
+    c.bench_function("compose_full_outfit", |b| {
+        b.iter(|| composite().finalize().unwrap())
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_params,
+    bench_decode,
+    bench_overlay,
+    bench_layer_resize,
+    bench_encode
+);
+criterion_main!(benches);
@@ -0,0 +1,114 @@
+//! Legacy and marketing SKUs resolved to the SKUs assets are stored under
+//!
+//! ```toml
+//! [aliases]
+//! "hoodie-charcoal" = "hoodie-grey"    # in any category
+//! "hats/cap-classic" = "cap-black"     # only in hats
+//! ```
+//!
+//! The catalog manifest can carry the same table under `aliases`; the config
+//! file's entries win. Aliases are resolved before the normalization rules,
+//! so rules, catalog checks and cache keys all see the canonical SKU. Both
+//! sides are normalized first, and an alias's target is not resolved again.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::models::{LayerParam, Sku};
+use crate::tables::Tables;
+
+/// Map of alias SKUs to canonical SKUs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SkuAliases {
+    /// Keyed by "category/sku" or just "sku"
+    aliases: HashMap<String, Sku>,
+}
+
+impl SkuAliases {
+    pub fn new(aliases: &BTreeMap<String, String>) -> Self {
+        Self::default().with_overrides(aliases)
+    }
+
+    /// Add aliases, replacing existing ones with the same key
+    pub fn with_overrides(mut self, aliases: &BTreeMap<String, String>) -> Self {
+        for (alias, canonical) in aliases {
+            let key = match alias.split_once('/') {
+                Some((category, sku)) => key(Some(category.trim()), &Sku::new(sku)),
+                None => key(None, &Sku::new(alias)),
+            };
+            self.aliases.insert(key, Sku::new(canonical));
+        }
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.aliases.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    /// Canonical SKU of a layer, if it is an alias
+    /// An alias scoped to the layer's category wins over an unscoped one
+    pub fn resolve(&self, category: &str, sku: &Sku) -> Option<&Sku> {
+        self.aliases
+            .get(&key(Some(category), sku))
+            .or_else(|| self.aliases.get(&key(None, sku)))
+    }
+
+    /// The layer with its SKU resolved; remote layers are returned as-is
+    pub fn apply(&self, param: &LayerParam) -> LayerParam {
+        match self.resolve(&param.category, &param.sku) {
+            Some(sku) if !param.is_remote() => LayerParam {
+                sku: sku.clone(),
+                ..param.clone()
+            },
+            _ => param.clone(),
+        }
+    }
+
+    /// The current tables' aliases (none unless some were installed)
+    pub fn global() -> Arc<SkuAliases> {
+        Tables::current().aliases.clone()
+    }
+}
+
+fn key(category: Option<&str>, sku: &Sku) -> String {
+    match category {
+        Some(category) => format!("{}/{}", category, sku),
+        None => sku.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let aliases = SkuAliases::new(&BTreeMap::from([
+            ("hoodie-charcoal".to_string(), "hoodie-grey".to_string()),
+            ("hats/cap-classic-xl".to_string(), "cap-black".to_string()),
+            (
+                "hats/hoodie-charcoal".to_string(),
+                "beanie-grey".to_string(),
+            ),
+        ]));
+
+        let resolved = aliases.apply(&LayerParam::new("hoodies", "Hoodie-Charcoal-L"));
+        assert_eq!(resolved, LayerParam::new("hoodies", "hoodie-grey"));
+
+        let resolved = aliases.apply(&LayerParam::new("hats", "cap-classic"));
+        assert_eq!(resolved.sku.as_str(), "cap-black");
+        let resolved = aliases.apply(&LayerParam::new("hats", "hoodie-charcoal"));
+        assert_eq!(resolved.sku.as_str(), "beanie-grey");
+
+        // Scoped aliases don't apply to other categories
+        let param = LayerParam::new("caps", "cap-classic");
+        assert_eq!(aliases.apply(&param), param);
+
+        let remote = LayerParam::remote("hoodies", "https://cdn.example.com/hoodie-charcoal.png");
+        assert_eq!(aliases.apply(&remote), remote);
+    }
+}
@@ -0,0 +1,261 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Kind of failure for a single batch item
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum BatchErrorKind {
+    /// The item's parameters or view could not be parsed
+    InvalidParams,
+    /// Fetching, compositing, or caching the item failed
+    Render,
+}
+
+/// Typed error attached to a failed batch item
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchError {
+    pub kind: BatchErrorKind,
+    pub message: String,
+}
+
+impl BatchError {
+    pub fn new(kind: BatchErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(BatchErrorKind::InvalidParams, message)
+    }
+
+    pub fn render(message: impl Into<String>) -> Self {
+        Self::new(BatchErrorKind::Render, message)
+    }
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.message)
+    }
+}
+
+/// Outcome of a single batch item
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BatchOutcome<T> {
+    Success(T),
+    Failed { error: BatchError },
+}
+
+/// A single batch item with its position in the original input
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchItem<T> {
+    pub index: usize,
+    #[serde(flatten)]
+    pub outcome: BatchOutcome<T>,
+}
+
+impl<T> BatchItem<T> {
+    pub fn new(index: usize, result: Result<T, BatchError>) -> Self {
+        Self {
+            index,
+            outcome: match result {
+                Ok(value) => BatchOutcome::Success(value),
+                Err(error) => BatchOutcome::Failed { error },
+            },
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        matches!(self.outcome, BatchOutcome::Success(_))
+    }
+}
+
+/// Counts of succeeded and failed batch items
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchSummary {
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+impl BatchSummary {
+    pub fn new(total: usize, failed: usize) -> Self {
+        Self {
+            total,
+            succeeded: total - failed,
+            failed,
+        }
+    }
+}
+
+/// Per-item results of a batch operation
+/// One failing item never aborts the rest of the batch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchResult<T> {
+    pub summary: BatchSummary,
+    pub items: Vec<BatchItem<T>>,
+}
+
+impl<T> BatchResult<T> {
+    /// Collect per-item results, keeping their input order
+    pub fn from_results(results: impl IntoIterator<Item = Result<T, BatchError>>) -> Self {
+        let items: Vec<BatchItem<T>> = results
+            .into_iter()
+            .enumerate()
+            .map(|(index, result)| BatchItem::new(index, result))
+            .collect();
+
+        let failed = items.iter().filter(|item| !item.is_success()).count();
+
+        Self {
+            summary: BatchSummary::new(items.len(), failed),
+            items,
+        }
+    }
+
+    /// Iterate over successful items
+    pub fn successes(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.items.iter().filter_map(|item| match &item.outcome {
+            BatchOutcome::Success(value) => Some((item.index, value)),
+            BatchOutcome::Failed { .. } => None,
+        })
+    }
+
+    /// Iterate over failed items
+    pub fn failures(&self) -> impl Iterator<Item = (usize, &BatchError)> {
+        self.items.iter().filter_map(|item| match &item.outcome {
+            BatchOutcome::Success(_) => None,
+            BatchOutcome::Failed { error } => Some((item.index, error)),
+        })
+    }
+
+    /// Check if every item succeeded
+    pub fn is_success(&self) -> bool {
+        self.summary.failed == 0
+    }
+}
+
+/// Last outcome of each item of a batch, by cache key
+/// Kept between runs so running the same input again only retries the items
+/// that failed or never finished
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchManifest<T> {
+    pub items: BTreeMap<String, BatchOutcome<T>>,
+}
+
+impl<T> Default for BatchManifest<T> {
+    fn default() -> Self {
+        Self {
+            items: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Clone> BatchManifest<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Result of an item that succeeded in an earlier run
+    pub fn completed(&self, cache_key: &str) -> Option<&T> {
+        match self.items.get(cache_key) {
+            Some(BatchOutcome::Success(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Record the outcome of an item, replacing the earlier one
+    pub fn record(&mut self, cache_key: impl Into<String>, result: &Result<T, BatchError>) {
+        let outcome = match result {
+            Ok(value) => BatchOutcome::Success(value.clone()),
+            Err(error) => BatchOutcome::Failed {
+                error: error.clone(),
+            },
+        };
+        self.items.insert(cache_key.into(), outcome);
+    }
+
+    /// Counts over every recorded item
+    pub fn summary(&self) -> BatchSummary {
+        let failed = self
+            .items
+            .values()
+            .filter(|outcome| matches!(outcome, BatchOutcome::Failed { .. }))
+            .count();
+        BatchSummary::new(self.items.len(), failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    struct Rendered {
+        cache_key: String,
+    }
+
+    fn rendered(key: &str) -> Rendered {
+        Rendered {
+            cache_key: key.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_from_results_keeps_order_and_counts() {
+        let result = BatchResult::from_results(vec![
+            Ok(rendered("a")),
+            Err(BatchError::invalid_params("empty params")),
+            Ok(rendered("b")),
+        ]);
+
+        assert_eq!(result.summary.total, 3);
+        assert_eq!(result.summary.succeeded, 2);
+        assert_eq!(result.summary.failed, 1);
+        assert!(!result.is_success());
+
+        let failures: Vec<_> = result.failures().collect();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, 1);
+        assert_eq!(failures[0].1.kind, BatchErrorKind::InvalidParams);
+
+        let successes: Vec<_> = result.successes().map(|(i, _)| i).collect();
+        assert_eq!(successes, vec![0, 2]);
+    }
+
+    #[test]
+    fn test_manifest() {
+        let mut manifest = BatchManifest::new();
+        manifest.record("a", &Ok(rendered("a")));
+        manifest.record("b", &Err(BatchError::render("Base plate not found")));
+        assert_eq!(manifest.completed("a"), Some(&rendered("a")));
+        assert_eq!(manifest.completed("b"), None);
+        assert_eq!(manifest.summary().failed, 1);
+
+        let json = serde_json::to_string(&manifest).unwrap();
+        let mut manifest: BatchManifest<Rendered> = serde_json::from_str(&json).unwrap();
+        manifest.record("b", &Ok(rendered("b")));
+        assert!(manifest.completed("b").is_some());
+        assert_eq!(manifest.summary(), BatchSummary::new(2, 0));
+    }
+
+    #[test]
+    fn test_serialized_shape() {
+        let result = BatchResult::from_results(vec![
+            Ok(rendered("abc")),
+            Err(BatchError::render("Base plate not found")),
+        ]);
+        let json = serde_json::to_value(&result).unwrap();
+
+        assert_eq!(json["summary"]["failed"], 1);
+        assert_eq!(json["items"][0]["status"], "success");
+        assert_eq!(json["items"][0]["cache_key"], "abc");
+        assert_eq!(json["items"][1]["status"], "failed");
+        assert_eq!(json["items"][1]["error"]["kind"], "render");
+    }
+}
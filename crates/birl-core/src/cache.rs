@@ -7,12 +7,16 @@ pub fn generate_cache_key(params: &[LayerParam], view: View, plate_value: &str)
     // Sort parameters to ensure consistent cache keys
     let mut param_strings: Vec<String> = params
         .iter()
-        .map(|p| format!("{}/{}", p.category, p.sku.as_str()))
+        .map(|p| match p.z {
+            Some(z) => format!("{}/{}@z={}", p.category, p.sku.as_str(), z),
+            None => format!("{}/{}", p.category, p.sku.as_str()),
+        })
         .collect();
     param_strings.sort();
 
     // Create combined string: sorted_params_view_plate
-    let combined_string = format!("{}_{}_{}",
+    let combined_string = format!(
+        "{}_{}_{}",
         param_strings.join("_"),
         view.as_str(),
         plate_value
@@ -25,6 +29,17 @@ pub fn generate_cache_key(params: &[LayerParam], view: View, plate_value: &str)
     format!("{:x}", hash)
 }
 
+/// Derive the cache key for a rendering variant of a composite (filters, etc.)
+/// An empty variant leaves the key unchanged so plain renders keep the shared key
+pub fn variant_cache_key(cache_key: &str, variant: &str) -> String {
+    if variant.is_empty() {
+        return cache_key.to_string();
+    }
+
+    let hash = xxh64(format!("{}_{}", cache_key, variant).as_bytes(), 0);
+    format!("{:x}", hash)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,6 +56,14 @@ mod tests {
         // Should produce a valid hex string
         assert!(!key.is_empty());
         assert!(key.chars().all(|c| c.is_ascii_hexdigit()));
+
+        // A z-order override changes the composite
+        let mut reordered = params.clone();
+        reordered[1] = reordered[1].clone().with_z(40);
+        assert_ne!(
+            generate_cache_key(&reordered, View::Front, "base-model-black"),
+            key
+        );
     }
 
     #[test]
@@ -82,4 +105,14 @@ mod tests {
         // Should produce different keys for different plates
         assert_ne!(key1, key2);
     }
+
+    #[test]
+    fn test_variant_cache_key() {
+        assert_eq!(variant_cache_key("abc123", ""), "abc123");
+
+        let grayscale = variant_cache_key("abc123", "grayscale");
+        assert_ne!(grayscale, "abc123");
+        assert_ne!(grayscale, variant_cache_key("abc123", "sepia"));
+        assert!(grayscale.chars().all(|c| c.is_ascii_hexdigit()));
+    }
 }
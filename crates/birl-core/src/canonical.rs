@@ -0,0 +1,184 @@
+use crate::aliases::SkuAliases;
+use crate::cache::generate_cache_key;
+use crate::layers::{parse_params, requested_plate, ConflictPolicy, LayerNormalizer};
+use crate::models::View;
+use serde::{Deserialize, Serialize};
+
+/// Canonical form of a raw parameter string
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CanonicalParams {
+    /// "category/sku" pairs with normalized, unaliased SKUs, comma-separated,
+    /// sorted by category and deduplicated as far as the conflict policy allows
+    pub params: String,
+    pub view: View,
+    pub cache_key: String,
+}
+
+/// Canonicalize a raw "category/sku,..." string for a view
+/// Equivalent inputs (order of categories, whitespace, size suffixes, SKU
+/// aliases) produce the same output, so a CDN can use the canonical string as
+/// its own cache key. Order within a category and repeats are kept where the
+/// conflict policy renders them differently: all of them under
+/// [`ConflictPolicy::Stack`], repeats under [`ConflictPolicy::Error`], and
+/// the winning occurrence of each pair under the first/last-wins policies.
+pub fn canonicalize(params_str: &str, view: View) -> CanonicalParams {
+    canonicalize_with(
+        params_str,
+        view,
+        &SkuAliases::global(),
+        ConflictPolicy::global(),
+    )
+}
+
+fn canonicalize_with(
+    params_str: &str,
+    view: View,
+    aliases: &SkuAliases,
+    policy: ConflictPolicy,
+) -> CanonicalParams {
+    let mut params: Vec<_> = parse_params(params_str)
+        .iter()
+        .map(|param| aliases.apply(param))
+        .collect();
+    match policy {
+        // Layers of a category stack in request order
+        ConflictPolicy::Stack => params.sort_by(|a, b| a.category.cmp(&b.category)),
+        // Any repeat is a conflict, whatever the order
+        ConflictPolicy::Error => params.sort_by_cached_key(ToString::to_string),
+        ConflictPolicy::FirstWins => {
+            dedup_keeping_first(&mut params);
+            params.sort_by(|a, b| a.category.cmp(&b.category));
+        }
+        ConflictPolicy::LastWins => {
+            params.reverse();
+            dedup_keeping_first(&mut params);
+            params.reverse();
+            params.sort_by(|a, b| a.category.cmp(&b.category));
+        }
+    }
+
+    let pairs: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+
+    let normalizer = LayerNormalizer::new(view, &params).with_conflict_policy(policy);
+    let normalized = normalizer.normalize_all(&params);
+
+    CanonicalParams {
+        params: pairs.join(","),
+        view,
+        cache_key: generate_cache_key(
+            &normalized,
+            view,
+            requested_plate(&params).unwrap_or(view.plate_value()),
+        ),
+    }
+}
+
+/// Remove every repeat of a param after its first occurrence
+fn dedup_keeping_first<T: PartialEq>(params: &mut Vec<T>) {
+    let mut index = 0;
+    while index < params.len() {
+        if params[..index].contains(&params[index]) {
+            params.remove(index);
+        } else {
+            index += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_canonicalize_sorts_and_normalizes() {
+        let canonical = canonicalize(
+            " pants/cargo-darkgreen-40 , hoodies/hoodie-black-XL",
+            View::Front,
+        );
+        assert_eq!(
+            canonical.params,
+            "hoodies/hoodie-black,pants/cargo-darkgreen"
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_equivalent_inputs() {
+        let a = canonicalize(
+            "hoodies/hoodie-black-xl,pants/cargo-darkgreen-40",
+            View::Front,
+        );
+        let b = canonicalize("pants/cargo-darkgreen,hoodies/hoodie-black", View::Front);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_canonicalize_dedupes_and_unaliases() {
+        let aliases = SkuAliases::new(&BTreeMap::from([(
+            "hoodie-charcoal".to_string(),
+            "hoodie-grey".to_string(),
+        )]));
+        let canonical = canonicalize_with(
+            "hoodies/hoodie-charcoal-xl,pants/cargo-black,pants/cargo-black-36",
+            View::Front,
+            &aliases,
+            ConflictPolicy::FirstWins,
+        );
+        assert_eq!(canonical.params, "hoodies/hoodie-grey,pants/cargo-black");
+        assert_eq!(
+            canonical,
+            canonicalize_with(
+                "pants/cargo-black,hoodies/hoodie-grey",
+                View::Front,
+                &SkuAliases::default(),
+                ConflictPolicy::FirstWins,
+            )
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_keeps_what_the_policy_renders() {
+        let aliases = SkuAliases::default();
+        let params = "patches-left/flag-patch-red,hoodies/hoodie-black,patches-left/logo-patch,patches-left/flag-patch-red";
+        let canonical = |policy| canonicalize_with(params, View::Left, &aliases, policy).params;
+
+        assert_eq!(
+            canonical(ConflictPolicy::Stack),
+            "hoodies/hoodie-black,patches-left/flag-patch-red,patches-left/logo-patch,patches-left/flag-patch-red"
+        );
+        assert_eq!(
+            canonical(ConflictPolicy::Error),
+            "hoodies/hoodie-black,patches-left/flag-patch-red,patches-left/flag-patch-red,patches-left/logo-patch"
+        );
+        assert_eq!(
+            canonical(ConflictPolicy::FirstWins),
+            "hoodies/hoodie-black,patches-left/flag-patch-red,patches-left/logo-patch"
+        );
+        assert_eq!(
+            canonical(ConflictPolicy::LastWins),
+            "hoodies/hoodie-black,patches-left/logo-patch,patches-left/flag-patch-red"
+        );
+
+        // Stacking order changes the composite, so it is kept
+        let swapped = canonicalize_with(
+            "patches-left/logo-patch,patches-left/flag-patch-red",
+            View::Left,
+            &aliases,
+            ConflictPolicy::Stack,
+        );
+        assert_eq!(
+            swapped.params,
+            "patches-left/logo-patch,patches-left/flag-patch-red"
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_is_idempotent() {
+        let first = canonicalize(
+            "patches-left/flag-patch-red,jackets/softshell-grey-m",
+            View::Left,
+        );
+        let second = canonicalize(&first.params, View::Left);
+        assert_eq!(first, second);
+    }
+}
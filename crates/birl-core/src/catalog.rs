@@ -0,0 +1,484 @@
+//! Manifest of the assets that exist in storage
+//!
+//! ```json
+//! {
+//!   "plates": {"front": ["base-model-black"], "left": ["patch-plate"]},
+//!   "categories": {
+//!     "hoodies": {
+//!       "views": ["front", "back", "side", "left", "right"],
+//!       "default": "hoodie-black",
+//!       "skus": ["hoodie-black", {"sku": "hoodie-grey", "views": ["front", "back"]}]
+//!     }
+//!   },
+//!   "aliases": {"hoodie-charcoal": "hoodie-grey"},
+//!   "placements": {
+//!     "jackets": {"front": {"patches-left": {"x": 612, "y": 540, "width": 96}}},
+//!     "jackets/softshell-grey": {"front": {"patches-left": {"x": 604, "y": 552}}}
+//!   },
+//!   "presets": {
+//!     "winter-tactical": {"params": "hoodies/hoodie-black,jackets/softshell-grey"}
+//!   }
+//! }
+//! ```
+//!
+//! Categories are the storage (normalized) ones, e.g. `gloves-top` rather than
+//! `gloves`. A SKU is available in its category's views unless it lists its own.
+//! `aliases` map old SKUs to catalogued ones (see [`crate::aliases`]), and a
+//! category's `default` is what `category/*` renders (see [`crate::wildcards`]).
+//!
+//! `placements` say where small patch assets go on a garment ("category" or
+//! "category/sku", the SKU winning), per view and patch category, so one patch
+//! image serves every garment. Patches without a placement are full-frame layers.
+//! `presets` are named outfits (see [`crate::presets`]).
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use thiserror::Error;
+
+use crate::error::Result;
+use crate::graph::Placement;
+use crate::layers::patch_garments;
+use crate::manifest::{self, ManifestFormat};
+use crate::models::{LayerParam, Sku, View};
+use crate::presets::Preset;
+use crate::suggest::closest_sku;
+
+/// Key of the catalog manifest in the storage JSON cache
+pub const CATALOG_KEY: &str = "catalog";
+
+/// Available categories, SKUs, views, and plates
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Catalog {
+    /// Plates available for each view
+    #[serde(default)]
+    pub plates: BTreeMap<View, Vec<String>>,
+    #[serde(default)]
+    pub categories: BTreeMap<String, CatalogCategory>,
+    /// Legacy SKUs ("sku" or "category/sku") and the SKUs they resolve to
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, String>,
+    /// Patch placements by garment ("category" or "category/sku"), view, and
+    /// patch category
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub placements: BTreeMap<String, BTreeMap<View, BTreeMap<String, Placement>>>,
+    /// Named outfits (see [`crate::presets`])
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub presets: BTreeMap<String, Preset>,
+}
+
+/// SKUs of one category
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogCategory {
+    /// Views the category has layers for (default: every view)
+    #[serde(default = "all_views")]
+    pub views: Vec<View>,
+    /// SKU a `category/*` param renders, where it has a layer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub skus: Vec<CatalogSku>,
+}
+
+/// A SKU, optionally available in fewer views than its category
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CatalogSku {
+    Name(String),
+    Detailed { sku: String, views: Vec<View> },
+}
+
+/// Why a layer isn't available according to the catalog
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CatalogIssue {
+    UnknownCategory(String),
+    UnknownSku {
+        category: String,
+        sku: String,
+        /// A catalogued SKU the requested one is likely a typo of
+        suggestion: Option<String>,
+    },
+    MissingView {
+        category: String,
+        sku: String,
+    },
+    MissingPlate(String),
+}
+
+/// Layers or plate of a render that don't exist according to the catalog
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Not in catalog for {view} view: {}", join(.issues))]
+pub struct CatalogError {
+    pub view: View,
+    pub issues: Vec<CatalogIssue>,
+}
+
+fn all_views() -> Vec<View> {
+    View::all()
+}
+
+fn join(issues: &[CatalogIssue]) -> String {
+    issues
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for CatalogIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogIssue::UnknownCategory(category) => write!(f, "unknown category {}", category),
+            CatalogIssue::UnknownSku {
+                category,
+                sku,
+                suggestion,
+            } => {
+                write!(f, "unknown SKU {}/{}", category, sku)?;
+                match suggestion {
+                    Some(suggestion) => write!(f, " (did you mean {}?)", suggestion),
+                    None => Ok(()),
+                }
+            }
+            CatalogIssue::MissingView { category, sku } => {
+                write!(f, "{}/{} has no layer for this view", category, sku)
+            }
+            CatalogIssue::MissingPlate(plate) => write!(f, "plate {}", plate),
+        }
+    }
+}
+
+impl CatalogSku {
+    /// SKU with size suffixes removed, as used in storage paths
+    pub fn sku(&self) -> Sku {
+        match self {
+            CatalogSku::Name(sku) | CatalogSku::Detailed { sku, .. } => Sku::new(sku),
+        }
+    }
+
+    /// Views of this SKU, if narrower than its category's
+    pub fn views(&self) -> Option<&[View]> {
+        match self {
+            CatalogSku::Name(_) => None,
+            CatalogSku::Detailed { views, .. } => Some(views),
+        }
+    }
+}
+
+impl CatalogCategory {
+    pub fn find(&self, sku: &Sku) -> Option<&CatalogSku> {
+        self.skus.iter().find(|entry| &entry.sku() == sku)
+    }
+
+    /// Views a SKU of this category is available in
+    pub fn views_of<'a>(&'a self, entry: &'a CatalogSku) -> &'a [View] {
+        entry.views().unwrap_or(&self.views)
+    }
+}
+
+impl Catalog {
+    pub fn from_json(json: &str) -> Result<Self> {
+        manifest::parse("catalog JSON", json, ManifestFormat::Json)
+    }
+
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        manifest::parse("catalog TOML", toml, ManifestFormat::Toml)
+    }
+
+    /// Load a manifest file, TOML if the extension says so, JSON otherwise
+    pub fn load(path: &Path) -> Result<Self> {
+        manifest::load("catalog", path)
+    }
+
+    pub fn category(&self, name: &str) -> Option<&CatalogCategory> {
+        self.categories.get(name)
+    }
+
+    pub fn categories(&self) -> impl Iterator<Item = &str> {
+        self.categories.keys().map(String::as_str)
+    }
+
+    /// SKUs of a category (empty for unknown categories)
+    pub fn skus(&self, category: &str) -> Vec<Sku> {
+        self.category(category)
+            .map(|category| category.skus.iter().map(CatalogSku::sku).collect())
+            .unwrap_or_default()
+    }
+
+    /// Views with at least one plate
+    pub fn views(&self) -> Vec<View> {
+        self.plates.keys().copied().collect()
+    }
+
+    /// Plates available for a view
+    pub fn plates(&self, view: View) -> &[String] {
+        self.plates.get(&view).map_or(&[], Vec::as_slice)
+    }
+
+    /// Where a patch of `patch_category` goes on `garment` in a view
+    pub fn placement(
+        &self,
+        garment: &LayerParam,
+        patch_category: &str,
+        view: View,
+    ) -> Option<Placement> {
+        let sku_key = format!("{}/{}", garment.category, garment.sku.as_str());
+        let placement = [sku_key.as_str(), garment.category.as_str()]
+            .into_iter()
+            .find_map(|key| self.placements.get(key)?.get(&view)?.get(patch_category));
+        placement.copied()
+    }
+
+    /// Placement of each normalized layer, aligned with `normalized`
+    /// Only patches on a garment with a placement get one (see [`patch_garments`])
+    pub fn patch_placements(
+        &self,
+        normalized: &[LayerParam],
+        view: View,
+    ) -> Vec<Option<Placement>> {
+        normalized
+            .iter()
+            .zip(patch_garments(normalized))
+            .map(|(param, garment)| self.placement(garment?, &param.category, view))
+            .collect()
+    }
+
+    /// A SKU of `category` available in `view` that `sku` may be a typo of
+    pub fn suggest_sku(&self, category: &str, sku: &str, view: View) -> Option<String> {
+        let category = self.category(category)?;
+        let skus: Vec<Sku> = category
+            .skus
+            .iter()
+            .filter(|entry| category.views_of(entry).contains(&view))
+            .map(CatalogSku::sku)
+            .collect();
+        closest_sku(sku, skus.iter().map(Sku::as_str)).map(str::to_string)
+    }
+
+    /// Whether a layer exists for a view
+    /// Remote layers aren't catalogued and always count as available
+    pub fn contains(&self, param: &LayerParam, view: View) -> bool {
+        self.check(param, view).is_none()
+    }
+
+    /// Check that the plate and every (normalized) layer of a render exist
+    /// Without any plates listed, plates aren't checked
+    pub fn validate(
+        &self,
+        params: &[LayerParam],
+        view: View,
+        plate: &str,
+    ) -> Result<(), CatalogError> {
+        let mut issues = Vec::new();
+
+        if !self.plates.is_empty() && !self.plates(view).iter().any(|p| p == plate) {
+            issues.push(CatalogIssue::MissingPlate(plate.to_string()));
+        }
+        for param in params {
+            if let Some(issue) = self.check(param, view) {
+                if !issues.contains(&issue) {
+                    issues.push(issue);
+                }
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(CatalogError { view, issues })
+        }
+    }
+
+    fn check(&self, param: &LayerParam, view: View) -> Option<CatalogIssue> {
+        if param.url.is_some() {
+            return None;
+        }
+
+        let Some(category) = self.category(&param.category) else {
+            return Some(CatalogIssue::UnknownCategory(param.category.clone()));
+        };
+        let Some(entry) = category.find(&param.sku) else {
+            return Some(CatalogIssue::UnknownSku {
+                category: param.category.clone(),
+                sku: param.sku.to_string(),
+                suggestion: self.suggest_sku(&param.category, param.sku.as_str(), view),
+            });
+        };
+        if !category.views_of(entry).contains(&view) {
+            return Some(CatalogIssue::MissingView {
+                category: param.category.clone(),
+                sku: param.sku.to_string(),
+            });
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CATALOG: &str = r#"{
+        "plates": {"front": ["base-model-black"], "left": ["patch-plate"]},
+        "categories": {
+            "hoodies": {
+                "skus": ["hoodie-black", {"sku": "hoodie-grey", "views": ["front"]}]
+            },
+            "pants": {"views": ["front", "back", "side"], "skus": ["cargo-black-36"]}
+        }
+    }"#;
+
+    #[test]
+    fn test_lookups() {
+        let catalog = Catalog::from_json(CATALOG).unwrap();
+
+        assert_eq!(
+            catalog.categories().collect::<Vec<_>>(),
+            ["hoodies", "pants"]
+        );
+        assert_eq!(catalog.skus("pants"), vec![Sku::new("cargo-black")]);
+        assert!(catalog.skus("hats").is_empty());
+        assert_eq!(catalog.views(), vec![View::Front, View::Left]);
+        assert_eq!(catalog.plates(View::Left), ["patch-plate"]);
+
+        let grey = LayerParam::new("hoodies", "hoodie-grey-xl");
+        assert!(catalog.contains(&grey, View::Front));
+        assert!(!catalog.contains(&grey, View::Left));
+        assert!(catalog.contains(&LayerParam::new("hoodies", "hoodie-black"), View::Left));
+    }
+
+    #[test]
+    fn test_suggest_sku() {
+        let catalog = Catalog::from_json(CATALOG).unwrap();
+        let suggest = |sku| catalog.suggest_sku("hoodies", sku, View::Front);
+        assert_eq!(suggest("hoodie-blakc").as_deref(), Some("hoodie-black"));
+        assert_eq!(suggest("hodie-grey").as_deref(), Some("hoodie-grey"));
+        // hoodie-grey has no layer for the left view
+        assert_eq!(
+            catalog.suggest_sku("hoodies", "hodie-grey", View::Left),
+            None
+        );
+        assert_eq!(catalog.suggest_sku("hats", "beanie", View::Front), None);
+
+        let err = catalog
+            .validate(
+                &[LayerParam::new("pants", "cargo-blak")],
+                View::Front,
+                "base-model-black",
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Not in catalog for front view: unknown SKU pants/cargo-blak (did you mean cargo-black?)"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let catalog = Catalog::from_json(CATALOG).unwrap();
+        let params = vec![
+            LayerParam::new("pants", "cargo-black"),
+            LayerParam::new("hoodies", "hoodie-grey"),
+            LayerParam::new("hats", "beanie-black"),
+            LayerParam::new("hoodies", "hoodie-pink"),
+        ];
+
+        assert!(catalog
+            .validate(&params[..2], View::Front, "base-model-black")
+            .is_ok());
+        let err = catalog
+            .validate(&params[..2], View::Front, "base-model-tall")
+            .unwrap_err();
+        assert_eq!(
+            err.issues,
+            vec![CatalogIssue::MissingPlate("base-model-tall".to_string())]
+        );
+
+        let err = catalog
+            .validate(&params, View::Back, View::Back.plate_value())
+            .unwrap_err();
+        assert_eq!(
+            err.issues,
+            vec![
+                CatalogIssue::MissingPlate("base-model-black".to_string()),
+                CatalogIssue::MissingView {
+                    category: "hoodies".to_string(),
+                    sku: "hoodie-grey".to_string()
+                },
+                CatalogIssue::UnknownCategory("hats".to_string()),
+                CatalogIssue::UnknownSku {
+                    category: "hoodies".to_string(),
+                    sku: "hoodie-pink".to_string(),
+                    suggestion: None,
+                },
+            ]
+        );
+        assert!(err
+            .to_string()
+            .starts_with("Not in catalog for back view: plate base-model-black"));
+    }
+
+    #[test]
+    fn test_toml_manifest() {
+        let catalog = Catalog::from_toml(
+            r#"
+            [plates]
+            front = ["base-model-black"]
+
+            [categories.hats]
+            views = ["front"]
+            skus = ["beanie-black", { sku = "cap-red", views = ["front", "back"] }]
+            "#,
+        )
+        .unwrap();
+
+        assert!(catalog.contains(&LayerParam::new("hats", "beanie-black"), View::Front));
+        assert!(catalog.contains(&LayerParam::new("hats", "cap-red"), View::Back));
+        assert!(Catalog::from_toml("[categories.hats]\nskuz = []").is_err());
+    }
+
+    #[test]
+    fn test_patch_placements() {
+        let catalog = Catalog::from_json(
+            r#"{"placements": {
+                "jackets": {"front": {"patches-left": {"x": 612, "y": 540, "width": 96}}},
+                "jackets/softshell-grey": {"front": {"patches-left": {"x": 604, "y": 552}}}
+            }}"#,
+        )
+        .unwrap();
+
+        let normalized = vec![
+            LayerParam::new("jackets", "parka-black"),
+            LayerParam::new("patches-left", "flag-patch-red"),
+        ];
+        assert_eq!(
+            catalog.patch_placements(&normalized, View::Front),
+            vec![None, Some(Placement::new(612, 540).with_width(96))]
+        );
+        assert_eq!(
+            catalog.patch_placements(&normalized, View::Back),
+            vec![None, None]
+        );
+
+        let softshell = LayerParam::new("jackets", "softshell-grey-xl");
+        assert_eq!(
+            catalog.placement(&softshell, "patches-left", View::Front),
+            Some(Placement::new(604, 552))
+        );
+        assert_eq!(
+            catalog.placement(&softshell, "patches-right", View::Front),
+            None
+        );
+
+        // No garment beneath the patch
+        assert_eq!(
+            catalog.patch_placements(&normalized[1..], View::Front),
+            vec![None]
+        );
+    }
+}
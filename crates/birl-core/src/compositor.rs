@@ -1,97 +1,533 @@
-use anyhow::{Context, Result};
+use crate::error::{BirlError, Result};
+use crate::filters::Filter;
+use crate::format::OutputFormat;
+use crate::graph::Placement;
+use crate::offsets::Offset;
+use crate::options::{Background, CompositeOptions, ResizeFilter};
+use crate::phash::dhash;
+use crate::sanity::{check_image, SanityIssue};
+use crate::tiered::TieredCache;
 use bytes::Bytes;
-use image::{DynamicImage, ImageFormat, ImageReader};
-use std::io::Cursor;
+use image::codecs::{avif::AvifEncoder, jpeg::JpegEncoder, png::PngEncoder, webp::WebPEncoder};
+use image::{DynamicImage, ImageReader, Rgb, RgbImage, RgbaImage};
+use std::io::{Cursor, Write};
+#[cfg(feature = "async")]
+use std::io::{self, BufWriter};
+use std::sync::Arc;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+#[cfg(feature = "async")]
+use tokio::sync::mpsc;
 use tracing::{debug, info};
+use xxhash_rust::xxh64::xxh64;
 
-/// Composite multiple PNG layers over a base JPEG image
+/// Size of the chunks handed to the async writer while streaming an encode
+#[cfg(feature = "async")]
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Composite multiple PNG layers over a base image: an opaque JPEG plate, or a
+/// transparent PNG one for renders without a background
 pub struct Compositor {
     base_image: DynamicImage,
+    /// Resampling filter for layers that don't match the base size
+    layer_filter: ResizeFilter,
 }
 
 impl Compositor {
     /// Create a new compositor with a base image
     pub fn new(base_image_data: &[u8]) -> Result<Self> {
-        let reader = ImageReader::new(Cursor::new(base_image_data))
-            .with_guessed_format()
-            .context("Failed to guess image format")?;
-
-        let base_image = reader.decode().context("Failed to decode base image")?;
+        let base_image =
+            decode_image(base_image_data).map_err(|e| BirlError::decode("base image", e))?;
 
         debug!("Loaded base image: {}x{}", base_image.width(), base_image.height());
 
-        Ok(Self { base_image })
+        Ok(Self::from_image(base_image))
+    }
+
+    /// Wrap an already decoded image
+    /// The canvas is 8-bit RGB, or RGBA if the image has alpha, so layers
+    /// blend in color (not onto a grayscale plate's luma) and keep a
+    /// transparent plate's alpha
+    pub fn from_image(base_image: DynamicImage) -> Self {
+        let base_image = match base_image {
+            DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) => base_image,
+            image if image.color().has_alpha() => DynamicImage::ImageRgba8(image.to_rgba8()),
+            image => DynamicImage::ImageRgb8(image.to_rgb8()),
+        };
+        Self {
+            base_image,
+            layer_filter: ResizeFilter::default(),
+        }
+    }
+
+    /// Set the resampling filter for layers that need resizing (default: Lanczos3)
+    /// Faster filters like Triangle or Nearest trade edge quality for speed
+    pub fn with_layer_filter(mut self, filter: ResizeFilter) -> Self {
+        self.layer_filter = filter;
+        self
+    }
+
+    /// Current composite, without encoding
+    pub fn into_image(self) -> DynamicImage {
+        self.base_image
     }
 
     /// Add a layer to the composite
     pub fn add_layer(&mut self, layer_data: &[u8]) -> Result<()> {
-        let reader = ImageReader::new(Cursor::new(layer_data))
-            .with_guessed_format()
-            .context("Failed to guess layer format")?;
+        let layer = decode_image(layer_data).map_err(|e| BirlError::decode("layer image", e))?;
+        self.overlay(layer);
+
+        Ok(())
+    }
+
+    /// Add a layer clipped to a grayscale mask
+    /// White keeps the layer, black hides it; used to keep patches on the garment
+    pub fn add_masked_layer(&mut self, layer_data: &[u8], mask_data: &[u8]) -> Result<()> {
+        let layer = decode_image(layer_data).map_err(|e| BirlError::decode("layer image", e))?;
+        let mask = decode_image(mask_data).map_err(|e| BirlError::decode("mask", e))?;
+
+        let (width, height) = self.dimensions();
+        let layer = fit_to(layer, width, height, self.layer_filter);
+        self.overlay(apply_mask(layer, &mask, self.layer_filter));
 
-        let layer = reader.decode().context("Failed to decode layer image")?;
+        Ok(())
+    }
 
+    /// Alpha-blend a decoded layer over the composite, resizing it to fit if needed
+    pub fn overlay(&mut self, layer: DynamicImage) {
         debug!("Adding layer: {}x{}", layer.width(), layer.height());
 
-        // Ensure the layer matches the base image size
-        let layer = if layer.width() != self.base_image.width()
-            || layer.height() != self.base_image.height()
-        {
+        let (width, height) = self.dimensions();
+        let layer = fit_to(layer, width, height, self.layer_filter);
+
+        image::imageops::overlay(&mut self.base_image, &layer, 0, 0);
+    }
+
+    /// Like [`Self::overlay`], shifting the resized layer by an offset
+    /// Whatever is shifted past the edges of the composite is cut off
+    pub fn overlay_offset(&mut self, layer: DynamicImage, offset: &Offset) {
+        debug!(
+            "Adding layer: {}x{} shifted {}",
+            layer.width(),
+            layer.height(),
+            offset
+        );
+
+        let (width, height) = self.dimensions();
+        let layer = fit_to(layer, width, height, self.layer_filter);
+
+        image::imageops::overlay(
+            &mut self.base_image,
+            &layer,
+            offset.x.into(),
+            offset.y.into(),
+        );
+    }
+
+    /// Alpha-blend a small layer over the composite at a placement, scaling it
+    /// to the placement's width if it has one
+    pub fn overlay_at(&mut self, layer: DynamicImage, at: &Placement) {
+        debug!(
+            "Placing layer: {}x{} at {}",
+            layer.width(),
+            layer.height(),
+            at
+        );
+
+        let (width, height) = at.size(layer.width(), layer.height());
+        let layer = fit_to(layer, width, height, self.layer_filter);
+
+        image::imageops::overlay(&mut self.base_image, &layer, at.x.into(), at.y.into());
+    }
+
+    /// Apply a post-processing filter to the current composite
+    pub fn apply_filter(&mut self, filter: Filter) {
+        debug!("Applying filter: {}", filter);
+        self.base_image = filter.apply(&self.base_image);
+    }
+
+    /// Apply the processing steps of the options: resize, background, filters
+    /// Encoding settings are applied by [`Compositor::encode_with`]
+    pub fn apply_options(&mut self, options: &CompositeOptions) {
+        let (width, height) = self.dimensions();
+        if let Some((width, height)) = options.target_size(width, height) {
             debug!(
-                "Resizing layer from {}x{} to {}x{}",
-                layer.width(),
-                layer.height(),
-                self.base_image.width(),
-                self.base_image.height()
+                "Resizing composite to {}x{} ({})",
+                width, height, options.resize_filter
             );
-            layer.resize_exact(
-                self.base_image.width(),
-                self.base_image.height(),
-                image::imageops::FilterType::Lanczos3,
-            )
-        } else {
-            layer
-        };
+            let filter = options.resize_filter.filter_type();
+            self.base_image = self.base_image.resize_exact(width, height, filter);
+        }
 
-        // Composite the layer over the base using alpha blending
-        image::imageops::overlay(&mut self.base_image, &layer, 0, 0);
+        if let Some(background) = options.background {
+            self.fill_background(background);
+        }
 
-        Ok(())
+        for filter in &options.filters {
+            self.apply_filter(*filter);
+        }
+    }
+
+    /// Blend the composite over a solid color, removing transparency
+    fn fill_background(&mut self, background: Background) {
+        self.base_image = DynamicImage::ImageRgba8(flatten(&self.base_image, background));
     }
 
     /// Finalize and encode the composite as JPEG
     pub fn finalize(self) -> Result<Bytes> {
-        let mut buffer = Vec::new();
-        let mut cursor = Cursor::new(&mut buffer);
+        self.encode(OutputFormat::Jpeg)
+    }
 
-        self.base_image
-            .write_to(&mut cursor, ImageFormat::Jpeg)
-            .context("Failed to encode composite as JPEG")?;
+    /// Finalize and encode the composite in the given format
+    pub fn encode(self, format: OutputFormat) -> Result<Bytes> {
+        self.encode_with(&CompositeOptions::from(format))
+    }
+
+    /// Finalize and encode with the format and quality of the options
+    pub fn encode_with(self, options: &CompositeOptions) -> Result<Bytes> {
+        let mut buffer = Vec::new();
+        let image = self.into_output_image(options);
+        write_encoded(&image, options, &mut buffer)?;
 
-        info!("Composite created: {} bytes", buffer.len());
+        info!(
+            "Composite created: {} bytes ({})",
+            buffer.len(),
+            options.format
+        );
 
         Ok(Bytes::from(buffer))
     }
 
+    /// Finalize and stream the composite as JPEG into an async writer
+    #[cfg(feature = "async")]
+    pub async fn finalize_into<W>(self, writer: &mut W) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        self.encode_into(&CompositeOptions::default(), writer).await
+    }
+
+    /// Encode the composite into an async writer without buffering the whole output
+    /// The encoder runs on a blocking thread and hands over chunks as they are produced
+    /// Returns the number of bytes written
+    #[cfg(feature = "async")]
+    pub async fn encode_into<W>(self, options: &CompositeOptions, writer: &mut W) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let format = options.format;
+        let image = self.into_output_image(options);
+        let options = options.clone();
+        let (tx, mut rx) = mpsc::channel::<Vec<u8>>(4);
+
+        let encoder = tokio::task::spawn_blocking(move || -> Result<()> {
+            let mut sink = BufWriter::with_capacity(STREAM_CHUNK_SIZE, ChunkSender(tx));
+            write_encoded(&image, &options, &mut sink)?;
+            sink.flush()
+                .map_err(|e| BirlError::io("Failed to flush encoded composite", e))?;
+            Ok(())
+        });
+
+        let mut written = 0u64;
+        while let Some(chunk) = rx.recv().await {
+            writer
+                .write_all(&chunk)
+                .await
+                .map_err(|e| BirlError::io("Failed to write encoded composite", e))?;
+            written += chunk.len() as u64;
+        }
+
+        encoder
+            .await
+            .map_err(|e| BirlError::io("Encoder task panicked", io::Error::other(e)))??;
+        writer
+            .flush()
+            .await
+            .map_err(|e| BirlError::io("Failed to flush encoded composite", e))?;
+
+        info!("Composite streamed: {} bytes ({})", written, format);
+
+        Ok(written)
+    }
+
+    /// Final image in a color type the format can store
+    /// Formats without alpha get transparent areas flattened onto the matte
+    fn into_output_image(self, options: &CompositeOptions) -> DynamicImage {
+        if options.format.has_alpha() {
+            return self.base_image;
+        }
+        if !self.base_image.color().has_alpha() {
+            return DynamicImage::ImageRgb8(self.base_image.to_rgb8());
+        }
+
+        let flat = flatten(&self.base_image, options.matte());
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(flat).to_rgb8())
+    }
+
+    /// Perceptual hash (dHash) of the current composite
+    pub fn perceptual_hash(&self) -> u64 {
+        dhash(&self.base_image)
+    }
+
+    /// Check the current composite for obviously broken output
+    pub fn check_sanity(&self) -> Option<SanityIssue> {
+        check_image(&self.base_image)
+    }
+
     /// Get the width and height of the base image
     pub fn dimensions(&self) -> (u32, u32) {
         (self.base_image.width(), self.base_image.height())
     }
 }
 
+/// Blend an image over a solid color, leaving it fully opaque
+fn flatten(image: &DynamicImage, Background([br, bg, bb]): Background) -> RgbaImage {
+    let mut rgba = image.to_rgba8();
+
+    for pixel in rgba.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let blend =
+            |c: u8, base: u8| ((c as u16 * a as u16 + base as u16 * (255 - a as u16)) / 255) as u8;
+        pixel.0 = [blend(r, br), blend(g, bg), blend(b, bb), 255];
+    }
+
+    rgba
+}
+
+/// Decode an image, guessing its format
+pub(crate) fn decode_image(data: &[u8]) -> Result<DynamicImage, image::ImageError> {
+    ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .decode()
+}
+
+/// Decoded images shared between renders, keyed by content hash
+/// Layers with identical bytes (e.g. the same art in several views) decode once
+#[derive(Default)]
+pub struct DecodeCache {
+    images: TieredCache<u64, Arc<DynamicImage>>,
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode `data`, reusing an earlier decode of the same bytes
+    pub fn decode(&self, data: &[u8]) -> Result<DynamicImage, image::ImageError> {
+        let hash = xxh64(data, 0);
+        if let Some(image) = self.images.get(&hash) {
+            return Ok((*image).clone());
+        }
+
+        let image = decode_image(data)?;
+        self.images.insert(hash, Arc::new(image.clone()));
+        Ok(image)
+    }
+
+    /// Decodes served from the cache and decodes performed
+    pub fn stats(&self) -> (usize, usize) {
+        let stats = self.images.stats();
+        (stats.hits as usize, stats.inserts as usize)
+    }
+}
+
+/// Resize an image to exactly `width` x `height` if it differs
+pub(crate) fn fit_to(
+    image: DynamicImage,
+    width: u32,
+    height: u32,
+    filter: ResizeFilter,
+) -> DynamicImage {
+    if image.width() == width && image.height() == height {
+        return image;
+    }
+
+    debug!(
+        "Resizing layer from {}x{} to {}x{} ({})",
+        image.width(),
+        image.height(),
+        width,
+        height,
+        filter
+    );
+    image.resize_exact(width, height, filter.filter_type())
+}
+
+/// Multiply the image's alpha by the mask's luminance, resizing the mask to fit
+pub(crate) fn apply_mask(
+    image: DynamicImage,
+    mask: &DynamicImage,
+    filter: ResizeFilter,
+) -> DynamicImage {
+    let mut image = image.to_rgba8();
+    let mask = fit_to(mask.clone(), image.width(), image.height(), filter).to_luma8();
+
+    for (pixel, coverage) in image.pixels_mut().zip(mask.pixels()) {
+        pixel.0[3] = ((pixel.0[3] as u16 * coverage.0[0] as u16) / 255) as u8;
+    }
+
+    DynamicImage::ImageRgba8(image)
+}
+
+/// Like [`apply_mask`] for a layer at a placement: the image is scaled for the
+/// placement and masked by the region of the (full-size) mask it covers
+/// Outside the mask counts as black
+pub(crate) fn apply_mask_at(
+    image: DynamicImage,
+    mask: &DynamicImage,
+    at: &Placement,
+    filter: ResizeFilter,
+) -> DynamicImage {
+    let (width, height) = at.size(image.width(), image.height());
+    let mut image = fit_to(image, width, height, filter).to_rgba8();
+    let mask = mask.to_luma8();
+
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let coverage = mask
+            .get_pixel_checked(at.x.saturating_add(x), at.y.saturating_add(y))
+            .map_or(0, |luma| luma.0[0]);
+        pixel.0[3] = ((pixel.0[3] as u16 * coverage as u16) / 255) as u8;
+    }
+
+    DynamicImage::ImageRgba8(image)
+}
+
+/// A `width` x `height` image of one color, transparent without a background
+pub(crate) fn canvas(width: u32, height: u32, background: Option<Background>) -> DynamicImage {
+    match background {
+        Some(Background(rgb)) => {
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb(rgb)))
+        }
+        None => DynamicImage::ImageRgba8(RgbaImage::new(width, height)),
+    }
+}
+
+/// The visible part of an image, scaled to fit `width` x `height` keeping its
+/// aspect ratio, centered on a transparent image of that size
+/// A fully transparent image gives an empty one
+pub(crate) fn fit_into(
+    image: DynamicImage,
+    width: u32,
+    height: u32,
+    filter: ResizeFilter,
+) -> DynamicImage {
+    let image = image.to_rgba8();
+    let mut fitted = RgbaImage::new(width, height);
+    let Some((left, top, right, bottom)) = visible_bounds(&image) else {
+        return DynamicImage::ImageRgba8(fitted);
+    };
+
+    let (visible_width, visible_height) = (right - left, bottom - top);
+    let visible = image::imageops::crop_imm(&image, left, top, visible_width, visible_height);
+    let scale = (f64::from(width) / f64::from(visible_width))
+        .min(f64::from(height) / f64::from(visible_height));
+    let scaled_width = ((f64::from(visible_width) * scale).round() as u32).clamp(1, width.max(1));
+    let scaled_height =
+        ((f64::from(visible_height) * scale).round() as u32).clamp(1, height.max(1));
+    let scaled = fit_to(
+        DynamicImage::ImageRgba8(visible.to_image()),
+        scaled_width,
+        scaled_height,
+        filter,
+    );
+
+    image::imageops::overlay(
+        &mut fitted,
+        &scaled.to_rgba8(),
+        i64::from((width - scaled_width) / 2),
+        i64::from((height - scaled_height) / 2),
+    );
+    DynamicImage::ImageRgba8(fitted)
+}
+
+/// Bounding box (left, top, right, bottom; right and bottom exclusive) of the
+/// pixels that aren't fully transparent
+fn visible_bounds(image: &RgbaImage) -> Option<(u32, u32, u32, u32)> {
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if pixel.0[3] == 0 {
+            continue;
+        }
+        bounds = Some(match bounds {
+            Some((left, top, right, bottom)) => {
+                (left.min(x), top.min(y), right.max(x + 1), bottom.max(y + 1))
+            }
+            None => (x, y, x + 1, y + 1),
+        });
+    }
+    bounds
+}
+
+/// Default JPEG quality, matching `JpegEncoder::new`
+const DEFAULT_JPEG_QUALITY: u8 = 75;
+/// Default AVIF speed and quality, matching `AvifEncoder::new`
+const DEFAULT_AVIF_SPEED: u8 = 4;
+const DEFAULT_AVIF_QUALITY: u8 = 80;
+
+/// Encode with the same encoders `DynamicImage::write_to` picks, but without needing `Seek`
+fn write_encoded<W: Write>(
+    image: &DynamicImage,
+    options: &CompositeOptions,
+    writer: W,
+) -> Result<()> {
+    let quality = options.quality_for(image.width(), image.height());
+    let result = match options.format {
+        OutputFormat::Jpeg => image.write_with_encoder(JpegEncoder::new_with_quality(
+            writer,
+            quality.unwrap_or(DEFAULT_JPEG_QUALITY),
+        )),
+        OutputFormat::Png => image.write_with_encoder(PngEncoder::new(writer)),
+        OutputFormat::WebP => image.write_with_encoder(WebPEncoder::new_lossless(writer)),
+        OutputFormat::Avif => image.write_with_encoder(AvifEncoder::new_with_speed_quality(
+            writer,
+            DEFAULT_AVIF_SPEED,
+            quality.unwrap_or(DEFAULT_AVIF_QUALITY),
+        )),
+    };
+
+    result.map_err(|e| BirlError::encode(format!("composite as {}", options.format), e))
+}
+
+/// Forwards encoder output to the async side of [`Compositor::encode_into`]
+#[cfg(feature = "async")]
+struct ChunkSender(mpsc::Sender<Vec<u8>>);
+
+#[cfg(feature = "async")]
+impl Write for ChunkSender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "composite writer closed"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 /// Composite multiple layers over a base image in one operation
-pub fn compose_layers(base_image_data: &[u8], layers: Vec<Bytes>) -> Result<Bytes> {
+/// The options are applied to the finished composite before encoding
+pub fn compose_layers(
+    base_image_data: &[u8],
+    layers: Vec<Bytes>,
+    options: &CompositeOptions,
+) -> Result<Bytes> {
     let start = std::time::Instant::now();
 
-    let mut compositor = Compositor::new(base_image_data)?;
+    let mut compositor =
+        Compositor::new(base_image_data)?.with_layer_filter(options.layer_filter);
 
     for (idx, layer_data) in layers.iter().enumerate() {
-        compositor
-            .add_layer(layer_data)
-            .with_context(|| format!("Failed to add layer {}", idx))?;
+        let layer =
+            decode_image(layer_data).map_err(|e| BirlError::decode(format!("layer {}", idx), e))?;
+        compositor.overlay(layer);
     }
 
-    let result = compositor.finalize()?;
+    compositor.apply_options(options);
+
+    let result = compositor.encode_with(options)?;
 
     info!("Image composition took {:?}", start.elapsed());
 
@@ -101,6 +537,7 @@ pub fn compose_layers(base_image_data: &[u8], layers: Vec<Bytes>) -> Result<Byte
 #[cfg(test)]
 mod tests {
     use super::*;
+    use image::ImageFormat;
 
     fn create_test_image(width: u32, height: u32, r: u8, g: u8, b: u8) -> Vec<u8> {
         let img = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
@@ -140,6 +577,29 @@ mod tests {
         assert_eq!(compositor.dimensions(), (100, 100));
     }
 
+    #[test]
+    fn test_perceptual_hash_changes_with_layers() {
+        let base = create_test_image(100, 100, 255, 0, 0);
+        let mut compositor = Compositor::new(&base).unwrap();
+        let before = compositor.perceptual_hash();
+
+        // Opaque layer covering the left half
+        let layer = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(100, 100, |x, _| {
+            if x < 50 {
+                image::Rgba([255, 255, 255, 255])
+            } else {
+                image::Rgba([0, 0, 0, 0])
+            }
+        }));
+        let mut buffer = Vec::new();
+        layer
+            .write_to(&mut Cursor::new(&mut buffer), ImageFormat::Png)
+            .unwrap();
+        compositor.add_layer(&buffer).unwrap();
+
+        assert_ne!(before, compositor.perceptual_hash());
+    }
+
     #[test]
     fn test_add_layer() {
         let base = create_test_image(100, 100, 255, 0, 0);
@@ -148,6 +608,82 @@ mod tests {
         assert!(compositor.add_layer(&layer).is_ok());
     }
 
+    #[test]
+    fn test_add_masked_layer() {
+        let base = create_test_image(100, 100, 255, 0, 0);
+        let layer = create_test_layer(100, 100, 0, 255, 0, 255);
+
+        // Half-size mask: white on the left, black on the right
+        let mask = DynamicImage::ImageLuma8(image::GrayImage::from_fn(50, 50, |x, _| {
+            image::Luma([if x < 25 { 255 } else { 0 }])
+        }));
+        let mut mask_data = Vec::new();
+        mask.write_to(&mut Cursor::new(&mut mask_data), ImageFormat::Png)
+            .unwrap();
+
+        let mut compositor = Compositor::new(&base).unwrap();
+        compositor.add_masked_layer(&layer, &mask_data).unwrap();
+
+        let composite = compositor.encode(OutputFormat::Png).unwrap();
+        let decoded = image::load_from_memory(&composite).unwrap().to_rgb8();
+
+        // Allow for JPEG noise in the base
+        let [r, g, _] = decoded.get_pixel(10, 50).0;
+        assert!(g > 240 && r < 16);
+        let [r, g, _] = decoded.get_pixel(90, 50).0;
+        assert!(r > 240 && g < 16);
+    }
+
+    #[test]
+    fn test_overlay_offset() {
+        let base = create_test_image(100, 100, 255, 0, 0);
+        let mut compositor = Compositor::new(&base).unwrap();
+
+        // Opaque layer covering the left half, shifted 20 pixels right and up
+        let layer = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(100, 100, |x, _| {
+            if x < 50 {
+                image::Rgba([0, 255, 0, 255])
+            } else {
+                image::Rgba([0, 0, 0, 0])
+            }
+        }));
+        compositor.overlay_offset(layer, &Offset::new(20, -20));
+
+        let composite = compositor.into_image().to_rgb8();
+        let [r, g, _] = composite.get_pixel(10, 50).0;
+        assert!(r > 240 && g < 16);
+        let [r, g, _] = composite.get_pixel(60, 50).0;
+        assert!(g > 240 && r < 16);
+        let [r, g, _] = composite.get_pixel(60, 90).0;
+        assert!(r > 240 && g < 16);
+    }
+
+    #[test]
+    fn test_layer_filter() {
+        let base = create_test_image(100, 100, 255, 0, 0);
+
+        // Half-size checkerboard, so the layer has to be upscaled
+        let layer = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(50, 50, |x, y| {
+            let v = if (x + y) % 2 == 0 { 255 } else { 0 };
+            image::Rgba([v, v, v, 255])
+        }));
+        let mut layer_data = Vec::new();
+        layer
+            .write_to(&mut Cursor::new(&mut layer_data), ImageFormat::Png)
+            .unwrap();
+
+        let render = |filter| {
+            let mut compositor = Compositor::new(&base).unwrap().with_layer_filter(filter);
+            compositor.add_layer(&layer_data).unwrap();
+            compositor.into_image().to_rgb8()
+        };
+
+        // Nearest only duplicates pixels, so no intermediate grays appear
+        let nearest = render(ResizeFilter::Nearest);
+        assert!(nearest.pixels().all(|p| p.0[0] == 0 || p.0[0] == 255));
+        assert_ne!(nearest, render(ResizeFilter::Lanczos3));
+    }
+
     #[test]
     fn test_compose_layers() {
         let base = create_test_image(100, 100, 255, 0, 0);
@@ -155,9 +691,146 @@ mod tests {
         let layer2 = create_test_layer(100, 100, 0, 0, 255, 128);
         let layers = vec![Bytes::from(layer1), Bytes::from(layer2)];
 
-        let result = compose_layers(&base, layers);
+        let result = compose_layers(&base, layers, &CompositeOptions::default());
         assert!(result.is_ok());
         let composite = result.unwrap();
         assert!(!composite.is_empty());
     }
+
+    #[test]
+    fn test_compose_layers_with_filter() {
+        let base = create_test_image(100, 100, 255, 0, 0);
+        let layers = vec![Bytes::from(create_test_layer(100, 100, 0, 255, 0, 128))];
+
+        let options = CompositeOptions::new().with_filter(Filter::Grayscale);
+        let composite = compose_layers(&base, layers, &options).unwrap();
+        let decoded = image::load_from_memory(&composite).unwrap().to_rgb8();
+        let [r, g, b] = decoded.get_pixel(50, 50).0;
+
+        // JPEG may shift channels slightly, but they should be near-equal
+        assert!(r.abs_diff(g) <= 4 && g.abs_diff(b) <= 4);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_encode_into_matches_encode() {
+        let base = create_test_image(64, 64, 255, 0, 0);
+        let layer = create_test_layer(64, 64, 0, 255, 0, 128);
+
+        for format in [OutputFormat::Jpeg, OutputFormat::Png] {
+            let mut compositor = Compositor::new(&base).unwrap();
+            compositor.add_layer(&layer).unwrap();
+            let buffered = compositor.encode(format).unwrap();
+
+            let mut compositor = Compositor::new(&base).unwrap();
+            compositor.add_layer(&layer).unwrap();
+            let mut streamed = Vec::new();
+            let written = compositor
+                .encode_into(&CompositeOptions::from(format), &mut streamed)
+                .await
+                .unwrap();
+
+            assert_eq!(written, streamed.len() as u64);
+            assert_eq!(streamed, buffered.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_compose_layers_with_options() {
+        let base = create_test_image(100, 60, 255, 0, 0);
+        let options = CompositeOptions::new()
+            .with_format(OutputFormat::Png)
+            .with_max_size(Some(50), None)
+            .with_resize_filter(crate::options::ResizeFilter::Triangle);
+
+        let composite = compose_layers(&base, vec![], &options).unwrap();
+        let decoded = image::load_from_memory(&composite).unwrap();
+
+        assert_eq!(image::guess_format(&composite).unwrap(), ImageFormat::Png);
+        assert_eq!((decoded.width(), decoded.height()), (50, 30));
+    }
+
+    #[test]
+    fn test_jpeg_quality() {
+        let base = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8])
+        }));
+        let mut png = Vec::new();
+        base.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+
+        let encode = |quality| {
+            let options = CompositeOptions::new().with_quality(quality);
+            let compositor = Compositor::new(&png).unwrap();
+            compositor.encode_with(&options).unwrap()
+        };
+        assert!(encode(20).len() < encode(95).len());
+    }
+
+    #[test]
+    fn test_background_fills_transparency() {
+        let layer = create_test_layer(10, 10, 0, 0, 0, 0);
+        let mut compositor = Compositor::new(&layer).unwrap();
+        compositor.apply_options(&CompositeOptions::new().with_background(Background([0, 0, 255])));
+
+        let png = compositor.encode(OutputFormat::Png).unwrap();
+        let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(decoded.get_pixel(5, 5).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_transparent_and_grayscale_plates() {
+        // Half-covering layer on a transparent plate: the rest stays transparent
+        let plate = create_test_layer(10, 10, 0, 0, 0, 0);
+        let mut compositor = Compositor::new(&plate).unwrap();
+        let layer = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(10, 10, |x, _| {
+            image::Rgba([0, 255, 0, if x < 5 { 255 } else { 0 }])
+        }));
+        compositor.overlay(layer.clone());
+        let png = compositor.encode(OutputFormat::Png).unwrap();
+        let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(decoded.get_pixel(2, 5).0, [0, 255, 0, 255]);
+        assert_eq!(decoded.get_pixel(7, 5).0[3], 0);
+
+        // Layers on a grayscale plate keep their color
+        let gray =
+            DynamicImage::ImageLuma8(image::GrayImage::from_pixel(10, 10, image::Luma([128])));
+        let mut compositor = Compositor::from_image(gray);
+        compositor.overlay(layer);
+        let image = compositor.into_image().to_rgb8();
+        assert_eq!(image.get_pixel(2, 5).0, [0, 255, 0]);
+        assert_eq!(image.get_pixel(7, 5).0, [128, 128, 128]);
+    }
+
+    #[test]
+    fn test_jpeg_flattens_onto_matte() {
+        let layer = create_test_layer(16, 16, 0, 0, 0, 0);
+        let encode = |options: &CompositeOptions| {
+            let jpeg = Compositor::new(&layer)
+                .unwrap()
+                .encode_with(options)
+                .unwrap();
+            let decoded = image::load_from_memory(&jpeg).unwrap().to_rgb8();
+            decoded.get_pixel(8, 8).0
+        };
+
+        let white = encode(&CompositeOptions::new());
+        assert!(white.iter().all(|&c| c > 250), "{:?}", white);
+        let red = encode(&CompositeOptions::new().with_matte(Background([255, 0, 0])));
+        assert!(red[0] > 250 && red[1] < 5 && red[2] < 5, "{:?}", red);
+    }
+
+    #[test]
+    fn test_encode_formats() {
+        let base = create_test_image(16, 16, 255, 0, 0);
+
+        for (format, image_format) in [
+            (OutputFormat::Png, ImageFormat::Png),
+            (OutputFormat::WebP, ImageFormat::WebP),
+            (OutputFormat::Avif, ImageFormat::Avif),
+        ] {
+            let encoded = Compositor::new(&base).unwrap().encode(format).unwrap();
+            assert_eq!(image::guess_format(&encoded).unwrap(), image_format);
+        }
+    }
 }
@@ -0,0 +1,291 @@
+//! Catalog completeness: which views each SKU has layers for
+//!
+//! Built from a storage listing (`{view}/{category}/.../{sku}.{ext}` paths).
+//! A SKU is expected in the views the catalog lists for it, or without a
+//! catalog in the views its category is rendered in. Files no SKU expects
+//! are reported as orphans.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::catalog::Catalog;
+use crate::graph::MASKS_CATEGORY;
+use crate::layers::PLATE_CATEGORY;
+use crate::lint::AssetFile;
+use crate::models::{Sku, View};
+use crate::registry::CategoryRegistry;
+
+/// Availability of one SKU in one view
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Cell {
+    /// Expected and in storage
+    Present,
+    /// Expected but not in storage
+    Missing,
+    /// In storage but not expected (an orphan)
+    Unexpected,
+    /// Neither expected nor in storage
+    NotApplicable,
+}
+
+/// Views of one SKU
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CoverageRow {
+    pub category: String,
+    pub sku: String,
+    pub cells: BTreeMap<View, Cell>,
+}
+
+/// A file that no SKU of the matrix expects
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Orphan {
+    pub path: String,
+    pub view: Option<View>,
+    pub reason: String,
+}
+
+/// SKU × view matrix of an asset listing
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Coverage {
+    /// Columns of the matrix
+    pub views: Vec<View>,
+    /// One row per SKU, sorted by category and SKU
+    pub rows: Vec<CoverageRow>,
+    pub orphans: Vec<Orphan>,
+}
+
+impl CoverageRow {
+    pub fn cell(&self, view: View) -> Cell {
+        self.cells
+            .get(&view)
+            .copied()
+            .unwrap_or(Cell::NotApplicable)
+    }
+
+    /// Expected views without a layer
+    pub fn missing(&self) -> Vec<View> {
+        self.cells
+            .iter()
+            .filter(|(_, cell)| **cell == Cell::Missing)
+            .map(|(view, _)| *view)
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        !self.cells.values().any(|cell| *cell == Cell::Missing)
+    }
+}
+
+impl Coverage {
+    /// Build the matrix from paths relative to the asset root
+    /// Plates and masks aren't SKUs and are left out
+    pub fn from_paths<'a>(
+        paths: impl IntoIterator<Item = &'a str>,
+        registry: &CategoryRegistry,
+        catalog: Option<&Catalog>,
+    ) -> Self {
+        let mut orphans = Vec::new();
+        let mut found: BTreeMap<(String, String), Vec<(View, String)>> = BTreeMap::new();
+
+        for path in paths {
+            let asset = AssetFile::from_path(path);
+            let orphan = |reason: String| Orphan {
+                path: asset.path.clone(),
+                view: asset.view,
+                reason,
+            };
+
+            let Some(view) = asset.view else {
+                orphans.push(orphan("not under a view directory".to_string()));
+                continue;
+            };
+            match asset.category.as_str() {
+                "" => orphans.push(orphan("not under a category directory".to_string())),
+                PLATE_CATEGORY | MASKS_CATEGORY => {}
+                category if registry.get(category).is_none() => {
+                    orphans.push(orphan(format!("unknown category '{}'", category)))
+                }
+                category if asset.extension != registry.extension(category) => {
+                    orphans.push(orphan(format!(
+                        "{} category reads .{} files, not .{}",
+                        category,
+                        registry.extension(category),
+                        asset.extension
+                    )))
+                }
+                category => found
+                    .entry((category.to_string(), Sku::new(&asset.sku).to_string()))
+                    .or_default()
+                    .push((view, asset.path.clone())),
+            }
+        }
+
+        // Catalogued SKUs get a row even without any layers
+        if let Some(catalog) = catalog {
+            for (name, category) in &catalog.categories {
+                for entry in &category.skus {
+                    found
+                        .entry((name.clone(), entry.sku().to_string()))
+                        .or_default();
+                }
+            }
+        }
+
+        let views = View::all();
+        let mut rows = Vec::new();
+        for ((category, sku), files) in found {
+            let expected = expected_views(&category, &sku, &views, registry, catalog);
+            let cells = views
+                .iter()
+                .map(|view| {
+                    let present = files.iter().any(|(v, _)| v == view);
+                    let cell = match (expected.contains(view), present) {
+                        (true, true) => Cell::Present,
+                        (true, false) => Cell::Missing,
+                        (false, true) => Cell::Unexpected,
+                        (false, false) => Cell::NotApplicable,
+                    };
+                    (*view, cell)
+                })
+                .collect();
+
+            for (view, path) in files.iter().filter(|(v, _)| !expected.contains(v)) {
+                let reason = match catalog {
+                    Some(_) => format!("{}/{} is not catalogued for this view", category, sku),
+                    None => format!("{} is not rendered in the {} view", category, view),
+                };
+                orphans.push(Orphan {
+                    path: path.clone(),
+                    view: Some(*view),
+                    reason,
+                });
+            }
+
+            rows.push(CoverageRow {
+                category,
+                sku,
+                cells,
+            });
+        }
+        orphans.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Self {
+            views,
+            rows,
+            orphans,
+        }
+    }
+
+    /// Rows missing at least one expected view
+    pub fn incomplete(&self) -> impl Iterator<Item = &CoverageRow> {
+        self.rows.iter().filter(|row| !row.is_complete())
+    }
+
+    /// Number of orphans under each view directory
+    pub fn orphans_by_view(&self) -> BTreeMap<View, usize> {
+        let mut counts = BTreeMap::new();
+        for view in self.orphans.iter().filter_map(|orphan| orphan.view) {
+            *counts.entry(view).or_default() += 1;
+        }
+        counts
+    }
+}
+
+/// Views a SKU should have layers for
+fn expected_views(
+    category: &str,
+    sku: &str,
+    views: &[View],
+    registry: &CategoryRegistry,
+    catalog: Option<&Catalog>,
+) -> Vec<View> {
+    match catalog {
+        Some(catalog) => catalog
+            .category(category)
+            .and_then(|c| {
+                c.find(&Sku::new(sku))
+                    .map(|entry| c.views_of(entry).to_vec())
+            })
+            .unwrap_or_default(),
+        None => views
+            .iter()
+            .copied()
+            .filter(|view| registry.allows(category, *view))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTING: &[&str] = &[
+        "front/hoodies/hoodie-black.png",
+        "back/hoodies/hoodie-black.png",
+        "front/hats/beanie.png",
+        "left/hats/beanie.png",
+        "front/plate/base-model-black.jpg",
+        "front/capes/wool.png",
+        "readme.txt",
+    ];
+
+    #[test]
+    fn test_matrix_from_registry() {
+        let coverage =
+            Coverage::from_paths(LISTING.iter().copied(), &CategoryRegistry::builtin(), None);
+
+        let skus: Vec<&str> = coverage.rows.iter().map(|row| row.sku.as_str()).collect();
+        assert_eq!(skus, ["beanie", "hoodie-black"]);
+
+        let beanie = &coverage.rows[0];
+        assert_eq!(beanie.cell(View::Front), Cell::Present);
+        assert_eq!(beanie.cell(View::Back), Cell::Missing);
+        assert_eq!(beanie.cell(View::Left), Cell::Unexpected);
+
+        let hoodie = &coverage.rows[1];
+        assert_eq!(hoodie.cell(View::Back), Cell::Present);
+        assert!(hoodie.missing().contains(&View::Side));
+        assert_eq!(coverage.incomplete().count(), 2);
+
+        let reasons: Vec<&str> = coverage.orphans.iter().map(|o| o.reason.as_str()).collect();
+        assert_eq!(
+            reasons,
+            [
+                "unknown category 'capes'",
+                "hats is not rendered in the left view",
+                "not under a view directory"
+            ]
+        );
+        assert_eq!(
+            coverage.orphans_by_view(),
+            BTreeMap::from([(View::Front, 1), (View::Left, 1)])
+        );
+    }
+
+    #[test]
+    fn test_matrix_from_catalog() {
+        let catalog = Catalog::from_json(
+            r#"{"categories": {"hats": {"views": ["front"], "skus": ["beanie", "cap"]}}}"#,
+        )
+        .unwrap();
+        let coverage = Coverage::from_paths(
+            LISTING.iter().copied(),
+            &CategoryRegistry::builtin(),
+            Some(&catalog),
+        );
+
+        let cap = coverage.rows.iter().find(|row| row.sku == "cap").unwrap();
+        assert_eq!(cap.missing(), [View::Front]);
+
+        // Hoodies aren't catalogued, so every hoodie layer is an orphan
+        let hoodie = coverage
+            .rows
+            .iter()
+            .find(|row| row.sku == "hoodie-black")
+            .unwrap();
+        assert!(hoodie.is_complete());
+        assert_eq!(hoodie.cell(View::Back), Cell::Unexpected);
+        assert_eq!(coverage.orphans.len(), 5);
+    }
+}
@@ -0,0 +1,187 @@
+//! Synthetic plates and layers for demos and tests
+//!
+//! Generates a simple silhouette plate and flat-colored garment layers on the fly,
+//! so the full pipeline can run without any stored assets.
+
+use bytes::Bytes;
+use image::{DynamicImage, GrayImage, ImageFormat, Luma, Rgb, RgbImage, Rgba, RgbaImage};
+use std::io::Cursor;
+use xxhash_rust::xxh64::xxh64;
+
+use crate::error::{BirlError, Result};
+use crate::models::View;
+
+pub const DEMO_WIDTH: u32 = 400;
+pub const DEMO_HEIGHT: u32 = 600;
+
+/// Rectangle in demo canvas coordinates: (x, y, width, height)
+type Region = (u32, u32, u32, u32);
+
+/// Generate a base plate: light backdrop with a grey figure silhouette
+pub fn synthetic_plate(view: View) -> Result<Bytes> {
+    let backdrop = match view {
+        View::Front => Rgb([236, 236, 232]),
+        View::Back => Rgb([228, 232, 236]),
+        _ => Rgb([232, 236, 228]),
+    };
+
+    let mut img = RgbImage::from_pixel(DEMO_WIDTH, DEMO_HEIGHT, backdrop);
+    let figure = Rgb([150, 150, 150]);
+
+    for region in [
+        (175, 40, 50, 60),    // head
+        (185, 100, 30, 20),   // neck
+        (140, 120, 120, 220), // torso
+        (110, 130, 30, 200),  // left arm
+        (260, 130, 30, 200),  // right arm
+        (145, 340, 50, 220),  // left leg
+        (205, 340, 50, 220),  // right leg
+    ] {
+        fill_rgb(&mut img, region, figure);
+    }
+
+    encode(DynamicImage::ImageRgb8(img), ImageFormat::Jpeg)
+}
+
+/// Generate a transparent layer with a flat garment shape for the category
+/// The color comes from a color word in the SKU, or a hash of it
+pub fn synthetic_layer(category: &str, sku: &str) -> Result<Bytes> {
+    let mut img = RgbaImage::new(DEMO_WIDTH, DEMO_HEIGHT);
+    let color = sku_color(sku);
+
+    for region in category_regions(category) {
+        fill_rgba(&mut img, *region, color);
+    }
+
+    encode(DynamicImage::ImageRgba8(img), ImageFormat::Png)
+}
+
+/// Generate a grayscale garment mask: white where the category's layer is drawn
+pub fn synthetic_mask(category: &str) -> Result<Bytes> {
+    let mut img = GrayImage::new(DEMO_WIDTH, DEMO_HEIGHT);
+
+    for &(x, y, w, h) in category_regions(category) {
+        for py in y..(y + h).min(DEMO_HEIGHT) {
+            for px in x..(x + w).min(DEMO_WIDTH) {
+                img.put_pixel(px, py, Luma([255]));
+            }
+        }
+    }
+
+    encode(DynamicImage::ImageLuma8(img), ImageFormat::Png)
+}
+
+fn category_regions(category: &str) -> &'static [Region] {
+    match category {
+        "pants" => &[
+            (140, 330, 120, 30),
+            (140, 340, 55, 225),
+            (205, 340, 55, 225),
+        ],
+        "tops" => &[(140, 120, 120, 210)],
+        "hoodies" => &[
+            (135, 110, 130, 225),
+            (105, 125, 40, 195),
+            (255, 125, 40, 195),
+        ],
+        "jackets" => &[
+            (130, 105, 140, 235),
+            (100, 120, 45, 190),
+            (255, 120, 45, 190),
+        ],
+        "outer-jackets" => &[
+            (125, 100, 150, 250),
+            (95, 115, 50, 195),
+            (255, 115, 50, 195),
+        ],
+        "hats" => &[(170, 30, 60, 30)],
+        "boots" => &[(140, 525, 60, 65), (200, 525, 60, 65)],
+        "belts" => &[(140, 322, 120, 14)],
+        "scarves" => &[(168, 92, 64, 44)],
+        "backpacks" => &[(150, 112, 18, 170), (232, 112, 18, 170)],
+        "gloves-bottom" | "gloves-top" | "gloves" => &[(105, 320, 40, 35), (255, 320, 40, 35)],
+        c if c.ends_with("-left") => &[(160, 160, 30, 30)],
+        c if c.ends_with("-right") => &[(210, 160, 30, 30)],
+        c if c.contains("patches") => &[(185, 200, 30, 30)],
+        _ => &[(150, 150, 100, 100)],
+    }
+}
+
+fn sku_color(sku: &str) -> Rgba<u8> {
+    const NAMED: &[(&str, [u8; 3])] = &[
+        ("darkgreen", [40, 70, 40]),
+        ("black", [30, 30, 30]),
+        ("grey", [110, 110, 115]),
+        ("gray", [110, 110, 115]),
+        ("red", [180, 40, 40]),
+        ("blue", [40, 70, 160]),
+        ("green", [60, 140, 60]),
+        ("white", [245, 245, 245]),
+        ("tan", [190, 160, 120]),
+    ];
+
+    let [r, g, b] = NAMED
+        .iter()
+        .find(|(name, _)| sku.contains(name))
+        .map(|(_, rgb)| *rgb)
+        .unwrap_or_else(|| {
+            let hash = xxh64(sku.as_bytes(), 0).to_be_bytes();
+            [hash[0], hash[1], hash[2]]
+        });
+
+    Rgba([r, g, b, 255])
+}
+
+fn fill_rgb(img: &mut RgbImage, (x, y, w, h): Region, color: Rgb<u8>) {
+    for py in y..(y + h).min(img.height()) {
+        for px in x..(x + w).min(img.width()) {
+            img.put_pixel(px, py, color);
+        }
+    }
+}
+
+fn fill_rgba(img: &mut RgbaImage, (x, y, w, h): Region, color: Rgba<u8>) {
+    for py in y..(y + h).min(img.height()) {
+        for px in x..(x + w).min(img.width()) {
+            img.put_pixel(px, py, color);
+        }
+    }
+}
+
+fn encode(img: DynamicImage, format: ImageFormat) -> Result<Bytes> {
+    let mut buffer = Vec::new();
+    img.write_to(&mut Cursor::new(&mut buffer), format)
+        .map_err(|e| BirlError::encode("synthetic image", e))?;
+    Ok(Bytes::from(buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compose_layers;
+    use crate::options::CompositeOptions;
+    use crate::sanity::check_image;
+
+    #[test]
+    fn test_synthetic_assets_compose() {
+        let plate = synthetic_plate(View::Front).unwrap();
+        let layers = vec![
+            synthetic_layer("pants", "cargo-darkgreen").unwrap(),
+            synthetic_layer("hoodies", "hoodie-black").unwrap(),
+        ];
+
+        let composite = compose_layers(&plate, layers, &CompositeOptions::default()).unwrap();
+        let image = image::load_from_memory(&composite).unwrap();
+
+        assert_eq!(image.width(), DEMO_WIDTH);
+        assert_eq!(image.height(), DEMO_HEIGHT);
+        assert_eq!(check_image(&image), None);
+    }
+
+    #[test]
+    fn test_sku_color() {
+        assert_eq!(sku_color("hoodie-black"), Rgba([30, 30, 30, 255]));
+        assert_eq!(sku_color("cargo-darkgreen"), Rgba([40, 70, 40, 255]));
+        assert_eq!(sku_color("mystery-item"), sku_color("mystery-item"));
+    }
+}
@@ -0,0 +1,163 @@
+use bytes::Bytes;
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+use std::io::Cursor;
+
+use crate::error::{BirlError, Result};
+
+/// Per-channel difference below which pixels count as unchanged
+/// Absorbs JPEG re-encoding noise between otherwise identical renders
+pub const DEFAULT_DIFF_THRESHOLD: u8 = 16;
+
+/// Result of comparing two composites
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct DiffReport {
+    pub width: u32,
+    pub height: u32,
+    pub changed_pixels: u64,
+    pub total_pixels: u64,
+    /// PNG with unchanged pixels faded and changed pixels in red
+    pub diff_image: Bytes,
+}
+
+impl DiffReport {
+    /// Percentage of pixels that changed (0.0 - 100.0)
+    pub fn changed_percent(&self) -> f64 {
+        if self.total_pixels == 0 {
+            return 0.0;
+        }
+        self.changed_pixels as f64 / self.total_pixels as f64 * 100.0
+    }
+
+    /// Check if the composites are identical within the threshold
+    pub fn is_identical(&self) -> bool {
+        self.changed_pixels == 0
+    }
+}
+
+/// Compare two encoded composites using the default threshold
+pub fn diff_composites(a: &[u8], b: &[u8]) -> Result<DiffReport> {
+    diff_composites_with_threshold(a, b, DEFAULT_DIFF_THRESHOLD)
+}
+
+/// Compare two encoded composites pixel by pixel
+/// A pixel changed if any channel differs by more than `threshold`
+pub fn diff_composites_with_threshold(a: &[u8], b: &[u8], threshold: u8) -> Result<DiffReport> {
+    let a = image::load_from_memory(a)
+        .map_err(|e| BirlError::decode("first composite", e))?
+        .to_rgba8();
+    let b = image::load_from_memory(b)
+        .map_err(|e| BirlError::decode("second composite", e))?
+        .to_rgba8();
+
+    if a.dimensions() != b.dimensions() {
+        return Err(BirlError::InvalidParam(format!(
+            "Composite dimensions differ: {}x{} vs {}x{}",
+            a.width(),
+            a.height(),
+            b.width(),
+            b.height()
+        )));
+    }
+
+    let (width, height) = a.dimensions();
+    let mut diff = RgbaImage::new(width, height);
+    let mut changed_pixels = 0;
+
+    for (x, y, pa) in a.enumerate_pixels() {
+        let pb = b.get_pixel(x, y);
+        let changed =
+            pa.0.iter()
+                .zip(pb.0.iter())
+                .any(|(ca, cb)| ca.abs_diff(*cb) > threshold);
+
+        let pixel = if changed {
+            changed_pixels += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            // Faded luminance of the original for context
+            let [r, g, b, _] = pa.0;
+            let luma = (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32) as u8;
+            let faded = 255 - (255 - luma) / 4;
+            Rgba([faded, faded, faded, 255])
+        };
+        diff.put_pixel(x, y, pixel);
+    }
+
+    let mut buffer = Vec::new();
+    DynamicImage::ImageRgba8(diff)
+        .write_to(&mut Cursor::new(&mut buffer), ImageFormat::Png)
+        .map_err(|e| BirlError::encode("diff image", e))?;
+
+    Ok(DiffReport {
+        width,
+        height,
+        changed_pixels,
+        total_pixels: width as u64 * height as u64,
+        diff_image: Bytes::from(buffer),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32, paint: impl Fn(u32, u32) -> [u8; 4]) -> Vec<u8> {
+        let img = RgbaImage::from_fn(width, height, |x, y| Rgba(paint(x, y)));
+        let mut buffer = Vec::new();
+        DynamicImage::ImageRgba8(img)
+            .write_to(&mut Cursor::new(&mut buffer), ImageFormat::Png)
+            .unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_identical_composites() {
+        let a = png(10, 10, |_, _| [10, 20, 30, 255]);
+        let report = diff_composites(&a, &a).unwrap();
+
+        assert!(report.is_identical());
+        assert_eq!(report.changed_percent(), 0.0);
+        assert_eq!(report.total_pixels, 100);
+    }
+
+    #[test]
+    fn test_changed_region() {
+        let a = png(10, 10, |_, _| [0, 0, 0, 255]);
+        let b = png(10, 10, |x, _| {
+            if x < 5 {
+                [255, 255, 255, 255]
+            } else {
+                [0, 0, 0, 255]
+            }
+        });
+
+        let report = diff_composites(&a, &b).unwrap();
+        assert_eq!(report.changed_pixels, 50);
+        assert_eq!(report.changed_percent(), 50.0);
+
+        let diff = image::load_from_memory(&report.diff_image)
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(diff.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_ne!(diff.get_pixel(9, 0).0, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_threshold_ignores_noise() {
+        let a = png(4, 4, |_, _| [100, 100, 100, 255]);
+        let b = png(4, 4, |_, _| [105, 100, 100, 255]);
+
+        assert!(diff_composites(&a, &b).unwrap().is_identical());
+        assert!(!diff_composites_with_threshold(&a, &b, 0)
+            .unwrap()
+            .is_identical());
+    }
+
+    #[test]
+    fn test_dimension_mismatch() {
+        let a = png(4, 4, |_, _| [0, 0, 0, 255]);
+        let b = png(5, 4, |_, _| [0, 0, 0, 255]);
+        assert!(diff_composites(&a, &b).is_err());
+    }
+}
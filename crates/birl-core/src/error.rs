@@ -0,0 +1,137 @@
+//! Errors of the core crate
+//!
+//! Callers can match on the variant instead of parsing messages, e.g. to
+//! answer a missing plate with 404 and an unreadable upload with 422.
+
+use std::io;
+use thiserror::Error;
+
+use crate::catalog::CatalogError;
+use crate::layers::{LayerConflictError, ViewValidationError};
+use crate::licenses::LicenseError;
+use crate::limits::LimitError;
+use crate::models::LayerParamError;
+use crate::rules::ConstraintError;
+
+/// Result with [`BirlError`] as the default error
+pub type Result<T, E = BirlError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum BirlError {
+    /// Bytes that aren't a readable image
+    #[error("Failed to decode {what}")]
+    DecodeError {
+        what: String,
+        #[source]
+        source: image::ImageError,
+    },
+    /// An image that couldn't be encoded in the requested format
+    #[error("Failed to encode {what}")]
+    EncodeError {
+        what: String,
+        #[source]
+        source: image::ImageError,
+    },
+    /// The plate of a render doesn't exist
+    #[error("Base plate not found: {0}")]
+    MissingPlate(String),
+    /// A source node whose asset wasn't supplied
+    #[error("Source '{id}' not found: {asset}")]
+    MissingSource { id: String, asset: String },
+    /// A view, param, option or pattern that doesn't parse
+    #[error("{0}")]
+    InvalidParam(String),
+    /// A composition graph that can't be evaluated
+    #[error("Invalid recipe: {0}")]
+    InvalidRecipe(String),
+    /// A layer param rejected by strict parsing
+    #[error(transparent)]
+    InvalidLayer(#[from] LayerParamError),
+    #[error(transparent)]
+    NotInCatalog(#[from] CatalogError),
+    #[error(transparent)]
+    NotInView(#[from] ViewValidationError),
+    #[error(transparent)]
+    LayerConflict(#[from] LayerConflictError),
+    #[error(transparent)]
+    ConstraintViolation(#[from] ConstraintError),
+    /// A request over the configured complexity limits
+    #[error(transparent)]
+    LimitExceeded(#[from] LimitError),
+    /// A render showing artwork where its license doesn't allow it
+    #[error(transparent)]
+    Unlicensed(#[from] LicenseError),
+    /// A catalog or other manifest that doesn't parse
+    #[error("Invalid {what}")]
+    InvalidManifest {
+        what: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl BirlError {
+    pub fn decode(what: impl Into<String>, source: image::ImageError) -> Self {
+        BirlError::DecodeError {
+            what: what.into(),
+            source,
+        }
+    }
+
+    pub fn encode(what: impl Into<String>, source: image::ImageError) -> Self {
+        BirlError::EncodeError {
+            what: what.into(),
+            source,
+        }
+    }
+
+    pub fn manifest(
+        what: impl Into<String>,
+        source: impl std::error::Error + Send + Sync + 'static,
+    ) -> Self {
+        BirlError::InvalidManifest {
+            what: what.into(),
+            source: Box::new(source),
+        }
+    }
+
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        BirlError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compositor::Compositor;
+    use crate::graph::{MissingSources, Recipe};
+    use crate::options::CompositeOptions;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_variants() {
+        let err = Compositor::new(b"not an image").err().unwrap();
+        assert!(matches!(err, BirlError::DecodeError { .. }));
+        assert_eq!(err.to_string(), "Failed to decode base image");
+
+        let recipe = Recipe::compile(&[], &[], &CompositeOptions::default());
+        let err = recipe
+            .evaluate(&HashMap::new(), MissingSources::Skip)
+            .err()
+            .unwrap();
+        assert!(matches!(err, BirlError::MissingSource { .. }));
+
+        let err = "sideways".parse::<crate::View>().unwrap_err();
+        assert!(matches!(err, BirlError::InvalidParam(_)));
+    }
+}
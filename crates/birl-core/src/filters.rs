@@ -0,0 +1,207 @@
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Post-processing filter applied to the finished composite before encoding
+/// String form: "grayscale", "sepia", "brightness:<-255..255>", "watermark"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Filter {
+    Grayscale,
+    Sepia,
+    Brightness(i32),
+    /// Translucent diagonal stripes, for artwork shown where it's restricted
+    Watermark,
+}
+
+impl Filter {
+    /// Apply the filter to an image
+    pub fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        match self {
+            Filter::Grayscale => DynamicImage::ImageRgba8(image.grayscale().to_rgba8()),
+            Filter::Sepia => sepia(image),
+            Filter::Brightness(value) => image.brighten(*value),
+            Filter::Watermark => watermark(image),
+        }
+    }
+}
+
+/// Classic sepia tone matrix
+fn sepia(image: &DynamicImage) -> DynamicImage {
+    let mut rgba = image.to_rgba8();
+
+    for pixel in rgba.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let (r, g, b) = (r as f32, g as f32, b as f32);
+
+        let tone = |cr: f32, cg: f32, cb: f32| (r * cr + g * cg + b * cb).min(255.0) as u8;
+
+        pixel.0 = [
+            tone(0.393, 0.769, 0.189),
+            tone(0.349, 0.686, 0.168),
+            tone(0.272, 0.534, 0.131),
+            a,
+        ];
+    }
+
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// Opacity of the watermark stripes
+const WATERMARK_OPACITY: f32 = 0.35;
+
+/// White stripes a sixteenth of the image wide, one band in four
+fn watermark(image: &DynamicImage) -> DynamicImage {
+    let mut rgba = image.to_rgba8();
+    let band = (rgba.width().max(rgba.height()) / 16).max(1);
+
+    for (x, y, pixel) in rgba.enumerate_pixels_mut() {
+        if !((x + y) / band).is_multiple_of(4) {
+            continue;
+        }
+        let [r, g, b, a] = pixel.0;
+        let blend = |c: u8| (c as f32 + (255.0 - c as f32) * WATERMARK_OPACITY) as u8;
+        let alpha = a.max((255.0 * WATERMARK_OPACITY) as u8);
+        pixel.0 = [blend(r), blend(g), blend(b), alpha];
+    }
+
+    DynamicImage::ImageRgba8(rgba)
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::Grayscale => write!(f, "grayscale"),
+            Filter::Sepia => write!(f, "sepia"),
+            Filter::Brightness(value) => write!(f, "brightness:{}", value),
+            Filter::Watermark => write!(f, "watermark"),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+
+        match s.split_once(':') {
+            None if s == "grayscale" || s == "greyscale" => Ok(Filter::Grayscale),
+            None if s == "sepia" => Ok(Filter::Sepia),
+            None if s == "watermark" => Ok(Filter::Watermark),
+            Some(("brightness", value)) => {
+                let value: i32 = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid brightness value: {}", value))?;
+                if !(-255..=255).contains(&value) {
+                    return Err(format!(
+                        "Brightness must be between -255 and 255: {}",
+                        value
+                    ));
+                }
+                Ok(Filter::Brightness(value))
+            }
+            _ => Err(format!(
+                "Invalid filter: {}. Must be one of: grayscale, sepia, brightness:<n>, watermark",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Filter {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Filter> for String {
+    fn from(filter: Filter) -> Self {
+        filter.to_string()
+    }
+}
+
+/// Cache key variant for a filter chain, empty when no filters are applied
+pub fn filters_variant(filters: &[Filter]) -> String {
+    filters
+        .iter()
+        .map(|f| f.to_string())
+        .collect::<Vec<_>>()
+        .join("+")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(r: u8, g: u8, b: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 4, image::Rgb([r, g, b])))
+    }
+
+    #[test]
+    fn test_filter_parse() {
+        assert_eq!("grayscale".parse::<Filter>().unwrap(), Filter::Grayscale);
+        assert_eq!("Sepia".parse::<Filter>().unwrap(), Filter::Sepia);
+        assert_eq!(
+            "brightness:-20".parse::<Filter>().unwrap(),
+            Filter::Brightness(-20)
+        );
+        assert!("brightness:300".parse::<Filter>().is_err());
+        assert!("blur".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn test_filter_roundtrip() {
+        for filter in [
+            Filter::Grayscale,
+            Filter::Sepia,
+            Filter::Brightness(15),
+            Filter::Watermark,
+        ] {
+            assert_eq!(filter.to_string().parse::<Filter>().unwrap(), filter);
+        }
+    }
+
+    #[test]
+    fn test_grayscale() {
+        let result = Filter::Grayscale.apply(&solid(255, 0, 0)).to_rgba8();
+        let [r, g, b, _] = result.get_pixel(0, 0).0;
+        assert_eq!(r, g);
+        assert_eq!(g, b);
+    }
+
+    #[test]
+    fn test_brightness() {
+        let result = Filter::Brightness(20)
+            .apply(&solid(100, 100, 100))
+            .to_rgb8();
+        assert_eq!(result.get_pixel(0, 0).0, [120, 120, 120]);
+    }
+
+    #[test]
+    fn test_sepia() {
+        let result = Filter::Sepia.apply(&solid(100, 100, 100)).to_rgb8();
+        let [r, g, b] = result.get_pixel(0, 0).0;
+        assert!(r > g && g > b);
+    }
+
+    #[test]
+    fn test_watermark() {
+        let result = Filter::Watermark.apply(&solid(0, 0, 0)).to_rgba8();
+        assert_eq!(result.get_pixel(0, 0).0, [89, 89, 89, 255]);
+        assert_eq!(result.get_pixel(1, 0).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_filters_variant() {
+        assert_eq!(filters_variant(&[]), "");
+        assert_eq!(
+            filters_variant(&[Filter::Grayscale, Filter::Brightness(10)]),
+            "grayscale+brightness:10"
+        );
+    }
+}
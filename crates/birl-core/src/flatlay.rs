@@ -0,0 +1,281 @@
+//! Flat lays: an outfit's garments laid out on a canvas instead of worn
+//!
+//! ```toml
+//! [flat_lay]
+//! width = 1600
+//! height = 1200
+//! background = "#f4f1ec"
+//!
+//! [flat_lay.slots]
+//! hoodies = { x = 40, y = 40, width = 720, height = 720 }
+//! pants = { x = 840, y = 40, width = 720, height = 1120 }
+//! hats = { x = 40, y = 800, width = 360, height = 360 }
+//! ```
+//!
+//! Layers are read from one view (front by default). Each is trimmed to its
+//! visible part and scaled to fit its category's slot, centered; slots are
+//! keyed by normalized category. Layers of categories without a slot are
+//! dropped. Without a `background` the canvas is transparent.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::cache::{generate_cache_key, variant_cache_key};
+use crate::error::{BirlError, Result};
+use crate::graph::{push_output, Asset, Node, Placement, Recipe};
+use crate::layers::{DropReason, DroppedLayer};
+use crate::models::{LayerParam, View};
+use crate::options::{Background, CompositeOptions};
+use crate::tables::Tables;
+
+/// Plate value of flat lays in their cache keys
+const FLAT_LAY_PLATE: &str = "flat-lay";
+
+/// Box a category's layer is fitted into, in canvas pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Slot {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Slot {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// `x,y+WxH`
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}+{}x{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+/// Canvas and slots of a flat lay
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlatLay {
+    pub width: u32,
+    pub height: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background: Option<Background>,
+    /// View the garment layers are read from
+    #[serde(default = "default_view")]
+    pub view: View,
+    /// Slot of each normalized category
+    #[serde(default)]
+    pub slots: BTreeMap<String, Slot>,
+}
+
+fn default_view() -> View {
+    View::Front
+}
+
+impl FlatLay {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            background: None,
+            view: default_view(),
+            slots: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_background(mut self, background: Background) -> Self {
+        self.background = Some(background);
+        self
+    }
+
+    pub fn with_view(mut self, view: View) -> Self {
+        self.view = view;
+        self
+    }
+
+    pub fn with_slot(mut self, category: &str, slot: Slot) -> Self {
+        self.slots.insert(category.to_string(), slot);
+        self
+    }
+
+    pub fn slot(&self, category: &str) -> Option<&Slot> {
+        self.slots.get(category)
+    }
+
+    /// Check the canvas isn't empty and every slot is on it
+    pub fn validate(&self) -> Result<()> {
+        if self.width == 0 || self.height == 0 {
+            return Err(BirlError::InvalidParam(format!(
+                "Empty flat lay canvas {}x{}",
+                self.width, self.height
+            )));
+        }
+        for (category, slot) in &self.slots {
+            let fits = u64::from(slot.x) + u64::from(slot.width) <= u64::from(self.width)
+                && u64::from(slot.y) + u64::from(slot.height) <= u64::from(self.height);
+            if slot.width == 0 || slot.height == 0 || !fits {
+                return Err(BirlError::InvalidParam(format!(
+                    "Flat lay slot {} of {} is empty or outside the {}x{} canvas",
+                    slot, category, self.width, self.height
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Split normalized layers into those with a slot and those dropped
+    pub fn arrange(&self, layers: &[LayerParam]) -> (Vec<LayerParam>, Vec<DroppedLayer>) {
+        let mut slotted = Vec::with_capacity(layers.len());
+        let mut dropped = Vec::new();
+        for layer in layers {
+            if self.slots.contains_key(&layer.category) {
+                slotted.push(layer.clone());
+            } else {
+                dropped.push(DroppedLayer {
+                    layer: layer.clone(),
+                    reason: DropReason::NoSlot,
+                });
+            }
+        }
+        (slotted, dropped)
+    }
+
+    /// Compile the recipe: the canvas, then each layer with a slot fitted
+    /// into it, in order
+    pub fn compile(&self, layers: &[LayerParam], options: &CompositeOptions) -> Recipe {
+        let mut nodes = vec![Node::Canvas {
+            id: "canvas".to_string(),
+            width: self.width,
+            height: self.height,
+            background: self.background,
+        }];
+        let mut current = "canvas".to_string();
+
+        for (idx, layer) in layers.iter().enumerate() {
+            let Some(slot) = self.slot(&layer.category) else {
+                continue;
+            };
+            let layer_id = format!("layer-{}", idx);
+            nodes.push(Node::Source {
+                id: layer_id.clone(),
+                asset: Asset::Layer(layer.clone()),
+            });
+
+            let fit_id = format!("fit-{}", idx);
+            nodes.push(Node::Fit {
+                id: fit_id.clone(),
+                input: layer_id,
+                width: slot.width,
+                height: slot.height,
+                resize_filter: options.layer_filter,
+            });
+
+            let blend_id = format!("blend-{}", idx);
+            nodes.push(Node::Blend {
+                id: blend_id.clone(),
+                base: current,
+                layer: fit_id,
+                resize_filter: options.layer_filter,
+                at: Some(Placement::new(slot.x, slot.y)),
+                offset: None,
+            });
+            current = blend_id;
+        }
+
+        push_output(&mut nodes, current, options);
+        Recipe { nodes }
+    }
+
+    /// Cache key of a flat lay of normalized layers
+    /// The layout is part of the variant, so changing it re-renders
+    pub fn cache_key(&self, layers: &[LayerParam], options: &CompositeOptions) -> String {
+        let mut variant = format!("{}_{}x{}", options.cache_variant(), self.width, self.height);
+        if let Some(background) = self.background {
+            variant.push_str(&format!("_{}", background));
+        }
+        for layer in layers {
+            if let Some(slot) = self.slot(&layer.category) {
+                variant.push_str(&format!("_{}@{}", layer.category, slot));
+            }
+        }
+        variant_cache_key(
+            &generate_cache_key(layers, self.view, FLAT_LAY_PLATE),
+            &variant,
+        )
+    }
+
+    /// The current tables' flat lay, if one was installed
+    pub fn global() -> Option<Arc<FlatLay>> {
+        Tables::current().flat_lay.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::synthetic_layer;
+    use crate::format::OutputFormat;
+    use crate::layers::parse_params;
+    use crate::render::{render, Assets};
+    use bytes::Bytes;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_flat_lay() {
+        let layout = FlatLay::new(400, 200)
+            .with_background("#ffffff".parse().unwrap())
+            .with_slot("hoodies", Slot::new(0, 0, 200, 200))
+            .with_slot("pants", Slot::new(200, 0, 200, 200));
+        layout.validate().unwrap();
+        assert!(layout
+            .clone()
+            .with_slot("hats", Slot::new(300, 100, 200, 200))
+            .validate()
+            .is_err());
+
+        let layers = parse_params("hoodies/hoodie-black,pants/cargo-black,hats/beanie-red");
+        let (slotted, dropped) = layout.arrange(&layers);
+        assert_eq!(slotted.len(), 2);
+        assert_eq!(dropped[0].layer, layers[2]);
+        assert_eq!(dropped[0].reason.name(), "no-slot");
+
+        let options = CompositeOptions::new().with_format(OutputFormat::Png);
+        let recipe = layout.compile(&slotted, &options);
+        let sources: HashMap<String, Bytes> = recipe
+            .assets()
+            .into_iter()
+            .map(|asset| match asset {
+                Asset::Layer(p) => (
+                    asset.to_string(),
+                    synthetic_layer(&p.category, p.sku.as_str()).unwrap(),
+                ),
+                Asset::Plate => unreachable!("flat lays have no plate"),
+            })
+            .collect();
+        let rendered = render(&recipe, &Assets::from(sources)).unwrap();
+        let image = image::load_from_memory(&rendered.data).unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), (400, 200));
+        // Each garment fills its slot, centered, on the white canvas
+        let center = image.get_pixel(100, 100).0;
+        assert_ne!(center, [255, 255, 255, 255]);
+        assert_eq!(image.get_pixel(0, 0).0, [255, 255, 255, 255]);
+
+        // The layout is part of the cache key
+        let moved = layout
+            .clone()
+            .with_slot("pants", Slot::new(200, 0, 100, 100));
+        assert_ne!(
+            layout.cache_key(&slotted, &options),
+            moved.cache_key(&slotted, &options)
+        );
+    }
+}
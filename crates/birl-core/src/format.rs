@@ -0,0 +1,115 @@
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Encoded output format of a composite
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Jpeg,
+    Png,
+    WebP,
+    Avif,
+}
+
+impl OutputFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::Png => "png",
+            OutputFormat::WebP => "webp",
+            OutputFormat::Avif => "avif",
+        }
+    }
+
+    /// File extension used for stored composites
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Png => "png",
+            OutputFormat::WebP => "webp",
+            OutputFormat::Avif => "avif",
+        }
+    }
+
+    /// MIME type for Content-Type and Accept matching
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Png => "image/png",
+            OutputFormat::WebP => "image/webp",
+            OutputFormat::Avif => "image/avif",
+        }
+    }
+
+    pub fn image_format(&self) -> ImageFormat {
+        match self {
+            OutputFormat::Jpeg => ImageFormat::Jpeg,
+            OutputFormat::Png => ImageFormat::Png,
+            OutputFormat::WebP => ImageFormat::WebP,
+            OutputFormat::Avif => ImageFormat::Avif,
+        }
+    }
+
+    /// Whether the encoder takes a quality
+    pub fn is_lossy(&self) -> bool {
+        matches!(self, OutputFormat::Jpeg | OutputFormat::Avif)
+    }
+
+    /// Whether the format stores transparency; JPEG output is flattened onto a matte
+    pub fn has_alpha(&self) -> bool {
+        !matches!(self, OutputFormat::Jpeg)
+    }
+
+    /// Cache key variant for this format
+    /// JPEG is the original output, so it keeps the plain key
+    pub fn cache_variant(&self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "",
+            other => other.as_str(),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            "png" => Ok(OutputFormat::Png),
+            "webp" => Ok(OutputFormat::WebP),
+            "avif" => Ok(OutputFormat::Avif),
+            _ => Err(format!(
+                "Invalid format: {}. Must be one of: jpeg, png, webp, avif",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_format_parse() {
+        assert_eq!("jpg".parse::<OutputFormat>().unwrap(), OutputFormat::Jpeg);
+        assert_eq!("AVIF".parse::<OutputFormat>().unwrap(), OutputFormat::Avif);
+        assert!("gif".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_jpeg_keeps_plain_cache_key() {
+        assert_eq!(OutputFormat::Jpeg.cache_variant(), "");
+        assert_eq!(OutputFormat::Avif.cache_variant(), "avif");
+    }
+}
@@ -0,0 +1,1312 @@
+//! Composition graph: a small DAG of image operations
+//!
+//! The standard pipeline compiles into a [`Recipe`] (plate, underlays and the plate
+//! restored over them, then each layer blended in z-order, patches masked to their
+//! garment, then transform and encode). Patches with a [`Placement`] are small
+//! assets positioned on the garment rather than full-frame layers.
+//! Advanced callers can send their own recipe as JSON:
+//!
+//! ```json
+//! { "nodes": [
+//!     { "op": "source", "id": "plate", "asset": "plate" },
+//!     { "op": "source", "id": "hoodie", "asset": "hoodies/hoodie-black" },
+//!     { "op": "blend", "id": "out", "base": "plate", "layer": "hoodie" },
+//!     { "op": "encode", "input": "out", "format": "webp" }
+//! ] }
+//! ```
+
+use bytes::Bytes;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::compositor::{
+    apply_mask, apply_mask_at, canvas, decode_image, fit_into, Compositor, DecodeCache,
+};
+use crate::error::{BirlError, Result};
+use crate::filters::Filter;
+use crate::format::OutputFormat;
+use crate::limits::Limits;
+use crate::models::LayerParam;
+use crate::offsets::Offset;
+use crate::options::{Background, CompositeOptions, ResizeFilter};
+use crate::orient::{Flip, Orientation, Rotation};
+use crate::registry::CategoryRegistry;
+use crate::render::{render, Assets};
+use crate::svg::{is_svg, rasterize as rasterize_svg};
+
+/// Nodes a caller-supplied recipe may have (see [`Recipe::check_sources`])
+pub const MAX_RECIPE_NODES: usize = 256;
+
+/// Category of garment masks in storage (`{view}/masks/{garment}.png`)
+pub const MASKS_CATEGORY: &str = "masks";
+
+/// Stored image a source node reads: the view's base plate or a layer
+/// String form: "plate", "category/sku", or "category/https://..."
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Asset {
+    Plate,
+    Layer(LayerParam),
+}
+
+impl Asset {
+    /// Mask asset for a garment category
+    pub fn mask(garment: &str) -> Self {
+        Asset::Layer(LayerParam::new(MASKS_CATEGORY, garment))
+    }
+}
+
+impl fmt::Display for Asset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Asset::Plate => write!(f, "plate"),
+            Asset::Layer(param) => write!(f, "{}", param),
+        }
+    }
+}
+
+impl TryFrom<String> for Asset {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if s.trim() == "plate" {
+            return Ok(Asset::Plate);
+        }
+        LayerParam::parse(&s)
+            .map(Asset::Layer)
+            .ok_or_else(|| format!("Invalid asset: {}. Expected plate or category/sku", s))
+    }
+}
+
+impl From<Asset> for String {
+    fn from(asset: Asset) -> Self {
+        asset.to_string()
+    }
+}
+
+/// Where a small layer (e.g. a patch) goes on its base, in base pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Placement {
+    /// Left edge of the layer
+    pub x: u32,
+    /// Top edge of the layer
+    pub y: u32,
+    /// Width to scale the layer to, keeping its aspect ratio (default: as stored)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    /// Clockwise turn of the layer before it's placed (see [`crate::orient`])
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub rotate: Rotation,
+    /// Mirroring of the layer before it's placed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flip: Option<Flip>,
+}
+
+impl Placement {
+    pub fn new(x: u32, y: u32) -> Self {
+        Self {
+            x,
+            y,
+            width: None,
+            rotate: Rotation::default(),
+            flip: None,
+        }
+    }
+
+    pub fn with_width(mut self, width: u32) -> Self {
+        self.width = Some(width);
+        self
+    }
+
+    pub fn with_rotation(mut self, rotate: Rotation) -> Self {
+        self.rotate = rotate;
+        self
+    }
+
+    pub fn with_flip(mut self, flip: Flip) -> Self {
+        self.flip = Some(flip);
+        self
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        Orientation::new(self.rotate, self.flip)
+    }
+
+    /// Size a `width` x `height` layer is scaled to
+    pub fn size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.width {
+            Some(target) if target != width && width > 0 => {
+                let scaled = (f64::from(height) * f64::from(target) / f64::from(width)).round();
+                (target, (scaled as u32).max(1))
+            }
+            _ => (width, height),
+        }
+    }
+}
+
+impl fmt::Display for Placement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.x, self.y)?;
+        if let Some(width) = self.width {
+            write!(f, ",w{}", width)?;
+        }
+        if !self.orientation().is_identity() {
+            write!(f, ",{}", self.orientation())?;
+        }
+        Ok(())
+    }
+}
+
+/// One operation in a recipe
+/// Nodes reference the outputs of earlier nodes by id
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Node {
+    /// Decode a stored image
+    Source { id: String, asset: Asset },
+    /// A blank `width` x `height` image, filled with `background` if set
+    Canvas {
+        id: String,
+        width: u32,
+        height: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        background: Option<Background>,
+    },
+    /// A transparent image the size of `input`
+    Clear { id: String, input: String },
+    /// Turn and flip `input` (see [`crate::orient`]), keeping its size when
+    /// `keep_size` is set (full-frame layers)
+    Orient {
+        id: String,
+        input: String,
+        orientation: Orientation,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        keep_size: bool,
+    },
+    /// Alpha-blend `layer` over `base`, resizing the layer to the base, or
+    /// scaling and positioning it `at` a placement
+    /// A resized layer is shifted by its `offset`, if it has one
+    Blend {
+        id: String,
+        base: String,
+        layer: String,
+        #[serde(default, skip_serializing_if = "is_default")]
+        resize_filter: ResizeFilter,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        at: Option<Placement>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<Offset>,
+    },
+    /// Multiply `input`'s alpha by the luminance of `mask`
+    /// With `at`, `input` is scaled for the placement and masked by the region
+    /// of `mask` it will cover
+    Mask {
+        id: String,
+        input: String,
+        mask: String,
+        #[serde(default, skip_serializing_if = "is_default")]
+        resize_filter: ResizeFilter,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        at: Option<Placement>,
+    },
+    /// Trim `input`'s transparent border and scale what's left to fit
+    /// `width` x `height`, centered
+    Fit {
+        id: String,
+        input: String,
+        width: u32,
+        height: u32,
+        #[serde(default, skip_serializing_if = "is_default")]
+        resize_filter: ResizeFilter,
+    },
+    /// Resize, fill the background, and apply filters
+    Transform {
+        id: String,
+        input: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_width: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_height: Option<u32>,
+        #[serde(default, skip_serializing_if = "is_default")]
+        resize_filter: ResizeFilter,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        background: Option<Background>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        filters: Vec<Filter>,
+    },
+    /// Encode `input`; must be the last node
+    Encode {
+        input: String,
+        #[serde(default)]
+        format: OutputFormat,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        quality: Option<u8>,
+        /// Color transparent areas are flattened onto for JPEG (default white)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        matte: Option<Background>,
+    },
+}
+
+/// Blend one layer over `current`, oriented if it has an orientation and
+/// through a garment mask if it has one
+/// Returns the id of the blend
+#[allow(clippy::too_many_arguments)]
+fn push_layer(
+    nodes: &mut Vec<Node>,
+    current: String,
+    idx: usize,
+    layer: &LayerParam,
+    garment: Option<&str>,
+    at: Option<Placement>,
+    offset: Option<Offset>,
+    orientation: Option<Orientation>,
+    options: &CompositeOptions,
+) -> String {
+    let mut layer_id = format!("layer-{}", idx);
+    nodes.push(Node::Source {
+        id: layer_id.clone(),
+        asset: Asset::Layer(layer.clone()),
+    });
+
+    // The orient node turns the layer; the placement only positions it
+    let at = at.map(|at| Placement {
+        rotate: Rotation::default(),
+        flip: None,
+        ..at
+    });
+    if let Some(orientation) = orientation {
+        let oriented_id = format!("oriented-{}", idx);
+        nodes.push(Node::Orient {
+            id: oriented_id.clone(),
+            input: layer_id,
+            orientation,
+            keep_size: at.is_none(),
+        });
+        layer_id = oriented_id;
+    }
+
+    if let Some(garment) = garment {
+        let mask_id = format!("mask-{}", idx);
+        nodes.push(Node::Source {
+            id: mask_id.clone(),
+            asset: Asset::mask(garment),
+        });
+
+        let masked_id = format!("masked-{}", idx);
+        nodes.push(Node::Mask {
+            id: masked_id.clone(),
+            input: layer_id,
+            mask: mask_id,
+            resize_filter: options.layer_filter,
+            at,
+        });
+        layer_id = masked_id;
+    }
+
+    let blend_id = format!("blend-{}", idx);
+    nodes.push(Node::Blend {
+        id: blend_id.clone(),
+        base: current,
+        layer: layer_id,
+        resize_filter: options.layer_filter,
+        at,
+        offset,
+    });
+    blend_id
+}
+
+/// Transform `current` as the options ask, if they ask for anything, and encode it
+pub(crate) fn push_output(nodes: &mut Vec<Node>, mut current: String, options: &CompositeOptions) {
+    let processing = CompositeOptions {
+        format: OutputFormat::default(),
+        quality: None,
+        layer_filter: ResizeFilter::default(),
+        matte: None,
+        cutout: false,
+        ..options.clone()
+    };
+    if !processing.is_passthrough() {
+        nodes.push(Node::Transform {
+            id: "transform".to_string(),
+            input: current.clone(),
+            max_width: options.max_width,
+            max_height: options.max_height,
+            resize_filter: options.resize_filter,
+            background: options.background,
+            filters: options.filters.clone(),
+        });
+        current = "transform".to_string();
+    }
+
+    nodes.push(Node::Encode {
+        input: current,
+        format: options.format,
+        quality: options.quality,
+        matte: options.matte,
+    });
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+impl Node {
+    fn id(&self) -> Option<&str> {
+        match self {
+            Node::Source { id, .. }
+            | Node::Canvas { id, .. }
+            | Node::Clear { id, .. }
+            | Node::Orient { id, .. }
+            | Node::Blend { id, .. }
+            | Node::Mask { id, .. }
+            | Node::Fit { id, .. }
+            | Node::Transform { id, .. } => Some(id),
+            Node::Encode { .. } => None,
+        }
+    }
+
+    fn inputs(&self) -> Vec<&str> {
+        match self {
+            Node::Source { .. } | Node::Canvas { .. } => vec![],
+            Node::Blend { base, layer, .. } => vec![base, layer],
+            Node::Mask { input, mask, .. } => vec![input, mask],
+            Node::Clear { input, .. }
+            | Node::Orient { input, .. }
+            | Node::Fit { input, .. }
+            | Node::Transform { input, .. }
+            | Node::Encode { input, .. } => vec![input],
+        }
+    }
+}
+
+/// How evaluation treats sources missing from the provided images
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingSources {
+    /// Fail the evaluation
+    Error,
+    /// Blend without the missing layer and skip missing masks
+    /// The base of a blend is still required
+    Skip,
+}
+
+/// A composition graph, evaluated in node order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Recipe {
+    pub nodes: Vec<Node>,
+}
+
+impl Recipe {
+    /// Compile the standard pipeline: plate, layers in order, optional patch masks
+    /// `masks` is aligned with `layers` (see [`crate::layers::patch_mask_categories`])
+    pub fn compile(
+        layers: &[LayerParam],
+        masks: &[Option<String>],
+        options: &CompositeOptions,
+    ) -> Self {
+        Self::compile_with(
+            &CategoryRegistry::global(),
+            layers,
+            masks,
+            &[],
+            &[],
+            &[],
+            options,
+        )
+    }
+
+    /// Like [`Self::compile`], placing layers with a placement instead of
+    /// stretching them over the plate, shifting the others by an offset, and
+    /// orienting layers with an orientation
+    /// `placements`, `offsets` and `orientations` are aligned with `layers` (see
+    /// [`crate::Catalog::patch_placements`], [`crate::LayerOffsets::for_layers`]
+    /// and [`crate::Orientations::for_layers`])
+    pub fn compile_placed(
+        layers: &[LayerParam],
+        masks: &[Option<String>],
+        placements: &[Option<Placement>],
+        offsets: &[Option<Offset>],
+        orientations: &[Option<Orientation>],
+        options: &CompositeOptions,
+    ) -> Self {
+        Self::compile_with(
+            &CategoryRegistry::global(),
+            layers,
+            masks,
+            placements,
+            offsets,
+            orientations,
+            options,
+        )
+    }
+
+    /// Like [`Self::compile_placed`], with underlay categories looked up in `registry`
+    ///
+    /// Layers of underlay categories are blended first, whatever their z-order;
+    /// the plate is then blended back over them through each underlay mask.
+    /// Without its mask, an underlay ends up hidden under the whole plate.
+    /// Cutouts blend the layers over a transparent copy of the plate instead,
+    /// so underlays are shown whole.
+    pub fn compile_with(
+        registry: &CategoryRegistry,
+        layers: &[LayerParam],
+        masks: &[Option<String>],
+        placements: &[Option<Placement>],
+        offsets: &[Option<Offset>],
+        orientations: &[Option<Orientation>],
+        options: &CompositeOptions,
+    ) -> Self {
+        let mut nodes = vec![Node::Source {
+            id: "plate".to_string(),
+            asset: Asset::Plate,
+        }];
+        let mut current = "plate".to_string();
+        if options.cutout {
+            nodes.push(Node::Clear {
+                id: "cutout".to_string(),
+                input: current,
+            });
+            current = "cutout".to_string();
+        }
+
+        let mut underlay_masks: Vec<&str> = Vec::new();
+        for (idx, layer) in layers.iter().enumerate() {
+            if let Some(mask) = registry.underlay_mask(&layer.category) {
+                let offset = offsets.get(idx).copied().flatten();
+                let orientation = orientations.get(idx).copied().flatten();
+                current = push_layer(
+                    &mut nodes,
+                    current,
+                    idx,
+                    layer,
+                    None,
+                    None,
+                    offset,
+                    orientation,
+                    options,
+                );
+                if !options.cutout && !underlay_masks.contains(&mask) {
+                    underlay_masks.push(mask);
+                }
+            }
+        }
+        for mask in underlay_masks {
+            let mask_id = format!("underlay-mask-{}", mask);
+            nodes.push(Node::Source {
+                id: mask_id.clone(),
+                asset: Asset::mask(mask),
+            });
+
+            let masked_id = format!("plate-{}", mask);
+            nodes.push(Node::Mask {
+                id: masked_id.clone(),
+                input: "plate".to_string(),
+                mask: mask_id,
+                resize_filter: options.layer_filter,
+                at: None,
+            });
+
+            let restore_id = format!("restore-{}", mask);
+            nodes.push(Node::Blend {
+                id: restore_id.clone(),
+                base: current,
+                layer: masked_id,
+                resize_filter: options.layer_filter,
+                at: None,
+                offset: None,
+            });
+            current = restore_id;
+        }
+
+        for (idx, layer) in layers.iter().enumerate() {
+            if registry.underlay_mask(&layer.category).is_none() {
+                let mask = masks.get(idx).and_then(Option::as_deref);
+                let at = placements.get(idx).copied().flatten();
+                let offset = offsets.get(idx).copied().flatten();
+                let orientation = orientations.get(idx).copied().flatten();
+                current = push_layer(
+                    &mut nodes,
+                    current,
+                    idx,
+                    layer,
+                    mask,
+                    at,
+                    offset,
+                    orientation,
+                    options,
+                );
+            }
+        }
+
+        push_output(&mut nodes, current, options);
+        Self { nodes }
+    }
+
+    /// Check ids are unique, inputs refer to earlier nodes, and the recipe ends in one encode
+    pub fn validate(&self) -> Result<()> {
+        let mut defined = HashSet::new();
+
+        for (idx, node) in self.nodes.iter().enumerate() {
+            for input in node.inputs() {
+                if !defined.contains(input) {
+                    return Err(BirlError::InvalidRecipe(format!(
+                        "Node {} refers to unknown or later node '{}'",
+                        idx, input
+                    )));
+                }
+            }
+
+            match node.id() {
+                Some(id) if !defined.insert(id) => {
+                    return Err(BirlError::InvalidRecipe(format!(
+                        "Duplicate node id '{}'",
+                        id
+                    )));
+                }
+                None if idx + 1 != self.nodes.len() => {
+                    return Err(BirlError::InvalidRecipe(format!(
+                        "Encode must be the last node (found at {})",
+                        idx
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        if !matches!(self.nodes.last(), Some(Node::Encode { .. })) {
+            return Err(BirlError::InvalidRecipe(
+                "Recipe must end with an encode node".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Check a caller-supplied recipe reads only what a render of an outfit
+    /// could: the plate, stored layers of registered categories and their
+    /// masks, with at most [`MAX_RECIPE_NODES`] nodes and
+    /// [`Limits::max_layers`] layers
+    pub fn check_sources(&self, registry: &CategoryRegistry, limits: &Limits) -> Result<()> {
+        if self.nodes.len() > MAX_RECIPE_NODES {
+            return Err(BirlError::InvalidRecipe(format!(
+                "Too many nodes: {} (at most {})",
+                self.nodes.len(),
+                MAX_RECIPE_NODES
+            )));
+        }
+
+        let mut layers = 0;
+        for asset in self.assets() {
+            let Asset::Layer(param) = asset else {
+                continue;
+            };
+            let allowed = match param.category.as_str() {
+                _ if param.url.is_some() => false,
+                MASKS_CATEGORY => {
+                    let mask = param.sku.as_str();
+                    registry.is_patch_garment(mask)
+                        || registry
+                            .names()
+                            .any(|name| registry.underlay_mask(name) == Some(mask))
+                }
+                category => {
+                    layers += 1;
+                    registry.get(category).is_some()
+                }
+            };
+            if !allowed {
+                return Err(BirlError::InvalidRecipe(format!(
+                    "Source '{}' isn't a plate, layer or garment mask",
+                    asset
+                )));
+            }
+        }
+        limits.check_layers(layers)?;
+        Ok(())
+    }
+
+    /// Assets read by source nodes, without duplicates
+    pub fn assets(&self) -> Vec<&Asset> {
+        let mut assets: Vec<&Asset> = Vec::new();
+        for node in &self.nodes {
+            if let Node::Source { asset, .. } = node {
+                if !assets.contains(&asset) {
+                    assets.push(asset);
+                }
+            }
+        }
+        assets
+    }
+
+    /// Filter layers are resized with when blended, from the first blend node
+    pub fn layer_filter(&self) -> ResizeFilter {
+        self.nodes
+            .iter()
+            .find_map(|node| match node {
+                Node::Blend { resize_filter, .. } => Some(*resize_filter),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Number of source nodes, counting those reading the same asset
+    fn sources(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|node| matches!(node, Node::Source { .. }))
+            .count()
+    }
+
+    /// Encode settings of the final node
+    pub fn encode_options(&self) -> CompositeOptions {
+        match self.nodes.last() {
+            Some(Node::Encode {
+                format,
+                quality,
+                matte,
+                ..
+            }) => CompositeOptions {
+                format: *format,
+                quality: *quality,
+                matte: *matte,
+                ..CompositeOptions::default()
+            },
+            _ => CompositeOptions::default(),
+        }
+    }
+
+    /// Evaluate the graph up to the encode node
+    /// `sources` maps asset strings (see [`Asset`]) to encoded images; sources
+    /// over the current pixel limit are rejected before decoding
+    /// Returns the compositor holding the image to encode
+    pub fn evaluate(
+        &self,
+        sources: &HashMap<String, Bytes>,
+        missing: MissingSources,
+    ) -> Result<Compositor> {
+        self.evaluate_inner(sources, missing, None)
+    }
+
+    /// Like [`Self::evaluate`], decoding sources through a cache shared with other renders
+    pub fn evaluate_with(
+        &self,
+        sources: &HashMap<String, Bytes>,
+        missing: MissingSources,
+        decoded: &DecodeCache,
+    ) -> Result<Compositor> {
+        self.evaluate_inner(sources, missing, Some(decoded))
+    }
+
+    fn evaluate_inner(
+        &self,
+        sources: &HashMap<String, Bytes>,
+        missing: MissingSources,
+        decoded: Option<&DecodeCache>,
+    ) -> Result<Compositor> {
+        self.validate()?;
+        let limits = Limits::global();
+
+        // Sources read by several nodes (e.g. one garment mask under two
+        // patches) decode once, even without a shared cache
+        let local;
+        let decoded = match decoded {
+            None if self.assets().len() < self.sources() => {
+                local = DecodeCache::new();
+                Some(&local)
+            }
+            decoded => decoded,
+        };
+
+        // Count remaining uses so a node's output is moved, not cloned, on its last use
+        let mut uses: HashMap<&str, usize> = HashMap::new();
+        for input in self.nodes.iter().flat_map(Node::inputs) {
+            *uses.entry(input).or_default() += 1;
+        }
+
+        // SVG sources are rasterized to fit the plate (or canvas)
+        let mut plate_size = None;
+        let mut values: HashMap<&str, Option<DynamicImage>> = HashMap::new();
+        let mut take = |values: &mut HashMap<&str, Option<DynamicImage>>, id: &str| {
+            let remaining = uses.get_mut(id).expect("validated input");
+            *remaining -= 1;
+            if *remaining == 0 {
+                values.remove(id).flatten()
+            } else {
+                values.get(id).cloned().flatten()
+            }
+        };
+
+        for node in &self.nodes {
+            let output = match node {
+                Node::Source { id, asset } => match sources.get(&asset.to_string()) {
+                    Some(data) => {
+                        limits.check_image(&asset.to_string(), data)?;
+                        let image = match decoded {
+                            _ if is_svg(data) => rasterize_svg(data, plate_size),
+                            Some(cache) => cache.decode(data),
+                            None => decode_image(data),
+                        }
+                        .map_err(|e| BirlError::decode(asset.to_string(), e))?;
+                        if *asset == Asset::Plate {
+                            plate_size = Some((image.width(), image.height()));
+                        }
+                        Some(image)
+                    }
+                    None if missing == MissingSources::Skip && *asset != Asset::Plate => None,
+                    None => {
+                        return Err(BirlError::MissingSource {
+                            id: id.clone(),
+                            asset: asset.to_string(),
+                        })
+                    }
+                },
+                Node::Canvas {
+                    id,
+                    width,
+                    height,
+                    background,
+                } => {
+                    limits.check_pixels(id, *width, *height)?;
+                    plate_size.get_or_insert((*width, *height));
+                    Some(canvas(*width, *height, *background))
+                }
+                Node::Clear { input, .. } => take(&mut values, input)
+                    .map(|image| canvas(image.width(), image.height(), None)),
+                Node::Orient {
+                    input,
+                    orientation,
+                    keep_size,
+                    ..
+                } => take(&mut values, input).map(|image| match keep_size {
+                    true => orientation.apply_in_frame(image),
+                    false => orientation.apply(image),
+                }),
+                Node::Blend {
+                    id,
+                    base,
+                    layer,
+                    resize_filter,
+                    at,
+                    offset,
+                } => {
+                    let base_image = take(&mut values, base).ok_or_else(|| {
+                        BirlError::InvalidRecipe(format!("Blend '{}' has no base image", id))
+                    })?;
+                    let mut compositor =
+                        Compositor::from_image(base_image).with_layer_filter(*resize_filter);
+                    match (take(&mut values, layer), at) {
+                        (Some(layer), Some(at)) => compositor.overlay_at(layer, at),
+                        (Some(layer), None) => match offset {
+                            Some(offset) => compositor.overlay_offset(layer, offset),
+                            None => compositor.overlay(layer),
+                        },
+                        (None, _) => {}
+                    }
+                    Some(compositor.into_image())
+                }
+                Node::Mask {
+                    input,
+                    mask,
+                    resize_filter,
+                    at,
+                    ..
+                } => {
+                    let input = take(&mut values, input);
+                    match (input, take(&mut values, mask), at) {
+                        (Some(input), Some(mask), Some(at)) => {
+                            Some(apply_mask_at(input, &mask, at, *resize_filter))
+                        }
+                        (Some(input), Some(mask), None) => {
+                            Some(apply_mask(input, &mask, *resize_filter))
+                        }
+                        (input, _, _) => input,
+                    }
+                }
+                Node::Fit {
+                    id,
+                    input,
+                    width,
+                    height,
+                    resize_filter,
+                } => {
+                    limits.check_pixels(id, *width, *height)?;
+                    take(&mut values, input)
+                        .map(|image| fit_into(image, *width, *height, *resize_filter))
+                }
+                Node::Transform {
+                    id,
+                    input,
+                    max_width,
+                    max_height,
+                    resize_filter,
+                    background,
+                    filters,
+                } => {
+                    let image = take(&mut values, input).ok_or_else(|| {
+                        BirlError::InvalidRecipe(format!("Transform '{}' has no input image", id))
+                    })?;
+                    let options = CompositeOptions {
+                        max_width: *max_width,
+                        max_height: *max_height,
+                        resize_filter: *resize_filter,
+                        background: *background,
+                        filters: filters.clone(),
+                        ..CompositeOptions::default()
+                    };
+                    let mut compositor = Compositor::from_image(image);
+                    compositor.apply_options(&options);
+                    Some(compositor.into_image())
+                }
+                Node::Encode { input, .. } => {
+                    let image = take(&mut values, input)
+                        .ok_or_else(|| BirlError::InvalidRecipe("Nothing to encode".to_string()))?;
+                    return Ok(Compositor::from_image(image));
+                }
+            };
+
+            if let Some(id) = node.id() {
+                values.insert(id, output);
+            }
+        }
+
+        unreachable!("validated recipes end with an encode node")
+    }
+
+    /// Evaluate and encode the recipe (see [`crate::render::render`])
+    pub fn execute(&self, sources: &HashMap<String, Bytes>) -> Result<Bytes> {
+        Ok(render(self, &Assets::from(sources.clone()))?.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compose_layers;
+    use crate::demo::{synthetic_layer, synthetic_mask, synthetic_plate};
+    use crate::layers::{parse_params, patch_mask_categories};
+    use crate::models::View;
+    use crate::registry::Category;
+
+    fn sources(recipe: &Recipe) -> HashMap<String, Bytes> {
+        recipe
+            .assets()
+            .into_iter()
+            .map(|asset| {
+                let data = match asset {
+                    Asset::Plate => synthetic_plate(View::Front).unwrap(),
+                    Asset::Layer(p) if p.category == MASKS_CATEGORY => {
+                        synthetic_mask(p.sku.as_str()).unwrap()
+                    }
+                    Asset::Layer(p) => synthetic_layer(&p.category, p.sku.as_str()).unwrap(),
+                };
+                (asset.to_string(), data)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_compiled_recipe_matches_flat_pipeline() {
+        let layers = parse_params("pants/cargo-black,hoodies/hoodie-black");
+        let options = CompositeOptions::new().with_format(OutputFormat::Png);
+        let recipe = Recipe::compile(&layers, &[None, None], &options);
+        let sources = sources(&recipe);
+
+        let flat = compose_layers(
+            &sources["plate"],
+            vec![
+                sources["pants/cargo-black"].clone(),
+                sources["hoodies/hoodie-black"].clone(),
+            ],
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(recipe.execute(&sources).unwrap(), flat);
+    }
+
+    #[test]
+    fn test_shared_decode_cache() {
+        let layers = parse_params("pants/cargo-black,hoodies/hoodie-black");
+        let recipe = Recipe::compile(&layers, &[None, None], &CompositeOptions::default());
+        let sources = sources(&recipe);
+        let decoded = DecodeCache::new();
+
+        let first = recipe
+            .evaluate_with(&sources, MissingSources::Error, &decoded)
+            .unwrap();
+        let second = recipe
+            .evaluate_with(&sources, MissingSources::Error, &decoded)
+            .unwrap();
+
+        assert_eq!(first.into_image(), second.into_image());
+        assert_eq!(decoded.stats(), (3, 3));
+    }
+
+    #[test]
+    fn test_compile_masks_and_transform() {
+        let layers = parse_params("hoodies/hoodie-black,patches-left/flag-patch-red");
+        let masks = vec![None, Some("hoodies".to_string())];
+        let options = CompositeOptions::new().with_filter(Filter::Grayscale);
+        let recipe = Recipe::compile(&layers, &masks, &options);
+
+        assert!(recipe.validate().is_ok());
+        assert!(recipe.nodes.iter().any(|n| matches!(n, Node::Mask { .. })));
+        assert!(recipe
+            .nodes
+            .iter()
+            .any(|n| matches!(n, Node::Transform { .. })));
+        assert!(recipe.assets().contains(&&Asset::mask("hoodies")));
+        assert!(recipe.execute(&sources(&recipe)).is_ok());
+        assert_eq!(recipe.layer_filter(), ResizeFilter::default());
+
+        let options = CompositeOptions {
+            layer_filter: ResizeFilter::Nearest,
+            ..options
+        };
+        let recipe = Recipe::compile(&layers, &masks, &options);
+        assert_eq!(recipe.layer_filter(), ResizeFilter::Nearest);
+    }
+
+    #[test]
+    fn test_compile_underlays() {
+        let mut registry = CategoryRegistry::builtin();
+        registry.insert("straps", Category::new(5).as_underlay("torso"));
+        let layers = parse_params("pants/cargo-black,straps/strap-black");
+        let recipe = Recipe::compile_with(
+            &registry,
+            &layers,
+            &[None, None],
+            &[],
+            &[],
+            &[],
+            &CompositeOptions::default(),
+        );
+        assert!(recipe.validate().is_ok());
+        assert!(recipe.assets().contains(&&Asset::mask("torso")));
+
+        // The strap goes on the plate before the plate is restored, pants after
+        let position = |id: &str| recipe.nodes.iter().position(|n| n.id() == Some(id));
+        assert!(position("blend-1") < position("restore-torso"));
+        assert!(position("restore-torso") < position("blend-0"));
+
+        // Red plate, blue strap, torso mask covering the left half
+        let png = |paint: fn(u32) -> [u8; 4]| {
+            let image = image::RgbaImage::from_fn(4, 4, |x, _| image::Rgba(paint(x)));
+            let mut buffer = std::io::Cursor::new(Vec::new());
+            DynamicImage::ImageRgba8(image)
+                .write_to(&mut buffer, image::ImageFormat::Png)
+                .unwrap();
+            Bytes::from(buffer.into_inner())
+        };
+        let sources = HashMap::from([
+            ("plate".to_string(), png(|_| [255, 0, 0, 255])),
+            ("straps/strap-black".to_string(), png(|_| [0, 0, 255, 255])),
+            ("masks/torso".to_string(), png(|x| [255 * (x < 2) as u8; 4])),
+        ]);
+
+        let image = recipe
+            .evaluate(&sources, MissingSources::Skip)
+            .unwrap()
+            .into_image()
+            .to_rgba8();
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(3, 0).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_compile_cutout() {
+        let mut registry = CategoryRegistry::builtin();
+        registry.insert("straps", Category::new(5).as_underlay("torso"));
+        let layers = parse_params("pants/cargo-black,straps/strap-black");
+        let options = CompositeOptions::new().with_cutout();
+        assert_eq!(options.format, OutputFormat::Png);
+        let recipe =
+            Recipe::compile_with(&registry, &layers, &[None, None], &[], &[], &[], &options);
+        assert!(recipe.validate().is_ok());
+        // Nothing of the plate is restored over the underlay
+        assert!(!recipe.assets().contains(&&Asset::mask("torso")));
+
+        // Red plate, blue pants on the left half, no strap
+        let png = |paint: fn(u32) -> [u8; 4]| {
+            let image = image::RgbaImage::from_fn(4, 4, |x, _| image::Rgba(paint(x)));
+            let mut buffer = std::io::Cursor::new(Vec::new());
+            DynamicImage::ImageRgba8(image)
+                .write_to(&mut buffer, image::ImageFormat::Png)
+                .unwrap();
+            Bytes::from(buffer.into_inner())
+        };
+        let sources = HashMap::from([
+            ("plate".to_string(), png(|_| [255, 0, 0, 255])),
+            (
+                "pants/cargo-black".to_string(),
+                png(|x| [0, 0, 255, 255 * (x < 2) as u8]),
+            ),
+        ]);
+
+        let image = recipe
+            .evaluate(&sources, MissingSources::Skip)
+            .unwrap()
+            .into_image()
+            .to_rgba8();
+        assert_eq!(image.dimensions(), (4, 4));
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(3, 0).0[3], 0);
+        assert_ne!(
+            options.cache_variant(),
+            CompositeOptions::new()
+                .with_format(OutputFormat::Png)
+                .cache_variant()
+        );
+    }
+
+    #[test]
+    fn test_compile_placements() {
+        let layers = parse_params("jackets/softshell-grey,patches-left/flag-patch-red");
+        let masks = vec![None, Some("jackets".to_string())];
+        let placements = vec![None, Some(Placement::new(3, 2).with_width(4))];
+        let offsets = vec![Some(Offset::new(1, 0)), None];
+        let options = CompositeOptions::default();
+        let recipe = Recipe::compile_placed(&layers, &masks, &placements, &offsets, &[], &options);
+        assert!(recipe.validate().is_ok());
+        assert!(recipe
+            .nodes
+            .iter()
+            .any(|n| matches!(n, Node::Blend { at: Some(_), .. })));
+        let shifted = Node::Blend {
+            id: "blend-0".to_string(),
+            base: "plate".to_string(),
+            layer: "layer-0".to_string(),
+            resize_filter: ResizeFilter::default(),
+            at: None,
+            offset: Some(Offset::new(1, 0)),
+        };
+        assert!(recipe.nodes.contains(&shifted));
+        let json = serde_json::to_string(&recipe).unwrap();
+        assert_eq!(serde_json::from_str::<Recipe>(&json).unwrap(), recipe);
+
+        // Red 8x8 plate, blue 2x2 patch, jacket mask covering the left half
+        let png = |size: u32, paint: fn(u32) -> [u8; 4]| {
+            let image = image::RgbaImage::from_fn(size, size, |x, _| image::Rgba(paint(x)));
+            let mut buffer = std::io::Cursor::new(Vec::new());
+            DynamicImage::ImageRgba8(image)
+                .write_to(&mut buffer, image::ImageFormat::Png)
+                .unwrap();
+            Bytes::from(buffer.into_inner())
+        };
+        let sources = HashMap::from([
+            ("plate".to_string(), png(8, |_| [255, 0, 0, 255])),
+            (
+                "patches-left/flag-patch-red".to_string(),
+                png(2, |_| [0, 0, 255, 255]),
+            ),
+            (
+                "masks/jackets".to_string(),
+                png(8, |x| [255 * (x < 4) as u8; 4]),
+            ),
+        ]);
+
+        let image = recipe
+            .evaluate(&sources, MissingSources::Skip)
+            .unwrap()
+            .into_image()
+            .to_rgba8();
+        assert_eq!(image.dimensions(), (8, 8));
+        assert_eq!(image.get_pixel(3, 2).0, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(3, 5).0, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(3, 6).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(2, 2).0, [255, 0, 0, 255]);
+        // Masked off the garment
+        assert_eq!(image.get_pixel(5, 3).0, [255, 0, 0, 255]);
+
+        let reparsed: Recipe =
+            serde_json::from_str(&serde_json::to_string(&recipe).unwrap()).unwrap();
+        assert_eq!(reparsed, recipe);
+    }
+
+    #[test]
+    fn test_compile_orientations() {
+        let layers = parse_params("hoodies/hoodie-black,patches-left/flag-patch-red");
+        let placed = Placement::new(0, 0).with_rotation(Rotation::Quarter);
+        let placements = vec![None, Some(placed)];
+        let orientations = vec![
+            Some(Orientation::new(Rotation::Half, None)),
+            Some(placed.orientation()),
+        ];
+        let recipe = Recipe::compile_placed(
+            &layers,
+            &[None, None],
+            &placements,
+            &[],
+            &orientations,
+            &CompositeOptions::default(),
+        );
+        assert!(recipe.validate().is_ok());
+        // Only the orient node turns the patch
+        assert!(recipe.nodes.iter().any(|n| matches!(
+            n,
+            Node::Blend { at: Some(at), .. } if at.orientation().is_identity()
+        )));
+        let reparsed: Recipe =
+            serde_json::from_str(&serde_json::to_string(&recipe).unwrap()).unwrap();
+        assert_eq!(reparsed, recipe);
+
+        // Red 4x2 plate, a hoodie covering its left half, a 2x1 green patch
+        let png = |width: u32, height: u32, paint: fn(u32) -> [u8; 4]| {
+            let image = image::RgbaImage::from_fn(width, height, |x, _| image::Rgba(paint(x)));
+            let mut buffer = std::io::Cursor::new(Vec::new());
+            DynamicImage::ImageRgba8(image)
+                .write_to(&mut buffer, image::ImageFormat::Png)
+                .unwrap();
+            Bytes::from(buffer.into_inner())
+        };
+        let sources = HashMap::from([
+            ("plate".to_string(), png(4, 2, |_| [255, 0, 0, 255])),
+            (
+                "hoodies/hoodie-black".to_string(),
+                png(4, 2, |x| [0, 0, 255, 255 * (x < 2) as u8]),
+            ),
+            (
+                "patches-left/flag-patch-red".to_string(),
+                png(2, 1, |_| [0, 255, 0, 255]),
+            ),
+        ]);
+
+        let image = recipe
+            .evaluate(&sources, MissingSources::Skip)
+            .unwrap()
+            .into_image()
+            .to_rgba8();
+        assert_eq!(image.dimensions(), (4, 2));
+        // The turned hoodie covers the right half, the turned patch is 1x2
+        assert_eq!(image.get_pixel(3, 0).0, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(0, 1).0, [0, 255, 0, 255]);
+        assert_eq!(image.get_pixel(1, 0).0, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let json = r#"{ "nodes": [
+            { "op": "source", "id": "plate", "asset": "plate" },
+            { "op": "source", "id": "hoodie", "asset": "hoodies/hoodie-black" },
+            { "op": "blend", "id": "out", "base": "plate", "layer": "hoodie" },
+            { "op": "transform", "id": "small", "input": "out", "max_width": 200 },
+            { "op": "encode", "input": "small", "format": "webp" }
+        ] }"#;
+
+        let recipe: Recipe = serde_json::from_str(json).unwrap();
+        assert!(recipe.validate().is_ok());
+        assert_eq!(recipe.encode_options().format, OutputFormat::WebP);
+
+        let reparsed: Recipe =
+            serde_json::from_str(&serde_json::to_string(&recipe).unwrap()).unwrap();
+        assert_eq!(reparsed, recipe);
+
+        let encoded = recipe.execute(&sources(&recipe)).unwrap();
+        assert_eq!(image::load_from_memory(&encoded).unwrap().width(), 200);
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_graphs() {
+        let parse = |json: &str| serde_json::from_str::<Recipe>(json).unwrap();
+
+        // Forward reference
+        assert!(parse(
+            r#"{ "nodes": [
+            { "op": "blend", "id": "out", "base": "plate", "layer": "plate" },
+            { "op": "source", "id": "plate", "asset": "plate" },
+            { "op": "encode", "input": "out" }
+        ] }"#
+        )
+        .validate()
+        .is_err());
+
+        // Missing encode
+        assert!(
+            parse(r#"{ "nodes": [ { "op": "source", "id": "plate", "asset": "plate" } ] }"#)
+                .validate()
+                .is_err()
+        );
+
+        // Duplicate id
+        assert!(parse(
+            r#"{ "nodes": [
+            { "op": "source", "id": "a", "asset": "plate" },
+            { "op": "source", "id": "a", "asset": "plate" },
+            { "op": "encode", "input": "a" }
+        ] }"#
+        )
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_missing_sources() {
+        let layers = parse_params("hoodies/hoodie-black");
+        let recipe = Recipe::compile(&layers, &[None], &CompositeOptions::default());
+
+        let mut sources = sources(&recipe);
+        sources.remove("hoodies/hoodie-black");
+
+        assert!(recipe.execute(&sources).is_err());
+        assert!(recipe.evaluate(&sources, MissingSources::Skip).is_ok());
+
+        sources.remove("plate");
+        assert!(recipe.evaluate(&sources, MissingSources::Skip).is_err());
+    }
+
+    #[test]
+    fn test_check_sources() {
+        let registry = CategoryRegistry::builtin();
+        let limits = Limits::default();
+        let layers = parse_params("hoodies/hoodie-black,patches-left/flag-patch-red");
+        let recipe = Recipe::compile_with(
+            &registry,
+            &layers,
+            &patch_mask_categories(&layers),
+            &[],
+            &[],
+            &[],
+            &CompositeOptions::default(),
+        );
+        assert!(recipe.check_sources(&registry, &limits).is_ok());
+
+        let reading = |asset: &str| -> Recipe {
+            serde_json::from_value(serde_json::json!({ "nodes": [
+                { "op": "source", "id": "a", "asset": asset },
+                { "op": "encode", "input": "a" }
+            ] }))
+            .unwrap()
+        };
+        for asset in [
+            "masks/secrets",
+            "hoodies/https://example.com/a.png",
+            "unknown/sku",
+        ] {
+            assert!(reading(asset).check_sources(&registry, &limits).is_err());
+        }
+
+        let limits = limits.with_max_layers(1);
+        assert!(matches!(
+            recipe.check_sources(&registry, &limits),
+            Err(BirlError::LimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_fit_checks_pixels() {
+        let recipe: Recipe = serde_json::from_str(
+            r#"{ "nodes": [
+            { "op": "source", "id": "plate", "asset": "plate" },
+            { "op": "fit", "id": "huge", "input": "plate", "width": 100000, "height": 100000 },
+            { "op": "encode", "input": "huge" }
+        ] }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            recipe.execute(&sources(&recipe)),
+            Err(BirlError::LimitExceeded(_))
+        ));
+    }
+}
@@ -1,146 +1,523 @@
-use crate::models::{LayerParam, Sku, View};
+use crate::error::{BirlError, Result};
+use crate::limits::Limits;
+use crate::models::{LayerParam, LayerParamError, View};
+use crate::registry::CategoryRegistry;
+use crate::rules::{
+    check_constraints, Constraint, ConstraintAction, ConstraintError, ConstraintViolation,
+};
+use crate::tables::Tables;
+use crate::wildcards::outfit_seed;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Category of a `plate/<sku>` param, which selects the plate instead of adding a layer
+pub const PLATE_CATEGORY: &str = "plate";
+
+/// What to do with an outfit that has several layers in one category
+/// (after normalization, e.g. two hoodies)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    /// Composite all of them, in request order
+    #[default]
+    Stack,
+    /// Reject the outfit
+    Error,
+    /// Keep the first one requested
+    FirstWins,
+    /// Keep the last one requested
+    LastWins,
+}
+
+impl ConflictPolicy {
+    /// The current tables' policy (stack unless another was installed)
+    pub fn global() -> ConflictPolicy {
+        Tables::current().conflicts
+    }
+}
+
+/// Several layers requested in one category, and which of them were kept
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LayerConflict {
+    pub category: String,
+    pub kept: Vec<LayerParam>,
+    pub dropped: Vec<LayerParam>,
+}
+
+impl fmt::Display for LayerConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |params: &[LayerParam]| {
+            params
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        write!(f, "Several {} layers", self.category)?;
+        if !self.dropped.is_empty() {
+            write!(
+                f,
+                ": kept {}, dropped {}",
+                join(&self.kept),
+                join(&self.dropped)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Outfit rejected by [`ConflictPolicy::Error`]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Several layers in one category: {}", self.categories().join(", "))]
+pub struct LayerConflictError {
+    pub conflicts: Vec<LayerConflict>,
+}
+
+impl LayerConflictError {
+    pub fn categories(&self) -> Vec<&str> {
+        self.conflicts
+            .iter()
+            .map(|conflict| conflict.category.as_str())
+            .collect()
+    }
+}
+
+/// Why normalization left a requested item out of the outfit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum DropReason {
+    /// The view doesn't render the category (e.g. pants on left view)
+    NotInView { view: View },
+    /// Another layer of the category won under the conflict policy
+    Conflict { policy: ConflictPolicy },
+    /// A constraint with the drop action (its "category/sku" pattern)
+    Constraint { pattern: String },
+    /// The flat lay has no slot for the category
+    NoSlot,
+}
+
+impl DropReason {
+    /// Short name of the reason, as serialized
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NotInView { .. } => "not-in-view",
+            Self::Conflict { .. } => "conflict",
+            Self::Constraint { .. } => "constraint",
+            Self::NoSlot => "no-slot",
+        }
+    }
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotInView { view } => write!(f, "not rendered in {} view", view),
+            Self::Conflict { policy } => match policy {
+                ConflictPolicy::LastWins => write!(f, "a later layer of the category was kept"),
+                _ => write!(f, "an earlier layer of the category was kept"),
+            },
+            Self::Constraint { pattern } => write!(f, "dropped by constraint on {}", pattern),
+            Self::NoSlot => write!(f, "no flat lay slot for its category"),
+        }
+    }
+}
+
+/// A requested item that isn't in the normalized outfit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DroppedLayer {
+    /// The item as requested for view drops, as resolved otherwise
+    pub layer: LayerParam,
+    #[serde(flatten)]
+    pub reason: DropReason,
+}
+
+impl fmt::Display for DroppedLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Dropped {}: {}", self.layer, self.reason)
+    }
+}
+
+/// Layers of an outfit after normalization, in layer order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Normalized {
+    pub layers: Vec<LayerParam>,
+    /// Categories with more than one layer, and how each was resolved
+    pub conflicts: Vec<LayerConflict>,
+    /// Constraints the outfit breaks; dropped layers are not in `layers`
+    pub violations: Vec<ConstraintViolation>,
+    /// Requested items left out of `layers`, and why
+    pub dropped: Vec<DroppedLayer>,
+}
+
+/// Params that would be silently dropped when rendering a view
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Categories not rendered for {view} view: {}", .categories.join(", "))]
+pub struct ViewValidationError {
+    pub view: View,
+    pub categories: Vec<String>,
+}
 
 /// Normalize and filter layer parameters based on view and context
 pub struct LayerNormalizer {
     view: View,
-    has_softshell_jacket: bool,
+    /// Picks the SKUs of the outfit's `category/*` params
+    seed: u64,
+    outfit: Vec<LayerParam>,
+    /// Categories, aliases, wildcards and rules, as of [`Self::new`]
+    tables: Arc<Tables>,
+    conflicts: ConflictPolicy,
+    constraints: Option<Vec<Constraint>>,
+    limits: Limits,
 }
 
 impl LayerNormalizer {
     pub fn new(view: View, params: &[LayerParam]) -> Self {
-        // First pass to detect softshell jacket
-        let has_softshell_jacket = params.iter().any(|param| {
-            param.category == "jackets" && param.sku.as_str().contains("softshell")
-        });
-
+        // Rules can depend on the rest of the outfit (e.g. patches on a softshell jacket)
+        let tables = Tables::current();
+        let seed = outfit_seed(params, view);
         Self {
             view,
-            has_softshell_jacket,
+            seed,
+            outfit: params
+                .iter()
+                .map(|param| {
+                    let param = tables.aliases.apply(param);
+                    tables.wildcards.apply(&param, view, seed)
+                })
+                .collect(),
+            conflicts: tables.conflicts,
+            constraints: None,
+            limits: tables.limits,
+            tables,
         }
     }
 
+    /// Resolve conflicts with a policy other than the tables' one
+    pub fn with_conflict_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.conflicts = policy;
+        self
+    }
+
+    /// Check these constraints instead of the tables' ones
+    pub fn with_constraints(mut self, constraints: Vec<Constraint>) -> Self {
+        self.constraints = Some(constraints);
+        self
+    }
+
+    /// Enforce these limits instead of the tables' ones
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
     /// Normalize a single layer parameter
+    /// Plate params are never layers (see [`requested_plate`])
     pub fn normalize(&self, param: &LayerParam) -> Option<LayerParam> {
-        let category = &param.category;
-        let sku = param.sku.as_str();
-
-        // Skip categories that aren't relevant for specific views
-        if matches!(self.view, View::Left | View::Right) {
-            if !["hoodies", "jackets", "patches-left", "patches-right"].contains(&category.as_str())
-            {
-                return None;
-            }
+        if is_plate(param) {
+            return None;
         }
 
-        // Handle patches based on position, jacket type, and view
-        if category.starts_with("patches-") {
-            return self.normalize_patch(category, sku);
+        // Skip categories that aren't rendered in this view
+        if !self.tables.categories.allows(&param.category, self.view) {
+            return None;
         }
 
-        // Handle gloves special case
-        if category == "gloves" {
-            return self.normalize_gloves(sku);
+        // Resolve aliases and wildcards, then remap the category with the
+        // first matching rule
+        let param = self.tables.aliases.apply(param);
+        let param = self.tables.wildcards.apply(&param, self.view, self.seed);
+        match self.tables.rules.apply(&param, self.view, &self.outfit) {
+            Some(category) => Some(param.with_category(category)),
+            None => Some(param),
         }
+    }
+
+    /// Normalize and sort all parameters by layer order
+    /// Conflicts are resolved by the policy and constraints applied, except that
+    /// [`ConflictPolicy::Error`] and [`ConstraintAction::Error`] keep every
+    /// layer; use [`Self::resolve`] to reject them
+    pub fn normalize_all(&self, params: &[LayerParam]) -> Vec<LayerParam> {
+        self.resolve_lenient(params).layers
+    }
 
-        // Handle jackets special case
-        if category == "jackets" {
-            return self.normalize_jacket(sku);
+    /// Constraints the outfit breaks, as [`Self::normalize_all`] would apply them
+    pub fn violations(&self, params: &[LayerParam]) -> Vec<ConstraintViolation> {
+        self.resolve_lenient(params).violations
+    }
+
+    /// Items [`Self::normalize_all`] leaves out, and why
+    pub fn dropped(&self, params: &[LayerParam]) -> Vec<DroppedLayer> {
+        self.resolve_lenient(params).dropped
+    }
+
+    /// Normalize and sort all parameters, reporting how conflicts were resolved
+    /// and which constraints the outfit breaks
+    /// Outfits with more layers than the limits allow are rejected
+    pub fn resolve(&self, params: &[LayerParam]) -> Result<Normalized> {
+        let normalized = self.resolve_with(params, self.conflicts);
+        if self.conflicts == ConflictPolicy::Error && !normalized.conflicts.is_empty() {
+            return Err(LayerConflictError {
+                conflicts: normalized.conflicts,
+            }
+            .into());
         }
+        if normalized
+            .violations
+            .iter()
+            .any(|violation| violation.action == ConstraintAction::Error)
+        {
+            return Err(ConstraintError {
+                violations: normalized.violations,
+            }
+            .into());
+        }
+        self.limits.check_layers(normalized.layers.len())?;
+        Ok(normalized)
+    }
 
-        Some(param.clone())
+    fn resolve_lenient(&self, params: &[LayerParam]) -> Normalized {
+        match self.conflicts {
+            ConflictPolicy::Error => self.resolve_with(params, ConflictPolicy::Stack),
+            policy => self.resolve_with(params, policy),
+        }
     }
 
-    /// Normalize patch parameters
-    fn normalize_patch(&self, category: &str, sku: &str) -> Option<LayerParam> {
-        // Extract position from "patches-left" or "patches-right"
-        let position = category.strip_prefix("patches-")?;
+    fn resolve_with(&self, params: &[LayerParam], policy: ConflictPolicy) -> Normalized {
+        let mut dropped = Vec::new();
+        let mut layers = Vec::new();
+        for param in params.iter().filter(|param| !is_plate(param)) {
+            match self.normalize(param) {
+                Some(layer) => layers.push(layer),
+                None => dropped.push(DroppedLayer {
+                    layer: param.clone(),
+                    reason: DropReason::NotInView { view: self.view },
+                }),
+            }
+        }
 
-        // Skip patches for back view
-        if self.view == View::Back {
-            return None;
+        let mut conflicts = Vec::new();
+        let mut categories: Vec<&str> = Vec::new();
+        for param in &layers {
+            let category = param.category.as_str();
+            if categories.contains(&category)
+                || layers.iter().filter(|p| p.category == category).count() < 2
+            {
+                continue;
+            }
+            categories.push(category);
+
+            let mut kept: Vec<LayerParam> = layers
+                .iter()
+                .filter(|p| p.category == category)
+                .cloned()
+                .collect();
+            let dropped = match policy {
+                ConflictPolicy::Stack | ConflictPolicy::Error => Vec::new(),
+                ConflictPolicy::FirstWins => kept.split_off(1),
+                ConflictPolicy::LastWins => kept.drain(..kept.len() - 1).collect(),
+            };
+            conflicts.push(LayerConflict {
+                category: category.to_string(),
+                kept,
+                dropped,
+            });
         }
 
-        // For left/right views, only show the patch on the matching side
-        if (self.view == View::Left && position != "left")
-            || (self.view == View::Right && position != "right")
-        {
-            return None;
+        for conflict in &conflicts {
+            dropped.extend(conflict.dropped.iter().map(|param| DroppedLayer {
+                layer: param.clone(),
+                reason: DropReason::Conflict { policy },
+            }));
+            let mut remaining = conflict.dropped.clone();
+            layers.retain(|param| match remaining.iter().position(|p| p == param) {
+                Some(idx) => {
+                    remaining.remove(idx);
+                    false
+                }
+                None => true,
+            });
         }
 
-        // Determine base category based on jacket type
-        let base_category = if self.has_softshell_jacket {
-            "softshell-patches"
-        } else {
-            "patches"
-        };
+        // Constraints see the layers that survived the conflict policy
+        let violations = check_constraints(
+            self.constraints
+                .as_deref()
+                .unwrap_or(self.tables.rules.constraints()),
+            &layers,
+            self.view,
+        );
+        layers.retain(|param| {
+            let violation = violations.iter().find(|violation| {
+                violation.action == ConstraintAction::Drop && violation.layer == *param
+            });
+            if let Some(violation) = violation {
+                dropped.push(DroppedLayer {
+                    layer: param.clone(),
+                    reason: DropReason::Constraint {
+                        pattern: violation.pattern.clone(),
+                    },
+                });
+            }
+            violation.is_none()
+        });
 
-        // For front view, use position suffix
-        let new_category = if self.view == View::Front {
-            format!("{}-{}", base_category, position)
-        } else {
-            // For side views, use the standard patch folder
-            base_category.to_string()
-        };
+        // Sort by z-order, keeping request order within a category
+        layers.sort_by_key(|param| param.z_order());
 
-        Some(LayerParam::new(new_category, sku))
+        Normalized {
+            layers,
+            conflicts,
+            violations,
+            dropped,
+        }
     }
+}
 
-    /// Normalize gloves parameters
-    fn normalize_gloves(&self, sku: &str) -> Option<LayerParam> {
-        // Ski gloves go on top, others go on bottom
-        // Careful: "regular" is NOT a ski glove
-        let is_ski_glove = sku.starts_with("ski");
-        let category = if is_ski_glove {
-            "gloves-top"
-        } else {
-            "gloves-bottom"
-        };
+/// Check that every param will be rendered for the view
+/// Returns the categories normalization would drop (e.g. pants on left view)
+pub fn validate_for_view(params: &[LayerParam], view: View) -> Result<(), ViewValidationError> {
+    let normalizer = LayerNormalizer::new(view, params);
 
-        Some(LayerParam::new(category, sku))
+    let mut categories: Vec<String> = Vec::new();
+    for param in params.iter().filter(|param| !is_plate(param)) {
+        if normalizer.normalize(param).is_none() && !categories.contains(&param.category) {
+            categories.push(param.category.clone());
+        }
     }
 
-    /// Normalize jacket parameters
-    fn normalize_jacket(&self, sku: &str) -> Option<LayerParam> {
-        // Greenland jackets are outer jackets
-        let is_outer_jacket = sku.contains("greenland");
-        let category = if is_outer_jacket {
-            "outer-jackets"
-        } else {
-            "jackets"
-        };
-
-        Some(LayerParam::new(category, sku))
+    if categories.is_empty() {
+        Ok(())
+    } else {
+        Err(ViewValidationError { view, categories })
     }
+}
 
-    /// Normalize and sort all parameters by layer order
-    pub fn normalize_all(&self, params: &[LayerParam]) -> Vec<LayerParam> {
-        let mut normalized: Vec<LayerParam> = params
-            .iter()
-            .filter_map(|param| self.normalize(param))
-            .collect();
+/// Plate selected by a `plate/<sku>` param (e.g. `plate/swatthermals-grey`), if any
+/// The last one wins; remote URLs can't be plates and are ignored
+pub fn requested_plate(params: &[LayerParam]) -> Option<&str> {
+    params
+        .iter()
+        .rev()
+        .find(|param| is_plate(param) && !param.is_remote())
+        .map(|param| param.sku.as_str())
+}
+
+fn is_plate(param: &LayerParam) -> bool {
+    param.category == PLATE_CATEGORY
+}
 
-        // Sort by layer order
-        normalized.sort_by_key(|param| param.layer_order());
+/// Garment each normalized layer sits on, aligned with `normalized`
+/// Patches go on the topmost torso garment beneath them; other layers get `None`
+pub fn patch_garments(normalized: &[LayerParam]) -> Vec<Option<&LayerParam>> {
+    let registry = CategoryRegistry::global();
 
-        normalized
-    }
+    normalized
+        .iter()
+        .enumerate()
+        .map(|(idx, param)| {
+            if !registry.is_patch(&param.category) {
+                return None;
+            }
+
+            normalized[..idx]
+                .iter()
+                .rev()
+                .find(|below| registry.is_patch_garment(&below.category))
+        })
+        .collect()
+}
+
+/// Mask category for each normalized layer, aligned with `normalized`
+/// Patches are clipped to their garment (see [`patch_garments`]); other layers get `None`
+pub fn patch_mask_categories(normalized: &[LayerParam]) -> Vec<Option<String>> {
+    patch_garments(normalized)
+        .into_iter()
+        .map(|garment| garment.map(|garment| garment.category.clone()))
+        .collect()
 }
 
 /// Parse comma-separated parameter string into LayerParams
+/// Values may be SKUs or absolute URLs for remote layers
 pub fn parse_params(params_str: &str) -> Vec<LayerParam> {
     params_str
         .split(',')
-        .filter_map(|param| {
-            let parts: Vec<&str> = param.split('/').map(|s| s.trim()).collect();
-            if parts.len() == 2 {
-                Some(LayerParam::new(parts[0], Sku::new(parts[1])))
-            } else {
-                None
-            }
-        })
+        .filter_map(LayerParam::parse)
         .collect()
 }
 
+/// Like [`parse_params`], failing on the first param that doesn't parse (see
+/// [`LayerParam::try_parse`]) instead of skipping it
+/// Empty items, e.g. from a trailing comma, are still skipped
+pub fn parse_params_strict(params_str: &str) -> Result<Vec<LayerParam>, LayerParamError> {
+    params_str
+        .split(',')
+        .filter(|param| !param.trim().is_empty())
+        .map(LayerParam::try_parse)
+        .collect()
+}
+
+/// Parse a JSON array of `{"category", "sku"}` objects into LayerParams
+/// Unlike [`parse_params`], an invalid entry fails the whole list
+pub fn parse_params_json(json: &str) -> Result<Vec<LayerParam>> {
+    serde_json::from_str(json)
+        .map_err(|e| BirlError::InvalidParam(format!("Invalid params: {}", e)))
+}
+
+/// Layer params as sent by clients: a "category/sku,..." string or a list of
+/// `{"category", "sku"}` objects
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Params {
+    Text(String),
+    List(Vec<LayerParam>),
+}
+
+impl Params {
+    pub fn to_params(&self) -> Vec<LayerParam> {
+        match self {
+            Params::Text(params) => parse_params(params),
+            Params::List(params) => params.clone(),
+        }
+    }
+
+    /// Like [`Self::to_params`], rejecting params that don't parse or name an
+    /// unknown category instead of skipping them
+    pub fn try_to_params(&self) -> Result<Vec<LayerParam>, LayerParamError> {
+        match self {
+            Params::Text(params) => parse_params_strict(params),
+            Params::List(params) => {
+                for param in params {
+                    param.validate()?;
+                }
+                Ok(params.clone())
+            }
+        }
+    }
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params::Text(String::new())
+    }
+}
+
+/// Format as a "category/sku,..." string
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Params::Text(params) => write!(f, "{}", params),
+            Params::List(params) => {
+                let params: Vec<String> = params.iter().map(ToString::to_string).collect();
+                write!(f, "{}", params.join(","))
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -155,6 +532,55 @@ mod tests {
         assert_eq!(params[1].sku.as_str(), "cargo-darkgreen");
     }
 
+    #[test]
+    fn test_parse_params_strict() {
+        let params = parse_params_strict("hoodies/hoodie-black-xl, gloves/ski-black,").unwrap();
+        assert_eq!(
+            params,
+            parse_params("hoodies/hoodie-black-xl,gloves/ski-black")
+        );
+        assert!(matches!(
+            parse_params_strict("hoodies/hoodie-black;pants/cargo-black"),
+            Err(LayerParamError::ExtraSeparator(_))
+        ));
+
+        let list = Params::List(vec![LayerParam::new("capes", "cape-red")]);
+        assert!(list.to_params().len() == 1);
+        assert!(matches!(
+            list.try_to_params(),
+            Err(LayerParamError::UnknownCategory { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_params_json() {
+        let params = parse_params_json(
+            r#"[
+                {"category": "hoodies", "sku": "hoodie-black-xl"},
+                {"category": "patches-left", "sku": "https://cdn.partner.com/flag.png"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(params[0], LayerParam::new("hoodies", "hoodie-black"));
+        assert_eq!(
+            params[1],
+            LayerParam::remote("patches-left", "https://cdn.partner.com/flag.png")
+        );
+
+        // Serialized params read back unchanged, remote layers included
+        let json = serde_json::to_string(&params).unwrap();
+        assert_eq!(parse_params_json(&json).unwrap(), params);
+
+        assert!(parse_params_json(r#"[{"category": "hoodies", "sku": "a/b"}]"#).is_err());
+        assert!(parse_params_json(r#"[{"category": "hoodies"}]"#).is_err());
+
+        let text: Params = serde_json::from_str(r#""hoodies/hoodie-black""#).unwrap();
+        let list: Params =
+            serde_json::from_str(r#"[{"category": "hoodies", "sku": "hoodie-black"}]"#).unwrap();
+        assert_eq!(text.to_params(), list.to_params());
+        assert_eq!(list.to_string(), "hoodies/hoodie-black");
+    }
+
     #[test]
     fn test_normalize_gloves() {
         let params = vec![LayerParam::new("gloves", "ski-black")];
@@ -236,4 +662,241 @@ mod tests {
         assert_eq!(normalized[1].category, "hoodies");
         assert_eq!(normalized[2].category, "hats");
     }
+
+    #[test]
+    fn test_z_override() {
+        // A promo patch under the jacket, clipped to the hoodie beneath it
+        let params = parse_params(
+            "jackets/puffer-black,patches-left/flag-patch-red@z=40,hoodies/hoodie-black",
+        );
+        let normalized = LayerNormalizer::new(View::Front, &params).normalize_all(&params);
+        let categories: Vec<&str> = normalized.iter().map(|p| p.category.as_str()).collect();
+        assert_eq!(categories, ["hoodies", "patches-left", "jackets"]);
+        assert_eq!(normalized[1].z, Some(40));
+        assert_eq!(
+            patch_mask_categories(&normalized),
+            vec![None, Some("hoodies".to_string()), None]
+        );
+    }
+
+    #[test]
+    fn test_validate_for_view() {
+        let params = parse_params("pants/cargo-black,hoodies/hoodie-black,hats/beanie-black");
+
+        assert!(validate_for_view(&params, View::Front).is_ok());
+
+        let err = validate_for_view(&params, View::Left).unwrap_err();
+        assert_eq!(err.view, View::Left);
+        assert_eq!(err.categories, vec!["pants", "hats"]);
+        assert_eq!(
+            err.to_string(),
+            "Categories not rendered for left view: pants, hats"
+        );
+    }
+
+    #[test]
+    fn test_validate_for_view_back_patches() {
+        let params = parse_params("hoodies/hoodie-black,patches-left/flag-patch-red");
+        let err = validate_for_view(&params, View::Back).unwrap_err();
+        assert_eq!(err.categories, vec!["patches-left"]);
+    }
+
+    #[test]
+    fn test_patch_mask_categories() {
+        let params = parse_params(
+            "pants/cargo-black,hoodies/hoodie-black,jackets/softshell-grey,patches-left/flag-patch-red",
+        );
+        let normalized = LayerNormalizer::new(View::Front, &params).normalize_all(&params);
+        let masks = patch_mask_categories(&normalized);
+
+        assert_eq!(masks, vec![None, None, None, Some("jackets".to_string())]);
+
+        // No garment beneath the patch: nothing to clip to
+        let params = parse_params("patches-left/flag-patch-red");
+        let normalized = LayerNormalizer::new(View::Front, &params).normalize_all(&params);
+        assert_eq!(patch_mask_categories(&normalized), vec![None]);
+    }
+
+    #[test]
+    fn test_normalize_keeps_remote_url() {
+        let params = parse_params("patches-left/https://cdn.partner.com/flag.png");
+        let normalizer = LayerNormalizer::new(View::Front, &params);
+        let normalized = normalizer.normalize(&params[0]).unwrap();
+
+        assert_eq!(normalized.category, "patches-left");
+        assert_eq!(normalized.url, params[0].url);
+    }
+
+    #[test]
+    fn test_requested_plate() {
+        let params = parse_params("plate/swatthermals-grey,hoodies/hoodie-black");
+        assert_eq!(requested_plate(&params), Some("swatthermals-grey"));
+        assert_eq!(requested_plate(&params[1..]), None);
+
+        // The plate is never a layer, and strict validation accepts it in any view
+        let normalized = LayerNormalizer::new(View::Left, &params).normalize_all(&params);
+        assert_eq!(normalized.len(), 1);
+        assert!(validate_for_view(&params, View::Left).is_ok());
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let params = parse_params("hoodies/hoodie-black,pants/cargo-black,hoodies/hoodie-grey");
+        let normalizer =
+            |policy| LayerNormalizer::new(View::Front, &params).with_conflict_policy(policy);
+
+        let stacked = normalizer(ConflictPolicy::Stack).resolve(&params).unwrap();
+        assert_eq!(stacked.layers.len(), 3);
+        assert_eq!(stacked.conflicts[0].kept.len(), 2);
+        assert!(stacked.conflicts[0].dropped.is_empty());
+
+        let first = normalizer(ConflictPolicy::FirstWins)
+            .resolve(&params)
+            .unwrap();
+        assert_eq!(
+            first.layers,
+            parse_params("pants/cargo-black,hoodies/hoodie-black")
+        );
+        assert_eq!(
+            first.conflicts[0].dropped,
+            parse_params("hoodies/hoodie-grey")
+        );
+        assert_eq!(
+            first.conflicts[0].to_string(),
+            "Several hoodies layers: kept hoodies/hoodie-black, dropped hoodies/hoodie-grey"
+        );
+
+        let last = normalizer(ConflictPolicy::LastWins).normalize_all(&params);
+        assert_eq!(last, parse_params("pants/cargo-black,hoodies/hoodie-grey"));
+
+        let Err(BirlError::LayerConflict(err)) = normalizer(ConflictPolicy::Error).resolve(&params)
+        else {
+            panic!("expected a layer conflict");
+        };
+        assert_eq!(err.categories(), vec!["hoodies"]);
+        assert_eq!(
+            normalizer(ConflictPolicy::Error)
+                .normalize_all(&params)
+                .len(),
+            3
+        );
+
+        // Conflicts are found after normalization: both gloves become gloves-top
+        let params = parse_params("gloves/ski-black,gloves/ski-red,hats/beanie-black");
+        let normalized = LayerNormalizer::new(View::Front, &params)
+            .with_conflict_policy(ConflictPolicy::LastWins)
+            .resolve(&params)
+            .unwrap();
+        assert_eq!(normalized.conflicts[0].category, "gloves-top");
+        assert_eq!(normalized.layers.len(), 2);
+    }
+
+    #[test]
+    fn test_constraints() {
+        let constraints = vec![
+            Constraint::requires("gloves-top", "*jackets").with_action(ConstraintAction::Drop),
+            Constraint::excludes("outer-jackets", "hoodies/*-hood-*")
+                .with_action(ConstraintAction::Error),
+        ];
+        let normalizer = |params: &[LayerParam]| {
+            LayerNormalizer::new(View::Front, params).with_constraints(constraints.clone())
+        };
+
+        // Constraints match the normalized categories: ski gloves are gloves-top
+        let params = parse_params("gloves/ski-black,hats/beanie-black");
+        let normalized = normalizer(&params).resolve(&params).unwrap();
+        assert_eq!(normalized.layers, parse_params("hats/beanie-black"));
+        assert_eq!(
+            normalized.violations[0].layer,
+            LayerParam::new("gloves-top", "ski-black")
+        );
+
+        let params = parse_params("gloves/ski-black,jackets/softshell-grey");
+        let normalized = normalizer(&params).resolve(&params).unwrap();
+        assert_eq!(normalized.layers.len(), 2);
+        assert!(normalized.violations.is_empty());
+
+        let params = parse_params("hoodies/zip-hood-black,jackets/greenland-black");
+        let Err(BirlError::ConstraintViolation(err)) = normalizer(&params).resolve(&params) else {
+            panic!("expected a constraint violation");
+        };
+        assert_eq!(
+            err.violations[0].conflicting,
+            parse_params("hoodies/zip-hood-black")
+        );
+        assert_eq!(normalizer(&params).normalize_all(&params).len(), 2);
+        assert_eq!(normalizer(&params).violations(&params).len(), 1);
+    }
+
+    #[test]
+    fn test_dropped() {
+        let params = parse_params(
+            "plate/base-model-tall,hoodies/hoodie-black,hoodies/hoodie-grey,gloves/ski-black",
+        );
+        let normalized = LayerNormalizer::new(View::Front, &params)
+            .with_conflict_policy(ConflictPolicy::FirstWins)
+            .with_constraints(vec![
+                Constraint::requires("gloves-top", "*jackets").with_action(ConstraintAction::Drop)
+            ])
+            .resolve(&params)
+            .unwrap();
+        let reasons: Vec<(String, &str)> = normalized
+            .dropped
+            .iter()
+            .map(|dropped| (dropped.layer.to_string(), dropped.reason.name()))
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                ("hoodies/hoodie-grey".to_string(), "conflict"),
+                ("gloves-top/ski-black".to_string(), "constraint"),
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(&normalized.dropped[0]).unwrap(),
+            serde_json::json!({
+                "layer": {"category": "hoodies", "sku": "hoodie-grey"},
+                "reason": "conflict",
+                "policy": "first-wins",
+            })
+        );
+
+        let params = parse_params("hoodies/hoodie-black,pants/cargo-black");
+        let dropped = LayerNormalizer::new(View::Left, &params).dropped(&params);
+        assert_eq!(
+            dropped[0].to_string(),
+            "Dropped pants/cargo-black: not rendered in left view"
+        );
+
+        // Back-view patches are dropped by the view
+        let params = parse_params("hoodies/hoodie-black,patches-left/flag-patch-red");
+        let dropped = LayerNormalizer::new(View::Back, &params).dropped(&params);
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(
+            dropped[0].reason,
+            DropReason::NotInView { view: View::Back }
+        );
+        assert!(LayerNormalizer::new(View::Front, &params)
+            .dropped(&params)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_layer_limit() {
+        let params = parse_params("hoodies/hoodie-black,pants/cargo-black,gloves/ski-black");
+        let normalizer = |max| {
+            LayerNormalizer::new(View::Front, &params)
+                .with_limits(Limits::default().with_max_layers(max))
+        };
+        assert!(normalizer(3).resolve(&params).is_ok());
+        assert!(matches!(
+            normalizer(2).resolve(&params),
+            Err(BirlError::LimitExceeded(_))
+        ));
+
+        // Only layers the view renders count
+        let normalizer = LayerNormalizer::new(View::Left, &params)
+            .with_limits(Limits::default().with_max_layers(1));
+        assert!(normalizer.resolve(&params).is_ok());
+    }
 }
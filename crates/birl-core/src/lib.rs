@@ -3,21 +3,112 @@
 //! This crate provides the business logic for layering clothing items over base models.
 //! It handles SKU normalization, layer ordering, and image composition.
 
+pub mod aliases;
+pub mod batch;
 pub mod cache;
+pub mod canonical;
+pub mod catalog;
 pub mod compositor;
+pub mod coverage;
+pub mod demo;
+pub mod diff;
+pub mod error;
+pub mod filters;
+pub mod flatlay;
+pub mod format;
+pub mod graph;
 pub mod layers;
+pub mod licenses;
+pub mod limits;
+pub mod lint;
+pub mod manifest;
 pub mod models;
+pub mod naming;
+pub mod names;
+pub mod offsets;
+pub mod options;
+pub mod orient;
+pub mod oversize;
+pub mod phash;
+pub mod prelude;
+pub mod presets;
+pub mod quality;
+pub mod random;
+pub mod registry;
+pub mod render;
+pub mod rules;
+pub mod sanity;
+pub mod sizes;
+pub mod suggest;
+pub mod svg;
+pub mod tables;
+pub mod tiered;
+pub mod views;
+pub mod wildcards;
 
 // Re-export commonly used types
-pub use cache::generate_cache_key;
-pub use compositor::{compose_layers, Compositor};
-pub use layers::{parse_params, LayerNormalizer};
-pub use models::{LayerOrder, LayerParam, Sku, View};
+pub use aliases::SkuAliases;
+pub use batch::{
+    BatchError, BatchErrorKind, BatchItem, BatchManifest, BatchOutcome, BatchResult, BatchSummary,
+};
+pub use cache::{generate_cache_key, variant_cache_key};
+pub use canonical::{canonicalize, CanonicalParams};
+pub use catalog::{Catalog, CatalogError, CatalogIssue};
+pub use compositor::{compose_layers, Compositor, DecodeCache};
+pub use coverage::{Cell, Coverage, CoverageRow, Orphan};
+pub use diff::{diff_composites, DiffReport};
+pub use error::BirlError;
+pub use filters::Filter;
+pub use flatlay::{FlatLay, Slot};
+pub use format::OutputFormat;
+pub use graph::{Asset, MissingSources, Node, Placement, Recipe};
+pub use layers::{
+    parse_params, parse_params_json, parse_params_strict, patch_garments, patch_mask_categories,
+    requested_plate, validate_for_view,
+    ConflictPolicy, DropReason, DroppedLayer, LayerConflict, LayerConflictError, LayerNormalizer,
+    Normalized, Params, ViewValidationError, PLATE_CATEGORY,
+};
+pub use licenses::{LicenseAction, LicenseDecision, LicenseError, LicensePolicy, Licenses};
+pub use limits::{LimitError, Limits};
+pub use lint::{
+    convert_image, AssetCheck, AssetFile, Check, Finding, LintReport, LintRule, Linter, Severity,
+};
+pub use manifest::{ManifestError, ManifestFormat};
+pub use models::{fold_sku, is_remote_url, LayerParam, LayerParamError, SizedSku, Sku, View};
+pub use naming::{CollisionPolicy, NamingContext, NamingTemplate};
+pub use names::DisplayNames;
+pub use offsets::{LayerOffsets, Offset};
+pub use options::{Background, CompositeOptions, ResizeFilter, DEFAULT_MATTE};
+pub use orient::{Flip, Orientation, OrientationRule, Orientations, Rotation};
+pub use oversize::{Oversize, OVERSIZE_FACTOR};
+pub use presets::{Preset, Presets};
+pub use quality::QualityCurve;
+pub use random::generate_random_outfit;
+pub use registry::{Category, CategoryRegistry};
+pub use render::{render, Assets, RenderedImage};
+pub use rules::{
+    check_constraints, Constraint, ConstraintAction, ConstraintError, ConstraintKind,
+    ConstraintViolation, Rule, RuleSet, SkuRegex,
+};
+pub use sanity::SanityIssue;
+pub use sizes::{SizeLocale, SizePatterns, SizeSpec};
+pub use suggest::{closest_sku, edit_distance, SkuIndex};
+pub use tables::Tables;
+pub use tiered::{Capacity, TieredCache, TieredStats};
+pub use views::ViewSpec;
+pub use wildcards::Wildcards;
 
 #[cfg(test)]
 mod integration_tests {
     use super::*;
 
+    /// The crate's public surface is deliberate: changing it means updating
+    /// `public-api.txt` (rerun with `BIRL_BLESS=1` to rewrite it)
+    #[test]
+    fn test_public_api() {
+        birl_public_api::check(env!("CARGO_MANIFEST_DIR"));
+    }
+
     #[test]
     fn test_full_workflow() {
         // Parse parameters
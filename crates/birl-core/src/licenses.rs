@@ -0,0 +1,264 @@
+//! Licensing policies of artwork, from the licenses JSON
+//!
+//! ```json
+//! {
+//!   "patches-left/flag-us": {"blocked_regions": ["DE"], "attribution": "Flag art © Studio North"},
+//!   "logo-collab": {"regions": ["US", "CA"], "action": "watermark"}
+//! }
+//! ```
+//!
+//! Policies are keyed by "category/sku" or just "sku", the category winning,
+//! and SKUs are normalized like layer SKUs. A layer with `regions` may only be
+//! shown there, one with `blocked_regions` anywhere but there. Regions are
+//! matched case-insensitively; a render whose region isn't known counts as
+//! being outside every region, so restricted artwork fails closed.
+//!
+//! A composite showing restricted artwork is blocked, or watermarked when every
+//! restricted layer allows it. Attributions are collected from every licensed
+//! layer, restricted or not, for frontends to show with the composite.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::error::Result;
+use crate::manifest::{self, ManifestFormat};
+use crate::models::{LayerParam, Sku};
+
+/// Key of the licenses JSON in the storage JSON cache
+pub const LICENSES_KEY: &str = "licenses";
+
+/// What happens to a composite showing artwork where it's restricted
+/// Ordered from mildest to strictest
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum LicenseAction {
+    /// Render it with a watermark (see [`crate::Filter::Watermark`])
+    Watermark,
+    /// Refuse to render it
+    #[default]
+    Block,
+}
+
+impl fmt::Display for LicenseAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LicenseAction::Watermark => write!(f, "watermark"),
+            LicenseAction::Block => write!(f, "block"),
+        }
+    }
+}
+
+/// Where a SKU's artwork may be shown, and how it's credited
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LicensePolicy {
+    /// Regions the artwork may be shown in; anywhere unless set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regions: Option<Vec<String>>,
+    /// Regions the artwork must not be shown in
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub blocked_regions: Vec<String>,
+    pub action: LicenseAction,
+    /// Credit the composite must be shown with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attribution: Option<String>,
+}
+
+impl LicensePolicy {
+    /// Whether the artwork may be shown in `region` (`None` if unknown)
+    pub fn allows(&self, region: Option<&str>) -> bool {
+        if self.regions.is_none() && self.blocked_regions.is_empty() {
+            return true;
+        }
+        let Some(region) = region.map(str::trim) else {
+            return false;
+        };
+        let listed = |regions: &[String]| {
+            regions
+                .iter()
+                .any(|listed| listed.trim().eq_ignore_ascii_case(region))
+        };
+        !listed(&self.blocked_regions) && self.regions.as_deref().is_none_or(listed)
+    }
+}
+
+/// Outcome of checking a render's layers against the policies
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LicenseDecision {
+    /// Layers with a policy, bottom to top
+    pub licensed: Vec<LayerParam>,
+    /// Licensed layers the region may not show
+    pub restricted: Vec<LayerParam>,
+    /// Strictest action of the restricted layers; `None` if nothing is restricted
+    pub action: Option<LicenseAction>,
+    /// Credits of the licensed layers, without repeats
+    pub attributions: Vec<String>,
+}
+
+/// A render blocked by the licenses of its layers
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub struct LicenseError {
+    pub layers: Vec<LayerParam>,
+    /// Region of the render, `None` if it wasn't known
+    pub region: Option<String>,
+}
+
+impl fmt::Display for LicenseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let layers: Vec<String> = self.layers.iter().map(ToString::to_string).collect();
+        match &self.region {
+            Some(region) => write!(f, "Not licensed in {}: {}", region, layers.join(", ")),
+            None => write!(f, "Not licensed without a region: {}", layers.join(", ")),
+        }
+    }
+}
+
+/// Policy of each licensed, normalized SKU
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Licenses {
+    /// Keyed by "category/sku" or just "sku"
+    policies: HashMap<String, LicensePolicy>,
+}
+
+impl Licenses {
+    /// Policies from the licenses JSON
+    pub fn from_json(json: &str) -> Result<Self> {
+        let policies: HashMap<String, LicensePolicy> =
+            manifest::parse("licenses JSON", json, ManifestFormat::Json)?;
+        Ok(policies
+            .into_iter()
+            .fold(Self::default(), |licenses, (key, policy)| {
+                match key.rsplit_once('/') {
+                    Some((category, sku)) => licenses.with_policy(Some(category), sku, policy),
+                    None => licenses.with_policy(None, &key, policy),
+                }
+            }))
+    }
+
+    /// License a SKU, in one category or in all of them
+    pub fn with_policy(mut self, category: Option<&str>, sku: &str, policy: LicensePolicy) -> Self {
+        self.policies
+            .insert(key(category.map(str::trim), &Sku::new(sku)), policy);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.policies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.policies.is_empty()
+    }
+
+    /// Policy of a layer, if it's licensed; remote layers aren't
+    pub fn policy(&self, param: &LayerParam) -> Option<&LicensePolicy> {
+        if param.is_remote() {
+            return None;
+        }
+        self.policies
+            .get(&key(Some(&param.category), &param.sku))
+            .or_else(|| self.policies.get(&key(None, &param.sku)))
+    }
+
+    /// Check normalized layers against their policies for a render in `region`
+    pub fn decide(&self, layers: &[LayerParam], region: Option<&str>) -> LicenseDecision {
+        let mut decision = LicenseDecision::default();
+        for layer in layers {
+            let Some(policy) = self.policy(layer) else {
+                continue;
+            };
+            decision.licensed.push(layer.clone());
+            if let Some(attribution) = &policy.attribution {
+                if !decision.attributions.contains(attribution) {
+                    decision.attributions.push(attribution.clone());
+                }
+            }
+            if !policy.allows(region) {
+                decision.restricted.push(layer.clone());
+                decision.action = decision.action.max(Some(policy.action));
+            }
+        }
+        decision
+    }
+}
+
+impl LicenseDecision {
+    /// The error blocking the render, if it's blocked
+    pub fn blocked(&self, region: Option<&str>) -> Option<LicenseError> {
+        (self.action == Some(LicenseAction::Block)).then(|| LicenseError {
+            layers: self.restricted.clone(),
+            region: region.map(str::to_string),
+        })
+    }
+}
+
+fn key(category: Option<&str>, sku: &Sku) -> String {
+    match category {
+        Some(category) => format!("{}/{}", category, sku),
+        None => sku.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decide() {
+        let licenses = Licenses::from_json(
+            r#"{
+                "patches-left/flag-us": {"blocked_regions": ["DE"], "attribution": "Studio North"},
+                "logo-collab": {"regions": ["US", "CA"], "action": "watermark"},
+                "patches-right/logo-collab": {"attribution": "Collab Co"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(licenses.len(), 3);
+
+        let flag = LayerParam::new("patches-left", "flag-us");
+        let collab = LayerParam::new("patches-chest", "logo-collab");
+        let layers = [
+            LayerParam::new("hoodies", "hoodie-black"),
+            flag.clone(),
+            collab.clone(),
+        ];
+
+        let decision = licenses.decide(&layers, Some("us"));
+        assert_eq!(decision.licensed, [flag.clone(), collab.clone()]);
+        assert!(decision.restricted.is_empty());
+        assert_eq!(decision.action, None);
+        assert_eq!(decision.attributions, ["Studio North"]);
+        assert!(decision.blocked(Some("us")).is_none());
+
+        // The stricter action wins
+        let decision = licenses.decide(&layers, Some("DE"));
+        assert_eq!(decision.restricted, [flag.clone(), collab.clone()]);
+        assert_eq!(decision.action, Some(LicenseAction::Block));
+        let error = decision.blocked(Some("DE")).unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Not licensed in DE: patches-left/flag-us, patches-chest/logo-collab"
+        );
+
+        let decision = licenses.decide(&layers[2..], Some("FR"));
+        assert_eq!(decision.action, Some(LicenseAction::Watermark));
+        assert!(decision.blocked(Some("FR")).is_none());
+
+        // Unknown regions fail closed, unless the policy restricts none
+        let decision = licenses.decide(&layers[..2], None);
+        assert_eq!(decision.action, Some(LicenseAction::Block));
+        let scoped = LayerParam::new("patches-right", "logo-collab-xl");
+        let decision = licenses.decide(&[scoped], None);
+        assert_eq!(decision.action, None);
+        assert_eq!(decision.attributions, ["Collab Co"]);
+
+        let remote = LayerParam::remote("patches-left", "https://cdn.example.com/flag-us.png");
+        assert!(licenses.policy(&remote).is_none());
+        assert!(Licenses::from_json(r#"{"flag-us": {"action": "hide"}}"#).is_err());
+    }
+}
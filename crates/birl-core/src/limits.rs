@@ -0,0 +1,144 @@
+//! Guardrails on how much work a single render may ask for
+//!
+//! Every layer is one storage fetch and one compositing pass, and every
+//! source image is decoded at full size, so an unbounded request can tie up a
+//! server. Outfits with more layers than `max_layers` (after normalization)
+//! are rejected before anything is fetched, and sources larger than
+//! `max_pixels` before they are decoded:
+//!
+//! ```toml
+//! [limits]
+//! max_layers = 30
+//! max_pixels = 24_000_000
+//! ```
+
+use image::ImageReader;
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use thiserror::Error;
+
+use crate::error::Result;
+use crate::tables::Tables;
+
+/// Default for [`Limits::max_layers`]
+pub const DEFAULT_MAX_LAYERS: usize = 20;
+/// Default for [`Limits::max_pixels`] (about 7000 × 7000)
+pub const DEFAULT_MAX_PIXELS: u64 = 50_000_000;
+
+/// Complexity limits of a render
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Layers of an outfit after normalization, plate excluded
+    pub max_layers: usize,
+    /// Width × height of any image a render decodes, and so of the output
+    pub max_pixels: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_layers: DEFAULT_MAX_LAYERS,
+            max_pixels: DEFAULT_MAX_PIXELS,
+        }
+    }
+}
+
+/// A request over one of the [`Limits`]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[non_exhaustive]
+pub enum LimitError {
+    #[error("Too many layers: {layers} (at most {max})")]
+    TooManyLayers { layers: usize, max: usize },
+    #[error("Image '{what}' is too large: {width}x{height} (at most {max} pixels)")]
+    TooManyPixels {
+        what: String,
+        width: u32,
+        height: u32,
+        max: u64,
+    },
+}
+
+impl Limits {
+    pub fn with_max_layers(mut self, max_layers: usize) -> Self {
+        self.max_layers = max_layers;
+        self
+    }
+
+    pub fn with_max_pixels(mut self, max_pixels: u64) -> Self {
+        self.max_pixels = max_pixels;
+        self
+    }
+
+    pub fn check_layers(&self, layers: usize) -> Result<(), LimitError> {
+        if layers > self.max_layers {
+            return Err(LimitError::TooManyLayers {
+                layers,
+                max: self.max_layers,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn check_pixels(&self, what: &str, width: u32, height: u32) -> Result<(), LimitError> {
+        if u64::from(width) * u64::from(height) > self.max_pixels {
+            return Err(LimitError::TooManyPixels {
+                what: what.to_string(),
+                width,
+                height,
+                max: self.max_pixels,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check an encoded image's size from its header, without decoding it
+    /// Data whose header can't be read is left for the decoder to reject
+    pub fn check_image(&self, what: &str, data: &[u8]) -> Result<(), LimitError> {
+        let dimensions = ImageReader::new(Cursor::new(data))
+            .with_guessed_format()
+            .ok()
+            .and_then(|reader| reader.into_dimensions().ok());
+        match dimensions {
+            Some((width, height)) => self.check_pixels(what, width, height),
+            None => Ok(()),
+        }
+    }
+
+    /// The current tables' limits (the defaults unless others were installed)
+    pub fn global() -> Limits {
+        Tables::current().limits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::synthetic_layer;
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits::default().with_max_layers(2).with_max_pixels(100);
+        assert!(limits.check_layers(2).is_ok());
+        assert_eq!(
+            limits.check_layers(3),
+            Err(LimitError::TooManyLayers { layers: 3, max: 2 })
+        );
+        assert!(limits.check_pixels("plate", 10, 10).is_ok());
+        assert_eq!(
+            limits
+                .check_pixels("plate", 11, 10)
+                .unwrap_err()
+                .to_string(),
+            "Image 'plate' is too large: 11x10 (at most 100 pixels)"
+        );
+
+        let layer = synthetic_layer("hoodies", "hoodie-black").unwrap();
+        assert!(limits.check_image("hoodies/hoodie-black", &layer).is_err());
+        assert!(Limits::default().check_image("layer", &layer).is_ok());
+        assert!(limits.check_image("layer", b"not an image").is_ok());
+
+        let limits: Limits = toml::from_str("max_layers = 5").unwrap();
+        assert_eq!(limits.max_pixels, DEFAULT_MAX_PIXELS);
+    }
+}
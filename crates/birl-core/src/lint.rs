@@ -0,0 +1,706 @@
+//! Lint rules for layer asset drops
+//!
+//! Rules are configured as `[[lint]]` entries in `birl.toml`:
+//!
+//! ```toml
+//! [[lint]]
+//! check = "max-file-size"
+//! max_bytes = 2_000_000
+//! severity = "warning"
+//!
+//! [[lint]]
+//! check = "dimensions"
+//! view = "front"
+//! width = 2000
+//! height = 2500
+//!
+//! [[lint]]
+//! check = "naming"
+//! pattern = "^[a-z0-9]+(-[a-z0-9]+)*$"
+//! categories = ["hoodies", "pants"]
+//! ```
+//!
+//! Assets are the files under the storage root, laid out as
+//! `{view}/{category}/[...]/{sku}.{ext}`. Custom checks implement
+//! [`AssetCheck`] and are added with [`Linter::with_check`].
+//!
+//! Images stored in another format than their category's (`[formats]`) can be
+//! re-encoded with [`AssetFile::conversion`] and [`convert_image`].
+
+use bytes::Bytes;
+use image::{ImageDecoder, ImageReader};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Cursor;
+use std::str::FromStr;
+
+use crate::catalog::Catalog;
+use crate::compositor::Compositor;
+use crate::error::{BirlError, Result};
+use crate::format::OutputFormat;
+use crate::graph::MASKS_CATEGORY;
+use crate::layers::PLATE_CATEGORY;
+use crate::models::{LayerParam, View};
+use crate::options::CompositeOptions;
+use crate::registry::CategoryRegistry;
+
+/// How serious a finding is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// A configured check and where it applies
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LintRule {
+    #[serde(flatten)]
+    pub check: Check,
+    /// Severity of the findings (default: the check's own)
+    #[serde(default)]
+    pub severity: Option<Severity>,
+    /// Categories checked (default: all)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+}
+
+/// Built-in checks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "check", rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum Check {
+    /// Files larger than `max_bytes`
+    MaxFileSize { max_bytes: u64 },
+    /// Images of a view (default: every view) that aren't `width`x`height`
+    Dimensions {
+        #[serde(default)]
+        view: Option<View>,
+        width: u32,
+        height: u32,
+    },
+    /// PNG layers without an alpha channel
+    Alpha,
+    /// SKUs (file names without extension) not matching a regex
+    Naming { pattern: String },
+    /// Files storage never reads: unknown views or categories, wrong
+    /// extensions, or SKUs missing from the catalog
+    Orphans,
+}
+
+/// Size and color type read from an image header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+    pub has_alpha: bool,
+}
+
+/// One file of an asset drop
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetFile {
+    /// Path relative to the asset root, with `/` separators
+    pub path: String,
+    /// View directory, if it names a known view
+    pub view: Option<View>,
+    /// Category directory (empty for files directly under a view)
+    pub category: String,
+    /// File name without extension
+    pub sku: String,
+    pub extension: String,
+    pub size: u64,
+    /// `None` if the file isn't a readable image
+    pub image: Option<ImageInfo>,
+}
+
+/// A problem with one file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Finding {
+    pub path: String,
+    pub check: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Findings for an asset drop
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct LintReport {
+    /// Files checked
+    pub files: usize,
+    pub findings: Vec<Finding>,
+}
+
+/// A check over single files, built in or supplied by the caller
+pub trait AssetCheck: Send + Sync {
+    /// Name reported with the findings
+    fn name(&self) -> &str;
+
+    /// Severity used unless the rule overrides it
+    fn default_severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    /// Problems with the file, one message each
+    fn check(&self, asset: &AssetFile) -> Vec<String>;
+}
+
+/// Runs checks over asset files
+pub struct Linter {
+    checks: Vec<Entry>,
+}
+
+struct Entry {
+    check: Box<dyn AssetCheck>,
+    severity: Severity,
+    categories: Vec<String>,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "info" => Ok(Severity::Info),
+            "warning" | "warn" => Ok(Severity::Warning),
+            "error" => Ok(Severity::Error),
+            _ => Err(format!(
+                "Invalid severity: {}. Must be one of: info, warning, error",
+                s
+            )),
+        }
+    }
+}
+
+impl LintRule {
+    pub fn new(check: Check) -> Self {
+        Self {
+            check,
+            severity: None,
+            categories: Vec::new(),
+        }
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = Some(severity);
+        self
+    }
+
+    pub fn with_categories(mut self, categories: &[&str]) -> Self {
+        self.categories = categories.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    /// Rules used when the config has none: PNG alpha and orphaned files
+    pub fn defaults() -> Vec<LintRule> {
+        vec![LintRule::new(Check::Alpha), LintRule::new(Check::Orphans)]
+    }
+}
+
+impl AssetFile {
+    /// Describe a file from its path under the asset root and its contents
+    pub fn new(path: &str, data: &[u8]) -> Self {
+        Self {
+            size: data.len() as u64,
+            image: probe(data),
+            ..Self::from_path(path)
+        }
+    }
+
+    /// Describe a file from its path alone, e.g. from a storage listing
+    pub fn from_path(path: &str) -> Self {
+        let path = path.trim_start_matches('/').replace('\\', "/");
+        let parts: Vec<&str> = path.split('/').collect();
+
+        let file = parts.last().copied().unwrap_or_default();
+        let (sku, extension) = file.rsplit_once('.').unwrap_or((file, ""));
+        let view = match parts.len() {
+            0 | 1 => None,
+            _ => parts[0].parse().ok(),
+        };
+        let category = match parts.len() {
+            0..=2 => "",
+            _ => parts[1],
+        };
+
+        Self {
+            view,
+            category: category.to_string(),
+            sku: sku.to_string(),
+            extension: extension.to_lowercase(),
+            size: 0,
+            image: None,
+            path,
+        }
+    }
+
+    /// The format to re-encode this image in, if its category expects another
+    /// `None` for files that aren't images, unknown categories, conforming
+    /// files and PNG plates
+    pub fn conversion(&self, registry: &CategoryRegistry) -> Option<OutputFormat> {
+        self.image.as_ref()?;
+        if self.is_png_plate() {
+            return None;
+        }
+        let known = matches!(self.category.as_str(), PLATE_CATEGORY | MASKS_CATEGORY)
+            || registry.get(&self.category).is_some();
+        let format = registry.format(&self.category).filter(|_| known)?;
+        (self.extension != format.extension()).then_some(format)
+    }
+
+    /// A PNG plate, kept for transparent renders next to the configured
+    /// format (plates are read in any of their formats)
+    pub fn is_png_plate(&self) -> bool {
+        self.category == PLATE_CATEGORY && self.extension == "png"
+    }
+
+    /// Path of the image once converted to `format`
+    pub fn converted_path(&self, format: OutputFormat) -> String {
+        let stem = match self.extension.len() {
+            0 => self.path.as_str(),
+            len => &self.path[..self.path.len() - len - 1],
+        };
+        format!("{}.{}", stem, format.extension())
+    }
+}
+
+/// Quality for lossy conversions, high enough to keep assets sharp
+const CONVERSION_QUALITY: u8 = 95;
+
+/// Re-encode an image in another format
+/// Transparency is flattened onto white for formats without alpha
+pub fn convert_image(data: &[u8], format: OutputFormat) -> Result<Bytes> {
+    let options = CompositeOptions::from(format).with_quality(CONVERSION_QUALITY);
+    Compositor::new(data)?.encode_with(&options)
+}
+
+/// Read the size and color type without decoding the pixels
+fn probe(data: &[u8]) -> Option<ImageInfo> {
+    let decoder = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?;
+    let (width, height) = decoder.dimensions();
+
+    Some(ImageInfo {
+        width,
+        height,
+        has_alpha: decoder.color_type().has_alpha(),
+    })
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Linter {
+    /// A linter without checks
+    pub fn new() -> Self {
+        Self { checks: Vec::new() }
+    }
+
+    /// Build the checks of configured rules
+    /// Orphan checks resolve categories with `registry` and SKUs with `catalog`
+    pub fn from_rules(
+        rules: &[LintRule],
+        registry: &CategoryRegistry,
+        catalog: Option<&Catalog>,
+    ) -> Result<Self> {
+        let mut linter = Self::new();
+
+        for rule in rules {
+            let check: Box<dyn AssetCheck> = match &rule.check {
+                Check::MaxFileSize { max_bytes } => Box::new(MaxFileSize(*max_bytes)),
+                Check::Dimensions {
+                    view,
+                    width,
+                    height,
+                } => Box::new(Dimensions {
+                    view: *view,
+                    width: *width,
+                    height: *height,
+                }),
+                Check::Alpha => Box::new(Alpha),
+                Check::Naming { pattern } => {
+                    Box::new(Naming(Regex::new(pattern).map_err(|e| {
+                        BirlError::manifest(format!("naming pattern '{}'", pattern), e)
+                    })?))
+                }
+                Check::Orphans => Box::new(Orphans {
+                    registry: registry.clone(),
+                    catalog: catalog.cloned(),
+                }),
+            };
+            linter.push(check, rule.severity, rule.categories.clone());
+        }
+
+        Ok(linter)
+    }
+
+    /// Add a check for every category, at its default severity
+    pub fn with_check(mut self, check: impl AssetCheck + 'static) -> Self {
+        self.push(Box::new(check), None, Vec::new());
+        self
+    }
+
+    fn push(
+        &mut self,
+        check: Box<dyn AssetCheck>,
+        severity: Option<Severity>,
+        categories: Vec<String>,
+    ) {
+        self.checks.push(Entry {
+            severity: severity.unwrap_or(check.default_severity()),
+            check,
+            categories,
+        });
+    }
+
+    /// Findings for one file
+    pub fn lint(&self, asset: &AssetFile) -> Vec<Finding> {
+        self.checks
+            .iter()
+            .filter(|entry| {
+                entry.categories.is_empty() || entry.categories.contains(&asset.category)
+            })
+            .flat_map(|entry| {
+                entry.check.check(asset).into_iter().map(|message| Finding {
+                    path: asset.path.clone(),
+                    check: entry.check.name().to_string(),
+                    severity: entry.severity,
+                    message,
+                })
+            })
+            .collect()
+    }
+}
+
+impl LintReport {
+    /// Lint a file into the report
+    pub fn add(&mut self, linter: &Linter, asset: &AssetFile) {
+        self.files += 1;
+        self.findings.extend(linter.lint(asset));
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    }
+
+    /// Whether any finding is at least as severe as `threshold`
+    pub fn fails(&self, threshold: Severity) -> bool {
+        self.findings
+            .iter()
+            .any(|finding| finding.severity >= threshold)
+    }
+}
+
+struct MaxFileSize(u64);
+
+impl AssetCheck for MaxFileSize {
+    fn name(&self) -> &str {
+        "max-file-size"
+    }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    fn check(&self, asset: &AssetFile) -> Vec<String> {
+        if asset.size > self.0 {
+            vec![format!(
+                "{} bytes, over the {} byte limit",
+                asset.size, self.0
+            )]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+struct Dimensions {
+    view: Option<View>,
+    width: u32,
+    height: u32,
+}
+
+impl AssetCheck for Dimensions {
+    fn name(&self) -> &str {
+        "dimensions"
+    }
+
+    fn check(&self, asset: &AssetFile) -> Vec<String> {
+        if self.view.is_some() && asset.view != self.view {
+            return Vec::new();
+        }
+        match asset.image {
+            Some(image) if (image.width, image.height) != (self.width, self.height) => {
+                vec![format!(
+                    "{}x{}, expected {}x{}",
+                    image.width, image.height, self.width, self.height
+                )]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+struct Alpha;
+
+impl AssetCheck for Alpha {
+    fn name(&self) -> &str {
+        "alpha"
+    }
+
+    fn check(&self, asset: &AssetFile) -> Vec<String> {
+        if asset.extension != "png" {
+            return Vec::new();
+        }
+        match asset.image {
+            Some(image) if !image.has_alpha => vec!["PNG layer has no alpha channel".to_string()],
+            _ => Vec::new(),
+        }
+    }
+}
+
+struct Naming(Regex);
+
+impl AssetCheck for Naming {
+    fn name(&self) -> &str {
+        "naming"
+    }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    fn check(&self, asset: &AssetFile) -> Vec<String> {
+        if self.0.is_match(&asset.sku) {
+            Vec::new()
+        } else {
+            vec![format!("'{}' doesn't match {}", asset.sku, self.0)]
+        }
+    }
+}
+
+struct Orphans {
+    registry: CategoryRegistry,
+    catalog: Option<Catalog>,
+}
+
+impl AssetCheck for Orphans {
+    fn name(&self) -> &str {
+        "orphans"
+    }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    fn check(&self, asset: &AssetFile) -> Vec<String> {
+        let Some(view) = asset.view else {
+            return vec!["not under a view directory".to_string()];
+        };
+        if asset.category.is_empty() {
+            return vec!["not under a category directory".to_string()];
+        }
+
+        let extension = match asset.category.as_str() {
+            PLATE_CATEGORY | MASKS_CATEGORY => self.registry.extension(&asset.category),
+            category if self.registry.get(category).is_some() => self.registry.extension(category),
+            category => return vec![format!("unknown category '{}'", category)],
+        };
+        if asset.extension != extension && !asset.is_png_plate() {
+            return vec![format!(
+                "{} category reads .{} files, not .{}",
+                asset.category, extension, asset.extension
+            )];
+        }
+
+        // Plates and masks aren't catalogued
+        if asset.category == PLATE_CATEGORY || asset.category == MASKS_CATEGORY {
+            return Vec::new();
+        }
+        let param = LayerParam::new(asset.category.as_str(), asset.sku.as_str());
+        match &self.catalog {
+            Some(catalog) if !catalog.contains(&param, view) => {
+                vec![format!("{} is not in the catalog", param)]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::synthetic_layer;
+    use image::{DynamicImage, ImageFormat, RgbImage};
+
+    fn opaque_png() -> Vec<u8> {
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(4, 4))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn test_asset_file() {
+        let layer = synthetic_layer("hoodies", "hoodie-black").unwrap();
+        let asset = AssetFile::new("front/hoodies/winter/hoodie-black.png", &layer);
+
+        assert_eq!(asset.view, Some(View::Front));
+        assert_eq!(asset.category, "hoodies");
+        assert_eq!(asset.sku, "hoodie-black");
+        assert_eq!(asset.extension, "png");
+        assert!(asset.image.unwrap().has_alpha);
+
+        let asset = AssetFile::new("notes.txt", b"hello");
+        assert_eq!((asset.view, asset.category.as_str()), (None, ""));
+        assert_eq!(asset.image, None);
+    }
+
+    #[test]
+    fn test_rules() {
+        let rules: Vec<LintRule> = serde_json::from_str(
+            r#"[
+                {"check": "max-file-size", "max_bytes": 10},
+                {"check": "dimensions", "view": "front", "width": 4, "height": 4},
+                {"check": "alpha", "categories": ["hoodies"]},
+                {"check": "naming", "pattern": "^[a-z-]+$", "severity": "info"},
+                {"check": "orphans"}
+            ]"#,
+        )
+        .unwrap();
+        let linter = Linter::from_rules(&rules, &CategoryRegistry::builtin(), None).unwrap();
+
+        let png = opaque_png();
+        let findings = linter.lint(&AssetFile::new("front/hoodies/Hoodie_1.png", &png));
+        let checks: Vec<(&str, Severity)> = findings
+            .iter()
+            .map(|f| (f.check.as_str(), f.severity))
+            .collect();
+        assert_eq!(
+            checks,
+            [
+                ("max-file-size", Severity::Warning),
+                ("alpha", Severity::Error),
+                ("naming", Severity::Info)
+            ]
+        );
+
+        // Alpha only applies to hoodies here; orphans catch the wrong extension
+        let findings = linter.lint(&AssetFile::new("back/pants/cargo.jpg", &png));
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[1].check, "orphans");
+        assert_eq!(
+            findings[1].message,
+            "pants category reads .png files, not .jpg"
+        );
+
+        let bad = LintRule::new(Check::Naming {
+            pattern: "(".to_string(),
+        });
+        assert!(Linter::from_rules(&[bad], &CategoryRegistry::builtin(), None).is_err());
+    }
+
+    #[test]
+    fn test_conversion() {
+        let mut registry = CategoryRegistry::builtin();
+        registry.set_format("jackets", OutputFormat::WebP).unwrap();
+        let png = opaque_png();
+
+        let asset = AssetFile::new("front/plate/base-model-black.webp", &png);
+        assert_eq!(asset.conversion(&registry), Some(OutputFormat::Jpeg));
+        assert_eq!(
+            asset.converted_path(OutputFormat::Jpeg),
+            "front/plate/base-model-black.jpg"
+        );
+        let jpeg = convert_image(&png, OutputFormat::Jpeg).unwrap();
+        let converted = AssetFile::new("front/plate/base-model-black.jpg", &jpeg);
+        assert_eq!(converted.image.map(|image| image.width), Some(4));
+        assert_eq!(converted.conversion(&registry), None);
+
+        // PNG plates keep their transparency
+        let plate = AssetFile::new("front/plate/base-model-black.png", &png);
+        assert!(plate.is_png_plate());
+        assert_eq!(plate.conversion(&registry), None);
+
+        let asset = AssetFile::new("front/jackets/puffer.PNG", &png);
+        assert_eq!(asset.conversion(&registry), Some(OutputFormat::WebP));
+        assert_eq!(
+            asset.converted_path(OutputFormat::WebP),
+            "front/jackets/puffer.webp"
+        );
+
+        // Conforming files, unknown categories and non-images stay as they are
+        assert_eq!(
+            AssetFile::new("front/hoodies/a.png", &png).conversion(&registry),
+            None
+        );
+        assert_eq!(
+            AssetFile::new("front/capes/a.jpg", &png).conversion(&registry),
+            None
+        );
+        assert_eq!(
+            AssetFile::new("front/hoodies/a.jpg", b"").conversion(&registry),
+            None
+        );
+    }
+
+    #[test]
+    fn test_orphans_and_report() {
+        let catalog =
+            Catalog::from_json(r#"{"categories": {"hats": {"skus": ["beanie"]}}}"#).unwrap();
+        let linter = Linter::from_rules(
+            &[LintRule::new(Check::Orphans)],
+            &CategoryRegistry::builtin(),
+            Some(&catalog),
+        )
+        .unwrap();
+
+        let mut report = LintReport::default();
+        for path in [
+            "front/hats/beanie.png",
+            "front/hats/cap.png",
+            "front/plate/base-model-black.jpg",
+            "front/plate/base-model-black.png",
+            "front/capes/wool.png",
+            "sideways/hats/beanie.png",
+        ] {
+            report.add(&linter, &AssetFile::new(path, b""));
+        }
+
+        let messages: Vec<&str> = report.findings.iter().map(|f| f.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "hats/cap is not in the catalog",
+                "unknown category 'capes'",
+                "not under a view directory"
+            ]
+        );
+        assert_eq!(report.files, 6);
+        assert_eq!(report.count(Severity::Warning), 3);
+        assert!(report.fails(Severity::Warning));
+        assert!(!report.fails(Severity::Error));
+    }
+}
@@ -0,0 +1,247 @@
+//! Parsing of catalogs, presets and other data files, with errors pointing at
+//! the offending field and line
+//!
+//! A typo in a manifest used to fail with the bare serde message, e.g.
+//! "Unknown view 'frnt'", and finding the entry meant reading the whole file.
+//! Files parsed here fail with a [`ManifestError`] naming the field by its
+//! path, its line and column, and the line itself:
+//!
+//! ```text
+//! categories.hoodies.views[1]: Unknown view 'frnt' (expected one of: front, ...)
+//!   4 |       "views": ["front", "frnt"]
+//!     |                                ^
+//! ```
+//!
+//! JSON positions are where the parser stopped, just past the offending
+//! value; TOML positions are its start.
+
+use std::fmt;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+use crate::error::{BirlError, Result};
+
+/// Syntax of a manifest file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    Json,
+    Toml,
+}
+
+impl ManifestFormat {
+    /// TOML if the extension says so, JSON otherwise
+    pub fn of_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => ManifestFormat::Toml,
+            _ => ManifestFormat::Json,
+        }
+    }
+}
+
+impl fmt::Display for ManifestFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestFormat::Json => f.write_str("JSON"),
+            ManifestFormat::Toml => f.write_str("TOML"),
+        }
+    }
+}
+
+/// Where and why a manifest doesn't parse
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub struct ManifestError {
+    /// Path of the offending field, e.g. `categories.hoodies.views[1]`;
+    /// empty for the top level
+    pub field: String,
+    /// 1-based line and column, unless the error has no position
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+    /// The offending line
+    pub line: Option<String>,
+}
+
+impl ManifestError {
+    fn new(field: String, message: String, source: &str, position: Option<(usize, usize)>) -> Self {
+        let field = match field.as_str() {
+            "." | "?" => String::new(),
+            _ => field,
+        };
+        let line = position.and_then(|(line, _)| source.lines().nth(line - 1));
+        Self {
+            field,
+            position,
+            message,
+            line: line.map(|line| line.trim_end().to_string()),
+        }
+    }
+
+    fn from_json(source: &str, field: String, inner: serde_json::Error) -> Self {
+        let position = (inner.line() > 0).then(|| (inner.line(), inner.column().max(1)));
+        let message = inner.to_string();
+        let suffix = format!(" at line {} column {}", inner.line(), inner.column());
+        let message = message
+            .strip_suffix(&suffix)
+            .unwrap_or(&message)
+            .to_string();
+        Self::new(field, message, source, position)
+    }
+
+    fn from_toml(source: &str, error: serde_path_to_error::Error<toml::de::Error>) -> Self {
+        let field = error.path().to_string();
+        let inner = error.into_inner();
+        let position = inner.span().map(|span| line_column(source, span.start));
+        Self::new(field, inner.message().to_string(), source, position)
+    }
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.field.is_empty() {
+            write!(f, "{}: ", self.field)?;
+        }
+        f.write_str(&self.message)?;
+        let Some((line, column)) = self.position else {
+            return Ok(());
+        };
+        let Some(text) = &self.line else {
+            return write!(f, " (line {}, column {})", line, column);
+        };
+        let number = line.to_string();
+        let gutter = " ".repeat(number.len());
+        let indent: String = text
+            .chars()
+            .take(column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        write!(f, "\n  {} | {}\n  {} | {}^", number, text, gutter, indent)
+    }
+}
+
+/// 1-based line and column of a byte offset
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map_or(0, |line| line.chars().count())
+        + 1;
+    (line, column)
+}
+
+/// Parse a manifest, `what` naming it in the error (e.g. "catalog JSON")
+pub fn parse<T: DeserializeOwned>(what: &str, source: &str, format: ManifestFormat) -> Result<T> {
+    let parsed = match format {
+        ManifestFormat::Json => {
+            let mut deserializer = serde_json::Deserializer::from_str(source);
+            match serde_path_to_error::deserialize(&mut deserializer) {
+                // Trailing characters after the value
+                Ok(value) => deserializer
+                    .end()
+                    .map(|()| value)
+                    .map_err(|e| ManifestError::from_json(source, String::new(), e)),
+                Err(e) => Err(ManifestError::from_json(
+                    source,
+                    e.path().to_string(),
+                    e.into_inner(),
+                )),
+            }
+        }
+        ManifestFormat::Toml => serde_path_to_error::deserialize(toml::Deserializer::new(source))
+            .map_err(|e| ManifestError::from_toml(source, e)),
+    };
+    parsed.map_err(|e| BirlError::manifest(what, e))
+}
+
+/// Read and parse a manifest file, TOML if the extension says so, JSON
+/// otherwise; `what` names it in errors (e.g. "catalog")
+pub fn load<T: DeserializeOwned>(what: &str, path: &Path) -> Result<T> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| BirlError::io(format!("Failed to read {} {}", what, path.display()), e))?;
+    parse(
+        &format!("{} {}", what, path.display()),
+        &source,
+        ManifestFormat::of_path(path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::View;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Category {
+        views: Vec<View>,
+        #[serde(default)]
+        z_order: u32,
+    }
+
+    type Categories = BTreeMap<String, Category>;
+
+    fn error(source: &str, format: ManifestFormat) -> ManifestError {
+        let err = parse::<Categories>("test", source, format).unwrap_err();
+        match err {
+            BirlError::InvalidManifest { source, .. } => *source.downcast().unwrap(),
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn test_json_error_span() {
+        let json = "{\n  \"hoodies\": {\"views\": [\"front\"]},\n  \"hats\": {\"views\": [\"front\", \"frnt\"]}\n}";
+        let err = error(json, ManifestFormat::Json);
+        assert_eq!(err.field, "hats.views[1]");
+        assert_eq!(err.position.map(|(line, _)| line), Some(3));
+        assert!(err.message.starts_with("Unknown view 'frnt'"));
+        let shown = err.to_string();
+        assert!(shown.starts_with("hats.views[1]: Unknown view"));
+        assert!(shown.contains("\n  3 |   \"hats\""));
+        assert!(!shown.contains("at line"));
+
+        let err = error(
+            "{\"hats\": {\"views\": [], \"z\": 1}}",
+            ManifestFormat::Json,
+        );
+        assert_eq!(err.field, "hats.z");
+        assert!(err.message.contains("unknown field `z`"));
+
+        let err = error("{} {}", ManifestFormat::Json);
+        assert_eq!((err.field.as_str(), err.position), ("", Some((1, 4))));
+    }
+
+    #[test]
+    fn test_toml_error_span() {
+        let toml =
+            "[hoodies]\nviews = [\"front\"]\n\n[hats]\nviews = [\"front\"]\nz_order = \"high\"\n";
+        let err = error(toml, ManifestFormat::Toml);
+        assert_eq!(err.field, "hats.z_order");
+        assert_eq!(err.position, Some((6, 11)));
+        assert_eq!(err.line.as_deref(), Some("z_order = \"high\""));
+        assert!(err
+            .to_string()
+            .ends_with("\n  6 | z_order = \"high\"\n    |           ^"));
+
+        let categories: Categories = parse(
+            "test",
+            "[hats]\nviews = [\"back\"]\nz_order = 5",
+            ManifestFormat::Toml,
+        )
+        .unwrap();
+        assert_eq!(categories["hats"].views, [View::Back]);
+        assert_eq!(categories["hats"].z_order, 5);
+        assert_eq!(
+            ManifestFormat::of_path(Path::new("a/b.toml")),
+            ManifestFormat::Toml
+        );
+        assert_eq!(
+            ManifestFormat::of_path(Path::new("b.json")),
+            ManifestFormat::Json
+        );
+    }
+}
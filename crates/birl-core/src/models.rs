@@ -1,30 +1,100 @@
-use serde::{Deserialize, Serialize};
+use icu_normalizer::ComposingNormalizerBorrowed;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+use xxhash_rust::xxh64::xxh64;
+
+use crate::error::BirlError;
+use crate::layers::PLATE_CATEGORY;
+use crate::registry::CategoryRegistry;
+use crate::rules::RuleSet;
+use crate::sizes::SizePatterns;
+use crate::views;
 
 /// View types for the birl composition
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
+/// Views beyond the built-in five are defined in configuration (see [`crate::views`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
 pub enum View {
     Front,
     Back,
     Side,
     Left,
     Right,
+    /// A view registered with [`crate::Tables::register_view`]
+    Custom(&'static str),
 }
 
 impl fmt::Display for View {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            View::Front => write!(f, "front"),
-            View::Back => write!(f, "back"),
-            View::Side => write!(f, "side"),
-            View::Left => write!(f, "left"),
-            View::Right => write!(f, "right"),
+        f.write_str(self.as_str())
+    }
+}
+
+/// Case-insensitive, with the aliases `rear` (back) and `profile` (side)
+impl FromStr for View {
+    type Err = BirlError;
+
+    fn from_str(s: &str) -> Result<Self, BirlError> {
+        let s = s.trim();
+        let name = s.to_ascii_lowercase();
+        match name.as_str() {
+            "front" => Ok(View::Front),
+            "back" | "rear" => Ok(View::Back),
+            "side" | "profile" => Ok(View::Side),
+            "left" => Ok(View::Left),
+            "right" => Ok(View::Right),
+            _ => views::lookup(s)
+                .or_else(|| views::lookup(&name))
+                .ok_or_else(|| {
+                    let known: Vec<_> = View::all().iter().map(View::as_str).collect();
+                    BirlError::InvalidParam(format!(
+                        "Unknown view '{}' (expected one of: {})",
+                        s,
+                        known.join(", ")
+                    ))
+                }),
         }
     }
 }
 
+impl TryFrom<&str> for View {
+    type Error = BirlError;
+
+    fn try_from(s: &str) -> Result<Self, BirlError> {
+        s.parse()
+    }
+}
+
+impl Serialize for View {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Only built-in and registered views deserialize
+impl<'de> Deserialize<'de> for View {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 impl View {
+    /// The built-in views
+    pub fn builtin() -> [View; 5] {
+        [View::Front, View::Back, View::Side, View::Left, View::Right]
+    }
+
+    /// Built-in views followed by the registered ones
+    pub fn all() -> Vec<View> {
+        let mut all = View::builtin().to_vec();
+        all.extend(views::registered());
+        all
+    }
+
     pub fn as_str(&self) -> &'static str {
         match self {
             View::Front => "front",
@@ -32,6 +102,7 @@ impl View {
             View::Side => "side",
             View::Left => "left",
             View::Right => "right",
+            View::Custom(name) => name,
         }
     }
 
@@ -41,6 +112,7 @@ impl View {
             View::Left | View::Right => "patch-plate",
             View::Side => "side-special-plate",
             View::Front | View::Back => "base-model-black",
+            View::Custom(name) => views::spec(name).map_or("base-model-black", |spec| &spec.plate),
         }
     }
 
@@ -53,114 +125,53 @@ impl View {
     pub fn allows_full_composition(&self) -> bool {
         matches!(self, View::Front)
     }
-
-    /// Check if this view only allows certain categories
-    pub fn allowed_categories(&self) -> Option<&[&str]> {
-        match self {
-            View::Left | View::Right => {
-                Some(&["hoodies", "jackets", "patches-left", "patches-right"])
-            }
-            _ => None,
-        }
-    }
 }
 
-/// Layer ordering with compile-time guarantees
-/// The order here defines the z-index of layers (lowest to highest)
-#[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub enum LayerOrder {
-    Pants = 0,
-    Tops = 1,
-    Hoodies = 2,
-    GlovesBottom = 3,
-    Jackets = 4,
-    GlovesTop = 5,
-    OuterJackets = 6,
-    Hats = 7,
-    Patches = 8,
-    PatchesLeft = 9,
-    PatchesRight = 10,
-    SoftshellPatches = 11,
-    SoftshellPatchesLeft = 12,
-    SoftshellPatchesRight = 13,
-}
+/// Characters folded to `-` in SKUs: Unicode hyphens, dashes and minus signs
+const DASHES: [char; 11] = [
+    '\u{2010}', '\u{2011}', '\u{2012}', '\u{2013}', '\u{2014}', '\u{2015}', '\u{2212}', '\u{2E3A}',
+    '\u{2E3B}', '\u{FE58}', '\u{FE63}',
+];
 
-impl LayerOrder {
-    pub fn from_category(category: &str) -> Option<Self> {
-        match category {
-            "pants" => Some(LayerOrder::Pants),
-            "tops" => Some(LayerOrder::Tops),
-            "hoodies" => Some(LayerOrder::Hoodies),
-            "gloves-bottom" => Some(LayerOrder::GlovesBottom),
-            "jackets" => Some(LayerOrder::Jackets),
-            "gloves-top" => Some(LayerOrder::GlovesTop),
-            "outer-jackets" => Some(LayerOrder::OuterJackets),
-            "hats" => Some(LayerOrder::Hats),
-            "patches" => Some(LayerOrder::Patches),
-            "patches-left" => Some(LayerOrder::PatchesLeft),
-            "patches-right" => Some(LayerOrder::PatchesRight),
-            "softshell-patches" => Some(LayerOrder::SoftshellPatches),
-            "softshell-patches-left" => Some(LayerOrder::SoftshellPatchesLeft),
-            "softshell-patches-right" => Some(LayerOrder::SoftshellPatchesRight),
-            _ => None,
-        }
-    }
+/// Invisible characters dropped from SKUs: soft hyphen, zero-width spaces and joiners, BOM
+const INVISIBLE: [char; 5] = ['\u{00AD}', '\u{200B}', '\u{200C}', '\u{200D}', '\u{FEFF}'];
 
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            LayerOrder::Pants => "pants",
-            LayerOrder::Tops => "tops",
-            LayerOrder::Hoodies => "hoodies",
-            LayerOrder::GlovesBottom => "gloves-bottom",
-            LayerOrder::Jackets => "jackets",
-            LayerOrder::GlovesTop => "gloves-top",
-            LayerOrder::OuterJackets => "outer-jackets",
-            LayerOrder::Hats => "hats",
-            LayerOrder::Patches => "patches",
-            LayerOrder::PatchesLeft => "patches-left",
-            LayerOrder::PatchesRight => "patches-right",
-            LayerOrder::SoftshellPatches => "softshell-patches",
-            LayerOrder::SoftshellPatchesLeft => "softshell-patches-left",
-            LayerOrder::SoftshellPatchesRight => "softshell-patches-right",
-        }
-    }
+/// Fold a raw SKU to one spelling, whatever encoding it came in: NFKC
+/// (composed accents, full-width forms as ASCII), dashes as `-`, invisible
+/// characters dropped, lowercased and trimmed
+/// Examples:
+///   "Hoodie–Black" (en dash) -> hoodie-black
+///   "pull-e\u{301}te\u{301}" (combining accents) -> pull-été
+pub fn fold_sku(raw: &str) -> String {
+    let folded: String = ComposingNormalizerBorrowed::new_nfkc()
+        .normalize(raw)
+        .chars()
+        .filter(|c| !INVISIBLE.contains(c))
+        .map(|c| if DASHES.contains(&c) { '-' } else { c })
+        .collect();
+    folded.trim().to_lowercase()
 }
 
 /// Normalized SKU that removes size variations
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct Sku(String);
+pub struct Sku(pub(crate) String);
 
 impl Sku {
-    /// Create a new normalized SKU by removing size suffixes
+    /// Create a new normalized SKU by removing size suffixes, after folding
+    /// its spelling with [`fold_sku`]
     /// Examples:
     ///   mensdenimjeans-blue-36 -> mensdenimjeans-blue
     ///   zip-hoodie-grey-s -> zip-hoodie-grey
     ///   hoodie-black-lxl -> hoodie-black
     pub fn new(raw: &str) -> Self {
-        // Convert to lowercase first
-        let mut result = raw.trim().to_lowercase();
-
-        // Apply pattern matching to remove size suffixes
-        let size_patterns = [
-            "-xs", "-s", "-m", "-l", "-xl", "-xxl", "-2xl", "-3xl", "-4xl", "-5xl", "-lxl",
-        ];
-
-        for pattern in &size_patterns {
-            if result.ends_with(pattern) {
-                result = result[..result.len() - pattern.len()].to_string();
-                break;
-            }
-        }
-
-        // Also handle numeric sizes like -36, -38, -40
-        if let Some(pos) = result.rfind('-') {
-            if result[pos + 1..].chars().all(|c| c.is_ascii_digit()) {
-                result = result[..pos].to_string();
-            }
-        }
+        SizedSku::parse(raw).base
+    }
 
-        Self(result)
+    /// A SKU that is already normalized, kept as is
+    /// For reading back SKUs normalized earlier, e.g. from a serialized plan,
+    /// since normalizing twice can strip a second size-like segment
+    pub fn normalized(sku: impl Into<String>) -> Self {
+        Sku(sku.into())
     }
 
     pub fn as_str(&self) -> &str {
@@ -168,6 +179,25 @@ impl Sku {
     }
 }
 
+/// A SKU split into its normalized base and the size suffix it carried
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct SizedSku {
+    pub base: Sku,
+    /// Size without the dash, e.g. "xl" or "36"
+    pub size: Option<String>,
+}
+
+impl SizedSku {
+    /// Split off the size suffix, using the current size patterns
+    /// Examples:
+    ///   mensdenimjeans-blue-36 -> mensdenimjeans-blue, 36
+    ///   hoodie-black-xl -> hoodie-black, xl
+    ///   hoodie-black -> hoodie-black, none
+    pub fn parse(raw: &str) -> Self {
+        SizePatterns::global().split(raw)
+    }
+}
+
 impl fmt::Display for Sku {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.0)
@@ -186,11 +216,54 @@ impl From<String> for Sku {
     }
 }
 
+impl Serialize for Sku {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Deserialized SKUs are normalized like parsed ones
+impl<'de> Deserialize<'de> for Sku {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Sku::new(&String::deserialize(deserializer)?))
+    }
+}
+
+/// Check if a param value is an absolute http(s) URL rather than a SKU
+pub fn is_remote_url(value: &str) -> bool {
+    value.starts_with("https://") || value.starts_with("http://")
+}
+
 /// A layer parameter with category and SKU
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LayerParam {
     pub category: String,
     pub sku: Sku,
+    /// Absolute URL for layers fetched from a remote host instead of storage
+    pub url: Option<String>,
+    /// Z-order overriding the category's (`hats/beanie-black@z=3`)
+    pub z: Option<u32>,
+}
+
+/// Suffix of a param value that sets [`LayerParam::z`]
+const Z_SUFFIX: &str = "@z=";
+
+/// Why a "category/sku" param was rejected by [`LayerParam::try_parse`]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[non_exhaustive]
+pub enum LayerParamError {
+    #[error("'{0}' is not a category/sku param")]
+    MissingSeparator(String),
+    #[error("'{0}' has more than one '/' (separate params with ',')")]
+    ExtraSeparator(String),
+    #[error("'{0}' has no category")]
+    EmptyCategory(String),
+    #[error("'{0}' has no SKU")]
+    EmptySku(String),
+    #[error("'{0}' has an invalid z-order")]
+    InvalidZ(String),
+    #[error("Unknown category '{category}' in '{param}'")]
+    UnknownCategory { param: String, category: String },
 }
 
 impl LayerParam {
@@ -198,27 +271,193 @@ impl LayerParam {
         Self {
             category: category.into(),
             sku: sku.into(),
+            url: None,
+            z: None,
         }
     }
 
-    /// Parse from "category/sku" format
+    /// Create a layer fetched from a remote URL
+    /// The SKU is derived from a hash of the URL so it participates in cache keys
+    pub fn remote(category: impl Into<String>, url: impl Into<String>) -> Self {
+        let url = url.into();
+        let hash = xxh64(url.as_bytes(), 0);
+
+        Self {
+            category: category.into(),
+            sku: Sku(format!("url-{:x}", hash)),
+            url: Some(url),
+            z: None,
+        }
+    }
+
+    /// Render at z-order `z` instead of the category's
+    pub fn with_z(mut self, z: u32) -> Self {
+        self.z = Some(z);
+        self
+    }
+
+    /// Same layer under a different category (used by normalization)
+    pub fn with_category(&self, category: impl Into<String>) -> Self {
+        Self {
+            category: category.into(),
+            ..self.clone()
+        }
+    }
+
+    /// Parse from "category/sku" or "category/https://host/path.png" format,
+    /// optionally followed by a z-order override (`@z=3`)
     pub fn parse(param: &str) -> Option<Self> {
-        let parts: Vec<&str> = param.split('/').collect();
-        if parts.len() == 2 {
-            Some(Self::new(parts[0], parts[1]))
+        let (category, value) = param.split_once('/')?;
+        let (category, value) = (category.trim(), value.trim());
+        let (value, z) = match value.rsplit_once(Z_SUFFIX) {
+            Some((value, z)) => (value.trim(), Some(z.trim().parse().ok()?)),
+            None => (value, None),
+        };
+
+        let param = if is_remote_url(value) {
+            Self::remote(category, value)
+        } else if !value.contains('/') {
+            Self::new(category, value)
         } else {
-            None
+            return None;
+        };
+        Some(Self { z, ..param })
+    }
+
+    /// Like [`Self::parse`], saying why a param doesn't parse, and rejecting
+    /// empty SKUs and categories that are neither registered nor remapped by a
+    /// normalization rule
+    pub fn try_parse(param: &str) -> Result<Self, LayerParamError> {
+        let raw = param.trim();
+        let error = |kind: fn(String) -> LayerParamError| kind(raw.to_string());
+        let (category, value) = raw
+            .split_once('/')
+            .ok_or_else(|| error(LayerParamError::MissingSeparator))?;
+        let (sku, z) = match value.rsplit_once(Z_SUFFIX) {
+            Some((sku, z)) => (sku.trim(), Some(z.trim())),
+            None => (value.trim(), None),
+        };
+
+        if category.trim().is_empty() {
+            return Err(error(LayerParamError::EmptyCategory));
         }
+        if sku.is_empty() {
+            return Err(error(LayerParamError::EmptySku));
+        }
+        if sku.contains('/') && !is_remote_url(sku) {
+            return Err(error(LayerParamError::ExtraSeparator));
+        }
+        if z.is_some_and(|z| z.parse::<u32>().is_err()) {
+            return Err(error(LayerParamError::InvalidZ));
+        }
+
+        let param = Self::parse(raw).ok_or_else(|| error(LayerParamError::MissingSeparator))?;
+        param.validate()?;
+        Ok(param)
+    }
+
+    /// Reject an empty SKU or a category that is neither registered nor
+    /// remapped by a normalization rule
+    pub fn validate(&self) -> Result<(), LayerParamError> {
+        if self.category.trim().is_empty() {
+            return Err(LayerParamError::EmptyCategory(self.to_string()));
+        }
+        if self.sku.as_str().is_empty() {
+            return Err(LayerParamError::EmptySku(self.to_string()));
+        }
+        let known = self.category == PLATE_CATEGORY
+            || CategoryRegistry::global().get(&self.category).is_some()
+            || RuleSet::global().remaps(&self.category);
+        if !known {
+            return Err(LayerParamError::UnknownCategory {
+                param: self.to_string(),
+                category: self.category.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Check if this layer is fetched from a remote URL
+    pub fn is_remote(&self) -> bool {
+        self.url.is_some()
     }
 
     /// Get the layer order for this parameter
-    pub fn layer_order(&self) -> Option<LayerOrder> {
-        LayerOrder::from_category(&self.category)
+    /// The param's own z-order if set, otherwise its category's in the global registry
+    pub fn z_order(&self) -> Option<u32> {
+        self.z
+            .or_else(|| CategoryRegistry::global().z_order(&self.category))
     }
+}
 
-    /// Format as "category/sku"
-    pub fn to_string(&self) -> String {
-        format!("{}/{}", self.category, self.sku)
+/// Serialized as `{"category", "sku"}`, plus `"url"` for remote layers and
+/// `"z"` for z-order overrides
+impl Serialize for LayerParam {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let len = 2 + usize::from(self.url.is_some()) + usize::from(self.z.is_some());
+        let mut state = serializer.serialize_struct("LayerParam", len)?;
+        state.serialize_field("category", &self.category)?;
+        state.serialize_field("sku", &self.sku)?;
+        if let Some(url) = &self.url {
+            state.serialize_field("url", url)?;
+        }
+        if let Some(z) = self.z {
+            state.serialize_field("z", &z)?;
+        }
+        state.end()
+    }
+}
+
+/// Accepts `{"category", "sku"}` with an optional `"z"`, where the SKU may be a
+/// remote URL like in "category/sku" params, or the serialized form of a
+/// remote layer
+impl<'de> Deserialize<'de> for LayerParam {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Raw {
+            category: String,
+            sku: String,
+            #[serde(default)]
+            url: Option<String>,
+            #[serde(default)]
+            z: Option<u32>,
+        }
+
+        let Raw {
+            category,
+            sku,
+            url,
+            z,
+        } = Raw::deserialize(deserializer)?;
+        let category = category.trim();
+        let param = match url {
+            Some(url) => Self::remote(category, url),
+            None if is_remote_url(sku.trim()) => Self::remote(category, sku.trim()),
+            None if category.is_empty() || category.contains('/') || sku.contains('/') => {
+                return Err(serde::de::Error::custom(format!(
+                    "Invalid layer param {}/{}",
+                    category, sku
+                )))
+            }
+            None => Self::new(category, sku.as_str()),
+        };
+        Ok(Self { z, ..param })
+    }
+}
+
+/// Format as "category/sku" (or "category/url" for remote layers), plus the
+/// z-order override (`@z=3`) if set
+impl fmt::Display for LayerParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.url {
+            Some(url) => write!(f, "{}/{}", self.category, url)?,
+            None => write!(f, "{}/{}", self.category, self.sku)?,
+        }
+        match self.z {
+            Some(z) => write!(f, "{}{}", Z_SUFFIX, z),
+            None => Ok(()),
+        }
     }
 }
 
@@ -226,9 +465,25 @@ impl LayerParam {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_sized_sku() {
+        let sized = SizedSku::parse("Hoodie-Black-XL");
+        assert_eq!(sized.base.as_str(), "hoodie-black");
+        assert_eq!(sized.size.as_deref(), Some("xl"));
+
+        let sized = SizedSku::parse("mensdenimjeans-blue-36");
+        assert_eq!(sized.base.as_str(), "mensdenimjeans-blue");
+        assert_eq!(sized.size.as_deref(), Some("36"));
+
+        assert_eq!(SizedSku::parse("beanie-black").size, None);
+    }
+
     #[test]
     fn test_sku_normalization() {
-        assert_eq!(Sku::new("mensdenimjeans-blue-36").as_str(), "mensdenimjeans-blue");
+        assert_eq!(
+            Sku::new("mensdenimjeans-blue-36").as_str(),
+            "mensdenimjeans-blue"
+        );
         assert_eq!(Sku::new("zip-hoodie-grey-s").as_str(), "zip-hoodie-grey");
         assert_eq!(Sku::new("hoodie-black-lxl").as_str(), "hoodie-black");
         assert_eq!(Sku::new("hoodie-black-xl").as_str(), "hoodie-black");
@@ -236,6 +491,46 @@ mod tests {
         assert_eq!(Sku::new("cargo-darkgreen-40").as_str(), "cargo-darkgreen");
     }
 
+    #[test]
+    fn test_fold_sku() {
+        // Composed and decomposed accents, any case
+        assert_eq!(fold_sku("pull-\u{e9}t\u{e9}"), "pull-\u{e9}t\u{e9}");
+        assert_eq!(fold_sku("pull-e\u{301}te\u{301}"), "pull-\u{e9}t\u{e9}");
+        assert_eq!(fold_sku("PULL-\u{c9}T\u{c9}"), "pull-\u{e9}t\u{e9}");
+        // Dashes, full-width forms, invisible and surrounding whitespace
+        assert_eq!(fold_sku("hoodie\u{2013}black"), "hoodie-black");
+        assert_eq!(fold_sku("hoodie\u{2011}black"), "hoodie-black");
+        assert_eq!(fold_sku("hoodie\u{2212}black"), "hoodie-black");
+        assert_eq!(fold_sku("\u{ff28}oodie\u{ff0d}black"), "hoodie-black");
+        assert_eq!(
+            fold_sku("\u{feff}hoodie-\u{ad}black\u{200b}\u{a0}"),
+            "hoodie-black"
+        );
+        // Compatibility characters
+        assert_eq!(fold_sku("\u{fb01}eld-jacket"), "field-jacket");
+
+        // Sizes behind a non-ASCII dash are still stripped, so keys match
+        let sized = SizedSku::parse("Hoodie\u{2013}Black\u{2013}XL");
+        assert_eq!(sized.base.as_str(), "hoodie-black");
+        assert_eq!(sized.size.as_deref(), Some("xl"));
+        assert_eq!(
+            Sku::new("pull-e\u{301}te\u{301}-36"),
+            Sku::new("PULL-\u{c9}T\u{c9}\u{2010}36")
+        );
+    }
+
+    #[test]
+    fn test_view_from_str() {
+        assert_eq!("front".parse::<View>().unwrap(), View::Front);
+        assert_eq!(" Back ".parse::<View>().unwrap(), View::Back);
+        assert_eq!("REAR".parse::<View>().unwrap(), View::Back);
+        assert_eq!(View::try_from("profile").unwrap(), View::Side);
+        let err = "sideways".parse::<View>().unwrap_err();
+        assert!(err.to_string().starts_with("Unknown view 'sideways'"));
+        let view: View = serde_json::from_str("\"Left\"").unwrap();
+        assert_eq!(view, View::Left);
+    }
+
     #[test]
     fn test_view_plate_value() {
         assert_eq!(View::Front.plate_value(), "base-model-black");
@@ -262,10 +557,76 @@ mod tests {
     }
 
     #[test]
-    fn test_layer_order() {
-        assert!(LayerOrder::Pants < LayerOrder::Tops);
-        assert!(LayerOrder::Tops < LayerOrder::Hoodies);
-        assert!(LayerOrder::Hoodies < LayerOrder::Jackets);
-        assert!(LayerOrder::Jackets < LayerOrder::Hats);
+    fn test_layer_param_z() {
+        let param = LayerParam::parse("hats/Beanie-Black@z=3").unwrap();
+        assert_eq!((param.sku.as_str(), param.z), ("beanie-black", Some(3)));
+        assert_eq!(param.z_order(), Some(3));
+        assert_eq!(param.to_string(), "hats/beanie-black@z=3");
+        assert_eq!(LayerParam::parse(&param.to_string()), Some(param.clone()));
+        assert!(LayerParam::parse("hats/beanie-black@z=top").is_none());
+
+        let remote = LayerParam::parse("patches/https://cdn.partner.com/flag.png@z=40").unwrap();
+        assert_eq!(
+            remote.url.as_deref(),
+            Some("https://cdn.partner.com/flag.png")
+        );
+        assert_eq!(remote.z, Some(40));
+
+        let json = serde_json::to_string(&param).unwrap();
+        assert_eq!(json, r#"{"category":"hats","sku":"beanie-black","z":3}"#);
+        assert_eq!(serde_json::from_str::<LayerParam>(&json).unwrap(), param);
+    }
+
+    #[test]
+    fn test_layer_param_parse_remote() {
+        let param = LayerParam::parse("patches-left/https://cdn.partner.com/art/Flag.png").unwrap();
+        assert_eq!(param.category, "patches-left");
+        assert_eq!(
+            param.url.as_deref(),
+            Some("https://cdn.partner.com/art/Flag.png")
+        );
+        assert!(param.sku.as_str().starts_with("url-"));
+        assert_eq!(
+            param.to_string(),
+            "patches-left/https://cdn.partner.com/art/Flag.png"
+        );
+
+        let other =
+            LayerParam::parse("patches-left/https://cdn.partner.com/art/other.png").unwrap();
+        assert_ne!(param.sku, other.sku);
+
+        assert!(LayerParam::parse("hoodies/black/extra").is_none());
+    }
+
+    #[test]
+    fn test_layer_param_try_parse() {
+        let param = LayerParam::try_parse(" hats/Beanie-Black@z=3 ").unwrap();
+        assert_eq!(Some(param), LayerParam::parse("hats/beanie-black@z=3"));
+        assert!(LayerParam::try_parse("gloves/ski-black").is_ok());
+        assert!(LayerParam::try_parse("plate/base-model-tall").is_ok());
+        assert!(LayerParam::try_parse("patches-left/https://cdn.partner.com/a/flag.png").is_ok());
+
+        let error = |param: &str| LayerParam::try_parse(param).unwrap_err();
+        assert_eq!(
+            error("hoodies-hoodie-black"),
+            LayerParamError::MissingSeparator("hoodies-hoodie-black".to_string())
+        );
+        assert!(matches!(
+            error("hoodies/a;pants/b"),
+            LayerParamError::ExtraSeparator(_)
+        ));
+        assert!(matches!(
+            error("/hoodie-black"),
+            LayerParamError::EmptyCategory(_)
+        ));
+        assert!(matches!(error("hoodies/ "), LayerParamError::EmptySku(_)));
+        assert!(matches!(
+            error("hats/beanie@z=top"),
+            LayerParamError::InvalidZ(_)
+        ));
+        assert_eq!(
+            error("hoodys/hoodie-black").to_string(),
+            "Unknown category 'hoodys' in 'hoodys/hoodie-black'"
+        );
     }
 }
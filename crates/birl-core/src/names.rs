@@ -0,0 +1,163 @@
+//! Human display names of SKUs, from the products JSON
+//!
+//! ```json
+//! [
+//!   {"sku": "baerskin4-black", "name": "BÆRSkin 4 Hoodie", "variant": "Black"},
+//!   {"sku": "cap-black", "name": "Classic Cap", "category": "hats"}
+//! ]
+//! ```
+//!
+//! The list may also be wrapped as `{"products": [...]}`, and any other fields
+//! of a product are ignored. SKUs are normalized like layer SKUs, so sized
+//! SKUs share their product's name. A product with a `category` only names
+//! the SKU in that category. Names are for people reading CLI output and debug
+//! headers; they never change what is rendered or how it is cached.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::error::Result;
+use crate::manifest::{self, ManifestFormat};
+use crate::models::{LayerParam, Sku};
+use crate::tables::Tables;
+
+/// Key of the products JSON in the storage JSON cache
+pub const PRODUCTS_KEY: &str = "products-dynamic-cache";
+
+/// Between a product's name and its variant
+const VARIANT_SEPARATOR: &str = " — ";
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Products {
+    List(Vec<Product>),
+    Wrapped { products: Vec<Product> },
+}
+
+#[derive(Deserialize)]
+struct Product {
+    sku: String,
+    name: String,
+    #[serde(default)]
+    variant: Option<String>,
+    #[serde(default)]
+    category: Option<String>,
+}
+
+/// Display name of each normalized SKU
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DisplayNames {
+    /// Keyed by "category/sku" or just "sku"
+    names: HashMap<String, String>,
+}
+
+impl DisplayNames {
+    /// Names from the products JSON
+    pub fn from_products_json(json: &str) -> Result<Self> {
+        let products = match manifest::parse("products JSON", json, ManifestFormat::Json)? {
+            Products::List(products) | Products::Wrapped { products } => products,
+        };
+        Ok(products
+            .into_iter()
+            .fold(Self::default(), |names, product| {
+                let name = match product.variant {
+                    Some(variant) => format!("{}{}{}", product.name, VARIANT_SEPARATOR, variant),
+                    None => product.name,
+                };
+                names.with_name(product.category.as_deref(), &product.sku, name)
+            }))
+    }
+
+    /// Name a SKU, in one category or in all of them
+    pub fn with_name(mut self, category: Option<&str>, sku: &str, name: impl Into<String>) -> Self {
+        self.names
+            .insert(key(category.map(str::trim), &Sku::new(sku)), name.into());
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Display name of a SKU in a category, if it has one
+    /// A name scoped to the category wins over an unscoped one
+    pub fn get(&self, category: &str, sku: &Sku) -> Option<&str> {
+        self.names
+            .get(&key(Some(category), sku))
+            .or_else(|| self.names.get(&key(None, sku)))
+            .map(String::as_str)
+    }
+
+    /// Display name of a layer, if it has one; remote layers have none
+    pub fn name(&self, param: &LayerParam) -> Option<&str> {
+        if param.is_remote() {
+            return None;
+        }
+        self.get(&param.category, &param.sku)
+    }
+
+    /// The layer's display name, or `category/sku` without one
+    pub fn label(&self, param: &LayerParam) -> String {
+        match self.name(param) {
+            Some(name) => name.to_string(),
+            None => param.to_string(),
+        }
+    }
+
+    /// The current tables' names (none unless some were installed)
+    pub fn global() -> Arc<DisplayNames> {
+        Tables::current().names.clone()
+    }
+}
+
+fn key(category: Option<&str>, sku: &Sku) -> String {
+    match category {
+        Some(category) => format!("{}/{}", category, sku),
+        None => sku.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_names() {
+        let names = DisplayNames::from_products_json(
+            r#"[
+                {"sku": "baerskin4-black", "name": "BÆRSkin 4 Hoodie", "variant": "Black", "price": 99},
+                {"sku": "cap-black", "name": "Classic Cap", "category": "hats"},
+                {"sku": "cap-black", "name": "Cap"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(names.len(), 3);
+
+        let hoodie = LayerParam::new("hoodies", "BAERSkin4-Black-XL");
+        assert_eq!(names.name(&hoodie), Some("BÆRSkin 4 Hoodie — Black"));
+        assert_eq!(
+            names.label(&LayerParam::new("hats", "cap-black")),
+            "Classic Cap"
+        );
+        assert_eq!(names.label(&LayerParam::new("caps", "cap-black")), "Cap");
+        assert_eq!(
+            names.label(&LayerParam::new("pants", "cargo-black")),
+            "pants/cargo-black"
+        );
+        let remote = LayerParam::remote("hoodies", "https://cdn.example.com/baerskin4-black.png");
+        assert_eq!(names.name(&remote), None);
+
+        let wrapped = DisplayNames::from_products_json(
+            r#"{"products": [{"sku": "cap-black", "name": "Cap"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(wrapped.get("hats", &Sku::new("cap-black")), Some("Cap"));
+        assert!(DisplayNames::from_products_json(r#"[{"sku": "cap-black"}]"#).is_err());
+    }
+}
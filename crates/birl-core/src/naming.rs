@@ -0,0 +1,279 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::format::OutputFormat;
+use crate::models::{LayerParam, View};
+
+/// Default output name: the opaque cache key
+pub const DEFAULT_NAMING_TEMPLATE: &str = "{key}.{ext}";
+
+const PLACEHOLDERS: &[&str] = &[
+    "key",
+    "view",
+    "ext",
+    "index",
+    "category-slug",
+    "sku-slug",
+    "outfit-slug",
+];
+
+/// Template for output file names, e.g. `{category-slug}/{sku-slug}/{view}.{ext}`
+///
+/// Placeholders:
+/// - `{key}` cache key
+/// - `{view}` view name
+/// - `{ext}` file extension of the output format
+/// - `{index}` position of the item in the batch
+/// - `{category-slug}` / `{sku-slug}` first layer of the outfit as requested
+/// - `{outfit-slug}` every layer as `category-sku`, joined with `_`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamingTemplate {
+    template: String,
+}
+
+/// Values substituted into a naming template
+pub struct NamingContext<'a> {
+    pub params: &'a [LayerParam],
+    pub view: View,
+    pub format: OutputFormat,
+    pub cache_key: &'a str,
+    pub index: usize,
+}
+
+impl NamingTemplate {
+    /// Render the relative output path for a composite
+    pub fn render(&self, ctx: &NamingContext<'_>) -> String {
+        let first = ctx.params.first();
+        let outfit = ctx
+            .params
+            .iter()
+            .map(|p| format!("{}-{}", slugify(&p.category), slugify(p.sku.as_str())))
+            .collect::<Vec<_>>()
+            .join("_");
+
+        let mut out = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            // Placeholders are validated on parse, so the closing brace exists
+            let end = start + rest[start..].find('}').unwrap_or(rest.len() - start);
+            let value = match &rest[start + 1..end] {
+                "key" => ctx.cache_key.to_string(),
+                "view" => ctx.view.as_str().to_string(),
+                "ext" => ctx.format.extension().to_string(),
+                "index" => ctx.index.to_string(),
+                "category-slug" => slug_or_none(first.map(|p| p.category.as_str())),
+                "sku-slug" => slug_or_none(first.map(|p| p.sku.as_str())),
+                "outfit-slug" if !outfit.is_empty() => outfit.clone(),
+                _ => "none".to_string(),
+            };
+            out.push_str(&value);
+            rest = &rest[(end + 1).min(rest.len())..];
+        }
+        out.push_str(rest);
+
+        out
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+}
+
+impl Default for NamingTemplate {
+    fn default() -> Self {
+        Self {
+            template: DEFAULT_NAMING_TEMPLATE.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for NamingTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+impl FromStr for NamingTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let template = s.trim();
+        if template.is_empty() {
+            return Err("Naming template is empty".to_string());
+        }
+        if template.starts_with('/') || template.split('/').any(|part| part == "..") {
+            return Err(format!(
+                "Naming template must be a relative path: {}",
+                template
+            ));
+        }
+
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .map(|i| start + i)
+                .ok_or_else(|| format!("Unclosed placeholder in '{}'", template))?;
+
+            let name = &rest[start + 1..end];
+            if !PLACEHOLDERS.contains(&name) {
+                return Err(format!(
+                    "Unknown placeholder '{{{}}}'. Must be one of: {}",
+                    name,
+                    PLACEHOLDERS.join(", ")
+                ));
+            }
+            rest = &rest[end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(format!("Unmatched '}}' in '{}'", template));
+        }
+
+        Ok(Self {
+            template: template.to_string(),
+        })
+    }
+}
+
+/// What to do when an output path already exists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Replace the existing file
+    #[default]
+    Overwrite,
+    /// Keep the existing file and skip the write
+    Skip,
+    /// Write next to it with a `-N` suffix
+    Version,
+}
+
+impl CollisionPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CollisionPolicy::Overwrite => "overwrite",
+            CollisionPolicy::Skip => "skip",
+            CollisionPolicy::Version => "version",
+        }
+    }
+}
+
+impl fmt::Display for CollisionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for CollisionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "overwrite" => Ok(CollisionPolicy::Overwrite),
+            "skip" => Ok(CollisionPolicy::Skip),
+            "version" => Ok(CollisionPolicy::Version),
+            _ => Err(format!(
+                "Invalid collision policy: {}. Must be one of: overwrite, skip, version",
+                s
+            )),
+        }
+    }
+}
+
+/// Insert a version suffix before the extension: `a/front.jpg` -> `a/front-2.jpg`
+pub fn versioned_path(path: &str, version: u32) -> String {
+    let file_start = path.rfind('/').map(|i| i + 1).unwrap_or(0);
+    match path[file_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let dot = file_start + dot;
+            format!("{}-{}{}", &path[..dot], version, &path[dot..])
+        }
+        _ => format!("{}-{}", path, version),
+    }
+}
+
+/// Lowercase, with runs of anything other than ASCII letters and digits collapsed to `-`
+pub fn slugify(value: &str) -> String {
+    let mut slug = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+fn slug_or_none(value: Option<&str>) -> String {
+    value
+        .map(slugify)
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "none".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::parse_params;
+
+    fn render(template: &str, params: &str) -> String {
+        let params = parse_params(params);
+        let template: NamingTemplate = template.parse().unwrap();
+        template.render(&NamingContext {
+            params: &params,
+            view: View::Back,
+            format: OutputFormat::WebP,
+            cache_key: "abc123",
+            index: 7,
+        })
+    }
+
+    #[test]
+    fn test_render_placeholders() {
+        assert_eq!(
+            render(DEFAULT_NAMING_TEMPLATE, "hoodies/hoodie-black"),
+            "abc123.webp"
+        );
+        assert_eq!(
+            render(
+                "{category-slug}/{sku-slug}/{view}.{ext}",
+                "hoodies/Hoodie_Black,pants/cargo-black"
+            ),
+            "hoodies/hoodie-black/back.webp"
+        );
+        assert_eq!(
+            render(
+                "{index}-{outfit-slug}.{ext}",
+                "hoodies/hoodie-black,pants/cargo-black"
+            ),
+            "7-hoodies-hoodie-black_pants-cargo-black.webp"
+        );
+        assert_eq!(render("{sku-slug}.{ext}", ""), "none.webp");
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        assert!("{bogus}.jpg".parse::<NamingTemplate>().is_err());
+        assert!("{view.jpg".parse::<NamingTemplate>().is_err());
+        assert!("view}.jpg".parse::<NamingTemplate>().is_err());
+        assert!("../{key}.jpg".parse::<NamingTemplate>().is_err());
+        assert!("/abs/{key}.jpg".parse::<NamingTemplate>().is_err());
+        assert!("".parse::<NamingTemplate>().is_err());
+    }
+
+    #[test]
+    fn test_versioned_path() {
+        assert_eq!(versioned_path("a/front.jpg", 2), "a/front-2.jpg");
+        assert_eq!(versioned_path("a.b/front", 1), "a.b/front-1");
+        assert_eq!(versioned_path(".hidden", 3), ".hidden-3");
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Hoodie_Black  XL"), "hoodie-black-xl");
+        assert_eq!(slugify("--patches-left--"), "patches-left");
+        assert_eq!(slugify("***"), "");
+    }
+}
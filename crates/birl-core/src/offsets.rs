@@ -0,0 +1,151 @@
+//! Per-view layer offsets: small art fixes applied at composition time
+//!
+//! ```toml
+//! [offsets.side]
+//! hoodies = { x = 3, y = -2 }
+//! ```
+//!
+//! Every layer of a category is shifted by its view's offset, in plate pixels,
+//! when it is blended over the plate, so a garment that sits a few pixels off
+//! in one view is fixed without re-exporting its PNGs. Patches clipped to a
+//! garment move with it (and its mask); layers positioned by a catalog
+//! placement are not shifted, their placement already being in plate pixels.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::graph::Placement;
+use crate::layers::patch_mask_categories;
+use crate::models::{LayerParam, View};
+use crate::tables::Tables;
+
+/// How far a layer is shifted, right and down, in plate pixels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Offset {
+    #[serde(default)]
+    pub x: i32,
+    #[serde(default)]
+    pub y: i32,
+}
+
+impl Offset {
+    pub fn new(x: i32, y: i32) -> Self {
+        Self { x, y }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// `+3-2` for 3 pixels right and 2 up
+impl fmt::Display for Offset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:+}{:+}", self.x, self.y)
+    }
+}
+
+/// Offset of each category in each view
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LayerOffsets {
+    offsets: BTreeMap<(View, String), Offset>,
+}
+
+impl LayerOffsets {
+    /// Offsets from a table of view names to categories, as in the config file
+    pub fn from_table(table: &BTreeMap<String, BTreeMap<String, Offset>>) -> Result<Self> {
+        let mut offsets = BTreeMap::new();
+        for (view, categories) in table {
+            let view: View = view.parse()?;
+            for (category, offset) in categories {
+                if !offset.is_zero() {
+                    offsets.insert((view, category.clone()), *offset);
+                }
+            }
+        }
+        Ok(Self { offsets })
+    }
+
+    pub fn with_offset(mut self, view: View, category: &str, offset: Offset) -> Self {
+        self.offsets.insert((view, category.to_string()), offset);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// The offset of a category's layers in a view, if it has one
+    pub fn get(&self, view: View, category: &str) -> Option<Offset> {
+        self.offsets.get(&(view, category.to_string())).copied()
+    }
+
+    /// Offset of each normalized layer, aligned with `normalized`
+    /// `placements` is aligned with `normalized` too; placed layers get `None`
+    pub fn for_layers(
+        &self,
+        normalized: &[LayerParam],
+        placements: &[Option<Placement>],
+        view: View,
+    ) -> Vec<Option<Offset>> {
+        if self.is_empty() {
+            return vec![None; normalized.len()];
+        }
+        let masks = patch_mask_categories(normalized);
+        normalized
+            .iter()
+            .zip(masks)
+            .enumerate()
+            .map(|(idx, (layer, garment))| {
+                if placements.get(idx).copied().flatten().is_some() {
+                    return None;
+                }
+                self.get(view, garment.as_deref().unwrap_or(&layer.category))
+            })
+            .collect()
+    }
+
+    /// The current tables' offsets (none unless some were installed)
+    pub fn global() -> Arc<LayerOffsets> {
+        Tables::current().offsets.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::parse_params;
+
+    #[test]
+    fn test_for_layers() {
+        let table: BTreeMap<String, BTreeMap<String, Offset>> =
+            toml::from_str("[side]\nhoodies = { x = 3, y = -2 }\npants = { y = 1 }\n").unwrap();
+        let offsets = LayerOffsets::from_table(&table).unwrap();
+        assert_eq!(offsets.get(View::Side, "pants"), Some(Offset::new(0, 1)));
+        assert_eq!(offsets.get(View::Front, "hoodies"), None);
+        assert_eq!(Offset::new(3, -2).to_string(), "+3-2");
+
+        // Patches on a garment move with it, unless they are placed
+        let layers =
+            parse_params("hoodies/hoodie-black,patches-left/flag-patch-red,hats/beanie-red");
+        assert_eq!(
+            offsets.for_layers(&layers, &[], View::Side),
+            vec![Some(Offset::new(3, -2)), Some(Offset::new(3, -2)), None]
+        );
+        let placed = [None, Some(Placement::new(10, 10)), None];
+        assert_eq!(offsets.for_layers(&layers, &placed, View::Side)[1], None);
+        assert_eq!(
+            offsets.for_layers(&layers, &[], View::Front),
+            vec![None, None, None]
+        );
+
+        let mut unknown = BTreeMap::new();
+        unknown.insert("sideways".to_string(), BTreeMap::new());
+        assert!(LayerOffsets::from_table(&unknown).is_err());
+    }
+}
@@ -0,0 +1,459 @@
+use image::imageops::FilterType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::filters::{filters_variant, Filter};
+use crate::format::OutputFormat;
+use crate::models::View;
+use crate::quality::QualityCurve;
+
+/// Resampling filter used when resizing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResizeFilter::Nearest => "nearest",
+            ResizeFilter::Triangle => "triangle",
+            ResizeFilter::CatmullRom => "catmullrom",
+            ResizeFilter::Gaussian => "gaussian",
+            ResizeFilter::Lanczos3 => "lanczos3",
+        }
+    }
+
+    pub fn filter_type(&self) -> FilterType {
+        match self {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl fmt::Display for ResizeFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ResizeFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "nearest" => Ok(ResizeFilter::Nearest),
+            "triangle" | "bilinear" => Ok(ResizeFilter::Triangle),
+            "catmullrom" | "catmull-rom" => Ok(ResizeFilter::CatmullRom),
+            "gaussian" => Ok(ResizeFilter::Gaussian),
+            "lanczos3" | "lanczos" => Ok(ResizeFilter::Lanczos3),
+            _ => Err(format!(
+                "Invalid resize filter: {}. Must be one of: nearest, triangle, catmullrom, gaussian, lanczos3",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for ResizeFilter {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ResizeFilter> for String {
+    fn from(filter: ResizeFilter) -> Self {
+        filter.to_string()
+    }
+}
+
+/// Solid RGB color, string form "#rrggbb"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Background(pub [u8; 3]);
+
+impl fmt::Display for Background {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b] = self.0;
+        write!(f, "#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+impl FromStr for Background {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim().trim_start_matches('#');
+        let invalid = || format!("Invalid background color: {}. Expected #rrggbb", s);
+
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+        Ok(Background([channel(0)?, channel(2)?, channel(4)?]))
+    }
+}
+
+impl TryFrom<String> for Background {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Background> for String {
+    fn from(background: Background) -> Self {
+        background.to_string()
+    }
+}
+
+/// Color transparent areas are flattened onto for formats without alpha
+pub const DEFAULT_MATTE: Background = Background([255, 255, 255]);
+
+/// Per-request rendering options for a composite
+///
+/// ```
+/// use birl_core::{CompositeOptions, Filter, OutputFormat};
+///
+/// let options = CompositeOptions::new()
+///     .with_format(OutputFormat::WebP)
+///     .with_max_size(Some(400), None)
+///     .with_filter(Filter::Grayscale);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompositeOptions {
+    pub format: OutputFormat,
+    /// Encoder quality (1-100) for JPEG and AVIF; ignored by lossless formats
+    /// Unset, the installed [`QualityCurve`] picks one from the output size
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+    /// Downscale to fit within these bounds, keeping the aspect ratio
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_height: Option<u32>,
+    pub resize_filter: ResizeFilter,
+    /// Resampling filter for layers that don't match the plate size
+    pub layer_filter: ResizeFilter,
+    /// Fill transparent areas of the composite with this color
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<Background>,
+    /// Post-processing filters, applied in order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<Filter>,
+    /// Color transparent areas are flattened onto when the format has no alpha
+    /// (JPEG); white unless set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matte: Option<Background>,
+    /// Plate to render on instead of the view's own, e.g. another base model
+    /// or body type; part of the base cache key rather than the variant
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plate: Option<String>,
+    /// Render the garments alone on a transparent image the plate's size,
+    /// leaving the plate out
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cutout: bool,
+    /// Region the composite is shown in (e.g. "DE"), checked against the
+    /// licenses of its layers; not part of the cache key, which the license
+    /// decision changes through its filters instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+}
+
+impl CompositeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the encoder quality, clamped to 1-100
+    pub fn with_quality(mut self, quality: u8) -> Self {
+        self.quality = Some(quality.clamp(1, 100));
+        self
+    }
+
+    pub fn with_max_size(mut self, width: Option<u32>, height: Option<u32>) -> Self {
+        self.max_width = width;
+        self.max_height = height;
+        self
+    }
+
+    pub fn with_resize_filter(mut self, filter: ResizeFilter) -> Self {
+        self.resize_filter = filter;
+        self
+    }
+
+    pub fn with_layer_filter(mut self, filter: ResizeFilter) -> Self {
+        self.layer_filter = filter;
+        self
+    }
+
+    pub fn with_background(mut self, background: Background) -> Self {
+        self.background = Some(background);
+        self
+    }
+
+    pub fn with_filters(mut self, filters: Vec<Filter>) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn with_matte(mut self, matte: Background) -> Self {
+        self.matte = Some(matte);
+        self
+    }
+
+    /// Matte for formats without alpha
+    pub fn matte(&self) -> Background {
+        self.matte.unwrap_or(DEFAULT_MATTE)
+    }
+
+    pub fn with_plate(mut self, plate: impl Into<String>) -> Self {
+        self.plate = Some(plate.into());
+        self
+    }
+
+    /// Render a cutout of the garments; a format without alpha switches to PNG
+    pub fn with_cutout(mut self) -> Self {
+        self.cutout = true;
+        if !self.format.has_alpha() {
+            self.format = OutputFormat::Png;
+        }
+        self
+    }
+
+    pub fn with_region(mut self, region: impl Into<String>) -> Self {
+        self.region = Some(region.into());
+        self
+    }
+
+    /// Plate a view renders on: the requested one, or the view's default
+    pub fn plate_for(&self, view: View) -> &str {
+        self.plate.as_deref().unwrap_or(view.plate_value())
+    }
+
+    /// Check if the options leave a JPEG plate untouched
+    /// The plate, matte and region don't matter: plates are served as-is
+    pub fn is_passthrough(&self) -> bool {
+        Self {
+            plate: None,
+            matte: None,
+            region: None,
+            ..self.clone()
+        } == Self::default()
+    }
+
+    /// Encoder quality for an output of this size: the requested one, else
+    /// the installed quality curve's, else `None` for the encoder default
+    pub fn quality_for(&self, width: u32, height: u32) -> Option<u8> {
+        self.quality
+            .or_else(|| QualityCurve::global().quality(width, height))
+    }
+
+    /// Downscaled size for an image, or `None` if it already fits
+    pub fn target_size(&self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let max_width = self.max_width.unwrap_or(width).max(1);
+        let max_height = self.max_height.unwrap_or(height).max(1);
+
+        if width <= max_width && height <= max_height {
+            return None;
+        }
+
+        let scale = f64::min(
+            max_width as f64 / width as f64,
+            max_height as f64 / height as f64,
+        );
+        let scaled = |v: u32| ((v as f64 * scale).round() as u32).max(1);
+
+        Some((scaled(width), scaled(height)))
+    }
+
+    /// Cache key variant for these options
+    /// Empty for the defaults, so plain JPEG renders keep their original key
+    pub fn cache_variant(&self) -> String {
+        let mut parts = vec![
+            filters_variant(&self.filters),
+            self.format.cache_variant().to_string(),
+        ];
+
+        if let Some(quality) = self.quality {
+            parts.push(format!("q{}", quality));
+        } else if self.format.is_lossy() {
+            parts.push(QualityCurve::global().cache_variant());
+        }
+        if self.max_width.is_some() || self.max_height.is_some() {
+            let bound = |v: Option<u32>| v.map(|v| v.to_string()).unwrap_or_default();
+            parts.push(format!(
+                "fit{}x{}",
+                bound(self.max_width),
+                bound(self.max_height)
+            ));
+            if self.resize_filter != ResizeFilter::default() {
+                parts.push(self.resize_filter.to_string());
+            }
+        }
+        if self.layer_filter != ResizeFilter::default() {
+            parts.push(format!("layer-{}", self.layer_filter));
+        }
+        if self.cutout {
+            parts.push("cutout".to_string());
+        }
+        if let Some(background) = self.background {
+            parts.push(format!(
+                "bg{}",
+                background.to_string().trim_start_matches('#')
+            ));
+        }
+
+        if !self.format.has_alpha() && self.matte() != DEFAULT_MATTE {
+            parts.push(format!(
+                "matte{}",
+                self.matte().to_string().trim_start_matches('#')
+            ));
+        }
+
+        parts.retain(|part| !part.is_empty());
+        parts.join("_")
+    }
+}
+
+impl From<OutputFormat> for CompositeOptions {
+    fn from(format: OutputFormat) -> Self {
+        Self::new().with_format(format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_cache_variant_is_empty() {
+        assert_eq!(CompositeOptions::new().cache_variant(), "");
+        assert!(CompositeOptions::new().is_passthrough());
+    }
+
+    #[test]
+    fn test_cache_variant_matches_filter_format_keys() {
+        let options = CompositeOptions::new()
+            .with_filter(Filter::Grayscale)
+            .with_format(OutputFormat::WebP);
+        assert_eq!(options.cache_variant(), "grayscale_webp");
+    }
+
+    #[test]
+    fn test_cache_variant_includes_knobs() {
+        let options = CompositeOptions::new()
+            .with_quality(60)
+            .with_max_size(Some(400), None)
+            .with_resize_filter(ResizeFilter::Triangle)
+            .with_background(Background([255, 255, 255]));
+        assert_eq!(options.cache_variant(), "q60_fit400x_triangle_bgffffff");
+
+        // The resize filter only matters when resizing
+        let options = CompositeOptions::new().with_resize_filter(ResizeFilter::Nearest);
+        assert_eq!(options.cache_variant(), "");
+
+        let options = CompositeOptions::new().with_layer_filter(ResizeFilter::Nearest);
+        assert_eq!(options.cache_variant(), "layer-nearest");
+    }
+
+    #[test]
+    fn test_plate() {
+        let options = CompositeOptions::new().with_plate("base-model-tall");
+        assert_eq!(options.plate_for(View::Back), "base-model-tall");
+        assert_eq!(
+            CompositeOptions::new().plate_for(View::Side),
+            "side-special-plate"
+        );
+
+        // The plate is part of the base cache key, not the variant
+        assert_eq!(options.cache_variant(), "");
+        assert!(options.is_passthrough());
+        let options = options.with_region("DE");
+        assert_eq!(options.cache_variant(), "");
+        assert!(options.is_passthrough());
+    }
+
+    #[test]
+    fn test_matte_variant() {
+        let black = Background([0, 0, 0]);
+        let options = CompositeOptions::new().with_matte(black);
+        assert_eq!(options.cache_variant(), "matte000000");
+        assert!(options.is_passthrough());
+
+        // White is the default, and formats with alpha ignore the matte
+        let options = CompositeOptions::new().with_matte(DEFAULT_MATTE);
+        assert_eq!(options.cache_variant(), "");
+        let options = options.with_matte(black).with_format(OutputFormat::Png);
+        assert_eq!(options.cache_variant(), "png");
+    }
+
+    #[test]
+    fn test_target_size() {
+        let options = CompositeOptions::new().with_max_size(Some(200), None);
+        assert_eq!(options.target_size(400, 600), Some((200, 300)));
+        assert_eq!(options.target_size(100, 600), None);
+
+        let options = CompositeOptions::new().with_max_size(Some(200), Some(200));
+        assert_eq!(options.target_size(400, 600), Some((133, 200)));
+    }
+
+    #[test]
+    fn test_parse_background_and_filter() {
+        assert_eq!(
+            "#FF8000".parse::<Background>(),
+            Ok(Background([255, 128, 0]))
+        );
+        assert_eq!(Background([255, 128, 0]).to_string(), "#ff8000");
+        assert!("#fff".parse::<Background>().is_err());
+        assert!("zzzzzz".parse::<Background>().is_err());
+
+        assert_eq!("Catmull-Rom".parse(), Ok(ResizeFilter::CatmullRom));
+        assert!("cubic".parse::<ResizeFilter>().is_err());
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let options = CompositeOptions::new()
+            .with_format(OutputFormat::WebP)
+            .with_max_size(Some(400), None)
+            .with_filter(Filter::Sepia)
+            .with_plate("base-model-tall");
+        let json = serde_json::to_string(&options).unwrap();
+        assert!(!json.contains("quality"));
+        assert_eq!(
+            serde_json::from_str::<CompositeOptions>(&json).unwrap(),
+            options
+        );
+
+        let options: CompositeOptions = serde_json::from_str(r#"{"quality": 70}"#).unwrap();
+        assert_eq!(options, CompositeOptions::new().with_quality(70));
+        assert!(serde_json::from_str::<CompositeOptions>(r#"{"qualty": 70}"#).is_err());
+    }
+}
@@ -0,0 +1,353 @@
+//! Per-layer rotations and flips, for assets photographed upside down or
+//! reused mirrored in another view
+//!
+//! ```toml
+//! [[orientations]]
+//! category = "patches-*"
+//! sku = "*-inverted"
+//! rotate = 180
+//!
+//! [[orientations]]
+//! category = "hoodies"
+//! views = ["right"]
+//! flip = "horizontal"
+//! ```
+//!
+//! The first entry matching a layer orients it. A catalog placement can orient
+//! the patch it places (`rotate` and `flip` next to `x` and `y`), winning over
+//! the entries. Layers are turned clockwise, then flipped, before they are
+//! masked and blended. A full-frame layer keeps its canvas and turns about its
+//! center, so a quarter turn of a non-square layer crops what ends up outside
+//! it; a placed layer turns with its own size, the placement's `width`
+//! applying to the turned layer.
+
+use std::fmt;
+use std::sync::Arc;
+
+use image::{DynamicImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::graph::Placement;
+use crate::models::{LayerParam, View};
+use crate::rules::{any, is_any, matches_layer};
+use crate::tables::Tables;
+
+/// Clockwise turn of a layer, in degrees
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum Rotation {
+    #[default]
+    None,
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+impl Rotation {
+    pub fn degrees(&self) -> u16 {
+        match self {
+            Rotation::None => 0,
+            Rotation::Quarter => 90,
+            Rotation::Half => 180,
+            Rotation::ThreeQuarters => 270,
+        }
+    }
+}
+
+impl TryFrom<u16> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u16) -> Result<Self, Self::Error> {
+        match degrees {
+            0 | 360 => Ok(Rotation::None),
+            90 => Ok(Rotation::Quarter),
+            180 => Ok(Rotation::Half),
+            270 => Ok(Rotation::ThreeQuarters),
+            _ => Err(format!(
+                "Invalid rotation: {}. Must be one of: 0, 90, 180, 270",
+                degrees
+            )),
+        }
+    }
+}
+
+impl From<Rotation> for u16 {
+    fn from(rotation: Rotation) -> Self {
+        rotation.degrees()
+    }
+}
+
+/// Mirror axis of a layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Flip {
+    /// Left to right
+    Horizontal,
+    /// Top to bottom
+    Vertical,
+}
+
+/// How a layer is turned and flipped before it is blended
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Orientation {
+    #[serde(default, skip_serializing_if = "is_upright")]
+    pub rotate: Rotation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flip: Option<Flip>,
+}
+
+fn is_upright(rotation: &Rotation) -> bool {
+    *rotation == Rotation::None
+}
+
+impl Orientation {
+    pub fn new(rotate: Rotation, flip: Option<Flip>) -> Self {
+        Self { rotate, flip }
+    }
+
+    /// Whether the layer is left as stored
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Turn and flip an image; a quarter turn swaps its width and height
+    pub fn apply(&self, image: DynamicImage) -> DynamicImage {
+        let image = match self.rotate {
+            Rotation::None => image,
+            Rotation::Quarter => image.rotate90(),
+            Rotation::Half => image.rotate180(),
+            Rotation::ThreeQuarters => image.rotate270(),
+        };
+        match self.flip {
+            None => image,
+            Some(Flip::Horizontal) => image.fliph(),
+            Some(Flip::Vertical) => image.flipv(),
+        }
+    }
+
+    /// Like [`Self::apply`], keeping the image's size: a quarter turn of a
+    /// non-square image turns it about its center, cropping what ends up
+    /// outside and leaving the rest transparent
+    pub fn apply_in_frame(&self, image: DynamicImage) -> DynamicImage {
+        let (width, height) = (image.width(), image.height());
+        let turned = self.apply(image);
+        if (turned.width(), turned.height()) == (width, height) {
+            return turned;
+        }
+
+        let mut frame = RgbaImage::new(width, height);
+        let x = (i64::from(width) - i64::from(turned.width())) / 2;
+        let y = (i64::from(height) - i64::from(turned.height())) / 2;
+        image::imageops::overlay(&mut frame, &turned.to_rgba8(), x, y);
+        DynamicImage::ImageRgba8(frame)
+    }
+}
+
+/// `r90h` for a quarter turn, then a horizontal flip
+impl fmt::Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.rotate != Rotation::None {
+            write!(f, "r{}", self.rotate.degrees())?;
+        }
+        match self.flip {
+            Some(Flip::Horizontal) => write!(f, "h"),
+            Some(Flip::Vertical) => write!(f, "v"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Orient layers matching a category and SKU pattern
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OrientationRule {
+    /// Category pattern, e.g. "hoodies" or "patches-*"
+    pub category: String,
+    /// SKU pattern (default: any SKU)
+    #[serde(default = "any", skip_serializing_if = "is_any")]
+    pub sku: String,
+    /// Only apply in these views (default: all views)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub views: Vec<View>,
+    #[serde(default, skip_serializing_if = "is_upright")]
+    pub rotate: Rotation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flip: Option<Flip>,
+}
+
+impl OrientationRule {
+    pub fn new(category: impl Into<String>, orientation: Orientation) -> Self {
+        Self {
+            category: category.into(),
+            sku: any(),
+            views: Vec::new(),
+            rotate: orientation.rotate,
+            flip: orientation.flip,
+        }
+    }
+
+    pub fn with_sku(mut self, sku: impl Into<String>) -> Self {
+        self.sku = sku.into();
+        self
+    }
+
+    pub fn with_views(mut self, views: &[View]) -> Self {
+        self.views = views.to_vec();
+        self
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        Orientation::new(self.rotate, self.flip)
+    }
+
+    pub fn matches(&self, param: &LayerParam, view: View) -> bool {
+        matches_layer(&self.category, &self.sku, &self.views, param, view)
+    }
+}
+
+/// Orientation rules, checked in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Orientations {
+    rules: Vec<OrientationRule>,
+}
+
+impl Orientations {
+    pub fn new(rules: &[OrientationRule]) -> Self {
+        Self {
+            rules: rules.to_vec(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Orientation of the first rule matching a layer, unless it leaves the
+    /// layer as stored
+    pub fn get(&self, param: &LayerParam, view: View) -> Option<Orientation> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(param, view))
+            .map(OrientationRule::orientation)
+            .filter(|orientation| !orientation.is_identity())
+    }
+
+    /// Orientation of each normalized layer, aligned with `normalized`
+    /// `placements` is aligned with `normalized` too; an oriented placement
+    /// wins over the rules
+    pub fn for_layers(
+        &self,
+        normalized: &[LayerParam],
+        placements: &[Option<Placement>],
+        view: View,
+    ) -> Vec<Option<Orientation>> {
+        normalized
+            .iter()
+            .enumerate()
+            .map(|(idx, layer)| {
+                let placed = placements.get(idx).copied().flatten();
+                match placed.map(|at| at.orientation()) {
+                    Some(orientation) if !orientation.is_identity() => Some(orientation),
+                    _ => self.get(layer, view),
+                }
+            })
+            .collect()
+    }
+
+    /// The current tables' orientations (none unless some were installed)
+    pub fn global() -> Arc<Orientations> {
+        Tables::current().orientations.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::parse_params;
+    use image::Rgba;
+
+    #[test]
+    fn test_for_layers() {
+        let rules: Vec<OrientationRule> = toml::from_str::<toml::Table>(
+            r#"
+            [[orientations]]
+            category = "patches-*"
+            sku = "*-inverted"
+            rotate = 180
+
+            [[orientations]]
+            category = "hoodies"
+            views = ["right"]
+            flip = "horizontal"
+            "#,
+        )
+        .unwrap()["orientations"]
+            .clone()
+            .try_into()
+            .unwrap();
+        let orientations = Orientations::new(&rules);
+
+        let layers = parse_params("hoodies/hoodie-black,patches-left/flag-inverted");
+        let half = Orientation::new(Rotation::Half, None);
+        let mirrored = Orientation::new(Rotation::None, Some(Flip::Horizontal));
+        assert_eq!(
+            orientations.for_layers(&layers, &[], View::Right),
+            vec![Some(mirrored), Some(half)]
+        );
+        assert_eq!(
+            orientations.for_layers(&layers, &[], View::Front),
+            vec![None, Some(half)]
+        );
+
+        // A placement's orientation wins
+        let placed = [
+            None,
+            Some(Placement::new(10, 10).with_rotation(Rotation::Quarter)),
+        ];
+        assert_eq!(
+            orientations.for_layers(&layers, &placed, View::Front)[1],
+            Some(Orientation::new(Rotation::Quarter, None))
+        );
+        let upright = [None, Some(Placement::new(10, 10))];
+        assert_eq!(
+            orientations.for_layers(&layers, &upright, View::Front)[1],
+            Some(half)
+        );
+
+        assert_eq!(half.to_string(), "r180");
+        assert_eq!(
+            Orientation::new(Rotation::Quarter, Some(Flip::Vertical)).to_string(),
+            "r90v"
+        );
+        assert!(serde_json::from_str::<Orientation>(r#"{"rotate": 45}"#).is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        // 3x2, red top-left corner
+        let mut image = RgbaImage::from_pixel(3, 2, Rgba([0, 0, 255, 255]));
+        image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        let image = DynamicImage::ImageRgba8(image);
+
+        let turned = Orientation::new(Rotation::Quarter, None)
+            .apply(image.clone())
+            .to_rgba8();
+        assert_eq!(turned.dimensions(), (2, 3));
+        assert_eq!(turned.get_pixel(1, 0).0, [255, 0, 0, 255]);
+
+        let flipped = Orientation::new(Rotation::None, Some(Flip::Horizontal))
+            .apply(image.clone())
+            .to_rgba8();
+        assert_eq!(flipped.get_pixel(2, 0).0, [255, 0, 0, 255]);
+
+        // In its frame, the turned image is centered and cropped
+        let framed = Orientation::new(Rotation::Quarter, None)
+            .apply_in_frame(image)
+            .to_rgba8();
+        assert_eq!(framed.dimensions(), (3, 2));
+        assert_eq!(framed.get_pixel(1, 0).0, [255, 0, 0, 255]);
+        assert_eq!(framed.get_pixel(2, 0).0[3], 0);
+    }
+}
@@ -0,0 +1,126 @@
+//! Layers stored far larger than the plates they're drawn on
+//!
+//! Every render resizes a layer to its plate, so a 10000px scan of a patch
+//! costs a full-size decode and resize on every request. A layer more than
+//! [`OVERSIZE_FACTOR`] times the plate's width or height is oversized: storage
+//! downscales it once with [`downscale`] and keeps the derivative, and reports
+//! the source so it can be fixed.
+
+use bytes::Bytes;
+use image::{ImageFormat, ImageReader};
+use std::fmt;
+use std::io::Cursor;
+
+use crate::compositor::decode_image;
+use crate::error::{BirlError, Result};
+use crate::options::ResizeFilter;
+
+/// How many times the plate's width or height a layer may be before it's oversized
+pub const OVERSIZE_FACTOR: u32 = 2;
+
+/// An oversized layer and the plate it's drawn on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Oversize {
+    pub width: u32,
+    pub height: u32,
+    pub plate_width: u32,
+    pub plate_height: u32,
+}
+
+impl Oversize {
+    /// Check an encoded layer's size from its header against the plate's
+    /// Layers whose header can't be read are left alone
+    pub fn check(data: &[u8], plate_width: u32, plate_height: u32) -> Option<Self> {
+        let (width, height) = dimensions(data)?;
+        let oversized = u64::from(width) > u64::from(plate_width) * u64::from(OVERSIZE_FACTOR)
+            || u64::from(height) > u64::from(plate_height) * u64::from(OVERSIZE_FACTOR);
+
+        oversized.then_some(Self {
+            width,
+            height,
+            plate_width,
+            plate_height,
+        })
+    }
+
+    /// Size the layer is downscaled to: the largest that fits the plate and
+    /// keeps the aspect ratio, snapped to the plate's size when within a pixel
+    /// so full-frame layers need no resize at render time
+    pub fn target_size(&self) -> (u32, u32) {
+        let scale = f64::min(
+            f64::from(self.plate_width) / f64::from(self.width),
+            f64::from(self.plate_height) / f64::from(self.height),
+        );
+        let width = ((f64::from(self.width) * scale).round() as u32).max(1);
+        let height = ((f64::from(self.height) * scale).round() as u32).max(1);
+
+        if self.plate_width.abs_diff(width) <= 1 && self.plate_height.abs_diff(height) <= 1 {
+            (self.plate_width, self.plate_height)
+        } else {
+            (width, height)
+        }
+    }
+}
+
+impl fmt::Display for Oversize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{} on a {}x{} plate",
+            self.width, self.height, self.plate_width, self.plate_height
+        )
+    }
+}
+
+/// Width and height of an encoded image, read from its header
+pub fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+/// Resize an encoded layer to `width` x `height`, as PNG to keep its alpha
+pub fn downscale(data: &[u8], width: u32, height: u32, filter: ResizeFilter) -> Result<Bytes> {
+    let layer = decode_image(data).map_err(|e| BirlError::decode("layer image", e))?;
+    let resized = layer.resize_exact(width, height, filter.filter_type());
+
+    let mut buffer = Vec::new();
+    resized
+        .write_to(&mut Cursor::new(&mut buffer), ImageFormat::Png)
+        .map_err(|e| BirlError::encode("PNG", e))?;
+    Ok(Bytes::from(buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::{synthetic_layer, DEMO_HEIGHT, DEMO_WIDTH};
+
+    #[test]
+    fn test_oversize() {
+        let layer = synthetic_layer("hoodies", "hoodie-black").unwrap();
+        assert_eq!(Oversize::check(&layer, DEMO_WIDTH, DEMO_HEIGHT), None);
+        assert_eq!(Oversize::check(&layer, 200, 300), None);
+        assert_eq!(Oversize::check(b"not an image", 10, 10), None);
+
+        let oversize = Oversize::check(&layer, 100, 150).unwrap();
+        assert_eq!((oversize.width, oversize.height), (DEMO_WIDTH, DEMO_HEIGHT));
+        assert_eq!(oversize.to_string(), "400x600 on a 100x150 plate");
+        assert_eq!(oversize.target_size(), (100, 150));
+
+        // Off by a pixel from the plate's aspect ratio snaps to the plate
+        let oversize = Oversize::check(&layer, 101, 150).unwrap();
+        assert_eq!(oversize.target_size(), (101, 150));
+        // A different aspect ratio is kept
+        let oversize = Oversize::check(&layer, 150, 150).unwrap();
+        assert_eq!(oversize.target_size(), (100, 150));
+
+        let (width, height) = oversize.target_size();
+        let small = downscale(&layer, width, height, ResizeFilter::default()).unwrap();
+        let image = decode_image(&small).unwrap();
+        assert_eq!((image.width(), image.height()), (100, 150));
+        assert!(image.color().has_alpha());
+    }
+}
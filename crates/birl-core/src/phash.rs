@@ -0,0 +1,89 @@
+use crate::error::{BirlError, Result};
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+/// Hamming distance at or below which two composites look the same
+pub const SIMILARITY_THRESHOLD: u32 = 5;
+
+/// 64-bit difference hash (dHash) of an image
+/// Each bit records whether a pixel is brighter than its right neighbour
+/// in a 9x8 grayscale thumbnail, so re-encoding and small shifts barely change it
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let thumb = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = thumb.get_pixel(x, y).0[0];
+            let right = thumb.get_pixel(x + 1, y).0[0];
+            hash = (hash << 1) | u64::from(left > right);
+        }
+    }
+
+    hash
+}
+
+/// dHash of an encoded image
+pub fn dhash_bytes(data: &[u8]) -> Result<u64> {
+    let image =
+        image::load_from_memory(data).map_err(|e| BirlError::decode("image for hashing", e))?;
+    Ok(dhash(&image))
+}
+
+/// Number of differing bits between two hashes
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Check if two hashes are perceptually similar
+pub fn is_similar(a: u64, b: u64) -> bool {
+    hamming_distance(a, b) <= SIMILARITY_THRESHOLD
+}
+
+/// Format a hash as 16 hex digits
+pub fn format_hash(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgb, RgbImage};
+    use std::io::Cursor;
+
+    fn gradient(width: u32, height: u32, reverse: bool) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, _| {
+            let v = (x * 255 / width) as u8;
+            let v = if reverse { 255 - v } else { v };
+            Rgb([v, v, v])
+        }))
+    }
+
+    #[test]
+    fn test_dhash_stable_across_encoding() {
+        let image = gradient(200, 100, false);
+
+        let mut jpeg = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+
+        let original = dhash(&image);
+        let reencoded = dhash_bytes(&jpeg).unwrap();
+        assert!(is_similar(original, reencoded));
+    }
+
+    #[test]
+    fn test_dhash_detects_different_images() {
+        let a = dhash(&gradient(200, 100, false));
+        let b = dhash(&gradient(200, 100, true));
+        assert!(!is_similar(a, b));
+    }
+
+    #[test]
+    fn test_hamming_distance() {
+        assert_eq!(hamming_distance(0, 0), 0);
+        assert_eq!(hamming_distance(0b1011, 0b0001), 2);
+        assert_eq!(format_hash(0xab), "00000000000000ab");
+    }
+}
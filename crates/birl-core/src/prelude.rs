@@ -0,0 +1,27 @@
+//! The types most embedders need, in one import
+//!
+//! ```
+//! use birl_core::prelude::*;
+//!
+//! let params = parse_params("hoodies/hoodie-black,pants/cargo-black");
+//! let layers = LayerNormalizer::new(View::Front, &params).normalize_all(&params);
+//! let options = CompositeOptions::new().with_format(OutputFormat::WebP);
+//! # assert_eq!(layers.len(), 2);
+//! # assert_eq!(options.format, OutputFormat::WebP);
+//! ```
+//!
+//! Items leave the prelude only in a breaking release. `Result` is left out so
+//! it doesn't shadow `anyhow::Result` or the standard one; it's
+//! [`crate::error::Result`].
+
+pub use crate::catalog::Catalog;
+pub use crate::compositor::Compositor;
+pub use crate::error::BirlError;
+pub use crate::filters::Filter;
+pub use crate::format::OutputFormat;
+pub use crate::graph::{Asset, Recipe};
+pub use crate::layers::{parse_params, LayerNormalizer, PLATE_CATEGORY};
+pub use crate::models::{LayerParam, Sku, View};
+pub use crate::options::{Background, CompositeOptions, ResizeFilter};
+pub use crate::registry::CategoryRegistry;
+pub use crate::render::{render, Assets, RenderedImage};
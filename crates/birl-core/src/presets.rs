@@ -0,0 +1,283 @@
+//! Named outfits, e.g. `winter-tactical`
+//!
+//! ```json
+//! {
+//!   "presets": {
+//!     "winter-tactical": {
+//!       "description": "Softshell over hoodie with beanie and gloves",
+//!       "params": "hoodies/hoodie-black,jackets/softshell-grey,hats/beanie-black,gloves/leather-gloves-black"
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! A few presets are built in; the catalog manifest adds its own under
+//! `presets`, and a presets file (same layout, JSON or TOML) adds more, each
+//! replacing earlier ones with the same name. Requests and the CLI name a
+//! preset instead of spelling out its params.
+
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::catalog::Catalog;
+use crate::error::{BirlError, Result};
+use crate::layers::{parse_params, parse_params_strict};
+use crate::manifest::{self, ManifestFormat};
+use crate::models::LayerParam;
+use crate::tables::Tables;
+
+/// Key of the presets file in the storage JSON cache
+pub const PRESETS_KEY: &str = "presets";
+
+/// Presets every deployment has, for trying out renders
+const BUILTIN: &[(&str, &str, &str)] = &[
+    (
+        "basic",
+        "Single black hoodie on front view",
+        "hoodies/hoodie-black",
+    ),
+    (
+        "full-outfit",
+        "Complete outfit: hoodie, pants, and beanie",
+        "hoodies/hoodie-black,pants/cargo-darkgreen,hats/beanie-black",
+    ),
+    (
+        "with-patches",
+        "Hoodie with American flag patch on left",
+        "hoodies/hoodie-black,patches-left/flag-patch-red",
+    ),
+    (
+        "jacket-outfit",
+        "Jacket over hoodie with pants",
+        "hoodies/hoodie-black,jackets/softshell-grey,pants/cargo-black",
+    ),
+    (
+        "gloves-hat",
+        "Full winter outfit with gloves and hat",
+        "hoodies/hoodie-black,pants/cargo-black,hats/beanie-black,gloves/leather-gloves-black",
+    ),
+    (
+        "outer-jacket",
+        "Greenland outer jacket over hoodie",
+        "hoodies/hoodie-black,jackets/greenland-black,pants/cargo-darkgreen",
+    ),
+    (
+        "accessories",
+        "Jacket outfit with boots, belt, scarf, and backpack",
+        "pants/cargo-black,boots/hiking-boots-tan,tops/tee-white,belts/belt-black,jackets/softshell-grey,scarves/scarf-red,backpacks/daypack-black",
+    ),
+];
+
+/// A named outfit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    /// "category/sku,..." as in a request
+    #[serde(deserialize_with = "layer_params")]
+    pub params: String,
+}
+
+/// Params of a preset file, each of which must parse
+fn layer_params<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<String, D::Error> {
+    let params = String::deserialize(deserializer)?;
+    match parse_params_strict(&params) {
+        Ok(layers) if layers.is_empty() => Err(de::Error::custom("preset without layers")),
+        Ok(_) => Ok(params),
+        Err(e) => Err(de::Error::custom(e)),
+    }
+}
+
+impl Preset {
+    pub fn new(description: impl Into<String>, params: impl Into<String>) -> Self {
+        Self {
+            description: description.into(),
+            params: params.into(),
+        }
+    }
+
+    pub fn to_params(&self) -> Vec<LayerParam> {
+        parse_params(&self.params)
+    }
+}
+
+/// Presets by name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Presets {
+    presets: BTreeMap<String, Preset>,
+}
+
+/// Contents of a presets file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PresetsFile {
+    #[serde(default)]
+    presets: BTreeMap<String, Preset>,
+}
+
+impl Presets {
+    /// The built-in presets
+    pub fn builtin() -> Self {
+        let presets = BUILTIN
+            .iter()
+            .map(|&(name, description, params)| {
+                (name.to_string(), Preset::new(description, params))
+            })
+            .collect();
+        Self { presets }
+    }
+
+    /// The built-in presets and a catalog's, the catalog's winning
+    pub fn for_catalog(catalog: Option<&Catalog>) -> Self {
+        let presets = Self::builtin();
+        match catalog {
+            Some(catalog) => presets.with_presets(&catalog.presets),
+            None => presets,
+        }
+    }
+
+    /// Presets of a presets file, without the built-in ones
+    pub fn from_json(json: &str) -> Result<Self> {
+        let file: PresetsFile = manifest::parse("presets JSON", json, ManifestFormat::Json)?;
+        Ok(Self {
+            presets: file.presets,
+        })
+    }
+
+    /// Load a presets file, TOML if the extension says so, JSON otherwise
+    pub fn load(path: &Path) -> Result<Self> {
+        let file: PresetsFile = manifest::load("presets", path)?;
+        Ok(Self {
+            presets: file.presets,
+        })
+    }
+
+    /// Add presets, replacing existing ones with the same name
+    pub fn with_presets(mut self, presets: &BTreeMap<String, Preset>) -> Self {
+        for (name, preset) in presets {
+            self.presets.insert(name.clone(), preset.clone());
+        }
+        self
+    }
+
+    /// Add another set's presets, e.g. a presets file's, replacing existing
+    /// ones with the same name
+    pub fn merge(self, other: &Presets) -> Self {
+        self.with_presets(&other.presets)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Preset> {
+        self.presets.get(name)
+    }
+
+    /// Layers of a preset; an unknown name is an invalid param
+    pub fn params(&self, name: &str) -> Result<Vec<LayerParam>> {
+        match self.get(name) {
+            Some(preset) => Ok(preset.to_params()),
+            None => Err(BirlError::InvalidParam(format!(
+                "Unknown preset '{}'",
+                name
+            ))),
+        }
+    }
+
+    /// Presets in name order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Preset)> {
+        self.presets
+            .iter()
+            .map(|(name, preset)| (name.as_str(), preset))
+    }
+
+    pub fn len(&self) -> usize {
+        self.presets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.presets.is_empty()
+    }
+
+    /// The current tables' presets (the built-in ones unless others were installed)
+    pub fn global() -> Arc<Presets> {
+        Tables::current().presets.clone()
+    }
+
+    /// Replace the process-wide presets, e.g. after the presets file changed
+    /// Renders already resolving a preset keep the ones they started with
+    pub fn reload(self) {
+        Tables::update(|tables| tables.presets = Arc::new(self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        let catalog = Catalog::from_json(
+            r#"{"presets": {
+                "winter-tactical": {"params": "jackets/softshell-grey,hats/beanie-black"},
+                "basic": {"description": "Grey hoodie", "params": "hoodies/hoodie-grey"}
+            }}"#,
+        )
+        .unwrap();
+        let presets = Presets::for_catalog(Some(&catalog));
+        assert_eq!(presets.len(), Presets::builtin().len() + 1);
+
+        let params = presets.params("winter-tactical").unwrap();
+        assert_eq!(
+            params,
+            [
+                LayerParam::new("jackets", "softshell-grey"),
+                LayerParam::new("hats", "beanie-black")
+            ]
+        );
+        // Catalog presets replace built-in ones
+        assert_eq!(presets.get("basic").unwrap().description, "Grey hoodie");
+        assert_eq!(
+            Presets::for_catalog(None).params("basic").unwrap(),
+            [LayerParam::new("hoodies", "hoodie-black")]
+        );
+        assert!(matches!(
+            presets.params("summer"),
+            Err(BirlError::InvalidParam(_))
+        ));
+    }
+
+    #[test]
+    fn test_presets_file() {
+        let file = Presets::from_json(
+            r#"{"presets": {
+                "summer": {"description": "Tee and cargos", "params": "tops/tee-white,pants/cargo-black"},
+                "basic": {"params": "hoodies/hoodie-grey"}
+            }}"#,
+        )
+        .unwrap();
+        assert_eq!(file.len(), 2);
+
+        // The file's presets win over the built-in ones
+        let presets = Presets::builtin().merge(&file);
+        assert_eq!(presets.len(), Presets::builtin().len() + 1);
+        assert_eq!(
+            presets.params("basic").unwrap(),
+            [LayerParam::new("hoodies", "hoodie-grey")]
+        );
+        assert_eq!(presets.get("summer").unwrap().description, "Tee and cargos");
+
+        assert!(Presets::from_json(r#"{"presets": {"basic": {}}}"#).is_err());
+        assert!(Presets::from_json(r#"{"examples": {}}"#).is_err());
+
+        // Every layer of a preset must parse, and the error points at it
+        let err = Presets::from_json("{\"presets\": {\n  \"summer\": {\"params\": \"tops\"}\n}}")
+            .unwrap_err();
+        let err = std::error::Error::source(&err).unwrap().to_string();
+        assert!(err.starts_with("presets.summer.params: "));
+        assert!(err.contains("\n  2 |   \"summer\""));
+        assert!(Presets::from_json(r#"{"presets": {"empty": {"params": ","}}}"#).is_err());
+    }
+}
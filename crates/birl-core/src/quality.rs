@@ -0,0 +1,127 @@
+//! Encoder quality chosen from the size of the output
+//!
+//! Compression artifacts show most on thumbnails, while bytes matter most on
+//! large images. A quality curve maps the longest side of the output to an
+//! encoder quality, interpolating between its points and holding the end
+//! values beyond them. Lossy formats (JPEG, AVIF) use it when a render doesn't
+//! ask for a quality:
+//!
+//! ```toml
+//! quality_curve = [[320, 92], [1600, 80], [4000, 68]]
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::error::{BirlError, Result};
+use crate::tables::Tables;
+
+/// Points of (longest side in pixels, quality), by side
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<(u32, u8)>", into = "Vec<(u32, u8)>")]
+pub struct QualityCurve {
+    points: Vec<(u32, u8)>,
+}
+
+impl QualityCurve {
+    /// Qualities must be 1-100, and each side can appear once
+    pub fn new(mut points: Vec<(u32, u8)>) -> Result<Self> {
+        points.sort_by_key(|(side, _)| *side);
+        if let Some((_, quality)) = points.iter().find(|(_, q)| !(1..=100).contains(q)) {
+            return Err(BirlError::InvalidParam(format!(
+                "Invalid quality {} in quality curve. Must be 1-100",
+                quality
+            )));
+        }
+        if let Some(pair) = points.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+            return Err(BirlError::InvalidParam(format!(
+                "Quality curve has several points for {}px",
+                pair[0].0
+            )));
+        }
+        Ok(Self { points })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn points(&self) -> &[(u32, u8)] {
+        &self.points
+    }
+
+    /// Quality for an output of this size, or `None` for an empty curve
+    pub fn quality(&self, width: u32, height: u32) -> Option<u8> {
+        let side = width.max(height);
+        let upper = self.points.iter().position(|(s, _)| *s >= side);
+        match upper {
+            None => self.points.last().map(|(_, quality)| *quality),
+            Some(0) => Some(self.points[0].1),
+            Some(idx) => {
+                let (s0, q0) = self.points[idx - 1];
+                let (s1, q1) = self.points[idx];
+                let t = f64::from(side - s0) / f64::from(s1 - s0);
+                Some((f64::from(q0) + t * (f64::from(q1) - f64::from(q0))).round() as u8)
+            }
+        }
+    }
+
+    /// Cache key variant for composites encoded with the curve
+    /// Empty for an empty curve, so renders keep their key until one is set
+    pub fn cache_variant(&self) -> String {
+        if self.points.is_empty() {
+            return String::new();
+        }
+        let points: Vec<String> = self
+            .points
+            .iter()
+            .map(|(side, quality)| format!("{}-{}", side, quality))
+            .collect();
+        format!("qc{}", points.join("-"))
+    }
+
+    /// The current tables' curve (empty unless one was installed)
+    pub fn global() -> Arc<QualityCurve> {
+        Tables::current().quality.clone()
+    }
+}
+
+impl TryFrom<Vec<(u32, u8)>> for QualityCurve {
+    type Error = BirlError;
+
+    fn try_from(points: Vec<(u32, u8)>) -> Result<Self> {
+        Self::new(points)
+    }
+}
+
+impl From<QualityCurve> for Vec<(u32, u8)> {
+    fn from(curve: QualityCurve) -> Self {
+        curve.points
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quality_curve() {
+        let curve = QualityCurve::new(vec![(1600, 80), (320, 92), (4000, 68)]).unwrap();
+        assert_eq!(curve.quality(200, 150), Some(92));
+        assert_eq!(curve.quality(240, 320), Some(92));
+        assert_eq!(curve.quality(960, 640), Some(86));
+        assert_eq!(curve.quality(1600, 1200), Some(80));
+        assert_eq!(curve.quality(8000, 6000), Some(68));
+        assert_eq!(curve.cache_variant(), "qc320-92-1600-80-4000-68");
+
+        assert_eq!(QualityCurve::default().quality(100, 100), None);
+        assert_eq!(QualityCurve::default().cache_variant(), "");
+
+        assert!(QualityCurve::new(vec![(320, 0)]).is_err());
+        assert!(QualityCurve::new(vec![(320, 90), (320, 80)]).is_err());
+
+        let curve: QualityCurve = serde_json::from_str("[[320, 92], [1600, 80]]").unwrap();
+        assert_eq!(curve.points(), [(320, 92), (1600, 80)]);
+        assert!(serde_json::from_str::<QualityCurve>("[[320, 101]]").is_err());
+    }
+}
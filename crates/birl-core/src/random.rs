@@ -0,0 +1,140 @@
+//! Seeded random outfits
+//!
+//! [`generate_random_outfit`] picks a plate and a few catalogued layers for a
+//! view, the same ones for the same seed, catalog, and config. Every outfit it
+//! returns renders without dropped layers, conflicts, constraint violations, or
+//! catalog errors, so it serves a "surprise me" button as well as fuzzing of
+//! the composition pipeline.
+
+use crate::catalog::Catalog;
+use crate::layers::{ConflictPolicy, LayerNormalizer, PLATE_CATEGORY};
+use crate::models::{LayerParam, View};
+
+/// SplitMix64: small, and stable across releases unlike `rand`'s generators
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Index below `len`, which must not be 0
+    pub(crate) fn below(&mut self, len: usize) -> usize {
+        (self.next() % len as u64) as usize
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for idx in (1..items.len()).rev() {
+            items.swap(idx, self.below(idx + 1));
+        }
+    }
+}
+
+/// A valid outfit for `view`, chosen by `seed`
+/// Starts with a `plate/<sku>` param when the catalog lists plates for the
+/// view. Each category is tried at most once, in random order; layers that
+/// would break the outfit are skipped. Empty only when no catalogued layer can
+/// be rendered in the view.
+pub fn generate_random_outfit(catalog: &Catalog, view: View, seed: u64) -> Vec<LayerParam> {
+    let mut rng = SplitMix64(seed);
+
+    let plates = catalog.plates(view);
+    let plate = (!plates.is_empty()).then(|| plates[rng.below(plates.len())].as_str());
+    let mut outfit: Vec<LayerParam> = plate
+        .map(|plate| LayerParam::new(PLATE_CATEGORY, plate))
+        .into_iter()
+        .collect();
+    let plate = plate.unwrap_or(view.plate_value());
+
+    let mut categories: Vec<&str> = catalog.categories().collect();
+    rng.shuffle(&mut categories);
+    for category in categories {
+        let Some(entry) = catalog.category(category) else {
+            continue;
+        };
+        let skus: Vec<_> = entry
+            .skus
+            .iter()
+            .filter(|sku| entry.views_of(sku).contains(&view))
+            .collect();
+        // Each category joins half the time once the outfit has a layer
+        if skus.is_empty()
+            || (outfit.iter().any(|p| p.category != PLATE_CATEGORY) && rng.next() & 1 == 0)
+        {
+            continue;
+        }
+
+        outfit.push(LayerParam::new(category, skus[rng.below(skus.len())].sku()));
+        if !is_valid(catalog, &outfit, view, plate) {
+            outfit.pop();
+        }
+    }
+
+    outfit
+}
+
+/// Whether every param is rendered as requested and exists in the catalog
+fn is_valid(catalog: &Catalog, params: &[LayerParam], view: View, plate: &str) -> bool {
+    let normalized = LayerNormalizer::new(view, params)
+        .with_conflict_policy(ConflictPolicy::Error)
+        .resolve(params);
+    match normalized {
+        Ok(normalized) => {
+            normalized.dropped.is_empty()
+                && normalized.violations.is_empty()
+                && catalog.validate(&normalized.layers, view, plate).is_ok()
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CATALOG: &str = r#"{
+        "plates": {"front": ["base-model-black", "base-model-tall"], "left": ["patch-plate"]},
+        "categories": {
+            "hoodies": {"skus": ["hoodie-black", "hoodie-grey", "zip-hood-black"]},
+            "pants": {"views": ["front", "back", "side"], "skus": ["cargo-black", "jeans-blue"]},
+            "hats": {"skus": ["beanie-black", {"sku": "cap-red", "views": ["back"]}]},
+            "gloves-top": {"skus": ["ski-black"]},
+            "patches-left": {"skus": ["flag-patch-red"]}
+        }
+    }"#;
+
+    #[test]
+    fn test_random_outfit() {
+        let catalog = Catalog::from_json(CATALOG).unwrap();
+
+        let outfit = generate_random_outfit(&catalog, View::Front, 7);
+        assert_eq!(outfit, generate_random_outfit(&catalog, View::Front, 7));
+        assert_eq!(outfit[0].category, PLATE_CATEGORY);
+
+        let mut distinct = Vec::new();
+        for seed in 0..50 {
+            let outfit = generate_random_outfit(&catalog, View::Front, seed);
+            assert!(outfit.len() > 1, "seed {} gave no layers", seed);
+            let plate = outfit[0].sku.as_str();
+            assert!(is_valid(&catalog, &outfit, View::Front, plate));
+            assert!(!outfit.iter().any(|p| p.sku.as_str() == "cap-red"));
+            if !distinct.contains(&outfit) {
+                distinct.push(outfit);
+            }
+        }
+        assert!(distinct.len() > 10);
+
+        // Pants aren't drawn on the left view
+        for seed in 0..20 {
+            let outfit = generate_random_outfit(&catalog, View::Left, seed);
+            assert_eq!(outfit[0], LayerParam::new(PLATE_CATEGORY, "patch-plate"));
+            assert!(!outfit.iter().any(|p| p.category == "pants"));
+        }
+
+        assert!(generate_random_outfit(&Catalog::default(), View::Front, 1).is_empty());
+    }
+}
@@ -0,0 +1,369 @@
+//! Layer categories and their metadata
+//!
+//! The built-in categories cover the current catalog. Deployments add or
+//! override categories at startup (see `[categories]` in the config file),
+//! so new garment types don't need a release:
+//!
+//! ```toml
+//! [categories.vests]
+//! z_order = 45
+//! views = ["front", "back", "side"]
+//! patch_garment = true
+//!
+//! [categories.backpack-straps]
+//! z_order = 5
+//! underlay = "torso"
+//! ```
+//!
+//! Underlay categories are composited beneath the plate's garment: their
+//! layers are blended onto the plate first, then the plate is blended back on
+//! top through the view's `masks/{underlay}` mask.
+//!
+//! The registry also says which image format each category is stored in,
+//! plates and masks included, so storage keys and asset checks never assume
+//! an extension (see `[formats]` in the config file):
+//!
+//! ```toml
+//! [formats]
+//! plate = "jpeg"
+//! hats = "webp"
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::error::{BirlError, Result};
+use crate::format::OutputFormat;
+use crate::graph::MASKS_CATEGORY;
+use crate::layers::PLATE_CATEGORY;
+use crate::models::View;
+use crate::tables::Tables;
+use crate::views;
+
+/// Extension of layer files when a category doesn't set one
+pub const DEFAULT_LAYER_EXTENSION: &str = "png";
+
+/// Extension of plate files unless configured otherwise
+pub const DEFAULT_PLATE_EXTENSION: &str = "jpg";
+
+/// Views a category is rendered in when it doesn't list them
+const DEFAULT_VIEWS: [View; 3] = [View::Front, View::Back, View::Side];
+
+/// Metadata for one layer category
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Category {
+    /// Stacking position, lowest first; categories without one sort below all others
+    pub z_order: Option<u32>,
+    /// Views the category is rendered in
+    #[serde(default = "default_views")]
+    pub views: Vec<View>,
+    /// Extension of the layer files in storage
+    #[serde(default = "default_extension")]
+    pub extension: String,
+    /// Placed on top of a garment and clipped to its mask
+    #[serde(default)]
+    pub patch: bool,
+    /// Garment whose mask clips the patches above it
+    #[serde(default)]
+    pub patch_garment: bool,
+    /// Mask of the plate regions drawn over this category's layers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub underlay: Option<String>,
+}
+
+fn default_views() -> Vec<View> {
+    DEFAULT_VIEWS.to_vec()
+}
+
+fn default_extension() -> String {
+    DEFAULT_LAYER_EXTENSION.to_string()
+}
+
+impl Category {
+    pub fn new(z_order: u32) -> Self {
+        Self {
+            z_order: Some(z_order),
+            views: default_views(),
+            extension: default_extension(),
+            patch: false,
+            patch_garment: false,
+            underlay: None,
+        }
+    }
+
+    pub fn with_views(mut self, views: &[View]) -> Self {
+        self.views = views.to_vec();
+        self
+    }
+
+    pub fn with_extension(mut self, extension: impl Into<String>) -> Self {
+        self.extension = extension.into();
+        self
+    }
+
+    pub fn as_patch(mut self) -> Self {
+        self.patch = true;
+        self
+    }
+
+    pub fn as_patch_garment(mut self) -> Self {
+        self.patch_garment = true;
+        self
+    }
+
+    /// Composite beneath the plate regions covered by `mask`
+    pub fn as_underlay(mut self, mask: impl Into<String>) -> Self {
+        self.underlay = Some(mask.into());
+        self
+    }
+}
+
+/// Layer categories by name
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoryRegistry {
+    categories: BTreeMap<String, Category>,
+    /// Extension of `plate/` files
+    plate_extension: String,
+    /// Extension of `masks/` files
+    mask_extension: String,
+}
+
+impl Default for CategoryRegistry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl CategoryRegistry {
+    /// Registry with no categories
+    pub fn empty() -> Self {
+        Self {
+            categories: BTreeMap::new(),
+            plate_extension: DEFAULT_PLATE_EXTENSION.to_string(),
+            mask_extension: DEFAULT_LAYER_EXTENSION.to_string(),
+        }
+    }
+
+    /// The built-in catalog categories
+    /// Garments are spaced by 10 and accessories sit at the 5s between them,
+    /// so new categories can still go in between
+    pub fn builtin() -> Self {
+        use View::*;
+        let all = [Front, Back, Side, Left, Right];
+
+        let mut registry = Self::empty();
+        registry
+            .insert("boots", Category::new(5))
+            .insert("pants", Category::new(10))
+            .insert("tops", Category::new(20).as_patch_garment())
+            .insert("belts", Category::new(25))
+            .insert(
+                "hoodies",
+                Category::new(30).with_views(&all).as_patch_garment(),
+            )
+            .insert("gloves-bottom", Category::new(40))
+            .insert(
+                "jackets",
+                Category::new(50).with_views(&all).as_patch_garment(),
+            )
+            .insert("gloves-top", Category::new(60))
+            .insert("outer-jackets", Category::new(70).as_patch_garment())
+            .insert("scarves", Category::new(75))
+            .insert("hats", Category::new(80))
+            .insert("backpacks", Category::new(85))
+            .insert("patches", Category::new(90).as_patch())
+            .insert(
+                "patches-left",
+                Category::new(100)
+                    .with_views(&[Front, Side, Left])
+                    .as_patch(),
+            )
+            .insert(
+                "patches-right",
+                Category::new(110)
+                    .with_views(&[Front, Side, Right])
+                    .as_patch(),
+            )
+            .insert("softshell-patches", Category::new(120).as_patch())
+            .insert("softshell-patches-left", Category::new(130).as_patch())
+            .insert("softshell-patches-right", Category::new(140).as_patch());
+        registry
+    }
+
+    /// Built-in categories with `overrides` added or replacing them
+    pub fn with_overrides(overrides: &BTreeMap<String, Category>) -> Self {
+        let mut registry = Self::builtin();
+        for (name, category) in overrides {
+            registry.insert(name.clone(), category.clone());
+        }
+        registry
+    }
+
+    /// Add or replace a category
+    pub fn insert(&mut self, name: impl Into<String>, category: Category) -> &mut Self {
+        self.categories.insert(name.into(), category);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Category> {
+        self.categories.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.categories.keys().map(String::as_str)
+    }
+
+    /// Z-order of a category (`None` for unknown categories)
+    pub fn z_order(&self, name: &str) -> Option<u32> {
+        self.get(name).and_then(|category| category.z_order)
+    }
+
+    /// Whether a category is rendered in a view
+    /// Unknown categories follow the default views
+    /// Configured views also render the categories their spec lists (for
+    /// registered views, every category unless the spec narrows them)
+    pub fn allows(&self, name: &str, view: View) -> bool {
+        let listed = views::spec(view.as_str()).and_then(|spec| spec.lists(name));
+        if listed.unwrap_or(matches!(view, View::Custom(_))) {
+            return true;
+        }
+        match self.get(name) {
+            Some(category) => category.views.contains(&view),
+            None => DEFAULT_VIEWS.contains(&view),
+        }
+    }
+
+    /// File extension of a category's layers, or of plates and masks
+    pub fn extension(&self, name: &str) -> &str {
+        match name {
+            PLATE_CATEGORY => &self.plate_extension,
+            MASKS_CATEGORY => &self.mask_extension,
+            _ => self.get(name).map_or(DEFAULT_LAYER_EXTENSION, |category| {
+                category.extension.as_str()
+            }),
+        }
+    }
+
+    /// Image format of a category's files, if its extension names one
+    pub fn format(&self, name: &str) -> Option<OutputFormat> {
+        self.extension(name).parse().ok()
+    }
+
+    /// Store a category's files (or plates, or masks) in `format`
+    pub fn set_format(&mut self, name: &str, format: OutputFormat) -> Result<&mut Self> {
+        let extension = format.extension().to_string();
+        match name {
+            PLATE_CATEGORY => self.plate_extension = extension,
+            MASKS_CATEGORY => self.mask_extension = extension,
+            _ => {
+                let category = self.categories.get_mut(name).ok_or_else(|| {
+                    BirlError::InvalidParam(format!("Unknown category '{}' for a format", name))
+                })?;
+                category.extension = extension;
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn is_patch(&self, name: &str) -> bool {
+        self.get(name).is_some_and(|category| category.patch)
+    }
+
+    pub fn is_patch_garment(&self, name: &str) -> bool {
+        self.get(name)
+            .is_some_and(|category| category.patch_garment)
+    }
+
+    /// Mask of the plate regions drawn over an underlay category's layers
+    pub fn underlay_mask(&self, name: &str) -> Option<&str> {
+        self.get(name)
+            .and_then(|category| category.underlay.as_deref())
+    }
+
+    /// The current tables' registry (built-in categories unless one was installed)
+    pub fn global() -> Arc<CategoryRegistry> {
+        Tables::current().categories.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_order() {
+        let registry = CategoryRegistry::builtin();
+        let order: Vec<u32> = ["pants", "tops", "hoodies", "jackets", "hats", "patches"]
+            .iter()
+            .map(|name| registry.z_order(name).unwrap())
+            .collect();
+        assert!(order.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(registry.z_order("capes"), None);
+
+        // Boots go under pant legs, belts over tops, scarves and backpacks over jackets
+        let z = |name| registry.z_order(name).unwrap();
+        assert!(z("boots") < z("pants"));
+        assert!(z("tops") < z("belts") && z("belts") < z("hoodies"));
+        assert!(z("outer-jackets") < z("scarves"));
+        assert!(z("outer-jackets") < z("backpacks") && z("backpacks") < z("patches"));
+        assert!(registry.allows("backpacks", View::Back));
+        assert!(!registry.allows("boots", View::Left));
+    }
+
+    #[test]
+    fn test_views() {
+        let registry = CategoryRegistry::builtin();
+        assert!(registry.allows("hoodies", View::Left));
+        assert!(!registry.allows("pants", View::Left));
+        assert!(registry.allows("patches-left", View::Left));
+        assert!(!registry.allows("patches-left", View::Right));
+        // Unknown categories render in the default views
+        assert!(registry.allows("capes", View::Front));
+        assert!(!registry.allows("capes", View::Right));
+    }
+
+    #[test]
+    fn test_overrides() {
+        let overrides: BTreeMap<String, Category> = serde_json::from_str(
+            r#"{
+                "vests": {"z_order": 45, "patch_garment": true},
+                "hats": {"z_order": 80, "extension": "webp", "views": ["front"]},
+                "straps": {"z_order": 5, "underlay": "torso"}
+            }"#,
+        )
+        .unwrap();
+        let registry = CategoryRegistry::with_overrides(&overrides);
+
+        assert_eq!(registry.z_order("vests"), Some(45));
+        assert!(registry.is_patch_garment("vests"));
+        assert_eq!(registry.extension("vests"), "png");
+        assert!(registry.allows("vests", View::Back));
+
+        assert_eq!(registry.extension("hats"), "webp");
+        assert!(!registry.allows("hats", View::Back));
+        assert_eq!(registry.z_order("pants"), Some(10));
+
+        assert_eq!(registry.underlay_mask("straps"), Some("torso"));
+        assert_eq!(registry.underlay_mask("vests"), None);
+    }
+
+    #[test]
+    fn test_formats() {
+        let mut registry = CategoryRegistry::builtin();
+        assert_eq!(registry.extension(PLATE_CATEGORY), "jpg");
+        assert_eq!(registry.format(PLATE_CATEGORY), Some(OutputFormat::Jpeg));
+        assert_eq!(registry.format(MASKS_CATEGORY), Some(OutputFormat::Png));
+        assert_eq!(registry.format("capes"), Some(OutputFormat::Png));
+
+        registry
+            .set_format(PLATE_CATEGORY, OutputFormat::WebP)
+            .unwrap()
+            .set_format("hats", OutputFormat::WebP)
+            .unwrap();
+        assert_eq!(registry.extension(PLATE_CATEGORY), "webp");
+        assert_eq!(registry.extension("hats"), "webp");
+        assert!(registry.set_format("capes", OutputFormat::WebP).is_err());
+    }
+}
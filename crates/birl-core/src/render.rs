@@ -0,0 +1,166 @@
+//! Pure composition entry point for embedding (WASM, FFI, Python)
+//!
+//! [`render`] does no I/O and needs no async runtime: the caller fetches the
+//! bytes of every asset the recipe reads ([`Recipe::assets`]) and gets back the
+//! encoded image. Storage and service layers are adapters that fetch the
+//! assets and call into this.
+
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::compositor::{Compositor, DecodeCache};
+use crate::error::Result;
+use crate::format::OutputFormat;
+use crate::graph::{Asset, MissingSources, Recipe};
+use crate::sanity::SanityIssue;
+
+/// Asset bytes for a render, keyed by asset
+#[derive(Clone)]
+pub struct Assets {
+    data: HashMap<String, Bytes>,
+    missing: MissingSources,
+    decoded: Option<Arc<DecodeCache>>,
+}
+
+/// An encoded composite
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedImage {
+    pub data: Bytes,
+    pub format: OutputFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Defect found by the sanity check (blank or transparent output)
+    pub sanity_issue: Option<SanityIssue>,
+}
+
+impl Default for Assets {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Assets {
+    /// No assets; every asset the recipe reads is required
+    pub fn new() -> Self {
+        Self {
+            data: HashMap::new(),
+            missing: MissingSources::Error,
+            decoded: None,
+        }
+    }
+
+    pub fn insert(&mut self, asset: &Asset, data: impl Into<Bytes>) -> &mut Self {
+        self.data.insert(asset.to_string(), data.into());
+        self
+    }
+
+    /// How the render treats assets that weren't provided
+    pub fn with_missing(mut self, missing: MissingSources) -> Self {
+        self.missing = missing;
+        self
+    }
+
+    /// Decode through a cache shared with other renders
+    pub fn with_decode_cache(mut self, decoded: Arc<DecodeCache>) -> Self {
+        self.decoded = Some(decoded);
+        self
+    }
+
+    pub fn get(&self, asset: &Asset) -> Option<&Bytes> {
+        self.data.get(&asset.to_string())
+    }
+
+    pub fn contains(&self, asset: &Asset) -> bool {
+        self.data.contains_key(&asset.to_string())
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+/// Assets keyed by their string form, as returned by storage
+impl From<HashMap<String, Bytes>> for Assets {
+    fn from(data: HashMap<String, Bytes>) -> Self {
+        Self {
+            data,
+            ..Self::new()
+        }
+    }
+}
+
+/// Compose and encode a recipe from in-memory assets
+pub fn render(recipe: &Recipe, assets: &Assets) -> Result<RenderedImage> {
+    let compositor = compose(recipe, assets)?;
+
+    let options = recipe.encode_options();
+    let (width, height) = compositor.dimensions();
+    let sanity_issue = compositor.check_sanity();
+
+    Ok(RenderedImage {
+        data: compositor.encode_with(&options)?,
+        format: options.format,
+        width,
+        height,
+        sanity_issue,
+    })
+}
+
+/// Compose a recipe without encoding, for callers that stream the encode
+pub fn compose(recipe: &Recipe, assets: &Assets) -> Result<Compositor> {
+    match &assets.decoded {
+        Some(decoded) => recipe.evaluate_with(&assets.data, assets.missing, decoded),
+        None => recipe.evaluate(&assets.data, assets.missing),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::{synthetic_layer, synthetic_plate};
+    use crate::layers::parse_params;
+    use crate::models::View;
+    use crate::options::CompositeOptions;
+
+    fn recipe() -> Recipe {
+        let layers = parse_params("pants/cargo-black,hoodies/hoodie-black");
+        let options = CompositeOptions::new().with_format(OutputFormat::Png);
+        Recipe::compile(&layers, &[None, None], &options)
+    }
+
+    #[test]
+    fn test_render() {
+        let recipe = recipe();
+        let mut assets = Assets::new();
+        for asset in recipe.assets() {
+            let data = match asset {
+                Asset::Plate => synthetic_plate(View::Front).unwrap(),
+                Asset::Layer(p) => synthetic_layer(&p.category, p.sku.as_str()).unwrap(),
+            };
+            assets.insert(asset, data);
+        }
+
+        let rendered = render(&recipe, &assets).unwrap();
+        assert_eq!(rendered.format, OutputFormat::Png);
+        assert!(rendered.width > 0 && rendered.height > 0);
+        assert_eq!(rendered.sanity_issue, None);
+        assert!(image::load_from_memory(&rendered.data).is_ok());
+    }
+
+    #[test]
+    fn test_missing_assets() {
+        let recipe = recipe();
+        let mut assets = Assets::new();
+        assets.insert(&Asset::Plate, synthetic_plate(View::Front).unwrap());
+
+        assert!(render(&recipe, &assets).is_err());
+
+        let assets = assets.with_missing(MissingSources::Skip);
+        assert!(render(&recipe, &assets).is_ok());
+    }
+}
@@ -0,0 +1,632 @@
+//! Declarative category remapping applied during layer normalization
+//!
+//! Each rule matches a layer by category and SKU (with `*` wildcards, or a
+//! regex for SKU lines a wildcard can't tell apart) and remaps it to another
+//! category. The first matching rule wins; layers no rule matches keep their
+//! category. Rules from the config file are checked before the built-in ones:
+//!
+//! ```toml
+//! [[rules]]
+//! category = "hats"
+//! sku = "*visor*"
+//! remap = "visors"
+//!
+//! [[rules]]
+//! category = "gloves"
+//! sku_regex = "(ski|alpine)(-.*)?"
+//! remap = "gloves-top"
+//! ```
+//!
+//! Constraints are checked after normalization, against the layers that would
+//! be rendered. Each one names layers that need, or can't be worn with, other
+//! layers of the outfit; a violation is reported, and depending on the
+//! constraint's action also drops the layer or rejects the outfit:
+//!
+//! ```toml
+//! [[constraints]]
+//! category = "gloves-top"
+//! requires = "*jackets"
+//! action = "drop"
+//! ```
+
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::error::{BirlError, Result};
+use crate::models::{LayerParam, View};
+use crate::tables::Tables;
+
+/// Remap layers matching a category and SKU pattern to another category
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Category pattern, e.g. "gloves" or "patches-*"
+    pub category: String,
+    /// SKU pattern (default: any SKU)
+    #[serde(default = "any", skip_serializing_if = "is_any")]
+    pub sku: String,
+    /// SKU regex the whole SKU must match, on top of `sku`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sku_regex: Option<SkuRegex>,
+    /// Only apply in these views (default: all views)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub views: Vec<View>,
+    /// Only apply when another layer of the outfit matches this "category/sku" pattern
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub with: Option<String>,
+    /// Target category; `{category}` is replaced by the original category
+    pub remap: String,
+}
+
+pub(crate) fn any() -> String {
+    "*".to_string()
+}
+
+pub(crate) fn is_any(pattern: &str) -> bool {
+    pattern == "*"
+}
+
+impl Rule {
+    pub fn new(category: impl Into<String>, remap: impl Into<String>) -> Self {
+        Self {
+            category: category.into(),
+            sku: any(),
+            sku_regex: None,
+            views: Vec::new(),
+            with: None,
+            remap: remap.into(),
+        }
+    }
+
+    pub fn with_sku(mut self, sku: impl Into<String>) -> Self {
+        self.sku = sku.into();
+        self
+    }
+
+    pub fn with_sku_regex(mut self, regex: SkuRegex) -> Self {
+        self.sku_regex = Some(regex);
+        self
+    }
+
+    pub fn with_views(mut self, views: &[View]) -> Self {
+        self.views = views.to_vec();
+        self
+    }
+
+    pub fn with_outfit(mut self, pattern: impl Into<String>) -> Self {
+        self.with = Some(pattern.into());
+        self
+    }
+
+    /// Whether the rule applies to a layer of `outfit` rendered in `view`
+    pub fn matches(&self, param: &LayerParam, view: View, outfit: &[LayerParam]) -> bool {
+        if !matches_layer(&self.category, &self.sku, &self.views, param, view) {
+            return false;
+        }
+        if let Some(regex) = &self.sku_regex {
+            if !regex.is_match(param.sku.as_str()) {
+                return false;
+            }
+        }
+
+        match &self.with {
+            Some(pattern) => outfit.iter().any(|other| pattern_match(pattern, other)),
+            None => true,
+        }
+    }
+
+    /// Category a matched layer is remapped to
+    pub fn target(&self, category: &str) -> String {
+        self.remap.replace("{category}", category)
+    }
+}
+
+/// A regex matched against whole SKUs, compiled when the rule is read
+#[derive(Debug, Clone)]
+pub struct SkuRegex {
+    pattern: String,
+    regex: Regex,
+}
+
+impl SkuRegex {
+    /// The pattern is anchored: it must match the whole SKU
+    pub fn new(pattern: &str) -> Result<Self> {
+        let regex = Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| {
+            BirlError::InvalidParam(format!("Invalid SKU regex '{}': {}", pattern, e))
+        })?;
+        Ok(Self {
+            pattern: pattern.to_string(),
+            regex,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn is_match(&self, sku: &str) -> bool {
+        self.regex.is_match(sku)
+    }
+}
+
+impl PartialEq for SkuRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Eq for SkuRegex {}
+
+impl Serialize for SkuRegex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.pattern)
+    }
+}
+
+impl<'de> Deserialize<'de> for SkuRegex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Self::new(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+/// What happens to an outfit that breaks a constraint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConstraintAction {
+    /// Render the outfit anyway and report the violation
+    #[default]
+    Warn,
+    /// Leave out the layer the constraint is about
+    Drop,
+    /// Reject the outfit
+    Error,
+}
+
+/// Layers that need, or can't be worn with, other layers of the outfit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Constraint {
+    /// Category pattern, matched after normalization (e.g. "gloves-top")
+    pub category: String,
+    /// SKU pattern (default: any SKU)
+    #[serde(default = "any", skip_serializing_if = "is_any")]
+    pub sku: String,
+    /// Only check in these views (default: all views)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub views: Vec<View>,
+    /// Another layer must match this "category/sku" pattern
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requires: Option<String>,
+    /// No other layer may match this "category/sku" pattern
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub excludes: Option<String>,
+    #[serde(default)]
+    pub action: ConstraintAction,
+}
+
+impl Constraint {
+    /// Layers of `category` need another layer matching `pattern`
+    pub fn requires(category: impl Into<String>, pattern: impl Into<String>) -> Self {
+        Self {
+            requires: Some(pattern.into()),
+            ..Self::new(category)
+        }
+    }
+
+    /// Layers of `category` can't be worn with layers matching `pattern`
+    pub fn excludes(category: impl Into<String>, pattern: impl Into<String>) -> Self {
+        Self {
+            excludes: Some(pattern.into()),
+            ..Self::new(category)
+        }
+    }
+
+    fn new(category: impl Into<String>) -> Self {
+        Self {
+            category: category.into(),
+            sku: any(),
+            views: Vec::new(),
+            requires: None,
+            excludes: None,
+            action: ConstraintAction::default(),
+        }
+    }
+
+    pub fn with_sku(mut self, sku: impl Into<String>) -> Self {
+        self.sku = sku.into();
+        self
+    }
+
+    pub fn with_views(mut self, views: &[View]) -> Self {
+        self.views = views.to_vec();
+        self
+    }
+
+    pub fn with_action(mut self, action: ConstraintAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Violations of this constraint by the layer at `idx` of `layers`
+    pub fn check(&self, layers: &[LayerParam], idx: usize, view: View) -> Vec<ConstraintViolation> {
+        let layer = &layers[idx];
+        if !matches_layer(&self.category, &self.sku, &self.views, layer, view) {
+            return Vec::new();
+        }
+        let others = || {
+            layers
+                .iter()
+                .enumerate()
+                .filter(move |(other, _)| *other != idx)
+                .map(|(_, param)| param)
+        };
+
+        let mut violations = Vec::new();
+        if let Some(pattern) = &self.requires {
+            if !others().any(|other| pattern_match(pattern, other)) {
+                violations.push(ConstraintViolation {
+                    layer: layer.clone(),
+                    kind: ConstraintKind::Requires,
+                    pattern: pattern.clone(),
+                    conflicting: Vec::new(),
+                    action: self.action,
+                });
+            }
+        }
+        if let Some(pattern) = &self.excludes {
+            let conflicting: Vec<LayerParam> = others()
+                .filter(|other| pattern_match(pattern, other))
+                .cloned()
+                .collect();
+            if !conflicting.is_empty() {
+                violations.push(ConstraintViolation {
+                    layer: layer.clone(),
+                    kind: ConstraintKind::Excludes,
+                    pattern: pattern.clone(),
+                    conflicting,
+                    action: self.action,
+                });
+            }
+        }
+        violations
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ConstraintKind {
+    Requires,
+    Excludes,
+}
+
+/// A layer that broke a constraint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConstraintViolation {
+    pub layer: LayerParam,
+    pub kind: ConstraintKind,
+    /// The constraint's "category/sku" pattern
+    pub pattern: String,
+    /// Layers matching an `excludes` pattern
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conflicting: Vec<LayerParam>,
+    pub action: ConstraintAction,
+}
+
+impl fmt::Display for ConstraintViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ConstraintKind::Requires => write!(f, "{} requires {}", self.layer, self.pattern)?,
+            ConstraintKind::Excludes => {
+                let conflicting: Vec<String> =
+                    self.conflicting.iter().map(ToString::to_string).collect();
+                write!(
+                    f,
+                    "{} can't be worn with {}",
+                    self.layer,
+                    conflicting.join(", ")
+                )?
+            }
+        }
+        if self.action == ConstraintAction::Drop {
+            write!(f, "; dropped {}", self.layer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Outfit rejected by a constraint with [`ConstraintAction::Error`]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Outfit breaks constraints: {}", self.describe())]
+pub struct ConstraintError {
+    pub violations: Vec<ConstraintViolation>,
+}
+
+impl ConstraintError {
+    fn describe(&self) -> String {
+        let violations: Vec<String> = self.violations.iter().map(ToString::to_string).collect();
+        violations.join("; ")
+    }
+}
+
+/// Violations of any of `constraints` by the layers of an outfit, in layer order
+pub fn check_constraints(
+    constraints: &[Constraint],
+    layers: &[LayerParam],
+    view: View,
+) -> Vec<ConstraintViolation> {
+    (0..layers.len())
+        .flat_map(|idx| {
+            constraints
+                .iter()
+                .flat_map(move |constraint| constraint.check(layers, idx, view))
+        })
+        .collect()
+}
+
+/// Ordered list of normalization rules, and the constraints checked after them
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    constraints: Vec<Constraint>,
+}
+
+impl RuleSet {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self {
+            rules,
+            constraints: Vec::new(),
+        }
+    }
+
+    /// Check these constraints after normalization
+    pub fn with_constraints(mut self, constraints: &[Constraint]) -> Self {
+        self.constraints = constraints.to_vec();
+        self
+    }
+
+    /// The built-in catalog rules
+    pub fn builtin() -> Self {
+        const SOFTSHELL: &str = "jackets/*softshell*";
+
+        let ski = SkuRegex::new("ski.*").expect("built-in SKU regex");
+        Self::new(vec![
+            // Ski gloves go on top, others go on bottom ("regular" is NOT a ski glove)
+            Rule::new("gloves", "gloves-top").with_sku_regex(ski),
+            Rule::new("gloves", "gloves-bottom"),
+            // Greenland jackets are outer jackets
+            Rule::new("jackets", "outer-jackets").with_sku("*greenland*"),
+            // Front view keeps the patch position; other views use the plain patch folder
+            Rule::new("patches-*", "softshell-{category}")
+                .with_views(&[View::Front])
+                .with_outfit(SOFTSHELL),
+            Rule::new("patches-*", "{category}").with_views(&[View::Front]),
+            Rule::new("patches-*", "softshell-patches").with_outfit(SOFTSHELL),
+            Rule::new("patches-*", "patches"),
+        ])
+    }
+
+    /// Config rules checked before the built-in ones
+    pub fn with_overrides(overrides: &[Rule]) -> Self {
+        let mut rules = overrides.to_vec();
+        rules.extend(Self::builtin().rules);
+        Self::new(rules)
+    }
+
+    /// Whether some rule remaps layers of `category` (e.g. "gloves")
+    pub fn remaps(&self, category: &str) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| wildcard_match(&rule.category, category))
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn constraints(&self) -> &[Constraint] {
+        &self.constraints
+    }
+
+    /// Category for a layer after the first matching rule, if any
+    pub fn apply(&self, param: &LayerParam, view: View, outfit: &[LayerParam]) -> Option<String> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(param, view, outfit))
+            .map(|rule| rule.target(&param.category))
+    }
+
+    /// The current tables' rules (built-in rules unless some were installed)
+    pub fn global() -> Arc<RuleSet> {
+        Tables::current().rules.clone()
+    }
+}
+
+pub(crate) fn matches_layer(
+    category: &str,
+    sku: &str,
+    views: &[View],
+    param: &LayerParam,
+    view: View,
+) -> bool {
+    (views.is_empty() || views.contains(&view))
+        && wildcard_match(category, &param.category)
+        && wildcard_match(sku, param.sku.as_str())
+}
+
+/// Match a layer against a "category/sku" pattern; a bare category matches any SKU
+fn pattern_match(pattern: &str, param: &LayerParam) -> bool {
+    let (category, sku) = pattern.split_once('/').unwrap_or((pattern, "*"));
+    wildcard_match(category, &param.category) && wildcard_match(sku, param.sku.as_str())
+}
+
+/// Match `text` against a pattern where `*` matches any run of characters
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard: exact match
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("gloves", "gloves"));
+        assert!(!wildcard_match("gloves", "gloves-top"));
+        assert!(wildcard_match("ski*", "ski-black"));
+        assert!(!wildcard_match("ski*", "regular-ski"));
+        assert!(wildcard_match("*greenland*", "greenland-grey"));
+        assert!(wildcard_match("patches-*", "patches-left"));
+        assert!(!wildcard_match("patches-*", "patches"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("a*a", "a"));
+    }
+
+    #[test]
+    fn test_overrides_take_precedence() {
+        let overrides: Vec<Rule> = serde_json::from_str(
+            r#"[{"category": "gloves", "sku": "*mitt*", "remap": "mittens"}]"#,
+        )
+        .unwrap();
+        let rules = RuleSet::with_overrides(&overrides);
+
+        let mitt = LayerParam::new("gloves", "ski-mitt-black");
+        assert_eq!(
+            rules.apply(&mitt, View::Front, &[]).as_deref(),
+            Some("mittens")
+        );
+
+        let glove = LayerParam::new("gloves", "ski-black");
+        assert_eq!(
+            rules.apply(&glove, View::Front, &[]).as_deref(),
+            Some("gloves-top")
+        );
+        assert_eq!(
+            rules.apply(&LayerParam::new("hats", "beanie"), View::Front, &[]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_sku_regex() {
+        let rules = RuleSet::builtin();
+        let category = |sku: &str| rules.apply(&LayerParam::new("gloves", sku), View::Front, &[]);
+        assert_eq!(category("ski-black").as_deref(), Some("gloves-top"));
+        assert_eq!(category("ski_mitt_red").as_deref(), Some("gloves-top"));
+        assert_eq!(category("skiglove-black").as_deref(), Some("gloves-top"));
+        assert_eq!(category("regular-ski").as_deref(), Some("gloves-bottom"));
+
+        let overrides: Vec<Rule> = serde_json::from_str(
+            r#"[{"category": "gloves", "sku_regex": "(alpine|touring)-\\d+.*", "remap": "gloves-top"}]"#,
+        )
+        .unwrap();
+        let rules = RuleSet::with_overrides(&overrides);
+        let glove = LayerParam::new("gloves", "alpine-200-black");
+        assert_eq!(
+            rules.apply(&glove, View::Front, &[]).as_deref(),
+            Some("gloves-top")
+        );
+        let glove = LayerParam::new("gloves", "alpine-black");
+        assert_eq!(
+            rules.apply(&glove, View::Front, &[]).as_deref(),
+            Some("gloves-bottom")
+        );
+        assert_eq!(
+            serde_json::to_value(&overrides[0]).unwrap()["sku_regex"],
+            "(alpine|touring)-\\d+.*"
+        );
+
+        let invalid = serde_json::from_str::<Rule>(
+            r#"{"category": "gloves", "sku_regex": "(ski", "remap": "gloves-top"}"#,
+        );
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_outfit_condition() {
+        let patch = LayerParam::new("patches-right", "flag");
+        let outfit = vec![LayerParam::new("jackets", "softshell-grey"), patch.clone()];
+        let rules = RuleSet::builtin();
+
+        assert_eq!(
+            rules.apply(&patch, View::Front, &outfit).as_deref(),
+            Some("softshell-patches-right")
+        );
+        assert_eq!(
+            rules.apply(&patch, View::Right, &outfit).as_deref(),
+            Some("softshell-patches")
+        );
+        assert_eq!(
+            rules.apply(&patch, View::Front, &[]).as_deref(),
+            Some("patches-right")
+        );
+    }
+
+    #[test]
+    fn test_constraints() {
+        let constraints = vec![
+            Constraint::requires("gloves-top", "*jackets").with_action(ConstraintAction::Drop),
+            Constraint::excludes("outer-jackets", "hoodies/*-hood-*"),
+        ];
+        let layers = vec![
+            LayerParam::new("hoodies", "hoodie-grey"),
+            LayerParam::new("outer-jackets", "greenland-black"),
+            LayerParam::new("gloves-top", "ski-black"),
+        ];
+
+        let violations = check_constraints(&constraints, &layers, View::Front);
+        assert!(violations.is_empty());
+
+        let violations = check_constraints(&constraints, &layers[..1], View::Front);
+        assert!(violations.is_empty());
+
+        let layers = vec![
+            LayerParam::new("hoodies", "zip-hood-black"),
+            LayerParam::new("hoodies", "hoodie-grey"),
+            LayerParam::new("outer-jackets", "greenland-black"),
+        ];
+        let violations = check_constraints(&constraints, &layers, View::Front);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].conflicting, layers[..1]);
+        assert_eq!(
+            violations[0].to_string(),
+            "outer-jackets/greenland-black can't be worn with hoodies/zip-hood-black"
+        );
+
+        let layers = vec![LayerParam::new("gloves-top", "ski-black")];
+        let violations = check_constraints(&constraints, &layers, View::Front);
+        assert_eq!(violations[0].kind, ConstraintKind::Requires);
+        assert_eq!(
+            violations[0].to_string(),
+            "gloves-top/ski-black requires *jackets; dropped gloves-top/ski-black"
+        );
+
+        // A layer never satisfies its own requirement
+        let constraint = Constraint::requires("gloves-*", "gloves-*").with_views(&[View::Back]);
+        let layers = vec![LayerParam::new("gloves-top", "ski-black")];
+        assert_eq!(constraint.check(&layers, 0, View::Back).len(), 1);
+        assert!(constraint.check(&layers, 0, View::Front).is_empty());
+    }
+}
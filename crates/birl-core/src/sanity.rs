@@ -0,0 +1,104 @@
+use image::imageops::FilterType;
+use image::DynamicImage;
+use std::fmt;
+
+/// Mean luminance below which a composite counts as all black
+const BLACK_MEAN: f64 = 8.0;
+/// Mean luminance above which a composite counts as all white
+const WHITE_MEAN: f64 = 247.0;
+/// Luminance standard deviation below which a composite counts as flat
+const MIN_STD_DEV: f64 = 2.0;
+
+/// Obvious defect in a finished composite
+/// Usually caused by a truncated or corrupt layer download
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum SanityIssue {
+    AllBlack,
+    AllWhite,
+    LowVariance { std_dev: f64 },
+}
+
+impl fmt::Display for SanityIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SanityIssue::AllBlack => write!(f, "composite is all black"),
+            SanityIssue::AllWhite => write!(f, "composite is all white"),
+            SanityIssue::LowVariance { std_dev } => {
+                write!(f, "composite is nearly flat (std dev {:.2})", std_dev)
+            }
+        }
+    }
+}
+
+/// Check a composite for obviously broken output
+/// Runs on a 64x64 thumbnail so it is cheap enough for every render
+pub fn check_image(image: &DynamicImage) -> Option<SanityIssue> {
+    let thumb = image
+        .resize_exact(64, 64, FilterType::Triangle)
+        .to_luma_alpha8();
+    // Transparent areas (of a transparent plate) count as mid-gray, not as
+    // the black their color channels usually hold
+    let luma = |[l, a]: [u8; 2]| {
+        let alpha = a as f64 / 255.0;
+        l as f64 * alpha + 128.0 * (1.0 - alpha)
+    };
+
+    let count = thumb.pixels().len() as f64;
+    let mean = thumb.pixels().map(|p| luma(p.0)).sum::<f64>() / count;
+    let variance = thumb
+        .pixels()
+        .map(|p| (luma(p.0) - mean).powi(2))
+        .sum::<f64>()
+        / count;
+    let std_dev = variance.sqrt();
+
+    if std_dev >= MIN_STD_DEV {
+        None
+    } else if mean < BLACK_MEAN {
+        Some(SanityIssue::AllBlack)
+    } else if mean > WHITE_MEAN {
+        Some(SanityIssue::AllWhite)
+    } else {
+        Some(SanityIssue::LowVariance { std_dev })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    fn solid(v: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(100, 100, Rgb([v, v, v])))
+    }
+
+    #[test]
+    fn test_flags_broken_composites() {
+        assert_eq!(check_image(&solid(0)), Some(SanityIssue::AllBlack));
+        assert_eq!(check_image(&solid(255)), Some(SanityIssue::AllWhite));
+        assert!(matches!(
+            check_image(&solid(128)),
+            Some(SanityIssue::LowVariance { .. })
+        ));
+    }
+
+    #[test]
+    fn test_transparent_areas_are_not_black() {
+        // A black garment on a transparent plate
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(100, 100, |x, _| {
+            Rgba([0, 0, 0, if x < 50 { 255 } else { 0 }])
+        }));
+        assert_eq!(check_image(&image), None);
+        let empty = DynamicImage::ImageRgba8(RgbaImage::new(100, 100));
+        assert_ne!(check_image(&empty), Some(SanityIssue::AllBlack));
+    }
+
+    #[test]
+    fn test_accepts_normal_composite() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(100, 100, |x, y| {
+            Rgb([(x * 2) as u8, (y * 2) as u8, 128])
+        }));
+        assert_eq!(check_image(&image), None);
+    }
+}
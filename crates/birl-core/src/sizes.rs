@@ -0,0 +1,302 @@
+//! Size suffixes stripped from SKUs
+//!
+//! `hoodie-black-xl` and `hoodie-black-s` share one layer, `hoodie-black`.
+//! The last `-` segment of a SKU is a size when it is one of the letter sizes;
+//! after that, a trailing segment matching one of the patterns (by default
+//! any number, as in `-36`) is stripped too. Sizing schemes the built-in list
+//! doesn't know are added in the config file:
+//!
+//! ```toml
+//! [sizes]
+//! suffixes = ["xxxl", "os"]
+//! patterns = ["eu\\d{2}"]
+//! ```
+//!
+//! With `builtin = false` only the configured sizes are stripped, so a scheme
+//! whose colors look like sizes keeps them.
+//!
+//! Regional size labels (`-eu44`, `-uk10`, `-jp27`) come as locale packs,
+//! each a set of patterns for one region's clothing and shoe sizes:
+//!
+//! ```toml
+//! [sizes]
+//! locales = ["eu", "uk"]
+//! ```
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::error::{BirlError, Result};
+use crate::models::{fold_sku, SizedSku, Sku};
+use crate::tables::Tables;
+
+const BUILTIN_SUFFIXES: [&str; 11] = [
+    "xs", "s", "m", "l", "xl", "xxl", "2xl", "3xl", "4xl", "5xl", "lxl",
+];
+const BUILTIN_PATTERNS: [&str; 1] = [r"\d+"];
+
+/// Regional size labels, stripped when the locale is listed in `[sizes]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeLocale {
+    /// EU sizes: `eu44`, `eu42.5`
+    Eu,
+    /// UK sizes: `uk10`, `uk7.5`
+    Uk,
+    /// Japanese sizes, in cm for shoes: `jp27`, `jp26.5`
+    Jp,
+    /// US sizes: `us9`, `us10.5`
+    Us,
+}
+
+impl SizeLocale {
+    pub const ALL: [SizeLocale; 4] = [Self::Eu, Self::Uk, Self::Jp, Self::Us];
+
+    /// Patterns of the pack, in the form of [`SizeSpec::patterns`]
+    pub fn patterns(self) -> &'static [&'static str] {
+        match self {
+            Self::Eu => &[r"eu\d{2}(?:\.5)?"],
+            Self::Uk => &[r"uk\d{1,2}(?:\.5)?"],
+            Self::Jp => &[r"jp\d{1,2}(?:\.5)?"],
+            Self::Us => &[r"us\d{1,2}(?:\.5)?"],
+        }
+    }
+}
+
+/// Size settings of the config file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SizeSpec {
+    /// Keep the built-in letter and numeric sizes (default: true)
+    #[serde(default = "yes")]
+    pub builtin: bool,
+    /// Letter sizes, without the dash
+    #[serde(default)]
+    pub suffixes: Vec<String>,
+    /// Regexes a whole trailing segment must match
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// Regional size packs, added whether or not `builtin` is set
+    #[serde(default)]
+    pub locales: Vec<SizeLocale>,
+}
+
+fn yes() -> bool {
+    true
+}
+
+impl Default for SizeSpec {
+    fn default() -> Self {
+        Self {
+            builtin: true,
+            suffixes: Vec::new(),
+            patterns: Vec::new(),
+            locales: Vec::new(),
+        }
+    }
+}
+
+/// Compiled set of size suffixes and patterns
+#[derive(Debug, Clone)]
+pub struct SizePatterns {
+    suffixes: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl SizePatterns {
+    /// Patterns are anchored: they must match the whole segment
+    pub fn new<S: AsRef<str>>(suffixes: &[S], patterns: &[S]) -> Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                let pattern = pattern.as_ref();
+                Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| {
+                    BirlError::InvalidParam(format!("Invalid size pattern '{}': {}", pattern, e))
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            suffixes: suffixes
+                .iter()
+                .map(|suffix| suffix.as_ref().trim_start_matches('-').to_lowercase())
+                .collect(),
+            patterns,
+        })
+    }
+
+    /// The built-in letter and numeric sizes
+    pub fn builtin() -> Self {
+        Self::new(&BUILTIN_SUFFIXES, &BUILTIN_PATTERNS).expect("built-in size patterns")
+    }
+
+    /// Configured sizes, after the built-in ones unless `builtin = false`
+    pub fn from_spec(spec: &SizeSpec) -> Result<Self> {
+        let mut suffixes = Vec::new();
+        let mut patterns = Vec::new();
+        if spec.builtin {
+            suffixes.extend(BUILTIN_SUFFIXES.map(String::from));
+            patterns.extend(BUILTIN_PATTERNS.map(String::from));
+        }
+        suffixes.extend(spec.suffixes.iter().cloned());
+        patterns.extend(spec.patterns.iter().cloned());
+        for locale in &spec.locales {
+            patterns.extend(locale.patterns().iter().map(|p| p.to_string()));
+        }
+        Self::new(&suffixes, &patterns)
+    }
+
+    /// Normalize a raw SKU, keeping the size that was stripped
+    /// When both a suffix and a pattern match, the pattern's segment is the size
+    pub fn split(&self, raw: &str) -> SizedSku {
+        let mut result = fold_sku(raw);
+        let mut size = None;
+
+        if let Some(pos) = result.rfind('-') {
+            if self
+                .suffixes
+                .iter()
+                .any(|suffix| *suffix == result[pos + 1..])
+            {
+                size = Some(result.split_off(pos)[1..].to_string());
+            }
+        }
+
+        if let Some(pos) = result.rfind('-') {
+            if self.patterns.iter().any(|p| p.is_match(&result[pos + 1..])) {
+                size = Some(result.split_off(pos)[1..].to_string());
+            }
+        }
+
+        SizedSku {
+            base: Sku(result),
+            size,
+        }
+    }
+
+    /// The current tables' sizes (built-in sizes unless some were installed)
+    pub fn global() -> Arc<SizePatterns> {
+        Tables::current().sizes.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_sizes() {
+        let sizes = SizePatterns::builtin();
+        assert_eq!(
+            sizes.split("hoodie-black-lxl").base.as_str(),
+            "hoodie-black"
+        );
+        assert_eq!(sizes.split("jeans-blue-36").size.as_deref(), Some("36"));
+        assert_eq!(sizes.split("hoodie-black-xxxl").size, None);
+        assert_eq!(sizes.split("xl").base.as_str(), "xl");
+    }
+
+    #[test]
+    fn test_configured_sizes() {
+        let spec = SizeSpec {
+            suffixes: vec!["xxxl".to_string()],
+            patterns: vec![r"eu\d{2}".to_string()],
+            ..SizeSpec::default()
+        };
+        let sizes = SizePatterns::from_spec(&spec).unwrap();
+        assert_eq!(
+            sizes.split("hoodie-black-xxxl").base.as_str(),
+            "hoodie-black"
+        );
+        assert_eq!(sizes.split("boot-brown-eu42").size.as_deref(), Some("eu42"));
+        assert_eq!(sizes.split("boot-brown-eu4").size, None);
+        assert_eq!(sizes.split("jeans-blue-36").size.as_deref(), Some("36"));
+
+        // Without the built-in sizes, a color that looks like a size stays
+        let spec = SizeSpec {
+            builtin: false,
+            suffixes: vec!["t".to_string()],
+            ..SizeSpec::default()
+        };
+        let sizes = SizePatterns::from_spec(&spec).unwrap();
+        assert_eq!(sizes.split("scarf-m").base.as_str(), "scarf-m");
+        assert_eq!(sizes.split("scarf-tan-t").base.as_str(), "scarf-tan");
+
+        let spec = SizeSpec {
+            patterns: vec!["(".to_string()],
+            ..SizeSpec::default()
+        };
+        assert!(SizePatterns::from_spec(&spec).is_err());
+    }
+
+    fn locale_sizes(locale: SizeLocale) -> SizePatterns {
+        let spec = SizeSpec {
+            locales: vec![locale],
+            ..SizeSpec::default()
+        };
+        SizePatterns::from_spec(&spec).unwrap()
+    }
+
+    #[test]
+    fn test_locale_eu() {
+        let sizes = locale_sizes(SizeLocale::Eu);
+        assert_eq!(sizes.split("boot-brown-eu44").base.as_str(), "boot-brown");
+        assert_eq!(
+            sizes.split("boot-brown-EU42.5").size.as_deref(),
+            Some("eu42.5")
+        );
+        assert_eq!(sizes.split("boot-brown-eu4").size, None);
+        assert_eq!(sizes.split("boot-brown-uk10").size, None);
+    }
+
+    #[test]
+    fn test_locale_uk() {
+        let sizes = locale_sizes(SizeLocale::Uk);
+        assert_eq!(sizes.split("dress-red-uk10").base.as_str(), "dress-red");
+        assert_eq!(
+            sizes.split("boot-brown-uk7.5").size.as_deref(),
+            Some("uk7.5")
+        );
+        assert_eq!(sizes.split("boot-brown-uk105").size, None);
+        assert_eq!(sizes.split("boot-brown-eu44").size, None);
+    }
+
+    #[test]
+    fn test_locale_jp() {
+        let sizes = locale_sizes(SizeLocale::Jp);
+        assert_eq!(
+            sizes.split("sneaker-white-jp27").base.as_str(),
+            "sneaker-white"
+        );
+        assert_eq!(
+            sizes.split("sneaker-white-jp26.5").size.as_deref(),
+            Some("jp26.5")
+        );
+        assert_eq!(sizes.split("sneaker-white-jpn").size, None);
+    }
+
+    #[test]
+    fn test_locale_us() {
+        let sizes = locale_sizes(SizeLocale::Us);
+        assert_eq!(
+            sizes.split("sneaker-white-us9").base.as_str(),
+            "sneaker-white"
+        );
+        assert_eq!(
+            sizes.split("sneaker-white-us10.5").size.as_deref(),
+            Some("us10.5")
+        );
+        assert_eq!(sizes.split("sneaker-white-usa").size, None);
+    }
+
+    #[test]
+    fn test_locales_without_builtin() {
+        let spec: SizeSpec = toml::from_str("builtin = false\nlocales = [\"eu\", \"jp\"]").unwrap();
+        let sizes = SizePatterns::from_spec(&spec).unwrap();
+        assert_eq!(sizes.split("boot-brown-eu44").base.as_str(), "boot-brown");
+        assert_eq!(sizes.split("boot-brown-jp27").base.as_str(), "boot-brown");
+        assert_eq!(sizes.split("jeans-blue-36").size, None);
+        assert!(toml::from_str::<SizeSpec>("locales = [\"fr\"]").is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! "Did you mean" suggestions for SKUs that don't exist
+//!
+//! Most missing layers are typos from the CMS (`baerskin4-black` for
+//! `bearskin4-black`). [`closest_sku`] picks the known SKU a few edits away
+//! from the requested one; the known SKUs come from the catalog or, without
+//! one, from a storage listing through [`SkuIndex`].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::graph::MASKS_CATEGORY;
+use crate::layers::PLATE_CATEGORY;
+use crate::lint::AssetFile;
+use crate::models::{Sku, View};
+
+/// Optimal string alignment distance: insertions, deletions, substitutions and
+/// swaps of adjacent characters each count as one edit
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // Rows for the current and two previous characters of `a`
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        current[0] = i;
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut before, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// Most edits a suggestion may be from the requested SKU: 1 per 5 characters,
+/// between 1 and 3
+fn max_distance(sku: &str) -> usize {
+    (sku.chars().count() / 5).clamp(1, 3)
+}
+
+/// The candidate closest to `sku`, if one is near enough to be a typo
+/// Ties go to the first candidate; an exact match is never suggested
+pub fn closest_sku<'a>(
+    sku: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let max = max_distance(sku);
+    let mut best: Option<(usize, &str)> = None;
+    for candidate in candidates {
+        if candidate == sku {
+            continue;
+        }
+        let distance = edit_distance(sku, candidate);
+        if distance <= max && best.is_none_or(|(best, _)| distance < best) {
+            best = Some((distance, candidate));
+        }
+    }
+    best.map(|(_, candidate)| candidate)
+}
+
+/// SKUs with layers in storage, by view and category
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SkuIndex {
+    skus: BTreeMap<(View, String), BTreeSet<String>>,
+}
+
+impl SkuIndex {
+    /// Index layer paths (`{view}/{category}/{sku}.{ext}`) from a listing
+    /// Plates, masks and files outside a category are left out
+    pub fn from_paths<'a>(paths: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut skus: BTreeMap<(View, String), BTreeSet<String>> = BTreeMap::new();
+        for path in paths {
+            let asset = AssetFile::from_path(path);
+            let Some(view) = asset.view else {
+                continue;
+            };
+            if matches!(
+                asset.category.as_str(),
+                "" | PLATE_CATEGORY | MASKS_CATEGORY
+            ) {
+                continue;
+            }
+            skus.entry((view, asset.category))
+                .or_default()
+                .insert(Sku::new(&asset.sku).to_string());
+        }
+        Self { skus }
+    }
+
+    /// A SKU of `category` with a layer in `view` that `sku` may be a typo of
+    pub fn suggest(&self, category: &str, sku: &str, view: View) -> Option<&str> {
+        let skus = self.skus.get(&(view, category.to_string()))?;
+        closest_sku(sku, skus.iter().map(String::as_str))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.skus.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("baerskin4-black", "bearskin4-black"), 1);
+        assert_eq!(edit_distance("hoodie-blck", "hoodie-black"), 1);
+        assert_eq!(edit_distance("hoodie-black", "hoodie-grey"), 5);
+        assert_eq!(edit_distance("", "cap"), 3);
+        assert_eq!(edit_distance("ca", "abc"), 3);
+    }
+
+    #[test]
+    fn test_closest_sku() {
+        let skus = ["bearskin4-black", "bearskin4-brown", "beanie-black"];
+        assert_eq!(
+            closest_sku("baerskin4-black", skus),
+            Some("bearskin4-black")
+        );
+        assert_eq!(closest_sku("bearskin4-bown", skus), Some("bearskin4-brown"));
+        assert_eq!(closest_sku("bearskin4-black", skus), None);
+        assert_eq!(closest_sku("cap-red", skus), None);
+        // Short SKUs get a single edit
+        assert_eq!(closest_sku("cpa", ["cap", "hat"]), Some("cap"));
+        assert_eq!(closest_sku("cop", ["hat"]), None);
+    }
+
+    #[test]
+    fn test_sku_index() {
+        let index = SkuIndex::from_paths([
+            "front/hoodies/hoodie-black.png",
+            "front/hoodies/hoodie-grey-xl.png",
+            "back/hoodies/zip-hood-black.png",
+            "front/plate/base-model-black.jpg",
+            "readme.txt",
+        ]);
+        assert_eq!(
+            index.suggest("hoodies", "hoodie-gery", View::Front),
+            Some("hoodie-grey")
+        );
+        assert_eq!(index.suggest("hoodies", "zip-hood-blak", View::Front), None);
+        assert_eq!(index.suggest("plate", "base-model-blak", View::Front), None);
+    }
+}
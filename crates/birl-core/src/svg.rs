@@ -0,0 +1,127 @@
+//! SVG layers, rasterized at plate resolution
+//!
+//! Some patch artwork is delivered as SVG. Recipes recognize SVG sources by
+//! their content and, with the `svg` feature, rasterize them to fit the plate
+//! they are blended over, so no PNG export (at the wrong DPI) is needed. Text
+//! must be converted to paths; embedded raster images are not drawn.
+//! Without the feature, an SVG source fails to decode.
+
+use image::error::ImageFormatHint;
+use image::{DynamicImage, ImageError};
+
+/// Extension of SVG layer files
+pub const SVG_EXTENSION: &str = "svg";
+
+/// Bytes read before giving up on finding an `<svg` root
+const SNIFF_BYTES: usize = 1024;
+
+/// Whether `data` is an SVG document
+pub fn is_svg(data: &[u8]) -> bool {
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    let start = data.iter().position(|b| !b.is_ascii_whitespace());
+    let Some(data) = start.map(|start| &data[start..]) else {
+        return false;
+    };
+    if data.starts_with(b"<svg") {
+        return true;
+    }
+    (data.starts_with(b"<?xml") || data.starts_with(b"<!")) && {
+        let head = &data[..data.len().min(SNIFF_BYTES)];
+        head.windows(4).any(|window| window == b"<svg")
+    }
+}
+
+fn format_hint() -> ImageFormatHint {
+    ImageFormatHint::Name("SVG".to_string())
+}
+
+/// Rasterize an SVG to fit `width` x `height`, keeping its aspect ratio
+/// Without a size, it is rasterized at its own
+#[cfg(feature = "svg")]
+pub fn rasterize(data: &[u8], fit: Option<(u32, u32)>) -> Result<DynamicImage, ImageError> {
+    use image::error::DecodingError;
+    use resvg::{tiny_skia, usvg};
+
+    let decoding = |e: String| ImageError::Decoding(DecodingError::new(format_hint(), e));
+    let tree = usvg::Tree::from_data(data, &usvg::Options::default())
+        .map_err(|e| decoding(e.to_string()))?;
+
+    let size = tree.size();
+    let scale = match fit {
+        Some((width, height)) => (width as f32 / size.width()).min(height as f32 / size.height()),
+        None => 1.0,
+    };
+    let width = (size.width() * scale).round().max(1.0) as u32;
+    let height = (size.height() * scale).round().max(1.0) as u32;
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| decoding(format!("Invalid SVG size {}x{}", width, height)))?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    // tiny-skia pixels are premultiplied
+    let image = image::RgbaImage::from_fn(width, height, |x, y| {
+        let pixel = pixmap.pixel(x, y).expect("in bounds").demultiply();
+        image::Rgba([pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()])
+    });
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+/// Rasterizing SVGs needs the `svg` feature
+#[cfg(not(feature = "svg"))]
+pub fn rasterize(_data: &[u8], _fit: Option<(u32, u32)>) -> Result<DynamicImage, ImageError> {
+    use image::error::{UnsupportedError, UnsupportedErrorKind};
+
+    Err(ImageError::Unsupported(
+        UnsupportedError::from_format_and_kind(
+            format_hint(),
+            UnsupportedErrorKind::Format(format_hint()),
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+        <rect x="0" y="0" width="10" height="10" fill="#ff0000"/>
+    </svg>"##;
+
+    #[test]
+    fn test_is_svg() {
+        assert!(is_svg(PATCH.as_bytes()));
+        assert!(is_svg(
+            b"\xef\xbb\xbf <?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>"
+        ));
+        assert!(!is_svg(b"<?xml version=\"1.0\"?><html/>"));
+        assert!(!is_svg(b"\x89PNG\r\n\x1a\n"));
+        assert!(!is_svg(b""));
+    }
+
+    #[cfg(feature = "svg")]
+    #[test]
+    fn test_rasterize() {
+        // Scaled to fit, keeping the 2:1 aspect ratio
+        let image = rasterize(PATCH.as_bytes(), Some((100, 100))).unwrap();
+        assert_eq!((image.width(), image.height()), (100, 50));
+        let image = image.to_rgba8();
+        assert_eq!(image.get_pixel(10, 25).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(90, 25).0[3], 0);
+
+        let image = rasterize(PATCH.as_bytes(), None).unwrap();
+        assert_eq!((image.width(), image.height()), (20, 10));
+        assert!(rasterize(b"<svg", None).is_err());
+    }
+
+    #[cfg(not(feature = "svg"))]
+    #[test]
+    fn test_rasterize_needs_feature() {
+        assert!(matches!(
+            rasterize(PATCH.as_bytes(), None),
+            Err(ImageError::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,242 @@
+//! The configured tables composition reads, carried as one context
+//!
+//! Categories, rules, sizes, aliases, presets and the rest are built from the
+//! config file (and the catalog) at startup, into a [`Tables`]:
+//!
+//! ```
+//! use birl_core::{CategoryRegistry, ConflictPolicy, Tables};
+//!
+//! let tables = Tables::builtin()
+//!     .with_categories(CategoryRegistry::builtin())
+//!     .with_conflicts(ConflictPolicy::LastWins);
+//! tables.install();
+//! ```
+//!
+//! The installed tables are the process-wide ones, and installing again
+//! replaces them. [`Tables::scope`] runs code on this thread against other
+//! tables, so tests and tools don't touch the installed ones.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use crate::aliases::SkuAliases;
+use crate::error::Result;
+use crate::flatlay::FlatLay;
+use crate::layers::ConflictPolicy;
+use crate::limits::Limits;
+use crate::models::View;
+use crate::names::DisplayNames;
+use crate::offsets::LayerOffsets;
+use crate::orient::Orientations;
+use crate::presets::Presets;
+use crate::quality::QualityCurve;
+use crate::registry::CategoryRegistry;
+use crate::rules::RuleSet;
+use crate::sizes::SizePatterns;
+use crate::views::{self, ViewSpec};
+use crate::wildcards::Wildcards;
+
+static INSTALLED: RwLock<Option<Arc<Tables>>> = RwLock::new(None);
+
+thread_local! {
+    /// Tables of the [`Tables::scope`] running on this thread
+    static SCOPED: RefCell<Option<Arc<Tables>>> = const { RefCell::new(None) };
+}
+
+/// Categories, rules and the other configured tables, as one context
+#[derive(Debug, Clone)]
+pub struct Tables {
+    pub(crate) categories: Arc<CategoryRegistry>,
+    pub(crate) rules: Arc<RuleSet>,
+    pub(crate) sizes: Arc<SizePatterns>,
+    pub(crate) aliases: Arc<SkuAliases>,
+    pub(crate) wildcards: Arc<Wildcards>,
+    pub(crate) presets: Arc<Presets>,
+    pub(crate) names: Arc<DisplayNames>,
+    pub(crate) quality: Arc<QualityCurve>,
+    pub(crate) offsets: Arc<LayerOffsets>,
+    pub(crate) orientations: Arc<Orientations>,
+    pub(crate) flat_lay: Option<Arc<FlatLay>>,
+    pub(crate) limits: Limits,
+    pub(crate) conflicts: ConflictPolicy,
+    /// Configured views, built-in ones included when given categories
+    pub(crate) views: BTreeMap<&'static str, &'static ViewSpec>,
+}
+
+impl Default for Tables {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl Tables {
+    /// The built-in categories, rules, sizes and presets, and nothing else
+    pub fn builtin() -> Self {
+        Self {
+            categories: Arc::new(CategoryRegistry::builtin()),
+            rules: Arc::new(RuleSet::builtin()),
+            sizes: Arc::new(SizePatterns::builtin()),
+            aliases: Arc::default(),
+            wildcards: Arc::default(),
+            presets: Arc::new(Presets::builtin()),
+            names: Arc::default(),
+            quality: Arc::default(),
+            offsets: Arc::default(),
+            orientations: Arc::default(),
+            flat_lay: None,
+            limits: Limits::default(),
+            conflicts: ConflictPolicy::default(),
+            views: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_categories(mut self, categories: CategoryRegistry) -> Self {
+        self.categories = Arc::new(categories);
+        self
+    }
+
+    pub fn with_rules(mut self, rules: RuleSet) -> Self {
+        self.rules = Arc::new(rules);
+        self
+    }
+
+    pub fn with_sizes(mut self, sizes: SizePatterns) -> Self {
+        self.sizes = Arc::new(sizes);
+        self
+    }
+
+    pub fn with_aliases(mut self, aliases: SkuAliases) -> Self {
+        self.aliases = Arc::new(aliases);
+        self
+    }
+
+    pub fn with_wildcards(mut self, wildcards: Wildcards) -> Self {
+        self.wildcards = Arc::new(wildcards);
+        self
+    }
+
+    pub fn with_presets(mut self, presets: Presets) -> Self {
+        self.presets = Arc::new(presets);
+        self
+    }
+
+    pub fn with_names(mut self, names: DisplayNames) -> Self {
+        self.names = Arc::new(names);
+        self
+    }
+
+    pub fn with_quality_curve(mut self, curve: QualityCurve) -> Self {
+        self.quality = Arc::new(curve);
+        self
+    }
+
+    pub fn with_offsets(mut self, offsets: LayerOffsets) -> Self {
+        self.offsets = Arc::new(offsets);
+        self
+    }
+
+    pub fn with_orientations(mut self, orientations: Orientations) -> Self {
+        self.orientations = Arc::new(orientations);
+        self
+    }
+
+    pub fn with_flat_lay(mut self, flat_lay: FlatLay) -> Self {
+        self.flat_lay = Some(Arc::new(flat_lay));
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_conflicts(mut self, conflicts: ConflictPolicy) -> Self {
+        self.conflicts = conflicts;
+        self
+    }
+
+    /// Register (or redefine) a view so it parses and renders with these tables
+    /// Built-in views can only be given categories, not a plate
+    pub fn register_view(&mut self, name: &str, spec: ViewSpec) -> Result<View> {
+        views::validate(name, &spec)?;
+        let builtin = View::builtin()
+            .into_iter()
+            .find(|view| view.as_str() == name);
+
+        let name = views::intern_name(name);
+        self.views.insert(name, views::intern_spec(spec));
+        Ok(builtin.unwrap_or(View::Custom(name)))
+    }
+
+    /// The tables of this thread's [`Self::scope`], or the installed ones
+    /// (the built-in tables unless others were installed)
+    pub fn current() -> Arc<Tables> {
+        if let Some(tables) = SCOPED.with(|cell| cell.borrow().clone()) {
+            return tables;
+        }
+        if let Some(tables) = INSTALLED.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            return tables.clone();
+        }
+        INSTALLED
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_or_insert_with(|| Arc::new(Self::builtin()))
+            .clone()
+    }
+
+    /// Make these the process-wide tables, replacing any installed before
+    pub fn install(self) {
+        *INSTALLED.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(self));
+    }
+
+    /// Change the process-wide tables, e.g. to reload the presets
+    pub fn update<T>(change: impl FnOnce(&mut Tables) -> T) -> T {
+        let mut installed = INSTALLED.write().unwrap_or_else(|e| e.into_inner());
+        let mut tables = installed.as_deref().cloned().unwrap_or_else(Self::builtin);
+        let changed = change(&mut tables);
+        *installed = Some(Arc::new(tables));
+        changed
+    }
+
+    /// Run `run` with these as the current tables on this thread
+    pub fn scope<T>(self: &Arc<Self>, run: impl FnOnce() -> T) -> T {
+        let outer = SCOPED.with(|cell| cell.replace(Some(self.clone())));
+        let result = run();
+        SCOPED.with(|cell| cell.replace(outer));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::{parse_params, LayerNormalizer};
+
+    #[test]
+    fn test_scope() {
+        let params = parse_params("hoodies/hoodie-black,hoodies/hoodie-grey");
+        let normalize = || LayerNormalizer::new(View::Front, &params).normalize_all(&params);
+        assert_eq!(normalize().len(), 2);
+
+        let tables = Arc::new(Tables::builtin().with_conflicts(ConflictPolicy::LastWins));
+        let normalized = tables.scope(normalize);
+        assert_eq!(normalized.len(), 1);
+        assert_eq!(normalized[0].sku.as_str(), "hoodie-grey");
+        assert_eq!(ConflictPolicy::global(), ConflictPolicy::Stack);
+
+        // Scopes nest, and end with their closure
+        let inner = Arc::new(Tables::builtin().with_conflicts(ConflictPolicy::FirstWins));
+        let policies = tables.scope(|| {
+            (
+                inner.scope(ConflictPolicy::global),
+                ConflictPolicy::global(),
+            )
+        });
+        assert_eq!(
+            policies,
+            (ConflictPolicy::FirstWins, ConflictPolicy::LastWins)
+        );
+        assert_eq!(normalize().len(), 2);
+    }
+}
@@ -0,0 +1,419 @@
+//! Generic in-memory cache tier
+//!
+//! [`TieredCache`] is the memory tier in front of slower sources: encoded
+//! composites in front of the backend cache, fetched layer bytes in front of
+//! storage, decoded images in front of the decoder, and storage listings.
+//! Entries are evicted least recently used first once the cache holds more
+//! than its capacity (a number of entries or of bytes), and can expire after a
+//! TTL. Misses can be cached too, as negative entries (e.g. `Option<Bytes>`
+//! values of `None` for layers that don't exist).
+//!
+//! With the `async` feature, [`TieredCache::get_or_try_insert_with`] loads a
+//! missing key once however many tasks ask for it at the same time.
+
+use lru::LruCache;
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+#[cfg(feature = "async")]
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+/// How much a [`TieredCache`] holds before evicting
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Capacity {
+    #[default]
+    Unbounded,
+    Entries(usize),
+    /// Total weight of the entries, as measured by the cache's weigher
+    Bytes(usize),
+}
+
+/// Counters and size of a [`TieredCache`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TieredStats {
+    pub entries: usize,
+    /// Total weight of the entries (0 unless the capacity is in bytes)
+    pub bytes: usize,
+    pub capacity: Capacity,
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+    /// Entries dropped to stay within the capacity
+    pub evictions: u64,
+    /// Entries dropped because they outlived the TTL
+    pub expirations: u64,
+    /// Loads that waited for another task's load of the same key
+    pub coalesced: u64,
+}
+
+impl TieredStats {
+    /// Share of lookups served from the cache, 0 before any lookup
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+struct Entry<V> {
+    value: V,
+    weight: usize,
+    inserted: Instant,
+}
+
+struct Entries<K: Hash + Eq, V> {
+    lru: LruCache<K, Entry<V>>,
+    weight: usize,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+    coalesced: AtomicU64,
+}
+
+/// A key being loaded by [`TieredCache::get_or_try_insert_with`]
+#[cfg(feature = "async")]
+type Flight<V> = Arc<tokio::sync::OnceCell<V>>;
+
+/// Bounded LRU cache with optional TTL, stats and single-flight loads
+///
+/// ```
+/// use birl_core::TieredCache;
+/// use bytes::Bytes;
+///
+/// let cache: TieredCache<String, Bytes> =
+///     TieredCache::new().with_max_bytes(1024, |data: &Bytes| data.len());
+/// cache.insert("front/hats/cap.png".to_string(), Bytes::from_static(b"cap"));
+/// assert_eq!(cache.stats().bytes, 3);
+/// ```
+pub struct TieredCache<K: Hash + Eq, V> {
+    capacity: Capacity,
+    ttl: Option<Duration>,
+    weigher: fn(&V) -> usize,
+    entries: Mutex<Entries<K, V>>,
+    counters: Counters,
+    #[cfg(feature = "async")]
+    flights: Mutex<HashMap<K, Flight<V>>>,
+}
+
+impl<K: Hash + Eq, V> Default for TieredCache<K, V> {
+    fn default() -> Self {
+        Self {
+            capacity: Capacity::Unbounded,
+            ttl: None,
+            weigher: |_| 0,
+            entries: Mutex::new(Entries {
+                lru: LruCache::unbounded(),
+                weight: 0,
+            }),
+            counters: Counters::default(),
+            #[cfg(feature = "async")]
+            flights: Mutex::default(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> TieredCache<K, V> {
+    /// An unbounded cache; entries never expire
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep at most `max` entries
+    pub fn with_max_entries(mut self, max: usize) -> Self {
+        self.capacity = Capacity::Entries(max);
+        self
+    }
+
+    /// Keep at most `max` bytes of values, as measured by `weigher`
+    pub fn with_max_bytes(mut self, max: usize, weigher: fn(&V) -> usize) -> Self {
+        self.capacity = Capacity::Bytes(max);
+        self.weigher = weigher;
+        self
+    }
+
+    /// Drop entries older than `ttl` instead of serving them
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn capacity(&self) -> Capacity {
+        self.capacity
+    }
+
+    /// The cached value of `key`, marking it recently used
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut entries = self.lock();
+        let expired = match entries.lru.get(key) {
+            Some(entry) if !self.is_expired(entry) => {
+                self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(entry.value.clone());
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            if let Some(entry) = entries.lru.pop(key) {
+                entries.weight -= entry.weight;
+            }
+            self.counters.expirations.fetch_add(1, Ordering::Relaxed);
+        }
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    /// Cache `value`, evicting the least recently used entries past the capacity
+    /// A value heavier than the whole capacity isn't kept
+    pub fn insert(&self, key: K, value: V) {
+        self.insert_aged(key, value, Duration::ZERO);
+    }
+
+    /// [`Self::insert`] a value that is already `age` old, e.g. one loaded from
+    /// a slower cache, so it expires that much sooner
+    pub fn insert_aged(&self, key: K, value: V, age: Duration) {
+        // Older than the clock can tell, so expired under any TTL
+        let Some(inserted) = Instant::now().checked_sub(age) else {
+            return;
+        };
+        let weight = (self.weigher)(&value);
+        let entry = Entry {
+            value,
+            weight,
+            inserted,
+        };
+
+        let mut entries = self.lock();
+        self.counters.inserts.fetch_add(1, Ordering::Relaxed);
+        if let Some(replaced) = entries.lru.put(key, entry) {
+            entries.weight -= replaced.weight;
+        }
+        entries.weight += weight;
+
+        while self.is_over(&entries) {
+            let Some((_, evicted)) = entries.lru.pop_lru() else {
+                break;
+            };
+            entries.weight -= evicted.weight;
+            self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut entries = self.lock();
+        let entry = entries.lru.pop(key)?;
+        entries.weight -= entry.weight;
+        Some(entry.value)
+    }
+
+    /// Drop every entry; the counters are kept
+    pub fn clear(&self) {
+        let mut entries = self.lock();
+        entries.lru.clear();
+        entries.weight = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().lru.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> TieredStats {
+        let (entries, bytes) = {
+            let entries = self.lock();
+            (entries.lru.len(), entries.weight)
+        };
+        let count = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        TieredStats {
+            entries,
+            bytes,
+            capacity: self.capacity,
+            hits: count(&self.counters.hits),
+            misses: count(&self.counters.misses),
+            inserts: count(&self.counters.inserts),
+            evictions: count(&self.counters.evictions),
+            expirations: count(&self.counters.expirations),
+            coalesced: count(&self.counters.coalesced),
+        }
+    }
+
+    /// The cached value of `key`, or the value `load` produces, which is cached
+    /// Concurrent calls for the same key share one load; a failed load isn't
+    /// cached, and the next caller tries again
+    #[cfg(feature = "async")]
+    pub async fn get_or_try_insert_with<F, Fut, E>(&self, key: K, load: F) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        if let Some(value) = self.get(&key) {
+            return Ok(value);
+        }
+
+        let flight = self
+            .flights
+            .lock()
+            .expect("cache flights lock poisoned")
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let mut loaded = false;
+        let result = flight
+            .get_or_try_init(|| {
+                loaded = true;
+                load()
+            })
+            .await
+            .cloned();
+
+        if !loaded {
+            self.counters.coalesced.fetch_add(1, Ordering::Relaxed);
+            return result;
+        }
+        // Cache before ending the flight, so later callers find the value
+        if let Ok(value) = &result {
+            self.insert(key.clone(), value.clone());
+        }
+        self.flights
+            .lock()
+            .expect("cache flights lock poisoned")
+            .remove(&key);
+        result
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries<K, V>> {
+        self.entries.lock().expect("cache lock poisoned")
+    }
+
+    fn is_expired(&self, entry: &Entry<V>) -> bool {
+        self.ttl.is_some_and(|ttl| entry.inserted.elapsed() >= ttl)
+    }
+
+    fn is_over(&self, entries: &Entries<K, V>) -> bool {
+        match self.capacity {
+            Capacity::Unbounded => false,
+            Capacity::Entries(max) => entries.lru.len() > max,
+            Capacity::Bytes(max) => entries.weight > max,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_entries() {
+        let cache = TieredCache::new().with_max_entries(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get(&"a"), Some(1));
+        cache.insert("c", 3);
+
+        // "b" was the least recently used
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!((cache.get(&"a"), cache.get(&"c")), (Some(1), Some(3)));
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.capacity), (2, Capacity::Entries(2)));
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (3, 1, 1));
+        assert_eq!(stats.hit_rate(), 0.75);
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let cache: TieredCache<&str, Vec<u8>> = TieredCache::new().with_max_bytes(10, Vec::len);
+        cache.insert("a", vec![0; 4]);
+        cache.insert("b", vec![0; 4]);
+        cache.insert("a", vec![0; 6]);
+        assert_eq!((cache.len(), cache.stats().bytes), (2, 10));
+
+        cache.insert("c", vec![0; 3]);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.stats().bytes, 9);
+
+        // Too heavy to keep at all
+        cache.insert("d", vec![0; 11]);
+        assert!(cache.is_empty());
+        assert_eq!(cache.stats().bytes, 0);
+    }
+
+    #[test]
+    fn test_ttl() {
+        let cache = TieredCache::new().with_ttl(Duration::ZERO);
+        cache.insert("a", 1);
+        assert_eq!(cache.get(&"a"), None);
+        assert!(cache.is_empty());
+        assert_eq!(cache.stats().expirations, 1);
+
+        let cache = TieredCache::new().with_ttl(Duration::from_secs(60));
+        cache.insert("a", 1);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.remove(&"a"), Some(1));
+        assert_eq!(cache.get(&"a"), None);
+
+        // An aged value has less of its TTL left
+        cache.insert_aged("b", 2, Duration::from_secs(59));
+        assert_eq!(cache.get(&"b"), Some(2));
+        cache.insert_aged("b", 2, Duration::from_secs(60));
+        assert_eq!(cache.get(&"b"), None);
+    }
+
+    #[tokio::test]
+    async fn test_single_flight() {
+        use std::sync::atomic::AtomicUsize;
+
+        let cache: TieredCache<&str, Option<u32>> = TieredCache::new();
+        let loads = AtomicUsize::new(0);
+        let load = |value: Option<u32>| {
+            let loads = &loads;
+            move || async move {
+                loads.fetch_add(1, Ordering::Relaxed);
+                tokio::task::yield_now().await;
+                Ok::<_, String>(value)
+            }
+        };
+
+        let (a, b) = tokio::join!(
+            cache.get_or_try_insert_with("cap", load(Some(1))),
+            cache.get_or_try_insert_with("cap", load(Some(2))),
+        );
+        assert_eq!((a, b), (Ok(Some(1)), Ok(Some(1))));
+        // Negative entries are cached like any other value
+        let missing = cache.get_or_try_insert_with("hat", load(None)).await;
+        assert_eq!(missing, Ok(None));
+        assert_eq!(cache.get(&"hat"), Some(None));
+        assert_eq!(loads.load(Ordering::Relaxed), 2);
+        assert_eq!(cache.stats().coalesced, 1);
+
+        // Failures aren't cached
+        let failed = cache
+            .get_or_try_insert_with("scarf", || async { Err("offline".to_string()) })
+            .await;
+        assert!(failed.is_err());
+        assert_eq!(
+            cache.get_or_try_insert_with("scarf", load(Some(3))).await,
+            Ok(Some(3))
+        );
+    }
+}
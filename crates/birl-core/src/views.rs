@@ -0,0 +1,259 @@
+//! Views defined in configuration, beyond the built-in five
+//!
+//! New photography angles (three-quarter, top-down, detail shots) are declared
+//! under `[views]` in the config file:
+//!
+//! ```toml
+//! [views.three-quarter]
+//! plate = "base-model-black-34"
+//! categories = ["pants", "tops", "hoodies", "jackets"]
+//! ```
+//!
+//! Registered views parse like the built-in ones and render from
+//! `birl/{view}/...` in storage.
+//!
+//! Built-in views keep their plates, but can list more categories, e.g. once
+//! pants are photographed for the profile views:
+//!
+//! ```toml
+//! [views.left]
+//! categories = ["pants"]
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+use crate::error::{BirlError, Result};
+use crate::models::View;
+use crate::tables::Tables;
+
+/// Every view name registered or declared so far, leaked once each
+static NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+/// Every view definition registered so far, leaked once each
+static SPECS: Mutex<Vec<&'static ViewSpec>> = Mutex::new(Vec::new());
+
+thread_local! {
+    /// Views of the config being parsed on this thread (see [`with_declared`])
+    static DECLARED: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Definition of a configured view
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ViewSpec {
+    /// Plate the view renders on (built-in views keep their own)
+    #[serde(default)]
+    pub plate: String,
+    /// Categories rendered in this view, in addition to those listing it in
+    /// their own `views` (default: every category)
+    pub categories: Option<Vec<String>>,
+}
+
+impl ViewSpec {
+    pub fn new(plate: impl Into<String>) -> Self {
+        Self {
+            plate: plate.into(),
+            categories: None,
+        }
+    }
+
+    pub fn with_categories(mut self, categories: &[&str]) -> Self {
+        self.categories = Some(categories.iter().map(|c| c.to_string()).collect());
+        self
+    }
+
+    /// Whether the spec restricts which categories render
+    pub fn lists(&self, category: &str) -> Option<bool> {
+        self.categories
+            .as_ref()
+            .map(|categories| categories.iter().any(|c| c == category))
+    }
+}
+
+/// Check a view definition as [`Tables::register_view`] would, without
+/// registering it
+pub fn validate(name: &str, spec: &ViewSpec) -> Result<()> {
+    let builtin = is_builtin(name);
+    if builtin && !spec.plate.is_empty() {
+        return Err(BirlError::InvalidParam(format!(
+            "'{}' is a built-in view: only its categories can be configured",
+            name
+        )));
+    }
+    if !builtin && spec.plate.is_empty() {
+        return Err(BirlError::InvalidParam(format!(
+            "View '{}' needs a plate",
+            name
+        )));
+    }
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(BirlError::InvalidParam(format!(
+            "Invalid view name '{}': use letters, digits and '-'",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Run `parse` with `names` parsing as views on this thread, without
+/// registering them, so a config can refer to the views it defines before
+/// they're installed
+pub fn with_declared<T>(names: &[&str], parse: impl FnOnce() -> T) -> T {
+    let declared: Vec<&'static str> = names
+        .iter()
+        .filter(|name| !is_builtin(name))
+        .map(|name| intern_name(name))
+        .collect();
+    let outer = DECLARED.with(|cell| cell.replace(declared));
+    let parsed = parse();
+    DECLARED.with(|cell| cell.replace(outer));
+    parsed
+}
+
+/// `name` as a `&'static str`, the same one each time it's registered or
+/// declared, so reloading a config doesn't leak its views again
+pub(crate) fn intern_name(name: &str) -> &'static str {
+    let mut names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(name) = names.get(name) {
+        return name;
+    }
+    let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+    names.insert(name);
+    name
+}
+
+/// `spec` as a `&'static ViewSpec`, reusing an equal one registered before
+pub(crate) fn intern_spec(spec: ViewSpec) -> &'static ViewSpec {
+    let mut specs = SPECS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(interned) = specs.iter().find(|interned| ***interned == spec) {
+        return interned;
+    }
+    let spec: &'static ViewSpec = Box::leak(Box::new(spec));
+    specs.push(spec);
+    spec
+}
+
+fn is_builtin(name: &str) -> bool {
+    View::builtin().iter().any(|view| view.as_str() == name)
+}
+
+/// A registered view by name, or one declared by the config being parsed
+pub fn lookup(name: &str) -> Option<View> {
+    let registered = Tables::current()
+        .views
+        .get_key_value(name)
+        .filter(|(name, _)| !is_builtin(name))
+        .map(|(name, _)| View::Custom(name));
+    registered.or_else(|| {
+        DECLARED.with(|cell| {
+            let declared = cell.borrow();
+            declared
+                .iter()
+                .find(|declared| **declared == name)
+                .map(|declared| View::Custom(declared))
+        })
+    })
+}
+
+/// Definition of a registered view, or the categories configured for a
+/// built-in one
+pub fn spec(name: &str) -> Option<&'static ViewSpec> {
+    Tables::current().views.get(name).copied()
+}
+
+/// Every registered view, by name
+pub fn registered() -> Vec<View> {
+    Tables::current()
+        .views
+        .keys()
+        .filter(|name| !is_builtin(name))
+        .map(|name| View::Custom(name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_register() {
+        let mut tables = Tables::builtin();
+        let view = tables
+            .register_view(
+                "three-quarter",
+                ViewSpec::new("base-model-black-34").with_categories(&["hoodies"]),
+            )
+            .unwrap();
+
+        Arc::new(tables).scope(|| {
+            assert_eq!(view.as_str(), "three-quarter");
+            assert_eq!(view.plate_value(), "base-model-black-34");
+            assert_eq!("three-quarter".parse::<View>().unwrap(), view);
+            assert_eq!(
+                serde_json::from_str::<View>("\"three-quarter\"").unwrap(),
+                view
+            );
+            assert!(View::all().contains(&view));
+        });
+        assert!("three-quarter".parse::<View>().is_err());
+
+        let declared = with_declared(&["bird-eye"], || "bird-eye".parse::<View>());
+        assert_eq!(declared.unwrap().as_str(), "bird-eye");
+        assert!("bird-eye".parse::<View>().is_err());
+
+        let mut tables = Tables::builtin();
+        assert!(tables.register_view("front", ViewSpec::new("x")).is_err());
+        assert!(tables.register_view("top-down", ViewSpec::new("")).is_err());
+        assert!(tables
+            .register_view("top down", ViewSpec::new("x"))
+            .is_err());
+        assert!("top-down".parse::<View>().is_err());
+    }
+
+    #[test]
+    fn test_interned() {
+        // Registering or declaring a view again reuses its name and definition
+        let spec = ViewSpec::new("base-model-black-side").with_categories(&["boots"]);
+        let (mut first, mut second) = (Tables::builtin(), Tables::builtin());
+        let view = first.register_view("detail", spec.clone()).unwrap();
+        assert_eq!(second.register_view("detail", spec.clone()).unwrap(), view);
+        assert!(std::ptr::eq(first.views["detail"], second.views["detail"]));
+        let names = (first.views.keys().next(), second.views.keys().next());
+        assert!(std::ptr::eq(*names.0.unwrap(), *names.1.unwrap()));
+
+        let declared = with_declared(&["detail"], || lookup("detail")).unwrap();
+        assert!(std::ptr::eq(declared.as_str(), view.as_str()));
+
+        second
+            .register_view("detail", ViewSpec::new("base-model-black-side"))
+            .unwrap();
+        assert!(!std::ptr::eq(first.views["detail"], second.views["detail"]));
+    }
+
+    #[test]
+    fn test_builtin_categories() {
+        let registry = crate::CategoryRegistry::builtin();
+        assert!(!registry.allows("skirts", View::Left));
+
+        let mut tables = Tables::builtin();
+        let view = tables
+            .register_view("left", ViewSpec::default().with_categories(&["skirts"]))
+            .unwrap();
+        assert_eq!(view, View::Left);
+        Arc::new(tables).scope(|| {
+            assert_eq!(view.plate_value(), "patch-plate");
+            assert!(registry.allows("skirts", View::Left));
+            assert!(registry.allows("hoodies", View::Left));
+            assert!(!registry.allows("boots", View::Left));
+            assert!(lookup("left").is_none());
+            assert_eq!(
+                View::all().iter().filter(|v| v.as_str() == "left").count(),
+                1
+            );
+        });
+        assert!(!registry.allows("skirts", View::Left));
+    }
+}
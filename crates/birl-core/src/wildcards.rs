@@ -0,0 +1,191 @@
+//! `category/*` params: a SKU of the category chosen from the catalog
+//!
+//! ```json
+//! {"categories": {"hoodies": {"default": "hoodie-black", "skus": ["hoodie-black", "hoodie-grey"]}}}
+//! ```
+//!
+//! `hoodies/*` renders the category's `default` SKU when it has a layer for
+//! the view, and otherwise one of its SKUs that do, picked by the rest of the
+//! outfit: the same outfit always gets the same SKU (and cache key), while
+//! different outfits spread over the category. Wildcards are resolved with the
+//! aliases, before the normalization rules, so they name catalog (storage)
+//! categories. Without a catalog, or for a category it doesn't list, the
+//! wildcard is left as is and reported missing like any unknown SKU.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use xxhash_rust::xxh64::xxh64;
+
+use crate::catalog::Catalog;
+use crate::models::{LayerParam, Sku, View};
+use crate::random::SplitMix64;
+use crate::tables::Tables;
+
+/// SKU of a param that asks for any SKU of its category
+pub const WILDCARD: &str = "*";
+
+/// SKUs a wildcard of each category can resolve to
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Wildcards {
+    categories: BTreeMap<String, Choices>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Choices {
+    default: Option<Sku>,
+    /// Every SKU, with the views it has layers for
+    skus: Vec<(Sku, Vec<View>)>,
+}
+
+impl Choices {
+    fn in_view(&self, sku: &Sku, view: View) -> bool {
+        self.skus
+            .iter()
+            .any(|(candidate, views)| candidate == sku && views.contains(&view))
+    }
+}
+
+impl Wildcards {
+    pub fn from_catalog(catalog: &Catalog) -> Self {
+        let categories = catalog
+            .categories
+            .iter()
+            .map(|(name, category)| {
+                let choices = Choices {
+                    default: category.default.as_deref().map(Sku::new),
+                    skus: category
+                        .skus
+                        .iter()
+                        .map(|entry| (entry.sku(), category.views_of(entry).to_vec()))
+                        .collect(),
+                };
+                (name.clone(), choices)
+            })
+            .collect();
+        Self { categories }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.categories.is_empty()
+    }
+
+    /// The SKU a wildcard of `category` resolves to in `view`, picked by `seed`
+    /// when the category has no default for the view
+    pub fn resolve(&self, category: &str, view: View, seed: u64) -> Option<Sku> {
+        let choices = self.categories.get(category)?;
+        if let Some(default) = &choices.default {
+            if choices.in_view(default, view) {
+                return Some(default.clone());
+            }
+        }
+
+        let skus: Vec<&Sku> = choices
+            .skus
+            .iter()
+            .filter(|(_, views)| views.contains(&view))
+            .map(|(sku, _)| sku)
+            .collect();
+        if skus.is_empty() {
+            return None;
+        }
+        Some(skus[SplitMix64(seed).below(skus.len())].clone())
+    }
+
+    /// The layer with a wildcard SKU resolved; other layers are returned as-is
+    pub fn apply(&self, param: &LayerParam, view: View, seed: u64) -> LayerParam {
+        if !is_wildcard(param) {
+            return param.clone();
+        }
+        match self.resolve(&param.category, view, seed) {
+            Some(sku) => LayerParam {
+                sku,
+                ..param.clone()
+            },
+            None => param.clone(),
+        }
+    }
+
+    /// Every param of an outfit with its wildcards resolved, as
+    /// [`crate::LayerNormalizer`] resolves them
+    /// For rendering parts of an outfit with the SKUs the whole one gets
+    pub fn apply_all(&self, params: &[LayerParam], view: View) -> Vec<LayerParam> {
+        let seed = outfit_seed(params, view);
+        params
+            .iter()
+            .map(|param| self.apply(param, view, seed))
+            .collect()
+    }
+
+    /// The current tables' wildcard choices (none unless some were installed)
+    pub fn global() -> Arc<Wildcards> {
+        Tables::current().wildcards.clone()
+    }
+}
+
+/// Whether a param asks for any SKU of its category
+pub fn is_wildcard(param: &LayerParam) -> bool {
+    !param.is_remote() && param.sku.as_str() == WILDCARD
+}
+
+/// Seed of an outfit's wildcards: a hash of its view and other params, in the
+/// order requested
+pub fn outfit_seed(params: &[LayerParam], view: View) -> u64 {
+    let outfit: Vec<String> = params
+        .iter()
+        .filter(|param| !is_wildcard(param))
+        .map(ToString::to_string)
+        .collect();
+    xxh64(format!("{}|{}", view, outfit.join(",")).as_bytes(), 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::parse_params;
+
+    const CATALOG: &str = r#"{
+        "categories": {
+            "hoodies": {"default": "hoodie-grey", "skus": [
+                "hoodie-black", {"sku": "hoodie-grey", "views": ["front", "back"]}, "zip-hood-black"
+            ]},
+            "hats": {"skus": ["beanie-black", "beanie-red", "cap-red"]},
+            "pants": {"views": ["front"], "skus": ["cargo-black"]}
+        }
+    }"#;
+
+    #[test]
+    fn test_resolve() {
+        let wildcards = Wildcards::from_catalog(&Catalog::from_json(CATALOG).unwrap());
+        let params = parse_params("hoodies/*,hats/*,scarves/*");
+        assert!(params.iter().all(is_wildcard));
+
+        // The default, where it has a layer
+        let hoodie = wildcards.apply(&params[0], View::Front, 1);
+        assert_eq!(hoodie, LayerParam::new("hoodies", "hoodie-grey"));
+        let hoodie = wildcards.apply(&params[0], View::Left, 1);
+        assert_ne!(hoodie.sku.as_str(), "hoodie-grey");
+        assert_ne!(hoodie.sku.as_str(), WILDCARD);
+
+        // Otherwise a SKU picked by the seed, the same for the same seed
+        let picks: Vec<Sku> = (0..20)
+            .map(|seed| wildcards.resolve("hats", View::Front, seed).unwrap())
+            .collect();
+        assert_eq!(picks[3], wildcards.resolve("hats", View::Front, 3).unwrap());
+        assert!(picks.iter().any(|sku| sku != &picks[0]));
+
+        // Categories without SKUs for the view, or not catalogued, stay wildcards
+        assert_eq!(wildcards.resolve("pants", View::Back, 1), None);
+        assert_eq!(wildcards.apply(&params[2], View::Front, 1), params[2]);
+
+        let seed = outfit_seed(&parse_params("hats/*,pants/cargo-black"), View::Front);
+        assert_eq!(
+            seed,
+            outfit_seed(&parse_params("pants/cargo-black"), View::Front)
+        );
+        assert_ne!(
+            seed,
+            outfit_seed(&parse_params("pants/cargo-black"), View::Back)
+        );
+    }
+}
@@ -0,0 +1,491 @@
+//! Public API snapshots of the library crates
+//!
+//! `birl-core` and `birl-storage` list their public surface in
+//! `public-api.txt`, and a test compares it with [`check`]. The surface is read
+//! from the crate's sources with `syn`, following `mod` declarations from
+//! `src/lib.rs`: one line per public item with its full signature, and one per
+//! public field, enum variant, trait item and public method, with the derives
+//! and `#[non_exhaustive]` markers callers rely on.
+//!
+//! Public items of private modules are listed too, since a `pub use` can expose
+//! them; test modules are left out.
+
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+use quote::ToTokens;
+use syn::{Attribute, Fields, ImplItem, Item, TraitItem, Type, UseTree, Visibility};
+
+/// Attributes that change what callers may do with an item
+const KEPT_ATTRIBUTES: &[&str] = &[
+    "cfg",
+    "deprecated",
+    "derive",
+    "must_use",
+    "non_exhaustive",
+    "repr",
+];
+
+/// Compare the public API of the crate at `manifest_dir` with its
+/// `public-api.txt`, rewriting the file first when `BIRL_BLESS` is set
+pub fn check(manifest_dir: &str) {
+    let dir = Path::new(manifest_dir);
+    let api = public_api(&dir.join("src/lib.rs")).unwrap_or_else(|e| panic!("{}", e));
+
+    let path = dir.join("public-api.txt");
+    if std::env::var_os("BIRL_BLESS").is_some() {
+        std::fs::write(&path, &api).unwrap();
+    }
+    let snapshot = std::fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        snapshot == api,
+        "Public API changed, rerun with BIRL_BLESS=1 to accept it:\n{}",
+        changes(&snapshot, &api)
+    );
+}
+
+/// The public API of the crate rooted at `lib`, one line per item or member
+pub fn public_api(lib: &Path) -> Result<String, String> {
+    let mut modules = Vec::new();
+    let dir = lib.parent().unwrap_or(Path::new("."));
+    read_module("crate".to_string(), lib, dir.to_path_buf(), &mut modules)?;
+
+    // Impls only belong to the API when their type does
+    let types: HashSet<String> = modules
+        .iter()
+        .flat_map(|module| &module.items)
+        .filter_map(public_type)
+        .collect();
+
+    let mut api = String::new();
+    for module in &modules {
+        for item in &module.items {
+            for line in describe(item, &types) {
+                api.push_str(&format!("{}: {}\n", module.path, line));
+            }
+        }
+    }
+    Ok(api)
+}
+
+/// Lines only in `old` (`-`) and only in `new` (`+`)
+fn changes(old: &str, new: &str) -> String {
+    let old: BTreeSet<&str> = old.lines().collect();
+    let new: BTreeSet<&str> = new.lines().collect();
+    let removed = old.difference(&new).map(|line| format!("- {}", line));
+    let added = new.difference(&old).map(|line| format!("+ {}", line));
+    removed.chain(added).collect::<Vec<_>>().join("\n")
+}
+
+/// A module's path from the crate root and its items
+struct Module {
+    path: String,
+    items: Vec<Item>,
+}
+
+/// Parse `file` as the module `path`, whose child modules live in `dir`, and
+/// every module declared in it
+fn read_module(
+    path: String,
+    file: &Path,
+    dir: PathBuf,
+    out: &mut Vec<Module>,
+) -> Result<(), String> {
+    let source = std::fs::read_to_string(file)
+        .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+    let parsed = syn::parse_file(&source)
+        .map_err(|e| format!("Failed to parse {}: {}", file.display(), e))?;
+    collect_module(path, parsed.items, dir, out)
+}
+
+fn collect_module(
+    path: String,
+    items: Vec<Item>,
+    dir: PathBuf,
+    out: &mut Vec<Module>,
+) -> Result<(), String> {
+    let items: Vec<Item> = items
+        .into_iter()
+        .filter(|item| !is_test(attrs(item)))
+        .collect();
+    let children: Vec<_> = items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Mod(module) => Some(module.clone()),
+            _ => None,
+        })
+        .collect();
+    out.push(Module {
+        path: path.clone(),
+        items,
+    });
+
+    for module in children {
+        let name = module.ident.to_string();
+        let child = format!("{}::{}", path, name);
+        match module.content {
+            Some((_, items)) => collect_module(child, items, dir.join(&name), out)?,
+            None => {
+                let file = dir.join(format!("{}.rs", name));
+                let file = match file.exists() {
+                    true => file,
+                    false => dir.join(&name).join("mod.rs"),
+                };
+                read_module(child, &file, dir.join(&name), out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lines describing a top-level item, none if it isn't public
+fn describe(item: &Item, types: &HashSet<String>) -> Vec<String> {
+    let mut lines = Vec::new();
+    match item {
+        Item::Mod(item) if is_pub(&item.vis) => {
+            lines.push(format!("{}pub mod {}", kept(&item.attrs), item.ident));
+        }
+        Item::Use(item) if is_pub(&item.vis) => {
+            let mut paths = Vec::new();
+            use_paths(&item.tree, String::new(), &mut paths);
+            lines.extend(paths.into_iter().map(|path| format!("pub use {}", path)));
+        }
+        Item::Fn(item) if is_pub(&item.vis) => {
+            lines.push(format!("{}pub {}", kept(&item.attrs), tokens(&item.sig)));
+        }
+        Item::Const(item) if is_pub(&item.vis) => {
+            lines.push(format!("pub const {}: {}", item.ident, tokens(&item.ty)));
+        }
+        Item::Static(item) if is_pub(&item.vis) => {
+            lines.push(format!("pub static {}: {}", item.ident, tokens(&item.ty)));
+        }
+        Item::Type(item) if is_pub(&item.vis) => {
+            let mut item = item.clone();
+            item.attrs.retain(is_kept);
+            lines.push(tokens(&item));
+        }
+        Item::Macro(item) if item.attrs.iter().any(|a| a.path().is_ident("macro_export")) => {
+            if let Some(ident) = &item.ident {
+                lines.push(format!("macro {}!", ident));
+            }
+        }
+        Item::Struct(item) if is_pub(&item.vis) => {
+            let name = format!("struct {}", item.ident);
+            let mut header = format!(
+                "{}pub {}{}{}",
+                kept(&item.attrs),
+                name,
+                tokens(&item.generics),
+                where_clause(&item.generics)
+            );
+            if item.fields.iter().any(|field| !is_pub(&field.vis)) {
+                header.push_str(" (private fields)");
+            }
+            lines.push(header);
+            lines.extend(
+                field_lines(&item.fields)
+                    .into_iter()
+                    .map(|field| format!("{} :: {}", name, field)),
+            );
+        }
+        Item::Enum(item) if is_pub(&item.vis) => {
+            let name = format!("enum {}", item.ident);
+            lines.push(format!(
+                "{}pub {}{}{}",
+                kept(&item.attrs),
+                name,
+                tokens(&item.generics),
+                where_clause(&item.generics)
+            ));
+            for variant in &item.variants {
+                let mut variant = variant.clone();
+                variant.attrs.retain(is_kept);
+                for field in variant.fields.iter_mut() {
+                    field.attrs.retain(is_kept);
+                }
+                lines.push(format!("{} :: {}", name, tokens(&variant)));
+            }
+        }
+        Item::Trait(item) if is_pub(&item.vis) => {
+            let mut header = item.clone();
+            header.attrs.retain(is_kept);
+            header.items.clear();
+            let header = tokens(&header);
+            lines.push(header.trim_end_matches("{ }").trim_end().to_string());
+            let name = format!("trait {}", item.ident);
+            for member in &item.items {
+                if let Some(member) = trait_item(member) {
+                    lines.push(format!("{} :: {}", name, member));
+                }
+            }
+        }
+        Item::Impl(item) => {
+            let Some(self_type) = type_name(&item.self_ty) else {
+                return lines;
+            };
+            if !types.contains(&self_type) {
+                return lines;
+            }
+            let self_ty = tokens(&item.self_ty);
+            let generics = tokens(&item.generics);
+            match &item.trait_ {
+                Some((negative, path, _)) => lines.push(format!(
+                    "{}impl{} {}{} for {}{}",
+                    kept(&item.attrs),
+                    generics,
+                    if negative.is_some() { "!" } else { "" },
+                    tidy(&path.to_token_stream().to_string()),
+                    self_ty,
+                    where_clause(&item.generics)
+                )),
+                None => {
+                    let owner = format!("impl{} {}", generics, self_ty);
+                    for member in &item.items {
+                        if let Some(member) = impl_item(member) {
+                            lines.push(format!("{} :: {}", owner, member));
+                        }
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    lines
+}
+
+fn field_lines(fields: &Fields) -> Vec<String> {
+    fields
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| is_pub(&field.vis))
+        .map(|(idx, field)| {
+            let name = match &field.ident {
+                Some(ident) => ident.to_string(),
+                None => idx.to_string(),
+            };
+            format!("{}pub {}: {}", kept(&field.attrs), name, tokens(&field.ty))
+        })
+        .collect()
+}
+
+/// A trait item's signature, marked "(provided)" when it has a default
+fn trait_item(item: &TraitItem) -> Option<String> {
+    let (line, provided) = match item {
+        TraitItem::Fn(item) => (
+            format!("{}{}", kept(&item.attrs), tokens(&item.sig)),
+            item.default.is_some(),
+        ),
+        TraitItem::Const(item) => (
+            format!("const {}: {}", item.ident, tokens(&item.ty)),
+            item.default.is_some(),
+        ),
+        TraitItem::Type(item) => {
+            let mut item = item.clone();
+            let provided = item.default.take().is_some();
+            item.attrs.retain(is_kept);
+            (tokens(&item), provided)
+        }
+        _ => return None,
+    };
+    Some(match provided {
+        true => format!("{} (provided)", line),
+        false => line,
+    })
+}
+
+/// A public method or constant of an inherent impl
+fn impl_item(item: &ImplItem) -> Option<String> {
+    match item {
+        ImplItem::Fn(item) if is_pub(&item.vis) => {
+            Some(format!("{}pub {}", kept(&item.attrs), tokens(&item.sig)))
+        }
+        ImplItem::Const(item) if is_pub(&item.vis) => {
+            Some(format!("pub const {}: {}", item.ident, tokens(&item.ty)))
+        }
+        _ => None,
+    }
+}
+
+/// Every path a use tree brings in, e.g. `a::{b, c as d}` as `a::b` and `a::c as d`
+fn use_paths(tree: &UseTree, prefix: String, out: &mut Vec<String>) {
+    match tree {
+        UseTree::Path(path) => use_paths(&path.tree, format!("{}{}::", prefix, path.ident), out),
+        UseTree::Name(name) => out.push(format!("{}{}", prefix, name.ident)),
+        UseTree::Rename(rename) => {
+            out.push(format!("{}{} as {}", prefix, rename.ident, rename.rename))
+        }
+        UseTree::Glob(_) => out.push(format!("{}*", prefix)),
+        UseTree::Group(group) => {
+            for tree in &group.items {
+                use_paths(tree, prefix.clone(), out);
+            }
+        }
+    }
+}
+
+/// Name of a public struct, enum, union or type alias
+fn public_type(item: &Item) -> Option<String> {
+    match item {
+        Item::Struct(item) if is_pub(&item.vis) => Some(item.ident.to_string()),
+        Item::Enum(item) if is_pub(&item.vis) => Some(item.ident.to_string()),
+        Item::Union(item) if is_pub(&item.vis) => Some(item.ident.to_string()),
+        Item::Type(item) if is_pub(&item.vis) => Some(item.ident.to_string()),
+        _ => None,
+    }
+}
+
+/// Last path segment of a type, e.g. `View` for `crate::View`
+fn type_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(path) => path.path.segments.last().map(|s| s.ident.to_string()),
+        _ => None,
+    }
+}
+
+fn attrs(item: &Item) -> &[Attribute] {
+    match item {
+        Item::Const(item) => &item.attrs,
+        Item::Enum(item) => &item.attrs,
+        Item::Fn(item) => &item.attrs,
+        Item::Impl(item) => &item.attrs,
+        Item::Macro(item) => &item.attrs,
+        Item::Mod(item) => &item.attrs,
+        Item::Static(item) => &item.attrs,
+        Item::Struct(item) => &item.attrs,
+        Item::Trait(item) => &item.attrs,
+        Item::Type(item) => &item.attrs,
+        Item::Union(item) => &item.attrs,
+        Item::Use(item) => &item.attrs,
+        _ => &[],
+    }
+}
+
+fn is_test(attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .any(|attr| tidy(&attr.meta.to_token_stream().to_string()) == "cfg(test)")
+}
+
+fn is_pub(vis: &Visibility) -> bool {
+    matches!(vis, Visibility::Public(_))
+}
+
+fn is_kept(attr: &Attribute) -> bool {
+    KEPT_ATTRIBUTES
+        .iter()
+        .any(|name| attr.path().is_ident(name))
+}
+
+/// The kept attributes, each followed by a space
+fn kept(attrs: &[Attribute]) -> String {
+    attrs
+        .iter()
+        .filter(|attr| is_kept(attr))
+        .map(|attr| format!("{} ", tokens(attr)))
+        .collect()
+}
+
+fn where_clause(generics: &syn::Generics) -> String {
+    match &generics.where_clause {
+        Some(clause) => format!(" {}", tokens(clause)),
+        None => String::new(),
+    }
+}
+
+fn tokens(item: &impl ToTokens) -> String {
+    tidy(&item.to_token_stream().to_string())
+}
+
+/// Token stream text without the spaces `quote` puts around punctuation
+fn tidy(tokens: &str) -> String {
+    let mut text = tokens.to_string();
+    for (from, to) in [
+        (" :: ", "::"),
+        (":: ", "::"),
+        (" : ", ": "),
+        ("# [", "#["),
+        ("( ", "("),
+        (" )", ")"),
+        ("[ ", "["),
+        (" ]", "]"),
+        (" ,", ","),
+        (" ;", ";"),
+        (",)", ")"),
+        (" < ", "<"),
+        ("< ", "<"),
+        (" >", ">"),
+        ("& ", "&"),
+        (" ?", "?"),
+        ("! ", "!"),
+    ] {
+        text = text.replace(from, to);
+    }
+
+    // `name (args)` to `name(args)`, keeping `-> (A, B)` apart
+    let mut tidied = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let call = tidied
+            .chars()
+            .last()
+            .is_some_and(|last| last.is_alphanumeric() || last == '_' || last == '>')
+            && !tidied.ends_with("->");
+        if c == ' ' && chars.peek() == Some(&'(') && call {
+            continue;
+        }
+        tidied.push(c);
+    }
+    tidied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_public_api() {
+        let dir = std::env::temp_dir().join(format!("birl-public-api-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(
+            dir.join("src/lib.rs"),
+            "pub mod shapes;\nmod hidden {}\n#[cfg(test)]\nmod tests { pub fn helper() {} }\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("src/shapes.rs"),
+            r#"
+            /// Docs are left out
+            #[derive(Debug)]
+            #[non_exhaustive]
+            pub enum Shape { Circle { radius: f32 }, Square(f32) }
+
+            pub struct Size { pub width: u32, height: u32 }
+
+            impl Size {
+                pub fn area(&self) -> u64 { 0 }
+                fn private(&self) {}
+            }
+
+            pub trait Draw { fn draw(&self, scale: f32) -> String { String::new() } }
+            "#,
+        )
+        .unwrap();
+
+        let api = public_api(&dir.join("src/lib.rs")).unwrap();
+        assert_eq!(
+            api.lines().collect::<Vec<_>>(),
+            [
+                "crate: pub mod shapes",
+                "crate::shapes: #[derive(Debug)] #[non_exhaustive] pub enum Shape",
+                "crate::shapes: enum Shape :: Circle { radius: f32 }",
+                "crate::shapes: enum Shape :: Square(f32)",
+                "crate::shapes: pub struct Size (private fields)",
+                "crate::shapes: struct Size :: pub width: u32",
+                "crate::shapes: impl Size :: pub fn area(&self) -> u64",
+                "crate::shapes: pub trait Draw",
+                "crate::shapes: trait Draw :: fn draw(&self, scale: f32) -> String (provided)",
+            ]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
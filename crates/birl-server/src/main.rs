@@ -1,18 +1,25 @@
 mod middleware;
+mod negotiation;
+mod options;
 mod routes;
+mod state;
 
 use axum::{
-    middleware::from_fn,
+    middleware::from_fn_with_state,
     routing::{get, post},
     Router,
 };
-use birl_storage::StorageService;
+use birl_core::Presets;
+use birl_storage::{Config, Prefetcher, RequestRecorder, RollupRecorder, StorageService};
+use negotiation::CapabilityClasses;
+use state::AppState;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tower_http::{
     cors::{Any, CorsLayer},
     trace::TraceLayer,
 };
-use tracing::{info, Level};
+use tracing::{error, info, warn, Level};
 use tracing_subscriber::FmtSubscriber;
 
 #[tokio::main]
@@ -23,18 +30,114 @@ async fn main() -> anyhow::Result<()> {
         .finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
-    // Load AWS configuration
-    let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
-    let s3_client = aws_sdk_s3::Client::new(&aws_config);
+    // Resolve the profile (--profile / APP_PROFILE, --config / BIRL_CONFIG)
+    let config_path = arg_value("--config").map(PathBuf::from);
+    let profile_name =
+        arg_value("--profile").or_else(|| std::env::var(birl_storage::config::PROFILE_ENV).ok());
+    let config = Config::discover(config_path.as_deref())?;
+    let profile = config.profile(profile_name.as_deref())?;
+    if let Some(name) = &profile_name {
+        info!("Using profile: {}", name);
+    }
+    let tables = config.tables()?;
+    tables.clone().install();
 
-    // Get bucket name from environment
-    let bucket_name = std::env::var("AWS_BUCKET_NAME")
-        .unwrap_or_else(|_| "birl-bucket".to_string());
+    // Create storage service (--demo serves synthetic assets without S3)
+    let storage = if std::env::args().any(|arg| arg == "--demo") {
+        info!("Demo mode: serving synthetic assets");
+        let storage = StorageService::new_demo(profile.cache_capacity.unwrap_or(0));
+        let storage = match profile.cache_max_bytes() {
+            Some(max) => storage.with_cache_max_bytes(max),
+            None => storage,
+        };
+        match profile.remote_layer_fetcher()? {
+            Some(fetcher) => storage.with_remote_layers(fetcher),
+            None => storage,
+        }
+    } else {
+        StorageService::from_profile(&profile).await?
+    };
 
-    info!("Using S3 bucket: {}", bucket_name);
+    let tables = config.with_aliases(tables, storage.catalog());
+    let tables = match storage.load_presets(profile.presets.as_deref()).await {
+        Ok(presets) => {
+            info!("Loaded {} presets", presets.len());
+            tables.with_presets(presets)
+        }
+        Err(e) => {
+            warn!("Ignoring presets file: {:#}", e);
+            tables.with_presets(Presets::for_catalog(storage.catalog()))
+        }
+    };
+    let tables = match storage.fetch_display_names().await {
+        Ok(Some(names)) => {
+            info!("Loaded display names of {} SKUs", names.len());
+            tables.with_names(names)
+        }
+        Ok(None) => tables,
+        Err(e) => {
+            warn!("Ignoring products JSON: {:#}", e);
+            tables
+        }
+    };
+    tables.install();
+    let storage = Arc::new(storage);
+    if let Some(interval) = profile.presets_reload_interval() {
+        info!("Reloading presets every {}s", interval.as_secs());
+        spawn_presets_reloads(storage.clone(), profile.presets.clone(), interval);
+    }
+    let auth = Arc::new(middleware::ApiKeys::new(&profile.auth)?);
+    if auth.required() {
+        info!("Requests require a valid Authorization or X-API-Key header");
+    }
 
-    // Create storage service
-    let storage = Arc::new(StorageService::new(s3_client, bucket_name, 1000));
+    // Output format classes for Accept negotiation
+    let capabilities = Arc::new(CapabilityClasses::from_env()?);
+
+    // Sampled /create requests for `birl-cli replay`
+    let recorder = match &profile.capture {
+        Some(settings) => {
+            info!(
+                "Capturing 1 in {} requests as '{}'",
+                settings.every, settings.name
+            );
+            Some(Arc::new(RequestRecorder::new(settings)?))
+        }
+        None => None,
+    };
+
+    // Daily request counts for `birl-cli rollups`
+    let rollup = match &profile.rollup {
+        Some(settings) => {
+            info!("Writing rollups every {}s", settings.flush_secs);
+            let rollup = Arc::new(RollupRecorder::new(settings)?);
+            spawn_rollup_ticks(storage.clone(), rollup.clone());
+            Some(rollup)
+        }
+        None => None,
+    };
+
+    // Background renders of the views shoppers rotate to
+    let prefetcher = profile.prefetch.as_ref().map(|settings| {
+        info!(
+            "Prefetching {} views after front views",
+            settings
+                .views
+                .iter()
+                .map(|view| view.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Arc::new(Prefetcher::new(settings))
+    });
+
+    let state = AppState {
+        storage,
+        capabilities,
+        recorder,
+        rollup,
+        prefetcher,
+    };
 
     // Setup CORS
     let cors = CorsLayer::new()
@@ -44,17 +147,31 @@ async fn main() -> anyhow::Result<()> {
 
     // Build router
     let app = Router::new()
-        // Health check endpoint
-        .route("/health", get(health_check))
         // API routes with authentication middleware
         .route("/create", post(routes::create_composite))
+        .route("/batch", post(routes::create_batch))
+        .route("/batch/stream", post(routes::stream_batch))
+        .route("/bundle", post(routes::create_bundle))
+        .route("/cache/:file", get(routes::get_cached))
+        .route("/flat-lay", post(routes::create_flat_lay))
+        .route("/plan", post(routes::plan_composite))
         .route("/products", get(routes::get_products))
-        .layer(from_fn(middleware::validate_webhook))
+        .route("/canonicalize", get(routes::get_canonical))
+        .route("/preflight", get(routes::get_preflight))
+        .route(
+            "/recipe",
+            get(routes::get_recipe).post(routes::render_recipe),
+        )
+        .layer(from_fn_with_state(auth, middleware::validate_webhook))
+        // Health check endpoint (no auth, for load balancers)
+        .route("/health", get(health_check))
+        // Readiness with the storage credentials' expiry, for orchestration
+        .route("/ready", get(routes::get_ready))
         // Middleware
         .layer(TraceLayer::new_for_http())
         .layer(cors)
         // Shared state
-        .with_state(storage);
+        .with_state(state);
 
     // Get port from environment or use default
     let port = std::env::var("PORT")
@@ -76,3 +193,57 @@ async fn main() -> anyhow::Result<()> {
 async fn health_check() -> &'static str {
     "OK"
 }
+
+/// Write rollup counts every `flush_secs` for as long as the server runs
+fn spawn_rollup_ticks(storage: Arc<StorageService>, rollup: Arc<RollupRecorder>) {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_secs(rollup.flush_secs()));
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = rollup.tick(&storage).await {
+                error!("Failed to write rollup counts: {:#}", e);
+            }
+        }
+    });
+}
+
+/// Reload the presets file every `interval`, keeping the last presets that
+/// loaded when it doesn't
+fn spawn_presets_reloads(
+    storage: Arc<StorageService>,
+    source: Option<String>,
+    interval: std::time::Duration,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            match storage.load_presets(source.as_deref()).await {
+                Ok(presets) => presets.reload(),
+                Err(e) => error!(
+                    "Failed to reload presets, keeping the current ones: {:#}",
+                    e
+                ),
+            }
+        }
+    });
+}
+
+/// Value of a `--name value` or `--name=value` command-line argument
+fn arg_value(name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return args.next();
+        }
+        if let Some(value) = arg.strip_prefix(&prefix) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
@@ -1,38 +1,127 @@
 use axum::{
     body::Body,
-    http::{Request, StatusCode},
+    extract::State,
+    http::{header, HeaderMap, Request, StatusCode},
     middleware::Next,
     response::Response,
 };
+use birl_storage::AuthSettings;
+use std::collections::HashSet;
+use std::sync::Arc;
 use tracing::warn;
 
-/// Validate webhook headers
-/// This is a placeholder implementation - customize based on your auth needs
-pub async fn validate_webhook(request: Request<Body>, next: Next) -> Result<Response, StatusCode> {
-    // For now, we'll allow all requests
-    // TODO: Implement proper webhook validation based on Hookdeck or your auth provider
-    //
-    // Example implementation:
-    // - Check for X-Webhook-Signature header
-    // - Verify HMAC signature
-    // - Check for API key in Authorization header
-
-    let has_auth = request
-        .headers()
-        .get("authorization")
-        .or_else(|| request.headers().get("x-api-key"))
-        .is_some();
-
-    if !has_auth {
-        // In development, we might want to allow requests without auth
-        // In production, uncomment the following:
-        // warn!("Unauthorized request");
-        // return Err(StatusCode::UNAUTHORIZED);
+/// API keys the server accepts, resolved from the profile's `auth` settings
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    required: bool,
+    /// Caller name and key
+    keys: Vec<(String, String)>,
+    internal: HashSet<String>,
+    /// Secret proving a request came through the CDN
+    cdn_secret: Option<String>,
+}
+
+impl ApiKeys {
+    pub fn new(settings: &AuthSettings) -> anyhow::Result<Self> {
+        Ok(Self {
+            required: settings.required,
+            keys: settings.resolved_keys()?,
+            internal: settings.internal.iter().cloned().collect(),
+            cdn_secret: settings.resolved_cdn_secret()?,
+        })
+    }
+
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    /// Caller a key belongs to, comparing every key in constant time
+    fn caller(&self, key: &str) -> Option<Caller> {
+        let mut found = None;
+        for (name, expected) in &self.keys {
+            if constant_time_eq(key.as_bytes(), expected.as_bytes()) {
+                found = Some(name);
+            }
+        }
+        let name = found?;
+        Some(Caller {
+            name: Some(name.clone()),
+            internal: self.internal.contains(name),
+            via_cdn: false,
+        })
+    }
+
+    /// Whether the request carries the CDN's origin secret
+    /// Without a configured secret no request is trusted to come from the CDN.
+    fn via_cdn(&self, headers: &HeaderMap) -> bool {
+        let (Some(expected), Some(presented)) =
+            (&self.cdn_secret, headers.get(ORIGIN_SECRET_HEADER))
+        else {
+            return false;
+        };
+        constant_time_eq(presented.as_bytes(), expected.as_bytes())
     }
+}
+
+/// Request header the CDN sets to the profile's `auth.cdn_secret`
+pub const ORIGIN_SECRET_HEADER: &str = "x-origin-secret";
+
+/// Who sent a request, added to its extensions by [`validate_webhook`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Caller {
+    /// Name of the caller's key; `None` for anonymous requests, which only
+    /// get through when auth isn't required
+    pub name: Option<String>,
+    /// Trusted to set what's otherwise read from CDN headers
+    pub internal: bool,
+    /// Came through the CDN, so its CDN headers can be trusted
+    pub via_cdn: bool,
+}
+
+/// Check the request's API key against the configured ones
+/// Profiles with `auth.required` reject requests without a valid key; others
+/// let anonymous requests through, but still reject a key they don't know
+pub async fn validate_webhook(
+    State(keys): State<Arc<ApiKeys>>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let mut caller = match presented_key(request.headers()) {
+        Some(key) => match keys.caller(key) {
+            Some(caller) => caller,
+            None => {
+                warn!("Request with an unknown API key");
+                return Err(StatusCode::UNAUTHORIZED);
+            }
+        },
+        None if keys.required => {
+            warn!("Unauthorized request");
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        None => Caller::default(),
+    };
+    caller.via_cdn = keys.via_cdn(request.headers());
+    request.extensions_mut().insert(caller);
 
     Ok(next.run(request).await)
 }
 
+/// Key of `Authorization: Bearer <key>` or `X-API-Key: <key>`
+fn presented_key(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.strip_prefix("Bearer ").unwrap_or(value));
+    bearer
+        .or_else(|| headers.get("x-api-key")?.to_str().ok())
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
 /// Middleware to validate Hookdeck webhook signature
 /// Reference: https://hookdeck.com/docs/verify-webhooks
 #[allow(dead_code)]
@@ -56,3 +145,60 @@ pub async fn validate_hookdeck_signature(
 
     Ok(next.run(request).await)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn keys(required: bool) -> ApiKeys {
+        let settings = AuthSettings {
+            required,
+            keys: BTreeMap::from([
+                ("storefront".to_string(), "sf-key".to_string()),
+                ("ops".to_string(), "ops-key".to_string()),
+            ]),
+            internal: vec!["ops".to_string()],
+            cdn_secret: Some("origin-secret".to_string()),
+        };
+        ApiKeys::new(&settings).unwrap()
+    }
+
+    fn headers(name: &str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+            value.parse().unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_key_is_verified() {
+        let keys = keys(true);
+        let key = |name, value| {
+            let headers = headers(name, value);
+            presented_key(&headers).and_then(|key| keys.caller(key))
+        };
+
+        let storefront = key("authorization", "Bearer sf-key").unwrap();
+        assert_eq!(storefront.name.as_deref(), Some("storefront"));
+        assert!(!storefront.internal);
+        assert!(key("x-api-key", "ops-key").unwrap().internal);
+        assert!(key("authorization", "Bearer sf-ke").is_none());
+        assert!(key("x-api-key", "anything").is_none());
+        assert!(presented_key(&headers("x-api-key", " ")).is_none());
+    }
+
+    #[test]
+    fn test_cdn_secret_is_verified() {
+        let keys = keys(false);
+        assert!(keys.via_cdn(&headers(ORIGIN_SECRET_HEADER, "origin-secret")));
+        assert!(!keys.via_cdn(&headers(ORIGIN_SECRET_HEADER, "origin-secre")));
+        assert!(!keys.via_cdn(&HeaderMap::new()));
+
+        // Without a configured secret, no request is from the CDN
+        let unset = ApiKeys::default();
+        assert!(!unset.via_cdn(&headers(ORIGIN_SECRET_HEADER, "origin-secret")));
+    }
+}
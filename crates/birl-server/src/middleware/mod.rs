@@ -1,3 +1,3 @@
 pub mod auth;
 
-pub use auth::validate_webhook;
+pub use auth::{validate_webhook, ApiKeys, Caller};
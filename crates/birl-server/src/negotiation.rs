@@ -0,0 +1,129 @@
+use anyhow::{Context, Result};
+use birl_core::OutputFormat;
+
+/// Default class mapping when BIRL_CAPABILITY_CLASSES is not set
+const DEFAULT_CLASSES: &str = "modern=avif,legacy=jpeg";
+
+/// A named group of clients that share one output format
+/// Caching per class rather than per exact Accept header keeps variants bounded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapabilityClass {
+    pub name: String,
+    pub format: OutputFormat,
+}
+
+/// Ordered capability classes: the first class whose format the client accepts wins
+/// JPEG is always acceptable, so a JPEG class is the fallback
+#[derive(Debug, Clone)]
+pub struct CapabilityClasses {
+    classes: Vec<CapabilityClass>,
+}
+
+impl CapabilityClasses {
+    /// Parse a mapping like "modern=avif,webp=webp,legacy=jpeg"
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut classes = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (name, format) = entry
+                    .split_once('=')
+                    .with_context(|| format!("Invalid capability class: {}", entry))?;
+                Ok(CapabilityClass {
+                    name: name.trim().to_string(),
+                    format: format.parse().map_err(anyhow::Error::msg)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if !classes.iter().any(|c| c.format == OutputFormat::Jpeg) {
+            classes.push(CapabilityClass {
+                name: "legacy".to_string(),
+                format: OutputFormat::Jpeg,
+            });
+        }
+
+        Ok(Self { classes })
+    }
+
+    /// Load from BIRL_CAPABILITY_CLASSES, falling back to the default mapping
+    pub fn from_env() -> Result<Self> {
+        let spec = std::env::var("BIRL_CAPABILITY_CLASSES")
+            .unwrap_or_else(|_| DEFAULT_CLASSES.to_string());
+        Self::parse(&spec)
+    }
+
+    /// Pick the class for a request's Accept header
+    pub fn classify(&self, accept: Option<&str>) -> &CapabilityClass {
+        let accept = accept.unwrap_or_default();
+
+        self.classes
+            .iter()
+            .find(|class| {
+                class.format == OutputFormat::Jpeg || accepts(accept, class.format.content_type())
+            })
+            .expect("capability classes always include a JPEG fallback")
+    }
+}
+
+impl Default for CapabilityClasses {
+    fn default() -> Self {
+        Self::parse(DEFAULT_CLASSES).expect("default capability classes are valid")
+    }
+}
+
+/// Check whether an Accept header explicitly lists a media type with non-zero quality
+fn accepts(accept: &str, media_type: &str) -> bool {
+    accept.split(',').any(|entry| {
+        let mut parts = entry.split(';').map(str::trim);
+        let matches = parts
+            .next()
+            .is_some_and(|t| t.eq_ignore_ascii_case(media_type));
+        let rejected = parts.any(|p| p.replace(' ', "") == "q=0");
+        matches && !rejected
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHROME_ACCEPT: &str = "image/avif,image/webp,image/apng,image/*,*/*;q=0.8";
+
+    #[test]
+    fn test_default_classes() {
+        let classes = CapabilityClasses::default();
+
+        assert_eq!(classes.classify(Some(CHROME_ACCEPT)).name, "modern");
+        assert_eq!(classes.classify(Some("image/*")).name, "legacy");
+        assert_eq!(classes.classify(None).format, OutputFormat::Jpeg);
+    }
+
+    #[test]
+    fn test_custom_classes() {
+        let classes = CapabilityClasses::parse("modern=avif,web=webp,legacy=jpeg").unwrap();
+
+        assert_eq!(classes.classify(Some("image/webp,*/*")).name, "web");
+        assert_eq!(classes.classify(Some(CHROME_ACCEPT)).name, "modern");
+    }
+
+    #[test]
+    fn test_jpeg_fallback_added() {
+        let classes = CapabilityClasses::parse("modern=avif").unwrap();
+        let class = classes.classify(Some("text/html"));
+        assert_eq!(class.format, OutputFormat::Jpeg);
+    }
+
+    #[test]
+    fn test_zero_quality_rejected() {
+        let classes = CapabilityClasses::default();
+        assert_eq!(classes.classify(Some("image/avif;q=0")).name, "legacy");
+    }
+
+    #[test]
+    fn test_invalid_spec() {
+        assert!(CapabilityClasses::parse("modern").is_err());
+        assert!(CapabilityClasses::parse("modern=gif").is_err());
+    }
+}
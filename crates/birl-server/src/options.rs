@@ -0,0 +1,278 @@
+//! Rendering options shared by /create, /batch, /bundle and /plan
+//!
+//! Every option a request can set lives in [`RenderOptions`]: it is validated
+//! by [`RenderOptions::validate`], echoed in its normalized form in the
+//! `X-Render-Options` response header, and turned into the
+//! [`CompositeOptions`] whose cache variant keys the composite. A new option
+//! is a field here, and the tests check that it reaches the cache key.
+
+use birl_core::{Background, BirlError, CompositeOptions, Filter, OutputFormat, ResizeFilter};
+use serde::{Deserialize, Serialize};
+
+/// Response header echoing the normalized options of a render
+pub const RENDER_OPTIONS_HEADER: &str = "x-render-options";
+
+/// Caching of a `/create` request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheMode {
+    /// Serve and store composites through the cache (see `bypass_cache`)
+    #[default]
+    Default,
+    /// Persist nothing about the render, e.g. for partners that require it
+    None,
+}
+
+/// Why /bundle and /batch refuse `"cache": "none"`
+pub const UNCACHED_BY_KEY: &str =
+    "\"cache\": \"none\" isn't supported here: composites are returned by their cache key";
+
+/// Options of a render request, flattened into its body
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderOptions {
+    /// Post-processing filters: "grayscale", "sepia", "brightness:<n>"
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<Filter>,
+    /// Output format; negotiated from the Accept header when omitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+    /// Encoder quality (1-100) for JPEG and AVIF; defaults to the configured
+    /// quality curve
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+    /// Downscale to fit within this width, keeping the aspect ratio
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_width: Option<u32>,
+    /// Downscale to fit within this height, keeping the aspect ratio
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_height: Option<u32>,
+    /// Resampling filter for downscaling (default: lanczos3)
+    #[serde(skip_serializing_if = "is_default")]
+    pub resize_filter: ResizeFilter,
+    /// Resampling filter for layers that don't match the plate size (default: lanczos3)
+    #[serde(skip_serializing_if = "is_default")]
+    pub layer_filter: ResizeFilter,
+    /// Fill transparent areas with this color ("#rrggbb")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<Background>,
+    /// Color transparent areas are flattened onto for JPEG output (default white)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matte: Option<Background>,
+    /// Plate (base model or body type) to render on instead of the view's own
+    #[serde(alias = "model", skip_serializing_if = "Option::is_none")]
+    pub plate: Option<String>,
+    /// The garments alone on a transparent image, without the plate; PNG
+    /// unless the format has alpha
+    #[serde(skip_serializing_if = "is_default")]
+    pub cutout: bool,
+    /// Bypass cache and force regeneration
+    #[serde(skip_serializing_if = "is_default")]
+    pub bypass_cache: bool,
+    /// `"none"` to render without reading or writing any cache, and without
+    /// capturing the request
+    #[serde(skip_serializing_if = "is_default")]
+    pub cache: CacheMode,
+    /// Reject params the view would silently drop instead of ignoring them
+    #[serde(skip_serializing_if = "is_default")]
+    pub strict: bool,
+    /// Include the composite's perceptual hash (X-Perceptual-Hash header)
+    #[serde(skip_serializing_if = "is_default")]
+    pub phash: bool,
+    /// Region the composite is shown in (e.g. "DE"), for licensed artwork;
+    /// defaults to the CloudFront-Viewer-Country header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+impl RenderOptions {
+    /// Reject options no render can honor
+    pub fn validate(&self) -> Result<(), BirlError> {
+        let invalid = |message: String| Err(BirlError::InvalidParam(message));
+
+        if let Some(quality) = self.quality {
+            if !(1..=100).contains(&quality) {
+                return invalid(format!("Invalid quality {}: must be 1-100", quality));
+            }
+        }
+        if self.max_width == Some(0) || self.max_height == Some(0) {
+            return invalid("max_width and max_height must be at least 1".to_string());
+        }
+        if let Some(plate) = &self.plate {
+            if plate.trim().is_empty() || plate.contains(['/', '\\']) {
+                return invalid(format!("Invalid plate name '{}'", plate));
+            }
+        }
+        if let Some(region) = &self.region {
+            let code = region.trim();
+            if code.is_empty() || !code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                return invalid(format!("Invalid region '{}'", region));
+            }
+        }
+        match self.format {
+            Some(format) if self.cutout && !format.has_alpha() => {
+                return invalid(format!("A cutout can't be {}: it has no alpha", format));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The options as rendered: `format` resolved (to `negotiated` if none
+    /// was requested, or PNG for a cutout when it has no alpha), the plate
+    /// trimmed, the region trimmed and uppercased, and `bypass_cache` cleared
+    /// when nothing is cached anyway
+    pub fn normalized(&self, negotiated: OutputFormat) -> Self {
+        let negotiated = match negotiated {
+            format if self.cutout && !format.has_alpha() => OutputFormat::Png,
+            format => format,
+        };
+        Self {
+            format: Some(self.format.unwrap_or(negotiated)),
+            plate: self.plate.as_ref().map(|plate| plate.trim().to_string()),
+            region: self
+                .region
+                .as_ref()
+                .map(|region| region.trim().to_uppercase()),
+            bypass_cache: self.bypass_cache && self.cache == CacheMode::Default,
+            ..self.clone()
+        }
+    }
+
+    /// Compositing options, with `negotiated` as the format if none was requested
+    pub fn composite_options(&self, negotiated: OutputFormat) -> CompositeOptions {
+        let normalized = self.normalized(negotiated);
+        let mut options = CompositeOptions::new()
+            .with_format(normalized.format.unwrap_or(negotiated))
+            .with_max_size(normalized.max_width, normalized.max_height)
+            .with_resize_filter(normalized.resize_filter)
+            .with_layer_filter(normalized.layer_filter)
+            .with_filters(normalized.filters);
+
+        if let Some(quality) = normalized.quality {
+            options = options.with_quality(quality);
+        }
+        if let Some(background) = normalized.background {
+            options = options.with_background(background);
+        }
+        if let Some(matte) = normalized.matte {
+            options = options.with_matte(matte);
+        }
+        if let Some(plate) = normalized.plate {
+            options = options.with_plate(plate);
+        }
+        if normalized.cutout {
+            options = options.with_cutout();
+        }
+        if let Some(region) = normalized.region {
+            options = options.with_region(region);
+        }
+
+        options
+    }
+
+    /// Compact JSON of the normalized options, for the `X-Render-Options` header
+    pub fn echo(&self, negotiated: OutputFormat) -> String {
+        serde_json::to_string(&self.normalized(negotiated)).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> RenderOptions {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(parse(r#"{"quality": 80, "model": "slim"}"#)
+            .validate()
+            .is_ok());
+        assert!(parse(r#"{"quality": 0}"#).validate().is_err());
+        assert!(parse(r#"{"quality": 101}"#).validate().is_err());
+        assert!(parse(r#"{"max_width": 0}"#).validate().is_err());
+        assert!(parse(r#"{"plate": "../secrets"}"#).validate().is_err());
+        assert!(parse(r#"{"plate": " "}"#).validate().is_err());
+        assert!(parse(r#"{"region": "de"}"#).validate().is_ok());
+        assert!(parse(r#"{"region": "d e"}"#).validate().is_err());
+        assert!(parse(r#"{"cutout": true, "format": "jpeg"}"#)
+            .validate()
+            .is_err());
+        assert!(parse(r#"{"cutout": true, "format": "webp"}"#)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_echo() {
+        let options = parse(r#"{"quality": 80, "plate": " slim ", "bypass_cache": true}"#);
+        assert_eq!(
+            options.echo(OutputFormat::Avif),
+            r#"{"format":"avif","quality":80,"plate":"slim","bypass_cache":true}"#
+        );
+
+        let uncached = parse(r#"{"format": "png", "bypass_cache": true, "cache": "none"}"#);
+        assert_eq!(
+            uncached.echo(OutputFormat::Avif),
+            r#"{"format":"png","cache":"none"}"#
+        );
+
+        let region = parse(r#"{"region": " de "}"#);
+        assert_eq!(
+            region.echo(OutputFormat::Jpeg),
+            r#"{"format":"jpeg","region":"DE"}"#
+        );
+
+        // Cutouts fall back to PNG when the negotiated format has no alpha
+        let cutout = parse(r#"{"cutout": true}"#);
+        assert_eq!(
+            cutout.echo(OutputFormat::Jpeg),
+            r#"{"format":"png","cutout":true}"#
+        );
+        assert_eq!(
+            cutout.echo(OutputFormat::WebP),
+            r#"{"format":"webp","cutout":true}"#
+        );
+    }
+
+    #[test]
+    fn test_every_output_option_reaches_the_cache_key() {
+        // The key of a render is its plate plus the options' cache variant
+        let key = |options: &RenderOptions| {
+            let options = options.composite_options(OutputFormat::Jpeg);
+            (options.plate.clone(), options.cache_variant())
+        };
+        let base = RenderOptions::default();
+        let variants = [
+            r#"{"filters": ["grayscale"]}"#,
+            r#"{"format": "png"}"#,
+            r#"{"quality": 70}"#,
+            r#"{"max_width": 400}"#,
+            r#"{"max_height": 400}"#,
+            r#"{"max_width": 400, "resize_filter": "nearest"}"#,
+            r#"{"layer_filter": "nearest"}"#,
+            r##"{"background": "#000000"}"##,
+            r##"{"matte": "#000000"}"##,
+            r#"{"plate": "slim"}"#,
+            r#"{"cutout": true}"#,
+        ];
+        let mut keys = vec![key(&base)];
+        for json in variants {
+            let variant = key(&parse(json));
+            assert!(!keys.contains(&variant), "{} doesn't change the key", json);
+            keys.push(variant);
+        }
+
+        // Flags that don't change the output keep the key; the region only
+        // changes it through the license decision
+        let flags =
+            parse(r#"{"bypass_cache": true, "strict": true, "phash": true, "region": "DE"}"#);
+        assert_eq!(key(&flags), key(&base));
+    }
+}
@@ -0,0 +1,506 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::sse::{Event, KeepAlive, Sse},
+    response::IntoResponse,
+    Extension, Json,
+};
+use birl_core::phash::{dhash_bytes, format_hash};
+use birl_core::{
+    validate_for_view, BatchError, BatchItem, BatchManifest, BatchOutcome, BatchResult,
+    BatchSummary, DroppedLayer, OutputFormat,
+};
+use birl_storage::{MissingLayer, RenderedComposite, StorageService, ThrottleStats};
+use futures::future::join_all;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tracing::{info, warn};
+
+use super::create::{CreateRequest, ErrorResponse, VARY_VIEWER_COUNTRY};
+use crate::middleware::Caller;
+use crate::options::{CacheMode, UNCACHED_BY_KEY};
+
+/// Request body for POST /batch
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    pub items: Vec<CreateRequest>,
+    /// Items rendered at once (default: all for /batch, 4 for /batch/stream)
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+    /// Name under which the items' status is kept, so posting the batch again
+    /// only renders the items that failed or never finished
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+/// Longest batch id accepted
+const MAX_ID_LEN: usize = 64;
+
+/// Items rendered at once by /batch/stream unless the request says otherwise
+const DEFAULT_STREAM_CONCURRENCY: usize = 4;
+/// Upper bound on the concurrency a request can ask for
+const MAX_CONCURRENCY: usize = 32;
+/// Most items one /batch or /batch/stream request can render
+const MAX_ITEMS: usize = 500;
+
+impl BatchRequest {
+    /// Refuse batches over [`MAX_ITEMS`]
+    fn check_items(&self) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+        if self.items.len() <= MAX_ITEMS {
+            return Ok(());
+        }
+        let error = format!(
+            "Too many batch items: {} (at most {})",
+            self.items.len(),
+            MAX_ITEMS
+        );
+        Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ErrorResponse::new(error)),
+        ))
+    }
+
+    fn concurrency(&self) -> Option<usize> {
+        self.concurrency.map(|n| n.clamp(1, MAX_CONCURRENCY))
+    }
+
+    /// The items, each rendered for the viewer's region
+    fn viewer_items(self, headers: &HeaderMap, caller: &Caller) -> Vec<CreateRequest> {
+        self.items
+            .into_iter()
+            .map(|item| item.with_viewer_region(headers, caller))
+            .collect()
+    }
+
+    /// Status kept from earlier runs with the same id by the same caller, if
+    /// the batch has one; fails while another run with the id is in progress
+    async fn status(
+        &self,
+        storage: &StorageService,
+        caller: &Caller,
+    ) -> Result<Option<Arc<BatchStatus>>, (StatusCode, Json<ErrorResponse>)> {
+        let Some(id) = &self.id else {
+            return Ok(None);
+        };
+        let valid = !id.is_empty()
+            && id.len() <= MAX_ID_LEN
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            let error = format!(
+                "Invalid batch id '{}': use up to {} letters, digits, '-' or '_'",
+                id, MAX_ID_LEN
+            );
+            return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse::new(error))));
+        }
+
+        let key = format!(
+            "batches/{}/{}",
+            caller.name.as_deref().unwrap_or(ANONYMOUS_NAMESPACE),
+            id
+        );
+        let Some(run) = BatchRun::start(&key) else {
+            let error = format!("Batch '{}' is already running", id);
+            return Err((StatusCode::CONFLICT, Json(ErrorResponse::new(error))));
+        };
+        BatchStatus::load(storage, key, run)
+            .await
+            .map(Some)
+            .map_err(|e| {
+                let error = format!("Failed to load batch status: {:#}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse::new(error)),
+                )
+            })
+    }
+}
+
+/// Namespace of the batch ids of requests without an API key
+const ANONYMOUS_NAMESPACE: &str = "_anonymous";
+
+/// Keys of the batches running in this process
+static RUNNING: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+/// A batch id claimed for one run, released when dropped
+struct BatchRun(String);
+
+impl BatchRun {
+    /// Claim `key`, unless a run with it is already in progress
+    fn start(key: &str) -> Option<Self> {
+        let running = RUNNING.get_or_init(Default::default);
+        running
+            .lock()
+            .unwrap()
+            .insert(key.to_string())
+            .then(|| Self(key.to_string()))
+    }
+}
+
+impl Drop for BatchRun {
+    fn drop(&mut self) {
+        if let Some(running) = RUNNING.get() {
+            running.lock().unwrap().remove(&self.0);
+        }
+    }
+}
+
+/// Per-item status of a batch with an id, stored as
+/// `cache/batches/{caller}/{id}.json` and saved as each item finishes
+pub struct BatchStatus {
+    key: String,
+    manifest: Mutex<BatchManifest<BatchRender>>,
+    /// Write throttle counts when the run started
+    writes_before: Option<ThrottleStats>,
+    /// Held while saving, so a later snapshot is never overwritten by an
+    /// earlier one
+    saving: tokio::sync::Mutex<()>,
+    _run: BatchRun,
+}
+
+/// Stored form of a [`BatchStatus`]: the manifest, and the throttled storage
+/// writes of the latest run
+#[derive(Serialize, Deserialize)]
+struct StatusFile<M> {
+    #[serde(flatten)]
+    manifest: M,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    writes: Option<ThrottleStats>,
+}
+
+/// Response of POST /batch
+#[derive(Debug, Serialize)]
+pub struct BatchReport {
+    #[serde(flatten)]
+    pub result: BatchResult<BatchRender>,
+    /// Throttled storage writes while the batch ran, if writes are throttled
+    /// The throttle is shared by the server, so other requests' writes count too
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub writes: Option<ThrottleStats>,
+}
+
+/// Final event of POST /batch/stream
+#[derive(Debug, Serialize)]
+struct BatchDone {
+    #[serde(flatten)]
+    summary: BatchSummary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    writes: Option<ThrottleStats>,
+}
+
+/// Throttled writes since `before`, if writes are throttled
+fn writes_since(storage: &StorageService, before: Option<ThrottleStats>) -> Option<ThrottleStats> {
+    Some(storage.write_throttle_stats()?.since(&before?))
+}
+
+impl BatchStatus {
+    async fn load(
+        storage: &StorageService,
+        key: String,
+        run: BatchRun,
+    ) -> anyhow::Result<Arc<Self>> {
+        let manifest = match storage.fetch_cached_json(&key).await? {
+            Some(json) => serde_json::from_str::<StatusFile<_>>(&json)?.manifest,
+            None => BatchManifest::new(),
+        };
+        Ok(Arc::new(Self {
+            key,
+            manifest: Mutex::new(manifest),
+            writes_before: storage.write_throttle_stats(),
+            saving: tokio::sync::Mutex::new(()),
+            _run: run,
+        }))
+    }
+
+    fn completed(&self, cache_key: &str) -> Option<BatchRender> {
+        self.manifest.lock().unwrap().completed(cache_key).cloned()
+    }
+
+    fn record(&self, cache_key: &str, result: &Result<BatchRender, BatchError>) {
+        self.manifest.lock().unwrap().record(cache_key, result);
+    }
+
+    async fn save(&self, storage: &StorageService) {
+        let _saving = self.saving.lock().await;
+        let json = serde_json::to_string(&StatusFile {
+            manifest: &*self.manifest.lock().unwrap(),
+            writes: writes_since(storage, self.writes_before),
+        });
+        let saved = match json {
+            Ok(json) => storage.save_cached_json(&self.key, &json).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = saved {
+            warn!("Failed to save batch status {}: {:#}", self.key, e);
+        }
+    }
+}
+
+/// Successful batch item
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchRender {
+    pub cache_key: String,
+    pub cached: bool,
+    pub bytes: usize,
+    /// Where the cached composite is stored, if the backend can address it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Problems that didn't fail the item, e.g. layers that weren't found
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// Requested items that weren't rendered, and why
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dropped: Vec<DroppedLayer>,
+    /// Layers that weren't found, with the SKUs they may be typos of
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing: Vec<MissingLayer>,
+    /// Credits the licenses of the layers require with the composite
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attributions: Vec<String>,
+    /// Perceptual hash, when requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phash: Option<String>,
+    /// Why the composite was rendered but not cached, if it looks broken
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sanity_issue: Option<String>,
+    /// Rendered by an earlier run of a batch with the same id
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub resumed: bool,
+}
+
+impl BatchRender {
+    /// Describe a composite, hashing it if `phash` is set
+    pub(crate) fn new(
+        storage: &StorageService,
+        rendered: RenderedComposite,
+        phash: bool,
+    ) -> anyhow::Result<Self> {
+        let phash = if phash {
+            Some(format_hash(dhash_bytes(&rendered.data)?))
+        } else {
+            None
+        };
+
+        let mut warnings = Vec::new();
+        if rendered.found_layers < rendered.requested_layers {
+            let missing: Vec<String> = rendered.missing.iter().map(ToString::to_string).collect();
+            warnings.push(format!(
+                "Found {}/{} requested layers, missing {}",
+                rendered.found_layers,
+                rendered.requested_layers,
+                missing.join(", ")
+            ));
+        }
+        warnings.extend(rendered.conflicts.iter().map(ToString::to_string));
+        warnings.extend(rendered.violations.iter().map(ToString::to_string));
+        let url = match rendered.sanity_issue {
+            Some(_) => None,
+            None => storage.cache_url(&rendered.cache_key, rendered.format),
+        };
+
+        Ok(Self {
+            url,
+            warnings,
+            dropped: rendered.dropped,
+            missing: rendered.missing,
+            attributions: rendered.attributions,
+            cache_key: rendered.cache_key,
+            cached: rendered.cached,
+            bytes: rendered.data.len(),
+            phash,
+            sanity_issue: rendered.sanity_issue.map(|issue| issue.to_string()),
+            resumed: false,
+        })
+    }
+}
+
+/// POST /batch - Render several composites, reporting each item separately
+/// With an `id`, items that succeeded in an earlier run aren't rendered again
+pub async fn create_batch(
+    State(storage): State<Arc<StorageService>>,
+    Extension(caller): Extension<Caller>,
+    headers: HeaderMap,
+    Json(request): Json<BatchRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    request.check_items()?;
+    let concurrency = request.concurrency();
+    let status = request.status(&storage, &caller).await?;
+    let writes_before = storage.write_throttle_stats();
+    let renders = request
+        .viewer_items(&headers, &caller)
+        .into_iter()
+        .map(|item| render_item(&storage, item, status.as_deref()));
+
+    let results = match concurrency {
+        Some(concurrency) => stream::iter(renders).buffered(concurrency).collect().await,
+        None => join_all(renders).await,
+    };
+    let result = BatchResult::from_results(results);
+
+    for (index, error) in result.failures() {
+        warn!("Batch item {} failed: {}", index, error);
+    }
+    info!(
+        "Batch complete: {}/{} succeeded",
+        result.summary.succeeded, result.summary.total
+    );
+
+    let report = BatchReport {
+        result,
+        writes: writes_since(&storage, writes_before),
+    };
+    Ok(([(header::VARY, VARY_VIEWER_COUNTRY)], Json(report)))
+}
+
+/// POST /batch/stream - Render several composites, streaming each result as
+/// Server-Sent Events
+///
+/// Every finished item is sent as an `item` event (in completion order, with
+/// its input `index`) and the counts as a final `done` event. At most
+/// `concurrency` items render at once, and new ones only start as the client
+/// reads events, so a slow reader holds back the batch instead of buffering it.
+/// The status of a batch with an `id` is saved as each item finishes.
+pub async fn stream_batch(
+    State(storage): State<Arc<StorageService>>,
+    Extension(caller): Extension<Caller>,
+    headers: HeaderMap,
+    Json(request): Json<BatchRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    request.check_items()?;
+    let total = request.items.len();
+    let concurrency = request.concurrency().unwrap_or(DEFAULT_STREAM_CONCURRENCY);
+    let status = request.status(&storage, &caller).await?;
+    let writes_before = storage.write_throttle_stats();
+    info!(
+        "Streaming batch of {} items ({} at once)",
+        total, concurrency
+    );
+
+    let failed = Arc::new(AtomicUsize::new(0));
+    let items = {
+        let failed = failed.clone();
+        let storage = storage.clone();
+        let status = status.clone();
+        let items = request.viewer_items(&headers, &caller);
+        stream::iter(items.into_iter().enumerate())
+            .map(move |(index, item)| {
+                let storage = storage.clone();
+                let status = status.clone();
+                async move {
+                    let result = render_item(&storage, item, status.as_deref()).await;
+                    BatchItem::new(index, result)
+                }
+            })
+            .buffer_unordered(concurrency)
+            .map(move |item| {
+                if let BatchOutcome::Failed { error } = &item.outcome {
+                    warn!("Batch item {} failed: {}", item.index, error);
+                    failed.fetch_add(1, Ordering::Relaxed);
+                }
+                Event::default().event("item").json_data(&item)
+            })
+    };
+    let done = stream::once(async move {
+        // Holds the batch id until the stream ends
+        drop(status);
+        let summary = BatchSummary::new(total, failed.load(Ordering::Relaxed));
+        info!(
+            "Batch stream complete: {}/{} succeeded",
+            summary.succeeded, summary.total
+        );
+        let done = BatchDone {
+            summary,
+            writes: writes_since(&storage, writes_before),
+        };
+        Event::default().event("done").json_data(done)
+    });
+
+    let events = Sse::new(items.chain(done)).keep_alive(KeepAlive::default());
+    Ok(([(header::VARY, VARY_VIEWER_COUNTRY)], events))
+}
+
+async fn render_item(
+    storage: &StorageService,
+    item: CreateRequest,
+    status: Option<&BatchStatus>,
+) -> Result<BatchRender, BatchError> {
+    let params = item
+        .params()
+        .map_err(|e| BatchError::invalid_params(e.to_string()))?;
+    if params.is_empty() {
+        return Err(BatchError::invalid_params(format!(
+            "No valid category/sku pairs in '{}'",
+            item.p
+        )));
+    }
+    if item.options.cache == CacheMode::None {
+        return Err(BatchError::invalid_params(UNCACHED_BY_KEY));
+    }
+
+    if item.options.strict {
+        validate_for_view(&params, item.view)
+            .map_err(|e| BatchError::invalid_params(e.to_string()))?;
+    }
+
+    let options = item
+        .composite_options(OutputFormat::default())
+        .map_err(|e| BatchError::invalid_params(e.to_string()))?;
+    let cache_key = storage.cache_key(&params, item.view, &options);
+    if let Some(render) = status.and_then(|status| status.completed(&cache_key)) {
+        if !item.options.bypass_cache {
+            return Ok(BatchRender {
+                resumed: true,
+                ..render
+            });
+        }
+    }
+
+    let result = storage
+        .render(&params, item.view, &options, item.options.bypass_cache)
+        .await
+        .map_err(|e| BatchError::render(format!("{:#}", e)))
+        .and_then(|rendered| {
+            BatchRender::new(storage, rendered, item.options.phash)
+                .map_err(|e| BatchError::render(e.to_string()))
+        });
+    if let Some(status) = status {
+        status.record(&cache_key, &result);
+        status.save(storage).await;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_run() {
+        let run = BatchRun::start("batches/ops/nightly").unwrap();
+        assert!(BatchRun::start("batches/ops/nightly").is_none());
+        assert!(BatchRun::start("batches/storefront/nightly").is_some());
+        drop(run);
+        assert!(BatchRun::start("batches/ops/nightly").is_some());
+    }
+
+    #[test]
+    fn test_status_file() {
+        // Statuses saved before write counts were recorded still load
+        let old: StatusFile<BatchManifest<BatchRender>> =
+            serde_json::from_str(r#"{"items": {}}"#).unwrap();
+        assert!(old.manifest.items.is_empty());
+        assert!(old.writes.is_none());
+
+        let saved = serde_json::to_value(StatusFile {
+            manifest: &old.manifest,
+            writes: Some(ThrottleStats::default()),
+        })
+        .unwrap();
+        assert_eq!(saved["items"], serde_json::json!({}));
+        assert_eq!(saved["writes"]["waited_ms"], 0);
+    }
+}
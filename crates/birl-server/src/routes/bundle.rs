@@ -0,0 +1,130 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use birl_core::{validate_for_view, OutputFormat, View};
+use birl_storage::StorageService;
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::{error, info};
+
+use super::batch::BatchRender;
+use super::create::{
+    error_status, CreateRequest, ErrorResponse, ValidationErrorResponse, VARY_VIEWER_COUNTRY,
+};
+use crate::middleware::Caller;
+use crate::options::{CacheMode, UNCACHED_BY_KEY};
+
+/// Response body for POST /bundle
+#[derive(Debug, Serialize)]
+pub struct BundleResponse {
+    pub view: View,
+    /// The whole outfit
+    pub outfit: BatchRender,
+    /// Each item alone on the plate, in request order
+    pub items: Vec<BundleItem>,
+}
+
+/// One item of a bundle
+#[derive(Debug, Serialize)]
+pub struct BundleItem {
+    pub category: String,
+    pub sku: String,
+    #[serde(flatten)]
+    pub render: BatchRender,
+}
+
+/// POST /bundle - Render an outfit and each of its items, returning the keys
+/// of all composites
+///
+/// Takes the same body as /create. Items the view doesn't show are left out.
+pub async fn create_bundle(
+    State(storage): State<Arc<StorageService>>,
+    Extension(caller): Extension<Caller>,
+    headers: HeaderMap,
+    Json(request): Json<CreateRequest>,
+) -> Response {
+    let request = request.with_viewer_region(&headers, &caller);
+    let params = match request.params() {
+        Ok(params) => params,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(e.to_string())),
+            )
+                .into_response()
+        }
+    };
+    if params.is_empty() {
+        let error = format!("No valid category/sku pairs in '{}'", request.p);
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse::new(error))).into_response();
+    }
+    if request.options.cache == CacheMode::None {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(UNCACHED_BY_KEY)),
+        )
+            .into_response();
+    }
+    if request.options.strict {
+        if let Err(e) = validate_for_view(&params, request.view) {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ValidationErrorResponse::from(e)),
+            )
+                .into_response();
+        }
+    }
+
+    let result = async {
+        let options = request.composite_options(OutputFormat::default())?;
+        let bundle = storage
+            .render_bundle(
+                &params,
+                request.view,
+                &options,
+                request.options.bypass_cache,
+            )
+            .await?;
+
+        let items = bundle
+            .items
+            .into_iter()
+            .map(|(item, rendered)| {
+                Ok(BundleItem {
+                    category: item.category,
+                    sku: item.sku.to_string(),
+                    render: BatchRender::new(&storage, rendered, request.options.phash)?,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        anyhow::Ok(BundleResponse {
+            view: request.view,
+            outfit: BatchRender::new(&storage, bundle.outfit, request.options.phash)?,
+            items,
+        })
+    };
+
+    match result.await {
+        Ok(response) => {
+            info!("Bundle complete: outfit and {} items", response.items.len());
+            (
+                StatusCode::OK,
+                [(header::VARY, VARY_VIEWER_COUNTRY)],
+                Json(response),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("Error rendering bundle: {:#}", e);
+            (
+                error_status(&e),
+                Json(ErrorResponse::new(format!("{:#}", e)).with_missing(&e)),
+            )
+                .into_response()
+        }
+    }
+}
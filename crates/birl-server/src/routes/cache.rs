@@ -0,0 +1,79 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use birl_core::OutputFormat;
+use birl_storage::StorageService;
+use std::sync::Arc;
+use tracing::{error, warn};
+
+use super::create::{error_status, viewer_region, ErrorResponse, VARY_VIEWER_COUNTRY};
+use crate::middleware::Caller;
+
+/// GET /cache/{cache_key}.{ext} - A cached composite by its cache key, where
+/// local storage's `url`s point
+///
+/// With licenses, composites are checked against the viewer's region like a
+/// render, so a key computed for another region doesn't lift its block.
+pub async fn get_cached(
+    State(storage): State<Arc<StorageService>>,
+    Extension(caller): Extension<Caller>,
+    headers: HeaderMap,
+    Path(file): Path<String>,
+) -> Response {
+    let Some((cache_key, format)) = parse_file(&file) else {
+        let error = format!("Invalid cached composite '{}'", file);
+        return (StatusCode::BAD_REQUEST, Json(ErrorResponse::new(error))).into_response();
+    };
+
+    let region = viewer_region(&headers, &caller, None);
+    let vary = [(header::VARY, VARY_VIEWER_COUNTRY)];
+    match storage
+        .get_cached_composite_for(cache_key, format, region.as_deref())
+        .await
+    {
+        Ok(Some(data)) => {
+            (vary, [(header::CONTENT_TYPE, format.content_type())], data).into_response()
+        }
+        Ok(None) => {
+            let error = format!("No cached composite '{}'", file);
+            (StatusCode::NOT_FOUND, vary, Json(ErrorResponse::new(error))).into_response()
+        }
+        Err(e) => {
+            let status = error_status(&e);
+            if status == StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS {
+                warn!("Refused cached composite {}: {:#}", file, e);
+                return (status, vary, Json(ErrorResponse::new(e.to_string()))).into_response();
+            }
+            error!("Error fetching cached composite {}: {:#}", file, e);
+            let error = ErrorResponse::new("Failed to fetch cached composite");
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response()
+        }
+    }
+}
+
+/// Cache key and format of `{cache_key}.{ext}`; keys are hex hashes
+fn parse_file(file: &str) -> Option<(&str, OutputFormat)> {
+    let (cache_key, extension) = file.rsplit_once('.')?;
+    let valid = !cache_key.is_empty() && cache_key.chars().all(|c| c.is_ascii_hexdigit());
+    let format = extension.parse().ok()?;
+    valid.then_some((cache_key, format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file() {
+        assert_eq!(
+            parse_file("d3e679cb3d375c4f.jpg"),
+            Some(("d3e679cb3d375c4f", OutputFormat::Jpeg))
+        );
+        assert_eq!(parse_file("d3e679cb3d375c4f"), None);
+        assert_eq!(parse_file("../secrets.png"), None);
+        assert_eq!(parse_file("d3e679cb3d375c4f.gif"), None);
+    }
+}
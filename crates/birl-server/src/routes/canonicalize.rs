@@ -0,0 +1,21 @@
+use axum::{extract::Query, Json};
+use birl_core::{canonicalize, CanonicalParams, View};
+use serde::Deserialize;
+
+use super::create::default_view;
+
+/// Query string for GET /canonicalize
+#[derive(Debug, Deserialize)]
+pub struct CanonicalizeQuery {
+    /// Comma-separated parameters: "category/sku,category/sku,..."
+    #[serde(default)]
+    pub p: String,
+    /// View the params will be rendered for (default: front)
+    #[serde(default = "default_view")]
+    pub view: View,
+}
+
+/// GET /canonicalize - Canonical param string and cache key, for edge query rewriting
+pub async fn get_canonical(Query(query): Query<CanonicalizeQuery>) -> Json<CanonicalParams> {
+    Json(canonicalize(&query.p, query.view))
+}
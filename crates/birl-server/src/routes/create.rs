@@ -1,29 +1,137 @@
 use axum::{
+    body::Body,
     extract::State,
-    http::{header, StatusCode},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
 };
-use birl_core::{compose_layers, generate_cache_key, parse_params, LayerNormalizer, View};
-use birl_storage::StorageService;
+use birl_core::phash::{dhash_bytes, format_hash};
+use birl_core::{
+    validate_for_view, BirlError, CatalogIssue, CompositeOptions, DisplayNames, DroppedLayer,
+    LayerParam, OutputFormat, Params, Presets, View, ViewValidationError,
+};
+use birl_storage::{
+    outfit_key, output_hash, MissingLayer, Prefetcher, RequestRecord, RequestRecorder,
+    RollupRecorder, StorageService,
+};
+use futures::{future, stream, StreamExt};
 use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::io;
 use std::sync::Arc;
-use tracing::{error, info, warn};
+use std::time::Instant;
+use tokio::io::DuplexStream;
+use tokio::sync::oneshot;
+use tokio_util::io::ReaderStream;
+use tracing::{debug, error};
+
+use crate::middleware::Caller;
+use crate::negotiation::CapabilityClasses;
+use crate::options::{CacheMode, RenderOptions, RENDER_OPTIONS_HEADER};
 
 /// Request body for POST /create
 #[derive(Debug, Deserialize)]
 pub struct CreateRequest {
-    /// Comma-separated parameters: "category/sku,category/sku,..."
-    pub p: String,
+    /// Parameters: "category/sku,category/sku,..." or [{"category", "sku"}, ...]
+    #[serde(default)]
+    pub p: Params,
+    /// Named outfit rendered under `p`'s layers
+    #[serde(default)]
+    pub preset: Option<String>,
     /// View to render (default: front)
     #[serde(default = "default_view")]
     pub view: View,
-    /// Bypass cache and force regeneration
-    #[serde(default)]
-    pub bypass_cache: bool,
+    /// Format, sizes, caching and policy flags
+    #[serde(flatten)]
+    pub options: RenderOptions,
+}
+
+impl CreateRequest {
+    /// The requested layers, the preset's first; strict requests reject params
+    /// that don't parse or name an unknown category instead of skipping them
+    pub fn params(&self) -> Result<Vec<LayerParam>, BirlError> {
+        let mut params = match &self.preset {
+            Some(name) => Presets::global().params(name)?,
+            None => Vec::new(),
+        };
+        if self.options.strict {
+            params.extend(self.p.try_to_params()?);
+        } else {
+            params.extend(self.p.to_params());
+        }
+        Ok(params)
+    }
+
+    /// Validated rendering options, with `format` if none was requested
+    pub fn composite_options(&self, format: OutputFormat) -> Result<CompositeOptions, BirlError> {
+        self.options.validate()?;
+        Ok(self.options.composite_options(format))
+    }
+
+    /// Render for the viewer's country, as CloudFront reports it
+    /// See [`viewer_region`] for when the request's own `region` counts
+    pub fn with_viewer_region(mut self, headers: &HeaderMap, caller: &Caller) -> Self {
+        self.options.region = viewer_region(headers, caller, self.options.region.take());
+        self
+    }
+
+    /// The `X-Render-Options` header echoing the normalized options
+    pub fn options_header(&self, format: OutputFormat) -> Option<[(&'static str, HeaderValue); 1]> {
+        let value = HeaderValue::from_str(&self.options.echo(format)).ok()?;
+        Some([(RENDER_OPTIONS_HEADER, value)])
+    }
+}
+
+/// Buffer between the encoder and the response body when streaming
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Response header carrying the composite's perceptual hash
+pub const PERCEPTUAL_HASH_HEADER: &str = "x-perceptual-hash";
+
+/// Response header listing requested items that weren't rendered, and why
+pub const DROPPED_LAYERS_HEADER: &str = "x-dropped-layers";
+
+/// Response header listing layers that weren't found, with "did you mean" SKUs
+pub const MISSING_LAYERS_HEADER: &str = "x-missing-layers";
+
+/// Response header naming the requested layers, from the products JSON
+pub const LAYER_NAMES_HEADER: &str = "x-layer-names";
+
+/// Response header carrying the credits the licenses of the layers require
+pub const ATTRIBUTION_HEADER: &str = "x-attribution";
+
+/// Request header CloudFront sets to the viewer's country code
+pub const VIEWER_COUNTRY_HEADER: &str = "cloudfront-viewer-country";
+
+/// `Vary` of responses the licenses of their layers can change
+pub const VARY_VIEWER_COUNTRY: &str = "CloudFront-Viewer-Country";
+
+/// `Vary` of negotiated images the licenses of their layers can change
+pub const VARY_ACCEPT_VIEWER_COUNTRY: &str = "Accept, CloudFront-Viewer-Country";
+
+/// The region a render's licenses are checked in: the viewer's country from
+/// CloudFront, or `requested` if an internal caller names one
+/// Anyone else could lift a regional block by naming another region, so their
+/// `requested` region is ignored. The viewer's country is only read from
+/// requests that came through the CDN; on others the region is unknown.
+pub(crate) fn viewer_region(
+    headers: &HeaderMap,
+    caller: &Caller,
+    requested: Option<String>,
+) -> Option<String> {
+    if caller.internal && requested.is_some() {
+        return requested;
+    }
+    if !caller.via_cdn {
+        return None;
+    }
+    headers
+        .get(VIEWER_COUNTRY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
 }
 
-fn default_view() -> View {
+pub(crate) fn default_view() -> View {
     View::Front
 }
 
@@ -31,105 +139,420 @@ fn default_view() -> View {
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
     pub error: String,
+    /// SKUs the catalog doesn't list, with the ones they may be typos of
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing: Vec<MissingLayer>,
+}
+
+impl ErrorResponse {
+    pub fn new(error: impl Into<String>) -> Self {
+        Self {
+            error: error.into(),
+            missing: Vec::new(),
+        }
+    }
+
+    /// Add the unknown SKUs of a failed render, if the catalog rejected it
+    pub(crate) fn with_missing(mut self, e: &anyhow::Error) -> Self {
+        let catalog_error = e.chain().find_map(|e| match e.downcast_ref::<BirlError>() {
+            Some(BirlError::NotInCatalog(catalog_error)) => Some(catalog_error),
+            _ => None,
+        });
+        if let Some(catalog_error) = catalog_error {
+            self.missing = catalog_error
+                .issues
+                .iter()
+                .filter_map(|issue| match issue {
+                    CatalogIssue::UnknownSku {
+                        category,
+                        sku,
+                        suggestion,
+                    } => Some(MissingLayer {
+                        layer: LayerParam::new(category, sku.as_str()),
+                        suggestion: suggestion.clone(),
+                    }),
+                    _ => None,
+                })
+                .collect();
+        }
+        self
+    }
+}
+
+/// Validation error response for strict requests
+#[derive(Debug, Serialize)]
+pub struct ValidationErrorResponse {
+    pub error: String,
+    pub view: View,
+    pub ignored_categories: Vec<String>,
+}
+
+impl From<ViewValidationError> for ValidationErrorResponse {
+    fn from(e: ViewValidationError) -> Self {
+        Self {
+            error: e.to_string(),
+            view: e.view,
+            ignored_categories: e.categories,
+        }
+    }
+}
+
+/// HTTP status for a failed render, from the core error behind it if any
+pub(crate) fn error_status(e: &anyhow::Error) -> StatusCode {
+    let Some(e) = e.chain().find_map(|e| e.downcast_ref::<BirlError>()) else {
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    };
+    match e {
+        BirlError::InvalidParam(_) | BirlError::InvalidLayer(_) => StatusCode::BAD_REQUEST,
+        BirlError::MissingPlate(_) | BirlError::MissingSource { .. } => StatusCode::NOT_FOUND,
+        BirlError::NotInCatalog(_) => StatusCode::NOT_FOUND,
+        BirlError::InvalidRecipe(_) | BirlError::NotInView(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        BirlError::LayerConflict(_)
+        | BirlError::ConstraintViolation(_)
+        | BirlError::LimitExceeded(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        BirlError::Unlicensed(_) => StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
 }
 
 /// POST /create - Create a composite image
+#[allow(clippy::too_many_arguments)]
 pub async fn create_composite(
     State(storage): State<Arc<StorageService>>,
+    State(capabilities): State<Arc<CapabilityClasses>>,
+    State(recorder): State<Option<Arc<RequestRecorder>>>,
+    State(rollup): State<Option<Arc<RollupRecorder>>>,
+    State(prefetcher): State<Option<Arc<Prefetcher>>>,
+    Extension(caller): Extension<Caller>,
+    headers: HeaderMap,
     Json(request): Json<CreateRequest>,
 ) -> Response {
-    if let Err(e) = create_composite_impl(storage, request).await {
-        error!("Error creating composite: {}", e);
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
-        )
-            .into_response();
-    }
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok());
+    let class = capabilities.classify(accept);
+    debug!("Capability class: {} ({})", class.name, class.format);
 
-    StatusCode::OK.into_response()
+    let recorder = recorder
+        .filter(|recorder| request.options.cache == CacheMode::Default && recorder.sample());
+    let prefetcher = prefetcher
+        .filter(|_| request.options.cache == CacheMode::Default && !request.options.bypass_cache);
+    match create_composite_impl(
+        storage,
+        request.with_viewer_region(&headers, &caller),
+        class.format,
+        recorder,
+        rollup.clone(),
+        prefetcher,
+    )
+    .await
+    {
+        Ok(response) => response,
+        Err(e) => {
+            error!("Error creating composite: {:#}", e);
+            if let Some(rollup) = rollup {
+                rollup.record_error();
+            }
+            (
+                error_status(&e),
+                Json(ErrorResponse::new(e.to_string()).with_missing(&e)),
+            )
+                .into_response()
+        }
+    }
 }
 
 async fn create_composite_impl(
     storage: Arc<StorageService>,
     request: CreateRequest,
+    negotiated_format: OutputFormat,
+    recorder: Option<Arc<RequestRecorder>>,
+    rollup: Option<Arc<RollupRecorder>>,
+    prefetcher: Option<Arc<Prefetcher>>,
 ) -> anyhow::Result<Response> {
-    let CreateRequest {
-        p,
-        view,
-        bypass_cache,
-    } = request;
-
-    // Fetch base plate image
-    let base_image_data = storage.fetch_base_plate(view).await?;
+    let options = request.composite_options(negotiated_format)?;
+    let params = request.params()?;
 
-    // If no parameters provided, return just the base plate
-    if p.trim().is_empty() {
-        return Ok((
-            StatusCode::OK,
-            [(header::CONTENT_TYPE, "image/jpeg")],
-            base_image_data,
-        )
-            .into_response());
-    }
-
-    // Parse and normalize parameters
-    let params = parse_params(&p);
-    let normalizer = LayerNormalizer::new(view, &params);
-    let normalized_params = normalizer.normalize_all(&params);
-
-    // Generate cache key
-    let cache_key = generate_cache_key(&normalized_params, view, view.plate_value());
-
-    // Check cache (unless bypassing)
-    if !bypass_cache {
-        if let Some(cached_data) = storage.get_cached_composite(&cache_key).await? {
-            info!("Serving cached image: {}", cache_key);
+    if request.options.strict {
+        if let Err(e) = validate_for_view(&params, request.view) {
+            if let Some(rollup) = rollup {
+                rollup.record_error();
+            }
             return Ok((
-                StatusCode::OK,
-                [(header::CONTENT_TYPE, "image/jpeg")],
-                cached_data,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ValidationErrorResponse::from(e)),
             )
                 .into_response());
         }
     }
+    let start = Instant::now();
+    let prepared = match request.options.cache {
+        CacheMode::Default => {
+            storage
+                .prepare(
+                    &params,
+                    request.view,
+                    &options,
+                    request.options.bypass_cache,
+                )
+                .await?
+        }
+        CacheMode::None => {
+            storage
+                .prepare_uncached(&params, request.view, &options)
+                .await?
+        }
+    };
 
-    // Fetch layers in parallel
-    let layers_result = storage.fetch_layers(&normalized_params, view).await?;
-
-    // Filter out None values and collect into Vec<Bytes>
-    let layers: Vec<_> = layers_result.into_iter().flatten().collect();
-
-    // Log if some layers are missing
-    let requested_count = normalized_params.len();
-    let found_count = layers.len();
+    if let Some(prefetcher) = prefetcher {
+        prefetcher.schedule(&storage, &params, request.view, &options);
+    }
 
-    if found_count < requested_count {
-        warn!(
-            "Found {}/{} requested layers for view {}",
-            found_count,
-            requested_count,
-            view.as_str()
+    // Uncached renders leave no trace, outfit counts included
+    let rollup = rollup.filter(|_| request.options.cache == CacheMode::Default);
+    if let Some(rollup) = rollup {
+        rollup.record(
+            outfit_key(&params, request.view),
+            prepared.cached(),
+            prepared.requested_layers,
+            prepared.found_layers,
         );
     }
 
-    // Compose the image
-    let composite_data = compose_layers(&base_image_data, layers)?;
+    let headers = [
+        (header::CONTENT_TYPE, prepared.format.content_type()),
+        (header::VARY, VARY_ACCEPT_VIEWER_COUNTRY),
+    ];
+    let dropped = dropped_header(&prepared.dropped);
+    let missing = missing_header(&prepared.missing);
+    let names = names_header(&params);
+    let attribution = attribution_header(&prepared.attributions);
+    let echoed = request.options_header(negotiated_format);
+
+    // Hashes need the encoded bytes, so those responses are buffered
+    if request.options.phash || recorder.is_some() {
+        let rendered = prepared.encode().await?;
+        if let Some(recorder) = recorder {
+            let record = RequestRecord {
+                params,
+                view: request.view,
+                options,
+                latency_ms: start.elapsed().as_millis() as u64,
+                cached: rendered.cached,
+                output_hash: output_hash(&rendered.data),
+            };
+            capture(storage, recorder, record);
+        }
 
-    // Only cache if all requested images were found
-    if requested_count == found_count {
-        if let Err(e) = storage.save_composite(&cache_key, composite_data.clone()).await {
-            error!("Failed to save to cache: {}", e);
-            // Don't fail the request if caching fails
+        let phash = if request.options.phash {
+            Some(format_hash(dhash_bytes(&rendered.data)?))
+        } else {
+            None
+        };
+        let mut response = (
+            StatusCode::OK,
+            headers,
+            dropped,
+            missing,
+            names,
+            attribution,
+            echoed,
+            rendered.data,
+        )
+            .into_response();
+        if let Some(hash) = phash {
+            response
+                .headers_mut()
+                .insert(PERCEPTUAL_HASH_HEADER, HeaderValue::from_str(&hash)?);
         }
+        return Ok(response);
+    }
+
+    if let Some(data) = prepared.encoded() {
+        return Ok((
+            StatusCode::OK,
+            headers,
+            dropped,
+            missing,
+            names,
+            attribution,
+            echoed,
+            data.clone(),
+        )
+            .into_response());
     }
 
+    // Stream the encode straight into the response body
+    let cache_key = prepared.cache_key.clone();
+    let body = streamed_body(cache_key, |mut writer| async move {
+        prepared.finalize_into(&mut writer).await
+    });
     Ok((
         StatusCode::OK,
-        [(header::CONTENT_TYPE, "image/jpeg")],
-        composite_data,
+        headers,
+        dropped,
+        missing,
+        names,
+        attribution,
+        echoed,
+        body,
     )
         .into_response())
 }
+
+/// Response body fed by `write` through an in-memory pipe
+/// A failed write ends the body with an error rather than a clean end of
+/// stream, so the response is aborted instead of passing for a whole image
+fn streamed_body<F, Fut>(cache_key: String, write: F) -> Body
+where
+    F: FnOnce(DuplexStream) -> Fut,
+    Fut: Future<Output = anyhow::Result<u64>> + Send + 'static,
+{
+    let (writer, reader) = tokio::io::duplex(STREAM_BUFFER_SIZE);
+    let (done, written) = oneshot::channel();
+    let write = write(writer);
+    tokio::spawn(async move {
+        // The writer is dropped with `write`, ending the reader's stream
+        let _ = done.send(write.await);
+    });
+
+    let outcome = stream::once(written).filter_map(move |written| {
+        let failure = match written {
+            Ok(Ok(_)) => None,
+            Ok(Err(e)) => Some(format!("{:#}", e)),
+            Err(_) => Some("encoder stopped".to_string()),
+        };
+        let failure = failure.map(|e| {
+            error!("Failed to stream composite {}: {}", cache_key, e);
+            Err(io::Error::other(e))
+        });
+        future::ready(failure)
+    });
+    Body::from_stream(ReaderStream::new(reader).chain(outcome))
+}
+
+/// `category/sku (reason), ...`, or no header when nothing was dropped
+pub(crate) fn dropped_header(dropped: &[DroppedLayer]) -> Option<[(&'static str, HeaderValue); 1]> {
+    let values = dropped
+        .iter()
+        .map(|dropped| format!("{} ({})", dropped.layer, dropped.reason.name()));
+    list_header(DROPPED_LAYERS_HEADER, values)
+}
+
+/// `category/sku (did you mean sku?), ...`, or no header when every layer was found
+pub(crate) fn missing_header(missing: &[MissingLayer]) -> Option<[(&'static str, HeaderValue); 1]> {
+    list_header(
+        MISSING_LAYERS_HEADER,
+        missing.iter().map(ToString::to_string),
+    )
+}
+
+/// `category/sku (display name), ...` for the layers that have a display name
+/// Names are sent as UTF-8
+pub(crate) fn names_header(params: &[LayerParam]) -> Option<[(&'static str, HeaderValue); 1]> {
+    let names = DisplayNames::global();
+    let values: Vec<String> = params
+        .iter()
+        .filter_map(|layer| Some(format!("{} ({})", layer, names.name(layer)?)))
+        .collect();
+    if values.is_empty() {
+        return None;
+    }
+    let value = HeaderValue::from_bytes(values.join(", ").as_bytes()).ok()?;
+    Some([(LAYER_NAMES_HEADER, value)])
+}
+
+/// `credit; credit` (credits may contain commas), sent as UTF-8, or no header
+/// when no layer needs one
+pub(crate) fn attribution_header(
+    attributions: &[String],
+) -> Option<[(&'static str, HeaderValue); 1]> {
+    if attributions.is_empty() {
+        return None;
+    }
+    let value = HeaderValue::from_bytes(attributions.join("; ").as_bytes()).ok()?;
+    Some([(ATTRIBUTION_HEADER, value)])
+}
+
+/// A header of comma-separated values, or none without values
+fn list_header(
+    name: &'static str,
+    values: impl Iterator<Item = String>,
+) -> Option<[(&'static str, HeaderValue); 1]> {
+    let values: Vec<String> = values.collect();
+    if values.is_empty() {
+        return None;
+    }
+    let value = HeaderValue::from_str(&values.join(", ")).ok()?;
+    Some([(name, value)])
+}
+
+/// Keep a captured request, writing the replay file in the background when due
+fn capture(storage: Arc<StorageService>, recorder: Arc<RequestRecorder>, record: RequestRecord) {
+    if recorder.record(record) {
+        tokio::spawn(async move {
+            if let Err(e) = recorder.flush(&storage).await {
+                error!("Failed to write capture {}: {:#}", recorder.name(), e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_streamed_body_fails_with_encode() {
+        let body = streamed_body("abc".to_string(), |mut writer| async move {
+            writer.write_all(b"partial").await?;
+            anyhow::bail!("Encoder failed")
+        });
+        assert!(axum::body::to_bytes(body, usize::MAX).await.is_err());
+
+        let body = streamed_body("abc".to_string(), |mut writer| async move {
+            writer.write_all(b"whole").await?;
+            Ok(5)
+        });
+        let data = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        assert_eq!(&data[..], b"whole");
+    }
+
+    #[test]
+    fn test_viewer_region() {
+        let mut headers = HeaderMap::new();
+        headers.insert(VIEWER_COUNTRY_HEADER, HeaderValue::from_static("DE"));
+        let anonymous = Caller {
+            via_cdn: true,
+            ..Caller::default()
+        };
+        let internal = Caller {
+            name: Some("ops".to_string()),
+            internal: true,
+            via_cdn: true,
+        };
+
+        // Only internal callers may name another region than the viewer's
+        let requested = || Some("US".to_string());
+        assert_eq!(
+            viewer_region(&headers, &anonymous, requested()).as_deref(),
+            Some("DE")
+        );
+        assert_eq!(
+            viewer_region(&headers, &internal, requested()).as_deref(),
+            Some("US")
+        );
+        assert_eq!(
+            viewer_region(&headers, &internal, None).as_deref(),
+            Some("DE")
+        );
+        assert_eq!(
+            viewer_region(&HeaderMap::new(), &anonymous, requested()),
+            None
+        );
+
+        // Straight to the origin, the viewer's country could be anything
+        assert_eq!(viewer_region(&headers, &Caller::default(), None), None);
+    }
+}
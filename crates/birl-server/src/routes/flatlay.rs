@@ -0,0 +1,94 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use birl_storage::StorageService;
+use std::sync::Arc;
+use tracing::{debug, error};
+
+use super::create::{
+    attribution_header, dropped_header, error_status, missing_header, names_header, CreateRequest,
+    ErrorResponse, VARY_ACCEPT_VIEWER_COUNTRY,
+};
+use crate::middleware::Caller;
+use crate::negotiation::CapabilityClasses;
+use crate::options::CacheMode;
+
+/// POST /flat-lay - The outfit's garments laid out on the configured
+/// `[flat_lay]` canvas instead of worn
+///
+/// Takes the same body as /create; the layout's view is used, not the body's.
+pub async fn create_flat_lay(
+    State(storage): State<Arc<StorageService>>,
+    State(capabilities): State<Arc<CapabilityClasses>>,
+    Extension(caller): Extension<Caller>,
+    headers: HeaderMap,
+    Json(request): Json<CreateRequest>,
+) -> Response {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok());
+    let class = capabilities.classify(accept);
+    debug!("Capability class: {} ({})", class.name, class.format);
+
+    let request = request.with_viewer_region(&headers, &caller);
+    let params = match request.params() {
+        Ok(params) => params,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(e.to_string())),
+            )
+                .into_response()
+        }
+    };
+    let rendered = match request.composite_options(class.format) {
+        Ok(options) => match request.options.cache {
+            CacheMode::Default => {
+                storage
+                    .prepare_flat_lay(&params, &options, request.options.bypass_cache)
+                    .await
+            }
+            CacheMode::None => storage.prepare_flat_lay_uncached(&params, &options).await,
+        },
+        Err(e) => Err(e.into()),
+    };
+    let prepared = match rendered {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            error!("Error creating flat lay: {:#}", e);
+            return (
+                error_status(&e),
+                Json(ErrorResponse::new(e.to_string()).with_missing(&e)),
+            )
+                .into_response();
+        }
+    };
+
+    let headers = [
+        (header::CONTENT_TYPE, prepared.format.content_type()),
+        (header::VARY, VARY_ACCEPT_VIEWER_COUNTRY),
+    ];
+    let dropped = dropped_header(&prepared.dropped);
+    let missing = missing_header(&prepared.missing);
+    let names = names_header(&params);
+    let attribution = attribution_header(&prepared.attributions);
+    match prepared.encode().await {
+        Ok(rendered) => (
+            StatusCode::OK,
+            headers,
+            dropped,
+            missing,
+            names,
+            attribution,
+            rendered.data,
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Error encoding flat lay: {:#}", e);
+            (error_status(&e), Json(ErrorResponse::new(e.to_string()))).into_response()
+        }
+    }
+}
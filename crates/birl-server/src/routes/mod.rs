@@ -1,5 +1,23 @@
+pub mod batch;
+pub mod bundle;
+pub mod cache;
+pub mod canonicalize;
 pub mod create;
+pub mod flatlay;
+pub mod plan;
+pub mod preflight;
 pub mod products;
+pub mod ready;
+pub mod recipe;
 
+pub use batch::{create_batch, stream_batch};
+pub use bundle::create_bundle;
+pub use cache::get_cached;
+pub use canonicalize::get_canonical;
 pub use create::create_composite;
+pub use flatlay::create_flat_lay;
+pub use plan::plan_composite;
+pub use preflight::get_preflight;
 pub use products::get_products;
+pub use ready::get_ready;
+pub use recipe::{get_recipe, render_recipe};
@@ -0,0 +1,43 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use birl_storage::StorageService;
+use std::sync::Arc;
+use tracing::debug;
+
+use super::create::{error_status, CreateRequest, ErrorResponse};
+use crate::middleware::Caller;
+use crate::negotiation::CapabilityClasses;
+
+/// POST /plan - What /create would render for the same body, without rendering
+pub async fn plan_composite(
+    State(storage): State<Arc<StorageService>>,
+    State(capabilities): State<Arc<CapabilityClasses>>,
+    Extension(caller): Extension<Caller>,
+    headers: HeaderMap,
+    Json(request): Json<CreateRequest>,
+) -> Response {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok());
+    let class = capabilities.classify(accept);
+    debug!("Capability class: {} ({})", class.name, class.format);
+
+    let request = request.with_viewer_region(&headers, &caller);
+    let plan = request
+        .composite_options(class.format)
+        .and_then(|options| Ok((request.params()?, options)))
+        .map_err(anyhow::Error::from)
+        .and_then(|(params, options)| storage.plan(&params, request.view, &options));
+    match plan {
+        Ok(plan) => (request.options_header(class.format), Json(plan)).into_response(),
+        Err(e) => (
+            error_status(&e),
+            Json(ErrorResponse::new(e.to_string()).with_missing(&e)),
+        )
+            .into_response(),
+    }
+}
@@ -0,0 +1,30 @@
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use birl_core::{parse_params, View};
+use birl_storage::{PreflightReport, StorageService};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use super::create::default_view;
+
+/// Query string for GET /preflight
+#[derive(Debug, Deserialize)]
+pub struct PreflightQuery {
+    /// Comma-separated parameters: "category/sku,category/sku,..."
+    #[serde(default)]
+    pub p: String,
+    /// View the params will be rendered for (default: front)
+    #[serde(default = "default_view")]
+    pub view: View,
+}
+
+/// GET /preflight - Check which layers of an outfit exist, without rendering
+pub async fn get_preflight(
+    State(storage): State<Arc<StorageService>>,
+    Query(query): Query<PreflightQuery>,
+) -> Json<PreflightReport> {
+    let params = parse_params(&query.p);
+    Json(storage.validate_params(&params, query.view).await)
+}
@@ -4,7 +4,7 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
-use birl_storage::StorageService;
+use birl_storage::{StorageService, PRODUCTS_KEY};
 use serde::Serialize;
 use std::sync::Arc;
 use tracing::error;
@@ -33,10 +33,8 @@ pub async fn get_products(State(storage): State<Arc<StorageService>>) -> Respons
 }
 
 async fn get_products_impl(storage: Arc<StorageService>) -> anyhow::Result<String> {
-    const CACHE_KEY: &str = "products-dynamic-cache";
-
     let json_data = storage
-        .fetch_cached_json(CACHE_KEY)
+        .fetch_cached_json(PRODUCTS_KEY)
         .await?
         .ok_or_else(|| anyhow::anyhow!("Products cache not found"))?;
 
@@ -0,0 +1,64 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use birl_storage::credentials::REFRESH_AHEAD;
+use birl_storage::StorageService;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+/// Readiness of the server and its storage credentials
+#[derive(Debug, Serialize)]
+pub struct ReadyResponse {
+    /// "ready", "expiring" (credentials close to expiry that couldn't be
+    /// refreshed) or "unavailable" (no usable credentials)
+    pub status: &'static str,
+    /// Unix time the storage credentials expire at, if they do
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credentials_expire_at: Option<u64>,
+    /// Seconds until then
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credentials_expire_in: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// GET /ready - Whether the server can reach storage, and when its
+/// credentials expire
+/// Credentials about to expire are refreshed first, so probing keeps an idle
+/// server's credentials current
+pub async fn get_ready(State(storage): State<Arc<StorageService>>) -> Response {
+    match storage.credentials_expiry().await {
+        Ok(expiry) => {
+            let now = SystemTime::now();
+            let expire_in = expiry.map(|expiry| expiry.duration_since(now).unwrap_or_default());
+            let status = match expire_in {
+                Some(left) if left <= REFRESH_AHEAD => "expiring",
+                _ => "ready",
+            };
+            let response = ReadyResponse {
+                status,
+                credentials_expire_at: expiry
+                    .and_then(|expiry| expiry.duration_since(UNIX_EPOCH).ok())
+                    .map(|since| since.as_secs()),
+                credentials_expire_in: expire_in.map(|left| left.as_secs()),
+                error: None,
+            };
+            (StatusCode::OK, Json(response)).into_response()
+        }
+        Err(e) => {
+            error!("Storage credentials unavailable: {:#}", e);
+            let response = ReadyResponse {
+                status: "unavailable",
+                credentials_expire_at: None,
+                credentials_expire_in: None,
+                error: Some(format!("{:#}", e)),
+            };
+            (StatusCode::SERVICE_UNAVAILABLE, Json(response)).into_response()
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use birl_core::{
+    parse_params, patch_mask_categories, CompositeOptions, LayerNormalizer, LayerOffsets,
+    Orientations, Recipe, View,
+};
+use birl_storage::StorageService;
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::error;
+
+use super::create::{
+    default_view, error_status, viewer_region, ErrorResponse, VARY_VIEWER_COUNTRY,
+};
+use crate::middleware::Caller;
+
+/// Query string for GET /recipe
+#[derive(Debug, Deserialize)]
+pub struct RecipeQuery {
+    /// Comma-separated parameters: "category/sku,category/sku,..."
+    #[serde(default)]
+    pub p: String,
+    /// View the params will be rendered for (default: front)
+    #[serde(default = "default_view")]
+    pub view: View,
+}
+
+/// Request body for POST /recipe
+#[derive(Debug, Deserialize)]
+pub struct RecipeRequest {
+    /// View whose plate and layers the sources read (default: front)
+    #[serde(default = "default_view")]
+    pub view: View,
+    /// Region the licenses are checked in; only internal callers may set it,
+    /// everyone else gets the viewer's country
+    #[serde(default)]
+    pub region: Option<String>,
+    pub recipe: Recipe,
+}
+
+/// GET /recipe - The composition graph /create would run for these params
+pub async fn get_recipe(
+    State(storage): State<Arc<StorageService>>,
+    Query(query): Query<RecipeQuery>,
+) -> Json<Recipe> {
+    let params = parse_params(&query.p);
+    let normalized = LayerNormalizer::new(query.view, &params).normalize_all(&params);
+    let masks = patch_mask_categories(&normalized);
+    let placements = storage.patch_placements(&normalized, query.view);
+    let offsets = LayerOffsets::global().for_layers(&normalized, &placements, query.view);
+    let orientations = Orientations::global().for_layers(&normalized, &placements, query.view);
+
+    Json(Recipe::compile_placed(
+        &normalized,
+        &masks,
+        &placements,
+        &offsets,
+        &orientations,
+        &CompositeOptions::default(),
+    ))
+}
+
+/// POST /recipe - Render a caller-supplied composition graph (never cached)
+pub async fn render_recipe(
+    State(storage): State<Arc<StorageService>>,
+    Extension(caller): Extension<Caller>,
+    headers: HeaderMap,
+    Json(request): Json<RecipeRequest>,
+) -> Response {
+    let error_response = |status, e: anyhow::Error| {
+        (status, Json(ErrorResponse::new(format!("{:#}", e)))).into_response()
+    };
+
+    if let Err(e) = request.recipe.validate() {
+        return error_response(StatusCode::UNPROCESSABLE_ENTITY, e.into());
+    }
+
+    let region = viewer_region(&headers, &caller, request.region);
+    let rendered = storage
+        .render_recipe(&request.recipe, request.view, region.as_deref())
+        .await;
+    match rendered {
+        Ok(data) => {
+            let format = request.recipe.encode_options().format;
+            (
+                StatusCode::OK,
+                [
+                    (header::CONTENT_TYPE, format.content_type()),
+                    (header::VARY, VARY_VIEWER_COUNTRY),
+                ],
+                data,
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("Error rendering recipe: {:#}", e);
+            error_response(error_status(&e), e)
+        }
+    }
+}
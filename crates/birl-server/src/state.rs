@@ -0,0 +1,47 @@
+use crate::negotiation::CapabilityClasses;
+use axum::extract::FromRef;
+use birl_storage::{Prefetcher, RequestRecorder, RollupRecorder, StorageService};
+use std::sync::Arc;
+
+/// Shared state for all routes
+#[derive(Clone)]
+pub struct AppState {
+    pub storage: Arc<StorageService>,
+    pub capabilities: Arc<CapabilityClasses>,
+    /// Samples /create requests when the profile sets up a capture
+    pub recorder: Option<Arc<RequestRecorder>>,
+    /// Counts /create requests when the profile sets up daily rollups
+    pub rollup: Option<Arc<RollupRecorder>>,
+    /// Renders other views after a front view when the profile sets up prefetch
+    pub prefetcher: Option<Arc<Prefetcher>>,
+}
+
+impl FromRef<AppState> for Arc<StorageService> {
+    fn from_ref(state: &AppState) -> Self {
+        state.storage.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<CapabilityClasses> {
+    fn from_ref(state: &AppState) -> Self {
+        state.capabilities.clone()
+    }
+}
+
+impl FromRef<AppState> for Option<Arc<RequestRecorder>> {
+    fn from_ref(state: &AppState) -> Self {
+        state.recorder.clone()
+    }
+}
+
+impl FromRef<AppState> for Option<Arc<RollupRecorder>> {
+    fn from_ref(state: &AppState) -> Self {
+        state.rollup.clone()
+    }
+}
+
+impl FromRef<AppState> for Option<Arc<Prefetcher>> {
+    fn from_ref(state: &AppState) -> Self {
+        state.prefetcher.clone()
+    }
+}
@@ -1,51 +1,128 @@
+use crate::disk::{DiskCacheStats, DiskLayerCache};
+use crate::plan::LicenseRecord;
 use crate::StorageBackend;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use birl_core::{Capacity, OutputFormat, TieredCache, TieredStats};
 use bytes::Bytes;
-use lru::LruCache;
-use std::num::NonZeroUsize;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tracing::{debug, info};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info, warn};
 
-/// Multi-tier image cache (LRU in-memory + persistent storage)
+/// Default memory budget of a byte-bounded composite cache (512 MB)
+pub const DEFAULT_MEMORY_CACHE_BYTES: usize = 512 * 1024 * 1024;
+
+/// Default memory budget of the layer cache of profiles (256 MB)
+pub const DEFAULT_LAYER_MEMORY_BYTES: usize = 256 * 1024 * 1024;
+
+/// Default time a layer is served from the memory layer cache of profiles
+pub const DEFAULT_LAYER_MEMORY_TTL: Duration = Duration::from_secs(300);
+
+/// Multi-tier image cache (LRU in-memory, optional local disk, persistent storage)
 pub struct ImageCache {
-    /// In-memory LRU cache
-    memory: Arc<Mutex<LruCache<String, Arc<Bytes>>>>,
+    /// In-memory LRU cache, bounded by entries or by composite bytes
+    memory: TieredCache<String, Bytes>,
+    /// Node-local disk tier, saving backend round-trips on memory misses
+    disk: Option<DiskLayerCache>,
     /// Storage backend (S3 or local filesystem)
     backend: Arc<dyn StorageBackend>,
+    /// How long after it was saved a composite is served, in every tier
+    ttl: Option<Duration>,
 }
 
 impl ImageCache {
     /// Create a new image cache
     pub fn new(backend: Arc<dyn StorageBackend>, capacity: usize) -> Self {
-        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::new(1000).unwrap());
+        let capacity = match capacity {
+            0 => 1000,
+            capacity => capacity,
+        };
+
+        Self {
+            memory: memory_tier(Capacity::Entries(capacity), None),
+            disk: None,
+            backend,
+            ttl: None,
+        }
+    }
+
+    /// Bound the memory tier by the bytes of its composites instead of their
+    /// count, since composites range from ~50 KB to several MB
+    pub fn with_max_bytes(mut self, max: usize) -> Self {
+        self.memory = memory_tier(Capacity::Bytes(max), self.ttl);
+        self
+    }
+
+    /// Expire composites `ttl` after they were saved, so ones rendered before
+    /// an asset update are rendered again
+    /// Backends that don't record when a composite was saved (like the demo
+    /// storage) have their composites aged from when they're read
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.memory = memory_tier(self.capacity(), Some(ttl));
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    /// Keep composites on local disk too, evicting the least recently used
+    /// past the disk cache's size cap
+    pub fn with_disk(mut self, disk: DiskLayerCache) -> Self {
+        self.disk = Some(disk);
+        self
+    }
 
+    /// An empty cache like this one (same capacity, disk tier and TTL) over
+    /// another backend
+    pub(crate) fn on_backend(&self, backend: Arc<dyn StorageBackend>) -> Self {
         Self {
-            memory: Arc::new(Mutex::new(LruCache::new(capacity))),
+            memory: memory_tier(self.capacity(), self.ttl),
+            disk: self.disk.clone(),
             backend,
+            ttl: self.ttl,
         }
     }
 
+    /// How much the memory tier holds
+    pub fn capacity(&self) -> Capacity {
+        self.memory.capacity()
+    }
+
     /// Get a cached composite image
-    /// First checks memory cache, then backend cache
-    pub async fn get(&self, cache_key: &str) -> Result<Option<Bytes>> {
+    /// First checks memory cache, then the disk tier, then backend cache
+    /// Cache keys already include the format variant, so memory and disk
+    /// lookups ignore it
+    pub async fn get(&self, cache_key: &str, format: OutputFormat) -> Result<Option<Bytes>> {
         // Check memory cache first
-        {
-            let mut cache = self.memory.lock().await;
-            if let Some(data) = cache.get(cache_key) {
-                debug!("Memory cache hit: {}", cache_key);
-                return Ok(Some((**data).clone()));
+        if let Some(data) = self.memory.get(cache_key) {
+            debug!("Memory cache hit: {}", cache_key);
+            return Ok(Some(data));
+        }
+
+        if let Some(disk) = &self.disk {
+            if let Some((data, stored)) = disk.get_stored(cache_key).await {
+                if let Some(age) = self.fresh_age(Some(stored)) {
+                    self.memory
+                        .insert_aged(cache_key.to_string(), data.clone(), age);
+                    return Ok(Some(data));
+                }
+                debug!("Disk cache entry expired: {}", cache_key);
             }
         }
 
         // Check backend cache
-        if let Some(data) = self.backend.fetch_cached(cache_key).await? {
+        if let Some((data, saved)) = self.backend.fetch_cached_saved(cache_key, format).await? {
+            let Some(age) = self.fresh_age(saved) else {
+                debug!("Backend cache entry expired: {}", cache_key);
+                return Ok(None);
+            };
             debug!("Backend cache hit: {}", cache_key);
 
-            // Store in memory cache for future requests
-            let arc_data = Arc::new(data.clone());
-            let mut cache = self.memory.lock().await;
-            cache.put(cache_key.to_string(), arc_data);
+            // Store in the faster tiers for future requests, as old as they are
+            self.put_disk(cache_key, &data, saved).await;
+            self.memory
+                .insert_aged(cache_key.to_string(), data.clone(), age);
 
             return Ok(Some(data));
         }
@@ -55,48 +132,126 @@ impl ImageCache {
     }
 
     /// Save a composite image to cache
-    /// Saves to both memory and backend
-    pub async fn put(&self, cache_key: &str, data: Bytes) -> Result<()> {
+    /// Saves to memory, the disk tier and backend
+    pub async fn put(&self, cache_key: &str, data: Bytes, format: OutputFormat) -> Result<()> {
         // Save to backend
-        self.backend.save_to_cache(cache_key, &data).await?;
+        self.backend.save_to_cache(cache_key, &data, format).await?;
+        self.put_disk(cache_key, &data, None).await;
 
         // Save to memory cache
-        let arc_data = Arc::new(data);
-        let mut cache = self.memory.lock().await;
-        cache.put(cache_key.to_string(), arc_data);
+        self.memory.insert(cache_key.to_string(), data);
 
         info!("Cached composite: {}", cache_key);
 
         Ok(())
     }
 
+    /// Record the layers of a composite before caching it, in the backend
+    /// (`cache/licensed/{cache_key}.json`)
+    pub(crate) async fn put_license(&self, cache_key: &str, record: &LicenseRecord) -> Result<()> {
+        let json = serde_json::to_string(record)?;
+        self.backend
+            .save_cached_json(&license_key(cache_key), &json)
+            .await
+    }
+
+    /// The layers recorded with a cached composite by [`Self::put_license`]
+    pub(crate) async fn license(&self, cache_key: &str) -> Result<Option<LicenseRecord>> {
+        match self
+            .backend
+            .fetch_cached_json(&license_key(cache_key))
+            .await?
+        {
+            Some(json) => serde_json::from_str(&json)
+                .map(Some)
+                .with_context(|| format!("Invalid license record of {}", cache_key)),
+            None => Ok(None),
+        }
+    }
+
+    /// How old something saved at `saved` (if known) is, unless past the TTL
+    fn fresh_age(&self, saved: Option<SystemTime>) -> Option<Duration> {
+        let age = saved
+            .and_then(|saved| SystemTime::now().duration_since(saved).ok())
+            .unwrap_or(Duration::ZERO);
+        match self.ttl {
+            Some(ttl) if age >= ttl => None,
+            _ => Some(age),
+        }
+    }
+
+    /// A failed disk write only costs a later backend read
+    async fn put_disk(&self, cache_key: &str, data: &[u8], saved: Option<SystemTime>) {
+        if let Some(disk) = &self.disk {
+            if let Err(e) = disk.put_saved(cache_key, data, saved).await {
+                warn!("Failed to write disk composite cache: {:#}", e);
+            }
+        }
+    }
+
     /// Clear memory cache
     pub async fn clear_memory(&self) {
-        let mut cache = self.memory.lock().await;
-        cache.clear();
+        self.memory.clear();
         info!("Memory cache cleared");
     }
 
     /// Get cache statistics
     pub async fn stats(&self) -> CacheStats {
-        let cache = self.memory.lock().await;
+        let memory = self.memory.stats();
+        let disk = match &self.disk {
+            Some(disk) => disk.stats().await.ok(),
+            None => None,
+        };
+        let memory_capacity = match memory.capacity {
+            Capacity::Entries(max) => max,
+            _ => 0,
+        };
         CacheStats {
-            memory_entries: cache.len(),
-            memory_capacity: cache.cap().get(),
+            memory_entries: memory.entries,
+            memory_capacity,
+            memory,
+            disk,
+            ttl: self.ttl,
         }
     }
 }
 
+/// Key of the license record of a cached composite in the storage JSON cache
+fn license_key(cache_key: &str) -> String {
+    format!("licensed/{}", cache_key)
+}
+
+fn memory_tier(capacity: Capacity, ttl: Option<Duration>) -> TieredCache<String, Bytes> {
+    let memory = match capacity {
+        Capacity::Entries(max) => TieredCache::new().with_max_entries(max),
+        Capacity::Bytes(max) => TieredCache::new().with_max_bytes(max, Bytes::len),
+        Capacity::Unbounded => TieredCache::new(),
+    };
+    match ttl {
+        Some(ttl) => memory.with_ttl(ttl),
+        None => memory,
+    }
+}
+
 /// Cache statistics
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct CacheStats {
     pub memory_entries: usize,
+    /// Entries the memory tier holds at most, 0 if it's bounded by bytes
     pub memory_capacity: usize,
+    /// Hits, misses and evictions of the memory tier
+    pub memory: TieredStats,
+    /// Disk usage of the disk tier, if there is one
+    pub disk: Option<DiskCacheStats>,
+    /// How long composites are served after they were saved, if they expire
+    pub ttl: Option<Duration>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::S3Storage;
     use aws_sdk_s3::Client;
 
     #[tokio::test]
@@ -120,17 +275,103 @@ mod tests {
 
         // Put data in memory cache
         let data = Bytes::from("test data");
-        {
-            let mut mem_cache = cache.memory.lock().await;
-            mem_cache.put("test-key".to_string(), Arc::new(data.clone()));
-        }
+        cache.memory.insert("test-key".to_string(), data.clone());
 
         // Get from memory cache
-        let result = {
-            let mut mem_cache = cache.memory.lock().await;
-            mem_cache.get("test-key").map(|d| (**d).clone())
-        };
+        let result = cache.get("test-key", OutputFormat::Jpeg).await.unwrap();
 
         assert_eq!(result, Some(data));
+        assert_eq!(cache.stats().await.memory.hits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_bytes() {
+        let cache = ImageCache::new(Arc::new(crate::DemoStorage::new()), 100).with_max_bytes(10);
+        assert_eq!(cache.capacity(), Capacity::Bytes(10));
+
+        for key in ["a", "b", "c"] {
+            cache
+                .put(key, Bytes::from_static(b"4444"), OutputFormat::Png)
+                .await
+                .unwrap();
+        }
+        let stats = cache.stats().await;
+        assert_eq!((stats.memory_entries, stats.memory.bytes), (2, 8));
+        assert_eq!(stats.memory_capacity, 0);
+        assert_eq!(stats.memory.evictions, 1);
+    }
+
+    #[tokio::test]
+    async fn test_disk_tier() {
+        let dir = std::env::temp_dir().join(format!("birl-composite-disk-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = ImageCache::new(Arc::new(crate::DemoStorage::new()), 100)
+            .with_disk(DiskLayerCache::new(&dir, 1 << 20));
+
+        let data = Bytes::from("composite");
+        cache
+            .put("a1b2c3d4", data.clone(), OutputFormat::Png)
+            .await
+            .unwrap();
+        assert_eq!(cache.stats().await.disk.unwrap().bytes, 9);
+
+        // A restarted node with an empty backend still has it on disk
+        let restarted = ImageCache::new(Arc::new(crate::DemoStorage::new()), 100)
+            .with_disk(DiskLayerCache::new(&dir, 1 << 20));
+        assert_eq!(
+            restarted.get("a1b2c3d4", OutputFormat::Png).await.unwrap(),
+            Some(data)
+        );
+        assert_eq!(restarted.stats().await.memory_entries, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ttl() {
+        let dir = std::env::temp_dir().join(format!("birl-composite-ttl-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let backend = Arc::new(crate::LocalStorage::new(&dir));
+        let ttl = Duration::from_secs(3600);
+        let cache = ImageCache::new(backend.clone(), 100)
+            .with_disk(DiskLayerCache::new(dir.join("disk"), 1 << 20))
+            .with_ttl(ttl);
+
+        let data = Bytes::from("composite");
+        cache
+            .put("a1b2c3d4", data.clone(), OutputFormat::Png)
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.get("a1b2c3d4", OutputFormat::Png).await.unwrap(),
+            Some(data.clone())
+        );
+
+        // Saved two hours ago in the backend, so expired there, and not
+        // served by the tiers of a restarted node either
+        let saved = SystemTime::now() - 2 * ttl;
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("cache/a1b2c3d4.png"))
+            .unwrap()
+            .set_modified(saved)
+            .unwrap();
+        let restarted = ImageCache::new(backend.clone(), 100).with_ttl(ttl);
+        assert_eq!(
+            restarted.get("a1b2c3d4", OutputFormat::Png).await.unwrap(),
+            None
+        );
+
+        // Without a TTL it's still served, and the disk tier keeps its age
+        let forever =
+            ImageCache::new(backend, 100).with_disk(DiskLayerCache::new(dir.join("disk"), 1 << 20));
+        let _ = std::fs::remove_dir_all(dir.join("disk"));
+        assert_eq!(
+            forever.get("a1b2c3d4", OutputFormat::Png).await.unwrap(),
+            Some(data)
+        );
+        let disk = DiskLayerCache::new(dir.join("disk"), 1 << 20);
+        let (_, stored) = disk.get_stored("a1b2c3d4").await.unwrap();
+        assert!(stored < SystemTime::now() - ttl);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
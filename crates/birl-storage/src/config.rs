@@ -0,0 +1,1214 @@
+//! Named deployment profiles loaded from a TOML config file
+//!
+//! ```toml
+//! default_profile = "dev"
+//! conflicts = "last-wins"
+//! quality_curve = [[320, 92], [1600, 80], [4000, 68]]
+//!
+//! [limits]
+//! max_layers = 30
+//!
+//! [profiles.dev]
+//! local_path = "./resources"
+//!
+//! [profiles.prod]
+//! bucket = "birl-prod"
+//! prefix = "birl"
+//! cache_max_mb = 2048
+//! cache_ttl_secs = 86400
+//! layer_memory_mb = 512
+//! layer_memory_ttl_secs = 600
+//! remote_layer_hosts = ["cdn.partner.com"]
+//! remote_layer_headers = { "X-Api-Key" = "$PARTNER_API_KEY" }
+//! catalog = "storage"
+//! presets = "storage"
+//! presets_reload_secs = 300
+//! layer_key = "{category}/{view}/{sku}.{ext}"
+//!
+//! [profiles.prod.s3]
+//! accelerate = true
+//!
+//! [profiles.prod.auth]
+//! required = true
+//! keys = { storefront = "$STOREFRONT_API_KEY", ops = "$OPS_API_KEY" }
+//! internal = ["ops"]
+//! cdn_secret = "$CDN_ORIGIN_SECRET"
+//!
+//! [profiles.prod.capture]
+//! name = "prod-2026-10"
+//! every = 500
+//!
+//! [profiles.prod.rollup]
+//! flush_secs = 600
+//!
+//! [profiles.prod.prefetch]
+//! views = ["back", "left", "right"]
+//! concurrency = 2
+//!
+//! [profiles.prod.disk_cache]
+//! dir = "/var/cache/birl"
+//! max_mb = 4096
+//!
+//! [profiles.prod.write_limit]
+//! ops_per_sec = 2000
+//! burst = 200
+//!
+//! [profiles.prod.fetch_limit]
+//! per_request = 8
+//! global = 64
+//!
+//! [views.three-quarter]
+//! plate = "base-model-black-34"
+//! categories = ["pants", "tops", "hoodies", "jackets"]
+//!
+//! [views.left]
+//! categories = ["pants"]
+//!
+//! [categories.vests]
+//! z_order = 45
+//! patch_garment = true
+//!
+//! [formats]
+//! plate = "jpeg"
+//! vests = "webp"
+//!
+//! [sizes]
+//! suffixes = ["xxxl"]
+//! patterns = ["eu\\d{2}"]
+//! locales = ["uk", "jp"]
+//!
+//! [aliases]
+//! "hoodie-charcoal" = "hoodie-grey"
+//!
+//! [offsets.side]
+//! hoodies = { x = 3, y = -2 }
+//!
+//! [[orientations]]
+//! category = "patches-*"
+//! sku = "*-inverted"
+//! rotate = 180
+//!
+//! [flat_lay]
+//! width = 1600
+//! height = 1200
+//! slots = { hoodies = { x = 40, y = 40, width = 720, height = 720 } }
+//!
+//! [[rules]]
+//! category = "jackets"
+//! sku = "*puffer*"
+//! remap = "outer-jackets"
+//!
+//! [[constraints]]
+//! category = "gloves-top"
+//! requires = "*jackets"
+//!
+//! [[lint]]
+//! check = "max-file-size"
+//! max_bytes = 2_000_000
+//! ```
+//!
+//! Settings a profile leaves out fall back to the environment variables and
+//! built-in defaults used without a config file. Views, categories, formats,
+//! sizes, aliases, offsets, orientations, rules, constraints, limits, the quality curve and the conflict
+//! policy are shared by all profiles: views add to the built-in five (or list more categories for one of them), categories add to or replace
+//! the built-in ones, formats set the extension of a category's files, sizes add to the built-in ones unless `builtin = false`,
+//! aliases override the catalog's, and rules are checked before the built-in
+//! ones.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use birl_core::{
+    manifest, views, Catalog, Category, CategoryRegistry, ConflictPolicy, Constraint, FlatLay,
+    LayerOffsets, Limits, LintRule, ManifestFormat, Offset, OrientationRule, Orientations,
+    OutputFormat, QualityCurve, Rule, RuleSet, SizePatterns, SizeSpec, SkuAliases, Tables, View,
+    ViewSpec, Wildcards,
+};
+
+use crate::cache::{
+    DEFAULT_LAYER_MEMORY_BYTES, DEFAULT_LAYER_MEMORY_TTL, DEFAULT_MEMORY_CACHE_BYTES,
+};
+use crate::disk::DEFAULT_DISK_CACHE_BYTES;
+use crate::fetch_limit::{FetchLimit, DEFAULT_FETCHES_PER_REQUEST};
+use crate::http::{HeaderSigner, RemoteLayerFetcher, DEFAULT_MAX_REMOTE_BYTES};
+use crate::resolver::TemplateKeyResolver;
+use crate::s3::S3Endpoint;
+use crate::throttle::WriteThrottle;
+
+/// Config file read when no path is given, if it exists
+pub const DEFAULT_CONFIG_PATH: &str = "birl.toml";
+/// Environment variable overriding the config file path
+pub const CONFIG_ENV: &str = "BIRL_CONFIG";
+/// Environment variable selecting the profile
+pub const PROFILE_ENV: &str = "APP_PROFILE";
+
+/// `catalog` value loading the manifest from storage instead of a file
+pub const CATALOG_FROM_STORAGE: &str = "storage";
+/// `presets` value loading the presets from storage instead of a file
+pub const PRESETS_FROM_STORAGE: &str = "storage";
+
+/// Bucket used when neither the profile nor AWS_BUCKET_NAME names one
+const DEFAULT_BUCKET: &str = "birl-bucket";
+
+/// Contents of the config file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Profile used when none is selected
+    pub default_profile: Option<String>,
+    /// What to do with several layers in one category (default: stack them)
+    #[serde(default)]
+    pub conflicts: ConflictPolicy,
+    /// Layer and pixel limits of a render
+    #[serde(default)]
+    pub limits: Limits,
+    /// Encoder quality by output size, for renders that don't set one
+    #[serde(default)]
+    pub quality_curve: QualityCurve,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Views added to the built-in ones, by name
+    #[serde(default)]
+    pub views: BTreeMap<String, ViewSpec>,
+    /// Layer categories added to or replacing the built-in ones
+    #[serde(default)]
+    pub categories: BTreeMap<String, Category>,
+    /// Image format of each category's files, `plate` and `masks` included
+    /// (default: JPEG plates, PNG layers and masks)
+    #[serde(default)]
+    pub formats: BTreeMap<String, OutputFormat>,
+    /// Size suffixes stripped from SKUs
+    #[serde(default)]
+    pub sizes: SizeSpec,
+    /// Legacy SKUs ("sku" or "category/sku") and the SKUs they resolve to
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// Pixels each category's layers are shifted by in a view, by view and
+    /// category
+    #[serde(default)]
+    pub offsets: BTreeMap<String, BTreeMap<String, Offset>>,
+    /// Rotations and flips of matching layers, first match winning
+    #[serde(default)]
+    pub orientations: Vec<OrientationRule>,
+    /// Canvas and category slots of flat lay renders
+    pub flat_lay: Option<FlatLay>,
+    /// Normalization rules checked before the built-in ones
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// Layer dependencies and exclusions checked after normalization
+    #[serde(default)]
+    pub constraints: Vec<Constraint>,
+    /// Asset lint rules for the validate command
+    #[serde(default)]
+    pub lint: Vec<LintRule>,
+}
+
+/// Settings for one environment (dev, staging, prod, ...)
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// S3 bucket (falls back to AWS_BUCKET_NAME)
+    pub bucket: Option<String>,
+    /// Key prefix inside the bucket (default: "birl")
+    pub prefix: Option<String>,
+    /// Serve from the local filesystem instead of S3
+    pub local_path: Option<PathBuf>,
+    /// Memory composite cache budget in megabytes (default 512)
+    pub cache_max_mb: Option<usize>,
+    /// Bound the memory composite cache by entries instead, if `cache_max_mb`
+    /// isn't set
+    pub cache_capacity: Option<usize>,
+    /// Seconds a cached composite is served after it was rendered
+    /// (default: until evicted)
+    pub cache_ttl_secs: Option<u64>,
+    /// Memory budget of fetched plates and layers in megabytes
+    /// (default 256, 0 disables)
+    pub layer_memory_mb: Option<usize>,
+    /// Seconds a fetched plate or layer is kept in memory (default 300)
+    pub layer_memory_ttl_secs: Option<u64>,
+    /// Hosts allowed for remote layers (falls back to BIRL_REMOTE_LAYER_HOSTS)
+    pub remote_layer_hosts: Option<Vec<String>>,
+    pub remote_layer_max_bytes: Option<usize>,
+    /// Headers sent with every remote layer request
+    /// Values starting with `$` are read from that environment variable
+    #[serde(default)]
+    pub remote_layer_headers: BTreeMap<String, String>,
+    /// Catalog manifest: a JSON/TOML file, or "storage" for `cache/catalog.json`
+    pub catalog: Option<String>,
+    /// Presets file adding to the built-in and catalog presets: a JSON/TOML
+    /// file, or "storage" for `cache/presets.json`
+    pub presets: Option<String>,
+    /// Seconds between two reloads of the presets file by the server
+    /// (default: loaded once at startup)
+    pub presets_reload_secs: Option<u64>,
+    /// Layout of layer keys (default: "{view}/{category}/{sku}.{ext}")
+    pub layer_key: Option<String>,
+    /// S3 endpoint options: Transfer Acceleration and dual-stack
+    #[serde(default)]
+    pub s3: S3Endpoint,
+    #[serde(default)]
+    pub auth: AuthSettings,
+    /// Sampled capture of /create requests for `birl-cli replay`
+    pub capture: Option<CaptureSettings>,
+    /// Daily rollups of /create traffic for `birl-cli rollups`
+    pub rollup: Option<RollupSettings>,
+    /// Background renders of the other views after a front view
+    pub prefetch: Option<PrefetchSettings>,
+    /// Node-local disk tier of the composite cache
+    pub disk_cache: Option<DiskCacheSettings>,
+    /// Rate limit on storage writes, shared by every task of the process
+    pub write_limit: Option<WriteLimitSettings>,
+    /// Layer fetches in flight per request and across the process
+    pub fetch_limit: Option<FetchLimitSettings>,
+}
+
+/// Request authentication settings for the server
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthSettings {
+    /// Reject API requests without a valid key
+    #[serde(default)]
+    pub required: bool,
+    /// API keys by caller name, sent as `Authorization: Bearer <key>` or
+    /// `X-API-Key: <key>`
+    /// Values starting with `$` are read from that environment variable
+    #[serde(default)]
+    pub keys: BTreeMap<String, String>,
+    /// Callers trusted to set what's otherwise read from CDN headers, such as
+    /// the viewer's region
+    #[serde(default)]
+    pub internal: Vec<String>,
+    /// Secret the CDN sends in the `X-Origin-Secret` header; CDN headers such
+    /// as the viewer's country are only read from requests carrying it
+    /// Starting with `$`, it's read from that environment variable
+    pub cdn_secret: Option<String>,
+}
+
+impl AuthSettings {
+    /// Caller names and their keys, read from the environment where needed
+    pub fn resolved_keys(&self) -> Result<Vec<(String, String)>> {
+        if let Some(unknown) = self
+            .internal
+            .iter()
+            .find(|name| !self.keys.contains_key(*name))
+        {
+            bail!("Internal caller '{}' has no key", unknown);
+        }
+        let keys = self
+            .keys
+            .iter()
+            .map(|(name, value)| match value.strip_prefix('$') {
+                Some(var) => std::env::var(var)
+                    .map(|key| (name.clone(), key))
+                    .with_context(|| format!("Key of '{}' needs the {} variable", name, var)),
+                None => Ok((name.clone(), value.clone())),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if let Some((name, _)) = keys.iter().find(|(_, key)| key.is_empty()) {
+            bail!("Key of '{}' is empty", name);
+        }
+        if self.required && keys.is_empty() {
+            bail!("auth.required needs at least one key in auth.keys");
+        }
+        Ok(keys)
+    }
+
+    /// The CDN's origin secret, read from the environment where needed
+    pub fn resolved_cdn_secret(&self) -> Result<Option<String>> {
+        let Some(value) = &self.cdn_secret else {
+            return Ok(None);
+        };
+        let secret = match value.strip_prefix('$') {
+            Some(var) => std::env::var(var)
+                .with_context(|| format!("auth.cdn_secret needs the {} variable", var))?,
+            None => value.clone(),
+        };
+        if secret.is_empty() {
+            bail!("auth.cdn_secret is empty");
+        }
+        Ok(Some(secret))
+    }
+}
+
+/// Which requests the server captures, and where they go
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureSettings {
+    /// Replay file, `cache/replay/{name}.json`
+    pub name: String,
+    /// Capture one request in every `every`
+    #[serde(default = "default_capture_every")]
+    pub every: u64,
+    /// Stop capturing after this many requests
+    #[serde(default = "default_capture_max_records")]
+    pub max_records: usize,
+}
+
+fn default_capture_every() -> u64 {
+    100
+}
+
+fn default_capture_max_records() -> usize {
+    1000
+}
+
+/// How the server counts requests for daily rollups
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RollupSettings {
+    /// Seconds between two writes of this instance's counts
+    #[serde(default = "default_rollup_flush_secs")]
+    pub flush_secs: u64,
+    /// Outfits kept in a report, most requested first
+    #[serde(default = "default_rollup_top_outfits")]
+    pub top_outfits: usize,
+}
+
+fn default_rollup_flush_secs() -> u64 {
+    300
+}
+
+fn default_rollup_top_outfits() -> usize {
+    50
+}
+
+/// Which views the server prefetches after rendering an outfit's front view
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrefetchSettings {
+    /// Views rendered in the background
+    #[serde(default = "default_prefetch_views")]
+    pub views: Vec<View>,
+    /// Prefetches running at once; more are dropped, not queued
+    #[serde(default = "default_prefetch_concurrency")]
+    pub concurrency: usize,
+}
+
+/// Where composites are cached on local disk, and how much room they get
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiskCacheSettings {
+    pub dir: PathBuf,
+    /// Size cap; least recently used composites are evicted past it
+    #[serde(default = "default_disk_cache_max_mb")]
+    pub max_mb: u64,
+}
+
+impl DiskCacheSettings {
+    pub fn max_bytes(&self) -> u64 {
+        self.max_mb.saturating_mul(1024 * 1024)
+    }
+}
+
+/// Token bucket limiting storage writes (see [`WriteThrottle`])
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WriteLimitSettings {
+    /// Writes a second, above 0
+    #[serde(deserialize_with = "positive_rate")]
+    pub ops_per_sec: f64,
+    /// Writes let through at once before the rate applies
+    #[serde(default = "default_write_burst")]
+    pub burst: u32,
+}
+
+impl WriteLimitSettings {
+    pub fn throttle(&self) -> WriteThrottle {
+        WriteThrottle::new(self.ops_per_sec, self.burst)
+    }
+}
+
+fn default_write_burst() -> u32 {
+    100
+}
+
+fn positive_rate<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let rate = f64::deserialize(deserializer)?;
+    if rate > 0.0 && rate.is_finite() {
+        Ok(rate)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "ops_per_sec must be above 0, got {}",
+            rate
+        )))
+    }
+}
+
+/// Layer fetches of a profile in flight at once
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FetchLimitSettings {
+    /// Fetches of one request at once
+    #[serde(default = "default_fetches_per_request")]
+    pub per_request: usize,
+    /// Fetches from the bucket or remote hosts at once across every request
+    /// (default: unbounded)
+    pub global: Option<usize>,
+}
+
+impl FetchLimitSettings {
+    pub fn limit(&self) -> FetchLimit {
+        FetchLimit::new(self.per_request, self.global)
+    }
+}
+
+fn default_fetches_per_request() -> usize {
+    DEFAULT_FETCHES_PER_REQUEST
+}
+
+fn default_disk_cache_max_mb() -> u64 {
+    DEFAULT_DISK_CACHE_BYTES / (1024 * 1024)
+}
+
+fn default_prefetch_views() -> Vec<View> {
+    vec![View::Back, View::Left, View::Right]
+}
+
+fn default_prefetch_concurrency() -> usize {
+    2
+}
+
+impl Config {
+    /// Parse a config file
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        Self::parse_named(&format!("config file {}", path.display()), &contents)
+    }
+
+    /// Parse a config, `what` naming it in errors, which point at the
+    /// offending field and line
+    /// The rest of the file can refer to the configured views, which are
+    /// checked but not registered (see [`Self::tables`])
+    fn parse_named(what: &str, s: &str) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Views {
+            #[serde(default)]
+            views: BTreeMap<String, ViewSpec>,
+        }
+
+        let Views { views } = manifest::parse(what, s, ManifestFormat::Toml)?;
+        for (name, spec) in &views {
+            views::validate(name, spec)?;
+        }
+        let names: Vec<&str> = views.keys().map(String::as_str).collect();
+        Ok(views::with_declared(&names, || {
+            manifest::parse(what, s, ManifestFormat::Toml)
+        })?)
+    }
+
+    /// Load the config from `path`, or from BIRL_CONFIG / `birl.toml` when not given
+    /// An explicit path must exist; a missing default file gives an empty config
+    pub fn discover(path: Option<&Path>) -> Result<Self> {
+        if let Some(path) = path {
+            return Self::load(path);
+        }
+        if let Ok(path) = std::env::var(CONFIG_ENV) {
+            return Self::load(Path::new(&path));
+        }
+
+        let path = Path::new(DEFAULT_CONFIG_PATH);
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// Resolve a profile by name, falling back to `default_profile`
+    /// With neither, returns an empty profile (environment and built-in defaults)
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(Profile::default());
+        };
+
+        self.profiles.get(name).cloned().with_context(|| {
+            let available: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            format!(
+                "Unknown profile '{}'. Available profiles: {}",
+                name,
+                if available.is_empty() {
+                    "none".to_string()
+                } else {
+                    available.join(", ")
+                }
+            )
+        })
+    }
+
+    /// Built-in categories merged with the ones from the config file, in the
+    /// configured formats
+    pub fn category_registry(&self) -> Result<CategoryRegistry> {
+        let mut registry = CategoryRegistry::with_overrides(&self.categories);
+        for (category, format) in &self.formats {
+            registry
+                .set_format(category, *format)
+                .context("Invalid [formats]")?;
+        }
+        Ok(registry)
+    }
+
+    /// Built-in normalization rules preceded by the ones from the config file,
+    /// with the configured constraints
+    pub fn rule_set(&self) -> RuleSet {
+        RuleSet::with_overrides(&self.rules).with_constraints(&self.constraints)
+    }
+
+    /// Size suffixes and patterns stripped from SKUs
+    pub fn size_patterns(&self) -> Result<SizePatterns> {
+        Ok(SizePatterns::from_spec(&self.sizes)?)
+    }
+
+    /// Configured per-view layer offsets
+    pub fn layer_offsets(&self) -> Result<LayerOffsets> {
+        LayerOffsets::from_table(&self.offsets).context("Invalid [offsets]")
+    }
+
+    /// Configured flat lay layout, if there is one
+    pub fn flat_lay(&self) -> Result<Option<&FlatLay>> {
+        if let Some(flat_lay) = &self.flat_lay {
+            flat_lay.validate().context("Invalid [flat_lay]")?;
+        }
+        Ok(self.flat_lay.as_ref())
+    }
+
+    /// The catalog's SKU aliases overridden by the ones from the config file
+    pub fn sku_aliases(&self, catalog: Option<&Catalog>) -> SkuAliases {
+        let aliases = catalog.map(|catalog| SkuAliases::new(&catalog.aliases));
+        aliases.unwrap_or_default().with_overrides(&self.aliases)
+    }
+
+    /// Configured lint rules, or the defaults if there are none
+    pub fn lint_rules(&self) -> Vec<LintRule> {
+        if self.lint.is_empty() {
+            LintRule::defaults()
+        } else {
+            self.lint.clone()
+        }
+    }
+
+    /// The configured views, sizes, categories, rules, constraints, limits,
+    /// quality curve, layer offsets and orientations, flat lay and conflict
+    /// policy, over the built-in tables
+    /// Install them before any SKUs or views are parsed
+    pub fn tables(&self) -> Result<Tables> {
+        let mut tables = Tables::builtin()
+            .with_sizes(self.size_patterns()?)
+            .with_categories(self.category_registry()?)
+            .with_rules(self.rule_set())
+            .with_conflicts(self.conflicts)
+            .with_limits(self.limits)
+            .with_quality_curve(self.quality_curve.clone())
+            .with_offsets(self.layer_offsets()?)
+            .with_orientations(Orientations::new(&self.orientations));
+        if let Some(flat_lay) = self.flat_lay()? {
+            tables = tables.with_flat_lay(flat_lay.clone());
+        }
+        for (name, spec) in &self.views {
+            tables.register_view(name, spec.clone())?;
+        }
+        Ok(tables)
+    }
+
+    /// `tables` with the SKU aliases, and the catalog's `category/*` choices
+    /// Call once the catalog is loaded, before any layers are normalized
+    pub fn with_aliases(&self, tables: Tables, catalog: Option<&Catalog>) -> Tables {
+        let tables = tables.with_aliases(self.sku_aliases(catalog));
+        match catalog {
+            Some(catalog) => tables.with_wildcards(Wildcards::from_catalog(catalog)),
+            None => tables,
+        }
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Config::parse_named("config", s)
+    }
+}
+
+impl Profile {
+    /// Byte budget of the memory composite cache, unless only an entry count
+    /// is set
+    pub fn cache_max_bytes(&self) -> Option<usize> {
+        match (self.cache_max_mb, self.cache_capacity) {
+            (Some(max_mb), _) => Some(max_mb * 1024 * 1024),
+            (None, Some(_)) => None,
+            (None, None) => Some(DEFAULT_MEMORY_CACHE_BYTES),
+        }
+    }
+
+    pub fn cache_ttl(&self) -> Option<Duration> {
+        self.cache_ttl_secs.map(Duration::from_secs)
+    }
+
+    /// Byte budget of the memory layer cache, unless disabled
+    pub fn layer_memory_bytes(&self) -> Option<usize> {
+        match self.layer_memory_mb {
+            Some(0) => None,
+            Some(max_mb) => Some(max_mb * 1024 * 1024),
+            None => Some(DEFAULT_LAYER_MEMORY_BYTES),
+        }
+    }
+
+    /// How long the memory layer cache keeps a plate or layer
+    pub fn layer_memory_ttl(&self) -> Duration {
+        self.layer_memory_ttl_secs
+            .map_or(DEFAULT_LAYER_MEMORY_TTL, Duration::from_secs)
+    }
+
+    /// How often the server reloads the presets file, if it has one
+    pub fn presets_reload_interval(&self) -> Option<Duration> {
+        self.presets.as_ref()?;
+        self.presets_reload_secs
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// S3 bucket: the profile's, AWS_BUCKET_NAME, or the default
+    pub fn bucket(&self) -> String {
+        self.bucket
+            .clone()
+            .or_else(|| std::env::var("AWS_BUCKET_NAME").ok())
+            .unwrap_or_else(|| DEFAULT_BUCKET.to_string())
+    }
+
+    /// Resolver for the profile's `layer_key` template, if it sets one
+    pub fn key_resolver(&self) -> Result<Option<TemplateKeyResolver>> {
+        self.layer_key
+            .as_deref()
+            .map(TemplateKeyResolver::new)
+            .transpose()
+    }
+
+    /// Remote layer fetcher from the profile's hosts, or from the environment
+    pub fn remote_layer_fetcher(&self) -> Result<Option<RemoteLayerFetcher>> {
+        let fetcher = match &self.remote_layer_hosts {
+            Some(hosts) if !hosts.is_empty() => {
+                let max_bytes = self
+                    .remote_layer_max_bytes
+                    .unwrap_or(DEFAULT_MAX_REMOTE_BYTES);
+                RemoteLayerFetcher::new(hosts.clone(), max_bytes)?
+            }
+            Some(_) => return Ok(None),
+            None => match RemoteLayerFetcher::from_env()? {
+                Some(fetcher) => fetcher,
+                None => return Ok(None),
+            },
+        };
+
+        if self.remote_layer_headers.is_empty() {
+            return Ok(Some(fetcher));
+        }
+        Ok(Some(fetcher.with_signer(self.remote_layer_signer()?)))
+    }
+
+    /// Signer adding the profile's `remote_layer_headers`
+    pub fn remote_layer_signer(&self) -> Result<HeaderSigner> {
+        let headers = self
+            .remote_layer_headers
+            .iter()
+            .map(|(name, value)| match value.strip_prefix('$') {
+                Some(var) => std::env::var(var)
+                    .map(|value| (name, value))
+                    .with_context(|| format!("Header '{}' needs the {} variable", name, var)),
+                None => Ok((name, value.clone())),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        HeaderSigner::new(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    const CONFIG: &str = r#"
+        default_profile = "dev"
+
+        [profiles.dev]
+        local_path = "./resources"
+
+        [profiles.prod]
+        bucket = "birl-prod"
+        prefix = "renders"
+        cache_max_mb = 2048
+        cache_ttl_secs = 86400
+        layer_memory_mb = 0
+        presets = "storage"
+        presets_reload_secs = 300
+
+        [profiles.prod.s3]
+        accelerate = true
+
+        [profiles.prod.auth]
+        required = true
+        keys = { storefront = "sf-key", ops = "$BIRL_TEST_OPS_KEY" }
+        internal = ["ops"]
+        cdn_secret = "origin-secret"
+
+        [profiles.prod.capture]
+        name = "prod-canary"
+
+        [profiles.prod.rollup]
+        top_outfits = 20
+
+        [profiles.prod.prefetch]
+        concurrency = 4
+
+        [profiles.prod.disk_cache]
+        dir = "/var/cache/birl"
+
+        [profiles.prod.write_limit]
+        ops_per_sec = 2000
+
+        [profiles.prod.fetch_limit]
+        global = 64
+    "#;
+
+    #[test]
+    fn test_select_profile() {
+        let config: Config = CONFIG.parse().unwrap();
+
+        let prod = config.profile(Some("prod")).unwrap();
+        assert_eq!(prod.bucket(), "birl-prod");
+        assert_eq!(prod.prefix.as_deref(), Some("renders"));
+        assert_eq!(prod.cache_max_bytes(), Some(2048 << 20));
+        let by_entries = Profile {
+            cache_capacity: Some(5000),
+            ..Profile::default()
+        };
+        assert_eq!(by_entries.cache_max_bytes(), None);
+        assert_eq!(prod.cache_ttl(), Some(Duration::from_secs(86400)));
+        assert_eq!(prod.layer_memory_bytes(), None);
+        assert_eq!(prod.presets.as_deref(), Some(PRESETS_FROM_STORAGE));
+        assert_eq!(
+            prod.presets_reload_interval(),
+            Some(Duration::from_secs(300))
+        );
+        assert!(prod.s3.accelerate && !prod.s3.dual_stack);
+        assert!(prod.auth.required);
+        assert!(prod.auth.resolved_keys().is_err());
+        std::env::set_var("BIRL_TEST_OPS_KEY", "ops-key");
+        assert_eq!(
+            prod.auth.resolved_keys().unwrap(),
+            [
+                ("ops".to_string(), "ops-key".to_string()),
+                ("storefront".to_string(), "sf-key".to_string())
+            ]
+        );
+        let no_keys = AuthSettings {
+            required: true,
+            ..AuthSettings::default()
+        };
+        assert!(no_keys.resolved_keys().is_err());
+        assert_eq!(
+            prod.auth.resolved_cdn_secret().unwrap().as_deref(),
+            Some("origin-secret")
+        );
+        assert_eq!(no_keys.resolved_cdn_secret().unwrap(), None);
+        let capture = prod.capture.unwrap();
+        assert_eq!(capture.name, "prod-canary");
+        assert_eq!((capture.every, capture.max_records), (100, 1000));
+        let rollup = prod.rollup.unwrap();
+        assert_eq!((rollup.flush_secs, rollup.top_outfits), (300, 20));
+        let prefetch = prod.prefetch.unwrap();
+        assert_eq!(prefetch.views, [View::Back, View::Left, View::Right]);
+        assert_eq!(prefetch.concurrency, 4);
+        let disk_cache = prod.disk_cache.unwrap();
+        assert_eq!(disk_cache.dir, PathBuf::from("/var/cache/birl"));
+        assert_eq!(disk_cache.max_bytes(), DEFAULT_DISK_CACHE_BYTES);
+        let write_limit = prod.write_limit.unwrap();
+        assert_eq!((write_limit.ops_per_sec, write_limit.burst), (2000.0, 100));
+        for rate in ["0", "-5.0"] {
+            let invalid = format!("[profiles.prod.write_limit]\nops_per_sec = {}", rate);
+            let err = format!("{:#}", invalid.parse::<Config>().unwrap_err());
+            assert!(err.contains("ops_per_sec must be above 0"), "{}", err);
+        }
+        let fetch_limit = prod.fetch_limit.unwrap().limit().stats();
+        assert_eq!(
+            (fetch_limit.per_request, fetch_limit.global),
+            (DEFAULT_FETCHES_PER_REQUEST, Some(64))
+        );
+
+        let dev = config.profile(None).unwrap();
+        assert_eq!(dev.local_path, Some(PathBuf::from("./resources")));
+        assert!(!dev.auth.required);
+        assert!(dev.capture.is_none());
+        assert!(dev.rollup.is_none());
+        assert!(dev.prefetch.is_none());
+        assert!(dev.disk_cache.is_none());
+        assert!(dev.write_limit.is_none());
+        assert_eq!(dev.presets_reload_interval(), None);
+        assert_eq!(dev.cache_max_bytes(), Some(DEFAULT_MEMORY_CACHE_BYTES));
+        assert_eq!(dev.layer_memory_bytes(), Some(DEFAULT_LAYER_MEMORY_BYTES));
+        assert_eq!(dev.layer_memory_ttl(), DEFAULT_LAYER_MEMORY_TTL);
+    }
+
+    #[test]
+    fn test_error_points_at_field() {
+        let invalid = "[profiles.prod]\nbucket = \"birl-prod\"\ncache_ttl_secs = \"1d\"\n";
+        let err = format!("{:#}", invalid.parse::<Config>().unwrap_err());
+        assert!(err.starts_with("Invalid config: profiles.prod.cache_ttl_secs: "));
+        assert!(err.contains("\n  3 | cache_ttl_secs = \"1d\""));
+    }
+
+    #[test]
+    fn test_unknown_profile() {
+        let config: Config = CONFIG.parse().unwrap();
+        let err = config.profile(Some("staging")).unwrap_err().to_string();
+        assert!(err.contains("dev, prod"));
+
+        // No config file and no profile selected: defaults
+        assert_eq!(Config::default().profile(None).unwrap(), Profile::default());
+        assert!(Config::default().profile(Some("prod")).is_err());
+    }
+
+    #[test]
+    fn test_categories() {
+        let config: Config = "[categories.vests]\nz_order = 45\nviews = [\"front\"]"
+            .parse()
+            .unwrap();
+        let registry = config.category_registry().unwrap();
+        assert_eq!(registry.z_order("vests"), Some(45));
+        assert!(!registry.allows("vests", birl_core::View::Back));
+        assert_eq!(registry.z_order("hoodies"), Some(30));
+    }
+
+    #[test]
+    fn test_formats() {
+        let config: Config = r#"
+            [categories.vests]
+            z_order = 45
+
+            [formats]
+            plate = "png"
+            vests = "webp"
+        "#
+        .parse()
+        .unwrap();
+        let registry = config.category_registry().unwrap();
+        assert_eq!(registry.extension("plate"), "png");
+        assert_eq!(registry.extension("vests"), "webp");
+        assert_eq!(registry.extension("hoodies"), "png");
+
+        let config: Config = "[formats]\ncapes = 'webp'".parse().unwrap();
+        assert!(config.category_registry().is_err());
+        assert!("[formats]\nplate = 'gif'".parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn test_views() {
+        let config: Config = r#"
+            [views.top-down]
+            plate = "flat-lay"
+            categories = ["hats"]
+
+            [categories.vests]
+            z_order = 45
+            views = ["front", "top-down"]
+        "#
+        .parse()
+        .unwrap();
+        // Parsing leaves the registry alone
+        assert!("top-down".parse::<birl_core::View>().is_err());
+        let tables = Arc::new(config.tables().unwrap());
+        tables.scope(|| {
+            let view: birl_core::View = "top-down".parse().unwrap();
+            assert_eq!(view.plate_value(), "flat-lay");
+
+            let registry = config.category_registry().unwrap();
+            assert!(registry.allows("hats", view));
+            assert!(registry.allows("vests", view));
+            assert!(!registry.allows("pants", view));
+        });
+        assert!("top-down".parse::<birl_core::View>().is_err());
+
+        assert!("[views.front]\nplate = \"x\"".parse::<Config>().is_err());
+        assert!("[views.bird-eye]\ncategories = []"
+            .parse::<Config>()
+            .is_err());
+
+        // Built-in views can render more categories
+        let config: Config = "[views.right]\ncategories = ['kilts']".parse().unwrap();
+        let tables = Arc::new(config.tables().unwrap());
+        let registry = config.category_registry().unwrap();
+        assert!(tables.scope(|| registry.allows("kilts", birl_core::View::Right)));
+        assert!(!registry.allows("kilts", birl_core::View::Right));
+        assert_eq!(birl_core::View::Right.plate_value(), "patch-plate");
+    }
+
+    #[test]
+    fn test_rules() {
+        let config: Config = r#"
+            [[rules]]
+            category = "jackets"
+            sku = "*puffer*"
+            remap = "outer-jackets"
+        "#
+        .parse()
+        .unwrap();
+        let rules = config.rule_set();
+        let puffer = birl_core::LayerParam::new("jackets", "puffer-black");
+        assert_eq!(
+            rules.apply(&puffer, birl_core::View::Front, &[]).as_deref(),
+            Some("outer-jackets")
+        );
+        assert_eq!(rules.rules().len(), RuleSet::builtin().rules().len() + 1);
+        assert!(rules.constraints().is_empty());
+        let invalid =
+            "[[rules]]\ncategory = \"gloves\"\nsku_regex = \"(ski\"\nremap = \"gloves-top\"";
+        assert!(invalid.parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn test_constraints() {
+        let config: Config = r#"
+            [[constraints]]
+            category = "gloves-top"
+            requires = "*jackets"
+            action = "drop"
+
+            [[constraints]]
+            category = "outer-jackets"
+            excludes = "hoodies/*-hood-*"
+        "#
+        .parse()
+        .unwrap();
+        let rules = config.rule_set();
+        assert_eq!(
+            rules.constraints()[0],
+            Constraint::requires("gloves-top", "*jackets")
+                .with_action(birl_core::ConstraintAction::Drop)
+        );
+        assert_eq!(
+            rules.constraints()[1],
+            Constraint::excludes("outer-jackets", "hoodies/*-hood-*")
+        );
+
+        let unknown = "[[constraints]]\ncategory = \"gloves\"\nneeds = \"jackets\"";
+        assert!(unknown.parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn test_sizes() {
+        let config: Config = "[sizes]\nsuffixes = [\"xxxl\"]\npatterns = [\"eu\\\\d{2}\"]"
+            .parse()
+            .unwrap();
+        let sizes = config.size_patterns().unwrap();
+        assert_eq!(sizes.split("boot-brown-eu42").base.as_str(), "boot-brown");
+        assert_eq!(
+            sizes.split("hoodie-black-xxxl").size.as_deref(),
+            Some("xxxl")
+        );
+
+        let config: Config = "[sizes]\nlocales = [\"uk\"]".parse().unwrap();
+        let sizes = config.size_patterns().unwrap();
+        assert_eq!(sizes.split("dress-red-uk10").base.as_str(), "dress-red");
+
+        let config: Config = "[sizes]\npatterns = [\"[\"]".parse().unwrap();
+        assert!(config.size_patterns().is_err());
+    }
+
+    #[test]
+    fn test_aliases() {
+        let config: Config = "[aliases]\n\"hoodie-charcoal\" = \"hoodie-grey\""
+            .parse()
+            .unwrap();
+        let catalog = Catalog::from_json(
+            r#"{"aliases": {"hoodie-charcoal": "hoodie-black", "cap-classic": "cap-black"}}"#,
+        )
+        .unwrap();
+
+        let aliases = config.sku_aliases(Some(&catalog));
+        assert_eq!(aliases.len(), 2);
+        let sku = birl_core::Sku::new("hoodie-charcoal");
+        assert_eq!(
+            aliases.resolve("hoodies", &sku).unwrap().as_str(),
+            "hoodie-grey"
+        );
+        assert_eq!(config.sku_aliases(None).len(), 1);
+    }
+
+    #[test]
+    fn test_offsets() {
+        let config: Config = "[offsets.side]\nhoodies = { x = 3, y = -2 }"
+            .parse()
+            .unwrap();
+        let offsets = config.layer_offsets().unwrap();
+        assert_eq!(
+            offsets.get(birl_core::View::Side, "hoodies"),
+            Some(Offset::new(3, -2))
+        );
+
+        let config: Config = "[offsets.sideways]\nhoodies = { x = 3 }".parse().unwrap();
+        assert!(config.layer_offsets().is_err());
+    }
+
+    #[test]
+    fn test_orientations() {
+        let config: Config = r#"
+            [[orientations]]
+            category = "hoodies"
+            views = ["right"]
+            flip = "horizontal"
+        "#
+        .parse()
+        .unwrap();
+        let orientations = Orientations::new(&config.orientations);
+        let hoodie = birl_core::LayerParam::new("hoodies", "hoodie-black");
+        assert_eq!(
+            orientations.get(&hoodie, birl_core::View::Right),
+            Some(birl_core::Orientation::new(
+                birl_core::Rotation::None,
+                Some(birl_core::Flip::Horizontal)
+            ))
+        );
+        assert_eq!(orientations.get(&hoodie, birl_core::View::Left), None);
+
+        let invalid = "[[orientations]]\ncategory = \"hoodies\"\nrotate = 45";
+        assert!(invalid.parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn test_flat_lay() {
+        let config: Config = r##"
+            [flat_lay]
+            width = 800
+            height = 400
+            background = "#f4f1ec"
+
+            [flat_lay.slots]
+            hoodies = { x = 0, y = 0, width = 400, height = 400 }
+            pants = { x = 400, y = 0, width = 400, height = 400 }
+        "##
+        .parse()
+        .unwrap();
+        let flat_lay = config.flat_lay().unwrap().unwrap();
+        assert_eq!(flat_lay.view, View::Front);
+        assert_eq!(
+            flat_lay.slot("pants"),
+            Some(&birl_core::Slot::new(400, 0, 400, 400))
+        );
+        assert!(Config::default().flat_lay().unwrap().is_none());
+
+        // Slots must be on the canvas
+        let config: Config = r#"
+            [flat_lay]
+            width = 100
+            height = 100
+            slots = { hats = { x = 50, y = 0, width = 100, height = 10 } }
+        "#
+        .parse()
+        .unwrap();
+        assert!(config.flat_lay().is_err());
+    }
+
+    #[test]
+    fn test_lint_rules() {
+        assert_eq!(Config::default().lint_rules(), LintRule::defaults());
+
+        let config: Config = r#"
+            [[lint]]
+            check = "dimensions"
+            view = "front"
+            width = 2000
+            height = 2500
+            severity = "warning"
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            config.lint_rules(),
+            vec![LintRule::new(birl_core::Check::Dimensions {
+                view: Some(birl_core::View::Front),
+                width: 2000,
+                height: 2500,
+            })
+            .with_severity(birl_core::Severity::Warning)]
+        );
+    }
+
+    #[test]
+    fn test_layer_key() {
+        let config: Config = "[profiles.prod]\nlayer_key = \"{category}/{view}/{sku}.{ext}\""
+            .parse()
+            .unwrap();
+        let profile = config.profile(Some("prod")).unwrap();
+        assert!(profile.key_resolver().unwrap().is_some());
+        assert!(Profile::default().key_resolver().unwrap().is_none());
+
+        let profile = Profile {
+            layer_key: Some("{view}/{category}.png".to_string()),
+            ..Profile::default()
+        };
+        assert!(profile.key_resolver().is_err());
+    }
+
+    #[test]
+    fn test_rejects_unknown_settings() {
+        assert!("[profiles.prod]\nbukcet = \"typo\""
+            .parse::<Config>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_empty_remote_hosts_disable_remote_layers() {
+        let profile = Profile {
+            remote_layer_hosts: Some(vec![]),
+            ..Profile::default()
+        };
+        assert!(profile.remote_layer_fetcher().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_remote_layer_headers() {
+        let config: Config = r#"
+            [profiles.prod]
+            remote_layer_hosts = ["cdn.partner.com"]
+            remote_layer_headers = { "X-Api-Key" = "$BIRL_TEST_UNSET_PARTNER_KEY" }
+        "#
+        .parse()
+        .unwrap();
+        let profile = config.profile(Some("prod")).unwrap();
+        let error = profile.remote_layer_fetcher().err().unwrap();
+        assert!(error.to_string().contains("BIRL_TEST_UNSET_PARTNER_KEY"));
+
+        let profile = Profile {
+            remote_layer_hosts: Some(vec!["cdn.partner.com".to_string()]),
+            remote_layer_headers: BTreeMap::from([("X-Api-Key".to_string(), "key".to_string())]),
+            ..Profile::default()
+        };
+        assert!(profile.remote_layer_fetcher().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let config: Config = "conflicts = \"last-wins\"".parse().unwrap();
+        assert_eq!(config.conflicts, ConflictPolicy::LastWins);
+        assert_eq!(Config::default().conflicts, ConflictPolicy::Stack);
+        assert!("conflicts = \"newest\"".parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let config: Config = "[limits]\nmax_layers = 30".parse().unwrap();
+        assert_eq!(config.limits, Limits::default().with_max_layers(30));
+        assert_eq!(Config::default().limits, Limits::default());
+        assert!("[limits]\nmax_width = 10".parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn test_quality_curve() {
+        let config: Config = "quality_curve = [[1600, 80], [320, 92]]".parse().unwrap();
+        assert_eq!(config.quality_curve.points(), [(320, 92), (1600, 80)]);
+        assert!(Config::default().quality_curve.is_empty());
+        assert!("quality_curve = [[320, 0]]".parse::<Config>().is_err());
+    }
+}
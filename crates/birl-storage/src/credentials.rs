@@ -0,0 +1,234 @@
+//! S3 credentials refreshed ahead of their expiry
+//!
+//! Temporary credentials (STS, SSO, instance roles) expire, and a batch that
+//! outlives them would start failing half-way. [`RefreshingCredentials`]
+//! caches the credentials of a provider and fetches new ones once they're
+//! within [`REFRESH_AHEAD`] of expiring, while the old ones still work. S3
+//! requests rejected as `ExpiredToken` anyway (e.g. after a clock jump)
+//! refresh and retry once. Each bucket's storage has its own credentials,
+//! and their expiry shows in the server's `/ready` endpoint.
+
+use aws_config::SdkConfig;
+use aws_credential_types::provider::{self, future, ProvideCredentials, SharedCredentialsProvider};
+use aws_credential_types::Credentials;
+use aws_sdk_s3::config::IdentityCache;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::Client;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use crate::s3::S3Endpoint;
+
+/// How long before their expiry credentials are refreshed
+pub const REFRESH_AHEAD: Duration = Duration::from_secs(300);
+
+/// Error codes of requests signed with expired credentials
+const EXPIRED_CODES: [&str; 3] = [
+    "ExpiredToken",
+    "ExpiredTokenException",
+    "TokenRefreshRequired",
+];
+
+/// Credentials of a provider, cached and refreshed before they expire
+#[derive(Debug)]
+pub struct RefreshingCredentials {
+    inner: SharedCredentialsProvider,
+    refresh_ahead: Duration,
+    current: tokio::sync::Mutex<Option<Credentials>>,
+    expiry: Mutex<Option<SystemTime>>,
+}
+
+impl RefreshingCredentials {
+    pub fn new(inner: impl ProvideCredentials + 'static) -> Self {
+        Self {
+            inner: SharedCredentialsProvider::new(inner),
+            refresh_ahead: REFRESH_AHEAD,
+            current: tokio::sync::Mutex::default(),
+            expiry: Mutex::default(),
+        }
+    }
+
+    pub fn with_refresh_ahead(mut self, refresh_ahead: Duration) -> Self {
+        self.refresh_ahead = refresh_ahead;
+        self
+    }
+
+    /// When the current credentials expire; `None` before the first request
+    /// and for credentials that don't expire
+    pub fn expiry(&self) -> Option<SystemTime> {
+        *self
+            .expiry
+            .lock()
+            .expect("credentials expiry lock poisoned")
+    }
+
+    /// The cached credentials, refreshed first when they're about to expire
+    /// If the refresh fails, credentials that haven't expired yet are still used
+    pub async fn credentials(&self) -> provider::Result {
+        let mut current = self.current.lock().await;
+        let expiry = match &*current {
+            Some(credentials) => match credentials.expiry() {
+                Some(expiry) if expiry <= SystemTime::now() + self.refresh_ahead => Some(expiry),
+                _ => return Ok(credentials.clone()),
+            },
+            None => None,
+        };
+
+        match self.inner.provide_credentials().await {
+            Ok(credentials) => {
+                if let Some(expiry) = credentials.expiry() {
+                    let left = expiry.duration_since(SystemTime::now()).unwrap_or_default();
+                    info!("Refreshed S3 credentials, valid for {}s", left.as_secs());
+                }
+                *self
+                    .expiry
+                    .lock()
+                    .expect("credentials expiry lock poisoned") = credentials.expiry();
+                *current = Some(credentials.clone());
+                Ok(credentials)
+            }
+            Err(e) => match (expiry, &*current) {
+                (Some(expiry), Some(credentials)) if expiry > SystemTime::now() => {
+                    warn!(
+                        "Failed to refresh S3 credentials, using the current ones: {}",
+                        e
+                    );
+                    Ok(credentials.clone())
+                }
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Drop the cached credentials, so the next request fetches new ones
+    pub async fn invalidate(&self) {
+        *self.current.lock().await = None;
+    }
+}
+
+impl ProvideCredentials for RefreshingCredentials {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(self.credentials())
+    }
+}
+
+/// S3 client for an SDK config and endpoint options, with its credentials
+/// refreshed ahead of expiry
+/// Returns the credentials too, unless the config has no provider
+pub fn s3_client(
+    config: &SdkConfig,
+    endpoint: S3Endpoint,
+) -> (Client, Option<Arc<RefreshingCredentials>>) {
+    let s3_config = endpoint.apply(aws_sdk_s3::config::Builder::from(config));
+    let Some(provider) = config.credentials_provider() else {
+        return (Client::from_conf(s3_config.build()), None);
+    };
+    let credentials = Arc::new(RefreshingCredentials::new(provider));
+
+    // The client's own cache would hold credentials until they expire
+    let s3_config = s3_config
+        .credentials_provider(SharedProvider(credentials.clone()))
+        .identity_cache(IdentityCache::no_cache())
+        .build();
+    (Client::from_conf(s3_config), Some(credentials))
+}
+
+/// Whether S3 rejected a request because its credentials expired
+pub fn is_expired(error: &impl ProvideErrorMetadata) -> bool {
+    error
+        .code()
+        .is_some_and(|code| EXPIRED_CODES.contains(&code))
+}
+
+/// [`RefreshingCredentials`] shared between a client and its storage
+#[derive(Debug)]
+struct SharedProvider(Arc<RefreshingCredentials>);
+
+impl ProvideCredentials for SharedProvider {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        self.0.provide_credentials()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_credential_types::credential_fn::provide_credentials_fn;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn expiring_in(secs: u64, calls: Arc<AtomicUsize>) -> RefreshingCredentials {
+        RefreshingCredentials::new(provide_credentials_fn(move || {
+            let calls = calls.clone();
+            async move {
+                calls.fetch_add(1, Ordering::Relaxed);
+                let expiry = SystemTime::now() + Duration::from_secs(secs);
+                Ok(Credentials::new(
+                    "key",
+                    "secret",
+                    None,
+                    Some(expiry),
+                    "test",
+                ))
+            }
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_refreshes_ahead_of_expiry() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let credentials = expiring_in(3600, calls.clone());
+        assert_eq!(credentials.expiry(), None);
+        credentials.credentials().await.unwrap();
+        credentials.credentials().await.unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert!(credentials.expiry().unwrap() > SystemTime::now() + REFRESH_AHEAD);
+
+        credentials.invalidate().await;
+        credentials.credentials().await.unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+        // Credentials inside the refresh window are replaced on every use
+        let calls = Arc::new(AtomicUsize::new(0));
+        let credentials = expiring_in(60, calls.clone());
+        credentials.credentials().await.unwrap();
+        credentials.credentials().await.unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_keeps_valid_credentials_when_refresh_fails() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let credentials = RefreshingCredentials::new(provide_credentials_fn(move || {
+            let first = counted.fetch_add(1, Ordering::Relaxed) == 0;
+            async move {
+                if !first {
+                    return Err(provider::error::CredentialsError::not_loaded_no_source());
+                }
+                let expiry = SystemTime::now() + Duration::from_secs(60);
+                Ok(Credentials::new(
+                    "key",
+                    "secret",
+                    None,
+                    Some(expiry),
+                    "test",
+                ))
+            }
+        }));
+
+        let first = credentials.credentials().await.unwrap();
+        let second = credentials.credentials().await.unwrap();
+        assert_eq!(first.access_key_id(), second.access_key_id());
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+        credentials.invalidate().await;
+        assert!(credentials.credentials().await.is_err());
+    }
+}
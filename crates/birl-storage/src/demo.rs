@@ -0,0 +1,136 @@
+use anyhow::Result;
+use birl_core::demo::{synthetic_layer, synthetic_mask, synthetic_plate};
+use birl_core::{OutputFormat, View};
+use bytes::Bytes;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::debug;
+
+use crate::MASKS_CATEGORY;
+
+/// Storage that generates synthetic assets on the fly and caches in memory
+/// Needs no S3 or local resources, for demos and first runs
+#[derive(Default)]
+pub struct DemoStorage {
+    cache: Mutex<HashMap<String, Bytes>>,
+}
+
+impl DemoStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Generate a plate or layer for any category and SKU
+    pub async fn fetch_layer(
+        &self,
+        category: &str,
+        sku: &str,
+        view: View,
+        _extension: &str,
+    ) -> Result<Option<Bytes>> {
+        let data = if category == "plate" {
+            synthetic_plate(view)?
+        } else if category == MASKS_CATEGORY {
+            synthetic_mask(sku)?
+        } else {
+            synthetic_layer(category, sku)?
+        };
+
+        debug!("Generated demo asset: {}/{}/{}", view, category, sku);
+        Ok(Some(data))
+    }
+
+    pub async fn fetch_cached(
+        &self,
+        cache_key: &str,
+        format: OutputFormat,
+    ) -> Result<Option<Bytes>> {
+        let cache = self.cache.lock().unwrap();
+        Ok(cache.get(&cache_path(cache_key, format)).cloned())
+    }
+
+    pub async fn save_to_cache(
+        &self,
+        cache_key: &str,
+        data: &[u8],
+        format: OutputFormat,
+    ) -> Result<()> {
+        let mut cache = self.cache.lock().unwrap();
+        cache.insert(cache_path(cache_key, format), Bytes::copy_from_slice(data));
+        Ok(())
+    }
+
+    pub async fn fetch_cached_json(&self, key: &str) -> Result<Option<String>> {
+        let cache = self.cache.lock().unwrap();
+        let json = cache.get(&format!("{}.json", key));
+        Ok(json.map(|data| String::from_utf8_lossy(data).into_owned()))
+    }
+
+    pub async fn save_cached_json(&self, key: &str, json: &str) -> Result<()> {
+        let mut cache = self.cache.lock().unwrap();
+        cache.insert(
+            format!("{}.json", key),
+            Bytes::copy_from_slice(json.as_bytes()),
+        );
+        Ok(())
+    }
+
+    pub async fn claim(&self, key: &str, holder: &str) -> Result<bool> {
+        let mut cache = self.cache.lock().unwrap();
+        match cache.entry(format!("locks/{}", key)) {
+            Entry::Occupied(_) => Ok(false),
+            Entry::Vacant(entry) => {
+                entry.insert(Bytes::copy_from_slice(holder.as_bytes()));
+                Ok(true)
+            }
+        }
+    }
+}
+
+fn cache_path(cache_key: &str, format: OutputFormat) -> String {
+    format!("{}.{}", cache_key, format.extension())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_demo_storage_generates_assets() {
+        let storage = DemoStorage::new();
+
+        let plate = storage
+            .fetch_layer("plate", "base-model-black", View::Front, "jpg")
+            .await
+            .unwrap();
+        assert!(plate.is_some());
+
+        let layer = storage
+            .fetch_layer("hoodies", "hoodie-black", View::Front, "png")
+            .await
+            .unwrap();
+        assert!(layer.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_demo_storage_cache() {
+        let storage = DemoStorage::new();
+        storage
+            .save_to_cache("abc", b"data", OutputFormat::Jpeg)
+            .await
+            .unwrap();
+
+        let hit = storage
+            .fetch_cached("abc", OutputFormat::Jpeg)
+            .await
+            .unwrap();
+        assert_eq!(hit, Some(Bytes::from_static(b"data")));
+
+        let miss = storage
+            .fetch_cached("abc", OutputFormat::Png)
+            .await
+            .unwrap();
+        assert!(miss.is_none());
+    }
+}
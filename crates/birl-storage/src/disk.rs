@@ -0,0 +1,383 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info, warn};
+use xxhash_rust::xxh64::xxh64;
+
+/// Default size cap of the disk layer cache (512 MB)
+pub const DEFAULT_DISK_CACHE_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Default age past which disk layer cache entries are fetched again (1 day)
+pub const DEFAULT_DISK_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Persistent, content-addressed cache of fetched plate and layer bytes, or
+/// of composites (see [`crate::ImageCache::with_disk`])
+///
+/// Layout:
+/// - `blobs/{content-hash}` the bytes, shared by every key with the same content
+/// - `refs/{key-hash}` the content hash a key resolved to
+///
+/// Keys are hashed with the cache's namespace (see [`Self::with_namespace`]),
+/// so one directory can serve several backends.
+///
+/// When the blobs exceed the size cap, the least recently used are removed.
+/// Refs left pointing at removed blobs count as misses. A ref's modification
+/// time is when its key was stored (see [`DiskLayerCache::get_stored`]).
+#[derive(Clone)]
+pub struct DiskLayerCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    namespace: String,
+    ttl: Option<Duration>,
+}
+
+/// Disk usage of the layer cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DiskCacheStats {
+    pub blobs: usize,
+    pub bytes: u64,
+    pub max_bytes: u64,
+}
+
+impl DiskLayerCache {
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        Self {
+            dir: dir.into(),
+            max_bytes,
+            namespace: String::new(),
+            ttl: None,
+        }
+    }
+
+    /// Keep keys apart from those stored under another namespace, e.g. the
+    /// same layer path in another bucket or prefix
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+
+    /// Treat entries stored more than `ttl` ago as misses in [`Self::get`],
+    /// so re-uploaded sources are fetched again
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Cached bytes for a key, marking them as recently used
+    /// Entries older than the TTL, if set, are misses
+    pub async fn get(&self, key: &str) -> Option<Bytes> {
+        let Some(ttl) = self.ttl else {
+            let hash = tokio::fs::read_to_string(self.ref_path(key)).await.ok()?;
+            return self.read_blob(key, &hash).await;
+        };
+        let (data, stored) = self.get_stored(key).await?;
+        let age = SystemTime::now().duration_since(stored).unwrap_or_default();
+        if age > ttl {
+            debug!("Disk cache entry expired: {} ({:?} old)", key, age);
+            return None;
+        }
+        Some(data)
+    }
+
+    /// [`Self::get`] with when the key was stored, e.g. to expire entries
+    /// past a TTL
+    pub async fn get_stored(&self, key: &str) -> Option<(Bytes, SystemTime)> {
+        let ref_path = self.ref_path(key);
+        let stored = tokio::fs::metadata(&ref_path).await.ok()?.modified().ok()?;
+        let hash = tokio::fs::read_to_string(&ref_path).await.ok()?;
+        Some((self.read_blob(key, &hash).await?, stored))
+    }
+
+    async fn read_blob(&self, key: &str, hash: &str) -> Option<Bytes> {
+        let blob = self.dir.join("blobs").join(hash.trim());
+
+        match tokio::fs::read(&blob).await {
+            Ok(data) => {
+                touch(&blob).await;
+                debug!("Disk cache hit: {} ({} bytes)", key, data.len());
+                Some(Bytes::from(data))
+            }
+            Err(_) => {
+                debug!("Disk cache blob evicted: {}", key);
+                None
+            }
+        }
+    }
+
+    /// Store the bytes for a key, evicting old blobs if over the size cap
+    pub async fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.put_saved(key, data, None).await
+    }
+
+    /// [`Self::put`] bytes first stored elsewhere at `saved`, so
+    /// [`Self::get_stored`] ages them from then
+    pub async fn put_saved(&self, key: &str, data: &[u8], saved: Option<SystemTime>) -> Result<()> {
+        let hash = format!("{:016x}", xxh64(data, 0));
+        let blob = self.dir.join("blobs").join(&hash);
+
+        if tokio::fs::metadata(&blob).await.is_ok() {
+            touch(&blob).await;
+        } else {
+            write_atomic(&blob, data).await?;
+        }
+        let ref_path = self.ref_path(key);
+        write_atomic(&ref_path, hash.as_bytes()).await?;
+        if let Some(saved) = saved {
+            set_modified(&ref_path, saved).await;
+        }
+
+        debug!("Disk cached: {} ({} bytes)", key, data.len());
+        self.evict().await
+    }
+
+    /// Current disk usage
+    pub async fn stats(&self) -> Result<DiskCacheStats> {
+        let blobs = self.blobs().await?;
+
+        Ok(DiskCacheStats {
+            blobs: blobs.len(),
+            bytes: blobs.iter().map(|blob| blob.size).sum(),
+            max_bytes: self.max_bytes,
+        })
+    }
+
+    /// Remove every cached blob and ref
+    pub async fn clear(&self) -> Result<()> {
+        for sub in ["blobs", "refs"] {
+            match tokio::fs::remove_dir_all(self.dir.join(sub)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e).context("Failed to clear disk layer cache");
+                }
+                _ => {}
+            }
+        }
+        info!("Disk layer cache cleared: {}", self.dir.display());
+        Ok(())
+    }
+
+    fn ref_path(&self, key: &str) -> PathBuf {
+        let hash = if self.namespace.is_empty() {
+            xxh64(key.as_bytes(), 0)
+        } else {
+            xxh64(format!("{}\n{}", self.namespace, key).as_bytes(), 0)
+        };
+        self.dir.join("refs").join(format!("{:016x}", hash))
+    }
+
+    /// Remove least recently used blobs until under the size cap
+    async fn evict(&self) -> Result<()> {
+        let mut blobs = self.blobs().await?;
+        let mut total: u64 = blobs.iter().map(|blob| blob.size).sum();
+        if total <= self.max_bytes {
+            return Ok(());
+        }
+
+        blobs.sort_by_key(|blob| blob.used);
+        for blob in blobs {
+            if total <= self.max_bytes {
+                break;
+            }
+            match tokio::fs::remove_file(&blob.path).await {
+                Ok(()) => total -= blob.size,
+                Err(e) => warn!("Failed to evict {}: {}", blob.path.display(), e),
+            }
+        }
+
+        debug!("Disk layer cache evicted down to {} bytes", total);
+        Ok(())
+    }
+
+    async fn blobs(&self) -> Result<Vec<Blob>> {
+        let mut blobs = Vec::new();
+        let mut entries = match tokio::fs::read_dir(self.dir.join("blobs")).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(blobs),
+            Err(e) => return Err(e).context("Failed to read disk layer cache"),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            let partial = entry.file_name().to_string_lossy().contains(".tmp-");
+            if metadata.is_file() && !partial {
+                blobs.push(Blob {
+                    path: entry.path(),
+                    size: metadata.len(),
+                    used: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                });
+            }
+        }
+
+        Ok(blobs)
+    }
+}
+
+struct Blob {
+    path: PathBuf,
+    size: u64,
+    used: SystemTime,
+}
+
+/// Write through a temporary file so readers never see partial content
+async fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    // Unique per write, so concurrent puts of the same content don't collide
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let tmp = path.with_extension(format!(
+        "tmp-{}-{}",
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    tokio::fs::write(&tmp, data)
+        .await
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Bump the modification time, which eviction treats as last use
+async fn touch(path: &Path) {
+    set_modified(path, SystemTime::now()).await;
+}
+
+async fn set_modified(path: &Path, time: SystemTime) {
+    let result = match tokio::fs::OpenOptions::new().write(true).open(path).await {
+        Ok(file) => file.into_std().await.set_modified(time),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        debug!("Failed to touch {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("birl-disk-cache-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip_and_dedup() {
+        let cache = DiskLayerCache::new(cache_dir("roundtrip"), DEFAULT_DISK_CACHE_BYTES);
+
+        assert!(cache.get("front/hoodies/hoodie-black.png").await.is_none());
+        cache
+            .put("front/hoodies/hoodie-black.png", b"layer")
+            .await
+            .unwrap();
+        cache
+            .put("back/hoodies/hoodie-black.png", b"layer")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            cache.get("front/hoodies/hoodie-black.png").await.as_deref(),
+            Some(&b"layer"[..])
+        );
+
+        // Same content is stored once
+        let stats = cache.stats().await.unwrap();
+        assert_eq!((stats.blobs, stats.bytes), (1, 5));
+
+        // Stored a minute ago, so expired for anything newer
+        let minute_ago = SystemTime::now() - std::time::Duration::from_secs(60);
+        cache
+            .put_saved("front/hoodies/hoodie-black.png", b"layer", Some(minute_ago))
+            .await
+            .unwrap();
+        let (_, stored) = cache
+            .get_stored("front/hoodies/hoodie-black.png")
+            .await
+            .unwrap();
+        let half_minute_ago = SystemTime::now() - std::time::Duration::from_secs(30);
+        assert!(stored < half_minute_ago);
+        let (_, stored) = cache
+            .get_stored("back/hoodies/hoodie-black.png")
+            .await
+            .unwrap();
+        assert!(stored > half_minute_ago);
+
+        cache.clear().await.unwrap();
+        assert!(cache.get("front/hoodies/hoodie-black.png").await.is_none());
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_namespace_and_ttl() {
+        let dir = cache_dir("namespace");
+        let bucket = DiskLayerCache::new(&dir, DEFAULT_DISK_CACHE_BYTES)
+            .with_namespace("s3://assets/prod")
+            .with_ttl(Duration::from_secs(60));
+        let staging = DiskLayerCache::new(&dir, DEFAULT_DISK_CACHE_BYTES)
+            .with_namespace("s3://assets/staging");
+
+        bucket
+            .put("front/hoodies/hoodie-black.png", b"prod")
+            .await
+            .unwrap();
+        assert!(staging
+            .get("front/hoodies/hoodie-black.png")
+            .await
+            .is_none());
+        assert_eq!(
+            bucket
+                .get("front/hoodies/hoodie-black.png")
+                .await
+                .as_deref(),
+            Some(&b"prod"[..])
+        );
+
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        bucket
+            .put_saved("front/hoodies/hoodie-black.png", b"prod", Some(hour_ago))
+            .await
+            .unwrap();
+        assert!(bucket.get("front/hoodies/hoodie-black.png").await.is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_evicts_least_recently_used() {
+        let cache = DiskLayerCache::new(cache_dir("evict"), 10);
+
+        cache.put("a", b"aaaa").await.unwrap();
+        cache.put("b", b"bbbb").await.unwrap();
+        // Make "b" the oldest
+        let old = SystemTime::now() - std::time::Duration::from_secs(60);
+        let b_blob = cache
+            .dir()
+            .join("blobs")
+            .join(format!("{:016x}", xxh64(b"bbbb", 0)));
+        std::fs::File::options()
+            .write(true)
+            .open(&b_blob)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        cache.put("c", b"cccc").await.unwrap();
+
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("b").await.is_none());
+        assert!(cache.get("c").await.is_some());
+        assert!(cache.stats().await.unwrap().bytes <= 10);
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+//! Dry-run cost estimates for bulk render jobs
+//!
+//! [`StorageService::estimate`] takes the plans of a job's renders and works
+//! out what running them would cost: storage reads and writes, bytes moved,
+//! CPU time spent compositing, and roughly how long the job takes at a given
+//! concurrency. Nothing is fetched or rendered. Per-operation costs come from a
+//! [`Calibration`], which `birl-cli bench --calibration` measures on the
+//! machine that will run the job.
+//!
+//! Every distinct composite is assumed to be missing from the cache, so the
+//! estimate is an upper bound for a job that finds some of them cached.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::plan::CompositionPlan;
+use crate::StorageService;
+
+/// Measured cost of the operations a render is made of
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Calibration {
+    /// CPU time of a render without layers (decoding the plate, encoding)
+    pub render_ms: f64,
+    /// CPU time each layer adds to a render
+    pub layer_ms: f64,
+    /// Latency of a storage read
+    pub get_ms: f64,
+    /// Latency of a storage write
+    pub put_ms: f64,
+    /// Average size of a plate or layer
+    pub layer_bytes: u64,
+    /// Average size of an encoded composite
+    pub composite_bytes: u64,
+}
+
+/// Rough S3 latencies and sizes of the demo assets
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            render_ms: 40.0,
+            layer_ms: 15.0,
+            get_ms: 60.0,
+            put_ms: 80.0,
+            layer_bytes: 300 * 1024,
+            composite_bytes: 250 * 1024,
+        }
+    }
+}
+
+impl Calibration {
+    /// Read a calibration written by `birl-cli bench --calibration`
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    fn cpu(&self, layers: usize) -> Duration {
+        Duration::from_secs_f64((self.render_ms + self.layer_ms * layers as f64).max(0.0) / 1000.0)
+    }
+}
+
+/// What a job would cost, estimated from its plans
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[non_exhaustive]
+pub struct JobEstimate {
+    /// Plans in the job
+    pub items: usize,
+    /// Distinct composites rendered; the other items are cache hits
+    pub renders: usize,
+    pub gets: u64,
+    pub puts: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// CPU time of compositing, over all renders
+    pub cpu: Duration,
+    /// Expected wall-clock time of the job
+    pub duration: Duration,
+}
+
+impl StorageService {
+    /// Estimate the cost of rendering `plans` with `concurrency` in flight
+    ///
+    /// Each render reads its plate and layers, unless a layer cache keeps
+    /// them after their first read, and writes its composite. Unless
+    /// `bypass_cache` is set, every item first looks its composite up.
+    pub fn estimate(
+        &self,
+        plans: &[CompositionPlan],
+        calibration: &Calibration,
+        concurrency: usize,
+        bypass_cache: bool,
+    ) -> JobEstimate {
+        let mut estimate = JobEstimate {
+            items: plans.len(),
+            ..JobEstimate::default()
+        };
+        let mut rendered = HashSet::new();
+        let mut read = HashSet::new();
+        let mut busy = Duration::ZERO;
+
+        for plan in plans {
+            let mut latency = 0.0;
+            if !bypass_cache {
+                estimate.gets += 1;
+                latency += calibration.get_ms;
+            }
+            if !bypass_cache && !rendered.insert(&plan.cache_key) {
+                busy += Duration::from_secs_f64(latency / 1000.0);
+                continue;
+            }
+
+            let assets = std::iter::once(&plan.plate).chain(&plan.layers);
+            let fetched = assets
+                .filter(|asset| self.layer_cache.is_none() || read.insert(&asset.key))
+                .count() as u64;
+            estimate.gets += fetched;
+            estimate.bytes_read += fetched * calibration.layer_bytes;
+            if fetched > 0 {
+                // Assets are fetched at once, so a render waits for one read
+                latency += calibration.get_ms;
+            }
+
+            let cpu = calibration.cpu(plan.layers.len());
+            estimate.renders += 1;
+            estimate.cpu += cpu;
+            estimate.puts += 1;
+            estimate.bytes_written += calibration.composite_bytes;
+            latency += calibration.put_ms;
+            busy += cpu + Duration::from_secs_f64(latency / 1000.0);
+        }
+
+        // Renders overlap up to the concurrency, but compositing can't go past
+        // the cores, nor writes past the write limit
+        let concurrency = concurrency.max(1);
+        let cores = std::thread::available_parallelism().map_or(1, usize::from);
+        let mut duration = busy / concurrency as u32;
+        duration = duration.max(estimate.cpu / concurrency.min(cores) as u32);
+        if let Some(throttle) = &self.throttle {
+            let writes = Duration::from_secs_f64(estimate.puts as f64 / throttle.ops_per_sec());
+            duration = duration.max(writes);
+        }
+        estimate.duration = duration;
+        estimate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DiskLayerCache, WriteThrottle};
+    use birl_core::{parse_params, CompositeOptions, View};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_estimate() {
+        let service = StorageService::new_demo(10);
+        let options = CompositeOptions::default();
+        let plans: Vec<CompositionPlan> = [
+            "hoodies/hoodie-black,pants/cargo-black",
+            "hoodies/hoodie-black,pants/cargo-black",
+            "hoodies/hoodie-black",
+        ]
+        .iter()
+        .map(|params| {
+            service
+                .plan(&parse_params(params), View::Front, &options)
+                .unwrap()
+        })
+        .collect();
+        let calibration = Calibration {
+            render_ms: 100.0,
+            layer_ms: 50.0,
+            get_ms: 10.0,
+            put_ms: 20.0,
+            layer_bytes: 1000,
+            composite_bytes: 500,
+        };
+
+        // The repeated outfit is a cache hit
+        let estimate = service.estimate(&plans, &calibration, 1, false);
+        assert_eq!((estimate.items, estimate.renders), (3, 2));
+        assert_eq!((estimate.gets, estimate.puts), (3 + 5, 2));
+        assert_eq!((estimate.bytes_read, estimate.bytes_written), (5000, 1000));
+        assert_eq!(estimate.cpu, Duration::from_millis(200 + 150));
+        assert_eq!(estimate.duration, Duration::from_millis(240 + 10 + 190));
+
+        let estimate = service.estimate(&plans, &calibration, 1, true);
+        assert_eq!((estimate.renders, estimate.gets, estimate.puts), (3, 8, 3));
+
+        // With a layer cache, each asset is read once
+        let dir = std::env::temp_dir().join(format!("birl-estimate-{}", std::process::id()));
+        let service = service
+            .with_layer_cache(DiskLayerCache::new(&dir, 1 << 20))
+            .with_write_throttle(Arc::new(WriteThrottle::new(1.0, 1)));
+        let estimate = service.estimate(&plans, &calibration, 8, false);
+        assert_eq!(estimate.gets, 3 + 3);
+        // Two writes at one a second
+        assert_eq!(estimate.duration, Duration::from_secs(2));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,122 @@
+//! Limits on concurrent layer fetches
+//!
+//! A composite reads a plate and up to a few dozen layers, and starting every
+//! GET at once lets a burst of requests open hundreds of S3 connections. A
+//! [`FetchLimit`] keeps at most `per_request` fetches of one request in
+//! flight, and optionally at most `global` fetches from the source (the
+//! backend or a remote host, past the layer caches) across every request of
+//! the service. Fetches past either limit wait for a slot; none are dropped.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::Instant;
+
+/// Fetches of one request in flight at once, unless configured
+pub const DEFAULT_FETCHES_PER_REQUEST: usize = 8;
+
+/// Caps on the layer fetches of a request and of a service
+pub struct FetchLimit {
+    per_request: usize,
+    global: Option<(usize, Semaphore)>,
+    fetches: AtomicU64,
+    queued: AtomicU64,
+    waited_us: AtomicU64,
+}
+
+/// Source fetches a limit has let through so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FetchLimitStats {
+    pub per_request: usize,
+    pub global: Option<usize>,
+    pub fetches: u64,
+    /// Fetches that had to wait for a global slot
+    pub queued: u64,
+    /// Time fetches spent waiting for a global slot, in total
+    pub waited: Duration,
+}
+
+impl Default for FetchLimit {
+    fn default() -> Self {
+        Self::new(DEFAULT_FETCHES_PER_REQUEST, None)
+    }
+}
+
+impl FetchLimit {
+    /// `per_request` fetches of a request at once, and `global` source
+    /// fetches of the service if set; both at least 1
+    pub fn new(per_request: usize, global: Option<usize>) -> Self {
+        Self {
+            per_request: per_request.max(1),
+            global: global.map(|max| (max.max(1), Semaphore::new(max.max(1)))),
+            fetches: AtomicU64::new(0),
+            queued: AtomicU64::new(0),
+            waited_us: AtomicU64::new(0),
+        }
+    }
+
+    /// Fetches of one request in flight at once
+    pub fn per_request(&self) -> usize {
+        self.per_request
+    }
+
+    /// Wait for a global slot, held until the permit is dropped
+    /// `None` without a global cap
+    pub async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.fetches.fetch_add(1, Ordering::Relaxed);
+        let (_, slots) = self.global.as_ref()?;
+        if let Ok(permit) = slots.try_acquire() {
+            return Some(permit);
+        }
+
+        let start = Instant::now();
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        let permit = slots.acquire().await.expect("fetch slots are never closed");
+        self.waited_us
+            .fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+        Some(permit)
+    }
+
+    pub fn stats(&self) -> FetchLimitStats {
+        FetchLimitStats {
+            per_request: self.per_request,
+            global: self.global.as_ref().map(|(max, _)| *max),
+            fetches: self.fetches.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+            waited: Duration::from_micros(self.waited_us.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_global_slots() {
+        let limit = Arc::new(FetchLimit::new(0, Some(1)));
+        assert_eq!(limit.per_request(), 1);
+
+        let held = limit.acquire().await.unwrap();
+        let waiting = tokio::spawn({
+            let limit = limit.clone();
+            async move {
+                let _permit = limit.acquire().await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        drop(held);
+        waiting.await.unwrap();
+
+        let stats = limit.stats();
+        assert_eq!((stats.fetches, stats.queued), (2, 1));
+        assert_eq!(stats.global, Some(1));
+
+        let unbounded = FetchLimit::default();
+        assert!(unbounded.acquire().await.is_none());
+        assert_eq!(unbounded.stats().per_request, DEFAULT_FETCHES_PER_REQUEST);
+    }
+}
@@ -0,0 +1,182 @@
+//! Flat lay renders: an outfit's garments on the configured `[flat_lay]` canvas
+//!
+//! Requests are normalized as for a render on the layout's view, so the same
+//! conflicts, constraints and catalog checks apply. Layers whose category has
+//! no slot are dropped, and missing layers leave their slot empty.
+
+use anyhow::{anyhow, Result};
+use birl_core::{
+    Asset, BirlError, CompositeOptions, FlatLay, LayerNormalizer, LayerParam, MissingSources,
+};
+use tracing::{debug, info, warn};
+
+use crate::{PreparedComposite, PreparedContent, RenderedComposite, StorageService};
+
+impl StorageService {
+    /// Render a flat lay of `params` with the installed layout
+    pub async fn render_flat_lay(
+        &self,
+        params: &[LayerParam],
+        options: &CompositeOptions,
+        bypass_cache: bool,
+    ) -> Result<RenderedComposite> {
+        self.prepare_flat_lay(params, options, bypass_cache)
+            .await?
+            .encode()
+            .await
+    }
+
+    /// [`Self::prepare`] for a flat lay
+    pub async fn prepare_flat_lay(
+        &self,
+        params: &[LayerParam],
+        options: &CompositeOptions,
+        bypass_cache: bool,
+    ) -> Result<PreparedComposite> {
+        let layout = FlatLay::global().ok_or_else(|| {
+            anyhow!(BirlError::InvalidParam(
+                "No [flat_lay] is configured".to_string()
+            ))
+        })?;
+        self.prepare_flat_lay_with(&layout, params, options, bypass_cache)
+            .await
+    }
+
+    /// [`Self::prepare_flat_lay`] persisting nothing, like
+    /// [`Self::prepare_uncached`]
+    pub async fn prepare_flat_lay_uncached(
+        &self,
+        params: &[LayerParam],
+        options: &CompositeOptions,
+    ) -> Result<PreparedComposite> {
+        let prepared = self
+            .without_layer_caches()
+            .prepare_flat_lay(params, options, true)
+            .await?;
+        Ok(prepared.uncached())
+    }
+
+    async fn prepare_flat_lay_with(
+        &self,
+        layout: &FlatLay,
+        params: &[LayerParam],
+        options: &CompositeOptions,
+        bypass_cache: bool,
+    ) -> Result<PreparedComposite> {
+        let view = layout.view;
+        let normalized = LayerNormalizer::new(view, params).resolve(params)?;
+        if let Some(catalog) = &self.catalog {
+            catalog
+                .validate(&normalized.layers, view, options.plate_for(view))
+                .map_err(BirlError::from)?;
+        }
+        let (options, attributions) = self.check_licenses(&normalized.layers, view, options)?;
+        let options = &options;
+        let (layers, unslotted) = layout.arrange(&normalized.layers);
+        let mut dropped = normalized.dropped;
+        dropped.extend(unslotted);
+        for dropped in &dropped {
+            debug!("{}", dropped);
+        }
+
+        let cache_key = layout.cache_key(&layers, options);
+        let format = options.format;
+        let prepared = |content, requested_layers, found_layers, sanity_issue| PreparedComposite {
+            cache_key: cache_key.clone(),
+            format,
+            requested_layers,
+            found_layers,
+            sanity_issue,
+            conflicts: normalized.conflicts.clone(),
+            violations: normalized.violations.clone(),
+            dropped: dropped.clone(),
+            missing: Vec::new(),
+            attributions: attributions.clone(),
+            options: options.clone(),
+            content,
+            cache: self.cache.clone(),
+            license: self.license_record(&layers, options),
+        };
+
+        if !bypass_cache {
+            if let Some(cached_data) = self.get_cached_composite(&cache_key, format).await? {
+                info!("Serving cached flat lay: {}", cache_key);
+                let content = PreparedContent::Encoded {
+                    data: cached_data,
+                    cached: true,
+                };
+                return Ok(prepared(content, layers.len(), layers.len(), None));
+            }
+        }
+
+        let recipe = layout.compile(&layers, options);
+        let assets = self
+            .fetch_sources_on(&recipe, view, view.plate_value(), options.layer_filter)
+            .await?
+            .with_missing(MissingSources::Skip);
+
+        let not_found: Vec<LayerParam> = layers
+            .iter()
+            .filter(|param| !assets.contains(&Asset::Layer((*param).clone())))
+            .cloned()
+            .collect();
+        let requested_count = layers.len();
+        let found_count = requested_count - not_found.len();
+        let missing = self.missing_layers(&not_found, view).await;
+        if !missing.is_empty() {
+            let missing: Vec<String> = missing.iter().map(ToString::to_string).collect();
+            warn!(
+                "Found {}/{} flat lay layers, missing {}",
+                found_count,
+                requested_count,
+                missing.join(", ")
+            );
+        }
+
+        let compositor = birl_core::render::compose(&recipe, &assets)?;
+        // Canvases are mostly background, so only completeness decides caching
+        let content = PreparedContent::Composed {
+            compositor,
+            cacheable: requested_count == found_count,
+        };
+        let mut prepared = prepared(content, requested_count, found_count, None);
+        prepared.missing = missing;
+        Ok(prepared)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use birl_core::{parse_params, OutputFormat, Slot};
+
+    #[tokio::test]
+    async fn test_flat_lay_render() {
+        let service = StorageService::new_demo(10);
+        let layout = FlatLay::new(400, 200)
+            .with_slot("hoodies", Slot::new(0, 0, 200, 200))
+            .with_slot("pants", Slot::new(200, 0, 200, 200));
+        let params = parse_params("hoodies/hoodie-black,pants/cargo-black,hats/beanie-black");
+        let options = CompositeOptions::default().with_format(OutputFormat::Png);
+
+        let prepared = service
+            .prepare_flat_lay_with(&layout, &params, &options, false)
+            .await
+            .unwrap();
+        assert_eq!(prepared.requested_layers, 2);
+        assert_eq!(prepared.found_layers, 2);
+        assert_eq!(prepared.dropped.len(), 1);
+        assert_eq!(prepared.dropped[0].reason.name(), "no-slot");
+        let rendered = prepared.encode().await.unwrap();
+        assert_eq!(
+            birl_core::oversize::dimensions(&rendered.data),
+            Some((400, 200))
+        );
+
+        let cached = service
+            .prepare_flat_lay_with(&layout, &params, &options, false)
+            .await
+            .unwrap();
+        assert!(cached.cached());
+    }
+}
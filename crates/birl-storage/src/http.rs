@@ -0,0 +1,424 @@
+//! Remote layers fetched from partner hosts
+//!
+//! Hosts that need credentials get them from a [`RequestSigner`], which sees
+//! every request before it is sent and can add headers, sign the URL, or
+//! refresh a token:
+//!
+//! ```ignore
+//! struct PartnerAuth { tokens: TokenCache }
+//!
+//! #[async_trait::async_trait]
+//! impl RequestSigner for PartnerAuth {
+//!     async fn sign(&self, request: &mut Request) -> anyhow::Result<()> {
+//!         let token = self.tokens.fresh().await?;
+//!         request.headers_mut().insert(AUTHORIZATION, format!("Bearer {}", token).parse()?);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let fetcher = RemoteLayerFetcher::new(hosts, max_bytes)?.with_signer(PartnerAuth::new());
+//! ```
+//!
+//! Static headers (API keys) don't need code: [`HeaderSigner`] adds them, and
+//! profiles set them with `remote_layer_headers`. Redirects are only followed
+//! to allow-listed hosts, so credentials aren't sent anywhere else. A fetcher
+//! with signers only fetches https URLs, and no fetcher follows a redirect
+//! from https to http, so credentials never go out in cleartext.
+
+use anyhow::{Context, Result};
+use bytes::{Bytes, BytesMut};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{header, redirect, Client, Method, Request, StatusCode, Url};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+/// Default maximum size of a remote layer (10 MB)
+pub const DEFAULT_MAX_REMOTE_BYTES: usize = 10 * 1024 * 1024;
+
+/// Content types accepted for remote layers
+const ALLOWED_CONTENT_TYPES: &[&str] = &["image/png", "image/webp", "image/jpeg"];
+
+/// Maximum redirects followed for a remote layer
+const MAX_REDIRECTS: usize = 5;
+
+/// Hook run on every outbound remote layer request, after the host check
+#[async_trait::async_trait]
+pub trait RequestSigner: Send + Sync {
+    /// Add credentials to the request; an error fails the fetch
+    async fn sign(&self, request: &mut Request) -> Result<()>;
+}
+
+/// Adds fixed headers, e.g. an API key, to every request
+#[derive(Debug, Clone, Default)]
+pub struct HeaderSigner {
+    headers: HeaderMap,
+}
+
+impl HeaderSigner {
+    pub fn new<K: AsRef<str>, V: AsRef<str>>(
+        headers: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            let name = name.as_ref();
+            let header = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid header name '{}'", name))?;
+            let mut value = HeaderValue::from_str(value.as_ref())
+                .with_context(|| format!("Invalid value for header '{}'", name))?;
+            value.set_sensitive(true);
+            map.insert(header, value);
+        }
+        Ok(Self { headers: map })
+    }
+}
+
+#[async_trait::async_trait]
+impl RequestSigner for HeaderSigner {
+    async fn sign(&self, request: &mut Request) -> Result<()> {
+        for (name, value) in &self.headers {
+            request.headers_mut().insert(name, value.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Fetches layers from absolute URLs on allow-listed hosts (partner CDNs)
+pub struct RemoteLayerFetcher {
+    client: Client,
+    allowed_hosts: Arc<Vec<String>>,
+    max_bytes: usize,
+    signers: Vec<Arc<dyn RequestSigner>>,
+}
+
+impl RemoteLayerFetcher {
+    /// Create a fetcher for the given hosts
+    /// Entries like "*.partner.com" also allow any subdomain
+    pub fn new(allowed_hosts: Vec<String>, max_bytes: usize) -> Result<Self> {
+        let allowed_hosts: Arc<Vec<String>> = Arc::new(
+            allowed_hosts
+                .into_iter()
+                .map(|host| host.trim().to_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+        );
+
+        let hosts = Arc::clone(&allowed_hosts);
+        let policy = redirect::Policy::custom(move |attempt| {
+            match redirect_error(&hosts, attempt.url(), attempt.previous()) {
+                Some(error) => attempt.error(error),
+                None => attempt.follow(),
+            }
+        });
+
+        let client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .redirect(policy)
+            .build()
+            .context("Failed to build HTTP client")?;
+
+        Ok(Self {
+            client,
+            allowed_hosts,
+            max_bytes,
+            signers: Vec::new(),
+        })
+    }
+
+    /// Run a signer on every request; signers run in the order they're added
+    pub fn with_signer(mut self, signer: impl RequestSigner + 'static) -> Self {
+        self.signers.push(Arc::new(signer));
+        self
+    }
+
+    /// Create from BIRL_REMOTE_LAYER_HOSTS (comma-separated) and
+    /// BIRL_REMOTE_LAYER_MAX_BYTES; None when no hosts are configured
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(hosts) = std::env::var("BIRL_REMOTE_LAYER_HOSTS") else {
+            return Ok(None);
+        };
+
+        let max_bytes = std::env::var("BIRL_REMOTE_LAYER_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_REMOTE_BYTES);
+
+        let hosts: Vec<String> = hosts.split(',').map(str::to_string).collect();
+        Self::new(hosts, max_bytes).map(Some)
+    }
+
+    /// Check if a URL points at an allow-listed host
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        is_allowed(&self.allowed_hosts, url)
+    }
+
+    /// Check for a remote layer with a HEAD request
+    /// Only 404 and 410 count as missing; other failures are errors
+    pub async fn exists(&self, url: &str) -> Result<bool> {
+        let request = self.signed_request(Method::HEAD, url).await?;
+        let response = self
+            .client
+            .execute(request)
+            .await
+            .with_context(|| format!("Failed to check remote layer: {}", url))?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND | StatusCode::GONE => Ok(false),
+            _ => {
+                response
+                    .error_for_status()
+                    .with_context(|| format!("Remote layer request failed: {}", url))?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Fetch a remote layer, validating host, content type, and size
+    pub async fn fetch(&self, url: &str) -> Result<Bytes> {
+        let request = self.signed_request(Method::GET, url).await?;
+        let response = self
+            .client
+            .execute(request)
+            .await
+            .with_context(|| format!("Failed to fetch remote layer: {}", url))?
+            .error_for_status()
+            .with_context(|| format!("Remote layer request failed: {}", url))?;
+
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        if !ALLOWED_CONTENT_TYPES
+            .iter()
+            .any(|allowed| content_type.starts_with(allowed))
+        {
+            anyhow::bail!("Unsupported remote layer type '{}': {}", content_type, url);
+        }
+
+        if let Some(length) = response.content_length() {
+            if length as usize > self.max_bytes {
+                anyhow::bail!(
+                    "Remote layer too large ({} bytes, max {}): {}",
+                    length,
+                    self.max_bytes,
+                    url
+                );
+            }
+        }
+
+        // Content-Length may be missing or wrong, so the body is read a chunk
+        // at a time and dropped as soon as it goes over
+        let mut response = response;
+        let mut data = BytesMut::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .context("Failed to read remote layer body")?
+        {
+            if data.len() + chunk.len() > self.max_bytes {
+                anyhow::bail!(
+                    "Remote layer too large (over {} bytes): {}",
+                    self.max_bytes,
+                    url
+                );
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        debug!("Fetched remote layer: {} ({} bytes)", url, data.len());
+
+        Ok(data.freeze())
+    }
+
+    /// Request for an allow-listed URL, after every signer has run
+    async fn signed_request(&self, method: Method, url: &str) -> Result<Request> {
+        let parsed = Url::parse(url).with_context(|| format!("Invalid layer URL: {}", url))?;
+
+        if !self.is_allowed(&parsed) {
+            anyhow::bail!("Layer host not allowed: {}", url);
+        }
+        // Signers add credentials, which must not go out in cleartext
+        if !self.signers.is_empty() && parsed.scheme() != "https" {
+            anyhow::bail!("Signed layer URLs must use https: {}", url);
+        }
+
+        let mut request = self
+            .client
+            .request(method, parsed)
+            .build()
+            .with_context(|| format!("Invalid layer URL: {}", url))?;
+        for signer in &self.signers {
+            signer
+                .sign(&mut request)
+                .await
+                .with_context(|| format!("Failed to sign remote layer request: {}", url))?;
+        }
+        Ok(request)
+    }
+}
+
+/// Why a redirect to `url` after `previous` isn't followed, if it isn't
+/// Redirects from https to plain http are refused, so headers a signer added
+/// aren't sent in cleartext to the same host
+fn redirect_error(allowed_hosts: &[String], url: &Url, previous: &[Url]) -> Option<&'static str> {
+    if previous.len() >= MAX_REDIRECTS {
+        Some("too many redirects")
+    } else if !is_allowed(allowed_hosts, url) {
+        Some("redirect to a host that is not allowed")
+    } else if url.scheme() != "https" && previous.iter().any(|url| url.scheme() == "https") {
+        Some("redirect from https to http")
+    } else {
+        None
+    }
+}
+
+fn is_allowed(allowed_hosts: &[String], url: &Url) -> bool {
+    if !matches!(url.scheme(), "https" | "http") {
+        return false;
+    }
+
+    let Some(host) = url.host_str().map(str::to_lowercase) else {
+        return false;
+    };
+
+    allowed_hosts
+        .iter()
+        .any(|allowed| match allowed.strip_prefix("*.") {
+            Some(domain) => host.ends_with(&format!(".{}", domain)),
+            None => host == *allowed,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fetcher(hosts: &[&str]) -> RemoteLayerFetcher {
+        let hosts = hosts.iter().map(|h| h.to_string()).collect();
+        RemoteLayerFetcher::new(hosts, DEFAULT_MAX_REMOTE_BYTES).unwrap()
+    }
+
+    #[test]
+    fn test_allowed_hosts() {
+        let fetcher = fetcher(&["cdn.partner.com", "*.assets.example"]);
+
+        let allowed = |url: &str| fetcher.is_allowed(&Url::parse(url).unwrap());
+
+        assert!(allowed("https://cdn.partner.com/art.png"));
+        assert!(allowed("https://CDN.partner.com/art.png"));
+        assert!(allowed("https://eu.assets.example/art.png"));
+        assert!(!allowed("https://assets.example/art.png"));
+        assert!(!allowed("https://evil.com/art.png"));
+        assert!(!allowed("https://cdn.partner.com.evil.com/art.png"));
+        assert!(!allowed("ftp://cdn.partner.com/art.png"));
+    }
+
+    #[test]
+    fn test_redirects() {
+        let hosts = ["cdn.partner.com".to_string()];
+        let url = |url: &str| Url::parse(url).unwrap();
+        let https = url("https://cdn.partner.com/art.png");
+        let http = url("http://cdn.partner.com/art.png");
+        let (from_https, from_http) = ([https.clone()], [http.clone()]);
+
+        assert_eq!(redirect_error(&hosts, &https, &from_http), None);
+        assert_eq!(redirect_error(&hosts, &http, &from_http), None);
+        assert!(redirect_error(&hosts, &http, &from_https)
+            .unwrap()
+            .contains("https to http"));
+        assert!(
+            redirect_error(&hosts, &url("https://evil.com/art.png"), &from_https)
+                .unwrap()
+                .contains("not allowed")
+        );
+        let hops = vec![https.clone(); MAX_REDIRECTS];
+        assert!(redirect_error(&hosts, &https, &hops)
+            .unwrap()
+            .contains("too many"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_rejects_disallowed_host() {
+        let fetcher = fetcher(&["cdn.partner.com"]);
+        let result = fetcher.fetch("https://evil.com/art.png").await;
+        assert!(result.unwrap_err().to_string().contains("not allowed"));
+    }
+
+    /// Serve one `image/png` response of `chunks` chunks of 512 bytes, with
+    /// no Content-Length, and return its URL
+    async fn serve_chunked(chunks: usize) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await;
+            let head = "HTTP/1.1 200 OK\r\nContent-Type: image/png\r\n\
+                        Transfer-Encoding: chunked\r\n\r\n";
+            let _ = socket.write_all(head.as_bytes()).await;
+            for _ in 0..chunks {
+                let chunk = format!("200\r\n{}\r\n", "x".repeat(512));
+                if socket.write_all(chunk.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+            let _ = socket.write_all(b"0\r\n\r\n").await;
+        });
+        format!("http://{}/art.png", addr)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_caps_streamed_body() {
+        let fetcher = RemoteLayerFetcher::new(vec!["127.0.0.1".to_string()], 1024).unwrap();
+
+        let data = fetcher.fetch(&serve_chunked(2).await).await.unwrap();
+        assert_eq!(data.len(), 1024);
+
+        let error = fetcher.fetch(&serve_chunked(64).await).await.unwrap_err();
+        assert!(error.to_string().contains("too large"));
+    }
+
+    struct FailingSigner;
+
+    #[async_trait::async_trait]
+    impl RequestSigner for FailingSigner {
+        async fn sign(&self, _request: &mut Request) -> Result<()> {
+            anyhow::bail!("token expired")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signers() {
+        let signer = HeaderSigner::new([("X-Api-Key", "secret")]).unwrap();
+        let mut request = Client::new()
+            .get("https://cdn.partner.com/art.png")
+            .build()
+            .unwrap();
+        signer.sign(&mut request).await.unwrap();
+        assert_eq!(request.headers()["x-api-key"], "secret");
+
+        assert!(HeaderSigner::new([("X Api Key", "secret")]).is_err());
+
+        // A failing signer fails the fetch before anything is sent
+        let fetcher = fetcher(&["cdn.partner.com"]).with_signer(FailingSigner);
+        let error = fetcher
+            .fetch("https://cdn.partner.com/art.png")
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("token expired"));
+
+        // Credentials are only sent over https
+        let signer = HeaderSigner::new([("X-Api-Key", "secret")]).unwrap();
+        let local = RemoteLayerFetcher::new(vec!["127.0.0.1".to_string()], 1024).unwrap();
+        let error = local
+            .with_signer(signer)
+            .fetch(&serve_chunked(1).await)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("must use https"));
+    }
+}
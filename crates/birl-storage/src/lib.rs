@@ -4,21 +4,91 @@
 //! caching composites, and managing a multi-tier cache (memory + S3).
 
 pub mod cache;
+pub mod config;
+pub mod credentials;
+pub mod demo;
+pub mod disk;
+pub mod estimate;
+pub mod fetch_limit;
+pub mod flatlay;
+pub mod http;
+pub mod listing;
 pub mod local;
+pub mod locks;
+pub mod oversize;
+pub mod plan;
+pub mod plates;
+pub mod prefetch;
+pub mod preflight;
+pub mod prelude;
+pub mod replay;
+pub mod resolver;
+pub mod rollup;
 pub mod s3;
+pub mod shared;
+pub mod suggest;
+pub mod throttle;
 
 use anyhow::{Context, Result};
 use aws_sdk_s3::Client;
 use bytes::Bytes;
 use futures::future::try_join_all;
-use birl_core::{LayerParam, View};
-use std::path::PathBuf;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use birl_core::svg::SVG_EXTENSION;
+use birl_core::{
+    generate_cache_key, patch_mask_categories, requested_plate, variant_cache_key, Asset, Assets,
+    Catalog, CategoryRegistry, CompositeOptions, Compositor, ConstraintViolation, DisplayNames,
+    DroppedLayer, LayerConflict, LayerNormalizer, LayerOffsets, LayerParam, Licenses, Limits,
+    MissingSources, Offset, Orientation, Orientations, OutputFormat, Placement, Presets, Recipe,
+    ResizeFilter, SanityIssue, TieredCache, TieredStats, View, Wildcards, PLATE_CATEGORY,
+};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::sync::Arc;
-use tracing::{debug, warn};
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::{debug, error, info, warn};
 
-pub use cache::{CacheStats, ImageCache};
+use oversize::OversizedLayers;
+use plan::LicenseRecord;
+use plates::PlateExtensions;
+use shared::SharedLayers;
+
+pub use cache::{
+    CacheStats, ImageCache, DEFAULT_LAYER_MEMORY_BYTES, DEFAULT_LAYER_MEMORY_TTL,
+    DEFAULT_MEMORY_CACHE_BYTES,
+};
+pub use config::{
+    AuthSettings, CaptureSettings, Config, DiskCacheSettings, FetchLimitSettings,
+    PrefetchSettings, Profile, RollupSettings, WriteLimitSettings,
+};
+pub use demo::DemoStorage;
+pub use disk::{DiskCacheStats, DiskLayerCache, DEFAULT_DISK_CACHE_TTL};
+pub use estimate::{Calibration, JobEstimate};
+pub use fetch_limit::{FetchLimit, FetchLimitStats, DEFAULT_FETCHES_PER_REQUEST};
+pub use http::{HeaderSigner, RemoteLayerFetcher, RequestSigner};
+pub use listing::{collect_pages, Page, PageStream};
 pub use local::LocalStorage;
-pub use s3::S3Storage;
+pub use oversize::OversizedLayer;
+pub use plan::{CompositionPlan, PlannedLayer};
+pub use plates::{PLATE_EXTENSIONS, RESOLUTION_TTL};
+pub use prefetch::Prefetcher;
+pub use preflight::{AssetStatus, LayerCheck, PreflightReport};
+pub use replay::{output_hash, ReplayOutcome, ReplaySummary, RequestRecord, RequestRecorder};
+pub use resolver::{DefaultKeyResolver, KeyResolver, TemplateKeyResolver};
+pub use rollup::{outfit_key, DailyRollup, Day, RollupRecorder};
+pub use s3::{S3Endpoint, S3Storage};
+pub use shared::SharingStats;
+pub use suggest::MissingLayer;
+pub use throttle::{ThrottleStats, ThrottledBackend, WriteThrottle};
+
+pub use birl_core::catalog::CATALOG_KEY;
+pub use birl_core::graph::MASKS_CATEGORY;
+pub use birl_core::licenses::LICENSES_KEY;
+pub use birl_core::names::PRODUCTS_KEY;
+pub use birl_core::presets::PRESETS_KEY;
 
 /// Storage backend trait
 #[async_trait::async_trait]
@@ -31,9 +101,82 @@ pub trait StorageBackend: Send + Sync {
         extension: &str,
     ) -> Result<Option<Bytes>>;
 
-    async fn fetch_cached(&self, cache_key: &str) -> Result<Option<Bytes>>;
-    async fn save_to_cache(&self, cache_key: &str, data: &[u8]) -> Result<()>;
+    /// Check for a layer without downloading it, where the backend can
+    async fn layer_exists(
+        &self,
+        category: &str,
+        sku: &str,
+        view: View,
+        extension: &str,
+    ) -> Result<bool> {
+        Ok(self
+            .fetch_layer(category, sku, view, extension)
+            .await?
+            .is_some())
+    }
+
+    async fn fetch_cached(&self, cache_key: &str, format: OutputFormat) -> Result<Option<Bytes>>;
+
+    /// [`Self::fetch_cached`] with when the composite was saved, where the
+    /// backend records it, so old composites can be expired
+    async fn fetch_cached_saved(
+        &self,
+        cache_key: &str,
+        format: OutputFormat,
+    ) -> Result<Option<(Bytes, Option<SystemTime>)>> {
+        Ok(self
+            .fetch_cached(cache_key, format)
+            .await?
+            .map(|data| (data, None)))
+    }
+
+    async fn save_to_cache(&self, cache_key: &str, data: &[u8], format: OutputFormat)
+        -> Result<()>;
     async fn fetch_cached_json(&self, key: &str) -> Result<Option<String>>;
+
+    /// Store JSON data where [`Self::fetch_cached_json`] reads it
+    async fn save_cached_json(&self, _key: &str, _json: &str) -> Result<()> {
+        anyhow::bail!("Storage backend can't save JSON data")
+    }
+
+    /// Where a cached composite is stored, for backends with addressable storage
+    fn cache_url(&self, _cache_key: &str, _format: OutputFormat) -> Option<String> {
+        None
+    }
+
+    /// Where the backend's assets live, e.g. `s3://{bucket}/{prefix}`, so
+    /// caches outliving one backend keep each backend's entries apart
+    fn location(&self) -> String {
+        String::new()
+    }
+
+    /// Key a layer is fetched from, e.g. to show where a render reads it
+    fn layer_key(&self, category: &str, sku: &str, view: View, extension: &str) -> String {
+        DefaultKeyResolver.layer_key(category, sku, view, extension)
+    }
+
+    /// Paths of all plates and layers (`{view}/{category}/{sku}.{ext}`) a page
+    /// at a time, leaving out the composite cache
+    fn asset_pages(&self) -> PageStream<'_, String> {
+        listing::unsupported("Storage backend can't list its assets")
+    }
+
+    /// Every page of [`Self::asset_pages`] in one list
+    async fn list_assets(&self) -> Result<Vec<String>> {
+        collect_pages(self.asset_pages()).await
+    }
+
+    /// Create the lock `cache/locks/{key}` unless it exists, in one atomic step
+    /// Returns whether this call created it
+    async fn claim(&self, _key: &str, _holder: &str) -> Result<bool> {
+        anyhow::bail!("Storage backend can't claim locks")
+    }
+
+    /// When the credentials the backend signs requests with expire, after
+    /// refreshing them if they're about to; `None` if they don't expire
+    async fn credentials_expiry(&self) -> Result<Option<SystemTime>> {
+        Ok(None)
+    }
 }
 
 #[async_trait::async_trait]
@@ -48,17 +191,68 @@ impl StorageBackend for S3Storage {
         S3Storage::fetch_layer(self, category, sku, view, extension).await
     }
 
-    async fn fetch_cached(&self, cache_key: &str) -> Result<Option<Bytes>> {
-        S3Storage::fetch_cached(self, cache_key).await
+    async fn layer_exists(
+        &self,
+        category: &str,
+        sku: &str,
+        view: View,
+        extension: &str,
+    ) -> Result<bool> {
+        S3Storage::layer_exists(self, category, sku, view, extension).await
+    }
+
+    async fn fetch_cached(&self, cache_key: &str, format: OutputFormat) -> Result<Option<Bytes>> {
+        S3Storage::fetch_cached(self, cache_key, format).await
+    }
+
+    async fn fetch_cached_saved(
+        &self,
+        cache_key: &str,
+        format: OutputFormat,
+    ) -> Result<Option<(Bytes, Option<SystemTime>)>> {
+        S3Storage::fetch_cached_saved(self, cache_key, format).await
     }
 
-    async fn save_to_cache(&self, cache_key: &str, data: &[u8]) -> Result<()> {
-        S3Storage::save_to_cache(self, cache_key, data).await
+    async fn save_to_cache(
+        &self,
+        cache_key: &str,
+        data: &[u8],
+        format: OutputFormat,
+    ) -> Result<()> {
+        S3Storage::save_to_cache(self, cache_key, data, format).await
     }
 
     async fn fetch_cached_json(&self, key: &str) -> Result<Option<String>> {
         S3Storage::fetch_cached_json(self, key).await
     }
+
+    async fn save_cached_json(&self, key: &str, json: &str) -> Result<()> {
+        S3Storage::save_cached_json(self, key, json).await
+    }
+
+    fn cache_url(&self, cache_key: &str, format: OutputFormat) -> Option<String> {
+        Some(S3Storage::cache_url(self, cache_key, format))
+    }
+
+    fn location(&self) -> String {
+        S3Storage::location(self)
+    }
+
+    fn layer_key(&self, category: &str, sku: &str, view: View, extension: &str) -> String {
+        S3Storage::layer_key(self, category, sku, view, extension)
+    }
+
+    fn asset_pages(&self) -> PageStream<'_, String> {
+        S3Storage::asset_pages(self)
+    }
+
+    async fn claim(&self, key: &str, holder: &str) -> Result<bool> {
+        S3Storage::claim(self, key, holder).await
+    }
+
+    async fn credentials_expiry(&self) -> Result<Option<SystemTime>> {
+        S3Storage::credentials_expiry(self).await
+    }
 }
 
 #[async_trait::async_trait]
@@ -73,40 +267,717 @@ impl StorageBackend for LocalStorage {
         LocalStorage::fetch_layer(self, category, sku, view, extension).await
     }
 
-    async fn fetch_cached(&self, cache_key: &str) -> Result<Option<Bytes>> {
-        LocalStorage::fetch_cached(self, cache_key).await
+    async fn layer_exists(
+        &self,
+        category: &str,
+        sku: &str,
+        view: View,
+        extension: &str,
+    ) -> Result<bool> {
+        Ok(LocalStorage::find_layer(self, category, sku, view, extension)
+            .await
+            .is_some())
+    }
+
+    async fn fetch_cached(&self, cache_key: &str, format: OutputFormat) -> Result<Option<Bytes>> {
+        LocalStorage::fetch_cached(self, cache_key, format).await
+    }
+
+    async fn fetch_cached_saved(
+        &self,
+        cache_key: &str,
+        format: OutputFormat,
+    ) -> Result<Option<(Bytes, Option<SystemTime>)>> {
+        LocalStorage::fetch_cached_saved(self, cache_key, format).await
     }
 
-    async fn save_to_cache(&self, cache_key: &str, data: &[u8]) -> Result<()> {
-        LocalStorage::save_to_cache(self, cache_key, data).await
+    async fn save_to_cache(
+        &self,
+        cache_key: &str,
+        data: &[u8],
+        format: OutputFormat,
+    ) -> Result<()> {
+        LocalStorage::save_to_cache(self, cache_key, data, format).await
     }
 
     async fn fetch_cached_json(&self, key: &str) -> Result<Option<String>> {
         LocalStorage::fetch_cached_json(self, key).await
     }
+
+    async fn save_cached_json(&self, key: &str, json: &str) -> Result<()> {
+        LocalStorage::save_cached_json(self, key, json).await
+    }
+
+    fn cache_url(&self, cache_key: &str, format: OutputFormat) -> Option<String> {
+        Some(LocalStorage::cache_url(self, cache_key, format))
+    }
+
+    fn location(&self) -> String {
+        format!("local:{}", self.base_path().display())
+    }
+
+    fn layer_key(&self, category: &str, sku: &str, view: View, extension: &str) -> String {
+        LocalStorage::layer_key(self, category, sku, view, extension)
+    }
+
+    fn asset_pages(&self) -> PageStream<'_, String> {
+        LocalStorage::asset_pages(self)
+    }
+
+    async fn list_assets(&self) -> Result<Vec<String>> {
+        LocalStorage::list_assets(self).await
+    }
+
+    async fn claim(&self, key: &str, holder: &str) -> Result<bool> {
+        LocalStorage::claim(self, key, holder).await
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for DemoStorage {
+    async fn fetch_layer(
+        &self,
+        category: &str,
+        sku: &str,
+        view: View,
+        extension: &str,
+    ) -> Result<Option<Bytes>> {
+        DemoStorage::fetch_layer(self, category, sku, view, extension).await
+    }
+
+    async fn layer_exists(
+        &self,
+        _category: &str,
+        _sku: &str,
+        _view: View,
+        _extension: &str,
+    ) -> Result<bool> {
+        Ok(true)
+    }
+
+    async fn fetch_cached(&self, cache_key: &str, format: OutputFormat) -> Result<Option<Bytes>> {
+        DemoStorage::fetch_cached(self, cache_key, format).await
+    }
+
+    async fn save_to_cache(
+        &self,
+        cache_key: &str,
+        data: &[u8],
+        format: OutputFormat,
+    ) -> Result<()> {
+        DemoStorage::save_to_cache(self, cache_key, data, format).await
+    }
+
+    async fn fetch_cached_json(&self, key: &str) -> Result<Option<String>> {
+        DemoStorage::fetch_cached_json(self, key).await
+    }
+
+    async fn save_cached_json(&self, key: &str, json: &str) -> Result<()> {
+        DemoStorage::save_cached_json(self, key, json).await
+    }
+
+    fn location(&self) -> String {
+        "demo".to_string()
+    }
+
+    async fn claim(&self, key: &str, holder: &str) -> Result<bool> {
+        DemoStorage::claim(self, key, holder).await
+    }
+}
+
+/// A composite produced by [`StorageService::render`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct RenderedComposite {
+    pub cache_key: String,
+    pub data: Bytes,
+    /// Whether the composite was served from cache
+    pub cached: bool,
+    pub format: OutputFormat,
+    pub requested_layers: usize,
+    pub found_layers: usize,
+    /// Defect found by the sanity check; flagged composites are not cached
+    pub sanity_issue: Option<SanityIssue>,
+    /// Categories requested more than once, and how each was resolved
+    pub conflicts: Vec<LayerConflict>,
+    /// Layer constraints the outfit breaks
+    pub violations: Vec<ConstraintViolation>,
+    /// Requested items normalization left out, and why
+    pub dropped: Vec<DroppedLayer>,
+    /// Layers that weren't found, with the SKUs they may be typos of
+    pub missing: Vec<MissingLayer>,
+    /// Credits the licenses of the layers require with the composite
+    pub attributions: Vec<String>,
+}
+
+/// Composites produced by [`StorageService::render_bundle`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct RenderedBundle {
+    /// The whole outfit
+    pub outfit: RenderedComposite,
+    /// Each item of the outfit alone on the plate, in request order
+    pub items: Vec<(LayerParam, RenderedComposite)>,
+}
+
+/// A composite that is ready to encode, returned by [`StorageService::prepare`]
+/// Lets callers stream the encode instead of buffering the whole output
+pub struct PreparedComposite {
+    pub cache_key: String,
+    pub format: OutputFormat,
+    pub requested_layers: usize,
+    pub found_layers: usize,
+    pub sanity_issue: Option<SanityIssue>,
+    pub conflicts: Vec<LayerConflict>,
+    pub violations: Vec<ConstraintViolation>,
+    pub dropped: Vec<DroppedLayer>,
+    pub missing: Vec<MissingLayer>,
+    pub attributions: Vec<String>,
+    options: CompositeOptions,
+    content: PreparedContent,
+    cache: Arc<ImageCache>,
+    /// Recorded with the composite when it's cached, if licenses are checked
+    license: Option<LicenseRecord>,
+}
+
+enum PreparedContent {
+    /// Already encoded: a cache hit or the bare plate
+    Encoded { data: Bytes, cached: bool },
+    /// Composed in memory; cached after encoding if `cacheable`
+    Composed {
+        compositor: Compositor,
+        cacheable: bool,
+    },
+}
+
+impl PreparedComposite {
+    /// Whether the composite was served from cache
+    pub fn cached(&self) -> bool {
+        matches!(self.content, PreparedContent::Encoded { cached: true, .. })
+    }
+
+    /// Encoded bytes, if no encode is needed (cache hit or bare plate)
+    pub fn encoded(&self) -> Option<&Bytes> {
+        match &self.content {
+            PreparedContent::Encoded { data, .. } => Some(data),
+            PreparedContent::Composed { .. } => None,
+        }
+    }
+
+    /// Leave the output out of the composite cache, e.g. for dry runs
+    pub fn uncached(mut self) -> Self {
+        if let PreparedContent::Composed { cacheable, .. } = &mut self.content {
+            *cacheable = false;
+        }
+        self
+    }
+
+    /// Encode into memory, caching the result when the composite is cacheable
+    pub async fn encode(self) -> Result<RenderedComposite> {
+        let (data, cached) = match self.content {
+            PreparedContent::Encoded { data, cached } => (data, cached),
+            PreparedContent::Composed {
+                compositor,
+                cacheable,
+            } => {
+                let data = compositor.encode_with(&self.options)?;
+                if cacheable {
+                    let license = self.license.as_ref();
+                    save_to_cache(&self.cache, &self.cache_key, data.clone(), self.format, license)
+                        .await;
+                }
+                (data, false)
+            }
+        };
+
+        Ok(RenderedComposite {
+            cache_key: self.cache_key,
+            data,
+            cached,
+            format: self.format,
+            requested_layers: self.requested_layers,
+            found_layers: self.found_layers,
+            sanity_issue: self.sanity_issue,
+            conflicts: self.conflicts,
+            violations: self.violations,
+            dropped: self.dropped,
+            missing: self.missing,
+            attributions: self.attributions,
+        })
+    }
+
+    /// Stream the encoded composite into a writer, returning the bytes written
+    /// Cacheable composites keep one copy of the output for the cache write
+    pub async fn finalize_into<W>(self, writer: &mut W) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        match self.content {
+            PreparedContent::Encoded { data, .. } => {
+                writer
+                    .write_all(&data)
+                    .await
+                    .context("Failed to write composite")?;
+                writer.flush().await.context("Failed to flush composite")?;
+                Ok(data.len() as u64)
+            }
+            PreparedContent::Composed {
+                compositor,
+                cacheable: false,
+            } => Ok(compositor.encode_into(&self.options, writer).await?),
+            PreparedContent::Composed {
+                compositor,
+                cacheable: true,
+            } => {
+                let mut tee = TeeWriter {
+                    inner: writer,
+                    copy: Vec::new(),
+                };
+                let written = compositor.encode_into(&self.options, &mut tee).await?;
+                let data = Bytes::from(tee.copy);
+                let license = self.license.as_ref();
+                save_to_cache(&self.cache, &self.cache_key, data, self.format, license).await;
+                Ok(written)
+            }
+        }
+    }
+}
+
+/// Save a composite, logging instead of failing the render
+/// A composite whose license record can't be saved isn't cached, since
+/// `/cache` couldn't tell where it may be shown
+async fn save_to_cache(
+    cache: &ImageCache,
+    cache_key: &str,
+    data: Bytes,
+    format: OutputFormat,
+    license: Option<&LicenseRecord>,
+) {
+    if let Some(license) = license {
+        if let Err(e) = cache.put_license(cache_key, license).await {
+            error!("Failed to save license record, not caching {}: {:#}", cache_key, e);
+            return;
+        }
+    }
+    if let Err(e) = cache.put(cache_key, data, format).await {
+        error!("Failed to save to cache: {}", e);
+    }
+}
+
+/// Writer that keeps a copy of everything written through it
+struct TeeWriter<'a, W> {
+    inner: &'a mut W,
+    copy: Vec<u8>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for TeeWriter<'_, W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let poll = Pin::new(&mut *this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            this.copy.extend_from_slice(&buf[..n]);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_shutdown(cx)
+    }
 }
 
 /// High-level storage service that combines storage backend and caching
+#[derive(Clone)]
 pub struct StorageService {
     backend: Arc<dyn StorageBackend>,
     cache: Arc<ImageCache>,
+    remote: Option<Arc<RemoteLayerFetcher>>,
+    layer_cache: Option<Arc<DiskLayerCache>>,
+    /// Fetched plates and layers by view, category, SKU and extension (or URL)
+    layer_memory: Option<Arc<TieredCache<String, Bytes>>>,
+    shared: Option<Arc<SharedLayers>>,
+    oversized: Option<Arc<OversizedLayers>>,
+    plates: Arc<PlateExtensions>,
+    catalog: Option<Arc<Catalog>>,
+    licenses: Option<Arc<Licenses>>,
+    listed_skus: Arc<suggest::ListedSkus>,
+    throttle: Option<Arc<WriteThrottle>>,
+    fetch_limit: Arc<FetchLimit>,
 }
 
 impl StorageService {
-    /// Create a new storage service with S3 backend
-    pub fn new_s3(s3_client: Client, bucket: String, cache_capacity: usize) -> Self {
-        let backend = Arc::new(S3Storage::new(s3_client, bucket));
+    /// Create a storage service over any backend, e.g. an `S3Storage` or
+    /// `LocalStorage` with a custom [`KeyResolver`]
+    pub fn from_backend(backend: Arc<dyn StorageBackend>, cache_capacity: usize) -> Self {
         let cache = Arc::new(ImageCache::new(backend.clone(), cache_capacity));
 
-        Self { backend, cache }
+        Self {
+            backend,
+            cache,
+            remote: None,
+            layer_cache: None,
+            layer_memory: None,
+            shared: None,
+            oversized: Some(Arc::default()),
+            plates: Arc::default(),
+            catalog: None,
+            licenses: None,
+            listed_skus: Arc::default(),
+            throttle: None,
+            fetch_limit: Arc::default(),
+        }
+    }
+
+    /// Create a new storage service with S3 backend
+    pub fn new_s3(s3_client: Client, bucket: String, cache_capacity: usize) -> Self {
+        Self::from_backend(Arc::new(S3Storage::new(s3_client, bucket)), cache_capacity)
     }
 
     /// Create a new storage service with local filesystem backend
     pub fn new_local(base_path: PathBuf, cache_capacity: usize) -> Self {
-        let backend = Arc::new(LocalStorage::new(base_path));
-        let cache = Arc::new(ImageCache::new(backend.clone(), cache_capacity));
+        Self::from_backend(Arc::new(LocalStorage::new(base_path)), cache_capacity)
+    }
+
+    /// Create a storage service with synthetic demo assets (no storage needed)
+    pub fn new_demo(cache_capacity: usize) -> Self {
+        Self::from_backend(Arc::new(DemoStorage::new()), cache_capacity)
+    }
+
+    /// Create a storage service for a config profile
+    /// Uses local storage when the profile sets `local_path`, S3 otherwise
+    pub async fn from_profile(profile: &Profile) -> Result<Self> {
+        let resolver = profile.key_resolver()?;
+
+        let backend: Arc<dyn StorageBackend> = match &profile.local_path {
+            Some(path) => {
+                info!("Using local filesystem storage: {}", path.display());
+                let local = LocalStorage::new(path.clone());
+                match resolver {
+                    Some(resolver) => Arc::new(local.with_resolver(resolver)),
+                    None => Arc::new(local),
+                }
+            }
+            None => {
+                let aws_config =
+                    aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+                let (client, credentials) = credentials::s3_client(&aws_config, profile.s3);
+                let mut s3 = S3Storage::new(client, profile.bucket());
+                if let Some(credentials) = credentials {
+                    s3 = s3.with_credentials(credentials);
+                }
+                if let Some(prefix) = &profile.prefix {
+                    s3 = s3.with_prefix(prefix);
+                }
+                if let Some(resolver) = resolver {
+                    s3 = s3.with_resolver(resolver);
+                }
+
+                info!(
+                    "Using S3 bucket: {} (prefix: {}, {} endpoint)",
+                    profile.bucket(),
+                    profile.prefix.as_deref().unwrap_or(s3::DEFAULT_S3_PREFIX),
+                    profile.s3
+                );
+                Arc::new(s3)
+            }
+        };
+        let storage = Self::from_backend(backend, profile.cache_capacity.unwrap_or(0));
+        let storage = match profile.cache_max_bytes() {
+            Some(max) => storage.with_cache_max_bytes(max),
+            None => storage,
+        };
+
+        let storage = match &profile.write_limit {
+            Some(settings) => {
+                info!(
+                    "Limiting storage writes to {}/s (burst {})",
+                    settings.ops_per_sec, settings.burst
+                );
+                storage.with_write_throttle(Arc::new(settings.throttle()))
+            }
+            None => storage,
+        };
+
+        let storage = match &profile.fetch_limit {
+            Some(settings) => {
+                info!(
+                    "Limiting layer fetches to {} per request ({} in total)",
+                    settings.per_request,
+                    settings
+                        .global
+                        .map_or_else(|| "unbounded".to_string(), |max| max.to_string())
+                );
+                storage.with_fetch_limit(Arc::new(settings.limit()))
+            }
+            None => storage,
+        };
+
+        let storage = match profile.cache_ttl() {
+            Some(ttl) => {
+                info!("Expiring cached composites after {}s", ttl.as_secs());
+                storage.with_cache_ttl(ttl)
+            }
+            None => storage,
+        };
+
+        // Fetch popular plates and layers once; they expire with composites,
+        // so an asset update shows up in renders after the TTL
+        let storage = match profile.layer_memory_bytes() {
+            Some(max) => storage.with_layer_memory(max, profile.layer_memory_ttl()),
+            None => storage,
+        };
+
+        // Serve cache hits from local disk before going to the backend
+        let storage = match &profile.disk_cache {
+            Some(settings) => {
+                info!(
+                    "Using disk composite cache: {} ({} MB)",
+                    settings.dir.display(),
+                    settings.max_mb
+                );
+                storage.with_disk_cache(DiskLayerCache::new(&settings.dir, settings.max_bytes()))
+            }
+            None => storage,
+        };
+
+        // Allow layers from partner CDNs when hosts are configured
+        let storage = match profile.remote_layer_fetcher()? {
+            Some(fetcher) => {
+                info!("Remote layers enabled");
+                storage.with_remote_layers(fetcher)
+            }
+            None => storage,
+        };
+
+        // Reject renders of assets the catalog doesn't list
+        let storage = match profile.catalog.as_deref() {
+            Some(config::CATALOG_FROM_STORAGE) => {
+                let catalog = storage
+                    .fetch_catalog()
+                    .await?
+                    .context("Catalog manifest not found in storage")?;
+                info!("Using catalog from storage");
+                storage.with_catalog(catalog)
+            }
+            Some(path) => {
+                info!("Using catalog: {}", path);
+                storage.with_catalog(Catalog::load(Path::new(path))?)
+            }
+            None => storage,
+        };
+
+        // Block or watermark licensed artwork where it's restricted; licenses
+        // that don't parse fail the startup rather than being ignored
+        Ok(match storage.fetch_licenses().await? {
+            Some(licenses) => {
+                info!("Using licenses of {} SKUs", licenses.len());
+                storage.with_licenses(licenses)
+            }
+            None => storage,
+        })
+    }
+
+    /// Allow layers given as absolute URLs, fetched through the remote fetcher
+    pub fn with_remote_layers(mut self, fetcher: RemoteLayerFetcher) -> Self {
+        self.remote = Some(Arc::new(fetcher));
+        self
+    }
 
-        Self { backend, cache }
+    /// Keep fetched plates and layers in a persistent disk cache
+    /// Useful for repeated CLI runs against S3 or a CDN
+    /// Entries are namespaced by the backend's location, so one directory can
+    /// be shared between profiles.
+    pub fn with_layer_cache(mut self, cache: DiskLayerCache) -> Self {
+        let cache = cache.with_namespace(self.backend.location());
+        self.layer_cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Keep fetched plates and layers in memory, up to `max_bytes` and for
+    /// `ttl`, so popular garments aren't fetched again on every composite
+    /// cache miss
+    /// Renders that bypass the composite cache bypass this one too.
+    pub fn with_layer_memory(mut self, max_bytes: usize, ttl: Duration) -> Self {
+        let memory = TieredCache::new()
+            .with_max_bytes(max_bytes, Bytes::len)
+            .with_ttl(ttl);
+        self.layer_memory = Some(Arc::new(memory));
+        self
+    }
+
+    /// Hits, misses and size of the memory layer cache, if enabled
+    pub fn layer_memory_stats(&self) -> Option<TieredStats> {
+        self.layer_memory.as_ref().map(|memory| memory.stats())
+    }
+
+    /// Keep composites in a disk tier between the memory cache and the backend
+    /// Replaces the composite cache, so call it before rendering anything
+    pub fn with_disk_cache(mut self, cache: DiskLayerCache) -> Self {
+        let cache = cache.with_namespace(self.backend.location());
+        self.cache = Arc::new(self.cache.on_backend(self.backend.clone()).with_disk(cache));
+        self
+    }
+
+    /// Bound the memory composite cache by bytes rather than entries
+    /// Replaces the composite cache, so call it before rendering anything
+    pub fn with_cache_max_bytes(mut self, max: usize) -> Self {
+        self.cache = Arc::new(
+            self.cache
+                .on_backend(self.backend.clone())
+                .with_max_bytes(max),
+        );
+        self
+    }
+
+    /// Stop serving cached composites `ttl` after they were rendered, in every
+    /// tier, so composites of updated assets are rendered again
+    /// Replaces the composite cache, so call it before rendering anything
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache = Arc::new(self.cache.on_backend(self.backend.clone()).with_ttl(ttl));
+        self
+    }
+
+    /// Rate-limit writes to the backend (composites, JSON, locks), e.g. for
+    /// bulk prerenders that would go past S3's PUT rate
+    /// Share one throttle between services that write to the same prefix
+    pub fn with_write_throttle(mut self, throttle: Arc<WriteThrottle>) -> Self {
+        self.backend = Arc::new(ThrottledBackend::new(self.backend.clone(), throttle.clone()));
+        self.cache = Arc::new(self.cache.on_backend(self.backend.clone()));
+        self.throttle = Some(throttle);
+        self
+    }
+
+    /// Writes the throttle has let through so far, if writes are throttled
+    pub fn write_throttle_stats(&self) -> Option<ThrottleStats> {
+        self.throttle.as_ref().map(|throttle| throttle.stats())
+    }
+
+    /// Cap the layer fetches in flight, per request and across the service
+    /// (default: 8 per request, no global cap)
+    /// Share one limit between services that read from the same bucket
+    pub fn with_fetch_limit(mut self, limit: Arc<FetchLimit>) -> Self {
+        self.fetch_limit = limit;
+        self
+    }
+
+    /// Source fetches the fetch limit has let through so far
+    pub fn fetch_limit_stats(&self) -> FetchLimitStats {
+        self.fetch_limit.stats()
+    }
+
+    /// Fetch each plate and layer at most once, and decode identical bytes once,
+    /// for the life of the service
+    /// Concurrent renders (e.g. several views of one outfit) share the work;
+    /// nothing is evicted, so only use this for short-lived processes
+    pub fn with_shared_fetches(mut self) -> Self {
+        self.shared = Some(Arc::default());
+        self
+    }
+
+    /// What renders shared so far, if shared fetches are enabled
+    pub fn sharing_stats(&self) -> Option<SharingStats> {
+        self.shared.as_ref().map(|shared| shared.stats())
+    }
+
+    /// Oversized layers renders downscaled so far, for reporting to the asset team
+    pub fn oversized_layers(&self) -> Vec<OversizedLayer> {
+        self.oversized
+            .as_ref()
+            .map(|oversized| oversized.layers())
+            .unwrap_or_default()
+    }
+
+    /// Check renders against a catalog before fetching anything
+    pub fn with_catalog(mut self, catalog: Catalog) -> Self {
+        self.catalog = Some(Arc::new(catalog));
+        self
+    }
+
+    pub fn catalog(&self) -> Option<&Catalog> {
+        self.catalog.as_deref()
+    }
+
+    /// Catalog placement of each normalized layer (all `None` without a catalog)
+    pub fn patch_placements(
+        &self,
+        normalized: &[LayerParam],
+        view: View,
+    ) -> Vec<Option<Placement>> {
+        match &self.catalog {
+            Some(catalog) => catalog.patch_placements(normalized, view),
+            None => vec![None; normalized.len()],
+        }
+    }
+
+    /// Fetch the catalog manifest kept with the cached JSON (`cache/catalog.json`)
+    pub async fn fetch_catalog(&self) -> Result<Option<Catalog>> {
+        match self.fetch_cached_json(CATALOG_KEY).await? {
+            Some(json) => Ok(Some(Catalog::from_json(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Check renders against the licenses of their layers, blocking or
+    /// watermarking restricted composites
+    pub fn with_licenses(mut self, licenses: Licenses) -> Self {
+        self.licenses = Some(Arc::new(licenses));
+        self
+    }
+
+    pub fn licenses(&self) -> Option<&Licenses> {
+        self.licenses.as_deref()
+    }
+
+    /// Fetch the licenses kept with the cached JSON (`cache/licenses.json`)
+    pub async fn fetch_licenses(&self) -> Result<Option<Licenses>> {
+        match self.fetch_cached_json(LICENSES_KEY).await? {
+            Some(json) => Ok(Some(Licenses::from_json(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The built-in and catalog presets, and those of the presets file at
+    /// `source` (a JSON/TOML path, or "storage" for `cache/presets.json`)
+    /// replacing them by name
+    pub async fn load_presets(&self, source: Option<&str>) -> Result<Presets> {
+        let presets = Presets::for_catalog(self.catalog());
+        let file = match source {
+            Some(config::PRESETS_FROM_STORAGE) => {
+                match self.fetch_cached_json(PRESETS_KEY).await? {
+                    Some(json) => Presets::from_json(&json)?,
+                    None => return Ok(presets),
+                }
+            }
+            Some(path) => Presets::load(Path::new(path))?,
+            None => return Ok(presets),
+        };
+        Ok(presets.merge(&file))
+    }
+
+    /// Display names of SKUs from the cached products JSON, if there is one
+    pub async fn fetch_display_names(&self) -> Result<Option<DisplayNames>> {
+        match self.fetch_cached_json(PRODUCTS_KEY).await? {
+            Some(json) => Ok(Some(DisplayNames::from_products_json(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Disk usage of the layer cache, if enabled
+    pub async fn layer_cache_stats(&self) -> Result<Option<DiskCacheStats>> {
+        match &self.layer_cache {
+            Some(cache) => cache.stats().await.map(Some),
+            None => Ok(None),
+        }
     }
 
     /// Legacy constructor for backward compatibility
@@ -117,39 +988,262 @@ impl StorageService {
 
     /// Fetch the base plate image
     pub async fn fetch_base_plate(&self, view: View) -> Result<Bytes> {
-        let plate_value = view.plate_value();
-
-        self.backend
-            .fetch_layer("plate", plate_value, view, "jpg")
-            .await?
-            .context("Base plate not found")
+        self.fetch_plate(view, view.plate_value()).await
     }
 
-    /// Fetch multiple layers in parallel
+    /// Fetch multiple layers in parallel, using each category's file extension
+    /// Params reading the same source (e.g. duplicates, or the same layer with
+    /// another z-order) share one fetch
+    /// Remote layers fail the fetch if remote layers are disabled or the URL is rejected
     pub async fn fetch_layers(
         &self,
         params: &[LayerParam],
         view: View,
     ) -> Result<Vec<Option<Bytes>>> {
-        let futures = params.iter().map(|param| {
-            let backend = self.backend.clone();
-            let category = param.category.clone();
-            let sku = param.sku.as_str().to_string();
+        let registry = CategoryRegistry::global();
+        let mut keys: Vec<String> = Vec::new();
+        let mut distinct: Vec<(&LayerParam, &str)> = Vec::new();
+        let slots: Vec<usize> = params
+            .iter()
+            .map(|param| {
+                let extension = registry.extension(&param.category);
+                let key = source_key(param, view, extension);
+                keys.iter()
+                    .position(|seen| *seen == key)
+                    .unwrap_or_else(|| {
+                        keys.push(key);
+                        distinct.push((param, extension));
+                        distinct.len() - 1
+                    })
+            })
+            .collect();
 
-            async move { backend.fetch_layer(&category, &sku, view, "png").await }
-        });
+        let futures: Vec<_> = distinct
+            .into_iter()
+            .map(|(param, extension)| self.fetch_layer_or_svg(param, view, extension))
+            .collect();
+        let fetched: Vec<_> = stream::iter(futures)
+            .buffered(self.fetch_limit.per_request())
+            .try_collect()
+            .await?;
+        Ok(slots
+            .into_iter()
+            .map(|slot| fetched[slot].clone())
+            .collect())
+    }
 
-        try_join_all(futures).await
+    /// Fetch a layer, or else the SVG of a patch stored as one (see [`svg_fallback`])
+    async fn fetch_layer_or_svg(
+        &self,
+        param: &LayerParam,
+        view: View,
+        extension: &str,
+    ) -> Result<Option<Bytes>> {
+        match self.fetch_layer(param, view, extension).await? {
+            None if svg_fallback(param, extension) => {
+                self.fetch_layer(param, view, SVG_EXTENSION).await
+            }
+            data => Ok(data),
+        }
+    }
+
+    /// Fetch one layer from storage or its remote URL, through the disk cache if enabled
+    pub(crate) async fn fetch_layer(
+        &self,
+        param: &LayerParam,
+        view: View,
+        extension: &str,
+    ) -> Result<Option<Bytes>> {
+        let cache_key = source_key(param, view, extension);
+
+        match &self.shared {
+            Some(shared) => {
+                shared
+                    .fetch(&cache_key, || {
+                        self.load_layer(param, view, extension, &cache_key)
+                    })
+                    .await
+            }
+            None => self.load_layer(param, view, extension, &cache_key).await,
+        }
+    }
+
+    async fn load_layer(
+        &self,
+        param: &LayerParam,
+        view: View,
+        extension: &str,
+        cache_key: &str,
+    ) -> Result<Option<Bytes>> {
+        if let Some(memory) = &self.layer_memory {
+            if let Some(data) = memory.get(cache_key) {
+                return Ok(Some(data));
+            }
+        }
+        let data = self
+            .load_layer_uncached(param, view, extension, cache_key)
+            .await?;
+        if let (Some(memory), Some(data)) = (&self.layer_memory, &data) {
+            memory.insert(cache_key.to_string(), data.clone());
+        }
+        Ok(data)
+    }
+
+    /// [`Self::load_layer`] past the memory layer cache: from the disk layer
+    /// cache if enabled, or from the source
+    async fn load_layer_uncached(
+        &self,
+        param: &LayerParam,
+        view: View,
+        extension: &str,
+        cache_key: &str,
+    ) -> Result<Option<Bytes>> {
+        if let Some(cache) = &self.layer_cache {
+            if let Some(data) = cache.get(cache_key).await {
+                return Ok(Some(data));
+            }
+        }
+
+        let slot = self.fetch_limit.acquire().await;
+        let data = match &param.url {
+            Some(url) => {
+                let remote = self
+                    .remote
+                    .as_ref()
+                    .context("Remote layers are not enabled")?;
+                Some(remote.fetch(url).await?)
+            }
+            None => {
+                self.backend
+                    .fetch_layer(&param.category, param.sku.as_str(), view, extension)
+                    .await?
+            }
+        };
+
+        drop(slot);
+
+        if let (Some(cache), Some(data)) = (&self.layer_cache, &data) {
+            if let Err(e) = cache.put(cache_key, data).await {
+                warn!("Failed to write disk layer cache: {:#}", e);
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Fetch every asset a recipe reads, ready for [`birl_core::render`]
+    /// Missing assets are left out
+    pub async fn fetch_sources(&self, recipe: &Recipe, view: View) -> Result<Assets> {
+        self.fetch_sources_on(recipe, view, view.plate_value(), recipe.layer_filter())
+            .await
+    }
+
+    /// Like [`Self::fetch_sources`], with the recipe's plate asset read from
+    /// `plate` and oversized layers downscaled with `layer_filter`
+    async fn fetch_sources_on(
+        &self,
+        recipe: &Recipe,
+        view: View,
+        plate: &str,
+        layer_filter: ResizeFilter,
+    ) -> Result<Assets> {
+        let assets = recipe.assets();
+        let layers: Vec<LayerParam> = assets
+            .iter()
+            .filter_map(|asset| match asset {
+                Asset::Layer(param) => Some(param.clone()),
+                Asset::Plate => None,
+            })
+            .collect();
+        let reads_plate = assets.contains(&&Asset::Plate);
+        let (plate, layers) = futures::try_join!(
+            async {
+                if reads_plate {
+                    self.fetch_plate(view, plate).await.map(Some)
+                } else {
+                    Ok(None)
+                }
+            },
+            self.fetch_layers(&layers, view)
+        )?;
+
+        let mut layers = layers.into_iter();
+        let mut fetched: Vec<(&Asset, Bytes)> = Vec::with_capacity(assets.len());
+        for asset in assets {
+            let data = match asset {
+                Asset::Plate => plate.clone(),
+                Asset::Layer(_) => layers.next().flatten(),
+            };
+            match data {
+                Some(data) => fetched.push((asset, data)),
+                None => debug!("Missing source: {}", asset),
+            }
+        }
+
+        // Layers far larger than the plate are swapped for copies downscaled once
+        let plate_size = fetched
+            .iter()
+            .find(|(asset, _)| **asset == Asset::Plate)
+            .and_then(|(_, data)| birl_core::oversize::dimensions(data));
+        if let (Some(oversized), Some(plate_size)) = (&self.oversized, plate_size) {
+            let fitted = fetched.into_iter().map(|(asset, data)| async move {
+                let data = match asset {
+                    Asset::Layer(param) => {
+                        let registry = CategoryRegistry::global();
+                        let key = source_key(param, view, registry.extension(&param.category));
+                        oversized
+                            .fit(self.backend.as_ref(), &key, data, plate_size, layer_filter)
+                            .await
+                    }
+                    Asset::Plate => data,
+                };
+                (asset, data)
+            });
+            fetched = futures::future::join_all(fitted).await;
+        }
+
+        let mut assets = Assets::new();
+        for (asset, data) in fetched {
+            assets.insert(asset, data);
+        }
+        Ok(match &self.shared {
+            Some(shared) => assets.with_decode_cache(shared.decoded.clone()),
+            None => assets,
+        })
     }
 
     /// Get a cached composite
-    pub async fn get_cached_composite(&self, cache_key: &str) -> Result<Option<Bytes>> {
-        self.cache.get(cache_key).await
+    pub async fn get_cached_composite(
+        &self,
+        cache_key: &str,
+        format: OutputFormat,
+    ) -> Result<Option<Bytes>> {
+        self.cache.get(cache_key, format).await
     }
 
     /// Save a composite to cache
-    pub async fn save_composite(&self, cache_key: &str, data: Bytes) -> Result<()> {
-        self.cache.put(cache_key, data).await
+    pub async fn save_composite(
+        &self,
+        cache_key: &str,
+        data: Bytes,
+        format: OutputFormat,
+    ) -> Result<()> {
+        self.cache.put(cache_key, data, format).await
+    }
+
+    /// Where a cached composite is stored, if the backend can address it
+    pub fn cache_url(&self, cache_key: &str, format: OutputFormat) -> Option<String> {
+        self.backend.cache_url(cache_key, format)
+    }
+
+    /// Paths of all plates and layers in storage, if the backend can list them
+    pub async fn list_assets(&self) -> Result<Vec<String>> {
+        self.backend.list_assets().await
+    }
+
+    /// Like [`Self::list_assets`], a page at a time for large buckets
+    pub fn asset_pages(&self) -> PageStream<'_, String> {
+        self.backend.asset_pages()
     }
 
     /// Fetch cached JSON data (e.g., product list)
@@ -157,6 +1251,17 @@ impl StorageService {
         self.backend.fetch_cached_json(key).await
     }
 
+    /// Save JSON data for [`Self::fetch_cached_json`]
+    pub async fn save_cached_json(&self, key: &str, json: &str) -> Result<()> {
+        self.backend.save_cached_json(key, json).await
+    }
+
+    /// When the backend's credentials expire, refreshing them first if
+    /// they're about to; `None` if they don't expire
+    pub async fn credentials_expiry(&self) -> Result<Option<SystemTime>> {
+        self.backend.credentials_expiry().await
+    }
+
     /// Get cache statistics
     pub async fn cache_stats(&self) -> CacheStats {
         self.cache.stats().await
@@ -166,48 +1271,892 @@ impl StorageService {
     pub async fn clear_cache(&self) {
         self.cache.clear_memory().await;
     }
-}
 
-/// Fetch layers with logging and filtering
-pub async fn fetch_and_filter_layers(
-    storage: &StorageService,
-    params: &[LayerParam],
-    view: View,
-) -> Result<(Vec<Bytes>, usize, usize)> {
-    let layers = storage.fetch_layers(params, view).await?;
-
-    let requested_count = params.len();
-    let found_layers: Vec<Bytes> = layers.into_iter().flatten().collect();
-    let found_count = found_layers.len();
-
-    if found_count < requested_count {
-        warn!(
-            "Found {}/{} requested layers for view {}",
-            found_count,
-            requested_count,
-            view.as_str()
-        );
+    /// Cache key of the composite [`Self::render`] would produce, without rendering
+    pub fn cache_key(
+        &self,
+        params: &[LayerParam],
+        view: View,
+        options: &CompositeOptions,
+    ) -> String {
+        let normalized = LayerNormalizer::new(view, params).normalize_all(params);
+        let placements = self.patch_placements(&normalized, view);
+        let offsets = LayerOffsets::global().for_layers(&normalized, &placements, view);
+        let orientations = Orientations::global().for_layers(&normalized, &placements, view);
+        let decision = self.license_decision(&normalized, options);
+        composite_key(
+            &normalized,
+            &placements,
+            &offsets,
+            &orientations,
+            view,
+            plate_for(params, view, options),
+            &plan::licensed_options(options, decision.as_ref()),
+        )
+    }
+
+    /// Run the full pipeline for one outfit: normalize, check cache, fetch, compose, cache
+    /// With no params and default options the base plate is returned as-is and nothing is cached
+    pub async fn render(
+        &self,
+        params: &[LayerParam],
+        view: View,
+        options: &CompositeOptions,
+        bypass_cache: bool,
+    ) -> Result<RenderedComposite> {
+        self.prepare(params, view, options, bypass_cache)
+            .await?
+            .encode()
+            .await
+    }
+
+    /// Like [`Self::render`], reading and writing no cache at all: not the
+    /// composite cache (memory or backend), the disk layer cache, or shared
+    /// fetches
+    /// For renders that must not be persisted, and for deterministic tests
+    pub async fn render_uncached(
+        &self,
+        params: &[LayerParam],
+        view: View,
+        options: &CompositeOptions,
+    ) -> Result<RenderedComposite> {
+        self.prepare_uncached(params, view, options)
+            .await?
+            .encode()
+            .await
+    }
+
+    /// [`Self::prepare`] for [`Self::render_uncached`]
+    pub async fn prepare_uncached(
+        &self,
+        params: &[LayerParam],
+        view: View,
+        options: &CompositeOptions,
+    ) -> Result<PreparedComposite> {
+        let prepared = self
+            .without_layer_caches()
+            .prepare(params, view, options, true)
+            .await?;
+        Ok(prepared.uncached())
+    }
+
+    /// The same storage, sharing fetches and decodes until it's dropped
+    /// For renders of one request that read the same layers
+    fn with_request_fetches(&self) -> Self {
+        Self {
+            shared: Some(Arc::default()),
+            ..self.clone()
+        }
+    }
+
+    /// The same storage, reading plates and layers past the memory layer
+    /// cache
+    fn without_layer_memory(&self) -> Self {
+        Self {
+            layer_memory: None,
+            ..self.clone()
+        }
+    }
+
+    /// The same storage, fetching every plate and layer from its source
+    fn without_layer_caches(&self) -> Self {
+        Self {
+            layer_cache: None,
+            layer_memory: None,
+            shared: None,
+            oversized: None,
+            ..self.clone()
+        }
+    }
+
+    /// Render an outfit together with each of its items alone on the plate
+    /// Items the view doesn't show are left out; a `plate/<sku>` param applies to every render
+    pub async fn render_bundle(
+        &self,
+        params: &[LayerParam],
+        view: View,
+        options: &CompositeOptions,
+        bypass_cache: bool,
+    ) -> Result<RenderedBundle> {
+        // Items render with the SKUs their `category/*` params get in the outfit
+        let params = &Wildcards::global().apply_all(params, view);
+        // and share the outfit's fetches and decodes
+        let scoped;
+        let service = match self.shared {
+            Some(_) => self,
+            None => {
+                scoped = self.with_request_fetches();
+                &scoped
+            }
+        };
+        let (plates, items): (Vec<LayerParam>, Vec<LayerParam>) = params
+            .iter()
+            .cloned()
+            .partition(|param| param.category == PLATE_CATEGORY);
+        let items: Vec<LayerParam> = items
+            .into_iter()
+            .filter(|item| {
+                let alone = std::slice::from_ref(item);
+                !LayerNormalizer::new(view, alone)
+                    .normalize_all(alone)
+                    .is_empty()
+            })
+            .collect();
 
-        // Log which layers were missing
-        for (i, param) in params.iter().enumerate() {
-            if i >= found_layers.len() {
-                debug!("Missing layer: {}/{}", param.category, param.sku.as_str());
+        let renders = items.iter().map(|item| {
+            let mut alone = plates.clone();
+            alone.push(item.clone());
+            async move {
+                service
+                    .render(&alone, view, options, bypass_cache)
+                    .await
+                    .with_context(|| format!("Failed to render {}/{}", item.category, item.sku))
             }
+        });
+        let (outfit, rendered) = futures::try_join!(
+            service.render(params, view, options, bypass_cache),
+            try_join_all(renders)
+        )?;
+
+        Ok(RenderedBundle {
+            outfit,
+            items: items.into_iter().zip(rendered).collect(),
+        })
+    }
+
+    /// Fetch and execute a caller-supplied recipe shown in `region`
+    /// Unlike [`Self::render`], every source must exist and nothing is cached;
+    /// sources a render couldn't read (see [`Recipe::check_sources`]) and
+    /// licensed layers the region may not show unchanged are refused
+    pub async fn render_recipe(
+        &self,
+        recipe: &Recipe,
+        view: View,
+        region: Option<&str>,
+    ) -> Result<Bytes> {
+        recipe.validate()?;
+        recipe.check_sources(&CategoryRegistry::global(), &Limits::global())?;
+        let layers: Vec<LayerParam> = recipe
+            .assets()
+            .into_iter()
+            .filter_map(|asset| match asset {
+                Asset::Layer(param) => Some(param.clone()),
+                Asset::Plate => None,
+            })
+            .collect();
+        self.check_recipe_licenses(&layers, view, region)?;
+        let assets = self.fetch_sources(recipe, view).await?;
+
+        let recipe = recipe.clone();
+        let rendered = tokio::task::spawn_blocking(move || birl_core::render(&recipe, &assets))
+            .await
+            .context("Recipe render panicked")??;
+        Ok(rendered.data)
+    }
+
+    /// Run the pipeline up to (not including) the encode
+    /// Use [`PreparedComposite::finalize_into`] to stream the output
+    pub async fn prepare(
+        &self,
+        params: &[LayerParam],
+        view: View,
+        options: &CompositeOptions,
+        bypass_cache: bool,
+    ) -> Result<PreparedComposite> {
+        let plan = self.plan(params, view, options)?;
+        self.prepare_plan(&plan, bypass_cache).await
+    }
+
+    /// Render a plan made earlier, possibly by another process, as planned:
+    /// its layers aren't normalized or checked against the catalog again
+    pub async fn render_plan(
+        &self,
+        plan: &CompositionPlan,
+        bypass_cache: bool,
+    ) -> Result<RenderedComposite> {
+        self.prepare_plan(plan, bypass_cache).await?.encode().await
+    }
+
+    /// [`Self::prepare`] for a plan made earlier
+    pub async fn prepare_plan(
+        &self,
+        plan: &CompositionPlan,
+        bypass_cache: bool,
+    ) -> Result<PreparedComposite> {
+        let view = plan.view;
+        let options = &plan.options;
+        for conflict in &plan.conflicts {
+            debug!("{}", conflict);
         }
+        for violation in &plan.violations {
+            debug!("{}", violation);
+        }
+        for dropped in &plan.dropped {
+            debug!("{}", dropped);
+        }
+        let normalized_params = plan.params();
+        let placements = plan.placements();
+        let offsets = plan.offsets();
+        let orientations = plan.orientations();
+        let plate = plan.plate.layer.sku.as_str();
+        let cache_key = plan.cache_key.clone();
+        let format = options.format;
+
+        let prepared = |content, requested_layers, found_layers, sanity_issue| PreparedComposite {
+            cache_key: cache_key.clone(),
+            format,
+            requested_layers,
+            found_layers,
+            sanity_issue,
+            conflicts: plan.conflicts.clone(),
+            violations: plan.violations.clone(),
+            dropped: plan.dropped.clone(),
+            missing: Vec::new(),
+            attributions: plan.attributions.clone(),
+            options: options.clone(),
+            content,
+            cache: self.cache.clone(),
+            license: self.license_record(&normalized_params, options),
+        };
+
+        if plan.is_plate_only() && options.is_passthrough() {
+            let content = PreparedContent::Encoded {
+                data: self.fetch_plate(view, plate).await?,
+                cached: false,
+            };
+            return Ok(prepared(content, 0, 0, None));
+        }
+
+        // Check cache (unless bypassing)
+        if !bypass_cache {
+            if let Some(cached_data) = self.get_cached_composite(&cache_key, format).await? {
+                info!("Serving cached image: {}", cache_key);
+                let content = PreparedContent::Encoded {
+                    data: cached_data,
+                    cached: true,
+                };
+                let count = normalized_params.len();
+                return Ok(prepared(content, count, count, None));
+            }
+        }
+
+        let masks = patch_mask_categories(&normalized_params);
+        let recipe = Recipe::compile_placed(
+            &normalized_params,
+            &masks,
+            &placements,
+            &offsets,
+            &orientations,
+            options,
+        );
+        // Layers kept in memory may predate a re-upload the caller is after
+        let assets = match (&self.layer_memory, bypass_cache) {
+            (Some(_), true) => {
+                self.without_layer_memory()
+                    .fetch_sources_on(&recipe, view, plate, options.layer_filter)
+                    .await?
+            }
+            _ => {
+                self.fetch_sources_on(&recipe, view, plate, options.layer_filter)
+                    .await?
+            }
+        };
+        let assets = assets.with_missing(MissingSources::Skip);
+
+        let requested_count = normalized_params.len();
+        let not_found: Vec<LayerParam> = normalized_params
+            .iter()
+            .filter(|param| !assets.contains(&Asset::Layer((*param).clone())))
+            .cloned()
+            .collect();
+        let found_count = requested_count - not_found.len();
+        let missing = self.missing_layers(&not_found, view).await;
+        if !missing.is_empty() {
+            let missing: Vec<String> = missing.iter().map(ToString::to_string).collect();
+            warn!(
+                "Found {}/{} requested layers for view {}, missing {}",
+                found_count,
+                requested_count,
+                view.as_str(),
+                missing.join(", ")
+            );
+        }
+
+        let start = std::time::Instant::now();
+        let compositor = birl_core::render::compose(&recipe, &assets)?;
+
+        let sanity_issue = compositor.check_sanity();
+        if let Some(issue) = sanity_issue {
+            warn!("Composite {} failed sanity check: {}", cache_key, issue);
+        }
+        info!("Image composition took {:?}", start.elapsed());
+
+        // Only cache if all requested images were found and the output looks sane
+        let content = PreparedContent::Composed {
+            compositor,
+            cacheable: requested_count == found_count && sanity_issue.is_none(),
+        };
+
+        let mut prepared = prepared(content, requested_count, found_count, sanity_issue);
+        prepared.missing = missing;
+        Ok(prepared)
     }
+}
+
+/// Where a layer is fetched from: its storage path or remote URL
+fn source_key(param: &LayerParam, view: View, extension: &str) -> String {
+    match &param.url {
+        Some(url) => url.clone(),
+        None => format!("{}/{}/{}.{}", view, param.category, param.sku, extension),
+    }
+}
 
-    Ok((found_layers, requested_count, found_count))
+/// Whether a layer missing in its configured format is looked for as SVG:
+/// stored patches, with the `svg` feature
+pub(crate) fn svg_fallback(param: &LayerParam, extension: &str) -> bool {
+    cfg!(feature = "svg")
+        && param.url.is_none()
+        && extension != SVG_EXTENSION
+        && CategoryRegistry::global().is_patch(&param.category)
+}
+
+/// Plate of a render: an explicit plate option wins over a `plate/<sku>` param
+fn plate_for<'a>(params: &'a [LayerParam], view: View, options: &'a CompositeOptions) -> &'a str {
+    match (&options.plate, requested_plate(params)) {
+        (None, Some(plate)) => plate,
+        _ => options.plate_for(view),
+    }
+}
+
+/// Cache key of a composite
+/// Placements, offsets and orientations come from the catalog and config
+/// rather than the request, so they join the variant and changing one doesn't
+/// serve renders from before it
+fn composite_key(
+    normalized: &[LayerParam],
+    placements: &[Option<Placement>],
+    offsets: &[Option<Offset>],
+    orientations: &[Option<Orientation>],
+    view: View,
+    plate: &str,
+    options: &CompositeOptions,
+) -> String {
+    let mut variant = options.cache_variant();
+    for (param, placement) in normalized.iter().zip(placements) {
+        if let Some(placement) = placement {
+            variant.push_str(&format!("_{}@{}", param.category, placement));
+        }
+    }
+    for (param, offset) in normalized.iter().zip(offsets) {
+        if let Some(offset) = offset {
+            variant.push_str(&format!("_{}~{}", param.category, offset));
+        }
+    }
+    for (param, orientation) in normalized.iter().zip(orientations) {
+        if let Some(orientation) = orientation {
+            variant.push_str(&format!("_{}^{}", param.category, orientation));
+        }
+    }
+    variant_cache_key(&generate_cache_key(normalized, view, plate), &variant)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use birl_core::{parse_params, BirlError};
+
+    /// The crate's public surface is deliberate: changing it means updating
+    /// `public-api.txt` (rerun with `BIRL_BLESS=1` to rewrite it)
+    #[test]
+    fn test_public_api() {
+        birl_public_api::check(env!("CARGO_MANIFEST_DIR"));
+    }
+
+    #[tokio::test]
+    async fn test_prepare_streams_and_caches() {
+        let service = StorageService::new_demo(10);
+        let params = parse_params("hoodies/hoodie-black,pants/cargo-black");
+
+        let prepared = service
+            .prepare(&params, View::Front, &CompositeOptions::default(), false)
+            .await
+            .unwrap();
+        assert!(!prepared.cached());
+        assert!(prepared.encoded().is_none());
+
+        let cache_key = prepared.cache_key.clone();
+        let mut streamed = Vec::new();
+        prepared.finalize_into(&mut streamed).await.unwrap();
+
+        let cached = service
+            .get_cached_composite(&cache_key, OutputFormat::Jpeg)
+            .await
+            .unwrap();
+        assert_eq!(cached.as_deref(), Some(streamed.as_slice()));
+
+        let rendered = service
+            .render(&params, View::Front, &CompositeOptions::default(), false)
+            .await
+            .unwrap();
+        assert!(rendered.cached);
+        assert_eq!(rendered.data, streamed);
+    }
+
+    #[tokio::test]
+    async fn test_render_uncached() {
+        let dir = std::env::temp_dir().join(format!("birl-uncached-{}", std::process::id()));
+        let service = StorageService::new_demo(10)
+            .with_layer_cache(DiskLayerCache::new(&dir, 1 << 20))
+            .with_shared_fetches();
+        let params = parse_params("hoodies/hoodie-black,pants/cargo-black");
+        let options = CompositeOptions::default();
+
+        let rendered = service
+            .render_uncached(&params, View::Front, &options)
+            .await
+            .unwrap();
+        assert!(!rendered.cached);
+        let cached = service
+            .get_cached_composite(&rendered.cache_key, options.format)
+            .await
+            .unwrap();
+        assert!(cached.is_none());
+        assert_eq!(service.sharing_stats().unwrap().requests, 0);
+        assert_eq!(service.layer_cache_stats().await.unwrap().unwrap().blobs, 0);
+
+        // Nor does it read what a cached render left behind
+        let cached = service
+            .render(&params, View::Front, &options, false)
+            .await
+            .unwrap();
+        let prepared = service
+            .prepare_uncached(&params, View::Front, &options)
+            .await
+            .unwrap();
+        assert!(!prepared.cached());
+        assert_eq!(prepared.encode().await.unwrap().data, cached.data);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_catalog_rejects_unknown_layers() {
+        let catalog = Catalog::from_json(
+            r#"{"categories": {"hoodies": {"skus": ["hoodie-black"]}}}"#,
+        )
+        .unwrap();
+        let service = StorageService::new_demo(10).with_catalog(catalog);
+        let options = CompositeOptions::default();
+
+        let params = parse_params("hoodies/hoodie-black");
+        assert!(service.render(&params, View::Front, &options, false).await.is_ok());
+
+        let params = parse_params("hoodies/hoodie-black,hats/beanie-black");
+        let err = service
+            .render(&params, View::Front, &options, false)
+            .await
+            .unwrap_err();
+        let Ok(BirlError::NotInCatalog(err)) = err.downcast::<BirlError>() else {
+            panic!("expected a catalog error");
+        };
+        assert_eq!(
+            err.issues,
+            vec![birl_core::CatalogIssue::UnknownCategory("hats".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_load_presets() {
+        let catalog = Catalog::from_json(
+            r#"{"presets": {"winter": {"params": "hats/beanie-black"}}}"#,
+        )
+        .unwrap();
+        let service = StorageService::new_demo(10).with_catalog(catalog);
+        let builtin = Presets::builtin().len();
+
+        // Without a presets file, or before one is uploaded, the built-in and
+        // catalog presets
+        let presets = service.load_presets(None).await.unwrap();
+        assert_eq!(presets.len(), builtin + 1);
+        let presets = service.load_presets(Some("storage")).await.unwrap();
+        assert_eq!(presets.len(), builtin + 1);
+
+        service
+            .save_cached_json(
+                PRESETS_KEY,
+                r#"{"presets": {
+                    "winter": {"params": "hats/beanie-grey"},
+                    "summer": {"params": "tops/tee-white"}
+                }}"#,
+            )
+            .await
+            .unwrap();
+        let presets = service.load_presets(Some("storage")).await.unwrap();
+        assert_eq!(presets.len(), builtin + 2);
+        assert_eq!(
+            presets.params("winter").unwrap(),
+            [LayerParam::new("hats", "beanie-grey")]
+        );
+
+        assert!(service.load_presets(Some("missing.json")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_catalog_patch_placements() {
+        let catalog = Catalog::from_json(
+            r#"{
+                "categories": {
+                    "hoodies": {"skus": ["hoodie-black"]},
+                    "patches-left": {"skus": ["flag-patch-red"]}
+                },
+                "placements": {
+                    "hoodies": {"front": {"patches-left": {"x": 40, "y": 60, "width": 32}}}
+                }
+            }"#,
+        )
+        .unwrap();
+        let params = parse_params("hoodies/hoodie-black,patches-left/flag-patch-red");
+        let options = CompositeOptions::default();
+
+        let full_frame = StorageService::new_demo(10)
+            .render(&params, View::Front, &options, false)
+            .await
+            .unwrap();
+        let service = StorageService::new_demo(10).with_catalog(catalog);
+        let placed = service
+            .render(&params, View::Front, &options, false)
+            .await
+            .unwrap();
+        assert_ne!(placed.cache_key, full_frame.cache_key);
+        assert_eq!(
+            placed.cache_key,
+            service.cache_key(&params, View::Front, &options)
+        );
+        assert_ne!(placed.data, full_frame.data);
+    }
+
+    /// Demo assets, counting the fetches
+    #[derive(Default)]
+    struct CountingStorage {
+        demo: DemoStorage,
+        fetches: std::sync::atomic::AtomicUsize,
+        in_flight: std::sync::atomic::AtomicUsize,
+        most_in_flight: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl StorageBackend for CountingStorage {
+        async fn fetch_layer(
+            &self,
+            category: &str,
+            sku: &str,
+            view: View,
+            extension: &str,
+        ) -> Result<Option<Bytes>> {
+            use std::sync::atomic::Ordering::Relaxed;
+            self.fetches.fetch_add(1, Relaxed);
+            let in_flight = self.in_flight.fetch_add(1, Relaxed) + 1;
+            self.most_in_flight.fetch_max(in_flight, Relaxed);
+            tokio::time::sleep(Duration::from_millis(2)).await;
+            let data = self.demo.fetch_layer(category, sku, view, extension).await;
+            self.in_flight.fetch_sub(1, Relaxed);
+            data
+        }
+
+        async fn fetch_cached(&self, key: &str, format: OutputFormat) -> Result<Option<Bytes>> {
+            self.demo.fetch_cached(key, format).await
+        }
+
+        async fn save_to_cache(&self, key: &str, data: &[u8], format: OutputFormat) -> Result<()> {
+            self.demo.save_to_cache(key, data, format).await
+        }
+
+        async fn fetch_cached_json(&self, key: &str) -> Result<Option<String>> {
+            self.demo.fetch_cached_json(key).await
+        }
+    }
+
+    #[cfg(feature = "svg")]
+    #[tokio::test]
+    async fn test_svg_patch() {
+        use birl_core::demo::{synthetic_layer, DEMO_HEIGHT, DEMO_WIDTH};
+
+        let dir = std::env::temp_dir().join(format!("birl-svg-{}", std::process::id()));
+        for category in ["plate", "hoodies", "patches-left"] {
+            std::fs::create_dir_all(dir.join("front").join(category)).unwrap();
+        }
+        let plate = synthetic_layer("hats", "beanie-red").unwrap();
+        std::fs::write(dir.join("front/plate/cutout.png"), &plate).unwrap();
+        let hoodie = synthetic_layer("hoodies", "hoodie-black").unwrap();
+        std::fs::write(dir.join("front/hoodies/hoodie-black.png"), &hoodie).unwrap();
+        let svg = format!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {} {}">
+                <circle cx="50" cy="75" r="20" fill="#2040c0"/>
+            </svg>"##,
+            DEMO_WIDTH / 4,
+            DEMO_HEIGHT / 4
+        );
+        std::fs::write(dir.join("front/patches-left/flag-svg.svg"), svg).unwrap();
+
+        let service = StorageService::new_local(dir.clone(), 10);
+        let patch = LayerParam::new("patches-left", "flag-svg");
+        let fetched = service.fetch_layers(&[patch], View::Front).await.unwrap();
+        assert!(fetched[0].is_some());
+        let params = parse_params("plate/cutout,hoodies/hoodie-black,patches-left/flag-svg");
+        assert!(service
+            .validate_params(&params, View::Front)
+            .await
+            .is_complete());
+
+        // Rasterized at the plate's size, four times the SVG's own
+        let options = CompositeOptions::default().with_format(OutputFormat::Png);
+        let rendered = service
+            .render_uncached(&params, View::Front, &options)
+            .await
+            .unwrap();
+        assert_eq!(rendered.found_layers, 2);
+        let without = service
+            .render_uncached(&params[..2], View::Front, &options)
+            .await
+            .unwrap();
+        assert_ne!(rendered.data, without.data);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_request_fetches_each_source_once() {
+        let backend = Arc::new(CountingStorage::default());
+        let service = StorageService::from_backend(backend.clone(), 10);
+        let fetches = || backend.fetches.load(std::sync::atomic::Ordering::Relaxed);
+
+        let params = parse_params("hats/beanie-black,hats/beanie-black@z=3,hats/beanie-black");
+        let layers = service.fetch_layers(&params, View::Front).await.unwrap();
+        assert_eq!(layers.len(), 3);
+        assert!(layers
+            .iter()
+            .all(|layer| layer.is_some() && *layer == layers[0]));
+        assert_eq!(fetches(), 1);
+
+        // The outfit and each item read the plate and layers once between them
+        let params = parse_params("hoodies/hoodie-black,pants/cargo-black");
+        let bundle = service
+            .render_bundle(&params, View::Front, &CompositeOptions::default(), true)
+            .await
+            .unwrap();
+        assert_eq!(bundle.items.len(), 2);
+        assert_eq!(fetches(), 1 + 3);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_limit() {
+        use std::sync::atomic::Ordering::Relaxed;
+
+        let backend = Arc::new(CountingStorage::default());
+        let limit = Arc::new(FetchLimit::new(2, None));
+        let service = StorageService::from_backend(backend.clone(), 10).with_fetch_limit(limit);
+        let params = parse_params("hats/beanie-black,hoodies/hoodie-black,pants/cargo-black");
+        let layers = service.fetch_layers(&params, View::Front).await.unwrap();
+        assert_eq!(layers.len(), 3);
+        assert_eq!(backend.most_in_flight.load(Relaxed), 2);
+
+        // A global cap holds across concurrent requests
+        let backend = Arc::new(CountingStorage::default());
+        let limit = Arc::new(FetchLimit::new(8, Some(3)));
+        let service = StorageService::from_backend(backend.clone(), 10).with_fetch_limit(limit);
+        let fetch = |params: &'static str| {
+            let service = &service;
+            async move {
+                service
+                    .fetch_layers(&parse_params(params), View::Front)
+                    .await
+            }
+        };
+        let (first, second) = tokio::join!(
+            fetch("hats/beanie-black,hoodies/hoodie-black,pants/cargo-black"),
+            fetch("hats/beanie-red,hoodies/hoodie-white,pants/cargo-darkgreen"),
+        );
+        assert_eq!((first.unwrap().len(), second.unwrap().len()), (3, 3));
+        assert_eq!(backend.most_in_flight.load(Relaxed), 3);
+        let stats = service.fetch_limit_stats();
+        assert_eq!((stats.fetches, stats.queued), (6, 3));
+    }
+
+    #[tokio::test]
+    async fn test_layer_memory_across_renders() {
+        let backend = Arc::new(CountingStorage::default());
+        let service = StorageService::from_backend(backend.clone(), 10)
+            .with_layer_memory(1 << 20, Duration::from_secs(60));
+        let fetches = || backend.fetches.load(std::sync::atomic::Ordering::Relaxed);
+
+        let params = parse_params("hoodies/hoodie-black,pants/cargo-black");
+        let options = CompositeOptions::default();
+        let first = service
+            .render(&params, View::Front, &options, false)
+            .await
+            .unwrap();
+        assert_eq!(fetches(), 3);
+
+        // On a composite cache miss, another outfit only fetches its new layer
+        let params = parse_params("hoodies/hoodie-black,pants/cargo-darkgreen");
+        service
+            .render(&params, View::Front, &options, false)
+            .await
+            .unwrap();
+        assert_eq!(fetches(), 4);
+        let stats = service.layer_memory_stats().unwrap();
+        assert_eq!((stats.hits, stats.entries), (2, 4));
+
+        // Bypassing the composite cache reads every layer from the source again
+        let params = parse_params("hoodies/hoodie-black,pants/cargo-black");
+        let again = service
+            .render(&params, View::Front, &options, true)
+            .await
+            .unwrap();
+        assert_eq!(fetches(), 7);
+        assert_eq!(service.layer_memory_stats().unwrap().hits, 2);
+        assert_eq!(again.data, first.data);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_layers_are_downscaled_once() {
+        use birl_core::demo::{synthetic_layer, synthetic_plate};
+
+        let dir = std::env::temp_dir().join(format!("birl-oversized-{}", std::process::id()));
+        let plate = synthetic_plate(View::Front).unwrap();
+        let plate = birl_core::oversize::downscale(&plate, 100, 150, Default::default()).unwrap();
+        let layer = synthetic_layer("hoodies", "hoodie-black").unwrap();
+        std::fs::create_dir_all(dir.join("front/plate")).unwrap();
+        std::fs::create_dir_all(dir.join("front/hoodies")).unwrap();
+        std::fs::write(dir.join("front/plate/base-model-black.jpg"), &plate).unwrap();
+        std::fs::write(dir.join("front/hoodies/hoodie-black.png"), &layer).unwrap();
+
+        let params = parse_params("hoodies/hoodie-black");
+        let options = CompositeOptions::default();
+        let service = StorageService::new_local(dir.clone(), 10);
+        let first = service
+            .render(&params, View::Front, &options, true)
+            .await
+            .unwrap();
+
+        let derived: Vec<_> = std::fs::read_dir(dir.join("cache/derived/front/hoodies"))
+            .unwrap()
+            .collect();
+        assert_eq!(derived.len(), 1);
+        let oversized = service.oversized_layers();
+        assert_eq!(oversized.len(), 1);
+        assert_eq!(oversized[0].key, "front/hoodies/hoodie-black.png");
+        assert_eq!(oversized[0].oversize.to_string(), "400x600 on a 100x150 plate");
+
+        // A new service reads the stored copy
+        let service = StorageService::new_local(dir.clone(), 10);
+        let second = service
+            .render(&params, View::Front, &options, true)
+            .await
+            .unwrap();
+        assert_eq!(second.data, first.data);
+        assert_eq!(service.oversized_layers()[0].fetches, 1);
+
+        // Uncached renders leave no trace and read the source
+        let uncached = StorageService::new_local(dir.clone(), 10);
+        uncached
+            .render_uncached(&params, View::Front, &options)
+            .await
+            .unwrap();
+        assert!(uncached.oversized_layers().is_empty());
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_plate_is_part_of_cache_key() {
+        let service = StorageService::new_demo(10);
+        let params = parse_params("hoodies/hoodie-black");
+        let default = CompositeOptions::default();
+        let tall = CompositeOptions::default().with_plate("base-model-tall");
+
+        let on_default = service
+            .render(&params, View::Front, &default, false)
+            .await
+            .unwrap();
+        let on_tall = service
+            .render(&params, View::Front, &tall, false)
+            .await
+            .unwrap();
+        assert_ne!(on_default.cache_key, on_tall.cache_key);
+        assert_eq!(service.cache_key(&params, View::Front, &tall), on_tall.cache_key);
+        assert!(!on_tall.cached);
+
+        // A `plate/<sku>` param selects the same plate
+        let params = parse_params("plate/base-model-tall,hoodies/hoodie-black");
+        let from_param = service
+            .render(&params, View::Front, &default, false)
+            .await
+            .unwrap();
+        assert_eq!(from_param.cache_key, on_tall.cache_key);
+        assert!(from_param.cached);
+
+        let bad = CompositeOptions::default().with_plate("../secrets");
+        assert!(service
+            .render(&params, View::Front, &bad, false)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_render_recipe() {
+        let service = StorageService::new_demo(10);
+        let params = parse_params("hoodies/hoodie-black,patches-left/flag-patch-red");
+        let options = CompositeOptions::default();
+
+        let normalized = LayerNormalizer::new(View::Front, &params).normalize_all(&params);
+        let recipe = Recipe::compile(&normalized, &patch_mask_categories(&normalized), &options);
+        let from_recipe = service
+            .render_recipe(&recipe, View::Front, None)
+            .await
+            .unwrap();
+
+        let rendered = service
+            .render(&params, View::Front, &options, true)
+            .await
+            .unwrap();
+        assert_eq!(rendered.data, from_recipe);
+    }
+
+    #[tokio::test]
+    async fn test_render_bundle() {
+        let service = StorageService::new_demo(10);
+        let params = parse_params("plate/tall,hoodies/hoodie-black,hats/beanie,patches-left/flag");
+        let options = CompositeOptions::default();
+
+        let bundle = service
+            .render_bundle(&params, View::Back, &options, false)
+            .await
+            .unwrap();
+        // Patches aren't rendered in the back view
+        let items: Vec<&str> = bundle
+            .items
+            .iter()
+            .map(|(item, _)| item.category.as_str())
+            .collect();
+        assert_eq!(items, ["hoodies", "hats"]);
+
+        let (_, hoodie) = &bundle.items[0];
+        assert_ne!(hoodie.cache_key, bundle.outfit.cache_key);
+        assert_eq!(hoodie.found_layers, 1);
+
+        let alone = parse_params("plate/tall,hoodies/hoodie-black");
+        let rendered = service
+            .render(&alone, View::Back, &options, false)
+            .await
+            .unwrap();
+        assert_eq!(rendered.cache_key, hoodie.cache_key);
+        assert!(rendered.cached);
+    }
 
     #[tokio::test]
     async fn test_storage_service_creation() {
         let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
         let client = Client::new(&config);
-        let service = StorageService::new(client, "test-bucket".to_string(), 100);
+        let service = StorageService::new_s3(client, "test-bucket".to_string(), 100);
 
         let stats = service.cache_stats().await;
         assert_eq!(stats.memory_capacity, 100);
@@ -0,0 +1,36 @@
+//! Paged storage listings
+//!
+//! A bucket can hold millions of objects, more than a single list should keep
+//! in memory. Backends list as a stream of [`Page`]s, fetched as the caller
+//! consumes them, so a caller can fold pages into a summary, stop early, or
+//! report progress. [`collect_pages`] builds the whole list for callers that
+//! need it.
+
+use anyhow::Result;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+
+/// One page of a storage listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Position of the page in the listing, from 0
+    pub number: usize,
+}
+
+/// Pages of a listing, fetched on demand
+pub type PageStream<'a, T> = BoxStream<'a, Result<Page<T>>>;
+
+/// Every item of a listing, in page order
+pub async fn collect_pages<T>(pages: PageStream<'_, T>) -> Result<Vec<T>> {
+    pages
+        .try_fold(Vec::new(), |mut items, page| async move {
+            items.extend(page.items);
+            Ok(items)
+        })
+        .await
+}
+
+/// A listing that fails with `message` on its first page
+pub(crate) fn unsupported<'a, T: Send + 'a>(message: &'static str) -> PageStream<'a, T> {
+    stream::once(async move { Err(anyhow::anyhow!(message)) }).boxed()
+}
@@ -1,12 +1,20 @@
 use anyhow::{Context, Result};
 use bytes::Bytes;
-use birl_core::View;
+use birl_core::{OutputFormat, View};
+use futures::StreamExt;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::io::AsyncWriteExt;
 use tracing::{debug, warn};
 
+use crate::listing::{collect_pages, Page, PageStream};
+use crate::resolver::{DefaultKeyResolver, KeyResolver};
+
 /// Local filesystem storage for development and testing
 pub struct LocalStorage {
     base_path: PathBuf,
+    resolver: Arc<dyn KeyResolver>,
 }
 
 impl LocalStorage {
@@ -15,12 +23,24 @@ impl LocalStorage {
     pub fn new(base_path: impl Into<PathBuf>) -> Self {
         Self {
             base_path: base_path.into(),
+            resolver: Arc::new(DefaultKeyResolver),
         }
     }
 
+    /// Look layers up with a different key layout under the base path
+    pub fn with_resolver(mut self, resolver: impl KeyResolver + 'static) -> Self {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Key of a layer relative to the base path, before any subdirectory search
+    pub fn layer_key(&self, category: &str, sku: &str, view: View, extension: &str) -> String {
+        self.resolver.layer_key(category, sku, view, extension)
+    }
+
     /// Fetch a layer image from local filesystem
-    /// Path format: {base_path}/{view}/{category}/{sku}.{extension}
-    /// Also searches in subdirectories if not found directly
+    /// Path format: {base_path}/{key}, with the key from the resolver
+    /// Also searches in subdirectories of the key's directory if not found directly
     pub async fn fetch_layer(
         &self,
         category: &str,
@@ -28,51 +48,94 @@ impl LocalStorage {
         view: View,
         extension: &str,
     ) -> Result<Option<Bytes>> {
-        let filename = format!("{}.{}", sku, extension);
+        let Some(path) = self.find_layer(category, sku, view, extension).await else {
+            debug!(
+                "Layer not found: {}",
+                self.resolver.layer_key(category, sku, view, extension)
+            );
+            return Ok(None);
+        };
+
+        match tokio::fs::read(&path).await {
+            Ok(data) => {
+                debug!("Fetched layer: {} ({} bytes)", path.display(), data.len());
+                Ok(Some(Bytes::from(data)))
+            }
+            Err(e) => {
+                warn!("Failed to read layer {}: {}", path.display(), e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Path of a layer: the key's path, or the same file name in a
+    /// subdirectory of the key's directory
+    pub async fn find_layer(
+        &self,
+        category: &str,
+        sku: &str,
+        view: View,
+        extension: &str,
+    ) -> Option<PathBuf> {
+        let key = self.resolver.layer_key(category, sku, view, extension);
 
         // Try direct path first
-        let direct_path = self.base_path.join(format!(
-            "{}/{}/{}",
-            view.as_str(),
-            category,
-            filename
-        ));
-
-        if let Ok(data) = tokio::fs::read(&direct_path).await {
-            debug!("Fetched layer: {} ({} bytes)", direct_path.display(), data.len());
-            return Ok(Some(Bytes::from(data)));
+        let direct_path = self.base_path.join(&key);
+        if is_file(&direct_path).await {
+            return Some(direct_path);
         }
 
         // If not found, search in subdirectories
-        let category_path = self.base_path.join(format!("{}/{}", view.as_str(), category));
+        let (Some(category_path), Some(filename)) = (direct_path.parent(), direct_path.file_name())
+        else {
+            return None;
+        };
 
-        if let Ok(mut entries) = tokio::fs::read_dir(&category_path).await {
+        if let Ok(mut entries) = tokio::fs::read_dir(category_path).await {
             while let Ok(Some(entry)) = entries.next_entry().await {
                 if entry.path().is_dir() {
-                    let subdir_path = entry.path().join(&filename);
-                    if let Ok(data) = tokio::fs::read(&subdir_path).await {
-                        debug!("Fetched layer from subdir: {} ({} bytes)", subdir_path.display(), data.len());
-                        return Ok(Some(Bytes::from(data)));
+                    let subdir_path = entry.path().join(filename);
+                    if is_file(&subdir_path).await {
+                        return Some(subdir_path);
                     }
                 }
             }
         }
 
-        debug!("Layer not found: {}/{}/{}", view.as_str(), category, filename);
-        Ok(None)
+        None
     }
 
     /// Fetch a cached composite image
-    /// Path format: {base_path}/cache/{cache_key}.jpg
-    pub async fn fetch_cached(&self, cache_key: &str) -> Result<Option<Bytes>> {
+    /// Path format: {base_path}/cache/{cache_key}.{ext}
+    pub async fn fetch_cached(
+        &self,
+        cache_key: &str,
+        format: OutputFormat,
+    ) -> Result<Option<Bytes>> {
+        Ok(self
+            .fetch_cached_saved(cache_key, format)
+            .await?
+            .map(|(data, _)| data))
+    }
+
+    /// [`Self::fetch_cached`] with the file's modification time
+    pub async fn fetch_cached_saved(
+        &self,
+        cache_key: &str,
+        format: OutputFormat,
+    ) -> Result<Option<(Bytes, Option<SystemTime>)>> {
         let path = self
             .base_path
-            .join(format!("cache/{}.jpg", cache_key));
+            .join(format!("cache/{}.{}", cache_key, format.extension()));
 
         match tokio::fs::read(&path).await {
             Ok(data) => {
                 debug!("Cache hit: {} ({} bytes)", cache_key, data.len());
-                Ok(Some(Bytes::from(data)))
+                let saved = tokio::fs::metadata(&path)
+                    .await
+                    .and_then(|metadata| metadata.modified())
+                    .ok();
+                Ok(Some((Bytes::from(data), saved)))
             }
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
                 debug!("Cache miss: {}", cache_key);
@@ -86,10 +149,15 @@ impl LocalStorage {
     }
 
     /// Save a composite image to cache
-    pub async fn save_to_cache(&self, cache_key: &str, data: &[u8]) -> Result<()> {
+    pub async fn save_to_cache(
+        &self,
+        cache_key: &str,
+        data: &[u8],
+        format: OutputFormat,
+    ) -> Result<()> {
         let path = self
             .base_path
-            .join(format!("cache/{}.jpg", cache_key));
+            .join(format!("cache/{}.{}", cache_key, format.extension()));
 
         // Create cache directory if it doesn't exist
         if let Some(parent) = path.parent() {
@@ -107,6 +175,102 @@ impl LocalStorage {
         Ok(())
     }
 
+    /// Save JSON data under `cache/{key}.json`
+    pub async fn save_cached_json(&self, key: &str, json: &str) -> Result<()> {
+        let path = self.base_path.join(format!("cache/{}.json", key));
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create cache directory")?;
+        }
+
+        tokio::fs::write(&path, json)
+            .await
+            .context("Failed to write cached JSON")?;
+
+        Ok(())
+    }
+
+    /// Create `cache/locks/{key}` exclusively
+    /// Returns false if the file already exists
+    pub async fn claim(&self, key: &str, holder: &str) -> Result<bool> {
+        let path = self.base_path.join(format!("cache/locks/{}", key));
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create lock directory")?;
+        }
+
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await;
+        match file {
+            Ok(mut file) => {
+                file.write_all(holder.as_bytes())
+                    .await
+                    .context("Failed to write lock file")?;
+                Ok(true)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e).with_context(|| format!("Failed to create lock {}", path.display())),
+        }
+    }
+
+    /// Route the server serves a cached composite from: /cache/{cache_key}.{ext}
+    pub fn cache_url(&self, cache_key: &str, format: OutputFormat) -> String {
+        format!("/cache/{}.{}", cache_key, format.extension())
+    }
+
+    /// Paths of all plates and layers, relative to the base path, sorted
+    /// Hidden files and the cache directory are skipped
+    pub async fn list_assets(&self) -> Result<Vec<String>> {
+        let mut paths = collect_pages(self.asset_pages()).await?;
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Like [`Self::list_assets`], one page per directory that has files
+    pub fn asset_pages(&self) -> PageStream<'_, String> {
+        let dirs = vec![self.base_path.clone()];
+
+        futures::stream::try_unfold((dirs, 0), move |(mut dirs, number)| async move {
+            while let Some(dir) = dirs.pop() {
+                let mut items = Vec::new();
+                let mut entries = tokio::fs::read_dir(&dir)
+                    .await
+                    .with_context(|| format!("Failed to list {}", dir.display()))?;
+                while let Some(entry) = entries.next_entry().await? {
+                    let path = entry.path();
+                    let relative = path
+                        .strip_prefix(&self.base_path)
+                        .unwrap_or(&path)
+                        .to_string_lossy()
+                        .replace('\\', "/");
+                    if relative.starts_with('.') || relative.contains("/.") || relative == "cache" {
+                        continue;
+                    }
+
+                    if entry.file_type().await?.is_dir() {
+                        dirs.push(path);
+                    } else {
+                        items.push(relative);
+                    }
+                }
+
+                if !items.is_empty() {
+                    items.sort();
+                    return Ok(Some((Page { items, number }, (dirs, number + 1))));
+                }
+            }
+            Ok(None)
+        })
+        .boxed()
+    }
+
     /// Fetch cached JSON data
     pub async fn fetch_cached_json(&self, key: &str) -> Result<Option<String>> {
         let path = self
@@ -126,15 +290,66 @@ impl LocalStorage {
     }
 }
 
+async fn is_file(path: &Path) -> bool {
+    tokio::fs::metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.is_file())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::env;
+    use futures::TryStreamExt;
 
     #[tokio::test]
     async fn test_local_storage_creation() {
         let storage = LocalStorage::new("/tmp/birl-test");
         assert_eq!(storage.base_path(), Path::new("/tmp/birl-test"));
+        assert_eq!(
+            storage.cache_url("d3e679cb3d375c4f", OutputFormat::Jpeg),
+            "/cache/d3e679cb3d375c4f.jpg"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_assets() {
+        let dir = std::env::temp_dir().join(format!("birl-list-{}", std::process::id()));
+        for path in [
+            "front/hats/beanie.png",
+            "front/plate/base.jpg",
+            "cache/abc.png",
+        ] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+
+        let storage = LocalStorage::new(&dir);
+        let paths = storage.list_assets().await.unwrap();
+        assert_eq!(paths, ["front/hats/beanie.png", "front/plate/base.jpg"]);
+
+        let pages: Vec<_> = storage.asset_pages().try_collect().await.unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[1].number, 1);
+        assert!(pages.iter().all(|page| page.items.len() == 1));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fetch_layer_with_resolver() {
+        let dir = std::env::temp_dir().join(format!("birl-resolver-{}", std::process::id()));
+        let path = dir.join("hoodies/front/winter/hoodie-black.png");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, b"layer").unwrap();
+
+        let resolver = crate::TemplateKeyResolver::new("{category}/{view}/{sku}.{ext}").unwrap();
+        let storage = LocalStorage::new(&dir).with_resolver(resolver);
+        let layer = storage
+            .fetch_layer("hoodies", "hoodie-black", View::Front, "png")
+            .await
+            .unwrap();
+        assert_eq!(layer.as_deref(), Some(&b"layer"[..]));
+        std::fs::remove_dir_all(dir).unwrap();
     }
 
     #[tokio::test]
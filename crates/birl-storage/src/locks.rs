@@ -0,0 +1,100 @@
+//! Per-period claims for scheduled maintenance across instances
+//!
+//! When several server instances run the same scheduled task (cache warm-up,
+//! GC), each should call [`StorageService::claim_period`] before a run: the
+//! first instance to create `cache/locks/{task}/{period}` runs the task for
+//! that period, the others skip it. A claim is a single conditional write
+//! (`If-None-Match: *` on S3, an exclusive create on local storage), so there
+//! is no lease to renew or release, and a crashed holder only costs its own
+//! period. Lock objects are a few bytes; old ones can be expired with a bucket
+//! lifecycle rule on `cache/locks/`.
+
+use anyhow::{Context, Result};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info};
+
+use crate::StorageService;
+
+impl StorageService {
+    /// Claim `task` for the current period, e.g. the current hour for
+    /// `Duration::from_secs(3600)`
+    /// Returns false if another instance has already claimed it
+    pub async fn claim_period(&self, task: &str, period: Duration) -> Result<bool> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("System clock is before the Unix epoch")?;
+        self.claim_period_at(task, period, now).await
+    }
+
+    pub(crate) async fn claim_period_at(
+        &self,
+        task: &str,
+        period: Duration,
+        now: Duration,
+    ) -> Result<bool> {
+        if task.is_empty()
+            || !task
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!("Invalid task name '{}'", task);
+        }
+        if period.as_secs() == 0 {
+            anyhow::bail!("Claim period must be at least a second");
+        }
+
+        let index = now.as_secs() / period.as_secs();
+        let claimed = self
+            .backend
+            .claim(&format!("{}/{}", task, index), &holder())
+            .await?;
+        if claimed {
+            info!("Claimed {} for period {}", task, index);
+        } else {
+            debug!("{} already claimed for period {}", task, index);
+        }
+        Ok(claimed)
+    }
+}
+
+/// Who holds a claim, written into the lock for debugging
+pub(crate) fn holder() -> String {
+    let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown".to_string());
+    format!("{}:{}", host, std::process::id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_claim_period() {
+        let dir = std::env::temp_dir().join(format!("birl-locks-{}", std::process::id()));
+        let hour = Duration::from_secs(3600);
+        let now = Duration::from_secs(1_700_000_000);
+
+        for service in [
+            StorageService::new_local(dir.clone(), 10),
+            StorageService::new_demo(10),
+        ] {
+            assert!(service.claim_period_at("warm", hour, now).await.unwrap());
+            assert!(!service
+                .claim_period_at("warm", hour, now + Duration::from_secs(60))
+                .await
+                .unwrap());
+            assert!(service.claim_period_at("gc", hour, now).await.unwrap());
+            assert!(service
+                .claim_period_at("warm", hour, now + hour)
+                .await
+                .unwrap());
+            assert!(service.claim_period_at("../warm", hour, now).await.is_err());
+            assert!(service
+                .claim_period_at("warm", Duration::ZERO, now)
+                .await
+                .is_err());
+        }
+
+        assert!(dir.join("cache/locks/warm/472222").is_file());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+use crate::StorageBackend;
+use birl_core::oversize::downscale;
+use birl_core::{Limits, OutputFormat, Oversize, ResizeFilter, TieredCache};
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tracing::{debug, warn};
+use xxhash_rust::xxh64::xxh64;
+
+/// Memory budget for downscaled layers
+const MEMORY_BYTES: usize = 64 * 1024 * 1024;
+
+/// A layer some render found oversized for its plate
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct OversizedLayer {
+    /// Layer path (`{view}/{category}/{sku}.{ext}`) or remote URL
+    pub key: String,
+    pub oversize: Oversize,
+    /// Fetches served the downscaled layer instead
+    pub fetches: usize,
+}
+
+/// Downscaled copies of oversized layers, kept in the composite cache under
+/// `derived/` and keyed by the source's content hash, so a fixed source
+/// replaces its copy
+pub(crate) struct OversizedLayers {
+    derived: TieredCache<String, Bytes>,
+    seen: Mutex<BTreeMap<String, OversizedLayer>>,
+}
+
+impl Default for OversizedLayers {
+    fn default() -> Self {
+        Self {
+            derived: TieredCache::new().with_max_bytes(MEMORY_BYTES, Bytes::len),
+            seen: Mutex::default(),
+        }
+    }
+}
+
+impl OversizedLayers {
+    /// The layer `data` fetched from `key`, downscaled with `filter` to fit
+    /// the plate if it's oversized
+    pub(crate) async fn fit(
+        &self,
+        backend: &dyn StorageBackend,
+        key: &str,
+        data: Bytes,
+        (plate_width, plate_height): (u32, u32),
+        filter: ResizeFilter,
+    ) -> Bytes {
+        let Some(oversize) = Oversize::check(&data, plate_width, plate_height) else {
+            return data;
+        };
+        // Over the pixel limit, the render rejects the source as it would anyway
+        if Limits::global().check_image(key, &data).is_err() {
+            return data;
+        }
+        self.record(key, oversize);
+
+        let (width, height) = oversize.target_size();
+        let derived_key = derived_key(key, &data, width, height, filter);
+        if let Some(derived) = self.derived.get(&derived_key) {
+            return derived;
+        }
+        match backend.fetch_cached(&derived_key, OutputFormat::Png).await {
+            Ok(Some(derived)) => {
+                debug!("Using downscaled layer: {}", derived_key);
+                self.derived.insert(derived_key, derived.clone());
+                return derived;
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to fetch downscaled layer {}: {:#}", derived_key, e),
+        }
+
+        let derived = match downscale(&data, width, height, filter) {
+            Ok(derived) => derived,
+            Err(e) => {
+                warn!("Failed to downscale layer {}: {}", key, e);
+                return data;
+            }
+        };
+        if let Err(e) = backend
+            .save_to_cache(&derived_key, &derived, OutputFormat::Png)
+            .await
+        {
+            warn!("Failed to save downscaled layer {}: {:#}", derived_key, e);
+        }
+        self.derived.insert(derived_key, derived.clone());
+        derived
+    }
+
+    /// Count a fetch of an oversized layer, reporting its first one
+    fn record(&self, key: &str, oversize: Oversize) {
+        let mut seen = self.seen.lock().expect("oversized layers lock poisoned");
+        let layer = seen.entry(key.to_string()).or_insert_with(|| {
+            warn!(
+                "Oversized layer {}: {} (more than {}x), downscaling it; fix the source asset",
+                key,
+                oversize,
+                birl_core::OVERSIZE_FACTOR
+            );
+            OversizedLayer {
+                key: key.to_string(),
+                oversize,
+                fetches: 0,
+            }
+        });
+        layer.oversize = oversize;
+        layer.fetches += 1;
+    }
+
+    /// Every oversized layer seen so far, by key
+    pub(crate) fn layers(&self) -> Vec<OversizedLayer> {
+        let seen = self.seen.lock().expect("oversized layers lock poisoned");
+        seen.values().cloned().collect()
+    }
+}
+
+/// Composite cache key of a downscaled layer
+/// Remote layers are named by a hash of their URL; copies made with a filter
+/// other than the default are suffixed with it
+fn derived_key(key: &str, data: &[u8], width: u32, height: u32, filter: ResizeFilter) -> String {
+    let name = if birl_core::is_remote_url(key) {
+        format!("remote/{:016x}", xxh64(key.as_bytes(), 0))
+    } else {
+        key.to_string()
+    };
+    let mut derived = format!(
+        "derived/{}-{:016x}-{}x{}",
+        name,
+        xxh64(data, 0),
+        width,
+        height
+    );
+    if filter != ResizeFilter::default() {
+        derived.push('-');
+        derived.push_str(filter.as_str());
+    }
+    derived
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derived_key() {
+        let lanczos = ResizeFilter::default();
+        let key = derived_key("front/patches/flag.png", b"flag", 100, 150, lanczos);
+        assert!(key.starts_with("derived/front/patches/flag.png-"));
+        assert!(key.ends_with("-100x150"));
+        assert_ne!(
+            key,
+            derived_key("front/patches/flag.png", b"new", 100, 150, lanczos)
+        );
+
+        let nearest = derived_key(
+            "front/patches/flag.png",
+            b"flag",
+            100,
+            150,
+            ResizeFilter::Nearest,
+        );
+        assert!(nearest.ends_with("-100x150-nearest"));
+
+        let remote = derived_key(
+            "https://cdn.example.com/a/flag.png",
+            b"flag",
+            100,
+            150,
+            lanczos,
+        );
+        assert!(remote.starts_with("derived/remote/"));
+        assert!(!remote.contains("cdn"));
+    }
+}
@@ -0,0 +1,577 @@
+//! What a render would do, without doing it
+//!
+//! [`StorageService::plan`] runs the same normalization, catalog check and
+//! cache key computation as a render, and says which plate and layers it would
+//! read and from where, without fetching or compositing anything. Frontends use
+//! it to debug an outfit and to decide which composites to pre-warm.
+//!
+//! A plan round-trips through JSON unchanged, so one process can plan renders
+//! and queue them (e.g. in SQS) for workers, which render them with
+//! [`StorageService::render_plan`] exactly as planned.
+
+use anyhow::Result;
+use birl_core::{
+    BirlError, CategoryRegistry, CompositeOptions, ConstraintViolation, DroppedLayer, Filter,
+    LayerConflict, LayerNormalizer, LayerOffsets, LayerParam, LicenseAction, LicenseDecision,
+    LicenseError, Offset, Orientation, Orientations, OutputFormat, Placement, Sku, View,
+    PLATE_CATEGORY,
+};
+use bytes::Bytes;
+use serde::{Deserialize, Deserializer, Serialize};
+use tracing::info;
+
+use crate::{composite_key, plate_for, StorageService};
+
+/// A plate or layer a render reads
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedLayer {
+    #[serde(flatten)]
+    pub layer: LayerParam,
+    /// Storage key it is fetched from, or its URL for remote layers
+    pub key: String,
+    /// Where a patch goes on its garment, from the catalog
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub placement: Option<Placement>,
+    /// How far the layer is shifted in the view, from the configured offsets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<Offset>,
+    /// How the layer is turned and flipped, from its placement or the
+    /// configured orientations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<Orientation>,
+}
+
+/// Layers are read back as planned, without normalizing them again
+impl<'de> Deserialize<'de> for PlannedLayer {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Raw {
+            category: String,
+            sku: String,
+            #[serde(default)]
+            url: Option<String>,
+            #[serde(default)]
+            z: Option<u32>,
+            key: String,
+            #[serde(default)]
+            placement: Option<Placement>,
+            #[serde(default)]
+            offset: Option<Offset>,
+            #[serde(default)]
+            orientation: Option<Orientation>,
+        }
+
+        let raw = Raw::deserialize(deserializer)?;
+        Ok(Self {
+            layer: LayerParam {
+                category: raw.category,
+                sku: Sku::normalized(raw.sku),
+                url: raw.url,
+                z: raw.z,
+            },
+            key: raw.key,
+            placement: raw.placement,
+            offset: raw.offset,
+            orientation: raw.orientation,
+        })
+    }
+}
+
+/// A render, planned
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompositionPlan {
+    pub view: View,
+    pub plate: PlannedLayer,
+    /// Normalized layers, bottom to top
+    pub layers: Vec<PlannedLayer>,
+    /// Options the composite is rendered and encoded with
+    pub options: CompositeOptions,
+    pub cache_key: String,
+    /// Where the composite is cached, if the backend can address it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<LayerConflict>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<ConstraintViolation>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dropped: Vec<DroppedLayer>,
+    /// Credits the licenses of the layers require with the composite
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attributions: Vec<String>,
+}
+
+impl CompositionPlan {
+    /// The normalized layers, as compiled into the render's recipe
+    pub fn params(&self) -> Vec<LayerParam> {
+        self.layers
+            .iter()
+            .map(|planned| planned.layer.clone())
+            .collect()
+    }
+
+    /// Catalog placement of each layer, aligned with [`Self::params`]
+    pub fn placements(&self) -> Vec<Option<Placement>> {
+        self.layers
+            .iter()
+            .map(|planned| planned.placement)
+            .collect()
+    }
+
+    /// Configured offset of each layer, aligned with [`Self::params`]
+    pub fn offsets(&self) -> Vec<Option<Offset>> {
+        self.layers.iter().map(|planned| planned.offset).collect()
+    }
+
+    /// Orientation of each layer, aligned with [`Self::params`]
+    pub fn orientations(&self) -> Vec<Option<Orientation>> {
+        self.layers
+            .iter()
+            .map(|planned| planned.orientation)
+            .collect()
+    }
+
+    /// Whether the render is of the plate alone, as requested rather than
+    /// because every layer was dropped
+    pub fn is_plate_only(&self) -> bool {
+        self.layers.is_empty() && self.dropped.is_empty() && self.violations.is_empty()
+    }
+}
+
+impl StorageService {
+    /// Plan a render of `params`, failing where [`Self::render`] would before
+    /// fetching (conflicts, constraints, limits, catalog, licenses)
+    pub fn plan(
+        &self,
+        params: &[LayerParam],
+        view: View,
+        options: &CompositeOptions,
+    ) -> Result<CompositionPlan> {
+        let normalized = LayerNormalizer::new(view, params).resolve(params)?;
+        let plate = plate_for(params, view, options);
+        if let Some(catalog) = &self.catalog {
+            catalog
+                .validate(&normalized.layers, view, plate)
+                .map_err(birl_core::BirlError::from)?;
+        }
+
+        let (options, attributions) = self.check_licenses(&normalized.layers, view, options)?;
+        let options = &options;
+
+        let placements = self.patch_placements(&normalized.layers, view);
+        let offsets = LayerOffsets::global().for_layers(&normalized.layers, &placements, view);
+        let orientations = Orientations::global().for_layers(&normalized.layers, &placements, view);
+        let cache_key = composite_key(
+            &normalized.layers,
+            &placements,
+            &offsets,
+            &orientations,
+            view,
+            plate,
+            options,
+        );
+        let layers = normalized
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(idx, layer)| PlannedLayer {
+                layer: layer.clone(),
+                key: self.asset_key(layer, view),
+                placement: placements[idx],
+                offset: offsets[idx],
+                orientation: orientations[idx],
+            })
+            .collect();
+
+        let plate = LayerParam::new(PLATE_CATEGORY, plate);
+        Ok(CompositionPlan {
+            view,
+            plate: PlannedLayer {
+                key: self.asset_key(&plate, view),
+                layer: plate,
+                placement: None,
+                offset: None,
+                orientation: None,
+            },
+            layers,
+            options: options.clone(),
+            cache_url: self.cache_url(&cache_key, options.format),
+            cache_key,
+            conflicts: normalized.conflicts,
+            violations: normalized.violations,
+            dropped: normalized.dropped,
+            attributions,
+        })
+    }
+
+    /// Check normalized `layers` against the licenses, recording the decision
+    /// in the audit log
+    /// Returns the options to render with, watermarked if the licenses say so,
+    /// and the attributions the composite needs; fails if it's blocked
+    pub(crate) fn check_licenses(
+        &self,
+        layers: &[LayerParam],
+        view: View,
+        options: &CompositeOptions,
+    ) -> Result<(CompositeOptions, Vec<String>)> {
+        let Some(decision) = self.license_decision(layers, options) else {
+            return Ok((options.clone(), Vec::new()));
+        };
+        let region = options.region.as_deref();
+        audit(&decision, view, region);
+        if let Some(error) = decision.blocked(region) {
+            return Err(BirlError::from(error).into());
+        }
+        Ok((
+            licensed_options(options, Some(&decision)),
+            decision.attributions,
+        ))
+    }
+
+    /// [`Self::check_licenses`] for the layers of a caller-supplied recipe
+    /// Nothing keeps a watermark in a graph the caller wrote, so layers the
+    /// region may only see watermarked are refused like blocked ones
+    pub(crate) fn check_recipe_licenses(
+        &self,
+        layers: &[LayerParam],
+        view: View,
+        region: Option<&str>,
+    ) -> Result<()> {
+        let Some(decision) = self.licenses.as_ref().map(|l| l.decide(layers, region)) else {
+            return Ok(());
+        };
+        if decision.licensed.is_empty() {
+            return Ok(());
+        }
+        audit(&decision, view, region);
+        if decision.action.is_some() {
+            let error = LicenseError {
+                layers: decision.restricted,
+                region: region.map(str::to_string),
+            };
+            return Err(BirlError::from(error).into());
+        }
+        Ok(())
+    }
+
+    /// How the licenses treat a render of normalized `layers`, if any of them
+    /// is licensed
+    pub(crate) fn license_decision(
+        &self,
+        layers: &[LayerParam],
+        options: &CompositeOptions,
+    ) -> Option<LicenseDecision> {
+        let decision = self
+            .licenses
+            .as_ref()?
+            .decide(layers, options.region.as_deref());
+        (!decision.licensed.is_empty()).then_some(decision)
+    }
+
+    /// What's recorded with a composite of normalized `layers` when it's
+    /// cached, if licenses are checked
+    pub(crate) fn license_record(
+        &self,
+        layers: &[LayerParam],
+        options: &CompositeOptions,
+    ) -> Option<LicenseRecord> {
+        self.licenses.as_ref()?;
+        Some(LicenseRecord {
+            layers: layers.to_vec(),
+            watermarked: options.filters.contains(&Filter::Watermark),
+        })
+    }
+
+    /// A cached composite by its cache key, for a viewer in `region`
+    /// With licenses, the layers recorded with the composite are checked
+    /// against them like a render's: a composite the region may not see, or
+    /// only see watermarked when it isn't, fails with the license error, and
+    /// one without a record (cached before licenses were checked) isn't served
+    pub async fn get_cached_composite_for(
+        &self,
+        cache_key: &str,
+        format: OutputFormat,
+        region: Option<&str>,
+    ) -> Result<Option<Bytes>> {
+        let Some(licenses) = &self.licenses else {
+            return self.get_cached_composite(cache_key, format).await;
+        };
+        let Some(record) = self.cache.license(cache_key).await? else {
+            return Ok(None);
+        };
+        let decision = licenses.decide(&record.layers, region);
+        let restricted = match decision.action {
+            Some(LicenseAction::Block) => true,
+            Some(LicenseAction::Watermark) => !record.watermarked,
+            None => false,
+        };
+        if restricted {
+            let error = LicenseError {
+                layers: decision.restricted,
+                region: region.map(str::to_string),
+            };
+            return Err(BirlError::from(error).into());
+        }
+        self.get_cached_composite(cache_key, format).await
+    }
+
+    fn asset_key(&self, layer: &LayerParam, view: View) -> String {
+        let extension = match layer.category.as_str() {
+            PLATE_CATEGORY => self.plate_extension(view, layer.sku.as_str()),
+            category => CategoryRegistry::global().extension(category).to_string(),
+        };
+        match &layer.url {
+            Some(url) => url.clone(),
+            None => self
+                .backend
+                .layer_key(&layer.category, layer.sku.as_str(), view, &extension),
+        }
+    }
+}
+
+/// Layers of a cached composite, saved with it when licenses are checked so
+/// it's only served by its cache key where they may be shown
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct LicenseRecord {
+    /// Normalized layers, bottom to top
+    pub layers: Vec<LayerParam>,
+    /// Whether the composite carries the license watermark
+    pub watermarked: bool,
+}
+
+/// The options a render is made with: watermarked if the licenses say so
+pub(crate) fn licensed_options(
+    options: &CompositeOptions,
+    decision: Option<&LicenseDecision>,
+) -> CompositeOptions {
+    let mut options = options.clone();
+    let watermark = decision.is_some_and(|d| d.action == Some(LicenseAction::Watermark));
+    if watermark && !options.filters.contains(&Filter::Watermark) {
+        options.filters.push(Filter::Watermark);
+    }
+    options
+}
+
+/// Record a license decision in the audit log (the `birl::audit` target)
+fn audit(decision: &LicenseDecision, view: View, region: Option<&str>) {
+    let layers = |layers: &[LayerParam]| {
+        layers
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let action = match decision.action {
+        Some(action) => action.to_string(),
+        None => "allow".to_string(),
+    };
+    info!(
+        target: "birl::audit",
+        region = region.unwrap_or("unknown"),
+        view = view.as_str(),
+        licensed = layers(&decision.licensed),
+        restricted = layers(&decision.restricted),
+        action,
+        "License decision"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use birl_core::{parse_params, patch_mask_categories, Catalog, Licenses, Recipe};
+
+    #[test]
+    fn test_plan() {
+        // Nothing is fetched, so the assets needn't exist
+        let dir = std::env::temp_dir().join(format!("birl-plan-{}", std::process::id()));
+        let service = StorageService::new_local(dir, 10);
+        let params =
+            parse_params("pants/cargo-black-36,plate/base-model-tall,hoodies/hoodie-black");
+        let options = CompositeOptions::default();
+
+        let plan = service.plan(&params, View::Front, &options).unwrap();
+        assert_eq!(plan.plate.key, "front/plate/base-model-tall.jpg");
+        let keys: Vec<&str> = plan
+            .layers
+            .iter()
+            .map(|planned| planned.key.as_str())
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "front/pants/cargo-black.png",
+                "front/hoodies/hoodie-black.png"
+            ]
+        );
+        assert_eq!(
+            plan.cache_key,
+            service.cache_key(&params, View::Front, &options)
+        );
+        assert!(plan.dropped.is_empty());
+
+        let params = parse_params("hoodies/hoodie-black,pants/cargo-black");
+        let plan = service.plan(&params, View::Left, &options).unwrap();
+        assert_eq!(plan.layers.len(), 1);
+        assert_eq!(
+            plan.dropped[0].layer,
+            LayerParam::new("pants", "cargo-black")
+        );
+
+        let catalog = Catalog::from_json(r#"{"categories": {"hoodies": {"skus": []}}}"#).unwrap();
+        let service = service.with_catalog(catalog);
+        assert!(service.plan(&params, View::Front, &options).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_plan_round_trip() {
+        let service = StorageService::new_demo(10);
+        let params =
+            parse_params("pants/cargo-black-36,hoodies/hoodie-black@z=9,hats/beanie-black");
+        let options = CompositeOptions::default().with_max_size(Some(200), None);
+        let plan = service.plan(&params, View::Front, &options).unwrap();
+        assert_eq!(plan.options, options);
+
+        let json = serde_json::to_string(&plan).unwrap();
+        let queued: CompositionPlan = serde_json::from_str(&json).unwrap();
+        assert_eq!(queued, plan);
+        assert_eq!(serde_json::to_string(&queued).unwrap(), json);
+
+        let rendered = service
+            .render(&params, View::Front, &options, true)
+            .await
+            .unwrap();
+        let replayed = service.render_plan(&queued, true).await.unwrap();
+        assert_eq!(replayed.cache_key, rendered.cache_key);
+        assert_eq!(replayed.data, rendered.data);
+
+        // Only the plan's own fields are accepted
+        let json = json.replacen('{', r#"{"extra":1,"#, 1);
+        assert!(serde_json::from_str::<CompositionPlan>(&json).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_plan_licenses() {
+        let licenses = Licenses::from_json(
+            r#"{
+                "hats/beanie-black": {"regions": ["US"], "action": "watermark", "attribution": "Knit Co"},
+                "pants/cargo-black": {"blocked_regions": ["DE"]}
+            }"#,
+        )
+        .unwrap();
+        let service = StorageService::new_demo(10).with_licenses(licenses);
+        let params = parse_params("hoodies/hoodie-black,hats/beanie-black");
+        let options = CompositeOptions::default().with_region("US");
+
+        let plan = service.plan(&params, View::Front, &options).unwrap();
+        assert_eq!(plan.options, options);
+        assert_eq!(plan.attributions, ["Knit Co"]);
+
+        // Restricted artwork is watermarked, under its own cache key
+        let elsewhere = options.clone().with_region("FR");
+        let watermarked = service.plan(&params, View::Front, &elsewhere).unwrap();
+        assert_eq!(watermarked.options.filters, [Filter::Watermark]);
+        assert_ne!(watermarked.cache_key, plan.cache_key);
+        assert_eq!(
+            watermarked.cache_key,
+            service.cache_key(&params, View::Front, &elsewhere)
+        );
+        let rendered = service.render_plan(&watermarked, true).await.unwrap();
+        assert_eq!(rendered.attributions, ["Knit Co"]);
+
+        let params = parse_params("pants/cargo-black,hoodies/hoodie-black");
+        let e = service
+            .plan(&params, View::Front, &options.with_region("DE"))
+            .unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<BirlError>(),
+            Some(BirlError::Unlicensed(_))
+        ));
+
+        // Recipes can't keep a watermark, so watermarked layers are refused too
+        let params = parse_params("hoodies/hoodie-black,hats/beanie-black");
+        let normalized = LayerNormalizer::new(View::Front, &params).normalize_all(&params);
+        let masks = patch_mask_categories(&normalized);
+        let recipe = Recipe::compile(&normalized, &masks, &CompositeOptions::default());
+        assert!(service
+            .render_recipe(&recipe, View::Front, Some("US"))
+            .await
+            .is_ok());
+        let e = service
+            .render_recipe(&recipe, View::Front, Some("FR"))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<BirlError>(),
+            Some(BirlError::Unlicensed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cached_composite_licenses() {
+        let licenses = Licenses::from_json(
+            r#"{
+                "hats/beanie-black": {"regions": ["US"], "action": "watermark"},
+                "pants/cargo-black": {"blocked_regions": ["DE"]}
+            }"#,
+        )
+        .unwrap();
+        let service = StorageService::new_demo(10).with_licenses(licenses);
+        let unlicensed = |result: Result<Option<Bytes>>| {
+            matches!(
+                result.unwrap_err().downcast_ref::<BirlError>(),
+                Some(BirlError::Unlicensed(_))
+            )
+        };
+        let format = OutputFormat::Jpeg;
+
+        // A composite rendered for the US isn't served by its key in DE
+        let params = parse_params("pants/cargo-black,hoodies/hoodie-black");
+        let options = CompositeOptions::default().with_region("US");
+        let rendered = service
+            .render(&params, View::Front, &options, true)
+            .await
+            .unwrap();
+        let key = &rendered.cache_key;
+        let served = service.get_cached_composite_for(key, format, Some("us"));
+        assert_eq!(served.await.unwrap(), Some(rendered.data));
+        assert!(unlicensed(
+            service
+                .get_cached_composite_for(key, format, Some("DE"))
+                .await
+        ));
+        assert!(unlicensed(
+            service.get_cached_composite_for(key, format, None).await
+        ));
+
+        // Watermarked renders are, unwatermarked ones only where they're licensed
+        let params = parse_params("hoodies/hoodie-black,hats/beanie-black");
+        let plain = service
+            .render(&params, View::Front, &options, true)
+            .await
+            .unwrap();
+        let elsewhere = options.clone().with_region("FR");
+        let watermarked = service
+            .render(&params, View::Front, &elsewhere, true)
+            .await
+            .unwrap();
+        assert!(unlicensed(
+            service
+                .get_cached_composite_for(&plain.cache_key, format, Some("FR"))
+                .await
+        ));
+        let served = service.get_cached_composite_for(&watermarked.cache_key, format, Some("FR"));
+        assert_eq!(served.await.unwrap(), Some(watermarked.data));
+
+        // Composites cached without a record aren't served
+        let data = Bytes::from_static(b"composite");
+        service
+            .save_composite("abc123", data, format)
+            .await
+            .unwrap();
+        let served = service.get_cached_composite_for("abc123", format, Some("US"));
+        assert_eq!(served.await.unwrap(), None);
+    }
+}
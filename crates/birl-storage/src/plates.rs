@@ -0,0 +1,159 @@
+//! Plates stored in more than one format
+//!
+//! Plates are read with the configured plate extension (`jpg` by default), but
+//! some views keep transparent PNG plates for renders without a background. A
+//! plate missing in the configured format is looked for in the other
+//! [`PLATE_EXTENSIONS`], and the extension it was found with is remembered for
+//! [`RESOLUTION_TTL`], so later renders read it directly.
+
+use anyhow::Result;
+use birl_core::{BirlError, CategoryRegistry, LayerParam, TieredCache, View, PLATE_CATEGORY};
+use bytes::Bytes;
+use std::time::Duration;
+
+use crate::StorageService;
+
+/// Extensions a plate is looked for with, after the configured one
+pub const PLATE_EXTENSIONS: [&str; 3] = ["jpg", "png", "webp"];
+
+/// How long the extension a plate was found with is remembered
+/// Past it, a plate re-uploaded in the configured format is read again
+pub const RESOLUTION_TTL: Duration = Duration::from_secs(600);
+
+/// Extension each plate was last found with, by `{view}/{name}`
+pub(crate) struct PlateExtensions(TieredCache<String, String>);
+
+impl Default for PlateExtensions {
+    fn default() -> Self {
+        Self(TieredCache::new().with_ttl(RESOLUTION_TTL))
+    }
+}
+
+impl PlateExtensions {
+    /// The extension a plate was found with, or else the configured one
+    pub(crate) fn extension(&self, view: View, name: &str) -> String {
+        self.0.get(&plate_path(view, name)).unwrap_or_else(|| {
+            CategoryRegistry::global()
+                .extension(PLATE_CATEGORY)
+                .to_string()
+        })
+    }
+
+    /// Extensions to look for a plate with, most likely first
+    fn candidates(&self, view: View, name: &str) -> Vec<String> {
+        let mut candidates = vec![
+            self.extension(view, name),
+            CategoryRegistry::global()
+                .extension(PLATE_CATEGORY)
+                .to_string(),
+        ];
+        candidates.extend(PLATE_EXTENSIONS.map(String::from));
+        let mut seen = Vec::with_capacity(candidates.len());
+        candidates.retain(|extension| {
+            let first = !seen.contains(extension);
+            seen.push(extension.clone());
+            first
+        });
+        candidates
+    }
+
+    fn found(&self, view: View, name: &str, extension: &str) {
+        self.0.insert(plate_path(view, name), extension.to_string());
+    }
+}
+
+fn plate_path(view: View, name: &str) -> String {
+    format!("{}/{}", view, name)
+}
+
+impl StorageService {
+    /// Fetch a plate of a view by name (`{view}/plate/{name}.jpg` unless
+    /// plates are configured in another format), trying the other
+    /// [`PLATE_EXTENSIONS`] if it isn't stored in that format
+    pub async fn fetch_plate(&self, view: View, name: &str) -> Result<Bytes> {
+        check_plate_name(name)?;
+        let plate = LayerParam::new(PLATE_CATEGORY, name);
+
+        for extension in self.plates.candidates(view, name) {
+            if let Some(data) = self.fetch_layer(&plate, view, &extension).await? {
+                self.plates.found(view, name, &extension);
+                return Ok(data);
+            }
+        }
+        Err(BirlError::MissingPlate(format!("{}/plate/{}", view, name)).into())
+    }
+
+    /// Whether a plate of a view exists in any of the [`PLATE_EXTENSIONS`]
+    pub async fn plate_exists(&self, view: View, name: &str) -> Result<bool> {
+        check_plate_name(name)?;
+        for extension in self.plates.candidates(view, name) {
+            if self
+                .backend
+                .layer_exists(PLATE_CATEGORY, name, view, &extension)
+                .await?
+            {
+                self.plates.found(view, name, &extension);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Extension a plate is read with: the one it was last found with, or
+    /// else the configured one
+    pub fn plate_extension(&self, view: View, name: &str) -> String {
+        self.plates.extension(view, name)
+    }
+}
+
+fn check_plate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains(['/', '\\']) {
+        anyhow::bail!("Invalid plate name '{}'", name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use birl_core::demo::{synthetic_layer, DEMO_HEIGHT, DEMO_WIDTH};
+    use birl_core::CompositeOptions;
+
+    #[tokio::test]
+    async fn test_png_plate() {
+        let dir = std::env::temp_dir().join(format!("birl-plates-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("front/plate")).unwrap();
+        std::fs::create_dir_all(dir.join("front/hoodies")).unwrap();
+        let hoodie = synthetic_layer("hoodies", "hoodie-black").unwrap();
+        std::fs::write(dir.join("front/hoodies/hoodie-black.png"), &hoodie).unwrap();
+
+        // A transparent plate, stored only as PNG
+        let plate = synthetic_layer("hats", "beanie-red").unwrap();
+        std::fs::write(dir.join("front/plate/cutout.png"), &plate).unwrap();
+
+        let service = StorageService::new_local(dir.clone(), 10);
+        assert_eq!(service.plate_extension(View::Front, "cutout"), "jpg");
+        assert!(service.plate_exists(View::Front, "cutout").await.unwrap());
+        assert!(!service.plate_exists(View::Front, "missing").await.unwrap());
+        assert_eq!(service.plate_extension(View::Front, "cutout"), "png");
+        assert!(service.fetch_plate(View::Front, "missing").await.is_err());
+        assert!(service.fetch_plate(View::Front, "../cutout").await.is_err());
+
+        let params = birl_core::parse_params("plate/cutout,hoodies/hoodie-black");
+        let options = CompositeOptions::default().with_format(birl_core::OutputFormat::Png);
+        let plan = service.plan(&params, View::Front, &options).unwrap();
+        assert_eq!(plan.plate.key, "front/plate/cutout.png");
+
+        let rendered = service
+            .render(&params, View::Front, &options, true)
+            .await
+            .unwrap();
+        assert_eq!(
+            birl_core::oversize::dimensions(&rendered.data),
+            Some((DEMO_WIDTH, DEMO_HEIGHT))
+        );
+        assert!(rendered.data.starts_with(b"\x89PNG"));
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,118 @@
+//! Background prefetch of an outfit's other views
+//!
+//! Most shoppers rotate the model within seconds of seeing an outfit. A server
+//! whose profile has a `[prefetch]` section renders the same outfit's other
+//! views (back, left and right by default) after each front view, so rotating
+//! is a cache hit. Prefetches only use idle capacity: they run in their own
+//! lane of `concurrency` slots, and a view that finds no free slot is skipped,
+//! not queued, so a busy server never falls behind on prefetches.
+
+use birl_core::{CompositeOptions, LayerParam, View};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::debug;
+
+use crate::config::PrefetchSettings;
+use crate::StorageService;
+
+/// Schedules low-priority renders of the views a shopper is likely to ask for next
+pub struct Prefetcher {
+    views: Vec<View>,
+    concurrency: usize,
+    lane: Arc<Semaphore>,
+    skipped: AtomicU64,
+}
+
+impl Prefetcher {
+    pub fn new(settings: &PrefetchSettings) -> Self {
+        Self {
+            views: settings.views.clone(),
+            concurrency: settings.concurrency,
+            lane: Arc::new(Semaphore::new(settings.concurrency)),
+            skipped: AtomicU64::new(0),
+        }
+    }
+
+    /// Render `params` in the background in each prefetched view, if `view`
+    /// is the front view
+    /// Returns the views scheduled; views with no free slot are skipped
+    pub fn schedule(
+        &self,
+        storage: &Arc<StorageService>,
+        params: &[LayerParam],
+        view: View,
+        options: &CompositeOptions,
+    ) -> Vec<View> {
+        if view != View::Front || params.is_empty() {
+            return Vec::new();
+        }
+        let mut scheduled = Vec::new();
+        for &next in self.views.iter().filter(|&&next| next != view) {
+            let Ok(permit) = self.lane.clone().try_acquire_owned() else {
+                self.skipped.fetch_add(1, Ordering::Relaxed);
+                debug!("Prefetch lane full, skipping {} view", next);
+                continue;
+            };
+            let storage = storage.clone();
+            let params = params.to_vec();
+            let options = options.clone();
+            tokio::spawn(async move {
+                if let Err(e) = storage.render(&params, next, &options, false).await {
+                    debug!("Failed to prefetch {} view: {:#}", next, e);
+                }
+                drop(permit);
+            });
+            scheduled.push(next);
+        }
+        scheduled
+    }
+
+    /// Views skipped so far because the lane was full
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    /// Wait until no prefetch is running
+    pub async fn idle(&self) {
+        let slots = u32::try_from(self.concurrency).unwrap_or(u32::MAX);
+        // The lane is never closed
+        let _ = self.lane.acquire_many(slots).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use birl_core::parse_params;
+
+    #[tokio::test]
+    async fn test_prefetch() {
+        let storage = Arc::new(StorageService::new_demo(10));
+        let prefetcher = Prefetcher::new(&PrefetchSettings {
+            views: vec![View::Back, View::Left, View::Right],
+            concurrency: 2,
+        });
+        let params = parse_params("pants/cargo-black,hoodies/hoodie-black");
+        let options = CompositeOptions::default();
+
+        // Only front views are prefetched from
+        let scheduled = prefetcher.schedule(&storage, &params, View::Back, &options);
+        assert!(scheduled.is_empty());
+
+        // Two slots: the third view is skipped
+        let scheduled = prefetcher.schedule(&storage, &params, View::Front, &options);
+        assert_eq!(scheduled, [View::Back, View::Left]);
+        assert_eq!(prefetcher.skipped(), 1);
+        prefetcher.idle().await;
+
+        for (view, cached) in [(View::Back, true), (View::Left, true), (View::Right, false)] {
+            let key = storage.cache_key(&params, view, &options);
+            let hit = storage
+                .get_cached_composite(&key, options.format)
+                .await
+                .unwrap();
+            assert_eq!(hit.is_some(), cached, "{}", view);
+        }
+    }
+}
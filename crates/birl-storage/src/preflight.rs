@@ -0,0 +1,242 @@
+//! Pre-flight checks of an outfit's layers, without rendering anything
+//!
+//! A render skips layers that aren't in storage and only warns about them.
+//! [`StorageService::validate_params`] reports up front which items would be
+//! found, which are missing, and which the view or catalog leaves out, along
+//! with the layer constraints the outfit breaks.
+
+use anyhow::{Context, Result};
+use birl_core::svg::SVG_EXTENSION;
+use birl_core::{
+    CategoryRegistry, ConstraintAction, ConstraintViolation, LayerNormalizer, LayerParam, View,
+    PLATE_CATEGORY,
+};
+use futures::future::join_all;
+use serde::Serialize;
+
+use crate::{plate_for, svg_fallback, StorageService};
+
+/// What a pre-flight check found for one asset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum AssetStatus {
+    Found,
+    /// Not in storage; the render would leave it out
+    Missing,
+    /// The view doesn't show the category; the render drops it
+    NotShown,
+    /// Not in the catalog; the render would be rejected
+    NotInCatalog,
+    /// The check itself failed (e.g. remote layers are disabled)
+    Error,
+}
+
+/// Pre-flight result for one requested item
+#[derive(Debug, Clone, Serialize)]
+#[non_exhaustive]
+pub struct LayerCheck {
+    /// The item as requested
+    #[serde(flatten)]
+    pub param: LayerParam,
+    /// The layer it resolves to after aliases and rules, if the view shows it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved: Option<LayerParam>,
+    pub status: AssetStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// SKU a missing layer may be a typo of
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+}
+
+/// Pre-flight result for a whole outfit
+#[derive(Debug, Clone, Serialize)]
+#[non_exhaustive]
+pub struct PreflightReport {
+    pub view: View,
+    pub plate: String,
+    pub plate_status: AssetStatus,
+    /// Every requested item except `plate/<sku>` params, in request order
+    pub layers: Vec<LayerCheck>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<ConstraintViolation>,
+}
+
+impl PreflightReport {
+    /// Whether the plate and every layer the view shows would be found, and no
+    /// constraint drops a layer or rejects the outfit
+    pub fn is_complete(&self) -> bool {
+        self.plate_status == AssetStatus::Found
+            && self
+                .layers
+                .iter()
+                .all(|check| matches!(check.status, AssetStatus::Found | AssetStatus::NotShown))
+            && self
+                .violations
+                .iter()
+                .all(|violation| violation.action == ConstraintAction::Warn)
+    }
+
+    /// Items the view shows that wouldn't be rendered
+    pub fn problems(&self) -> impl Iterator<Item = &LayerCheck> {
+        self.layers
+            .iter()
+            .filter(|check| !matches!(check.status, AssetStatus::Found | AssetStatus::NotShown))
+    }
+}
+
+impl StorageService {
+    /// Check that the plate and every layer of an outfit exist, without
+    /// downloading them where the backend can avoid it
+    pub async fn validate_params(&self, params: &[LayerParam], view: View) -> PreflightReport {
+        let normalizer = LayerNormalizer::new(view, params);
+        let plate = plate_for(params, view, &Default::default()).to_string();
+
+        let checks = params
+            .iter()
+            .filter(|param| param.category != PLATE_CATEGORY)
+            .map(|param| {
+                let resolved = normalizer.normalize(param);
+                async move {
+                    let (status, error) = match &resolved {
+                        None => (AssetStatus::NotShown, None),
+                        Some(layer) => self.check_layer(layer, view).await,
+                    };
+                    let suggestion = match (&resolved, status) {
+                        (Some(layer), AssetStatus::Missing | AssetStatus::NotInCatalog) => {
+                            self.suggest_sku(layer, view).await
+                        }
+                        _ => None,
+                    };
+                    LayerCheck {
+                        param: param.clone(),
+                        resolved,
+                        status,
+                        error,
+                        suggestion,
+                    }
+                }
+            });
+        let plate_layer = LayerParam::new(PLATE_CATEGORY, plate.as_str());
+        let ((plate_status, _), layers) =
+            futures::join!(self.check_layer(&plate_layer, view), join_all(checks));
+
+        PreflightReport {
+            view,
+            plate,
+            plate_status,
+            layers,
+            violations: normalizer.violations(params),
+        }
+    }
+
+    async fn check_layer(&self, layer: &LayerParam, view: View) -> (AssetStatus, Option<String>) {
+        let in_catalog = match &self.catalog {
+            Some(catalog) if layer.category == PLATE_CATEGORY => {
+                let plates = catalog.plates(view);
+                plates.is_empty() || plates.iter().any(|plate| plate == layer.sku.as_str())
+            }
+            Some(catalog) => catalog.contains(layer, view),
+            None => true,
+        };
+        if !in_catalog {
+            return (AssetStatus::NotInCatalog, None);
+        }
+
+        match self.layer_exists(layer, view).await {
+            Ok(true) => (AssetStatus::Found, None),
+            Ok(false) => (AssetStatus::Missing, None),
+            Err(e) => (AssetStatus::Error, Some(format!("{:#}", e))),
+        }
+    }
+
+    async fn layer_exists(&self, layer: &LayerParam, view: View) -> Result<bool> {
+        match &layer.url {
+            Some(url) => {
+                let remote = self
+                    .remote
+                    .as_ref()
+                    .context("Remote layers are not enabled")?;
+                remote.exists(url).await
+            }
+            None if layer.category == PLATE_CATEGORY => {
+                self.plate_exists(view, layer.sku.as_str()).await
+            }
+            None => {
+                let registry = CategoryRegistry::global();
+                let extension = registry.extension(&layer.category);
+                let exists = |extension| {
+                    self.backend
+                        .layer_exists(&layer.category, layer.sku.as_str(), view, extension)
+                };
+                if exists(extension).await? {
+                    return Ok(true);
+                }
+                Ok(svg_fallback(layer, extension) && exists(SVG_EXTENSION).await?)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use birl_core::{parse_params, Catalog};
+
+    #[tokio::test]
+    async fn test_validate_params() {
+        let dir = std::env::temp_dir().join(format!("birl-preflight-{}", std::process::id()));
+        for path in [
+            "left/plate/patch-plate.jpg",
+            "left/hoodies/hoodie-black.png",
+        ] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"asset").unwrap();
+        }
+        let service = StorageService::new_local(dir.clone(), 10);
+
+        let params = parse_params("hoodies/hoodie-black-xl,hoodies/hoodie-red,pants/cargo-black");
+        let report = service.validate_params(&params, View::Left).await;
+        assert_eq!(report.plate_status, AssetStatus::Found);
+        let statuses: Vec<_> = report.layers.iter().map(|check| check.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                AssetStatus::Found,
+                AssetStatus::Missing,
+                AssetStatus::NotShown
+            ]
+        );
+        assert!(!report.is_complete());
+        assert_eq!(report.problems().count(), 1);
+        assert_eq!(report.layers[1].suggestion, None);
+
+        let params = parse_params("hoodies/hoodie-blakc");
+        let report = service.validate_params(&params, View::Left).await;
+        assert_eq!(report.layers[0].suggestion.as_deref(), Some("hoodie-black"));
+
+        let params = parse_params("plate/base-model-tall,hoodies/hoodie-black");
+        let report = service.validate_params(&params, View::Left).await;
+        assert_eq!(report.plate, "base-model-tall");
+        assert_eq!(report.plate_status, AssetStatus::Missing);
+        assert_eq!(report.layers.len(), 1);
+
+        let catalog =
+            Catalog::from_json(r#"{"categories": {"hats": {"skus": ["beanie-red"]}}}"#).unwrap();
+        let service = StorageService::new_local(dir.clone(), 10).with_catalog(catalog);
+        let params = parse_params("hoodies/hoodie-black");
+        let report = service.validate_params(&params, View::Left).await;
+        assert_eq!(report.layers[0].status, AssetStatus::NotInCatalog);
+
+        let params = vec![LayerParam::remote(
+            "hoodies",
+            "https://cdn.partner.com/art.png",
+        )];
+        let report = service.validate_params(&params, View::Left).await;
+        assert_eq!(report.layers[0].status, AssetStatus::Error);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
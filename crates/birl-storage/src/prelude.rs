@@ -0,0 +1,28 @@
+//! The types most embedders need, in one import, including
+//! [`birl_core::prelude`]
+//!
+//! ```no_run
+//! use birl_storage::prelude::*;
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let storage = StorageService::new_demo(100);
+//! let params = parse_params("hoodies/hoodie-black");
+//! let rendered = storage
+//!     .render(&params, View::Front, &CompositeOptions::default(), false)
+//!     .await?;
+//! println!("{} bytes", rendered.data.len());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Items leave the prelude only in a breaking release.
+
+pub use birl_core::prelude::*;
+
+pub use crate::config::{Config, Profile};
+pub use crate::demo::DemoStorage;
+pub use crate::local::LocalStorage;
+pub use crate::plan::CompositionPlan;
+pub use crate::resolver::KeyResolver;
+pub use crate::s3::S3Storage;
+pub use crate::{PreparedComposite, RenderedComposite, StorageBackend, StorageService};
@@ -0,0 +1,304 @@
+//! Capture of production requests, and their replay against another build
+//!
+//! A server whose profile has a `[capture]` section records one `/create`
+//! request in every `every`: the params, view and options (nothing about the
+//! caller), how long the render took and a hash of the output. Records are
+//! written to `cache/replay/{name}.json` every few records. `birl-cli replay`
+//! renders each record again with the build at hand, without reading or
+//! writing the composite cache, and compares output hashes and latency, so a
+//! compositor change can be checked against real traffic before it ships.
+
+use anyhow::{Context, Result};
+use birl_core::{CompositeOptions, LayerParam, View};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use xxhash_rust::xxh64::xxh64;
+
+use crate::config::CaptureSettings;
+use crate::StorageService;
+
+/// Records captured between two writes of the replay file
+const FLUSH_EVERY: usize = 20;
+
+/// One captured request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestRecord {
+    pub params: Vec<LayerParam>,
+    pub view: View,
+    #[serde(default)]
+    pub options: CompositeOptions,
+    /// Time from the start of the render to the encoded output
+    pub latency_ms: u64,
+    /// Whether the composite was served from cache
+    pub cached: bool,
+    /// See [`output_hash`]
+    pub output_hash: String,
+}
+
+/// Hash of an encoded composite, as kept in a [`RequestRecord`]
+pub fn output_hash(data: &[u8]) -> String {
+    format!("{:016x}", xxh64(data, 0))
+}
+
+fn replay_key(name: &str) -> String {
+    format!("replay/{}", name)
+}
+
+/// Samples requests and keeps their records for the replay file
+pub struct RequestRecorder {
+    name: String,
+    every: u64,
+    max_records: usize,
+    seen: AtomicU64,
+    records: Mutex<Vec<RequestRecord>>,
+    /// Keeps an older snapshot from overwriting a newer one
+    flushing: tokio::sync::Mutex<()>,
+}
+
+impl RequestRecorder {
+    pub fn new(settings: &CaptureSettings) -> Result<Self> {
+        let name = &settings.name;
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!("Invalid capture name '{}'", name);
+        }
+        if settings.every == 0 {
+            anyhow::bail!("Capture `every` must be at least 1");
+        }
+
+        Ok(Self {
+            name: name.clone(),
+            every: settings.every,
+            max_records: settings.max_records,
+            seen: AtomicU64::new(0),
+            records: Mutex::new(Vec::new()),
+            flushing: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether to capture the next request: one in every `every`, until the
+    /// capture is full
+    pub fn sample(&self) -> bool {
+        if self.records.lock().unwrap().len() >= self.max_records {
+            return false;
+        }
+        self.seen
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.every)
+    }
+
+    /// Keep a record; returns whether the replay file is due a write
+    pub fn record(&self, record: RequestRecord) -> bool {
+        let mut records = self.records.lock().unwrap();
+        if records.len() >= self.max_records {
+            return false;
+        }
+        records.push(record);
+        records.len().is_multiple_of(FLUSH_EVERY) || records.len() == self.max_records
+    }
+
+    /// Write every record so far to the replay file
+    pub async fn flush(&self, storage: &StorageService) -> Result<()> {
+        let _flushing = self.flushing.lock().await;
+        let json = serde_json::to_string(&*self.records.lock().unwrap())?;
+        storage
+            .save_cached_json(&replay_key(&self.name), &json)
+            .await
+            .with_context(|| format!("Failed to write capture {}", self.name))
+    }
+}
+
+/// A captured request rendered again
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayOutcome {
+    pub record: RequestRecord,
+    pub latency_ms: u64,
+    /// Hash of the new output (empty if the render failed)
+    pub output_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ReplayOutcome {
+    /// Whether the new output is byte-for-byte the captured one
+    pub fn matches(&self) -> bool {
+        self.error.is_none() && self.output_hash == self.record.output_hash
+    }
+}
+
+/// Totals of a replay
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[non_exhaustive]
+pub struct ReplaySummary {
+    pub records: usize,
+    pub matched: usize,
+    /// Rendered, with a different output
+    pub mismatched: usize,
+    pub failed: usize,
+    /// Median latency of the captured renders (cache hits left out), and of
+    /// the same requests replayed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captured_median_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replayed_median_ms: Option<u64>,
+}
+
+impl ReplaySummary {
+    pub fn new(outcomes: &[ReplayOutcome]) -> Self {
+        let matched = outcomes.iter().filter(|outcome| outcome.matches()).count();
+        let failed = outcomes
+            .iter()
+            .filter(|outcome| outcome.error.is_some())
+            .count();
+
+        let rendered: Vec<&ReplayOutcome> = outcomes
+            .iter()
+            .filter(|outcome| !outcome.record.cached && outcome.error.is_none())
+            .collect();
+        let captured = median(rendered.iter().map(|outcome| outcome.record.latency_ms));
+        let replayed = median(rendered.iter().map(|outcome| outcome.latency_ms));
+
+        Self {
+            records: outcomes.len(),
+            matched,
+            mismatched: outcomes.len() - matched - failed,
+            failed,
+            captured_median_ms: captured,
+            replayed_median_ms: replayed,
+        }
+    }
+
+    /// Change of the median latency, in percent (positive is slower)
+    pub fn slowdown_percent(&self) -> Option<f64> {
+        match (self.captured_median_ms, self.replayed_median_ms) {
+            (Some(captured), Some(replayed)) if captured > 0 => {
+                Some((replayed as f64 / captured as f64 - 1.0) * 100.0)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn median(values: impl Iterator<Item = u64>) -> Option<u64> {
+    let mut values: Vec<u64> = values.collect();
+    values.sort_unstable();
+    values.get(values.len() / 2).copied()
+}
+
+impl StorageService {
+    /// Records of a capture, in the order they were captured
+    pub async fn fetch_replay(&self, name: &str) -> Result<Vec<RequestRecord>> {
+        let json = self
+            .fetch_cached_json(&replay_key(name))
+            .await?
+            .with_context(|| format!("No capture named '{}'", name))?;
+        serde_json::from_str(&json).with_context(|| format!("Invalid capture '{}'", name))
+    }
+
+    /// Render a captured request again, neither reading nor writing the
+    /// composite cache
+    pub async fn replay(&self, record: &RequestRecord) -> ReplayOutcome {
+        let start = Instant::now();
+        let rendered = async {
+            self.prepare(&record.params, record.view, &record.options, true)
+                .await?
+                .uncached()
+                .encode()
+                .await
+        }
+        .await;
+        let latency_ms = start.elapsed().as_millis() as u64;
+
+        let (output_hash, error) = match rendered {
+            Ok(rendered) => (output_hash(&rendered.data), None),
+            Err(e) => (String::new(), Some(format!("{:#}", e))),
+        };
+        ReplayOutcome {
+            record: record.clone(),
+            latency_ms,
+            output_hash,
+            error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use birl_core::parse_params;
+
+    #[tokio::test]
+    async fn test_capture_and_replay() {
+        let service = StorageService::new_demo(10);
+        let settings = CaptureSettings {
+            name: "canary".to_string(),
+            every: 2,
+            max_records: 3,
+        };
+        let recorder = RequestRecorder::new(&settings).unwrap();
+
+        let params = parse_params("hoodies/hoodie-black,pants/cargo-black");
+        let options = CompositeOptions::default();
+        let rendered = service
+            .render(&params, View::Front, &options, false)
+            .await
+            .unwrap();
+        let record = RequestRecord {
+            params,
+            view: View::Front,
+            options,
+            latency_ms: 40,
+            cached: false,
+            output_hash: output_hash(&rendered.data),
+        };
+
+        let sampled: Vec<bool> = (0..4).map(|_| recorder.sample()).collect();
+        assert_eq!(sampled, [true, false, true, false]);
+        assert!(!recorder.record(record.clone()));
+        assert!(!recorder.record(RequestRecord {
+            output_hash: "0".to_string(),
+            ..record.clone()
+        }));
+        assert!(recorder.record(RequestRecord {
+            options: CompositeOptions::default().with_plate("../secrets"),
+            ..record.clone()
+        }));
+        assert!(!recorder.sample());
+        recorder.flush(&service).await.unwrap();
+
+        let records = service.fetch_replay("canary").await.unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0], record);
+
+        let mut outcomes = Vec::new();
+        for record in &records {
+            outcomes.push(service.replay(record).await);
+        }
+        assert!(outcomes[0].matches());
+        assert!(!outcomes[1].matches());
+        assert!(outcomes[2].error.is_some());
+
+        let summary = ReplaySummary::new(&outcomes);
+        assert_eq!(
+            (summary.matched, summary.mismatched, summary.failed),
+            (1, 1, 1)
+        );
+        assert_eq!(summary.captured_median_ms, Some(40));
+
+        assert!(service.fetch_replay("missing").await.is_err());
+        let invalid = CaptureSettings {
+            name: "../canary".to_string(),
+            ..settings
+        };
+        assert!(RequestRecorder::new(&invalid).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+//! Mapping from (category, sku, view) to the key of a layer in storage
+//!
+//! The default layout is `{view}/{category}/{sku}.{ext}` under the bucket
+//! prefix or local base path. Deployments with other layouts (per-category
+//! roots, date-partitioned drops, hashed shards) implement [`KeyResolver`]
+//! and pass it to `S3Storage::with_resolver` / `LocalStorage::with_resolver`,
+//! or set a `layer_key` template in their profile.
+
+use anyhow::Result;
+use birl_core::View;
+
+/// Placeholders of a layer key template
+const PLACEHOLDERS: [&str; 4] = ["{view}", "{category}", "{sku}", "{ext}"];
+
+/// Where a layer (or plate, category `plate`) is stored
+pub trait KeyResolver: Send + Sync {
+    /// Key relative to the storage root, with `/` separators
+    fn layer_key(&self, category: &str, sku: &str, view: View, extension: &str) -> String;
+}
+
+/// `{view}/{category}/{sku}.{ext}`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DefaultKeyResolver;
+
+impl KeyResolver for DefaultKeyResolver {
+    fn layer_key(&self, category: &str, sku: &str, view: View, extension: &str) -> String {
+        format!("{}/{}/{}.{}", view.as_str(), category, sku, extension)
+    }
+}
+
+/// Layout given as a template, e.g. `"{category}/{view}/{sku}.{ext}"`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateKeyResolver {
+    template: String,
+}
+
+impl TemplateKeyResolver {
+    /// Parse a template; it must contain `{sku}` and only known placeholders
+    pub fn new(template: &str) -> Result<Self> {
+        if !template.contains("{sku}") {
+            anyhow::bail!("Layer key template '{}' must contain {{sku}}", template);
+        }
+
+        let mut rest = template.to_string();
+        for placeholder in PLACEHOLDERS {
+            rest = rest.replace(placeholder, "");
+        }
+        if rest.contains(['{', '}']) {
+            anyhow::bail!(
+                "Unknown placeholder in layer key template '{}' (expected {})",
+                template,
+                PLACEHOLDERS.join(", ")
+            );
+        }
+
+        Ok(Self {
+            template: template.trim_start_matches('/').to_string(),
+        })
+    }
+}
+
+impl KeyResolver for TemplateKeyResolver {
+    fn layer_key(&self, category: &str, sku: &str, view: View, extension: &str) -> String {
+        self.template
+            .replace("{view}", view.as_str())
+            .replace("{category}", category)
+            .replace("{sku}", sku)
+            .replace("{ext}", extension)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolvers() {
+        assert_eq!(
+            DefaultKeyResolver.layer_key("hoodies", "hoodie-black", View::Front, "png"),
+            "front/hoodies/hoodie-black.png"
+        );
+
+        let resolver = TemplateKeyResolver::new("/{category}/{view}/{sku}.{ext}").unwrap();
+        assert_eq!(
+            resolver.layer_key("hoodies", "hoodie-black", View::Back, "png"),
+            "hoodies/back/hoodie-black.png"
+        );
+
+        assert!(TemplateKeyResolver::new("{view}/{category}.png").is_err());
+        assert!(TemplateKeyResolver::new("{view}/{size}/{sku}.{ext}").is_err());
+    }
+}
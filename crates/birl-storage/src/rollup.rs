@@ -0,0 +1,502 @@
+//! Daily rollups of `/create` traffic
+//!
+//! A server whose profile has a `[rollup]` section counts its `/create`
+//! requests per UTC day: requests, cache hits, errors, layers that weren't
+//! found, and the most requested outfits. Each instance claims a slot for the
+//! day and writes its counts to `cache/reports/{date}/{slot}.json` every
+//! `flush_secs`. Once a day is over (and every instance has had a chance to
+//! write its last counts), the first instance to claim it merges the slots
+//! into `cache/reports/{date}.json`. `birl-cli rollups` prints a range of days.
+
+use anyhow::{Context, Result};
+use birl_core::{LayerParam, View};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::config::RollupSettings;
+use crate::locks::holder;
+use crate::StorageService;
+
+/// Instances that can report counts for one day
+const MAX_SLOTS: u32 = 64;
+/// Distinct outfits counted in memory; later ones only count as requests
+const MAX_TRACKED_OUTFITS: usize = 10_000;
+const SECS_PER_DAY: u64 = 86_400;
+
+/// A UTC day, serialized as `YYYY-MM-DD`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Day(u32);
+
+impl Day {
+    /// The day a Unix timestamp falls on
+    pub fn from_unix(secs: u64) -> Self {
+        Self((secs / SECS_PER_DAY) as u32)
+    }
+
+    pub fn today() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self::from_unix(now.as_secs())
+    }
+
+    pub fn previous(self) -> Self {
+        Self(self.0.saturating_sub(1))
+    }
+
+    pub fn next(self) -> Self {
+        Self(self.0 + 1)
+    }
+
+    /// (year, month, day), from Howard Hinnant's `civil_from_days`
+    fn civil(self) -> (i64, u32, u32) {
+        let z = i64::from(self.0) + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        (year, month, day)
+    }
+
+    /// Inverse of [`Self::civil`], for any month and day numbers
+    fn from_civil(year: i64, month: u32, day: u32) -> Option<Self> {
+        let year = year - i64::from(month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year - era * 400;
+        let mp = i64::from(if month > 2 { month - 3 } else { month + 9 });
+        let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        u32::try_from(era * 146_097 + doe - 719_468).ok().map(Self)
+    }
+}
+
+impl fmt::Display for Day {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = self.civil();
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+impl FromStr for Day {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("Invalid date '{}' (expected YYYY-MM-DD)", s);
+        let mut parts = s.trim().splitn(3, '-');
+        let mut part = || -> Result<u32> {
+            parts
+                .next()
+                .and_then(|p| p.parse().ok())
+                .ok_or_else(invalid)
+        };
+        let (year, month, day) = (part()?, part()?, part()?);
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return Err(invalid());
+        }
+        let parsed = Self::from_civil(i64::from(year), month, day).ok_or_else(invalid)?;
+        // Days past the end of the month (e.g. 02-30) come back as another date
+        if parsed.civil() != (i64::from(year), month, day) {
+            return Err(invalid());
+        }
+        Ok(parsed)
+    }
+}
+
+impl TryFrom<String> for Day {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Day> for String {
+    fn from(day: Day) -> Self {
+        day.to_string()
+    }
+}
+
+/// Request counts of one day, for one instance or all of them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DailyRollup {
+    pub date: Day,
+    pub requests: u64,
+    /// Requests served from the composite cache
+    pub cache_hits: u64,
+    /// Requests that failed
+    pub errors: u64,
+    /// Requests rendered without some of their layers
+    pub incomplete: u64,
+    /// Layers that weren't found, over all requests
+    pub missing_layers: u64,
+    /// Requests by outfit (`{view}:{category/sku,...}`, sorted); only the
+    /// most requested are kept in reports
+    #[serde(default)]
+    pub outfits: BTreeMap<String, u64>,
+}
+
+impl DailyRollup {
+    pub fn new(date: Day) -> Self {
+        Self {
+            date,
+            requests: 0,
+            cache_hits: 0,
+            errors: 0,
+            incomplete: 0,
+            missing_layers: 0,
+            outfits: BTreeMap::new(),
+        }
+    }
+
+    /// Share of successful requests served from cache
+    pub fn cache_hit_ratio(&self) -> Option<f64> {
+        let served = self.requests - self.errors;
+        (served > 0).then(|| self.cache_hits as f64 / served as f64)
+    }
+
+    /// The `n` most requested outfits, most requested first
+    pub fn top_outfits(&self, n: usize) -> Vec<(&str, u64)> {
+        let mut outfits: Vec<(&str, u64)> = self
+            .outfits
+            .iter()
+            .map(|(outfit, count)| (outfit.as_str(), *count))
+            .collect();
+        outfits.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        outfits.truncate(n);
+        outfits
+    }
+
+    /// Add another rollup's counts (of any day) to these
+    pub fn merge(&mut self, other: &DailyRollup) {
+        self.requests += other.requests;
+        self.cache_hits += other.cache_hits;
+        self.errors += other.errors;
+        self.incomplete += other.incomplete;
+        self.missing_layers += other.missing_layers;
+        for (outfit, count) in &other.outfits {
+            *self.outfits.entry(outfit.clone()).or_default() += count;
+        }
+    }
+
+    /// Keep only the `n` most requested outfits
+    fn keep_top(mut self, n: usize) -> Self {
+        let top: BTreeMap<String, u64> = self
+            .top_outfits(n)
+            .into_iter()
+            .map(|(outfit, count)| (outfit.to_string(), count))
+            .collect();
+        self.outfits = top;
+        self
+    }
+}
+
+/// Key of an outfit in [`DailyRollup::outfits`]
+pub fn outfit_key(params: &[LayerParam], view: View) -> String {
+    let mut params: Vec<String> = params.iter().map(ToString::to_string).collect();
+    params.sort();
+    format!("{}:{}", view, params.join(","))
+}
+
+fn report_key(day: Day) -> String {
+    format!("reports/{}", day)
+}
+
+fn slot_key(day: Day, slot: u32) -> String {
+    format!("reports/{}/{}", day, slot)
+}
+
+/// This instance's counts for the current day
+struct Current {
+    rollup: DailyRollup,
+    /// Claimed on the first write of the day
+    slot: Option<u32>,
+}
+
+/// Counts requests and writes them out on every [`Self::tick`]
+pub struct RollupRecorder {
+    settings: RollupSettings,
+    current: Mutex<Current>,
+    /// Keeps ticks from overlapping
+    ticking: tokio::sync::Mutex<()>,
+}
+
+impl RollupRecorder {
+    pub fn new(settings: &RollupSettings) -> Result<Self> {
+        if settings.flush_secs == 0 || settings.flush_secs >= SECS_PER_DAY / 2 {
+            anyhow::bail!("Rollup `flush_secs` must be between 1 and 43199");
+        }
+        Ok(Self::starting(settings.clone(), Day::today()))
+    }
+
+    fn starting(settings: RollupSettings, day: Day) -> Self {
+        Self {
+            settings,
+            current: Mutex::new(Current {
+                rollup: DailyRollup::new(day),
+                slot: None,
+            }),
+            ticking: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Seconds between two ticks
+    pub fn flush_secs(&self) -> u64 {
+        self.settings.flush_secs
+    }
+
+    /// Count a rendered request; `requested` and `found` are its layers
+    /// Requests go to the day of the last tick, so those in the first moments
+    /// of a day count toward the day before
+    pub fn record(&self, outfit: String, cached: bool, requested: usize, found: usize) {
+        let mut current = self.current.lock().unwrap();
+        let rollup = &mut current.rollup;
+        rollup.requests += 1;
+        rollup.cache_hits += u64::from(cached);
+        if found < requested {
+            rollup.incomplete += 1;
+            rollup.missing_layers += (requested - found) as u64;
+        }
+        let tracked = rollup.outfits.len() < MAX_TRACKED_OUTFITS;
+        match rollup.outfits.get_mut(&outfit) {
+            Some(count) => *count += 1,
+            None if tracked => {
+                rollup.outfits.insert(outfit, 1);
+            }
+            None => {}
+        }
+    }
+
+    /// Count a failed request
+    pub fn record_error(&self) {
+        let mut current = self.current.lock().unwrap();
+        current.rollup.requests += 1;
+        current.rollup.errors += 1;
+    }
+
+    /// Write this instance's counts, and roll up the previous day once it
+    /// has settled
+    pub async fn tick(&self, storage: &StorageService) -> Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("System clock is before the Unix epoch")?;
+        self.tick_at(storage, now.as_secs()).await
+    }
+
+    async fn tick_at(&self, storage: &StorageService, now: u64) -> Result<()> {
+        let _ticking = self.ticking.lock().await;
+        let today = Day::from_unix(now);
+
+        // A new day starts from zero; the old day's counts get their last write
+        let (finished, slot) = {
+            let mut current = self.current.lock().unwrap();
+            if current.rollup.date != today {
+                let finished = std::mem::replace(&mut current.rollup, DailyRollup::new(today));
+                let slot = current.slot.take();
+                (Some(finished), slot)
+            } else {
+                (None, None)
+            }
+        };
+        if let Some(finished) = finished {
+            self.write_slot(storage, finished, slot).await?;
+        }
+
+        let (rollup, slot) = {
+            let current = self.current.lock().unwrap();
+            (current.rollup.clone(), current.slot)
+        };
+        if rollup.requests > 0 {
+            let slot = self.write_slot(storage, rollup, slot).await?;
+            let mut current = self.current.lock().unwrap();
+            if current.rollup.date == today {
+                current.slot = Some(slot);
+            }
+        }
+
+        // Every instance writes its last counts of a day within a tick of
+        // midnight, so the day is rolled up two ticks later, by whichever
+        // instance claims the (settled) day first
+        let settled = now.saturating_sub(2 * self.settings.flush_secs);
+        let day = today.previous();
+        if Day::from_unix(settled) == today
+            && storage
+                .claim_period_at(
+                    "rollups",
+                    Duration::from_secs(SECS_PER_DAY),
+                    Duration::from_secs(settled),
+                )
+                .await?
+        {
+            let rollup = storage.roll_up(day, self.settings.top_outfits).await?;
+            info!("Rolled up {}: {} requests", day, rollup.requests);
+        }
+        Ok(())
+    }
+
+    /// Write a day's counts to the instance's slot, claiming one if needed
+    async fn write_slot(
+        &self,
+        storage: &StorageService,
+        rollup: DailyRollup,
+        slot: Option<u32>,
+    ) -> Result<u32> {
+        let day = rollup.date;
+        let slot = match slot {
+            Some(slot) => slot,
+            None => claim_slot(storage, day).await?,
+        };
+        let json = serde_json::to_string(&rollup.keep_top(self.settings.top_outfits))?;
+        storage
+            .save_cached_json(&slot_key(day, slot), &json)
+            .await
+            .with_context(|| format!("Failed to write rollup counts for {}", day))?;
+        Ok(slot)
+    }
+}
+
+async fn claim_slot(storage: &StorageService, day: Day) -> Result<u32> {
+    for slot in 0..MAX_SLOTS {
+        if storage
+            .backend
+            .claim(&slot_key(day, slot), &holder())
+            .await?
+        {
+            return Ok(slot);
+        }
+    }
+    anyhow::bail!("All {} rollup slots for {} are taken", MAX_SLOTS, day)
+}
+
+impl StorageService {
+    /// Merge every instance's counts for a day into `cache/reports/{date}.json`
+    pub async fn roll_up(&self, day: Day, top_outfits: usize) -> Result<DailyRollup> {
+        let rollup = self.merge_slots(day).await?.keep_top(top_outfits);
+        self.save_cached_json(&report_key(day), &serde_json::to_string(&rollup)?)
+            .await
+            .with_context(|| format!("Failed to write rollup for {}", day))?;
+        Ok(rollup)
+    }
+
+    /// A day's rollup, or the counts written so far if it isn't rolled up yet
+    pub async fn fetch_rollup(&self, day: Day) -> Result<DailyRollup> {
+        match self.fetch_cached_json(&report_key(day)).await? {
+            Some(json) => {
+                serde_json::from_str(&json).with_context(|| format!("Invalid rollup for {}", day))
+            }
+            None => self.merge_slots(day).await,
+        }
+    }
+
+    async fn merge_slots(&self, day: Day) -> Result<DailyRollup> {
+        let keys: Vec<String> = (0..MAX_SLOTS).map(|slot| slot_key(day, slot)).collect();
+        let slots = join_all(keys.iter().map(|key| self.fetch_cached_json(key))).await;
+        let mut rollup = DailyRollup::new(day);
+        for json in slots
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+        {
+            match serde_json::from_str::<DailyRollup>(&json) {
+                Ok(counts) => rollup.merge(&counts),
+                Err(e) => warn!("Skipping invalid rollup counts for {}: {}", day, e),
+            }
+        }
+        Ok(rollup)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use birl_core::parse_params;
+
+    #[test]
+    fn test_days() {
+        assert_eq!(Day::from_unix(0).to_string(), "1970-01-01");
+        assert_eq!(Day::from_unix(1_792_195_200).to_string(), "2026-10-17");
+        for date in ["2024-02-29", "2000-03-01", "2026-12-31"] {
+            assert_eq!(date.parse::<Day>().unwrap().to_string(), date);
+        }
+        let day: Day = "2026-12-31".parse().unwrap();
+        assert_eq!(day.next().to_string(), "2027-01-01");
+        for date in ["2026-02-29", "2026-13-01", "2026-10", "1969-12-31", "today"] {
+            assert!(date.parse::<Day>().is_err(), "{}", date);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rollup() {
+        let service = StorageService::new_demo(10);
+        let settings = RollupSettings {
+            flush_secs: 300,
+            top_outfits: 1,
+        };
+        let day = Day::from_unix(1_792_195_200);
+        let noon = 1_792_195_200 + SECS_PER_DAY / 2;
+        let hoodie = outfit_key(&parse_params("hoodies/hoodie-black"), View::Front);
+        let outfit = outfit_key(
+            &parse_params("pants/cargo-black,hoodies/hoodie-black"),
+            View::Front,
+        );
+        assert_eq!(outfit, "front:hoodies/hoodie-black,pants/cargo-black");
+
+        // Two instances, each with its own slot
+        let first = RollupRecorder::starting(settings.clone(), day);
+        first.record(outfit.clone(), false, 2, 2);
+        first.record(outfit.clone(), true, 2, 2);
+        first.record(hoodie.clone(), false, 2, 1);
+        first.tick_at(&service, noon).await.unwrap();
+        let second = RollupRecorder::starting(settings.clone(), day);
+        second.record(outfit.clone(), true, 2, 2);
+        second.record_error();
+        second.tick_at(&service, noon).await.unwrap();
+
+        let so_far = service.fetch_rollup(day).await.unwrap();
+        assert_eq!(
+            (so_far.requests, so_far.cache_hits, so_far.errors),
+            (5, 2, 1)
+        );
+        assert_eq!((so_far.incomplete, so_far.missing_layers), (1, 1));
+        assert_eq!(so_far.cache_hit_ratio(), Some(0.5));
+        assert_eq!(so_far.top_outfits(5), [(outfit.as_str(), 3)]);
+
+        // After the first tick past midnight, counts go to the next day; the
+        // day is rolled up once it has settled
+        let midnight = 1_792_195_200 + SECS_PER_DAY;
+        first.tick_at(&service, midnight + 10).await.unwrap();
+        first.record(hoodie.clone(), false, 1, 1);
+        first.tick_at(&service, midnight + 20).await.unwrap();
+        assert!(service
+            .fetch_cached_json("reports/2026-10-17")
+            .await
+            .unwrap()
+            .is_none());
+        second.tick_at(&service, midnight + 700).await.unwrap();
+        let rolled = service
+            .fetch_cached_json("reports/2026-10-17")
+            .await
+            .unwrap();
+        let rolled: DailyRollup = serde_json::from_str(&rolled.unwrap()).unwrap();
+        assert_eq!(rolled.requests, 5);
+        assert_eq!(rolled.outfits.len(), 1);
+        assert_eq!(service.fetch_rollup(day.next()).await.unwrap().requests, 1);
+
+        assert!(RollupRecorder::new(&RollupSettings {
+            flush_secs: 0,
+            ..settings
+        })
+        .is_err());
+    }
+}
@@ -1,23 +1,129 @@
 use anyhow::{Context, Result};
+use aws_sdk_s3::error::ProvideErrorMetadata;
 use aws_sdk_s3::Client;
 use bytes::Bytes;
-use birl_core::View;
+use birl_core::{OutputFormat, View};
+use futures::StreamExt;
+use serde::Deserialize;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::SystemTime;
 use tracing::{debug, warn};
 
+use crate::credentials::{is_expired, RefreshingCredentials};
+use crate::listing::{Page, PageStream};
+use crate::resolver::{DefaultKeyResolver, KeyResolver};
+
+/// Default key prefix for layers and the composite cache
+pub const DEFAULT_S3_PREFIX: &str = "birl";
+
+/// Endpoints the S3 client sends requests to, in a profile's `[s3]` table
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct S3Endpoint {
+    /// Transfer Acceleration (`{bucket}.s3-accelerate.amazonaws.com`), routed
+    /// through the nearest edge location; the bucket must have it enabled
+    #[serde(default)]
+    pub accelerate: bool,
+    /// IPv6 dual-stack endpoints (`s3.dualstack.{region}.amazonaws.com`)
+    /// Also enabled by `AWS_USE_DUALSTACK_ENDPOINT=true`
+    #[serde(default)]
+    pub dual_stack: bool,
+}
+
+impl S3Endpoint {
+    /// Set the enabled options on a client config, leaving the others to the
+    /// SDK config and environment
+    pub fn apply(self, mut config: aws_sdk_s3::config::Builder) -> aws_sdk_s3::config::Builder {
+        if self.accelerate {
+            config = config.accelerate(true);
+        }
+        if self.dual_stack {
+            config = config.use_dual_stack(true);
+        }
+        config
+    }
+}
+
+impl std::fmt::Display for S3Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let options: Vec<_> = [
+            (self.accelerate, "accelerated"),
+            (self.dual_stack, "dual-stack"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, name)| enabled.then_some(name))
+        .collect();
+        match options.is_empty() {
+            true => f.write_str("standard"),
+            false => f.write_str(&options.join(", ")),
+        }
+    }
+}
+
 /// S3 client wrapper for fetching and saving images
 pub struct S3Storage {
     client: Client,
     bucket: String,
+    prefix: String,
+    resolver: Arc<dyn KeyResolver>,
+    credentials: Option<Arc<RefreshingCredentials>>,
 }
 
 impl S3Storage {
     /// Create a new S3 storage client
     pub fn new(client: Client, bucket: String) -> Self {
-        Self { client, bucket }
+        Self {
+            client,
+            bucket,
+            prefix: DEFAULT_S3_PREFIX.to_string(),
+            resolver: Arc::new(DefaultKeyResolver),
+            credentials: None,
+        }
+    }
+
+    /// Store everything under a different key prefix (default: "birl")
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into().trim_matches('/').to_string();
+        self
+    }
+
+    /// Look layers up with a different key layout under the prefix
+    pub fn with_resolver(mut self, resolver: impl KeyResolver + 'static) -> Self {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Credentials the client signs with (see [`crate::credentials::s3_client`]),
+    /// refreshed and retried once when S3 rejects them as expired
+    pub fn with_credentials(mut self, credentials: Arc<RefreshingCredentials>) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// When the client's credentials expire, refreshing them first if
+    /// they're about to; `None` if they don't expire
+    pub async fn credentials_expiry(&self) -> Result<Option<SystemTime>> {
+        let Some(credentials) = &self.credentials else {
+            return Ok(None);
+        };
+        credentials
+            .credentials()
+            .await
+            .context("Failed to load S3 credentials")?;
+        Ok(credentials.expiry())
+    }
+
+    /// Object key of a layer: {prefix}/{key}, with the key from the resolver
+    pub fn layer_key(&self, category: &str, sku: &str, view: View, extension: &str) -> String {
+        format!(
+            "{}/{}",
+            self.prefix,
+            self.resolver.layer_key(category, sku, view, extension)
+        )
     }
 
     /// Fetch a layer image from S3
-    /// Path format: birl/{view}/{category}/{sku}.{extension}
     pub async fn fetch_layer(
         &self,
         category: &str,
@@ -25,7 +131,7 @@ impl S3Storage {
         view: View,
         extension: &str,
     ) -> Result<Option<Bytes>> {
-        let key = format!("birl/{}/{}/{}.{}", view.as_str(), category, sku, extension);
+        let key = self.layer_key(category, sku, view, extension);
 
         match self.fetch_object(&key).await {
             Ok(data) => {
@@ -39,15 +145,57 @@ impl S3Storage {
         }
     }
 
+    /// Check for a layer with a HEAD request
+    pub async fn layer_exists(
+        &self,
+        category: &str,
+        sku: &str,
+        view: View,
+        extension: &str,
+    ) -> Result<bool> {
+        let key = self.layer_key(category, sku, view, extension);
+
+        match self
+            .send(|| {
+                self.client
+                    .head_object()
+                    .bucket(&self.bucket)
+                    .key(&key)
+                    .send()
+            })
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(false),
+            Err(e) => Err(e).with_context(|| format!("Failed to check layer: {}", key)),
+        }
+    }
+
     /// Fetch a cached composite image from S3
-    /// Path format: birl/cache/{cache_key}.jpg
-    pub async fn fetch_cached(&self, cache_key: &str) -> Result<Option<Bytes>> {
-        let key = format!("birl/cache/{}.jpg", cache_key);
+    /// Path format: {prefix}/cache/{cache_key}.{ext}
+    pub async fn fetch_cached(
+        &self,
+        cache_key: &str,
+        format: OutputFormat,
+    ) -> Result<Option<Bytes>> {
+        Ok(self
+            .fetch_cached_saved(cache_key, format)
+            .await?
+            .map(|(data, _)| data))
+    }
 
-        match self.fetch_object(&key).await {
-            Ok(data) => {
+    /// [`Self::fetch_cached`] with the object's `Last-Modified` time
+    pub async fn fetch_cached_saved(
+        &self,
+        cache_key: &str,
+        format: OutputFormat,
+    ) -> Result<Option<(Bytes, Option<SystemTime>)>> {
+        let key = format!("{}/cache/{}.{}", self.prefix, cache_key, format.extension());
+
+        match self.fetch_object_saved(&key).await {
+            Ok((data, saved)) => {
                 debug!("Cache hit: {} ({} bytes)", cache_key, data.len());
-                Ok(Some(data))
+                Ok(Some((data, saved)))
             }
             Err(_) => {
                 debug!("Cache miss: {}", cache_key);
@@ -57,28 +205,88 @@ impl S3Storage {
     }
 
     /// Save a composite image to S3 cache
-    pub async fn save_to_cache(&self, cache_key: &str, data: &[u8]) -> Result<()> {
-        let key = format!("birl/cache/{}.jpg", cache_key);
+    pub async fn save_to_cache(
+        &self,
+        cache_key: &str,
+        data: &[u8],
+        format: OutputFormat,
+    ) -> Result<()> {
+        let key = format!("{}/cache/{}.{}", self.prefix, cache_key, format.extension());
 
-        self.client
-            .put_object()
-            .bucket(&self.bucket)
-            .key(&key)
-            .body(data.to_vec().into())
-            .content_type("image/jpeg")
-            .send()
-            .await
-            .context("Failed to save to cache")?;
+        self.send(|| {
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&key)
+                .body(data.to_vec().into())
+                .content_type(format.content_type())
+                .send()
+        })
+        .await
+        .context("Failed to save to cache")?;
 
         debug!("Saved to cache: {} ({} bytes)", cache_key, data.len());
 
         Ok(())
     }
 
+    /// Where the assets live: s3://{bucket}/{prefix}
+    pub fn location(&self) -> String {
+        format!("s3://{}/{}", self.bucket, self.prefix)
+    }
+
+    /// Location of a cached composite: s3://{bucket}/{prefix}/cache/{cache_key}.{ext}
+    pub fn cache_url(&self, cache_key: &str, format: OutputFormat) -> String {
+        format!(
+            "s3://{}/{}/cache/{}.{}",
+            self.bucket,
+            self.prefix,
+            cache_key,
+            format.extension()
+        )
+    }
+
+    /// Keys of all plates and layers, relative to the prefix, one
+    /// `ListObjectsV2` page (up to 1000 keys) at a time
+    pub fn asset_pages(&self) -> PageStream<'_, String> {
+        let root = format!("{}/", self.prefix);
+        let pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&root)
+            .into_paginator()
+            .send();
+
+        futures::stream::try_unfold((pages, root, 0), |(mut pages, root, number)| async move {
+            let Some(page) = pages.next().await else {
+                return Ok(None);
+            };
+            let page = page.context("Failed to list objects")?;
+            let cache = format!("{}cache/", root);
+            let items = page
+                .contents()
+                .iter()
+                .filter_map(|object| object.key())
+                .filter(|key| !key.starts_with(&cache) && !key.ends_with('/'))
+                .map(|key| key[root.len()..].to_string())
+                .collect::<Vec<_>>();
+
+            debug!(
+                "Listed {} assets under {} (page {})",
+                items.len(),
+                root,
+                number
+            );
+            Ok(Some((Page { items, number }, (pages, root, number + 1))))
+        })
+        .boxed()
+    }
+
     /// Fetch a cached JSON file from S3
-    /// Path format: birl/cache/{key}.json
+    /// Path format: {prefix}/cache/{key}.json
     pub async fn fetch_cached_json(&self, key: &str) -> Result<Option<String>> {
-        let s3_key = format!("birl/cache/{}.json", key);
+        let s3_key = format!("{}/cache/{}.json", self.prefix, key);
 
         match self.fetch_object(&s3_key).await {
             Ok(data) => {
@@ -90,17 +298,76 @@ impl S3Storage {
         }
     }
 
+    /// Save JSON data under `{prefix}/cache/{key}.json`
+    pub async fn save_cached_json(&self, key: &str, json: &str) -> Result<()> {
+        let s3_key = format!("{}/cache/{}.json", self.prefix, key);
+
+        self.send(|| {
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&s3_key)
+                .body(json.as_bytes().to_vec().into())
+                .content_type("application/json")
+                .send()
+        })
+        .await
+        .context("Failed to save cached JSON")?;
+
+        Ok(())
+    }
+
+    /// Create `{prefix}/cache/locks/{key}` with a conditional put
+    /// Returns false if the object already exists
+    pub async fn claim(&self, key: &str, holder: &str) -> Result<bool> {
+        let s3_key = format!("{}/cache/locks/{}", self.prefix, key);
+
+        let result = self
+            .send(|| {
+                self.client
+                    .put_object()
+                    .bucket(&self.bucket)
+                    .key(&s3_key)
+                    .body(holder.as_bytes().to_vec().into())
+                    .if_none_match("*")
+                    .send()
+            })
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            // 412 when the lock exists, 409 when another put of it is in flight
+            Err(e)
+                if e.raw_response()
+                    .is_some_and(|r| matches!(r.status().as_u16(), 409 | 412)) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to claim lock: {}", s3_key)),
+        }
+    }
+
     /// Generic fetch object from S3
     async fn fetch_object(&self, key: &str) -> Result<Bytes> {
+        Ok(self.fetch_object_saved(key).await?.0)
+    }
+
+    /// An object and when it was last modified
+    async fn fetch_object_saved(&self, key: &str) -> Result<(Bytes, Option<SystemTime>)> {
         let response = self
-            .client
-            .get_object()
-            .bucket(&self.bucket)
-            .key(key)
-            .send()
+            .send(|| {
+                self.client
+                    .get_object()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .send()
+            })
             .await
             .with_context(|| format!("Failed to fetch object: {}", key))?;
 
+        let saved = response
+            .last_modified()
+            .and_then(|time| SystemTime::try_from(*time).ok());
         let data = response
             .body
             .collect()
@@ -108,7 +375,26 @@ impl S3Storage {
             .context("Failed to read object body")?
             .into_bytes();
 
-        Ok(data)
+        Ok((data, saved))
+    }
+
+    /// Send a request, retrying it once with fresh credentials if S3 rejects
+    /// the current ones as expired
+    async fn send<T, E, F, Fut>(&self, request: F) -> Result<T, E>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: ProvideErrorMetadata,
+    {
+        let result = request().await;
+        match (&self.credentials, &result) {
+            (Some(credentials), Err(e)) if is_expired(e) => {
+                warn!("S3 credentials for {} expired, refreshing", self.bucket);
+                credentials.invalidate().await;
+                request().await
+            }
+            _ => result,
+        }
     }
 }
 
@@ -134,4 +420,14 @@ mod tests {
 
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_endpoint() {
+        assert_eq!(S3Endpoint::default().to_string(), "standard");
+        let endpoint: S3Endpoint = toml::from_str("accelerate = true").unwrap();
+        assert_eq!(endpoint.to_string(), "accelerated");
+        let endpoint: S3Endpoint = toml::from_str("accelerate = true\ndual_stack = true").unwrap();
+        assert_eq!(endpoint.to_string(), "accelerated, dual-stack");
+        assert!(toml::from_str::<S3Endpoint>("fips = true").is_err());
+    }
 }
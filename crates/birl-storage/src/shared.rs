@@ -0,0 +1,130 @@
+use anyhow::Result;
+use birl_core::{DecodeCache, TieredCache};
+use bytes::Bytes;
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use xxhash_rust::xxh64::xxh64;
+
+/// How much fetching and decoding the renders of a service shared
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SharingStats {
+    /// Plate and layer fetches requested by renders
+    pub requests: usize,
+    /// Distinct keys actually fetched
+    pub fetched: usize,
+    pub fetched_bytes: u64,
+    /// Fetched bytes identical to those of another key (e.g. the same art in two views)
+    pub duplicate_bytes: u64,
+    /// Decodes served from an earlier decode of the same bytes
+    pub decode_hits: usize,
+    pub decodes: usize,
+}
+
+impl SharingStats {
+    /// Fetches served by an earlier fetch of the same key
+    pub fn shared_fetches(&self) -> usize {
+        self.requests - self.fetched
+    }
+}
+
+/// Fetches and decodes shared by the renders of one service
+/// Nothing is evicted, so this is only meant for short-lived processes
+#[derive(Default)]
+pub(crate) struct SharedLayers {
+    /// Fetched bytes by key, `None` for layers that don't exist
+    fetches: TieredCache<String, Option<Bytes>>,
+    /// Content hashes of the fetched bytes
+    contents: Mutex<HashSet<u64>>,
+    pub(crate) decoded: Arc<DecodeCache>,
+    fetched_bytes: AtomicU64,
+    duplicate_bytes: AtomicU64,
+}
+
+impl SharedLayers {
+    /// Fetch `key` with `load`, unless it was (or is being) fetched already
+    pub(crate) async fn fetch<F, Fut>(&self, key: &str, load: F) -> Result<Option<Bytes>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<Bytes>>>,
+    {
+        self.fetches
+            .get_or_try_insert_with(key.to_string(), || async {
+                let data = load().await?;
+                if let Some(data) = &data {
+                    self.record(data);
+                }
+                Ok(data)
+            })
+            .await
+    }
+
+    fn record(&self, data: &[u8]) {
+        let size = data.len() as u64;
+        self.fetched_bytes.fetch_add(size, Ordering::Relaxed);
+
+        let is_new = self
+            .contents
+            .lock()
+            .expect("shared contents lock poisoned")
+            .insert(xxh64(data, 0));
+        if !is_new {
+            self.duplicate_bytes.fetch_add(size, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> SharingStats {
+        let (decode_hits, decodes) = self.decoded.stats();
+        let fetches = self.fetches.stats();
+
+        SharingStats {
+            requests: (fetches.hits + fetches.misses) as usize,
+            fetched: fetches.inserts as usize,
+            fetched_bytes: self.fetched_bytes.load(Ordering::Relaxed),
+            duplicate_bytes: self.duplicate_bytes.load(Ordering::Relaxed),
+            decode_hits,
+            decodes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[tokio::test]
+    async fn test_fetches_each_key_once() {
+        let shared = SharedLayers::default();
+        let loads = AtomicUsize::new(0);
+        let load = |data: &'static [u8]| {
+            let loads = &loads;
+            move || async move {
+                loads.fetch_add(1, Ordering::Relaxed);
+                Ok(Some(Bytes::from_static(data)))
+            }
+        };
+
+        let (a, b) = tokio::join!(
+            shared.fetch("https://cdn/flag.png", load(b"flag")),
+            shared.fetch("https://cdn/flag.png", load(b"flag")),
+        );
+        assert_eq!(a.unwrap(), b.unwrap());
+        shared
+            .fetch("front/hats/cap.png", load(b"cap"))
+            .await
+            .unwrap();
+        shared
+            .fetch("back/hats/cap.png", load(b"cap"))
+            .await
+            .unwrap();
+
+        assert_eq!(loads.load(Ordering::Relaxed), 3);
+        let stats = shared.stats();
+        assert_eq!((stats.requests, stats.fetched), (4, 3));
+        assert_eq!(stats.shared_fetches(), 1);
+        assert_eq!((stats.fetched_bytes, stats.duplicate_bytes), (10, 3));
+    }
+}
@@ -0,0 +1,196 @@
+//! "Did you mean" suggestions for layers that aren't in storage
+//!
+//! A render skips layers it can't find; [`StorageService::suggest_sku`] looks
+//! for the SKU the request most likely meant, so the warning (and the
+//! `x-missing-layers` header) can say "did you mean bearskin4-black?". SKUs
+//! come from the catalog when there is one, from the storage listing
+//! otherwise. The listing is reused for a few minutes. Renders don't wait for
+//! it: their first missing layer starts listing storage in the background and
+//! gets no suggestion itself.
+
+use birl_core::{LayerParam, SkuIndex, TieredCache, View};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::{StorageBackend, StorageService};
+
+/// How long a storage listing serves suggestions before it is fetched again
+const LISTING_TTL: Duration = Duration::from_secs(300);
+
+/// A layer a render didn't find, with the SKU it may be a typo of
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MissingLayer {
+    pub layer: LayerParam,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for MissingLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.layer)?;
+        match &self.suggestion {
+            Some(suggestion) => write!(f, " (did you mean {}?)", suggestion),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The last storage listing, by view and category
+pub(crate) struct ListedSkus {
+    index: TieredCache<(), Arc<SkuIndex>>,
+    /// Whether a listing is being fetched
+    listing: AtomicBool,
+}
+
+impl Default for ListedSkus {
+    fn default() -> Self {
+        Self {
+            index: TieredCache::new().with_ttl(LISTING_TTL),
+            listing: AtomicBool::new(false),
+        }
+    }
+}
+
+impl ListedSkus {
+    /// The listing, if a fresh one is kept
+    fn fresh(&self) -> Option<Arc<SkuIndex>> {
+        self.index.get(&())
+    }
+
+    /// List storage and keep the result, empty if the backend can't list
+    async fn fetch(&self, backend: &dyn StorageBackend) -> Arc<SkuIndex> {
+        let index = match backend.list_assets().await {
+            Ok(paths) => SkuIndex::from_paths(paths.iter().map(String::as_str)),
+            Err(e) => {
+                debug!("No SKU suggestions from the storage listing: {:#}", e);
+                SkuIndex::default()
+            }
+        };
+        let index = Arc::new(index);
+        self.index.insert((), index.clone());
+        index
+    }
+
+    /// [`Self::fetch`] in the background, unless a refresh is under way
+    fn refresh(self: &Arc<Self>, backend: &Arc<dyn StorageBackend>) {
+        if self.listing.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let listed = self.clone();
+        let backend = backend.clone();
+        tokio::spawn(async move {
+            listed.fetch(backend.as_ref()).await;
+            listed.listing.store(false, Ordering::Release);
+        });
+    }
+}
+
+impl StorageService {
+    /// A SKU of the layer's category that its SKU may be a typo of
+    /// `None` for remote layers, and for SKUs that exist (e.g. in another view)
+    /// Without a catalog, lists storage first if no listing is kept
+    pub async fn suggest_sku(&self, layer: &LayerParam, view: View) -> Option<String> {
+        self.suggest_sku_from(layer, view, true).await
+    }
+
+    /// [`Self::suggest_sku`], or without a kept listing and `wait_for_listing`,
+    /// `None` while storage is listed in the background
+    async fn suggest_sku_from(
+        &self,
+        layer: &LayerParam,
+        view: View,
+        wait_for_listing: bool,
+    ) -> Option<String> {
+        if layer.url.is_some() {
+            return None;
+        }
+        let sku = layer.sku.as_str();
+        if let Some(catalog) = &self.catalog {
+            let category = catalog.category(&layer.category)?;
+            if category.find(&layer.sku).is_some() {
+                return None;
+            }
+            return catalog.suggest_sku(&layer.category, sku, view);
+        }
+
+        let index = match self.listed_skus.fresh() {
+            Some(index) => index,
+            None if wait_for_listing => self.listed_skus.fetch(self.backend.as_ref()).await,
+            None => {
+                self.listed_skus.refresh(&self.backend);
+                return None;
+            }
+        };
+        index
+            .suggest(&layer.category, sku, view)
+            .map(str::to_string)
+    }
+
+    /// Each missing layer, with a suggestion where there is one
+    /// Doesn't wait for a storage listing, so a render's errors stay cheap
+    pub async fn missing_layers(&self, layers: &[LayerParam], view: View) -> Vec<MissingLayer> {
+        let mut missing = Vec::with_capacity(layers.len());
+        for layer in layers {
+            missing.push(MissingLayer {
+                layer: layer.clone(),
+                suggestion: self.suggest_sku_from(layer, view, false).await,
+            });
+        }
+        missing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use birl_core::Catalog;
+
+    #[tokio::test]
+    async fn test_suggest_sku() {
+        let dir = std::env::temp_dir().join(format!("birl-suggest-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("front/hats")).unwrap();
+        std::fs::write(dir.join("front/hats/bearskin4-black.png"), b"png").unwrap();
+        let service = StorageService::new_local(dir.clone(), 10);
+
+        // A render's first miss lists storage in the background
+        let typo = LayerParam::new("hats", "baerskin4-black");
+        let missing = service
+            .missing_layers(std::slice::from_ref(&typo), View::Front)
+            .await;
+        assert_eq!(missing[0].suggestion, None);
+        for _ in 0..100 {
+            if service.listed_skus.fresh().is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let missing = service
+            .missing_layers(std::slice::from_ref(&typo), View::Front)
+            .await;
+        assert_eq!(missing[0].suggestion.as_deref(), Some("bearskin4-black"));
+        assert_eq!(
+            missing[0].to_string(),
+            "hats/baerskin4-black (did you mean bearskin4-black?)"
+        );
+        assert_eq!(service.suggest_sku(&typo, View::Back).await, None);
+
+        // The catalog has the final say
+        let catalog =
+            Catalog::from_json(r#"{"categories": {"hats": {"skus": ["bearskin4-blue"]}}}"#)
+                .unwrap();
+        let service = service.with_catalog(catalog);
+        assert_eq!(service.suggest_sku(&typo, View::Front).await, None);
+        let typo = LayerParam::new("hats", "bearskin4-bleu");
+        assert_eq!(
+            service.suggest_sku(&typo, View::Front).await.as_deref(),
+            Some("bearskin4-blue")
+        );
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
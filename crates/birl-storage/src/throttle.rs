@@ -0,0 +1,282 @@
+//! Rate limit on backend writes, for bulk runs
+//!
+//! S3 throttles PUTs per prefix (3,500/s), and a batch prerender or sync that
+//! writes every composite under `cache/` can go past that and start failing
+//! with `SlowDown`. A [`WriteThrottle`] is a token bucket: writes run at once
+//! while the bucket holds tokens (`burst` of them when full), then at
+//! `ops_per_sec`. One throttle is shared by every task of the process, so
+//! concurrent renders don't each get the whole budget. Reads are not limited.
+
+use anyhow::Result;
+use birl_core::{OutputFormat, View};
+use bytes::Bytes;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::listing::PageStream;
+use crate::StorageBackend;
+
+/// Token bucket shared by the writes of a process
+pub struct WriteThrottle {
+    ops_per_sec: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+    writes: AtomicU64,
+    throttled: AtomicU64,
+    waited_us: AtomicU64,
+}
+
+struct Bucket {
+    /// Negative when writes are queued for tokens not yet refilled
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// Writes a throttle has let through so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct ThrottleStats {
+    pub writes: u64,
+    /// Writes that had to wait for a token
+    pub throttled: u64,
+    /// Time writes spent waiting, in total
+    #[serde(rename = "waited_ms", with = "millis")]
+    pub waited: Duration,
+}
+
+/// A duration as whole milliseconds
+mod millis {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+impl ThrottleStats {
+    /// What happened between `earlier` and these stats, e.g. during one batch
+    pub fn since(&self, earlier: &ThrottleStats) -> ThrottleStats {
+        ThrottleStats {
+            writes: self.writes.saturating_sub(earlier.writes),
+            throttled: self.throttled.saturating_sub(earlier.throttled),
+            waited: self.waited.saturating_sub(earlier.waited),
+        }
+    }
+}
+
+impl WriteThrottle {
+    /// `ops_per_sec` writes a second after the first `burst` (at least 1)
+    ///
+    /// # Panics
+    ///
+    /// If `ops_per_sec` isn't positive; [`crate::WriteLimitSettings`] rejects
+    /// such rates when the config is parsed
+    pub fn new(ops_per_sec: f64, burst: u32) -> Self {
+        assert!(
+            ops_per_sec > 0.0 && ops_per_sec.is_finite(),
+            "write rate must be positive, got {}",
+            ops_per_sec
+        );
+        let burst = f64::from(burst.max(1));
+        Self {
+            ops_per_sec,
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                refilled: Instant::now(),
+            }),
+            writes: AtomicU64::new(0),
+            throttled: AtomicU64::new(0),
+            waited_us: AtomicU64::new(0),
+        }
+    }
+
+    /// Wait for a token, returning how long that took
+    pub async fn acquire(&self) -> Duration {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.ops_per_sec).min(self.burst);
+            bucket.refilled = now;
+            // Reserve a token now, so waiting writes keep their order
+            bucket.tokens -= 1.0;
+            match bucket.tokens {
+                tokens if tokens >= 0.0 => Duration::ZERO,
+                tokens => Duration::from_secs_f64(-tokens / self.ops_per_sec),
+            }
+        };
+
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        if !wait.is_zero() {
+            self.throttled.fetch_add(1, Ordering::Relaxed);
+            self.waited_us
+                .fetch_add(wait.as_micros() as u64, Ordering::Relaxed);
+            debug!("Write throttled for {:?}", wait);
+            tokio::time::sleep(wait).await;
+        }
+        wait
+    }
+
+    pub(crate) fn ops_per_sec(&self) -> f64 {
+        self.ops_per_sec
+    }
+
+    pub fn stats(&self) -> ThrottleStats {
+        ThrottleStats {
+            writes: self.writes.load(Ordering::Relaxed),
+            throttled: self.throttled.load(Ordering::Relaxed),
+            waited: Duration::from_micros(self.waited_us.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// A backend whose writes go through a [`WriteThrottle`]
+pub struct ThrottledBackend {
+    inner: Arc<dyn StorageBackend>,
+    throttle: Arc<WriteThrottle>,
+}
+
+impl ThrottledBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>, throttle: Arc<WriteThrottle>) -> Self {
+        Self { inner, throttle }
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for ThrottledBackend {
+    async fn fetch_layer(
+        &self,
+        category: &str,
+        sku: &str,
+        view: View,
+        extension: &str,
+    ) -> Result<Option<Bytes>> {
+        self.inner.fetch_layer(category, sku, view, extension).await
+    }
+
+    async fn layer_exists(
+        &self,
+        category: &str,
+        sku: &str,
+        view: View,
+        extension: &str,
+    ) -> Result<bool> {
+        self.inner
+            .layer_exists(category, sku, view, extension)
+            .await
+    }
+
+    async fn fetch_cached(&self, cache_key: &str, format: OutputFormat) -> Result<Option<Bytes>> {
+        self.inner.fetch_cached(cache_key, format).await
+    }
+
+    async fn fetch_cached_saved(
+        &self,
+        cache_key: &str,
+        format: OutputFormat,
+    ) -> Result<Option<(Bytes, Option<SystemTime>)>> {
+        self.inner.fetch_cached_saved(cache_key, format).await
+    }
+
+    async fn save_to_cache(
+        &self,
+        cache_key: &str,
+        data: &[u8],
+        format: OutputFormat,
+    ) -> Result<()> {
+        self.throttle.acquire().await;
+        self.inner.save_to_cache(cache_key, data, format).await
+    }
+
+    async fn fetch_cached_json(&self, key: &str) -> Result<Option<String>> {
+        self.inner.fetch_cached_json(key).await
+    }
+
+    async fn save_cached_json(&self, key: &str, json: &str) -> Result<()> {
+        self.throttle.acquire().await;
+        self.inner.save_cached_json(key, json).await
+    }
+
+    fn cache_url(&self, cache_key: &str, format: OutputFormat) -> Option<String> {
+        self.inner.cache_url(cache_key, format)
+    }
+
+    fn location(&self) -> String {
+        self.inner.location()
+    }
+
+    fn layer_key(&self, category: &str, sku: &str, view: View, extension: &str) -> String {
+        self.inner.layer_key(category, sku, view, extension)
+    }
+
+    fn asset_pages(&self) -> PageStream<'_, String> {
+        self.inner.asset_pages()
+    }
+
+    async fn list_assets(&self) -> Result<Vec<String>> {
+        self.inner.list_assets().await
+    }
+
+    async fn claim(&self, key: &str, holder: &str) -> Result<bool> {
+        self.throttle.acquire().await;
+        self.inner.claim(key, holder).await
+    }
+
+    async fn credentials_expiry(&self) -> Result<Option<SystemTime>> {
+        self.inner.credentials_expiry().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DemoStorage;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_write_throttle() {
+        let throttle = Arc::new(WriteThrottle::new(10.0, 2));
+        let backend = ThrottledBackend::new(Arc::new(DemoStorage::new()), throttle.clone());
+
+        let start = Instant::now();
+        for key in ["a", "b", "c", "d"] {
+            backend
+                .save_to_cache(key, b"composite", OutputFormat::Png)
+                .await
+                .unwrap();
+        }
+        // Two from the burst, then one every 100ms
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+        let stats = throttle.stats();
+        assert_eq!((stats.writes, stats.throttled), (4, 2));
+        assert_eq!(stats.waited, Duration::from_millis(200));
+
+        // Reads aren't limited, and the bucket refills while idle
+        assert!(backend
+            .fetch_cached("a", OutputFormat::Png)
+            .await
+            .unwrap()
+            .is_some());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(throttle.acquire().await, Duration::ZERO);
+        assert_eq!(throttle.stats().since(&stats).throttled, 0);
+
+        let json = serde_json::to_value(stats).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"writes": 4, "throttled": 2, "waited_ms": 200})
+        );
+        assert_eq!(
+            serde_json::from_value::<ThrottleStats>(json).unwrap(),
+            stats
+        );
+    }
+}